@@ -0,0 +1,151 @@
+use crate::env::{accessibility_undeclared, required_accessibility};
+use anyhow::{anyhow, Error};
+use devcade_onboard_types::schema::{AccessibilityFlag, DevcadeGame};
+use devcade_onboard_types::Value;
+use lazy_static::lazy_static;
+use log::{log, Level};
+use std::sync::RwLock;
+
+lazy_static! {
+    // Flags required at runtime by an operator, which replace DEVCADE_REQUIRED_ACCESSIBILITY
+    // until the backend restarts
+    static ref REQUIRED: RwLock<Option<Vec<AccessibilityFlag>>> = RwLock::new(None);
+}
+
+/**
+ * Get the flags an operator required at runtime, or `None` if `DEVCADE_REQUIRED_ACCESSIBILITY`
+ * applies
+ */
+pub fn overridden() -> Option<Vec<AccessibilityFlag>> {
+    REQUIRED.read().unwrap().clone()
+}
+
+/**
+ * Get the flags every game must declare
+ */
+pub fn required() -> Vec<AccessibilityFlag> {
+    if let Some(flags) = REQUIRED.read().unwrap().clone() {
+        return flags;
+    }
+    required_accessibility()
+        .into_iter()
+        .filter_map(
+            |flag| match serde_json::from_value(Value::from(flag.as_str())) {
+                Ok(AccessibilityFlag::Unknown) | Err(_) => {
+                    log!(
+                        Level::Warn,
+                        "Ignoring unknown flag '{}' in DEVCADE_REQUIRED_ACCESSIBILITY",
+                        flag
+                    );
+                    None
+                }
+                Ok(flag) => Some(flag),
+            },
+        )
+        .collect()
+}
+
+/**
+ * Replace the required flags until the backend restarts, or go back to the configured ones
+ *
+ * # Errors
+ * This function will return an error if any of the flags is unknown.
+ */
+pub fn set_required(flags: Option<Vec<AccessibilityFlag>>) -> Result<(), Error> {
+    if let Some(flags) = &flags {
+        validate_flags(flags)?;
+    }
+    log!(
+        Level::Info,
+        "Required accessibility flags set to {:?}",
+        flags
+    );
+    *REQUIRED.write().unwrap() = flags;
+    Ok(())
+}
+
+/**
+ * Check that flags can be required, which unknown flags can't since no game could declare them
+ *
+ * # Errors
+ * This function will return an error if any of the flags is unknown.
+ */
+pub fn validate_flags(flags: &[AccessibilityFlag]) -> Result<(), Error> {
+    if flags.contains(&AccessibilityFlag::Unknown) {
+        return Err(anyhow!("Unknown accessibility flags can't be required"));
+    }
+    Ok(())
+}
+
+/**
+ * Whether a game declares all of the flags. Games that haven't declared anything only match
+ * when no flags are asked for.
+ */
+pub fn matches(game: &DevcadeGame, flags: &[AccessibilityFlag]) -> bool {
+    flags.is_empty()
+        || game
+            .accessibility
+            .as_ref()
+            .is_some_and(|declared| flags.iter().all(|flag| declared.contains(flag)))
+}
+
+/**
+ * Whether the policy lets a game be listed and launched
+ */
+pub fn allowed(game: &DevcadeGame) -> bool {
+    let required = required();
+    if required.is_empty() {
+        return true;
+    }
+    match game.accessibility {
+        None => accessibility_undeclared().is_some_and(|policy| policy == "include"),
+        Some(_) => matches(game, &required),
+    }
+}
+
+/**
+ * Refuse to launch a game the policy doesn't allow
+ *
+ * # Errors
+ * This function will return an `AccessibilityPolicy` error naming the missing flags.
+ */
+pub fn check(game: &DevcadeGame) -> Result<(), Error> {
+    if allowed(game) {
+        return Ok(());
+    }
+    let missing: Vec<AccessibilityFlag> = required()
+        .into_iter()
+        .filter(|flag| {
+            !game
+                .accessibility
+                .as_ref()
+                .is_some_and(|declared| declared.contains(flag))
+        })
+        .collect();
+    Err(anyhow!(
+        "AccessibilityPolicy: {} doesn't declare {:?}, which this cabinet requires",
+        game.name,
+        missing
+    ))
+}
+
+/**
+ * Reject games declaring flags outside the known set, so a typo in a manifest can't pass
+ * silently
+ *
+ * # Errors
+ * This function will return an error if the game declares an unknown flag.
+ */
+pub fn validate(game: &DevcadeGame) -> Result<(), Error> {
+    if game
+        .accessibility
+        .as_ref()
+        .is_some_and(|declared| declared.contains(&AccessibilityFlag::Unknown))
+    {
+        return Err(anyhow!(
+            "Game {} declares an unknown accessibility flag",
+            game.id
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,122 @@
+use crate::layout;
+use anyhow::{anyhow, Error};
+use devcade_onboard_types::{AtlasIcon, IconAtlas};
+use image::imageops::FilterType;
+use image::{Rgba, RgbaImage};
+use log::{log, Level};
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+/**
+ * Color of the cell used for games without a usable icon
+ */
+const PLACEHOLDER: Rgba<u8> = Rgba([64, 64, 64, 255]);
+
+// Builds replace the whole cache directory, so only one can run at a time
+static BUILDING: Mutex<()> = Mutex::new(());
+
+/**
+ * Pack the icons of the given games into square atlases of at most `max_size` pixels, with
+ * each icon scaled to `cell` pixels. Games without an icon, or with one that can't be decoded,
+ * get a placeholder cell.
+ *
+ * Atlases are cached under `.cache/atlas/`, keyed by the games and their icons' sizes and
+ * modification times, so they're only rebuilt when an icon changes. Icons are packed in game ID
+ * order, so the same icons always produce byte-identical atlases.
+ *
+ * # Errors
+ * This function will return an error if `cell` doesn't fit in `max_size`, or if the atlases
+ * cannot be written.
+ */
+pub fn build(mut game_ids: Vec<String>, max_size: u32, cell: u32) -> Result<IconAtlas, Error> {
+    if cell == 0 || cell > max_size {
+        return Err(anyhow!(
+            "Icon size {cell} doesn't fit in an atlas of {max_size}"
+        ));
+    }
+    game_ids.sort();
+    game_ids.dedup();
+
+    let _building = BUILDING.lock().unwrap();
+    let root = layout::cache_dir().join("atlas");
+    let dir = root.join(cache_key(&game_ids, max_size, cell));
+    let index = dir.join("index.json");
+    if let Some(atlas) = std::fs::read(&index)
+        .ok()
+        .and_then(|index| serde_json::from_slice::<IconAtlas>(&index).ok())
+    {
+        return Ok(atlas);
+    }
+
+    log!(
+        Level::Info,
+        "Building icon atlas for {} games",
+        game_ids.len()
+    );
+    // Only the atlases for the current icons are worth keeping
+    if root.exists() {
+        std::fs::remove_dir_all(&root)?;
+    }
+    std::fs::create_dir_all(&dir)?;
+
+    let per_row = max_size / cell;
+    let per_atlas = (per_row * per_row) as usize;
+    let mut atlas = IconAtlas::default();
+    for (n, chunk) in game_ids.chunks(per_atlas.max(1)).enumerate() {
+        let rows = (chunk.len() as u32).div_ceil(per_row);
+        let mut image = RgbaImage::new(per_row.min(chunk.len() as u32) * cell, rows * cell);
+        for (i, id) in chunk.iter().enumerate() {
+            let (x, y) = ((i as u32 % per_row) * cell, (i as u32 / per_row) * cell);
+            let icon = image::open(icon_path(id))
+                .map(|icon| {
+                    icon.resize_exact(cell, cell, FilterType::Triangle)
+                        .to_rgba8()
+                })
+                .unwrap_or_else(|_| RgbaImage::from_pixel(cell, cell, PLACEHOLDER));
+            image::imageops::replace(&mut image, &icon, i64::from(x), i64::from(y));
+            atlas.icons.push(AtlasIcon {
+                game_id: id.clone(),
+                atlas: n,
+                x,
+                y,
+                w: cell,
+                h: cell,
+            });
+        }
+        let path = dir.join(format!("atlas-{n}.png"));
+        image.save(&path)?;
+        atlas.atlases.push(path.to_string_lossy().into_owned());
+    }
+
+    layout::write_atomic(&index, serde_json::to_vec(&atlas)?)?;
+    Ok(atlas)
+}
+
+fn icon_path(game_id: &str) -> PathBuf {
+    layout::game_dir(game_id).join("icon.png")
+}
+
+/**
+ * Identify a set of icons by the games and each icon's size and modification time
+ */
+fn cache_key(game_ids: &[String], max_size: u32, cell: u32) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(format!("{max_size}/{cell}\n"));
+    for id in game_ids {
+        let stamp = std::fs::metadata(icon_path(id)).ok().map(|meta| {
+            let modified = meta
+                .modified()
+                .ok()
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .unwrap_or_default();
+            (meta.len(), modified.as_nanos())
+        });
+        hasher.update(format!("{id}:{stamp:?}\n"));
+    }
+    hasher.finalize()[..8]
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
@@ -0,0 +1,227 @@
+use crate::clock;
+use crate::env::cabinet_settings_writers;
+use crate::layout;
+use crate::state;
+use anyhow::{anyhow, Error};
+use lazy_static::lazy_static;
+use log::{log, Level};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/**
+ * Limits on the settings, which are meant for a handful of small values
+ */
+const MAX_KEY_LENGTH: usize = 64;
+const MAX_VALUE_LENGTH: usize = 4096;
+const MAX_KEYS: usize = 128;
+const MAX_TOTAL_LENGTH: usize = 64 * 1024;
+
+lazy_static! {
+    // Serializes writes to the settings file and journal
+    static ref SETTINGS: Mutex<()> = Mutex::new(());
+}
+
+/**
+ * One line of the journal
+ */
+#[derive(Serialize)]
+struct JournalEntry<'a> {
+    time: u64,
+    key: &'a str,
+    /// The game ID that wrote the setting, or `operator`
+    writer: &'a str,
+    /// The length of the new value, or `None` if the setting was removed
+    length: Option<usize>,
+}
+
+/**
+ * The file the settings are kept in, which is given to games
+ */
+pub fn path() -> PathBuf {
+    layout::state_dir().join("cabinet_settings.json")
+}
+
+fn journal_path() -> PathBuf {
+    layout::state_dir().join("cabinet_settings.journal")
+}
+
+fn read() -> Result<BTreeMap<String, String>, Error> {
+    match std::fs::read(path()) {
+        Ok(json) => Ok(serde_json::from_slice(&json)?),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/**
+ * Check that a key is short and made of letters, digits, `_`, `-`, `.` and `/`, like
+ * `calibration/p1/left_stick`
+ */
+fn validate_key(key: &str) -> Result<(), Error> {
+    let valid = !key.is_empty()
+        && key.len() <= MAX_KEY_LENGTH
+        && !key.starts_with('/')
+        && !key.ends_with('/')
+        && !key.contains("..")
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | '/'));
+    if valid {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "Invalid cabinet setting key '{key}', expected up to {MAX_KEY_LENGTH} letters, \
+            digits, '_', '-', '.' or '/'"
+        ))
+    }
+}
+
+/**
+ * Whether a game may write cabinet settings
+ */
+pub fn may_write(game_id: &str) -> bool {
+    cabinet_settings_writers().iter().any(|id| id == game_id)
+}
+
+/**
+ * Get a setting
+ *
+ * # Errors
+ * This function will return an error if the settings cannot be read, or the key isn't set.
+ */
+pub fn get(key: &str) -> Result<String, Error> {
+    validate_key(key)?;
+    read()?
+        .remove(key)
+        .ok_or_else(|| anyhow!("Cabinet setting '{key}' is not set"))
+}
+
+/**
+ * Set or remove a setting, and journal who did it
+ *
+ * # Errors
+ * This function will return an error if the key is invalid, the settings would be over quota,
+ * or the settings cannot be written.
+ */
+pub fn set(key: &str, value: Option<&str>, writer: &str) -> Result<(), Error> {
+    validate_key(key)?;
+    if let Some(value) = value {
+        validate_value(key, value)?;
+    }
+
+    let _guard = SETTINGS.lock().unwrap();
+    let mut settings = read()?;
+    match value {
+        Some(value) => {
+            settings.insert(key.to_string(), value.to_string());
+        }
+        None => {
+            settings.remove(key);
+        }
+    }
+    validate_quota(&settings)?;
+    write(&settings)?;
+    journal(key, value, writer)
+}
+
+/**
+ * Get all settings
+ *
+ * # Errors
+ * This function will return an error if the settings cannot be read.
+ */
+pub fn all() -> Result<BTreeMap<String, String>, Error> {
+    read()
+}
+
+/**
+ * Check a whole set of settings against the same limits as `set`
+ *
+ * # Errors
+ * This function will return an error naming the first setting over a limit.
+ */
+pub fn validate(settings: &BTreeMap<String, String>) -> Result<(), Error> {
+    for (key, value) in settings {
+        validate_key(key)?;
+        validate_value(key, value)?;
+    }
+    validate_quota(settings)
+}
+
+/**
+ * Replace all settings at once, and journal every setting that changed. Returns the keys of the
+ * settings that changed.
+ *
+ * # Errors
+ * This function will return an error if any setting is invalid, in which case nothing is
+ * changed, or if the settings cannot be written.
+ */
+pub fn replace(settings: &BTreeMap<String, String>, writer: &str) -> Result<Vec<String>, Error> {
+    validate(settings)?;
+    let _guard = SETTINGS.lock().unwrap();
+    let old = read()?;
+    let changed: Vec<String> = old
+        .keys()
+        .chain(settings.keys())
+        .filter(|key| old.get(*key) != settings.get(*key))
+        .cloned()
+        .collect::<std::collections::BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    if changed.is_empty() {
+        return Ok(changed);
+    }
+    write(settings)?;
+    for key in &changed {
+        journal(key, settings.get(key).map(String::as_str), writer)?;
+    }
+    Ok(changed)
+}
+
+fn validate_value(key: &str, value: &str) -> Result<(), Error> {
+    if value.len() > MAX_VALUE_LENGTH {
+        return Err(anyhow!(
+            "Cabinet setting '{key}' is {} bytes, the most is {MAX_VALUE_LENGTH}",
+            value.len()
+        ));
+    }
+    Ok(())
+}
+
+fn validate_quota(settings: &BTreeMap<String, String>) -> Result<(), Error> {
+    let total: usize = settings.iter().map(|(k, v)| k.len() + v.len()).sum();
+    if settings.len() > MAX_KEYS || total > MAX_TOTAL_LENGTH {
+        return Err(anyhow!(
+            "Cabinet settings are full ({} keys, {} bytes)",
+            settings.len(),
+            total
+        ));
+    }
+    Ok(())
+}
+
+fn write(settings: &BTreeMap<String, String>) -> Result<(), Error> {
+    // Written atomically, since games may be reading it
+    layout::write_atomic(&path(), serde_json::to_vec_pretty(settings)?)?;
+    Ok(())
+}
+
+fn journal(key: &str, value: Option<&str>, writer: &str) -> Result<(), Error> {
+    let entry = JournalEntry {
+        time: clock::unix_now(),
+        key,
+        writer,
+        length: value.map(str::len),
+    };
+    state::journal(journal_path().as_path(), &entry)?;
+    log!(
+        Level::Info,
+        "Cabinet setting '{}' {} by {}",
+        key,
+        if value.is_some() { "set" } else { "removed" },
+        writer
+    );
+    Ok(())
+}
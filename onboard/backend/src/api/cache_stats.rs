@@ -0,0 +1,367 @@
+use crate::clock;
+use crate::layout;
+use devcade_onboard_types::CacheReport;
+use log::{log, Level};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/**
+ * How many buckets the hourly window is split into, one per minute
+ */
+const BUCKETS: u64 = 60;
+
+/**
+ * Bits for each count in a bucket. Counts stop going up at `COUNT_MAX` a minute.
+ */
+const COUNT_BITS: u32 = 20;
+const COUNT_MAX: u64 = (1 << COUNT_BITS) - 1;
+
+/**
+ * Bits for the minute a bucket is for, in the rest of it. The minute only has to be told apart
+ * from the minutes of the last hour, so it wraps after about 30 years.
+ */
+const MINUTE_MASK: u64 = (1 << (64 - 2 * COUNT_BITS)) - 1;
+
+/**
+ * How often the summary is logged
+ */
+const SUMMARY_EVERY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/**
+ * Counters for how well a cache is doing. Every count is a single atomic operation, so they can be
+ * kept on paths that run on every request.
+ */
+pub struct Counters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    stale: AtomicU64,
+    evictions: AtomicU64,
+    /// Lookups and hits in each of the last `BUCKETS` minutes, packed with the minute they're for
+    /// so a bucket left over from an earlier hour is never counted, however long ago that was
+    window: [AtomicU64; BUCKETS as usize],
+}
+
+impl Counters {
+    pub const fn new() -> Self {
+        Self {
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            stale: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+            window: [const { AtomicU64::new(0) }; BUCKETS as usize],
+        }
+    }
+
+    /**
+     * Count a lookup in the bucket for a minute, starting the bucket over if it was for another
+     */
+    fn record(&self, minute: u64, hit: bool) {
+        #[allow(clippy::cast_possible_truncation)]
+        let bucket = &self.window[(minute % BUCKETS) as usize];
+        let minute = minute & MINUTE_MASK;
+        // The closure always returns `Some`, so this can't fail
+        let _ = bucket.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |packed| {
+            let (mut lookups, mut hits) = if packed >> (2 * COUNT_BITS) == minute {
+                ((packed >> COUNT_BITS) & COUNT_MAX, packed & COUNT_MAX)
+            } else {
+                (0, 0)
+            };
+            if lookups < COUNT_MAX {
+                lookups += 1;
+                hits += u64::from(hit);
+            }
+            Some(minute << (2 * COUNT_BITS) | lookups << COUNT_BITS | hits)
+        });
+    }
+
+    /**
+     * Get the lookups and hits in the hour up to and including a minute
+     */
+    fn hour(&self, minute: u64) -> (u64, u64) {
+        let minute = minute & MINUTE_MASK;
+        self.window
+            .iter()
+            .map(|bucket| bucket.load(Ordering::Relaxed))
+            .filter(|packed| {
+                minute.wrapping_sub(packed >> (2 * COUNT_BITS)) & MINUTE_MASK < BUCKETS
+            })
+            .fold((0, 0), |(lookups, hits), packed| {
+                (
+                    lookups + ((packed >> COUNT_BITS) & COUNT_MAX),
+                    hits + (packed & COUNT_MAX),
+                )
+            })
+    }
+
+    /**
+     * Count a lookup that the cache answered
+     */
+    pub fn hit(&self) {
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        self.record(minute(), true);
+    }
+
+    /**
+     * Count a lookup that had to go past the cache
+     */
+    pub fn miss(&self) {
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        self.record(minute(), false);
+    }
+
+    /**
+     * Count a lookup answered with an outdated entry because the source couldn't be reached. It's
+     * not a hit, since the cache would have been skipped if it could.
+     */
+    pub fn stale(&self) {
+        self.stale.fetch_add(1, Ordering::Relaxed);
+        self.record(minute(), false);
+    }
+
+    /**
+     * Count an entry that was replaced or dropped
+     */
+    pub fn evicted(&self) {
+        self.evictions.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/**
+ * Get the current minute as a bucket index
+ */
+fn minute() -> u64 {
+    clock::unix_now() / 60
+}
+
+/**
+ * A cache with counters, so it can be reported on
+ */
+pub trait CacheStats: Sync {
+    fn name(&self) -> &'static str;
+
+    fn counters(&self) -> &Counters;
+
+    /**
+     * Get how many entries the cache holds and roughly how many bytes they take
+     */
+    fn size(&self) -> (u64, u64);
+
+    fn report(&self) -> CacheReport {
+        let counters = self.counters();
+        let (entries, bytes) = self.size();
+        let (hour_lookups, hour_hits) = counters.hour(minute());
+        CacheReport {
+            name: self.name().to_string(),
+            entries,
+            bytes,
+            hits: counters.hits.load(Ordering::Relaxed),
+            misses: counters.misses.load(Ordering::Relaxed),
+            stale: counters.stale.load(Ordering::Relaxed),
+            evictions: counters.evictions.load(Ordering::Relaxed),
+            hour_lookups,
+            #[allow(clippy::cast_precision_loss)]
+            hour_hit_ratio: (hour_lookups > 0).then(|| hour_hits as f64 / hour_lookups as f64),
+        }
+    }
+}
+
+/**
+ * One of the caches counted here, sized by a function that looks at what it holds
+ */
+struct Cache {
+    name: &'static str,
+    counters: &'static Counters,
+    size: fn() -> (u64, u64),
+}
+
+impl CacheStats for Cache {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn counters(&self) -> &Counters {
+        self.counters
+    }
+
+    fn size(&self) -> (u64, u64) {
+        (self.size)()
+    }
+}
+
+/**
+ * Game art on disk, revalidated with its `ETag` when the game changes
+ */
+pub static ART: Counters = Counters::new();
+
+/**
+ * Art that failed to download, which isn't requested again until it's waited long enough. A hit
+ * is a request that wasn't sent.
+ */
+pub static FAILED_ART: Counters = Counters::new();
+
+/**
+ * The games with each tag
+ */
+pub static TAGS: Counters = Counters::new();
+
+/**
+ * Archive sizes from HEAD requests, for each version of a game
+ */
+pub static ARCHIVE_SIZES: Counters = Counters::new();
+
+/**
+ * Every cache that's reported on
+ */
+static CACHES: [&dyn CacheStats; 4] = [
+    &Cache {
+        name: "art",
+        counters: &ART,
+        size: art_size,
+    },
+    &Cache {
+        name: "failed art",
+        counters: &FAILED_ART,
+        size: failed_art_size,
+    },
+    &Cache {
+        name: "tags",
+        counters: &TAGS,
+        size: tags_size,
+    },
+    &Cache {
+        name: "archive sizes",
+        counters: &ARCHIVE_SIZES,
+        size: archive_sizes_size,
+    },
+];
+
+fn art_size() -> (u64, u64) {
+    let Ok(dirs) = std::fs::read_dir(layout::games_dir()) else {
+        return (0, 0);
+    };
+    dirs.flatten()
+        .flat_map(|dir| ["icon.png", "banner.png"].map(|file| dir.path().join(file)))
+        .filter_map(|path| path.metadata().ok())
+        .fold((0, 0), |(entries, bytes), meta| {
+            (entries + 1, bytes + meta.len())
+        })
+}
+
+fn failed_art_size() -> (u64, u64) {
+    let failures = super::ART_FAILURES.lock().unwrap();
+    let bytes = failures
+        .keys()
+        .map(|(game_id, file)| game_id.len() + file.len())
+        .sum::<usize>();
+    (failures.len() as u64, bytes as u64)
+}
+
+fn tags_size() -> (u64, u64) {
+    let tags = super::TAG_MEMBERSHIP
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map_or(0, |membership| membership.tags.len());
+    let bytes = std::fs::metadata(super::tag_membership_path()).map_or(0, |meta| meta.len());
+    (tags as u64, bytes)
+}
+
+fn archive_sizes_size() -> (u64, u64) {
+    let sizes = super::ARCHIVE_SIZES.lock().unwrap();
+    let bytes = sizes
+        .iter()
+        .map(|(game_id, (hash, _))| game_id.len() + hash.len() + 8)
+        .sum::<usize>();
+    (sizes.len() as u64, bytes as u64)
+}
+
+/**
+ * Get the stats of every cache
+ */
+pub fn report() -> Vec<CacheReport> {
+    CACHES.iter().map(|cache| cache.report()).collect()
+}
+
+/**
+ * Log a line with the stats of every cache since startup, once a day. This never returns.
+ */
+pub async fn watch() {
+    let mut interval = clock::interval(SUMMARY_EVERY);
+    // The first tick is immediate, and there's nothing to summarize yet
+    interval.tick().await;
+    loop {
+        interval.tick().await;
+        let summary: Vec<String> = report()
+            .into_iter()
+            .map(|cache| {
+                let lookups = cache.hits + cache.misses + cache.stale;
+                #[allow(clippy::cast_precision_loss)]
+                let percent = if lookups == 0 {
+                    0.0
+                } else {
+                    cache.hits as f64 * 100.0 / lookups as f64
+                };
+                format!(
+                    "{} {:.0}% of {} ({} stale, {} evicted, {} entries)",
+                    cache.name, percent, lookups, cache.stale, cache.evictions, cache.entries
+                )
+            })
+            .collect();
+        log!(
+            Level::Info,
+            "Cache hits since startup: {}",
+            summary.join(", ")
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hour_covers_the_last_sixty_minutes() {
+        let counters = Counters::new();
+        let start = 28_000_000;
+        counters.record(start, true);
+        counters.record(start, true);
+        counters.record(start + 30, false);
+        assert_eq!(counters.hour(start + 30), (3, 2));
+        assert_eq!(counters.hour(start + 59), (3, 2));
+        // The first minute has left the window
+        assert_eq!(counters.hour(start + 60), (1, 0));
+        assert_eq!(counters.hour(start + 120), (0, 0));
+
+        // The bucket is reused for a later hour, starting from nothing
+        counters.record(start + 60, true);
+        assert_eq!(counters.hour(start + 60), (2, 1));
+    }
+
+    #[test]
+    fn ratio_doesnt_drift_over_weeks() {
+        let counters = Counters::new();
+        // Starting just before the minute wraps
+        let start = MINUTE_MASK - 1000;
+        for minute in start..start + 3 * 7 * 24 * 60 {
+            counters.record(minute, minute % 4 != 0);
+            counters.record(minute, false);
+        }
+        let end = start + 3 * 7 * 24 * 60 - 1;
+        assert_eq!(counters.hour(end), (120, 45));
+        // Idle for a while, then back
+        assert_eq!(counters.hour(end + 600), (0, 0));
+        counters.record(end + 600, true);
+        assert_eq!(counters.hour(end + 600), (1, 1));
+    }
+
+    #[test]
+    fn counts_stop_at_the_bucket_limit() {
+        let counters = Counters::new();
+        for _ in 0..=COUNT_MAX {
+            counters.record(5, true);
+        }
+        assert_eq!(counters.hour(5), (COUNT_MAX, COUNT_MAX));
+        counters.record(6, false);
+        assert_eq!(counters.hour(6), (COUNT_MAX + 1, COUNT_MAX));
+    }
+}
@@ -0,0 +1,366 @@
+use super::network;
+use super::{
+    download_jobs, emit_launch_event, game_running, get_game, hash_changes, installed_game,
+    invalidate_game, route, setup,
+};
+use crate::clock;
+use crate::env::{api_url, catalog_events};
+use anyhow::{anyhow, Error};
+use devcade_onboard_types::schema::DevcadeGame;
+use devcade_onboard_types::{CatalogEvents, CatalogEventsState, DownloadPriority, LaunchEventKind};
+use futures_util::StreamExt;
+use log::{log, Level};
+use serde::Deserialize;
+use std::convert::Infallible;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/**
+ * How long to wait before reconnecting after the first failure. It doubles with each failure in a
+ * row, up to `RECONNECT_MAX`.
+ */
+const RECONNECT_MIN: Duration = Duration::from_secs(1);
+const RECONNECT_MAX: Duration = Duration::from_secs(5 * 60);
+
+/**
+ * How long to wait before checking again for an API that doesn't send notifications
+ */
+const UNAVAILABLE_RETRY: Duration = Duration::from_secs(60 * 60);
+
+/**
+ * How long the connection can go without anything from the API, not even a keep-alive comment,
+ * before it's given up on
+ */
+const IDLE_TIMEOUT: Duration = Duration::from_secs(2 * 60);
+
+/**
+ * How long a connection has to stay up for the reconnect delay to start over
+ */
+const STABLE_AFTER: Duration = Duration::from_secs(60);
+
+static STATE: Mutex<CatalogEventsState> = Mutex::new(CatalogEventsState::Disabled);
+static NOTIFICATIONS: AtomicU64 = AtomicU64::new(0);
+
+/**
+ * What's done about a game the API says changed
+ */
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Mode {
+    /// Forget what's cached about it
+    Invalidate,
+    /// Also fetch its details again
+    Refresh,
+    /// Also update it in the background if it's installed
+    Download,
+}
+
+/**
+ * What happened to a game, from the `event` field of a notification
+ */
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Change {
+    Created,
+    Updated,
+    Deleted,
+}
+
+/**
+ * The data of a notification
+ */
+#[derive(Deserialize)]
+struct Notification {
+    #[serde(alias = "game_id")]
+    id: String,
+}
+
+/**
+ * Get what's done about changed games, or `None` if notifications aren't listened for. Unknown
+ * modes only invalidate, the least a notification can do.
+ */
+pub fn mode() -> Option<Mode> {
+    Some(match catalog_events()?.as_str() {
+        "invalidate" => Mode::Invalidate,
+        "refresh" => Mode::Refresh,
+        "download" => Mode::Download,
+        mode => {
+            log!(
+                Level::Warn,
+                "Unknown catalog events mode '{}', only invalidating caches",
+                mode
+            );
+            Mode::Invalidate
+        }
+    })
+}
+
+/**
+ * Get the state of the connection and how many notifications were handled
+ */
+pub fn status() -> CatalogEvents {
+    CatalogEvents {
+        state: *STATE.lock().unwrap(),
+        notifications: NOTIFICATIONS.load(Ordering::Relaxed),
+    }
+}
+
+fn set_state(state: CatalogEventsState) {
+    *STATE.lock().unwrap() = state;
+}
+
+/**
+ * Reads server-sent events out of a stream of bytes, which may split lines anywhere
+ */
+#[derive(Default)]
+struct Parser {
+    /// The start of a line that hasn't ended yet
+    partial: Vec<u8>,
+    event: String,
+    data: String,
+}
+
+impl Parser {
+    /**
+     * Read a chunk, returning the name and data of every event it finished
+     */
+    fn push(&mut self, chunk: &[u8]) -> Vec<(String, String)> {
+        self.partial.extend_from_slice(chunk);
+        let mut events = Vec::new();
+        while let Some(end) = self.partial.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.partial.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\n', '\r']);
+            if line.is_empty() {
+                if !self.data.is_empty() {
+                    self.data.pop();
+                    let event = std::mem::take(&mut self.event);
+                    events.push((
+                        if event.is_empty() {
+                            String::from("message")
+                        } else {
+                            event
+                        },
+                        std::mem::take(&mut self.data),
+                    ));
+                }
+                self.event.clear();
+                continue;
+            }
+            // Comments keep the connection alive
+            if line.starts_with(':') {
+                continue;
+            }
+            let (field, value) = line.split_once(':').unwrap_or((line, ""));
+            let value = value.strip_prefix(' ').unwrap_or(value);
+            match field {
+                "event" => value.clone_into(&mut self.event),
+                "data" => {
+                    self.data.push_str(value);
+                    self.data.push('\n');
+                }
+                _ => {}
+            }
+        }
+        events
+    }
+}
+
+/**
+ * Read which game a notification is about and what happened to it, or `None` if it isn't about a
+ * game
+ */
+fn parse(event: &str, data: &str) -> Option<(Change, String)> {
+    let change = match event {
+        "game-created" => Change::Created,
+        "game-updated" => Change::Updated,
+        "game-deleted" => Change::Deleted,
+        _ => {
+            log!(Level::Debug, "Ignoring catalog event '{}'", event);
+            return None;
+        }
+    };
+    match serde_json::from_str::<Notification>(data) {
+        Ok(notification) => Some((change, notification.id)),
+        Err(e) => {
+            log!(
+                Level::Warn,
+                "Ignoring unreadable catalog event '{}': {}",
+                event,
+                e
+            );
+            None
+        }
+    }
+}
+
+/**
+ * Update an installed game the API says changed, the same way auto-update would
+ */
+fn update_installed(game_id: &str, latest: &DevcadeGame) {
+    let Some(installed) = installed_game(game_id) else {
+        return;
+    };
+    if installed.hash == latest.hash {
+        return;
+    }
+    if game_running() {
+        log!(
+            Level::Info,
+            "Leaving the update of game {} to auto-update, a game is running",
+            game_id
+        );
+        return;
+    }
+    if hash_changes::is_suspicious(&installed, latest) && !hash_changes::allow(&installed, latest) {
+        return;
+    }
+    log!(Level::Info, "Game {} changed, updating it", game_id);
+    download_jobs::enqueue(game_id, DownloadPriority::Background);
+}
+
+/**
+ * Act on a notification that a game changed
+ */
+async fn handle(mode: Mode, change: Change, game_id: &str) {
+    log!(
+        Level::Debug,
+        "Catalog event for game {}: {:?}",
+        game_id,
+        change
+    );
+    invalidate_game(game_id);
+    if mode != Mode::Invalidate && change != Change::Deleted {
+        match get_game(game_id).await {
+            Ok(latest) if mode == Mode::Download => update_installed(game_id, &latest),
+            Ok(_) => {}
+            Err(e) => log!(
+                Level::Warn,
+                "Couldn't refresh game {} after a catalog event: {}",
+                game_id,
+                e
+            ),
+        }
+    }
+    NOTIFICATIONS.fetch_add(1, Ordering::Relaxed);
+    emit_launch_event(LaunchEventKind::CatalogChanged(game_id.to_string()));
+}
+
+/**
+ * Handle notifications from one connection until it ends or goes quiet, returning why it did
+ */
+async fn consume(mode: Mode, url: &str) -> Result<Infallible, Error> {
+    let stream = network::event_stream(url).await?;
+    tokio::pin!(stream);
+    set_state(CatalogEventsState::Connected);
+    log!(Level::Info, "Listening for catalog events");
+    let mut parser = Parser::default();
+    while let Some(chunk) = clock::timeout(IDLE_TIMEOUT, stream.next())
+        .await
+        .map_err(|_| anyhow!("Nothing was heard for {IDLE_TIMEOUT:?}"))?
+    {
+        for (event, data) in parser.push(&chunk?) {
+            if let Some((change, game_id)) = parse(event.as_str(), data.as_str()) {
+                handle(mode, change, game_id.as_str()).await;
+            }
+        }
+    }
+    Err(anyhow!("The API closed the connection"))
+}
+
+/**
+ * How long to wait before reconnecting after some failures in a row
+ */
+fn reconnect_delay(failures: u32) -> Duration {
+    RECONNECT_MIN
+        .saturating_mul(1 << failures.saturating_sub(1).min(16))
+        .min(RECONNECT_MAX)
+}
+
+/**
+ * Listen for the API's notifications about games being added, changed or removed, as
+ * `DEVCADE_CATALOG_EVENTS` says, reconnecting with backoff when the connection is lost. If the
+ * API doesn't send them, polling stays the only way changes are noticed, and the API is checked
+ * again every hour. This never returns unless notifications are turned off.
+ */
+pub async fn watch() {
+    let Some(mode) = mode() else {
+        return;
+    };
+    let mut failures = 0;
+    loop {
+        if setup::required() {
+            clock::sleep(RECONNECT_MAX).await;
+            continue;
+        }
+        set_state(CatalogEventsState::Connecting);
+        let connected = clock::now();
+        let url = format!("{}/{}", api_url(), route::catalog_events());
+        let e = match consume(mode, url.as_str()).await {
+            Ok(never) => match never {},
+            Err(e) => e,
+        };
+        if network::is_not_found(&e) {
+            set_state(CatalogEventsState::Unavailable);
+            log!(
+                Level::Info,
+                "The API doesn't send catalog events, only polling for changes"
+            );
+            failures = 0;
+            clock::sleep(UNAVAILABLE_RETRY).await;
+            continue;
+        }
+        if clock::elapsed(connected) >= STABLE_AFTER {
+            failures = 0;
+        }
+        failures += 1;
+        let wait = reconnect_delay(failures);
+        set_state(CatalogEventsState::Reconnecting);
+        log!(
+            Level::Warn,
+            "Lost catalog events, reconnecting in {:?}: {}",
+            wait,
+            e
+        );
+        clock::sleep(wait).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_split_across_chunks() {
+        let mut parser = Parser::default();
+        assert!(parser.push(b": keep-alive\n\nevent: game-upd").is_empty());
+        assert!(parser.push(b"ated\r\ndata: {\"id\":").is_empty());
+        assert_eq!(
+            parser.push(b" \"pong\"}\r\n\r\ndata: a\ndata: b\n\n"),
+            vec![
+                ("game-updated".to_string(), "{\"id\": \"pong\"}".to_string()),
+                ("message".to_string(), "a\nb".to_string()),
+            ]
+        );
+        // A name without data isn't an event, and doesn't carry over
+        assert!(parser.push(b"event: game-deleted\n\n").is_empty());
+        assert_eq!(
+            parser.push(b"data: x\n\n"),
+            vec![("message".to_string(), "x".to_string())]
+        );
+
+        assert_eq!(
+            parse("game-deleted", "{\"game_id\": \"snake\"}"),
+            Some((Change::Deleted, "snake".to_string()))
+        );
+        assert_eq!(parse("message", "x"), None);
+        assert_eq!(parse("game-created", "not json"), None);
+    }
+
+    #[test]
+    fn reconnect_backs_off() {
+        assert_eq!(reconnect_delay(1), RECONNECT_MIN);
+        assert_eq!(reconnect_delay(2), RECONNECT_MIN * 2);
+        assert_eq!(reconnect_delay(5), RECONNECT_MIN * 16);
+        assert_eq!(reconnect_delay(40), RECONNECT_MAX);
+    }
+}
@@ -0,0 +1,83 @@
+use super::sessions;
+use super::{cached_tag_membership, game_list_from_fs};
+use anyhow::Error;
+use devcade_onboard_types::schema::DevcadeGame;
+use devcade_onboard_types::CatalogStats;
+use log::{log, Level};
+use std::collections::{BTreeMap, BTreeSet};
+
+/**
+ * Total up a set of games. `installed` are the installed games among them, which are the only ones
+ * whose upload date is known.
+ */
+fn total(game_ids: &BTreeSet<String>, installed: &[DevcadeGame]) -> CatalogStats {
+    let playtime = sessions::playtime();
+    let played: Vec<_> = game_ids
+        .iter()
+        .filter_map(|id| playtime.get(id).map(|playtime| (id, playtime)))
+        .collect();
+    CatalogStats {
+        games: game_ids.len() as u64,
+        seconds_played: played.iter().map(|(_, playtime)| playtime.seconds).sum(),
+        recent_seconds_played: played
+            .iter()
+            .map(|(_, playtime)| playtime.recent_seconds())
+            .sum(),
+        most_played: played
+            .iter()
+            .filter(|(_, playtime)| playtime.seconds > 0)
+            .max_by_key(|(_, playtime)| playtime.seconds)
+            .map(|(id, _)| (*id).clone()),
+        newest_upload: installed
+            .iter()
+            .max_by(|a, b| a.upload_date.cmp(&b.upload_date))
+            .map(|game| game.id.clone()),
+    }
+}
+
+/**
+ * Get the number of games with each tag
+ */
+pub fn tag_counts() -> BTreeMap<String, u64> {
+    cached_tag_membership()
+        .map(|membership| {
+            membership
+                .tags
+                .into_iter()
+                .map(|(name, games)| (name, games.len() as u64))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/**
+ * Total up the games with a tag
+ */
+pub fn tag(name: &str) -> CatalogStats {
+    let game_ids: BTreeSet<String> = cached_tag_membership()
+        .and_then(|mut membership| membership.tags.remove(name))
+        .unwrap_or_default()
+        .into_iter()
+        .collect();
+    let installed = game_list_from_fs().unwrap_or_else(|e| {
+        log!(Level::Warn, "Couldn't list installed games: {}", e);
+        Vec::new()
+    });
+    let installed: Vec<_> = installed
+        .into_iter()
+        .filter(|game| game_ids.contains(&game.id))
+        .collect();
+    total(&game_ids, &installed)
+}
+
+/**
+ * Total up the installed games by an author
+ */
+pub fn author(author: &str) -> Result<CatalogStats, Error> {
+    let installed: Vec<_> = game_list_from_fs()?
+        .into_iter()
+        .filter(|game| game.author == author)
+        .collect();
+    let game_ids = installed.iter().map(|game| game.id.clone()).collect();
+    Ok(total(&game_ids, &installed))
+}
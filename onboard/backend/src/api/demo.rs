@@ -0,0 +1,167 @@
+use crate::env::{demo_id_prefix, demo_ids};
+use crate::servers::persistence::{self, EPHEMERAL_NAMESPACE};
+use devcade_onboard_types::{Map, Value};
+use lazy_static::lazy_static;
+use log::{log, Level};
+use std::sync::Mutex;
+
+lazy_static! {
+    // The demo guest who tapped in last, until a member taps in or the guest's session ends
+    static ref GUEST: Mutex<Option<Guest>> = Mutex::new(None);
+}
+
+/**
+ * A synthetic user for a demo wristband
+ */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(super) struct Guest {
+    /// Like `guest042`, used as the user ID and in the guest's save namespace
+    pub uid: String,
+    /// Like `Guest 042`
+    pub name: String,
+}
+
+/**
+ * Whether an association ID belongs to a demo wristband, by `DEVCADE_DEMO_ID_PREFIX` or
+ * `DEVCADE_DEMO_IDS`
+ */
+fn is_demo_id(association_id: &str) -> bool {
+    demo_id_prefix().is_some_and(|prefix| association_id.starts_with(prefix.as_str()))
+        || demo_ids().iter().any(|id| id == association_id)
+}
+
+/**
+ * The guest a demo wristband resolves to. The guest number is derived from the ID, so the same
+ * wristband always resolves to the same guest.
+ */
+fn guest(association_id: &str) -> Guest {
+    let number = association_id.bytes().fold(0u32, |acc, b| {
+        acc.wrapping_mul(31).wrapping_add(u32::from(b))
+    }) % 1000;
+    Guest {
+        uid: format!("guest{number:03}"),
+        name: format!("Guest {number:03}"),
+    }
+}
+
+/**
+ * Returns a synthetic guest user if the association ID belongs to a demo wristband, and makes the
+ * guest the one who's playing. Returns `None` without changing anything otherwise.
+ */
+pub(super) fn user(association_id: &str) -> Option<Map<String, Value>> {
+    if !is_demo_id(association_id) {
+        return None;
+    }
+    let guest = guest(association_id);
+    let mut user = Map::new();
+    user.insert(String::from("uid"), Value::from(guest.uid.clone()));
+    user.insert(String::from("cn"), Value::from(guest.name.clone()));
+    user.insert(String::from("demo"), Value::from(true));
+    *GUEST.lock().unwrap() = Some(guest);
+    Some(user)
+}
+
+/**
+ * A member tapped in, so whoever plays next isn't the demo guest
+ */
+pub(super) fn member_tapped() {
+    GUEST.lock().unwrap().take();
+}
+
+/**
+ * Get the demo guest who's playing, if any
+ */
+pub(super) fn guest_playing() -> Option<Guest> {
+    GUEST.lock().unwrap().clone()
+}
+
+/**
+ * Get the save namespace of a game played by a demo guest. Saves under it are only kept in memory
+ * until the session ends.
+ */
+pub(super) fn namespace(guest: &Guest, game_id: &str) -> String {
+    format!("{EPHEMERAL_NAMESPACE}/{}/{game_id}", guest.uid)
+}
+
+/**
+ * Whether a save namespace is a demo guest's
+ */
+pub(super) fn is_demo_namespace(namespace: &str) -> bool {
+    namespace
+        .strip_prefix(EPHEMERAL_NAMESPACE)
+        .is_some_and(|rest| rest.starts_with('/'))
+}
+
+/**
+ * End a demo guest's session, throwing away the saves the game made. The guest has to tap in again
+ * to play another game.
+ */
+pub(super) async fn end(namespace: &str) {
+    GUEST.lock().unwrap().take();
+    match persistence::discard(namespace).await {
+        Ok(groups) => log!(
+            Level::Info,
+            "Demo session ended, discarded {} groups of saves",
+            groups
+        ),
+        Err(e) => log!(Level::Warn, "Couldn't discard demo saves: {}", e),
+    }
+}
+
+/**
+ * Throw away every demo guest's saves, for nightly maintenance. Sessions normally discard their
+ * saves as they end, this catches any left behind by a restart.
+ */
+pub(super) async fn nightly() {
+    if let Err(e) = persistence::discard(EPHEMERAL_NAMESPACE).await {
+        log!(Level::Warn, "Couldn't discard demo saves: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn demo_ids_resolve_to_stable_guests() {
+        assert_eq!(guest("DEMO-17"), guest("DEMO-17"));
+        assert_ne!(guest("DEMO-17"), guest("DEMO-18"));
+        let guest = guest("DEMO-17");
+        assert_eq!(guest.uid.len(), "guest000".len());
+        assert_eq!(guest.name[6..], guest.uid[5..]);
+
+        let namespace = namespace(&guest, "pong");
+        assert!(is_demo_namespace(namespace.as_str()));
+        assert!(!is_demo_namespace("pong"));
+        assert!(!is_demo_namespace("demolition-derby"));
+    }
+
+    #[tokio::test]
+    async fn demo_saves_do_not_survive_session_end() {
+        let game = format!("demo-test-{}", std::process::id());
+        let guest = guest("DEMO-1");
+        let demo = format!("{}/slot", namespace(&guest, game.as_str()));
+        let member = format!("{game}/slot");
+
+        persistence::save(demo.as_str(), "score", "9001")
+            .await
+            .unwrap();
+        persistence::flush().await.unwrap();
+        assert!(!persistence::save_root().join(EPHEMERAL_NAMESPACE).exists());
+        assert_eq!(
+            persistence::load(demo.as_str(), "score").await.unwrap(),
+            "9001"
+        );
+        persistence::save(member.as_str(), "score", "42")
+            .await
+            .unwrap();
+
+        end(namespace(&guest, game.as_str()).as_str()).await;
+        assert!(persistence::load(demo.as_str(), "score").await.is_err());
+        assert_eq!(
+            persistence::load(member.as_str(), "score").await.unwrap(),
+            "42"
+        );
+        persistence::discard(game.as_str()).await.unwrap();
+    }
+}
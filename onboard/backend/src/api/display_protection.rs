@@ -0,0 +1,164 @@
+use super::{game_running, sessions};
+use crate::clock;
+use crate::env::{dim_backlight_command, display_protection_after, restore_backlight_command};
+use crate::layout;
+use crate::state::JsonState;
+use anyhow::Error;
+use devcade_onboard_types::DisplayProtection;
+use lazy_static::lazy_static;
+use log::{log, Level};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::process::Command;
+
+/**
+ * How often idle time is checked. The display counts as static once it's gone this long
+ * without activity.
+ */
+const CHECK_EVERY: Duration = Duration::from_secs(30);
+/**
+ * How many days of static display time are kept
+ */
+const HISTORY_DAYS: usize = 90;
+
+struct State {
+    last_activity: Instant,
+    protecting: bool,
+    dimmed: bool,
+}
+
+lazy_static! {
+    static ref STATE: Mutex<State> = Mutex::new(State {
+        last_activity: clock::now(),
+        protecting: false,
+        dimmed: false,
+    });
+    // Seconds the display was static by local date
+    static ref STATIC_SECS: JsonState<BTreeMap<String, u64>> = JsonState::new(stats_path);
+}
+
+fn stats_path() -> PathBuf {
+    layout::state_dir().join("display.json")
+}
+
+/**
+ * Run a backlight command in the background, logging how it went
+ */
+fn run(command: String, what: &'static str) {
+    tokio::spawn(async move {
+        let mut args = command.split_whitespace();
+        // This unwrap is safe because the backlight commands are never blank
+        let program = args.next().unwrap();
+        match Command::new(program).args(args).status().await {
+            Ok(status) if status.success() => log!(Level::Info, "Ran command to {}", what),
+            Ok(status) => log!(Level::Warn, "Command to {} failed: {}", what, status),
+            Err(e) => log!(Level::Warn, "Couldn't run command to {}: {}", what, e),
+        }
+    });
+}
+
+/**
+ * Note activity at the cabinet, ending display protection if it was on
+ */
+pub fn activity(source: &str) {
+    let dimmed = {
+        let mut state = STATE.lock().unwrap();
+        state.last_activity = clock::now();
+        if !state.protecting {
+            return;
+        }
+        state.protecting = false;
+        std::mem::take(&mut state.dimmed)
+    };
+    log!(Level::Info, "Ending display protection ({})", source);
+    if dimmed {
+        if let Some(command) = restore_backlight_command() {
+            run(command, "restore the backlight");
+        }
+    }
+}
+
+/**
+ * Add static display time to today's total and save it
+ */
+fn add_static_time(secs: u64) -> Result<(), Error> {
+    let mut stats = STATIC_SECS.lock();
+    *stats.entry(sessions::today()).or_default() += secs;
+    while stats.len() > HISTORY_DAYS {
+        stats.pop_first();
+    }
+    stats.save()
+}
+
+/**
+ * Watch for the cabinet going idle. A running game counts as activity. Quiet hours don't
+ * change anything here, so the display is still protected while games can't be launched.
+ */
+pub async fn watch() {
+    let mut interval = clock::interval(CHECK_EVERY);
+    loop {
+        interval.tick().await;
+        if game_running() {
+            STATE.lock().unwrap().last_activity = clock::now();
+            continue;
+        }
+
+        let idle = clock::elapsed(STATE.lock().unwrap().last_activity);
+        if idle < CHECK_EVERY {
+            continue;
+        }
+        if let Err(e) = add_static_time(CHECK_EVERY.as_secs()) {
+            log!(Level::Warn, "Couldn't record static display time: {}", e);
+        }
+
+        let Some(after) = display_protection_after() else {
+            continue;
+        };
+        let dim = {
+            let mut state = STATE.lock().unwrap();
+            if idle < after || state.protecting {
+                continue;
+            }
+            let dim = dim_backlight_command();
+            state.protecting = true;
+            state.dimmed = dim.is_some();
+            dim
+        };
+        log!(
+            Level::Info,
+            "Cabinet idle for {:?}, suggesting display protection",
+            idle
+        );
+        if let Some(command) = dim {
+            run(command, "dim the backlight");
+        }
+    }
+}
+
+/**
+ * Get whether display protection is on, the static display time of recent days, and the featured
+ * games to show off while idle, which there are none of during quiet hours
+ */
+pub fn state() -> DisplayProtection {
+    let static_secs = STATIC_SECS.lock().clone();
+    // Attract mode stays off during quiet hours
+    let attract = if super::quiet_hours::until().is_some() {
+        Vec::new()
+    } else {
+        super::featured_games()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|game| game.id)
+            .collect()
+    };
+    let state = STATE.lock().unwrap();
+    DisplayProtection {
+        suggested: state.protecting,
+        dimmed: state.dimmed,
+        idle_secs: clock::elapsed(state.last_activity).as_secs(),
+        static_secs,
+        attract,
+    }
+}
@@ -0,0 +1,206 @@
+use super::{download_with_priority, operations, promote_queued, queue_position, DOWNLOADS};
+use anyhow::{anyhow, Error};
+use devcade_onboard_types::{DownloadJob, DownloadPriority, DownloadQueueState};
+use lazy_static::lazy_static;
+use log::{log, Level};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+/**
+ * How many completed and failed jobs are remembered, each
+ */
+const MAX_FINISHED: usize = 32;
+
+#[derive(Default)]
+struct Jobs {
+    next_id: u64,
+    /**
+     * Jobs that haven't finished, and whether each was cancelled before it started
+     */
+    running: Vec<(DownloadJob, bool)>,
+    completed: VecDeque<DownloadJob>,
+    failed: VecDeque<DownloadJob>,
+}
+
+impl Jobs {
+    fn finish(&mut self, job: DownloadJob) {
+        self.running.retain(|(running, _)| running.id != job.id);
+        let finished = if job.error.is_some() {
+            &mut self.failed
+        } else {
+            &mut self.completed
+        };
+        finished.push_back(job);
+        if finished.len() > MAX_FINISHED {
+            finished.pop_front();
+        }
+    }
+}
+
+lazy_static! {
+    static ref JOBS: Mutex<Jobs> = Mutex::new(Jobs::default());
+}
+
+/**
+ * Queue a game download and return its job ID. If the game already has a job, that job is kept
+ * (raised to this priority if it's higher) and its ID is returned.
+ */
+pub fn enqueue(game_id: &str, priority: DownloadPriority) -> u64 {
+    let mut jobs = JOBS.lock().unwrap();
+    if let Some((job, _)) = jobs
+        .running
+        .iter_mut()
+        .find(|(job, _)| job.game_id == game_id)
+    {
+        job.priority = job.priority.min(priority);
+        let id = job.id;
+        drop(jobs);
+        super::raise_queued(game_id, priority);
+        return id;
+    }
+    jobs.next_id += 1;
+    let job = DownloadJob {
+        id: jobs.next_id,
+        game_id: game_id.to_string(),
+        priority,
+        error: None,
+    };
+    log!(
+        Level::Info,
+        "Queued download of game {} at {:?} priority (job {})",
+        game_id,
+        priority,
+        job.id
+    );
+    jobs.running.push((job.clone(), false));
+    let id = job.id;
+    tokio::spawn(run(job));
+    id
+}
+
+/**
+ * Move a job's download to the front of the queue, at interactive priority
+ *
+ * # Errors
+ * This function will return an error if there's no queued or running job with the ID.
+ */
+pub fn promote(job_id: u64) -> Result<(), Error> {
+    let mut jobs = JOBS.lock().unwrap();
+    let (job, _) = jobs
+        .running
+        .iter_mut()
+        .find(|(job, _)| job.id == job_id)
+        .ok_or_else(|| anyhow!("No queued download with job ID {job_id}"))?;
+    log!(Level::Info, "Promoting download of game {}", job.game_id);
+    job.priority = DownloadPriority::Interactive;
+    let game_id = job.game_id.clone();
+    drop(jobs);
+    promote_queued(game_id.as_str());
+    Ok(())
+}
+
+/**
+ * Cancel a game's job if its download hasn't started tracking yet. Returns whether there was
+ * one.
+ */
+pub fn cancel(game_id: &str) -> bool {
+    let mut jobs = JOBS.lock().unwrap();
+    let Some((_, cancelled)) = jobs
+        .running
+        .iter_mut()
+        .find(|(job, _)| job.game_id == game_id)
+    else {
+        return false;
+    };
+    *cancelled = true;
+    true
+}
+
+/**
+ * The games with a job that hasn't finished
+ */
+pub fn games() -> Vec<String> {
+    JOBS.lock()
+        .unwrap()
+        .running
+        .iter()
+        .map(|(job, _)| job.game_id.clone())
+        .collect()
+}
+
+/**
+ * Whether a game's job was cancelled before its download started tracking
+ */
+pub fn cancelled(game_id: &str) -> bool {
+    JOBS.lock()
+        .unwrap()
+        .running
+        .iter()
+        .any(|(job, cancelled)| job.game_id == game_id && *cancelled)
+}
+
+/**
+ * The priority of a game's job, if it has one, which may have been raised since it was queued
+ */
+pub fn priority(game_id: &str) -> Option<DownloadPriority> {
+    JOBS.lock()
+        .unwrap()
+        .running
+        .iter()
+        .find(|(job, _)| job.game_id == game_id)
+        .map(|(job, _)| job.priority)
+}
+
+async fn run(mut job: DownloadJob) {
+    let result = if cancelled(job.game_id.as_str()) {
+        Err(anyhow!("Cancelled before it started"))
+    } else {
+        download_with_priority(job.game_id.clone(), job.priority).await
+    };
+    if let Err(e) = result {
+        log!(
+            Level::Warn,
+            "Queued download of game {} failed: {}",
+            job.game_id,
+            e
+        );
+        job.error = Some(e.to_string());
+    }
+    JOBS.lock().unwrap().finish(job);
+}
+
+/**
+ * Get the jobs waiting for a download slot, the ones downloading, the ones that finished
+ * recently, and recent conflicts between operations on the same game
+ */
+pub fn state() -> DownloadQueueState {
+    let (running, completed, failed) = {
+        let jobs = JOBS.lock().unwrap();
+        (
+            jobs.running
+                .iter()
+                .map(|(job, _)| job.clone())
+                .collect::<Vec<_>>(),
+            jobs.completed.iter().cloned().collect(),
+            jobs.failed.iter().cloned().collect(),
+        )
+    };
+    // Jobs that haven't started tracking yet are about to join the queue
+    let (mut pending, active): (Vec<_>, Vec<_>) = running.into_iter().partition(|job| {
+        !DOWNLOADS.lock().unwrap().contains_key(&job.game_id)
+            || queue_position(job.game_id.as_str()).is_some()
+    });
+    pending.sort_by_key(|job| {
+        (
+            queue_position(job.game_id.as_str()).unwrap_or(usize::MAX),
+            job.id,
+        )
+    });
+    DownloadQueueState {
+        pending,
+        active,
+        completed,
+        failed,
+        decisions: operations::decisions(),
+    }
+}
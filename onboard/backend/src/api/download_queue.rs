@@ -0,0 +1,373 @@
+use super::{download_jobs, ARCHIVE_SIZES, DOWNLOADS, THROUGHPUT};
+use crate::env::max_game_downloads;
+use anyhow::{anyhow, Error};
+use devcade_onboard_types::{DownloadPriority, DownloadProgress, DownloadStage};
+use lazy_static::lazy_static;
+use log::{log, Level};
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+use std::sync::Mutex;
+
+lazy_static! {
+    // Games whose downloads were cancelled, until the download notices
+    static ref CANCELLED: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+
+    // Game downloads waiting for a slot, in the order they'll get one, and the slots taken
+    static ref GAME_QUEUE: Mutex<GameQueue> = Mutex::new(GameQueue::default());
+    // Notified when a game download slot is freed or the queue changes
+    static ref GAME_QUEUE_CHANGED: tokio::sync::Notify = tokio::sync::Notify::new();
+}
+
+/**
+ * Work out where a queued game download is in the queue, and about how long until it starts: the
+ * rest of the running downloads plus the queued downloads ahead of it, at the recent throughput.
+ * Downloads of unknown size count as the median of the known sizes.
+ */
+pub(super) fn queued_stage(game_id: &str) -> DownloadStage {
+    let queued: Vec<String> = GAME_QUEUE
+        .lock()
+        .unwrap()
+        .waiting
+        .iter()
+        .take_while(|queued| queued.game_id != game_id)
+        .map(|queued| queued.game_id.clone())
+        .collect();
+    let running: Vec<Option<u64>> = DOWNLOADS
+        .lock()
+        .unwrap()
+        .values()
+        .filter(|progress| progress.stage == DownloadStage::Downloading)
+        .map(|progress| {
+            progress
+                .total_bytes
+                .map(|total| total.saturating_sub(progress.bytes_downloaded))
+        })
+        .collect();
+    let queued_sizes: Vec<Option<u64>> = {
+        let sizes = ARCHIVE_SIZES.lock().unwrap();
+        queued
+            .iter()
+            .map(|id| sizes.get(id).map(|(_, size)| *size))
+            .collect()
+    };
+
+    let mut known: Vec<u64> = running
+        .iter()
+        .chain(queued_sizes.iter())
+        .filter_map(|size| *size)
+        .collect();
+    known.sort_unstable();
+    let median = known.get(known.len() / 2).copied();
+    let bytes = running
+        .iter()
+        .chain(queued_sizes.iter())
+        .map(|size| size.or(median))
+        .sum::<Option<u64>>();
+    let seconds = bytes.zip(*THROUGHPUT.lock().unwrap()).map(|(bytes, rate)| {
+        // Truncation is fine, this is an estimate
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let seconds = (bytes as f64 / rate).ceil() as u64;
+        seconds
+    });
+    DownloadStage::Queued {
+        ahead: running.len() + queued.len(),
+        seconds,
+    }
+}
+
+/**
+ * Game downloads waiting for a download slot, and how many slots are taken. Downloads get a slot in
+ * priority order, and in the order they were queued within a priority, up to
+ * `DEVCADE_MAX_GAME_DOWNLOADS` at once.
+ */
+#[derive(Default)]
+struct GameQueue {
+    waiting: VecDeque<QueuedGame>,
+    downloading: usize,
+}
+
+struct QueuedGame {
+    game_id: String,
+    priority: DownloadPriority,
+    /**
+     * Whether the download is ready for a slot. Downloads still looking up their game don't hold
+     * up the ones behind them.
+     */
+    ready: bool,
+}
+
+impl GameQueue {
+    fn insert(&mut self, queued: QueuedGame) {
+        let i = self
+            .waiting
+            .iter()
+            .position(|waiting| waiting.priority > queued.priority)
+            .unwrap_or(self.waiting.len());
+        self.waiting.insert(i, queued);
+    }
+
+    fn remove(&mut self, game_id: &str) -> Option<QueuedGame> {
+        let i = self
+            .waiting
+            .iter()
+            .position(|queued| queued.game_id == game_id)?;
+        self.waiting.remove(i)
+    }
+
+    /**
+     * Whether a game is next in line for a slot, and there's one free
+     */
+    fn can_start(&self, game_id: &str) -> bool {
+        self.downloading < max_game_downloads()
+            && self
+                .waiting
+                .iter()
+                .find(|queued| queued.ready)
+                .is_some_and(|queued| queued.game_id == game_id)
+    }
+}
+
+/**
+ * Raise a waiting game download to a higher priority. Does nothing if the game isn't waiting for a
+ * slot, or is already at that priority or higher.
+ */
+pub(super) fn raise_queued(game_id: &str, priority: DownloadPriority) {
+    let mut queue = GAME_QUEUE.lock().unwrap();
+    let raise = queue
+        .waiting
+        .iter()
+        .any(|queued| queued.game_id == game_id && priority < queued.priority);
+    if !raise {
+        return;
+    }
+    // This unwrap is safe because the game was just found in the queue
+    let mut queued = queue.remove(game_id).unwrap();
+    log!(
+        Level::Debug,
+        "Raising queued download of game {} to {:?} priority",
+        game_id,
+        priority
+    );
+    queued.priority = priority;
+    queue.insert(queued);
+    drop(queue);
+    GAME_QUEUE_CHANGED.notify_waiters();
+}
+
+/**
+ * Move a waiting game download to the front of the queue, at interactive priority. Does nothing if
+ * the game isn't waiting for a slot.
+ */
+pub(super) fn promote_queued(game_id: &str) {
+    let mut queue = GAME_QUEUE.lock().unwrap();
+    if let Some(mut queued) = queue.remove(game_id) {
+        queued.priority = DownloadPriority::Interactive;
+        queue.waiting.push_front(queued);
+    }
+    drop(queue);
+    GAME_QUEUE_CHANGED.notify_waiters();
+}
+
+/**
+ * Where a game download is in the queue, or `None` if it isn't waiting for a slot
+ */
+pub(super) fn queue_position(game_id: &str) -> Option<usize> {
+    GAME_QUEUE
+        .lock()
+        .unwrap()
+        .waiting
+        .iter()
+        .position(|queued| queued.game_id == game_id)
+}
+
+/**
+ * A game download slot, freed when dropped
+ */
+pub(super) struct GameSlot;
+
+impl Drop for GameSlot {
+    fn drop(&mut self) {
+        GAME_QUEUE.lock().unwrap().downloading -= 1;
+        GAME_QUEUE_CHANGED.notify_waiters();
+    }
+}
+
+/**
+ * Publishes a download's progress for `download_progress`, and clears it when dropped, whether the
+ * download finished or failed
+ */
+pub(super) struct DownloadTracker {
+    progress: DownloadProgress,
+}
+
+impl DownloadTracker {
+    /**
+     * Start tracking a game download, which is queued at the given priority until it gets a slot.
+     * A download job for the game that was promoted or cancelled before the download started
+     * carries over.
+     */
+    pub(super) fn new(game_id: &str, priority: DownloadPriority) -> Self {
+        let tracker = Self {
+            progress: DownloadProgress {
+                game_id: game_id.to_string(),
+                bytes_downloaded: 0,
+                total_bytes: None,
+                stage: DownloadStage::Queued {
+                    ahead: 0,
+                    seconds: None,
+                },
+            },
+        };
+        let priority = download_jobs::priority(game_id).map_or(priority, |job| job.min(priority));
+        GAME_QUEUE.lock().unwrap().insert(QueuedGame {
+            game_id: game_id.to_string(),
+            priority,
+            ready: false,
+        });
+        tracker.publish();
+        if download_jobs::cancelled(game_id) {
+            CANCELLED.lock().unwrap().insert(game_id.to_string());
+        }
+        tracker
+    }
+
+    /**
+     * Wait for a download slot, in this download's place in the queue
+     *
+     * # Errors
+     * This function will return `DownloadCancelled` if the download is cancelled while it waits.
+     */
+    pub(super) async fn slot(&self) -> Result<GameSlot, Error> {
+        let game_id = self.progress.game_id.as_str();
+        loop {
+            // Registered before checking, so a change in between isn't missed
+            let changed = GAME_QUEUE_CHANGED.notified();
+            tokio::pin!(changed);
+            changed.as_mut().enable();
+            self.check()?;
+            {
+                let mut queue = GAME_QUEUE.lock().unwrap();
+                if let Some(queued) = queue
+                    .waiting
+                    .iter_mut()
+                    .find(|queued| queued.game_id == game_id)
+                {
+                    queued.ready = true;
+                }
+                if queue.can_start(game_id) {
+                    queue.remove(game_id);
+                    queue.downloading += 1;
+                    return Ok(GameSlot);
+                }
+            }
+            changed.await;
+        }
+    }
+
+    pub(super) fn downloaded(&mut self, bytes_downloaded: u64, total_bytes: Option<u64>) {
+        self.progress.bytes_downloaded = bytes_downloaded;
+        self.progress.total_bytes = total_bytes;
+        self.publish();
+    }
+
+    pub(super) fn stage(&mut self, stage: DownloadStage) {
+        if matches!(self.progress.stage, DownloadStage::Queued { .. }) {
+            self.dequeue();
+        }
+        self.progress.stage = stage;
+        self.publish();
+    }
+
+    fn dequeue(&self) {
+        if GAME_QUEUE
+            .lock()
+            .unwrap()
+            .remove(&self.progress.game_id)
+            .is_some()
+        {
+            GAME_QUEUE_CHANGED.notify_waiters();
+        }
+    }
+
+    fn publish(&self) {
+        DOWNLOADS
+            .lock()
+            .unwrap()
+            .insert(self.progress.game_id.clone(), self.progress.clone());
+    }
+
+    /**
+     * Fail with `DownloadCancelled` if the download was cancelled
+     */
+    pub(super) fn check(&self) -> Result<(), Error> {
+        if CANCELLED.lock().unwrap().contains(&self.progress.game_id) {
+            log!(
+                Level::Info,
+                "Download of game {} was cancelled",
+                self.progress.game_id
+            );
+            return Err(DownloadCancelled {
+                game_id: self.progress.game_id.clone(),
+            }
+            .into());
+        }
+        Ok(())
+    }
+}
+
+impl Drop for DownloadTracker {
+    fn drop(&mut self) {
+        self.dequeue();
+        DOWNLOADS.lock().unwrap().remove(&self.progress.game_id);
+        CANCELLED.lock().unwrap().remove(&self.progress.game_id);
+    }
+}
+
+/**
+ * The error a cancelled download fails with
+ */
+#[derive(Debug, Clone)]
+pub struct DownloadCancelled {
+    pub game_id: String,
+}
+
+impl fmt::Display for DownloadCancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "DownloadCancelled: download of game {} was cancelled",
+            self.game_id
+        )
+    }
+}
+
+impl std::error::Error for DownloadCancelled {}
+
+/**
+ * Cancel a game download or a queued download job
+ *
+ * # Errors
+ * This function will return an error if the game isn't being downloaded or queued.
+ */
+pub(super) fn cancel(game_id: &str) -> Result<(), Error> {
+    // Held so the download can't finish and forget its cancellation in between, or start tracking
+    // without seeing it
+    let downloads = DOWNLOADS.lock().unwrap();
+    if downloads.contains_key(game_id) {
+        log!(Level::Info, "Cancelling download of game {}", game_id);
+        CANCELLED.lock().unwrap().insert(game_id.to_string());
+        drop(downloads);
+        // Wakes the download if it's waiting for a slot
+        GAME_QUEUE_CHANGED.notify_waiters();
+        return Ok(());
+    }
+    if download_jobs::cancel(game_id) {
+        log!(
+            Level::Info,
+            "Cancelled queued download of game {} before it started",
+            game_id
+        );
+        return Ok(());
+    }
+    Err(anyhow!("Game {game_id} isn't being downloaded"))
+}
@@ -0,0 +1,193 @@
+use super::{game_list_from_fs, last_launched, read_manifest, Manifest};
+use crate::env::duplicate_similarity;
+use crate::layout;
+use crate::secrets;
+use anyhow::Error;
+use devcade_onboard_types::{DuplicateGroup, DuplicateInstall};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet};
+
+/**
+ * An install with what's needed to compare it, all from its manifest
+ */
+struct Install {
+    game: DuplicateInstall,
+    fingerprint: String,
+    /// Sizes of the install's files by hash. Files with the same contents are counted once.
+    files: BTreeMap<String, u64>,
+}
+
+impl Install {
+    fn new(game: DuplicateInstall, manifest: &Manifest) -> Self {
+        let hashes: BTreeSet<&str> = manifest.values().map(|file| file.hash.as_str()).collect();
+        let mut hasher = Sha256::new();
+        for hash in &hashes {
+            hasher.update(hash.as_bytes());
+        }
+        Self {
+            game: DuplicateInstall {
+                size: manifest.values().map(|file| file.size).sum(),
+                ..game
+            },
+            fingerprint: secrets::hex(&hasher.finalize()),
+            files: manifest
+                .values()
+                .map(|file| (file.hash.clone(), file.size))
+                .collect(),
+        }
+    }
+
+    /**
+     * Get the share of bytes two installs have in common, out of the bigger one
+     */
+    fn similarity(&self, other: &Self) -> f64 {
+        let total = |files: &BTreeMap<String, u64>| files.values().sum::<u64>();
+        let bigger = total(&self.files).max(total(&other.files));
+        if bigger == 0 {
+            return 0.0;
+        }
+        let shared: u64 = self
+            .files
+            .iter()
+            .filter(|(hash, _)| other.files.contains_key(*hash))
+            .map(|(_, size)| size)
+            .sum();
+        shared as f64 / bigger as f64
+    }
+}
+
+/**
+ * Find the root of an install in the union-find forest
+ */
+fn root(parents: &mut [usize], i: usize) -> usize {
+    let mut i = i;
+    while parents[i] != i {
+        parents[i] = parents[parents[i]];
+        i = parents[i];
+    }
+    i
+}
+
+/**
+ * Group installs that have at least `threshold` of their bytes in common with another in the group
+ */
+fn group(installs: Vec<Install>, threshold: f64) -> Vec<DuplicateGroup> {
+    let mut parents: Vec<usize> = (0..installs.len()).collect();
+    let mut lowest = vec![1.0f64; installs.len()];
+    for a in 0..installs.len() {
+        for b in a + 1..installs.len() {
+            let similarity = if installs[a].fingerprint == installs[b].fingerprint {
+                1.0
+            } else {
+                installs[a].similarity(&installs[b])
+            };
+            if similarity < threshold {
+                continue;
+            }
+            let (root_a, root_b) = (root(&mut parents, a), root(&mut parents, b));
+            parents[root_b] = root_a;
+            lowest[root_a] = lowest[root_a].min(lowest[root_b]).min(similarity);
+        }
+    }
+
+    let mut groups: BTreeMap<usize, Vec<Install>> = BTreeMap::new();
+    for (i, install) in installs.into_iter().enumerate() {
+        let root = root(&mut parents, i);
+        groups.entry(root).or_default().push(install);
+    }
+    groups
+        .into_iter()
+        .filter(|(_, installs)| installs.len() > 1)
+        .map(|(root, installs)| DuplicateGroup {
+            exact: installs
+                .iter()
+                .all(|install| install.fingerprint == installs[0].fingerprint),
+            similarity: lowest[root],
+            games: installs.into_iter().map(|install| install.game).collect(),
+        })
+        .collect()
+}
+
+/**
+ * Find the installed games with the same or mostly the same files. Only manifests are read, never
+ * the files themselves, so installs from before manifests were written are left out.
+ *
+ * # Errors
+ * This function will return an error if the installed games can't be listed.
+ */
+pub fn find() -> Result<Vec<DuplicateGroup>, Error> {
+    let installs = game_list_from_fs()?
+        .into_iter()
+        .filter_map(|game| {
+            let manifest = read_manifest(layout::game_dir(game.id.as_str()).as_path())?;
+            let install = DuplicateInstall {
+                last_launched: last_launched(game.id.as_str()),
+                id: game.id,
+                name: game.name,
+                size: 0,
+            };
+            Some(Install::new(install, &manifest))
+        })
+        .collect();
+    Ok(group(installs, duplicate_similarity()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::extract::ManifestEntry;
+    use super::*;
+
+    fn install(id: &str, files: &[(&str, &str, u64)]) -> Install {
+        let manifest: Manifest = files
+            .iter()
+            .map(|(path, hash, size)| {
+                (
+                    (*path).to_string(),
+                    ManifestEntry {
+                        hash: (*hash).to_string(),
+                        size: *size,
+                    },
+                )
+            })
+            .collect();
+        let game = DuplicateInstall {
+            id: id.to_string(),
+            ..DuplicateInstall::default()
+        };
+        Install::new(game, &manifest)
+    }
+
+    fn ids(group: &DuplicateGroup) -> Vec<&str> {
+        group.games.iter().map(|game| game.id.as_str()).collect()
+    }
+
+    #[test]
+    fn groups_exact_and_similar_installs() {
+        let groups = group(
+            vec![
+                install("pong", &[("game", "a", 90), ("icon.png", "b", 10)]),
+                // Same files under other names
+                install("pong2", &[("pong", "a", 90), ("art/icon.png", "b", 10)]),
+                install("snake", &[("game", "c", 100)]),
+                // Shares 90 of its 100 bytes with pong
+                install("pong3", &[("game", "a", 90), ("icon.png", "d", 10)]),
+                install("tetris", &[("game", "e", 100), ("shared", "c", 100)]),
+            ],
+            0.9,
+        );
+        assert_eq!(groups.len(), 1);
+        assert_eq!(ids(&groups[0]), vec!["pong", "pong2", "pong3"]);
+        assert!(!groups[0].exact);
+        assert!((groups[0].similarity - 0.9).abs() < f64::EPSILON);
+        assert_eq!(groups[0].games[0].size, 100);
+
+        let groups = group(
+            vec![
+                install("pong", &[("game", "a", 90)]),
+                install("pong2", &[("pong", "a", 90)]),
+            ],
+            1.0,
+        );
+        assert!(groups[0].exact);
+    }
+}
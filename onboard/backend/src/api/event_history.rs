@@ -0,0 +1,268 @@
+use crate::clock;
+use devcade_onboard_types::{GapDetected, LaunchEvent, LaunchEventKind, Subscription};
+use log::{log, Level};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::Notify;
+
+/**
+ * How long a subscription waits for an event before answering with none, so a client with nothing
+ * to catch up on isn't left waiting forever on a connection that may have gone
+ */
+pub const SUBSCRIBE_WAIT: Duration = Duration::from_secs(25);
+
+/**
+ * The most recent launch events, numbered in the order they were emitted
+ */
+pub struct History {
+    /// The sequence number of the last event, and the kept events, oldest first
+    events: Mutex<(u64, VecDeque<LaunchEvent>)>,
+    /// Notified after every event
+    emitted: Notify,
+    /// How many events are kept
+    capacity: usize,
+}
+
+impl History {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            events: Mutex::new((0, VecDeque::new())),
+            emitted: Notify::new(),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /**
+     * Record an event, dropping the oldest one if there are too many. Sequence numbers are handed
+     * out under the same lock events are stored under, so they always match the order events
+     * were emitted in.
+     */
+    pub fn emit(&self, kind: LaunchEventKind, event: Option<String>) -> u64 {
+        let seq = {
+            let mut events = self.events.lock().unwrap();
+            events.0 += 1;
+            let seq = events.0;
+            log!(Level::Debug, "Launch event {}: {:?}", seq, kind);
+            events.1.push_back(LaunchEvent { seq, kind, event });
+            if events.1.len() > self.capacity {
+                events.1.pop_front();
+            }
+            seq
+        };
+        self.emitted.notify_waiters();
+        seq
+    }
+
+    /**
+     * Get the kept events with a sequence number after `after`, oldest first
+     */
+    pub fn after(&self, after: u64) -> Vec<LaunchEvent> {
+        self.events
+            .lock()
+            .unwrap()
+            .1
+            .iter()
+            .filter(|event| event.seq > after)
+            .cloned()
+            .collect()
+    }
+
+    /**
+     * Get the sequence number of the last event, or 0 if there hasn't been one
+     */
+    pub fn seq(&self) -> u64 {
+        self.events.lock().unwrap().0
+    }
+
+    /**
+     * Get every kept event, oldest first
+     */
+    pub fn kept(&self) -> Vec<LaunchEvent> {
+        self.after(0)
+    }
+
+    /**
+     * Carry on from the events of the last backend, so clients catch up across a restart. Does
+     * nothing if an event was already emitted, since numbering can't go back.
+     */
+    pub fn restore(&self, seq: u64, kept: Vec<LaunchEvent>) {
+        let mut events = self.events.lock().unwrap();
+        if events.0 != 0 {
+            log!(
+                Level::Warn,
+                "Not restoring launch events from the last backend, {} were already emitted",
+                events.0
+            );
+            return;
+        }
+        events.0 = seq;
+        events.1 = kept.into_iter().filter(|event| event.seq <= seq).collect();
+        while events.1.len() > self.capacity {
+            events.1.pop_front();
+        }
+    }
+
+    /**
+     * Get the kept events after `since`, with a gap if some of them were dropped, or `since` is
+     * ahead of the last event because numbering started over
+     */
+    fn replay(&self, since: u64) -> Subscription {
+        let events = self.events.lock().unwrap();
+        let oldest = events.1.front().map_or(events.0 + 1, |event| event.seq);
+        if since.saturating_add(1) < oldest || since > events.0 {
+            return Subscription {
+                gap: Some(GapDetected {
+                    oldest_available: oldest,
+                }),
+                events: events.1.iter().cloned().collect(),
+            };
+        }
+        Subscription {
+            gap: None,
+            events: events
+                .1
+                .iter()
+                .filter(|event| event.seq > since)
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /**
+     * Get the events after `since`, or after the last one if it's `None`. If there aren't any
+     * yet, waits up to `wait` for the next one. A client that passes the `seq` of the last event
+     * it got each time sees every event once, in order, whatever is emitted while it catches up.
+     */
+    pub async fn subscribe(&self, since: Option<u64>, wait: Duration) -> Subscription {
+        let since = since.unwrap_or_else(|| self.seq());
+        // Registered before checking, so an event in between isn't missed
+        let emitted = self.emitted.notified();
+        tokio::pin!(emitted);
+        emitted.as_mut().enable();
+        let replay = self.replay(since);
+        if replay.gap.is_some() || !replay.events.is_empty() {
+            return replay;
+        }
+        let _ = clock::timeout(wait, emitted).await;
+        self.replay(since)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn seqs(subscription: &Subscription) -> Vec<u64> {
+        subscription.events.iter().map(|event| event.seq).collect()
+    }
+
+    fn emit(history: &History, pid: u32) -> u64 {
+        history.emit(LaunchEventKind::GameSpawned(pid), None)
+    }
+
+    #[tokio::test]
+    async fn replays_missed_events_in_order() {
+        let history = History::new(4);
+        for pid in 1..=3 {
+            emit(&history, pid);
+        }
+        let caught_up = history.subscribe(Some(1), SUBSCRIBE_WAIT).await;
+        assert_eq!(caught_up.gap, None);
+        assert_eq!(seqs(&caught_up), vec![2, 3]);
+        assert_eq!(
+            caught_up.events[1].kind,
+            LaunchEventKind::GameSpawned(3),
+            "events are replayed with what happened"
+        );
+        assert_eq!(
+            seqs(&history.subscribe(Some(0), SUBSCRIBE_WAIT).await),
+            vec![1, 2, 3]
+        );
+        // Nothing new
+        let idle = history.subscribe(Some(3), Duration::from_millis(10)).await;
+        assert_eq!(idle, Subscription::default());
+    }
+
+    #[tokio::test]
+    async fn signals_a_gap_when_events_were_dropped() {
+        let history = History::new(3);
+        for pid in 1..=5 {
+            emit(&history, pid);
+        }
+        let behind = history.subscribe(Some(1), SUBSCRIBE_WAIT).await;
+        assert_eq!(
+            behind.gap,
+            Some(GapDetected {
+                oldest_available: 3
+            })
+        );
+        assert_eq!(seqs(&behind), vec![3, 4, 5]);
+        // Just in time
+        let just_in_time = history.subscribe(Some(2), SUBSCRIBE_WAIT).await;
+        assert_eq!(just_in_time.gap, None);
+        assert_eq!(seqs(&just_in_time), vec![3, 4, 5]);
+
+        // A backend that started over without a handoff
+        let restarted = History::new(3);
+        emit(&restarted, 1);
+        let ahead = restarted.subscribe(Some(5), SUBSCRIBE_WAIT).await;
+        assert_eq!(
+            ahead.gap,
+            Some(GapDetected {
+                oldest_available: 1
+            })
+        );
+        assert_eq!(seqs(&ahead), vec![1]);
+
+        // One that was handed the last one's events
+        let handed_off = History::new(3);
+        handed_off.restore(history.seq(), history.kept());
+        emit(&handed_off, 6);
+        let resumed = handed_off.subscribe(Some(4), SUBSCRIBE_WAIT).await;
+        assert_eq!(resumed.gap, None);
+        assert_eq!(seqs(&resumed), vec![5, 6]);
+    }
+
+    #[tokio::test]
+    async fn live_events_during_catch_up_are_seen_once() {
+        let history = Arc::new(History::new(8));
+        emit(&history, 1);
+        emit(&history, 2);
+        let emitter = {
+            let history = history.clone();
+            tokio::spawn(async move {
+                for pid in 3..=40 {
+                    emit(&history, pid);
+                    tokio::task::yield_now().await;
+                }
+            })
+        };
+
+        let mut seen = Vec::new();
+        let mut since = Some(0);
+        while seen.last() != Some(&40) {
+            let subscription = history.subscribe(since, SUBSCRIBE_WAIT).await;
+            if subscription.gap.is_some() {
+                // Fell too far behind, so start over from what's kept
+                seen.clear();
+            }
+            seen.extend(seqs(&subscription));
+            since = seen.last().copied().or(since);
+        }
+        emitter.await.unwrap();
+        let first = seen[0];
+        assert_eq!(seen, (first..=40).collect::<Vec<_>>());
+
+        // Waiting for a live event
+        let waiting = {
+            let history = history.clone();
+            tokio::spawn(async move { history.subscribe(None, SUBSCRIBE_WAIT).await })
+        };
+        tokio::task::yield_now().await;
+        clock::sleep(Duration::from_millis(20)).await;
+        emit(&history, 41);
+        assert_eq!(seqs(&waiting.await.unwrap()), vec![41]);
+    }
+}
@@ -0,0 +1,395 @@
+use super::signal_game;
+use crate::clock;
+use crate::env::event_hooks;
+use anyhow::{anyhow, Error};
+use devcade_onboard_types::{
+    EventHookOutcome, EventHookStatus, EventHookTest, LaunchEvent, LaunchEventKind,
+};
+use futures_util::future::join_all;
+use lazy_static::lazy_static;
+use log::{log, Level};
+use serde::Deserialize;
+use std::process::Stdio;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader};
+use tokio::process::Command;
+use tokio::sync::Semaphore;
+
+/**
+ * How long a hook may run if it doesn't say
+ */
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/**
+ * How many runs of a hook can wait for one of its slots before events are skipped
+ */
+const MAX_WAITING: usize = 16;
+
+lazy_static! {
+    static ref HOOKS: Vec<Arc<Hook>> = load();
+}
+
+/**
+ * A hook as it's written in the hooks file
+ */
+#[derive(Deserialize)]
+struct HookConfig {
+    name: String,
+    /// The type of launch event it runs on
+    event: String,
+    /// Run with `sh -c`
+    command: String,
+    timeout_secs: Option<u64>,
+    /// How many runs can happen at once. Defaults to 1, so runs happen in the order of events.
+    max_concurrent: Option<usize>,
+}
+
+struct Hook {
+    name: String,
+    event: String,
+    command: String,
+    timeout: Duration,
+    slots: Semaphore,
+    /// Runs waiting for a slot
+    waiting: AtomicUsize,
+    status: Mutex<EventHookStatus>,
+}
+
+/**
+ * Get the type of a launch event, as hooks name it
+ */
+fn event_type(kind: &LaunchEventKind) -> &'static str {
+    match kind {
+        LaunchEventKind::GameTakingFocus(_) => "GameTakingFocus",
+        LaunchEventKind::GameSpawned(_) => "GameSpawned",
+        LaunchEventKind::GameReleasedFocus => "GameReleasedFocus",
+        LaunchEventKind::GameExited(_) => "GameExited",
+        LaunchEventKind::ResourcePressure(_) => "ResourcePressure",
+        LaunchEventKind::VerifyingFiles(..) => "VerifyingFiles",
+        LaunchEventKind::DownloadFinished(_) => "DownloadFinished",
+        LaunchEventKind::CatalogChanged(_) => "CatalogChanged",
+        LaunchEventKind::ScheduleChanged(_) => "ScheduleChanged",
+        LaunchEventKind::QuietHoursChanged(_) => "QuietHoursChanged",
+    }
+}
+
+/**
+ * An event of every type, for trying hooks out
+ */
+fn samples() -> Vec<LaunchEventKind> {
+    let game_id = String::from("sample-game");
+    vec![
+        LaunchEventKind::GameTakingFocus(game_id.clone()),
+        LaunchEventKind::GameSpawned(std::process::id()),
+        LaunchEventKind::GameReleasedFocus,
+        LaunchEventKind::GameExited(Some(0)),
+        LaunchEventKind::ResourcePressure(String::from("rss_mb=1900 (limit 1500)")),
+        LaunchEventKind::VerifyingFiles(50, 120),
+        LaunchEventKind::DownloadFinished(game_id.clone()),
+        LaunchEventKind::CatalogChanged(game_id),
+        LaunchEventKind::ScheduleChanged(Some(String::from("sample-week"))),
+        LaunchEventKind::QuietHoursChanged(Some(String::from("07:00"))),
+    ]
+}
+
+/**
+ * Read the hooks file. A file that can't be read or parsed runs no hooks, and hooks of unknown
+ * event types are left out, with a warning either way.
+ */
+fn load() -> Vec<Arc<Hook>> {
+    let Some(path) = event_hooks() else {
+        return Vec::new();
+    };
+    let configs: Vec<HookConfig> = match std::fs::read(&path)
+        .map_err(Error::from)
+        .and_then(|json| serde_json::from_slice(&json).map_err(Error::from))
+    {
+        Ok(configs) => configs,
+        Err(e) => {
+            log!(Level::Warn, "Not running event hooks from {}: {}", path, e);
+            return Vec::new();
+        }
+    };
+    let known: Vec<&str> = samples().iter().map(event_type).collect();
+    configs
+        .into_iter()
+        .filter(|config| {
+            let is_known = known.contains(&config.event.as_str());
+            if !is_known {
+                log!(
+                    Level::Warn,
+                    "Not running event hook {}, there's no '{}' event",
+                    config.name,
+                    config.event
+                );
+            }
+            is_known
+        })
+        .map(|config| {
+            log!(
+                Level::Info,
+                "Running event hook {} on {} events",
+                config.name,
+                config.event
+            );
+            Arc::new(Hook {
+                status: Mutex::new(EventHookStatus {
+                    name: config.name.clone(),
+                    event: config.event.clone(),
+                    ..EventHookStatus::default()
+                }),
+                name: config.name,
+                event: config.event,
+                command: config.command,
+                timeout: config
+                    .timeout_secs
+                    .map_or(DEFAULT_TIMEOUT, Duration::from_secs),
+                slots: Semaphore::new(config.max_concurrent.unwrap_or(1).max(1)),
+                waiting: AtomicUsize::new(0),
+            })
+        })
+        .collect()
+}
+
+/**
+ * Write a hook's output to the log, a line at a time, tagged with its name
+ */
+async fn log_lines(hook: &str, output: Option<impl AsyncRead + Unpin>, level: Level) {
+    let Some(output) = output else {
+        return;
+    };
+    let mut lines = BufReader::new(output).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        log!(level, "[hook {}] {}", hook, line);
+    }
+}
+
+/**
+ * Run a hook's command with `input` on its stdin, stopping it with the rest of its process group
+ * if it takes longer than the hook's timeout
+ */
+async fn execute(hook: &Hook, input: &[u8]) -> EventHookOutcome {
+    let mut command = Command::new("sh");
+    command
+        .arg("-c")
+        .arg(&hook.command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    // SAFETY: setpgid is async-signal-safe
+    unsafe {
+        command.pre_exec(|| {
+            if libc::setpgid(0, 0) == 0 {
+                Ok(())
+            } else {
+                Err(std::io::Error::last_os_error())
+            }
+        });
+    }
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(e) => return EventHookOutcome::Failed(format!("Couldn't start it: {e}")),
+    };
+    let pid = child.id();
+    let mut stdin = child.stdin.take();
+    let stdout = child.stdout.take();
+    let stderr = child.stderr.take();
+    let run = async {
+        let write = async {
+            if let Some(stdin) = stdin.as_mut() {
+                // A hook that doesn't read the event closes its end early, which is fine
+                let _ = stdin.write_all(input).await;
+            }
+            drop(stdin.take());
+        };
+        tokio::join!(
+            write,
+            log_lines(hook.name.as_str(), stdout, Level::Info),
+            log_lines(hook.name.as_str(), stderr, Level::Warn),
+        );
+        child.wait().await
+    };
+    match clock::timeout(hook.timeout, run).await {
+        Ok(Ok(status)) if status.success() => EventHookOutcome::Succeeded,
+        Ok(Ok(status)) => EventHookOutcome::Failed(format!("It {status}")),
+        Ok(Err(e)) => EventHookOutcome::Failed(format!("Couldn't wait for it: {e}")),
+        Err(_) => {
+            // Anything it started goes too, so a stuck hook can't pile up processes
+            if let Some(pid) = pid {
+                let _ = signal_game(pid, libc::SIGKILL);
+            }
+            EventHookOutcome::TimedOut
+        }
+    }
+}
+
+/**
+ * Count how a run of a hook went
+ */
+fn record(hook: &Hook, outcome: &EventHookOutcome) {
+    let mut status = hook.status.lock().unwrap();
+    status.runs += 1;
+    let error = match outcome {
+        EventHookOutcome::Succeeded => return,
+        EventHookOutcome::Failed(reason) => {
+            status.failures += 1;
+            reason.clone()
+        }
+        EventHookOutcome::TimedOut => {
+            status.timeouts += 1;
+            format!("It didn't finish within {} seconds", hook.timeout.as_secs())
+        }
+    };
+    log!(Level::Warn, "Event hook {} failed: {}", hook.name, error);
+    status.last_error = Some(error);
+}
+
+/**
+ * Run the hooks of a launch event's type on it in the background. This only spawns tasks, so
+ * hooks never hold up the event reaching clients. Each hook runs at most its `max_concurrent`
+ * at once, and events are skipped for a hook that has too many runs waiting.
+ */
+pub fn dispatch(event: &LaunchEvent) {
+    let event_type = event_type(&event.kind);
+    let hooks: Vec<Arc<Hook>> = HOOKS
+        .iter()
+        .filter(|hook| hook.event == event_type)
+        .cloned()
+        .collect();
+    if hooks.is_empty() {
+        return;
+    }
+    let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+        return;
+    };
+    let input: Arc<[u8]> = match serde_json::to_vec(event) {
+        Ok(input) => input.into(),
+        Err(e) => {
+            log!(
+                Level::Warn,
+                "Couldn't serialize launch event for hooks: {}",
+                e
+            );
+            return;
+        }
+    };
+    for hook in hooks {
+        if hook.waiting.fetch_add(1, Ordering::Relaxed) >= MAX_WAITING {
+            hook.waiting.fetch_sub(1, Ordering::Relaxed);
+            hook.status.lock().unwrap().skipped += 1;
+            log!(
+                Level::Warn,
+                "Skipping event hook {} for event {}, {} runs are already waiting",
+                hook.name,
+                event.seq,
+                MAX_WAITING
+            );
+            continue;
+        }
+        let input = input.clone();
+        runtime.spawn(async move {
+            let slot = hook.slots.acquire().await;
+            hook.waiting.fetch_sub(1, Ordering::Relaxed);
+            if slot.is_err() {
+                return;
+            }
+            let outcome = execute(&hook, &input).await;
+            record(&hook, &outcome);
+        });
+    }
+}
+
+/**
+ * Get how each hook has done since startup
+ */
+pub fn status() -> Vec<EventHookStatus> {
+    HOOKS
+        .iter()
+        .map(|hook| hook.status.lock().unwrap().clone())
+        .collect()
+}
+
+/**
+ * Run the hooks of a type of launch event on a sample event, all at once and waiting for them to
+ * finish. Test runs aren't counted in the hooks' status.
+ *
+ * # Errors
+ * This function will return an error if there's no such type of event, or the sample event can't
+ * be serialized.
+ */
+pub async fn test(event_type_name: &str) -> Result<EventHookTest, Error> {
+    let kind = samples()
+        .into_iter()
+        .find(|kind| event_type(kind) == event_type_name)
+        .ok_or_else(|| anyhow!("There's no '{event_type_name}' event"))?;
+    let event = LaunchEvent {
+        seq: 0,
+        kind,
+        event: None,
+    };
+    let input = serde_json::to_vec(&event)?;
+    let runs = join_all(
+        HOOKS
+            .iter()
+            .filter(|hook| hook.event == event_type_name)
+            .map(|hook| async {
+                let outcome = execute(hook, &input).await;
+                (hook.name.clone(), outcome)
+            }),
+    )
+    .await;
+    Ok(EventHookTest { event, runs })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hook(command: &str, timeout: Duration) -> Hook {
+        Hook {
+            name: String::from("test"),
+            event: String::from("DownloadFinished"),
+            command: command.to_string(),
+            timeout,
+            slots: Semaphore::new(1),
+            waiting: AtomicUsize::new(0),
+            status: Mutex::new(EventHookStatus::default()),
+        }
+    }
+
+    #[test]
+    fn every_event_type_has_a_sample() {
+        let types: Vec<&str> = samples().iter().map(event_type).collect();
+        let mut unique = types.clone();
+        unique.sort_unstable();
+        unique.dedup();
+        assert_eq!(unique.len(), types.len());
+    }
+
+    #[tokio::test]
+    async fn hooks_get_the_event_and_are_stopped_when_they_hang() {
+        let event = serde_json::to_vec(&LaunchEvent {
+            seq: 7,
+            kind: LaunchEventKind::DownloadFinished(String::from("pong")),
+            event: None,
+        })
+        .unwrap();
+
+        let reads = hook("grep -q '\"seq\":7'", DEFAULT_TIMEOUT);
+        assert_eq!(execute(&reads, &event).await, EventHookOutcome::Succeeded);
+
+        let fails = hook("echo oops >&2; exit 2", DEFAULT_TIMEOUT);
+        let outcome = execute(&fails, &event).await;
+        assert!(matches!(outcome, EventHookOutcome::Failed(_)));
+        record(&fails, &outcome);
+        assert_eq!(fails.status.lock().unwrap().failures, 1);
+
+        let started = clock::now();
+        let hangs = hook("sleep 30", Duration::from_millis(100));
+        assert_eq!(execute(&hangs, &event).await, EventHookOutcome::TimedOut);
+        assert!(clock::elapsed(started) < Duration::from_secs(10));
+    }
+}
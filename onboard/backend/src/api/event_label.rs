@@ -0,0 +1,105 @@
+use crate::clock;
+use crate::env::event_label_duration;
+use crate::layout;
+use crate::state::JsonState;
+use anyhow::{anyhow, Error};
+use devcade_onboard_types::EventLabel;
+use lazy_static::lazy_static;
+use log::{log, Level};
+use std::path::PathBuf;
+
+/**
+ * The longest event label
+ */
+const MAX_LABEL_LENGTH: usize = 64;
+
+lazy_static! {
+    static ref LABEL: JsonState<Option<EventLabel>> = JsonState::new(path);
+}
+
+fn path() -> PathBuf {
+    layout::state_dir().join("event_label.json")
+}
+
+/**
+ * Get the unix timestamp of the next local midnight
+ */
+fn next_midnight(now: u64) -> u64 {
+    let tm = clock::local_time();
+    let since_midnight = tm.tm_hour * 60 * 60 + tm.tm_min * 60 + tm.tm_sec;
+    now + 24 * 60 * 60 - u64::try_from(since_midnight).unwrap_or(0)
+}
+
+/**
+ * Tag sessions and launch events with a label until the next local midnight, or until
+ * `DEVCADE_EVENT_LABEL_HOURS` have passed if that's sooner. `None` clears the label.
+ *
+ * # Errors
+ * This function will return an error if the label isn't made of letters, digits, `_` and `-`, or
+ * it can't be saved.
+ */
+pub fn set(label: Option<String>) -> Result<Option<EventLabel>, Error> {
+    let label = match label {
+        Some(label) => {
+            let valid = !label.is_empty()
+                && label.len() <= MAX_LABEL_LENGTH
+                && label
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-'));
+            if !valid {
+                return Err(anyhow!(
+                    "Invalid event label '{label}', expected up to {MAX_LABEL_LENGTH} letters, \
+                    digits, '_' or '-'"
+                ));
+            }
+            let now = clock::unix_now();
+            let midnight = next_midnight(now);
+            let expires = event_label_duration()
+                .map_or(midnight, |duration| midnight.min(now + duration.as_secs()));
+            log!(
+                Level::Info,
+                "Tagging sessions with event label '{}' until {}",
+                label,
+                expires
+            );
+            Some(EventLabel {
+                label,
+                set: now,
+                expires,
+            })
+        }
+        None => {
+            log!(Level::Info, "Cleared event label");
+            None
+        }
+    };
+    let mut active = LABEL.lock();
+    *active = label.clone();
+    active.save()?;
+    Ok(label)
+}
+
+/**
+ * Get the active label, clearing it if it expired
+ */
+pub fn get() -> Option<EventLabel> {
+    let mut active = LABEL.lock();
+    if active
+        .as_ref()
+        .is_some_and(|label| label.expires <= clock::unix_now())
+    {
+        log!(Level::Info, "Event label expired");
+        *active = None;
+        if let Err(e) = active.save() {
+            log!(Level::Warn, "Couldn't clear event label: {}", e);
+        }
+    }
+    active.clone()
+}
+
+/**
+ * Get the name of the active label
+ */
+pub fn active() -> Option<String> {
+    get().map(|label| label.label)
+}
@@ -0,0 +1,179 @@
+use super::{
+    current_game, dir_size, download_jobs, free_space, game_list_from_fs, game_running, operations,
+    DOWNLOADS, INSTALLING,
+};
+use crate::clock::unix_now;
+use crate::env::min_free_space;
+use crate::layout;
+use anyhow::{anyhow, Error};
+use devcade_onboard_types::{GameIntent, GameOperation, OperationOrigin};
+use log::{log, Level};
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+/**
+ * Make room to write `size` bytes of a game and still leave `DEVCADE_MIN_FREE_MB` free, by
+ * removing installed games that were launched least recently (never launched ones go first). The
+ * game being installed, the running game, and games with a download in progress or queued are
+ * never removed. If there still isn't enough room,
+ * a warning is logged and the caller decides whether to go ahead.
+ *
+ * # Errors
+ * This function will return an error if the installed games can't be read, or if a game's
+ * directory can't be removed.
+ */
+pub(super) async fn make_room(installing: &str, size: u64) -> Result<(), Error> {
+    let needed = size.saturating_add(min_free_space());
+    let root = layout::root();
+    let Some(mut free) = free_space(root.as_path()) else {
+        return Ok(());
+    };
+    if free >= needed {
+        return Ok(());
+    }
+
+    // Launches wait until the eviction is done, so a game can't start while it's being removed
+    let _installing = INSTALLING.write().await;
+    let running = game_running().then(|| current_game().id);
+    // Other downloads may be writing into their game's directory
+    let mut busy: HashSet<String> = DOWNLOADS.lock().unwrap().keys().cloned().collect();
+    busy.extend(download_jobs::games());
+    let eviction = GameOperation {
+        intent: GameIntent::Remove,
+        origin: OperationOrigin::Background,
+    };
+    let installed = game_list_from_fs()?.into_iter().map(|game| game.id);
+    let candidates = eviction_order(installed, installing, running.as_deref(), &busy);
+
+    for (launched, id) in candidates {
+        if free >= needed {
+            break;
+        }
+        // Games something else is being done with aren't evicted
+        let Some(_evicting) = operations::claim(id.as_str(), eviction) else {
+            continue;
+        };
+        let dir = layout::game_dir(id.as_str());
+        let bytes = dir_size(dir.as_path()).unwrap_or(0);
+        log!(
+            Level::Warn,
+            "Evicting game {} ({} bytes, {}) to install {}: {} bytes free, {} needed",
+            id,
+            bytes,
+            if launched == 0 {
+                String::from("never launched")
+            } else {
+                format!("last launched at {launched}")
+            },
+            installing,
+            free,
+            needed
+        );
+        std::fs::remove_dir_all(&dir)
+            .map_err(|e| anyhow!("Couldn't remove {}: {}", dir.display(), e))?;
+        free = free_space(root.as_path()).unwrap_or(free.saturating_add(bytes));
+    }
+    if free < needed {
+        log!(
+            Level::Warn,
+            "Not enough space to install {} with nothing left to evict: {} bytes free, {} needed",
+            installing,
+            free,
+            needed
+        );
+    }
+    Ok(())
+}
+
+/**
+ * Order the games that may be evicted, least recently launched first with never launched ones
+ * before all of them, each paired with when it was last launched (0 if never). The game being
+ * installed, the running game and busy games are left out.
+ */
+fn eviction_order(
+    installed: impl IntoIterator<Item = String>,
+    installing: &str,
+    running: Option<&str>,
+    busy: &HashSet<String>,
+) -> Vec<(u64, String)> {
+    let mut candidates: Vec<(u64, String)> = installed
+        .into_iter()
+        .filter(|id| id != installing && running != Some(id.as_str()) && !busy.contains(id))
+        .map(|id| (last_launched(id.as_str()).unwrap_or(0), id))
+        .collect();
+    candidates.sort();
+    candidates.dedup();
+    candidates
+}
+
+/**
+ * The file next to a game's game.json holding when it was last launched, in seconds since the Unix
+ * epoch
+ */
+fn last_launched_path(game_id: &str) -> PathBuf {
+    layout::game_dir(game_id).join("last_launched")
+}
+
+/**
+ * Get when a game was last launched, in seconds since the Unix epoch, or `None` if it never was
+ */
+pub(super) fn last_launched(game_id: &str) -> Option<u64> {
+    std::fs::read_to_string(last_launched_path(game_id))
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/**
+ * Record that a game was just launched, for picking which games to evict when space runs low
+ */
+pub(super) fn record_launch(game_id: &str) {
+    let now = unix_now();
+    if let Err(e) = layout::write_atomic(&last_launched_path(game_id), now.to_string()) {
+        log!(
+            Level::Warn,
+            "Couldn't record launch of game {}: {}",
+            game_id,
+            e
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn least_recently_launched_games_are_evicted_first() {
+        let (_guard, root) = crate::testing::root("eviction-order");
+        for (id, launched) in [("pong", "300"), ("snake", "100"), ("tetris", "50")] {
+            layout::write_atomic(&last_launched_path(id), launched).unwrap();
+        }
+        let installed =
+            ["pong", "snake", "tetris", "breakout", "asteroids", "galaga"].map(String::from);
+        let busy = HashSet::from([String::from("asteroids")]);
+
+        // Tetris was launched longest ago, but it's running
+        let order = eviction_order(installed, "galaga", Some("tetris"), &busy);
+        assert_eq!(
+            order,
+            vec![
+                (0, String::from("breakout")),
+                (100, String::from("snake")),
+                (300, String::from("pong")),
+            ]
+        );
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn launches_are_recorded() {
+        let (_guard, root) = crate::testing::root("eviction-launch");
+        assert_eq!(last_launched("pong"), None);
+        let before = unix_now();
+        record_launch("pong");
+        assert!(last_launched("pong").is_some_and(|launched| launched >= before));
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
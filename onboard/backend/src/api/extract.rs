@@ -0,0 +1,891 @@
+use super::ExecutableStrategy;
+use crate::env::prune_patterns;
+use anyhow::{anyhow, Error};
+use log::{log, Level};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/**
+ * Get the total size of the files in a game archive once they're extracted
+ */
+pub(super) fn extracted_size(archive: &mut GameArchive) -> Result<u64, Error> {
+    Ok(archive.entries()?.iter().map(|(_, size)| size).sum())
+}
+
+/**
+ * Names in a game's directory that are used by the backend. Entries at the root of a game's archive
+ * with these names, and anything inside them, are skipped.
+ */
+const RESERVED_NAMES: [&str; 12] = [
+    "game.json",
+    "manifest.json",
+    "setup.json",
+    "sideloaded",
+    "last_launched",
+    "icon.png",
+    "banner.png",
+    "icon.png.hash",
+    "banner.png.hash",
+    "icon.png.etag",
+    "banner.png.etag",
+    "icon.thumb.png",
+];
+
+/**
+ * The result of extracting a game archive
+ */
+#[derive(Default)]
+pub(super) struct Extraction {
+    /**
+     * Problems with individual entries that were skipped
+     */
+    pub(super) warnings: Vec<String>,
+    /**
+     * Entries that couldn't be written, which leave the extraction incomplete
+     */
+    pub(super) errors: Vec<String>,
+    /**
+     * Entries that weren't extracted because they matched a prune pattern
+     */
+    pub(super) pruned: Vec<String>,
+    /**
+     * The uncompressed size of the pruned entries, in bytes
+     */
+    pub(super) pruned_bytes: u64,
+    /**
+     * The files that were extracted, or linked from the previous install
+     */
+    pub(super) manifest: Manifest,
+    /**
+     * How many unchanged files were linked from the previous install instead of written, and
+     * their size in bytes
+     */
+    pub(super) reused: usize,
+    pub(super) reused_bytes: u64,
+}
+
+/**
+ * A file of an install, as it was extracted
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub(super) struct ManifestEntry {
+    /**
+     * The SHA-256 hash of the file, in hex
+     */
+    pub(super) hash: String,
+    pub(super) size: u64,
+}
+
+/**
+ * The files of an install by path, written to `manifest.json` in the game's directory. Installs
+ * from before manifests were written don't have one, and are replaced in full when updated.
+ */
+pub(super) type Manifest = BTreeMap<String, ManifestEntry>;
+
+/**
+ * Files up to this size are hashed in memory before they're written, so an unchanged one can be
+ * linked from the previous install instead of written to the SD card again. Bigger files are
+ * always written.
+ */
+const REUSE_LIMIT: u64 = 16 * 1024 * 1024;
+
+/**
+ * Read the manifest of the install in a game's directory, or `None` if it doesn't have one
+ */
+pub(super) fn read_manifest(dir: &Path) -> Option<Manifest> {
+    let json = std::fs::read(dir.join("manifest.json")).ok()?;
+    serde_json::from_slice(&json)
+        .inspect_err(|e| {
+            log!(
+                Level::Warn,
+                "Ignoring unreadable manifest in {}: {}",
+                dir.display(),
+                e
+            )
+        })
+        .ok()
+}
+
+pub(super) fn to_hex(digest: &[u8]) -> String {
+    digest.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/**
+ * Passes writes through to a file, hashing them on the way
+ */
+struct HashingWriter {
+    file: std::fs::File,
+    hasher: sha2::Sha256,
+}
+
+impl std::io::Write for HashingWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        use sha2::Digest;
+
+        let written = self.file.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/**
+ * Write an archive entry to `out_path`, hashing it on the way
+ */
+fn write_entry(file: &mut dyn Read, out_path: &Path) -> Result<ManifestEntry, Error> {
+    use sha2::{Digest, Sha256};
+
+    let mut writer = HashingWriter {
+        file: std::fs::File::create(out_path)?,
+        hasher: Sha256::new(),
+    };
+    let size = std::io::copy(file, &mut writer)?;
+    Ok(ManifestEntry {
+        hash: to_hex(&writer.hasher.finalize()),
+        size,
+    })
+}
+
+/**
+ * Hash an archive entry in memory, then link the previous install's copy of it to `out_path` if
+ * it's unchanged, or write it if it isn't. Returns the entry as it goes in the manifest, and
+ * whether the previous copy was used.
+ */
+fn reuse_entry(
+    file: &mut dyn Read,
+    out_path: &Path,
+    previous: &Path,
+    expected: &ManifestEntry,
+) -> Result<(ManifestEntry, bool), Error> {
+    use sha2::{Digest, Sha256};
+
+    let mut data = Vec::new();
+    file.read_to_end(&mut data)?;
+    let entry = ManifestEntry {
+        hash: to_hex(&Sha256::digest(&data)),
+        size: data.len() as u64,
+    };
+    if entry == *expected {
+        match std::fs::hard_link(previous, out_path) {
+            Ok(()) => return Ok((entry, true)),
+            Err(e) => log!(
+                Level::Debug,
+                "Couldn't link {} from the previous install, writing it instead: {}",
+                previous.display(),
+                e
+            ),
+        }
+    }
+    std::fs::write(out_path, &data)?;
+    Ok((entry, false))
+}
+
+/**
+ * An entry of a game archive, as it's read
+ */
+enum ArchiveEntry<'a> {
+    /**
+     * A file or directory, and the file's contents
+     */
+    Contents(&'a mut dyn Read),
+    /**
+     * A symbolic link, and the path it points to
+     */
+    Symlink(String),
+    /**
+     * A hard link or special file, which can't be extracted
+     */
+    Unsupported,
+}
+
+/**
+ * A downloaded game archive. Games are published as zip files, or as gzipped tarballs by authors
+ * who build on Linux, and install the same either way.
+ */
+pub(super) enum GameArchive {
+    Zip(zip::ZipArchive<std::fs::File>),
+    /**
+     * Tarballs can't be read out of order, so they're read from the start of the file each time
+     */
+    TarGz(PathBuf),
+}
+
+impl GameArchive {
+    /**
+     * Open a game archive, telling the format from its first bytes, since the API doesn't say which
+     * format it's sending
+     *
+     * # Errors
+     * This function will return an error if the file cannot be read, or isn't a zip file or a
+     * gzipped tarball.
+     */
+    pub(super) fn open(path: &Path) -> Result<Self, Error> {
+        match Self::extension(path)? {
+            "zip" => Ok(Self::Zip(zip::ZipArchive::new(std::fs::File::open(path)?)?)),
+            _ => Ok(Self::TarGz(path.to_path_buf())),
+        }
+    }
+
+    /**
+     * Get the file extension for an archive's format (`zip` or `tar.gz`), telling the format from
+     * its first bytes
+     */
+    pub(super) fn extension(path: &Path) -> Result<&'static str, Error> {
+        let mut magic = Vec::with_capacity(2);
+        std::fs::File::open(path)?.take(2).read_to_end(&mut magic)?;
+        match magic.as_slice() {
+            b"PK" => Ok("zip"),
+            [0x1f, 0x8b] => Ok("tar.gz"),
+            _ => Err(anyhow!(
+                "Archive {} isn't a zip file or a gzipped tarball",
+                path.to_str().unwrap()
+            )),
+        }
+    }
+
+    fn tarball(path: &Path) -> Result<tar::Archive<flate2::read::GzDecoder<std::fs::File>>, Error> {
+        Ok(tar::Archive::new(flate2::read::GzDecoder::new(
+            std::fs::File::open(path)?,
+        )))
+    }
+
+    /**
+     * Get the name of a tarball entry the way a zip file would name it, with a trailing `/` for
+     * directories
+     */
+    fn tar_name<R: Read>(entry: &tar::Entry<R>) -> String {
+        let mut name = String::from_utf8_lossy(&entry.path_bytes()).into_owned();
+        if entry.header().entry_type().is_dir() && !name.ends_with('/') {
+            name.push('/');
+        }
+        name
+    }
+
+    /**
+     * List the archive's entries in order, as their raw names and uncompressed sizes
+     */
+    pub(super) fn entries(&mut self) -> Result<Vec<(String, u64)>, Error> {
+        match self {
+            Self::Zip(zip) => (0..zip.len())
+                .map(|i| {
+                    let file = zip.by_index_raw(i)?;
+                    Ok((file.name().to_string(), file.size()))
+                })
+                .collect(),
+            Self::TarGz(path) => {
+                let mut entries = Vec::new();
+                for entry in Self::tarball(path)?.entries()? {
+                    let entry = entry?;
+                    entries.push((Self::tar_name(&entry), entry.size()));
+                }
+                Ok(entries)
+            }
+        }
+    }
+
+    /**
+     * Get the compressed size of the archive's entries. Tarballs are compressed as a whole, so this
+     * is the size of the file.
+     */
+    pub(super) fn compressed_size(&mut self) -> Result<u64, Error> {
+        match self {
+            Self::Zip(zip) => {
+                let mut size = 0;
+                for i in 0..zip.len() {
+                    size += zip.by_index_raw(i)?.compressed_size();
+                }
+                Ok(size)
+            }
+            Self::TarGz(path) => Ok(std::fs::metadata(path)?.len()),
+        }
+    }
+
+    /**
+     * Read the archive's entries in the same order as `entries`, calling `visit` with each one's
+     * index and contents, or the reason it can't be read. Symlinks in zip files are stored as files
+     * whose contents are the target, marked by their unix mode. Reading stops if `visit` returns
+     * an error.
+     */
+    fn visit(
+        &mut self,
+        mut visit: impl FnMut(usize, Result<ArchiveEntry, Error>) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        const S_IFMT: u32 = 0o170_000;
+        const S_IFLNK: u32 = 0o120_000;
+
+        match self {
+            Self::Zip(zip) => {
+                for i in 0..zip.len() {
+                    match zip.by_index(i) {
+                        Ok(mut file) if file.unix_mode().unwrap_or(0) & S_IFMT == S_IFLNK => {
+                            let mut target = String::new();
+                            match file.read_to_string(&mut target) {
+                                Ok(_) => visit(i, Ok(ArchiveEntry::Symlink(target)))?,
+                                Err(e) => visit(i, Err(e.into()))?,
+                            }
+                        }
+                        Ok(mut file) => visit(i, Ok(ArchiveEntry::Contents(&mut file)))?,
+                        Err(e) => visit(i, Err(e.into()))?,
+                    }
+                }
+            }
+            Self::TarGz(path) => {
+                for (i, entry) in Self::tarball(path)?.entries()?.enumerate() {
+                    match entry {
+                        Ok(mut entry) => match entry.header().entry_type() {
+                            tar::EntryType::Regular
+                            | tar::EntryType::Continuous
+                            | tar::EntryType::Directory => {
+                                visit(i, Ok(ArchiveEntry::Contents(&mut entry)))?;
+                            }
+                            tar::EntryType::Symlink => match entry.link_name_bytes() {
+                                Some(target) => visit(
+                                    i,
+                                    Ok(ArchiveEntry::Symlink(
+                                        String::from_utf8_lossy(&target).into_owned(),
+                                    )),
+                                )?,
+                                None => visit(i, Err(anyhow!("Symlink has no target")))?,
+                            },
+                            _ => visit(i, Ok(ArchiveEntry::Unsupported))?,
+                        },
+                        Err(e) => {
+                            // The rest of the tarball can't be trusted after a bad entry
+                            visit(i, Err(e.into()))?;
+                            break;
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/**
+ * Extracts a game archive into a directory. Entries that can't be read or written, including hard
+ * links and special files, are logged and returned as errors instead of stopping the extraction,
+ * and reserved entries are skipped with a warning. Entries matching `DEVCADE_PRUNE_PATTERNS`
+ * (debug symbols, VCS directories, builds for other platforms) are skipped, and are the only
+ * entries that may fail without making the extraction incomplete. `progress` is called with the
+ * number of entries handled so far and the total, and the extraction stops if it returns an error.
+ *
+ * Symlinks (like versioned `.so` files) are created after everything else, so no file is written
+ * through one. A symlink whose target is absolute or outside of `dest` is an error, as is one that
+ * ends up resolving outside of it through other symlinks. Symlinks to nothing are skipped with a
+ * warning.
+ *
+ * With `previous`, the directory and manifest of the install being updated, files that haven't
+ * changed since it are hard linked from it instead of written again.
+ *
+ * # Errors
+ * This function will return an error, before extracting anything, if the archive can't be listed or
+ * any entry's path would end up outside of `dest`, or the error `progress` returned.
+ */
+pub(super) fn extract_archive(
+    archive: &mut GameArchive,
+    dest: &Path,
+    previous: Option<(&Path, &Manifest)>,
+    mut progress: impl FnMut(usize, usize) -> Result<(), Error>,
+) -> Result<Extraction, Error> {
+    // Check every name up front, so a malicious archive doesn't get partially extracted
+    let entries = archive.entries()?;
+    let mut names = Vec::with_capacity(entries.len());
+    for (raw, size) in entries {
+        let name = entry_name(raw.as_str())
+            .ok_or_else(|| anyhow!("Archive entry {raw:?} points outside of the game directory"))?;
+        names.push((name, size));
+    }
+    let total = names.len();
+    // Symlinks and their targets, created once every other entry has been written
+    let mut links = Vec::new();
+
+    let prune_patterns = prune_patterns();
+    let mut extraction = Extraction::default();
+    let mut warn = |warning: String| {
+        log!(Level::Warn, "{}", warning);
+        extraction.warnings.push(warning);
+    };
+    let mut fail = |error: String| {
+        log!(Level::Error, "{}", error);
+        extraction.errors.push(error);
+    };
+
+    archive.visit(|i, file| {
+        progress(i, total)?;
+        let Some((name, size)) = names.get(i) else {
+            return Ok(());
+        };
+        if name.is_empty() {
+            return Ok(());
+        }
+        if RESERVED_NAMES.contains(&name.split('/').next().unwrap_or_default()) {
+            warn(format!(
+                "Skipping {}, which is reserved for the backend",
+                name.as_str()
+            ));
+            return Ok(());
+        }
+        // The prune patterns are the only entries a game can do without, anything else that
+        // can't be extracted would leave the install broken
+        if is_pruned(&prune_patterns, name.as_str()) {
+            log!(Level::Trace, "Pruning {}", name.as_str());
+            extraction.pruned.push(name.clone());
+            extraction.pruned_bytes += size;
+            return Ok(());
+        }
+        let file = match file {
+            Ok(ArchiveEntry::Contents(f)) => f,
+            Ok(ArchiveEntry::Symlink(target)) => {
+                if link_stays_inside(name.as_str(), target.as_str()) {
+                    links.push((name.clone(), target));
+                } else {
+                    fail(format!(
+                        "Symlink {} points outside of the game directory ({})",
+                        name.as_str(),
+                        target
+                    ));
+                }
+                return Ok(());
+            }
+            Ok(ArchiveEntry::Unsupported) => {
+                fail(format!(
+                    "Can't extract {}, hard links and special files aren't supported",
+                    name.as_str()
+                ));
+                return Ok(());
+            }
+            Err(e) => {
+                fail(format!("Error reading {} from archive: {e}", name.as_str()));
+                return Ok(());
+            }
+        };
+        let out_path = dest.join(name.as_str());
+        log!(
+            Level::Trace,
+            "Extracting file {} to {}",
+            name.as_str(),
+            out_path.to_str().unwrap()
+        );
+        if name.as_str().ends_with('/') {
+            if let Err(e) = std::fs::create_dir_all(&out_path) {
+                fail(format!(
+                    "Error creating directory {}: {}",
+                    out_path.to_str().unwrap(),
+                    e
+                ));
+            }
+        } else {
+            if let Some(p) = out_path.parent() {
+                if !p.exists() {
+                    if let Err(e) = std::fs::create_dir_all(p) {
+                        fail(format!(
+                            "Error creating directory {} for {}: {}",
+                            p.to_str().unwrap(),
+                            name.as_str(),
+                            e
+                        ));
+                        return Ok(());
+                    }
+                }
+            }
+            let reusable = previous.and_then(|(dir, manifest)| {
+                manifest
+                    .get(name.as_str())
+                    .filter(|entry| entry.size == *size && *size <= REUSE_LIMIT)
+                    .map(|entry| (dir.join(name.as_str()), entry))
+            });
+            let written = match reusable {
+                Some((existing, expected)) => reuse_entry(file, &out_path, &existing, expected),
+                None => write_entry(file, &out_path).map(|entry| (entry, false)),
+            };
+            match written {
+                Ok((entry, reused)) => {
+                    if reused {
+                        extraction.reused += 1;
+                        extraction.reused_bytes += entry.size;
+                    }
+                    extraction.manifest.insert(name.clone(), entry);
+                }
+                Err(e) => fail(format!(
+                    "Error writing file {}: {}",
+                    out_path.to_str().unwrap(),
+                    e
+                )),
+            }
+        }
+        Ok(())
+    })?;
+
+    let root = dest.canonicalize()?;
+    for (name, target) in links {
+        let link = dest.join(name.as_str());
+        log!(Level::Trace, "Linking {} to {}", name.as_str(), target);
+        let created = link
+            .parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|()| std::os::unix::fs::symlink(target.as_str(), &link));
+        if let Err(e) = created {
+            fail(format!("Error creating symlink {}: {}", name.as_str(), e));
+            continue;
+        }
+        // Each target stays inside on its own, but a chain of symlinks with `..` can still escape
+        match link.canonicalize() {
+            Ok(resolved) if resolved.starts_with(&root) => {}
+            Ok(resolved) => {
+                let _ = std::fs::remove_file(&link);
+                fail(format!(
+                    "Symlink {} resolves outside of the game directory ({})",
+                    name.as_str(),
+                    resolved.display()
+                ));
+            }
+            Err(_) => {
+                let _ = std::fs::remove_file(&link);
+                warn(format!(
+                    "Skipping symlink {}, its target {} doesn't exist",
+                    name.as_str(),
+                    target
+                ));
+            }
+        }
+    }
+    progress(total, total)?;
+
+    if !extraction.pruned.is_empty() {
+        log!(
+            Level::Info,
+            "Pruned {} entries ({} bytes) from archive",
+            extraction.pruned.len(),
+            extraction.pruned_bytes
+        );
+    }
+    Ok(extraction)
+}
+
+/**
+ * Whether a symlink entry's target is relative and stays inside the directory it's extracted into,
+ * going by its path alone
+ */
+fn link_stays_inside(name: &str, target: &str) -> bool {
+    if target.is_empty() || target.starts_with('/') {
+        return false;
+    }
+    let path = match name.rsplit_once('/') {
+        Some((parent, _)) => format!("{parent}/{target}"),
+        None => target.to_string(),
+    };
+    entry_name(path.as_str()).is_some()
+}
+
+/**
+ * Normalize an archive entry's name into a relative path, with `/` separators and no `.` or `..`
+ * components. Archives built on Windows may use `\` as the separator. Entries that refer to
+ * the directory itself (like `./`) normalize to an empty name. Returns `None` if the entry is
+ * absolute or would escape the directory it's extracted into.
+ */
+fn entry_name(raw: &str) -> Option<String> {
+    if raw.contains('\0') {
+        return None;
+    }
+    let name = raw.replace('\\', "/");
+    let first = name.split('/').next().unwrap_or_default();
+    // Absolute paths, and Windows drive prefixes like `C:`
+    if name.starts_with('/') || first.ends_with(':') {
+        return None;
+    }
+
+    let mut components = Vec::new();
+    for component in name.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => {
+                components.pop()?;
+            }
+            component => components.push(component),
+        }
+    }
+    let mut normalized = components.join("/");
+    if name.ends_with('/') && !normalized.is_empty() {
+        normalized.push('/');
+    }
+    Some(normalized)
+}
+
+/**
+ * Whether an archive entry matches one of the prune patterns. Patterns ending in a `**` path
+ * component match everything inside a directory with a matching name, other patterns match file
+ * names. `*` matches any number of characters.
+ */
+fn is_pruned(patterns: &[String], name: &str) -> bool {
+    let is_dir = name.ends_with('/');
+    let components: Vec<&str> = name.trim_end_matches('/').split('/').collect();
+    // This unwrap is safe because split always returns at least one component
+    let (last, parents) = components.split_last().unwrap();
+    patterns
+        .iter()
+        .any(|pattern| match pattern.strip_suffix("/**") {
+            Some(dir) => {
+                parents.iter().any(|c| wildcard_match(dir, c))
+                    || (is_dir && wildcard_match(dir, last))
+            }
+            None => !is_dir && wildcard_match(pattern, last),
+        })
+}
+
+/**
+ * Match a name against a pattern where `*` matches any number of characters
+ */
+pub(super) fn wildcard_match(pattern: &str, name: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == name,
+        Some((prefix, rest)) => name.strip_prefix(prefix).is_some_and(|name| {
+            (0..=name.len())
+                .filter(|&i| name.is_char_boundary(i))
+                .any(|i| wildcard_match(rest, &name[i..]))
+        }),
+    }
+}
+
+/**
+ * Finds the executable of a game in its `publish` directory. The executable name is inferred from
+ * a `*.runtimeconfig.json` file if there is one (.NET games), otherwise a file with the same name
+ * as the game is used.
+ *
+ * # Errors
+ * This function will return an error if the directory cannot be read, or if the executable does not
+ * exist.
+ */
+pub(super) fn find_executable(
+    publish: &Path,
+    game_name: &str,
+) -> Result<(PathBuf, ExecutableStrategy), Error> {
+    for entry in std::fs::read_dir(publish)? {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(_) => continue,
+        };
+        let path = entry.path();
+        if !path.is_file() {
+            continue;
+        }
+
+        if let Some(filename) = path.file_name().map(|s| s.to_str().unwrap_or("")) {
+            if !filename.ends_with("runtimeconfig.json") {
+                continue;
+            }
+            log!(Level::Debug, "Found runtimeconfig.json file: {}", filename);
+            let executable = path
+                .file_prefix()
+                .unwrap_or(OsStr::new(""))
+                .to_str()
+                .unwrap_or("")
+                .to_string();
+            log!(
+                Level::Debug,
+                "Executable inferred from runtimeconfig.json: {}",
+                executable
+            );
+            let path = publish.join(executable);
+            if !path.exists() {
+                return Err(anyhow!("Game executable not found"));
+            }
+            return Ok((path, ExecutableStrategy::RuntimeConfig));
+        }
+    }
+
+    // If no *.runtimeconfig.json file is found, look for a file with the same name as the game
+    // (this is the case for games that don't use .NET)
+    // TODO: Some better way to find executable name?
+    let path = publish.join(game_name);
+    if !path.exists() {
+        return Err(anyhow!("Game executable not found"));
+    }
+    Ok((path, ExecutableStrategy::GameName))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entry_names_stay_inside_the_game_directory() {
+        assert_eq!(entry_name("publish/game").as_deref(), Some("publish/game"));
+        assert_eq!(entry_name("publish\\game").as_deref(), Some("publish/game"));
+        assert_eq!(entry_name("a/../b").as_deref(), Some("b"));
+        assert_eq!(entry_name("./").as_deref(), Some(""));
+        assert_eq!(entry_name("../evil"), None);
+        assert_eq!(entry_name("/etc/passwd"), None);
+        assert_eq!(entry_name("C:/evil"), None);
+    }
+
+    /**
+     * Write a zip file with these entries
+     */
+    fn zip(path: &Path, entries: &[(&str, &[u8])]) -> GameArchive {
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(path).unwrap());
+        for (name, contents) in entries {
+            zip.start_file(*name, zip::write::FileOptions::default())
+                .unwrap();
+            std::io::Write::write_all(&mut zip, contents).unwrap();
+        }
+        zip.finish().unwrap();
+        GameArchive::open(path).unwrap()
+    }
+
+    /**
+     * Write a gzipped tarball with these entries. Names are written into the header as they are,
+     * since the tar crate refuses to build entries with `..` in them.
+     */
+    fn tarball(path: &Path, entries: &[(&str, &[u8])]) -> GameArchive {
+        let gz = flate2::write::GzEncoder::new(
+            std::fs::File::create(path).unwrap(),
+            flate2::Compression::default(),
+        );
+        let mut tar = tar::Builder::new(gz);
+        for (name, contents) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_size(contents.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            tar.append(&header, *contents).unwrap();
+        }
+        tar.into_inner().unwrap().finish().unwrap();
+        GameArchive::open(path).unwrap()
+    }
+
+    #[test]
+    fn zips_and_tarballs_install_the_same() {
+        let (_guard, root) = crate::testing::root("extract-formats");
+        let entries: &[(&str, &[u8])] = &[
+            ("publish/game", b"game"),
+            ("publish/README", b"Press start"),
+            ("publish/data/level.dat", b"LEVEL"),
+        ];
+        let mut zipped = zip(&root.join("pong.zip"), entries);
+        let mut tarred = tarball(&root.join("pong.tar.gz"), entries);
+        assert!(matches!(zipped, GameArchive::Zip(_)));
+        assert!(matches!(tarred, GameArchive::TarGz(_)));
+        assert_eq!(zipped.entries().unwrap(), tarred.entries().unwrap());
+        assert_eq!(
+            extracted_size(&mut zipped).unwrap(),
+            extracted_size(&mut tarred).unwrap()
+        );
+
+        let from_zip = root.join("zip");
+        let from_tar = root.join("tar");
+        let zip_extraction = extract_archive(&mut zipped, &from_zip, None, |_, _| Ok(())).unwrap();
+        let tar_extraction = extract_archive(&mut tarred, &from_tar, None, |_, _| Ok(())).unwrap();
+        assert!(zip_extraction.errors.is_empty());
+        assert!(tar_extraction.errors.is_empty());
+        assert_eq!(zip_extraction.manifest, tar_extraction.manifest);
+        assert_eq!(zip_extraction.manifest.len(), entries.len());
+        for (name, contents) in entries {
+            assert_eq!(std::fs::read(from_zip.join(name)).unwrap(), *contents);
+            assert_eq!(std::fs::read(from_tar.join(name)).unwrap(), *contents);
+        }
+
+        // Tarballs get the same path checks
+        let mut evil = tarball(
+            &root.join("evil.tar.gz"),
+            &[("publish/game", b"game"), ("../../evil", b"evil")],
+        );
+        let dest = root.join("evil");
+        assert!(extract_archive(&mut evil, &dest, None, |_, _| Ok(())).is_err());
+        assert!(!dest.join("publish/game").exists());
+        assert!(!root.parent().unwrap().join("evil").exists());
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn crafted_archives_fail_without_extracting_anything() {
+        let (_guard, root) = crate::testing::root("extract-traversal");
+        let dest = root.join("games/pong");
+        for evil in [
+            "../../home/devcade/.bashrc",
+            "publish/../../../evil",
+            "/etc/evil",
+            "..\\..\\evil",
+            "publish\\..\\..\\evil",
+        ] {
+            let path = root.join("evil.zip");
+            let mut archive = zip(&path, &[("publish/game", b"game"), (evil, b"evil")]);
+            let result = extract_archive(&mut archive, &dest, None, |_, _| Ok(()));
+            assert!(result.is_err(), "{evil}");
+            assert!(!dest.join("publish/game").exists(), "{evil}");
+            assert!(!root.join("evil").exists(), "{evil}");
+        }
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn backslash_separators_are_extracted_as_directories() {
+        let (_guard, root) = crate::testing::root("extract-backslash");
+        let dest = root.join("games/pong");
+        let mut archive = zip(
+            &root.join("pong.zip"),
+            &[
+                ("publish\\data\\level.dat", b"LEVEL"),
+                ("publish/game", b"game"),
+            ],
+        );
+        let extraction = extract_archive(&mut archive, &dest, None, |_, _| Ok(())).unwrap();
+        assert!(extraction.errors.is_empty());
+        assert_eq!(
+            std::fs::read(dest.join("publish/data/level.dat")).unwrap(),
+            b"LEVEL"
+        );
+        assert!(extraction.manifest.contains_key("publish/data/level.dat"));
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn symlink_entries_are_linked_only_inside_the_game_directory() {
+        let (_guard, root) = crate::testing::root("extract-symlinks");
+        let dest = root.join("games/pong");
+        let path = root.join("links.zip");
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(&path).unwrap());
+        let options = zip::write::FileOptions::default();
+        zip.start_file("publish/libgame.so.1", options).unwrap();
+        std::io::Write::write_all(&mut zip, b"ELF").unwrap();
+        zip.add_symlink("publish/libgame.so", "libgame.so.1", options)
+            .unwrap();
+        zip.add_symlink("publish/absolute.so", "/etc/passwd", options)
+            .unwrap();
+        zip.add_symlink("publish/escaping.so", "../../../etc/passwd", options)
+            .unwrap();
+        // Each stays inside on its own, but together they climb out
+        zip.add_symlink("publish/up", "..", options).unwrap();
+        zip.add_symlink("publish/chained.so", "up/..", options)
+            .unwrap();
+        zip.finish().unwrap();
+
+        let mut archive = GameArchive::open(&path).unwrap();
+        let extraction = extract_archive(&mut archive, &dest, None, |_, _| Ok(())).unwrap();
+        let link = dest.join("publish/libgame.so");
+        assert!(link.symlink_metadata().unwrap().is_symlink());
+        assert_eq!(std::fs::read(&link).unwrap(), b"ELF");
+        for name in ["absolute.so", "escaping.so", "chained.so"] {
+            assert!(dest.join("publish").join(name).symlink_metadata().is_err());
+            assert!(extraction.errors.iter().any(|e| e.contains(name)), "{name}");
+        }
+        assert_eq!(extraction.errors.len(), 3);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn symlinks_must_point_inside_the_game_directory() {
+        assert!(link_stays_inside("publish/lib/a.so", "b.so"));
+        assert!(link_stays_inside("publish/lib/a.so", "../game"));
+        assert!(!link_stays_inside("publish/a.so", "../../etc/passwd"));
+        assert!(!link_stays_inside("publish/a.so", "/etc/passwd"));
+        assert!(!link_stays_inside("publish/a.so", ""));
+    }
+}
@@ -0,0 +1,219 @@
+use super::installed_game;
+use super::runtime::list_files;
+use crate::layout;
+use crate::state::JsonState;
+use devcade_onboard_types::{FeatureAdoption, FeatureUsage, RequestBody};
+use lazy_static::lazy_static;
+use log::{log, Level};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/**
+ * The variables games are launched with. Keep this in sync with `launch_game`.
+ */
+const GAME_ENV_VARS: &[&str] = &[
+    "DEVCADE_LOCALE",
+    "DEVCADE_TZ",
+    "DEVCADE_CABINET_SETTINGS",
+    "DEVCADE_SAVE_NAMESPACE",
+    "DEVCADE_DEMO",
+    "DEVCADE_PLAYER_NAME",
+    "DEVCADE_PEER_LOCAL_TOKEN",
+    "DEVCADE_PEER_HOST",
+    "DEVCADE_PEER_TOKEN",
+    "DEVCADE_PEER_PLAYERS",
+];
+
+/**
+ * The largest file searched for variable names. Bigger files are mostly assets.
+ */
+const MAX_SCANNED_BYTES: u64 = 64 * 1024 * 1024;
+
+/**
+ * Kinds of requests games send, by their bit in `USED`
+ */
+const REQUEST_KINDS: &[&str] = &[
+    "saves",
+    "streamed saves",
+    "session summary",
+    "cabinet hardware",
+    "cabinet settings",
+];
+
+lazy_static! {
+    // Feature usage by game ID
+    static ref USAGE: JsonState<BTreeMap<String, FeatureUsage>> = JsonState::new(path);
+}
+
+/**
+ * The kinds of requests the running game has sent this session, one bit per `REQUEST_KINDS` entry.
+ * Setting a bit is all the persistence server does, so tracking costs nothing per request.
+ */
+static USED: AtomicU32 = AtomicU32::new(0);
+
+fn path() -> PathBuf {
+    layout::state_dir().join("feature_usage.json")
+}
+
+/**
+ * Get the index in `REQUEST_KINDS` of a request from a game
+ */
+fn kind(body: &RequestBody) -> Option<usize> {
+    match body {
+        RequestBody::Save(_, _, _) | RequestBody::Load(_, _) | RequestBody::Flush => Some(0),
+        RequestBody::BeginSave(_, _)
+        | RequestBody::AppendSave(_, _)
+        | RequestBody::CommitSave(_)
+        | RequestBody::LoadRange(_, _, _, _) => Some(1),
+        RequestBody::SubmitSessionSummary(_) => Some(2),
+        RequestBody::GetCabinetHardware => Some(3),
+        RequestBody::GetCabinetSetting(_) | RequestBody::SetCabinetSetting(_, _) => Some(4),
+        _ => None,
+    }
+}
+
+/**
+ * Note that the running game sent a request
+ */
+pub fn used(body: &RequestBody) {
+    if let Some(kind) = kind(body) {
+        USED.fetch_or(1 << kind, Ordering::Relaxed);
+    }
+}
+
+/**
+ * Forget the requests of the last session, when a game is launched
+ */
+pub fn start() {
+    USED.store(0, Ordering::Relaxed);
+}
+
+/**
+ * Whether `needle` is in `haystack`, as ASCII or as the UTF-16 .NET assemblies keep strings in
+ */
+fn mentions(haystack: &[u8], needle: &str) -> bool {
+    let utf16: Vec<u8> = needle.bytes().flat_map(|b| [b, 0]).collect();
+    haystack
+        .windows(needle.len())
+        .any(|window| window == needle.as_bytes())
+        || haystack
+            .windows(utf16.len())
+            .any(|window| window == utf16.as_slice())
+}
+
+/**
+ * Find the variables a game's files mention. This reads every file in the install up to
+ * `MAX_SCANNED_BYTES`, so it's only done once per install.
+ */
+fn scan(game_id: &str) -> Vec<String> {
+    let publish = layout::game_dir(game_id).join("publish");
+    let mut files = Vec::new();
+    list_files(publish.as_path(), "", 0, &mut files);
+    let mut found = Vec::new();
+    for file in files {
+        let path = publish.join(file);
+        if path
+            .metadata()
+            .map_or(true, |meta| meta.len() > MAX_SCANNED_BYTES)
+        {
+            continue;
+        }
+        let Ok(bytes) = std::fs::read(path) else {
+            continue;
+        };
+        for var in GAME_ENV_VARS {
+            if !found.contains(&(*var).to_string()) && mentions(&bytes, var) {
+                found.push((*var).to_string());
+            }
+        }
+    }
+    found
+}
+
+/**
+ * Take the requests of the session that just ended, for `finish`
+ */
+pub fn take() -> u32 {
+    USED.swap(0, Ordering::Relaxed)
+}
+
+/**
+ * Fold a session's requests into the game's usage, scanning its files for variables if they
+ * changed since they were last scanned. Call this off the async runtime.
+ */
+pub fn finish(game_id: &str, used: u32) {
+    let hash = installed_game(game_id).map(|game| game.hash);
+    let scanned = USAGE
+        .lock()
+        .get(game_id)
+        .is_some_and(|usage| Some(&usage.scanned_hash) == hash.as_ref());
+    let env_vars = if scanned { None } else { Some(scan(game_id)) };
+
+    let mut usage = USAGE.lock();
+    let game = usage.entry(game_id.to_string()).or_default();
+    game.sessions += 1;
+    for (bit, kind) in REQUEST_KINDS.iter().enumerate() {
+        if used & (1 << bit) != 0 {
+            game.requests.insert((*kind).to_string());
+        }
+    }
+    if let (Some(env_vars), Some(hash)) = (env_vars, hash) {
+        game.env_vars = env_vars.into_iter().collect();
+        game.scanned_hash = hash;
+    }
+    if let Err(e) = usage.save() {
+        log!(Level::Warn, "Couldn't save feature usage: {}", e);
+    }
+}
+
+/**
+ * Flag a game as misbehaving, after it was disconnected for sending too many requests
+ */
+pub fn misbehaved(game_id: &str) {
+    let mut usage = USAGE.lock();
+    usage.entry(game_id.to_string()).or_default().misbehaved += 1;
+    if let Err(e) = usage.save() {
+        log!(Level::Warn, "Couldn't save feature usage: {}", e);
+    }
+}
+
+/**
+ * Get a game's feature usage, or `None` if it was never played
+ */
+pub fn get(game_id: &str) -> Option<FeatureUsage> {
+    USAGE.lock().get(game_id).cloned()
+}
+
+/**
+ * Count the games using each feature
+ */
+pub fn adoption() -> FeatureAdoption {
+    let usage = USAGE.lock();
+    let mut adoption = FeatureAdoption {
+        games: usage.len() as u64,
+        ..FeatureAdoption::default()
+    };
+    for game in usage.values() {
+        for kind in &game.requests {
+            *adoption.requests.entry(kind.clone()).or_default() += 1;
+        }
+        for var in &game.env_vars {
+            *adoption.env_vars.entry(var.clone()).or_default() += 1;
+        }
+    }
+    adoption
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_ascii_and_utf16_names() {
+        assert!(mentions(b"\0getenv\0DEVCADE_TZ\0", "DEVCADE_TZ"));
+        let utf16: Vec<u8> = "x DEVCADE_LOCALE y".bytes().flat_map(|b| [b, 0]).collect();
+        assert!(mentions(&utf16, "DEVCADE_LOCALE"));
+        assert!(!mentions(b"DEVCADE_T", "DEVCADE_TZ"));
+    }
+}
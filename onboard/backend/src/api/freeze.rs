@@ -0,0 +1,301 @@
+use super::signature::decode_hex;
+use super::{file_hash, game_list_from_fs, read_manifest};
+use crate::clock;
+use crate::layout;
+use crate::secrets::{self, FREEZE_KEY};
+use crate::state::JsonState;
+use anyhow::{anyhow, Error};
+use devcade_onboard_types::{CatalogSnapshot, FrozenGame};
+use hmac::{Hmac, Mac};
+use lazy_static::lazy_static;
+use log::{log, Level};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::PathBuf;
+
+/**
+ * The longest freeze label
+ */
+const MAX_LABEL_LENGTH: usize = 64;
+
+lazy_static! {
+    // The label of the active freeze
+    static ref ACTIVE: JsonState<Option<String>> = JsonState::new(active_path);
+}
+
+/**
+ * The error changes to installed games fail with while the catalog is frozen
+ */
+#[derive(Debug, Clone)]
+pub struct CatalogFrozen {
+    pub label: String,
+}
+
+impl fmt::Display for CatalogFrozen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "CatalogFrozen: installed games can't change during freeze '{}'",
+            self.label
+        )
+    }
+}
+
+impl std::error::Error for CatalogFrozen {}
+
+fn active_path() -> PathBuf {
+    layout::state_dir().join("freeze.json")
+}
+
+/**
+ * Get the file a snapshot is kept in, checking that the label is made of letters, digits, `_`
+ * and `-`, so it can't point outside of the snapshots directory
+ */
+fn snapshot_path(label: &str) -> Result<PathBuf, Error> {
+    let valid = !label.is_empty()
+        && label.len() <= MAX_LABEL_LENGTH
+        && label
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-'));
+    if !valid {
+        return Err(anyhow!(
+            "Invalid freeze label '{label}', expected up to {MAX_LABEL_LENGTH} letters, digits, \
+            '_' or '-'"
+        ));
+    }
+    Ok(layout::state_dir()
+        .join("freezes")
+        .join(format!("{label}.json")))
+}
+
+/**
+ * Start the signature of everything in a snapshot but the signature itself
+ */
+fn mac(key: &str, snapshot: &CatalogSnapshot) -> Result<Hmac<Sha256>, Error> {
+    let signed = serde_json::to_vec(&(&snapshot.label, snapshot.created, &snapshot.games))?;
+    let mut mac = secrets::mac(key);
+    mac.update(&signed);
+    Ok(mac)
+}
+
+/**
+ * Get the SHA-256 hash of a game's `manifest.json`, or `None` if it has none
+ */
+fn manifest_digest(game_id: &str) -> Option<String> {
+    let manifest = std::fs::read(layout::game_dir(game_id).join("manifest.json")).ok()?;
+    Some(secrets::hex(&Sha256::digest(manifest)))
+}
+
+/**
+ * Get every installed game as it is now, by ID
+ */
+fn installed() -> Result<BTreeMap<String, FrozenGame>, Error> {
+    Ok(game_list_from_fs()?
+        .into_iter()
+        .map(|game| {
+            let frozen = FrozenGame {
+                manifest_digest: manifest_digest(game.id.as_str()),
+                id: game.id,
+                hash: game.hash,
+            };
+            (frozen.id.clone(), frozen)
+        })
+        .collect())
+}
+
+/**
+ * Get the label of the active freeze
+ */
+#[must_use]
+pub fn active() -> Option<String> {
+    ACTIVE.lock().clone()
+}
+
+/**
+ * Call before changing installed games
+ *
+ * # Errors
+ * This function will return `CatalogFrozen` if the catalog is frozen.
+ */
+pub fn check() -> Result<(), CatalogFrozen> {
+    match active() {
+        Some(label) => Err(CatalogFrozen { label }),
+        None => Ok(()),
+    }
+}
+
+/**
+ * Snapshot the installed games as `.state/freezes/<label>.json`, and refuse changes to them until
+ * `unfreeze`. Freezing again with another label replaces the active freeze.
+ *
+ * # Errors
+ * This function will return an error if the label is invalid, a snapshot with it already exists,
+ * `freeze_key` isn't set, or the snapshot can't be written.
+ */
+pub fn freeze(label: &str) -> Result<CatalogSnapshot, Error> {
+    let path = snapshot_path(label)?;
+    if path.exists() {
+        return Err(anyhow!(
+            "A catalog snapshot labelled '{label}' already exists"
+        ));
+    }
+    let key = secrets::get(FREEZE_KEY, "freezing the catalog")?;
+    let mut snapshot = CatalogSnapshot {
+        label: label.to_string(),
+        created: clock::unix_now(),
+        games: installed()?.into_values().collect(),
+        signature: String::new(),
+    };
+    snapshot.signature = secrets::hex(&mac(key.as_str(), &snapshot)?.finalize().into_bytes());
+    layout::write_atomic(path.as_path(), serde_json::to_vec_pretty(&snapshot)?)?;
+
+    let mut active = ACTIVE.lock();
+    *active = Some(label.to_string());
+    active.save()?;
+    log!(
+        Level::Info,
+        "Froze the catalog as '{}' with {} games",
+        label,
+        snapshot.games.len()
+    );
+    Ok(snapshot)
+}
+
+/**
+ * Allow installed games to change again. The snapshot is kept, so it can still be verified.
+ *
+ * # Errors
+ * This function will return an error if the freeze state can't be saved.
+ */
+pub fn unfreeze() -> Result<(), Error> {
+    let mut active = ACTIVE.lock();
+    if let Some(label) = active.take() {
+        active.save()?;
+        log!(Level::Info, "Unfroze the catalog from '{}'", label);
+    }
+    Ok(())
+}
+
+/**
+ * Check the installed games against a snapshot, rehashing every file in their manifests. This
+ * reads every installed file, so it's slow on a big library.
+ *
+ * Returns what changed since the snapshot, or nothing if nothing did.
+ *
+ * # Errors
+ * This function will return an error if the snapshot can't be read, or its signature doesn't
+ * match, which means it was edited after it was taken.
+ */
+pub fn verify(label: &str) -> Result<Vec<String>, Error> {
+    let json = std::fs::read(snapshot_path(label)?)
+        .map_err(|e| anyhow!("Couldn't read catalog snapshot '{label}': {e}"))?;
+    let snapshot: CatalogSnapshot = serde_json::from_slice(&json)?;
+    let key = secrets::get(FREEZE_KEY, "verifying a catalog freeze")?;
+    let mac = mac(key.as_str(), &snapshot)?;
+    decode_hex(snapshot.signature.as_str())
+        .and_then(|signature| mac.verify_slice(&signature).ok())
+        .ok_or_else(|| anyhow!("Catalog snapshot '{label}' was modified after it was taken"))?;
+
+    let mut live = installed()?;
+    let mut drift = Vec::new();
+    for frozen in &snapshot.games {
+        let id = frozen.id.as_str();
+        let Some(game) = live.remove(id) else {
+            drift.push(format!("Game {id} was removed"));
+            continue;
+        };
+        if game.hash != frozen.hash {
+            drift.push(format!(
+                "Game {id} changed from hash {} to {}",
+                frozen.hash, game.hash
+            ));
+        }
+        if game.manifest_digest != frozen.manifest_digest {
+            drift.push(format!("Game {id} has a different manifest"));
+        }
+        let dir = layout::game_dir(id);
+        for (name, entry) in read_manifest(dir.as_path()).unwrap_or_default() {
+            match file_hash(dir.join(name.as_str()).as_path()) {
+                Ok(hash) if hash == entry.hash => {}
+                Ok(_) => drift.push(format!("Game {id} file {name} was modified")),
+                Err(_) => drift.push(format!("Game {id} file {name} is missing")),
+            }
+        }
+    }
+    for id in live.keys() {
+        drift.push(format!("Game {id} was installed after the freeze"));
+    }
+    Ok(drift)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use devcade_onboard_types::schema::DevcadeGame;
+
+    /**
+     * Install a game with one file and a manifest listing it
+     */
+    fn install(id: &str, contents: &str) {
+        let dir = layout::game_dir(id);
+        std::fs::create_dir_all(dir.join("publish")).unwrap();
+        std::fs::write(dir.join("publish").join("game"), contents).unwrap();
+        let game = DevcadeGame {
+            id: id.to_string(),
+            hash: format!("{id}-hash"),
+            ..DevcadeGame::default()
+        };
+        std::fs::write(dir.join("game.json"), serde_json::to_vec(&game).unwrap()).unwrap();
+        let manifest = format!(
+            r#"{{"publish/game":{{"hash":"{}","size":{}}}}}"#,
+            secrets::hex(&Sha256::digest(contents)),
+            contents.len()
+        );
+        std::fs::write(dir.join("manifest.json"), manifest).unwrap();
+    }
+
+    #[test]
+    fn verify_catches_tampering() {
+        let (_guard, root) = crate::testing::root("freeze");
+        secrets::set(FREEZE_KEY, "test key").unwrap();
+        install("pong", "pong v1");
+        install("tetris", "tetris v1");
+
+        assert!(check().is_ok());
+        let snapshot = freeze("cup").unwrap();
+        assert_eq!(snapshot.games.len(), 2);
+        assert_eq!(check().unwrap_err().label, "cup");
+        assert!(freeze("cup").is_err());
+        assert_eq!(verify("cup").unwrap(), Vec::<String>::new());
+
+        std::fs::write(
+            layout::game_dir("pong").join("publish").join("game"),
+            "pong v2",
+        )
+        .unwrap();
+        assert_eq!(
+            verify("cup").unwrap(),
+            vec!["Game pong file publish/game was modified".to_string()]
+        );
+
+        std::fs::remove_dir_all(layout::game_dir("tetris")).unwrap();
+        install("snake", "snake v1");
+        let drift = verify("cup").unwrap();
+        assert!(drift.contains(&"Game tetris was removed".to_string()));
+        assert!(drift.contains(&"Game snake was installed after the freeze".to_string()));
+
+        // Editing the snapshot to match doesn't get past the signature
+        let path = snapshot_path("cup").unwrap();
+        let edited = std::fs::read_to_string(&path)
+            .unwrap()
+            .replace("pong-hash", "pong-hash2");
+        std::fs::write(&path, edited).unwrap();
+        assert!(verify("cup").is_err());
+
+        unfreeze().unwrap();
+        assert!(check().is_ok());
+        assert_eq!(active(), None);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,207 @@
+use super::{game_command, installed_game, signal_game, CURRENT_GAME, INSTALLING, RUNNING_GAME};
+use crate::clock;
+use crate::layout;
+use anyhow::{anyhow, Error};
+use devcade_onboard_types::schema::{DevcadeGame, GameSetup};
+use devcade_onboard_types::{GameSetupRecord, GameSetupResult};
+use log::{log, Level};
+use std::ffi::OsStr;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::process::Command;
+
+/**
+ * How long a setup step may take if the game doesn't say
+ */
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/**
+ * The longest a game can ask its setup step to take
+ */
+const MAX_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+fn record_path(dir: &Path) -> PathBuf {
+    dir.join("setup.json")
+}
+
+/**
+ * Get the last run of the setup step of the install in `dir`, or `None` if it was never run
+ */
+pub fn record(dir: &Path) -> Option<GameSetupRecord> {
+    let json = std::fs::read(record_path(dir)).ok()?;
+    serde_json::from_slice(&json).ok()
+}
+
+/**
+ * Get why the setup step of the install in `dir` failed, or `None` if it didn't
+ */
+pub fn failed(dir: &Path) -> Option<String> {
+    match record(dir)?.result {
+        GameSetupResult::SetupFailed(reason) => Some(reason),
+        GameSetupResult::Completed { .. } => None,
+    }
+}
+
+/**
+ * Run a setup command to completion, writing its output to `log`, and stopping it with the rest of
+ * its process group if it takes longer than `timeout`
+ */
+async fn execute(mut command: Command, log: &Path, timeout: Duration) -> GameSetupResult {
+    let output = match File::create(log).and_then(|file| Ok((file.try_clone()?, file))) {
+        Ok(output) => output,
+        Err(e) => return GameSetupResult::SetupFailed(format!("Couldn't create its log: {e}")),
+    };
+    command
+        .stdin(Stdio::null())
+        .stdout(output.0)
+        .stderr(output.1)
+        .kill_on_drop(true);
+    let started = clock::now();
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(e) => return GameSetupResult::SetupFailed(format!("Couldn't start it: {e}")),
+    };
+    match clock::timeout(timeout, child.wait()).await {
+        Ok(Ok(status)) if status.success() => GameSetupResult::Completed {
+            seconds: clock::elapsed(started).as_secs(),
+        },
+        Ok(Ok(status)) => GameSetupResult::SetupFailed(format!("It {status}")),
+        Ok(Err(e)) => GameSetupResult::SetupFailed(format!("Couldn't wait for it: {e}")),
+        Err(_) => {
+            // Anything it started goes too, since it would keep the install busy
+            if let Some(pid) = child.id() {
+                let _ = signal_game(pid, libc::SIGKILL);
+            }
+            GameSetupResult::SetupFailed(format!(
+                "It didn't finish within {} seconds",
+                timeout.as_secs()
+            ))
+        }
+    }
+}
+
+/**
+ * Run a game's setup step in the install in `dir`, if it has one, and record how it went in
+ * `setup.json` there. Its output goes to `setup.log` in the game's log directory. A failed step
+ * doesn't fail the install.
+ *
+ * # Errors
+ * This function will return an error if the log directory or the record can't be written.
+ */
+pub async fn run(game: &DevcadeGame, dir: &Path) -> Result<Option<GameSetupRecord>, Error> {
+    let Some(GameSetup {
+        command,
+        timeout_secs,
+    }) = game.setup.as_ref()
+    else {
+        return Ok(None);
+    };
+    let logs = layout::game_logs_dir(game.id.as_str());
+    std::fs::create_dir_all(&logs)?;
+    let log = logs.join("setup.log");
+    let timeout = timeout_secs
+        .map_or(DEFAULT_TIMEOUT, Duration::from_secs)
+        .min(MAX_TIMEOUT);
+
+    log!(Level::Info, "Running setup of game {}...", game.name);
+    let mut setup = game_command(
+        OsStr::new("sh"),
+        dir.join("publish").as_path(),
+        game.id.as_str(),
+        game.id.as_str(),
+    );
+    setup.arg("-c").arg(command);
+    let result = execute(setup, log.as_path(), timeout).await;
+    match &result {
+        GameSetupResult::Completed { seconds } => log!(
+            Level::Info,
+            "Setup of game {} took {} seconds",
+            game.name,
+            seconds
+        ),
+        GameSetupResult::SetupFailed(reason) => log!(
+            Level::Warn,
+            "Setup of game {} failed, see {}: {}",
+            game.name,
+            log.display(),
+            reason
+        ),
+    }
+    let record = GameSetupRecord {
+        hash: game.hash.clone(),
+        at: clock::unix_now(),
+        result,
+        log: log.to_string_lossy().into_owned(),
+    };
+    layout::write_atomic(record_path(dir).as_path(), serde_json::to_vec(&record)?)?;
+    Ok(Some(record))
+}
+
+/**
+ * Run an installed game's setup step again. Launches of any game wait until it's done.
+ *
+ * # Errors
+ * This function will return an error if the game isn't installed, has no setup step, or is
+ * running.
+ */
+pub async fn rerun(game_id: &str) -> Result<GameSetupRecord, Error> {
+    let game = installed_game(game_id).ok_or_else(|| anyhow!("Game {game_id} isn't installed"))?;
+    if game.setup.is_none() {
+        return Err(anyhow!("Game {game_id} has no setup step"));
+    }
+    let _installing = INSTALLING.write().await;
+    let running = RUNNING_GAME.lock().unwrap().is_some()
+        && CURRENT_GAME.lock().unwrap().get_mut().id == game_id;
+    if running {
+        return Err(anyhow!(
+            "Game {game_id} is running, try again once it exits"
+        ));
+    }
+    run(&game, layout::game_dir(game_id).as_path())
+        .await?
+        .ok_or_else(|| anyhow!("Game {game_id} has no setup step"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sh(script: &str, dir: &Path) -> Command {
+        let mut command = Command::new("sh");
+        command.current_dir(dir).arg("-c").arg(script);
+        command
+    }
+
+    #[tokio::test]
+    async fn setup_output_is_logged_and_failures_recorded() {
+        let dir = std::env::temp_dir().join(format!("devcade-setup-step-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let log = dir.join("setup.log");
+
+        let script = "echo compiling shaders; echo default > config.ini";
+        let result = execute(sh(script, &dir), &log, DEFAULT_TIMEOUT).await;
+        assert!(matches!(result, GameSetupResult::Completed { .. }));
+        assert_eq!(
+            std::fs::read_to_string(&log).unwrap(),
+            "compiling shaders\n"
+        );
+        assert!(dir.join("config.ini").exists());
+
+        let result = execute(
+            sh("echo missing pak >&2; exit 3", &dir),
+            &log,
+            DEFAULT_TIMEOUT,
+        )
+        .await;
+        assert!(matches!(result, GameSetupResult::SetupFailed(_)));
+        assert_eq!(std::fs::read_to_string(&log).unwrap(), "missing pak\n");
+
+        let started = clock::now();
+        let result = execute(sh("sleep 30", &dir), &log, Duration::from_millis(100)).await;
+        assert!(matches!(result, GameSetupResult::SetupFailed(_)));
+        assert!(clock::elapsed(started) < Duration::from_secs(10));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
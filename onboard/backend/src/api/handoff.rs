@@ -0,0 +1,367 @@
+use super::{
+    download_jobs, emit_launch_event, end_session, feature_usage, installed_game, library, usage,
+    RunningGame, CURRENT_GAME, DOWNLOADS, LAUNCH_EVENTS, RUNNING_GAME, SAVE_NAMESPACE,
+};
+use crate::clock;
+use crate::layout;
+use crate::servers;
+use anyhow::{anyhow, Error};
+use devcade_onboard_types::{DownloadPriority, DownloadStage, LaunchEvent, LaunchEventKind};
+use log::{log, Level};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::time::Duration;
+
+/**
+ * What the backend exits with after writing a handoff, so the service manager restarts it
+ */
+pub const RESTART_EXIT_CODE: i32 = 75;
+
+/**
+ * How old a handoff can be and still be used. An older one is from a restart that didn't happen
+ * the way it was meant to, and the world has moved on since.
+ */
+const MAX_AGE: Duration = Duration::from_secs(5 * 60);
+
+/**
+ * How long to wait before exiting, so the response to `PrepareRestart` is sent first
+ */
+const EXIT_AFTER: Duration = Duration::from_millis(500);
+
+/**
+ * How often an adopted game is checked for having exited. It isn't a child of this process, so
+ * it can't be waited on.
+ */
+const POLL_EVERY: Duration = Duration::from_secs(1);
+
+/**
+ * What one backend hands the next across a restart
+ */
+#[derive(Serialize, Deserialize, Default)]
+struct Handoff {
+    /// When it was written, as a unix timestamp in seconds
+    written: u64,
+    game: Option<HandedOffGame>,
+    /// Game downloads that hadn't finished, with their priorities
+    downloads: Vec<(String, DownloadPriority)>,
+    library_generation: u64,
+    /// The sequence number of the last launch event, so numbering carries on
+    launch_event_seq: u64,
+    /// The kept launch events, so clients can catch up on them after the restart
+    #[serde(default)]
+    launch_events: Vec<LaunchEvent>,
+}
+
+/**
+ * The game that was running when the backend restarted
+ */
+#[derive(Serialize, Deserialize)]
+struct HandedOffGame {
+    game_id: String,
+    pid: u32,
+    /// When its session started, as a unix timestamp in seconds
+    started: u64,
+    save_namespace: String,
+    event: Option<String>,
+}
+
+fn path() -> PathBuf {
+    layout::state_dir().join("handoff.json")
+}
+
+/**
+ * Whether a handed off game is still running. The game leads its own process group, which a
+ * process that was given its PID afterwards wouldn't.
+ */
+fn alive(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // SAFETY: getpgid has no memory safety requirements
+    unsafe { libc::getpgid(pid) == pid }
+}
+
+/**
+ * Gather what the next backend needs
+ */
+fn collect() -> Handoff {
+    let game = RUNNING_GAME.lock().unwrap().as_ref().map(|running| {
+        let elapsed = clock::elapsed(running.spawned).as_secs();
+        HandedOffGame {
+            game_id: CURRENT_GAME.lock().unwrap().get_mut().id.clone(),
+            pid: running.pid,
+            started: clock::unix_now().saturating_sub(elapsed),
+            save_namespace: SAVE_NAMESPACE.lock().unwrap().clone(),
+            event: running.event.clone(),
+        }
+    });
+    let mut downloads: Vec<(String, DownloadPriority)> = download_jobs::games()
+        .into_iter()
+        .map(|game_id| {
+            let priority = download_jobs::priority(game_id.as_str()).unwrap_or_default();
+            (game_id, priority)
+        })
+        .collect();
+    for game_id in DOWNLOADS.lock().unwrap().keys() {
+        if !downloads.iter().any(|(queued, _)| queued == game_id) {
+            downloads.push((game_id.clone(), DownloadPriority::default()));
+        }
+    }
+    Handoff {
+        written: clock::unix_now(),
+        game,
+        downloads,
+        library_generation: library::generation(),
+        launch_event_seq: LAUNCH_EVENTS.seq(),
+        launch_events: LAUNCH_EVENTS.kept(),
+    }
+}
+
+/**
+ * Write the handoff and exit with `RESTART_EXIT_CODE` shortly after. Saves are flushed first, and
+ * a paused game is resumed, since the timer that would resume it doesn't survive the restart.
+ *
+ * # Errors
+ * This function will return an error if a game is being installed, or the saves or handoff can't
+ * be written. The backend keeps running if it does.
+ */
+pub async fn prepare() -> Result<(), Error> {
+    let installing = DOWNLOADS
+        .lock()
+        .unwrap()
+        .values()
+        .find(|progress| {
+            matches!(
+                progress.stage,
+                DownloadStage::Verifying
+                    | DownloadStage::Extracting { .. }
+                    | DownloadStage::RunningSetup
+            )
+        })
+        .map(|progress| progress.game_id.clone());
+    if let Some(game_id) = installing {
+        return Err(anyhow!(
+            "Game {game_id} is being installed, try again once it's done"
+        ));
+    }
+    let paused = RUNNING_GAME
+        .lock()
+        .unwrap()
+        .as_ref()
+        .is_some_and(|game| game.paused.is_some());
+    if paused {
+        super::resume_current_game()?;
+    }
+    servers::persistence::flush().await?;
+
+    let handoff = collect();
+    layout::write_atomic(path().as_path(), serde_json::to_vec(&handoff)?)?;
+    log!(
+        Level::Info,
+        "Wrote handoff with {} downloads and {}, restarting",
+        handoff.downloads.len(),
+        handoff.game.as_ref().map_or_else(
+            || "no game".to_string(),
+            |game| format!("game {}", game.game_id)
+        )
+    );
+    tokio::spawn(async {
+        clock::sleep(EXIT_AFTER).await;
+        std::process::exit(RESTART_EXIT_CODE);
+    });
+    Ok(())
+}
+
+/**
+ * Read a handoff, unless it's too old to use
+ */
+fn parse(json: &[u8], now: u64) -> Result<Handoff, Error> {
+    let handoff: Handoff = serde_json::from_slice(json)?;
+    let age = now.saturating_sub(handoff.written);
+    if age > MAX_AGE.as_secs() {
+        return Err(anyhow!("it was written {age} seconds ago"));
+    }
+    Ok(handoff)
+}
+
+/**
+ * Take over the game the last backend was running, until it exits
+ */
+fn adopt(game: HandedOffGame) {
+    let elapsed = Duration::from_secs(clock::unix_now().saturating_sub(game.started));
+    let spawned = clock::now().checked_sub(elapsed).unwrap_or_else(clock::now);
+    let started = (game.started, spawned);
+    let installed = installed_game(game.game_id.as_str()).filter(|_| alive(game.pid));
+    let Some(installed) = installed else {
+        log!(
+            Level::Info,
+            "Game {} exited while the backend restarted",
+            game.game_id
+        );
+        end_session(game.game_id.as_str(), started, game.event.as_deref(), None);
+        return;
+    };
+
+    *SAVE_NAMESPACE.lock().unwrap() = game.save_namespace;
+    CURRENT_GAME.lock().unwrap().set(installed);
+    *RUNNING_GAME.lock().unwrap() = Some(RunningGame {
+        pid: game.pid,
+        spawned,
+        paused: None,
+        event: game.event.clone(),
+    });
+    usage::start(game.game_id.as_str(), game.pid);
+    feature_usage::start();
+    emit_launch_event(LaunchEventKind::GameTakingFocus(game.game_id.clone()));
+    emit_launch_event(LaunchEventKind::GameSpawned(game.pid));
+    log!(
+        Level::Info,
+        "Adopted game {} (PID {}) from the last backend",
+        game.game_id,
+        game.pid
+    );
+
+    tokio::spawn(async move {
+        let mut interval = clock::interval(POLL_EVERY);
+        while alive(game.pid) {
+            interval.tick().await;
+        }
+        log!(Level::Info, "Adopted game {} exited", game.game_id);
+        end_session(game.game_id.as_str(), started, game.event.as_deref(), None);
+    });
+}
+
+/**
+ * Pick up where the last backend left off, if it wrote a handoff before restarting: adopt the
+ * game it was running, queue its unfinished downloads again (they resume from their partial
+ * files), and carry on the library generation and launch events. The handoff is removed
+ * either way, and ignored with a warning if it's too old.
+ */
+pub fn resume() {
+    let path = path();
+    let json = match std::fs::read(&path) {
+        Ok(json) => json,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return,
+        Err(e) => {
+            log!(Level::Warn, "Couldn't read handoff: {}", e);
+            return;
+        }
+    };
+    // Removed before it's used, so a crash while resuming can't make every later start resume it
+    if let Err(e) = std::fs::remove_file(&path) {
+        log!(Level::Warn, "Couldn't remove handoff: {}", e);
+    }
+    let handoff = match parse(&json, clock::unix_now()) {
+        Ok(handoff) => handoff,
+        Err(e) => {
+            log!(Level::Warn, "Ignoring handoff from the last backend: {}", e);
+            return;
+        }
+    };
+
+    library::restore(handoff.library_generation);
+    LAUNCH_EVENTS.restore(handoff.launch_event_seq, handoff.launch_events);
+    if let Some(game) = handoff.game {
+        adopt(game);
+    }
+    for (game_id, priority) in &handoff.downloads {
+        download_jobs::enqueue(game_id.as_str(), *priority);
+    }
+    log!(
+        Level::Info,
+        "Resumed from handoff with {} downloads",
+        handoff.downloads.len()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::network::{self, Priority};
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    #[test]
+    fn stale_handoff_is_ignored() {
+        let handoff = Handoff {
+            written: 1000,
+            downloads: vec![("pong".to_string(), DownloadPriority::default())],
+            ..Handoff::default()
+        };
+        let json = serde_json::to_vec(&handoff).unwrap();
+        let fresh = parse(&json, 1000 + MAX_AGE.as_secs()).unwrap();
+        assert_eq!(fresh.downloads, handoff.downloads);
+        assert!(parse(&json, 1001 + MAX_AGE.as_secs()).is_err());
+    }
+
+    /**
+     * Serve one request for `body`, honoring a `Range` header, and return the header's value
+     */
+    fn serve_once(listener: &TcpListener, body: &[u8]) -> Option<String> {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut range = None;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if line.trim().is_empty() {
+                break;
+            }
+            if let Some(value) = line.to_ascii_lowercase().strip_prefix("range: bytes=") {
+                range = Some(value.trim().to_string());
+            }
+        }
+        let start: usize = range
+            .as_deref()
+            .and_then(|range| range.trim_end_matches('-').parse().ok())
+            .unwrap_or(0);
+        let status = if start > 0 {
+            format!(
+                "206 Partial Content\r\nContent-Range: bytes {start}-{}/{}",
+                body.len() - 1,
+                body.len()
+            )
+        } else {
+            "200 OK".to_string()
+        };
+        let mut stream = stream;
+        write!(
+            stream,
+            "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            body.len() - start
+        )
+        .unwrap();
+        stream.write_all(&body[start..]).unwrap();
+        range
+    }
+
+    #[tokio::test]
+    async fn handed_off_download_resumes() {
+        let body: Vec<u8> = (0..=255).cycle().take(10_000).collect();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/game.zip", listener.local_addr().unwrap());
+        let server = {
+            let body = body.clone();
+            std::thread::spawn(move || serve_once(&listener, &body))
+        };
+
+        // The last backend exited partway through the download and handed it off
+        let partial = std::env::temp_dir().join(format!("devcade-handoff-{}", std::process::id()));
+        std::fs::write(&partial, &body[..4000]).unwrap();
+        let handoff = Handoff {
+            written: clock::unix_now(),
+            downloads: vec![("pong".to_string(), DownloadPriority::default())],
+            ..Handoff::default()
+        };
+        let handoff = parse(&serde_json::to_vec(&handoff).unwrap(), clock::unix_now()).unwrap();
+        assert_eq!(handoff.downloads[0].0, "pong");
+
+        let size = network::download(url.as_str(), Priority::Background, &partial, |_, _| Ok(()))
+            .await
+            .unwrap();
+        assert_eq!(server.join().unwrap().as_deref(), Some("4000-"));
+        assert_eq!(size, body.len() as u64);
+        assert_eq!(std::fs::read(&partial).unwrap(), body);
+        let _ = std::fs::remove_file(&partial);
+    }
+}
@@ -0,0 +1,200 @@
+use crate::clock;
+use crate::env::suspicious_update_policy;
+use crate::layout;
+use crate::state::{self, JsonState};
+use anyhow::{anyhow, Error};
+use devcade_onboard_types::schema::DevcadeGame;
+use devcade_onboard_types::SuspiciousUpdate;
+use lazy_static::lazy_static;
+use log::{log, Level};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+
+lazy_static! {
+    // Installed games whose hash changed without a new upload date, by game ID
+    static ref SUSPICIOUS: JsonState<BTreeMap<String, SuspiciousUpdate>> = JsonState::new(path);
+}
+
+/**
+ * What auto-update does with a suspicious update
+ */
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Policy {
+    Proceed,
+    Skip,
+    Confirm,
+}
+
+impl Policy {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Proceed => "proceed",
+            Self::Skip => "skip",
+            Self::Confirm => "confirm",
+        }
+    }
+}
+
+/**
+ * One line of the journal
+ */
+#[derive(Serialize)]
+struct JournalEntry<'a> {
+    time: u64,
+    game_id: &'a str,
+    installed_hash: &'a str,
+    api_hash: &'a str,
+    upload_date: &'a str,
+    policy: &'static str,
+    /// `updated`, `skipped`, `awaiting confirmation` or `confirmed`
+    decision: &'a str,
+}
+
+fn path() -> PathBuf {
+    layout::state_dir().join("suspicious_updates.json")
+}
+
+fn journal_path() -> PathBuf {
+    layout::state_dir().join("hash_changes.journal")
+}
+
+/**
+ * Get the suspicious update policy. Unknown policies wait for confirmation, like the default.
+ */
+pub fn policy() -> Policy {
+    match suspicious_update_policy().as_deref() {
+        None | Some("confirm") => Policy::Confirm,
+        Some("proceed") => Policy::Proceed,
+        Some("skip") => Policy::Skip,
+        Some(policy) => {
+            log!(
+                Level::Warn,
+                "Unknown suspicious update policy '{}', waiting for confirmation",
+                policy
+            );
+            Policy::Confirm
+        }
+    }
+}
+
+fn journal(update: &SuspiciousUpdate, policy: Policy, decision: &str) {
+    let entry = JournalEntry {
+        time: clock::unix_now(),
+        game_id: update.game_id.as_str(),
+        installed_hash: update.installed_hash.as_str(),
+        api_hash: update.api_hash.as_str(),
+        upload_date: update.upload_date.as_str(),
+        policy: policy.name(),
+        decision,
+    };
+    if let Err(e) = state::journal(journal_path().as_path(), &entry) {
+        log!(Level::Warn, "Couldn't journal hash change: {}", e);
+    }
+}
+
+/**
+ * Whether the API's copy of an installed game has a different hash but the same upload date. A
+ * real update always has a new upload date, so this is more likely a problem with the API's
+ * storage than a new version.
+ */
+#[must_use]
+pub fn is_suspicious(installed: &DevcadeGame, latest: &DevcadeGame) -> bool {
+    installed.hash != latest.hash && installed.upload_date == latest.upload_date
+}
+
+/**
+ * Flag a suspicious update and decide whether auto-update installs it under
+ * `DEVCADE_SUSPICIOUS_UPDATE_POLICY`. New flags and every update are journaled.
+ */
+pub fn allow(installed: &DevcadeGame, latest: &DevcadeGame) -> bool {
+    let policy = policy();
+    let mut suspicious = SUSPICIOUS.lock();
+    let known = suspicious.get(&latest.id).is_some_and(|update| {
+        update.installed_hash == installed.hash && update.api_hash == latest.hash
+    });
+    if !known {
+        log!(
+            Level::Warn,
+            "Hash of game {} changed from {} to {} without a new upload date ({})",
+            latest.id,
+            installed.hash,
+            latest.hash,
+            latest.upload_date
+        );
+        suspicious.insert(
+            latest.id.clone(),
+            SuspiciousUpdate {
+                game_id: latest.id.clone(),
+                installed_hash: installed.hash.clone(),
+                api_hash: latest.hash.clone(),
+                upload_date: latest.upload_date.clone(),
+                first_seen: clock::unix_now(),
+                confirmed: false,
+            },
+        );
+        if let Err(e) = suspicious.save() {
+            log!(Level::Warn, "Couldn't save suspicious updates: {}", e);
+        }
+    }
+    // Indexing is safe because the update was inserted above if it wasn't there
+    let update = &suspicious[&latest.id];
+    let allowed = match policy {
+        Policy::Proceed => true,
+        Policy::Skip => false,
+        Policy::Confirm => update.confirmed,
+    };
+    if allowed {
+        journal(update, policy, "updated");
+    } else if !known {
+        let decision = match policy {
+            Policy::Skip => "skipped",
+            _ => "awaiting confirmation",
+        };
+        journal(update, policy, decision);
+    }
+    allowed
+}
+
+/**
+ * Forget the games that are no longer suspicious, because they were updated, removed, or the API
+ * went back to the installed hash
+ */
+pub fn retain(game_ids: &BTreeSet<String>) {
+    let mut suspicious = SUSPICIOUS.lock();
+    let before = suspicious.len();
+    suspicious.retain(|game_id, _| game_ids.contains(game_id));
+    if suspicious.len() != before {
+        if let Err(e) = suspicious.save() {
+            log!(Level::Warn, "Couldn't save suspicious updates: {}", e);
+        }
+    }
+}
+
+/**
+ * Let auto-update install a game's suspicious update on its next cycle
+ *
+ * # Errors
+ * This function will return an error if the game has no suspicious update, or if the confirmation
+ * can't be saved.
+ */
+pub fn confirm(game_id: &str) -> Result<(), Error> {
+    let mut suspicious = SUSPICIOUS.lock();
+    let update = suspicious
+        .get_mut(game_id)
+        .ok_or_else(|| anyhow!("Game {game_id} has no suspicious update"))?;
+    if update.confirmed {
+        return Ok(());
+    }
+    update.confirmed = true;
+    journal(update, policy(), "confirmed");
+    suspicious.save()
+}
+
+/**
+ * Get the suspicious updates seen by auto-update
+ */
+#[must_use]
+pub fn list() -> Vec<SuspiciousUpdate> {
+    SUSPICIOUS.lock().values().cloned().collect()
+}
@@ -0,0 +1,71 @@
+use super::sessions;
+use crate::layout;
+use crate::state::JsonState;
+use anyhow::{anyhow, Error};
+use devcade_onboard_types::InputActivity;
+use lazy_static::lazy_static;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/**
+ * How many days of counts are kept for each game
+ */
+const HISTORY_DAYS: usize = 30;
+/**
+ * The most controls a report can count, and the longest control name, so a report can't grow
+ * the stats without bound
+ */
+const MAX_CONTROLS: usize = 64;
+const MAX_CONTROL_NAME: usize = 32;
+
+type Stats = BTreeMap<String, BTreeMap<String, InputActivity>>;
+
+lazy_static! {
+    // Counts by game ID and local date
+    static ref STATS: JsonState<Stats> = JsonState::new(stats_path);
+}
+
+fn stats_path() -> PathBuf {
+    layout::state_dir().join("input_activity.json")
+}
+
+/**
+ * Add a minute of counts to today's usage of a game's controls
+ *
+ * # Errors
+ * This function will return an error if the report has too many controls or a name that's too
+ * long, or if the counts can't be saved.
+ */
+pub fn report(game_id: &str, counts: BTreeMap<String, u64>) -> Result<(), Error> {
+    if counts.len() > MAX_CONTROLS {
+        return Err(anyhow!(
+            "Input activity counts {} controls, the most allowed is {MAX_CONTROLS}",
+            counts.len()
+        ));
+    }
+    if let Some(control) = counts.keys().find(|c| c.chars().count() > MAX_CONTROL_NAME) {
+        return Err(anyhow!(
+            "Control name '{control}' is longer than {MAX_CONTROL_NAME} characters"
+        ));
+    }
+
+    let mut stats = STATS.lock();
+    let days = stats.entry(game_id.to_string()).or_default();
+    let today = days.entry(sessions::today()).or_default();
+    today.minutes += 1;
+    for (control, count) in counts {
+        let total = today.controls.entry(control).or_default();
+        *total = total.saturating_add(count);
+    }
+    while days.len() > HISTORY_DAYS {
+        days.pop_first();
+    }
+    stats.save()
+}
+
+/**
+ * Get a game's control usage by local date
+ */
+pub fn get(game_id: &str) -> BTreeMap<String, InputActivity> {
+    STATS.lock().get(game_id).cloned().unwrap_or_default()
+}
@@ -0,0 +1,554 @@
+use super::network::Priority;
+use super::operations::Admission;
+use super::{
+    accessibility, archive_size, deduplicated, emit_launch_event, extract_archive, extracted_size,
+    free_space, freeze, game_setup, get_game, installed_game, make_room, network, object_store,
+    operations, raise_queued, read_manifest, route, runtime, signature, Download,
+    DownloadCancelled, DownloadTracker, GameArchive, GAMES_IN_FLIGHT, INSTALLING,
+    INSTALL_SPACE_FACTOR, THROUGHPUT,
+};
+use crate::env::{api_url, min_free_space};
+use crate::faults::{self, site};
+use crate::layout;
+use anyhow::{anyhow, Error};
+use devcade_onboard_types::{
+    schema::DevcadeGame, DownloadPriority, DownloadStage, GameIntent, GameOperation, InstallKind,
+    InstallOutcome, LaunchEventKind,
+};
+use log::{log, Level};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/**
+ * Download a game the same way as `download_game`, waiting for a download slot at the given
+ * priority. If the game is already being downloaded at a lower priority, it's raised to this one.
+ *
+ * # Errors
+ * This function will return an error if the download fails.
+ */
+pub(super) async fn download_with_priority(
+    game_id: String,
+    priority: DownloadPriority,
+) -> Result<InstallOutcome, Error> {
+    raise_queued(game_id.as_str(), priority);
+    let operation = GameOperation {
+        intent: if installed_game(game_id.as_str()).is_some() {
+            GameIntent::Update
+        } else {
+            GameIntent::Install
+        },
+        origin: operations::origin(priority),
+    };
+    match operations::admit(game_id.as_str(), operation).await? {
+        Admission::Run(ticket) => {
+            deduplicated(
+                &GAMES_IN_FLIGHT,
+                Download::Game,
+                game_id.clone(),
+                async move {
+                    let _ticket = ticket;
+                    fetch_game(game_id, priority).await
+                },
+            )
+            .await
+        }
+        // Taken over at this priority, which was already raised above
+        Admission::Joined => {
+            deduplicated(
+                &GAMES_IN_FLIGHT,
+                Download::Game,
+                game_id.clone(),
+                fetch_game(game_id, priority),
+            )
+            .await
+        }
+    }
+}
+
+async fn fetch_game(game_id: String, priority: DownloadPriority) -> Result<InstallOutcome, Error> {
+    freeze::check()?;
+    // Queued from the start, so the download shows up as soon as it's asked for
+    let mut tracker = DownloadTracker::new(game_id.as_str(), priority);
+    let path = layout::game_dir(game_id.as_str()).join("game.json");
+
+    let game = get_game(game_id.as_str()).await?;
+    accessibility::validate(&game)?;
+
+    // Check if the game is already downloaded, and if it is, check if the hash is the same
+    let kind = if path.exists() {
+        if let Some(game_) = installed_game(game_id.as_str()) {
+            if game_.hash == game.hash {
+                return Ok(InstallOutcome {
+                    game,
+                    kind: InstallKind::AlreadyInstalled,
+                    setup: None,
+                });
+            }
+        }
+        InstallKind::Updated
+    } else {
+        InstallKind::FreshInstall
+    };
+
+    // Stream the archive to disk, since some games are bigger than the cabinet's memory. If the
+    // download is interrupted, the partial archive is kept so the next attempt can resume it.
+    std::fs::create_dir_all(layout::tmp_dir())?;
+    let partial = layout::partial_download(game.id.as_str());
+    ensure_space(&game, partial.as_path()).await?;
+
+    log!(Level::Info, "Downloading game {}...", game.name);
+
+    let slot = tracker.slot().await?;
+    tracker.stage(DownloadStage::Downloading);
+    let started = Instant::now();
+    let mut resumed_from = None;
+    let size = network::download(
+        format!("{}/{}", api_url(), route::game_download(game_id.as_str())).as_str(),
+        Priority::Normal,
+        partial.as_path(),
+        |downloaded, total| {
+            resumed_from.get_or_insert(downloaded);
+            tracker.downloaded(downloaded, total);
+            tracker.check()
+        },
+    )
+    .await
+    .inspect_err(|e| {
+        // A cancelled download isn't worth resuming
+        if e.is::<DownloadCancelled>() {
+            let _ = std::fs::remove_file(&partial);
+        }
+    })?;
+    // Verifying and extracting don't touch the network, so the next download can start
+    drop(slot);
+    record_throughput(size - resumed_from.unwrap_or(0), started.elapsed());
+    // Once the download is complete, the archive is removed whether or not the install succeeds
+    let complete = layout::downloaded_archive(game.id.as_str(), GameArchive::extension(&partial)?);
+    std::fs::rename(&partial, &complete)?;
+    let archive = TempPath(complete);
+
+    tracker.stage(DownloadStage::Verifying);
+    // A truncated or corrupted download must not be extracted, and since game.json is only written
+    // after extracting, a failed attempt doesn't stop the next one from downloading again. This
+    // also catches a partial archive of an older version being resumed.
+    let archive_hash = file_hash(archive.0.as_path())?;
+    if !archive_hash.eq_ignore_ascii_case(game.hash.trim()) {
+        return Err(anyhow!(
+            "Downloaded archive for game {} is corrupt: expected hash {}, got {}",
+            game.name,
+            game.hash,
+            archive_hash
+        ));
+    }
+    // Only ask for the signature when there are keys to check it against
+    let published = if signature::enabled() {
+        game_signature(game.id.as_str()).await?
+    } else {
+        None
+    };
+    let verification =
+        signature::verify(game.id.as_str(), game.hash.as_str(), published.as_deref())?;
+    log!(Level::Info, "Game {} is {}", game.name, verification);
+
+    let mut game_archive = GameArchive::open(&archive.0)?;
+    make_room(game.id.as_str(), extracted_size(&mut game_archive)?).await?;
+
+    log!(Level::Info, "Extracting game {}...", game.name);
+    log!(Level::Trace, "Archive size: {} bytes", size);
+
+    // Extract the game into a staging directory, so a failed install leaves the previous one as it
+    // was. The staging directory is removed whether or not the install succeeds.
+    let dir = layout::game_dir(game.id.as_str());
+    let staging = TempPath(layout::staging_dir(game.id.as_str()));
+    if staging.0.exists() {
+        std::fs::remove_dir_all(&staging.0)?;
+    }
+    std::fs::create_dir_all(&staging.0)?;
+    // Unchanged files are linked from the previous install, unless it predates manifests
+    let previous = read_manifest(dir.as_path());
+    if kind == InstallKind::Updated && previous.is_none() {
+        log!(
+            Level::Debug,
+            "Game {} has no manifest, replacing every file",
+            game.name
+        );
+    }
+    let extraction = extract_archive(
+        &mut game_archive,
+        staging.0.as_path(),
+        previous.as_ref().map(|manifest| (dir.as_path(), manifest)),
+        |entries_extracted, total_entries| {
+            tracker.stage(DownloadStage::Extracting {
+                entries_extracted,
+                total_entries,
+            });
+            tracker.check()
+        },
+    )?;
+    drop(game_archive);
+    drop(archive);
+    if let Some(error) = extraction.errors.first() {
+        return Err(anyhow!(
+            "Couldn't extract game {} ({} errors, first: {})",
+            game.name,
+            extraction.errors.len(),
+            error
+        ));
+    }
+
+    // Write the game's JSON file along with the rest of the install (this is used later to get the
+    // games from the filesystem)
+    log!(
+        Level::Debug,
+        "Writing game.json file for game {}...",
+        game.name
+    );
+    log!(Level::Trace, "Game json path: {}", path.to_str().unwrap());
+    if extraction.reused > 0 {
+        log!(
+            Level::Info,
+            "Kept {} unchanged files ({} bytes) of game {}",
+            extraction.reused,
+            extraction.reused_bytes,
+            game.name
+        );
+    }
+    // A setup step could change files in place, which would change them for every game sharing them
+    let (shared, shared_bytes) = if game.setup.is_none() {
+        object_store::share(staging.0.as_path(), &extraction.manifest)
+    } else {
+        (0, 0)
+    };
+    if shared > 0 {
+        log!(
+            Level::Info,
+            "Shared {} files ({} bytes) of game {} with other games",
+            shared,
+            shared_bytes,
+            game.name
+        );
+    }
+    std::fs::write(
+        staging.0.join("manifest.json"),
+        serde_json::to_vec(&extraction.manifest)?,
+    )?;
+    let json = serde_json::to_string(&game)?;
+    std::fs::write(staging.0.join("game.json"), json)?;
+    // The last step of staging, so the first player doesn't wait on it
+    if game.setup.is_some() {
+        tracker.stage(DownloadStage::RunningSetup);
+    }
+    let setup = game_setup::run(&game, staging.0.as_path())
+        .await?
+        .map(|record| record.result);
+
+    // Launches wait for the swap, so they never see half of an install
+    let installing = INSTALLING.write().await;
+    // The last chance to cancel, the previous install is replaced from here on
+    tracker.check()?;
+    swap_install(
+        dir.as_path(),
+        staging.0.as_path(),
+        layout::replaced_dir(game.id.as_str()).as_path(),
+    )?;
+    drop(installing);
+
+    runtime::probe(game.id.as_str(), game.name.as_str()).await;
+    emit_launch_event(LaunchEventKind::DownloadFinished(game.id.clone()));
+    Ok(InstallOutcome { game, kind, setup })
+}
+
+/**
+ * Get the hex encoded SHA-256 hash of a file, reading it in chunks
+ */
+pub(super) fn file_hash(path: &Path) -> Result<String, Error> {
+    use sha2::{Digest, Sha256};
+
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut std::fs::File::open(path)?, &mut hasher)?;
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect())
+}
+
+/**
+ * Get the published signature of a game, or `None` if the game isn't signed
+ */
+pub(super) async fn game_signature(game_id: &str) -> Result<Option<String>, Error> {
+    let url = format!("{}/{}", api_url(), route::game_signature(game_id));
+    match network::request_bytes(url.as_str(), Priority::Normal).await {
+        Ok(bytes) => Ok(Some(String::from_utf8(bytes)?)),
+        Err(e)
+            if e.downcast_ref::<reqwest::Error>()
+                .and_then(reqwest::Error::status)
+                == Some(reqwest::StatusCode::NOT_FOUND) =>
+        {
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/**
+ * The error returned when a game won't fit on the cabinet's disk, even after evicting other games
+ */
+#[derive(Debug, Clone, Copy)]
+pub struct InsufficientDiskSpace {
+    /**
+     * The bytes the install needs, including the `DEVCADE_MIN_FREE_MB` margin
+     */
+    pub needed: u64,
+    /**
+     * The bytes free on the disk
+     */
+    pub available: u64,
+}
+
+impl fmt::Display for InsufficientDiskSpace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "InsufficientDiskSpace: {} bytes needed, {} available",
+            self.needed, self.available
+        )
+    }
+}
+
+impl std::error::Error for InsufficientDiskSpace {}
+
+/**
+ * Make sure a game will fit on disk before it's downloaded, evicting other games if it won't. The
+ * space needed is what's left of the archive plus the extracted files. If the archive's size can't
+ * be found out, the download goes ahead and the space is checked again before extracting.
+ *
+ * # Errors
+ * This function will return an `InsufficientDiskSpace` error if the game won't fit, or an error if
+ * evicting games fails.
+ */
+async fn ensure_space(game: &DevcadeGame, partial: &Path) -> Result<(), Error> {
+    let size = match archive_size(game, Priority::Normal).await {
+        Ok(Some(size)) => size,
+        Ok(None) => return Ok(()),
+        Err(e) => {
+            log!(
+                Level::Debug,
+                "Couldn't get size of game {}, not checking space: {}",
+                game.id,
+                e
+            );
+            return Ok(());
+        }
+    };
+    let downloaded = std::fs::metadata(partial).map_or(0, |meta| meta.len());
+    let size = size
+        .saturating_sub(downloaded)
+        .saturating_add(size.saturating_mul(INSTALL_SPACE_FACTOR - 1));
+
+    make_room(game.id.as_str(), size).await?;
+    let needed = size.saturating_add(min_free_space());
+    match free_space(layout::root().as_path()) {
+        Some(available) if available < needed => {
+            log!(
+                Level::Warn,
+                "Not downloading game {}: {} bytes needed, {} available",
+                game.id,
+                needed,
+                available
+            );
+            Err(InsufficientDiskSpace { needed, available }.into())
+        }
+        _ => Ok(()),
+    }
+}
+
+/**
+ * Fold a finished download into the throughput estimate. Small downloads are ignored, since
+ * connection setup dominates them.
+ */
+fn record_throughput(bytes: u64, elapsed: Duration) {
+    if bytes < 1024 * 1024 || elapsed.is_zero() {
+        return;
+    }
+    #[allow(clippy::cast_precision_loss)]
+    let sample = bytes as f64 / elapsed.as_secs_f64();
+    let mut throughput = THROUGHPUT.lock().unwrap();
+    *throughput = Some(throughput.map_or(sample, |average| average * 0.7 + sample * 0.3));
+}
+
+/**
+ * Move a staged install into a game's directory. Each top level entry of the staging directory
+ * replaces the entry with the same name, with `game.json` going last so an interrupted swap is
+ * redownloaded. The replaced entries are moved aside into `replaced` first and deleted once
+ * everything is in place. If a move fails, the entries already swapped are put back.
+ *
+ * # Errors
+ * This function will return an error if an entry cannot be moved.
+ */
+fn swap_install(dir: &Path, staging: &Path, replaced: &Path) -> Result<(), Error> {
+    faults::check(site::FS_INSTALL)?;
+    std::fs::create_dir_all(dir)?;
+    let old = TempPath(replaced.to_path_buf());
+    if old.0.exists() {
+        std::fs::remove_dir_all(&old.0)?;
+    }
+    std::fs::create_dir_all(&old.0)?;
+
+    let mut names: Vec<_> = std::fs::read_dir(staging)?
+        .map(|entry| entry.map(|entry| entry.file_name()))
+        .collect::<Result<_, _>>()?;
+    names.sort_by_key(|name| name == "game.json");
+
+    let mut swapped = Vec::new();
+    for name in &names {
+        let target = dir.join(name);
+        let result = (|| {
+            if target.exists() {
+                std::fs::rename(&target, old.0.join(name))?;
+            }
+            std::fs::rename(staging.join(name), &target)
+        })();
+        if let Err(e) = result {
+            log!(
+                Level::Error,
+                "Couldn't install {}, restoring the previous install: {}",
+                target.display(),
+                e
+            );
+            for name in swapped.iter().copied().chain(std::iter::once(name)) {
+                let target = dir.join(name);
+                if swapped.contains(&name) {
+                    let _ = std::fs::rename(&target, staging.join(name));
+                }
+                let previous = old.0.join(name);
+                if previous.exists() {
+                    let _ = std::fs::rename(&previous, &target);
+                }
+            }
+            return Err(e.into());
+        }
+        swapped.push(name);
+    }
+    Ok(())
+}
+
+/**
+ * A temporary file or directory that is deleted when dropped, so it's cleaned up whether or not the
+ * work using it succeeded
+ */
+struct TempPath(PathBuf);
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        let result = if self.0.is_dir() {
+            std::fs::remove_dir_all(&self.0)
+        } else {
+            std::fs::remove_file(&self.0)
+        };
+        if let Err(e) = result {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                log!(
+                    Level::Warn,
+                    "Couldn't remove temporary path {}: {}",
+                    self.0.display(),
+                    e
+                );
+            }
+        }
+    }
+}
+
+// The devcade directory lock is held across awaits on purpose, each test has its own runtime
+#[cfg(test)]
+#[allow(clippy::await_holding_lock)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, Reply};
+    use std::io::Write;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn pruned_installs_launch_and_stay_installed() {
+        let (_guard, root) = testing::root("install-pruned");
+        let kept: &[(&str, &[u8])] = &[
+            ("publish/Pong", b"#!/bin/sh\nexit 0\n"),
+            ("publish/data/level.dat", b"LEVEL"),
+        ];
+        let pruned: &[(&str, &[u8])] = &[
+            ("publish/Pong.pdb", &[0; 64 * 1024]),
+            ("publish/.git/HEAD", b"ref: refs/heads/main"),
+            ("publish/Pong.dSYM/Contents/Info.plist", b"<plist/>"),
+            ("publish/win-x64/Pong.exe", b"MZ"),
+        ];
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        for (name, contents) in kept.iter().chain(pruned) {
+            zip.start_file(*name, zip::write::FileOptions::default())
+                .unwrap();
+            zip.write_all(contents).unwrap();
+        }
+        let archive = zip.finish().unwrap().into_inner();
+        std::fs::write(root.join("pong.zip"), &archive).unwrap();
+        let game = DevcadeGame {
+            id: String::from("pong"),
+            name: String::from("Pong"),
+            hash: file_hash(&root.join("pong.zip")).unwrap(),
+            ..DevcadeGame::default()
+        };
+
+        let archive_requests = Arc::new(AtomicUsize::new(0));
+        let url = {
+            let (game, archive_requests) = (
+                serde_json::to_vec(&game).unwrap(),
+                Arc::clone(&archive_requests),
+            );
+            testing::serve(move |path, _| match path {
+                "/games/pong" => Reply::Respond("200 OK", Vec::new(), game.clone()),
+                "/games/pong/game" => {
+                    archive_requests.fetch_add(1, Ordering::SeqCst);
+                    Reply::Respond("200 OK", Vec::new(), archive.clone())
+                }
+                _ => Reply::Respond("404 Not Found", Vec::new(), Vec::new()),
+            })
+        };
+        std::env::set_var("DEVCADE_API_DOMAIN", url.as_str());
+        std::env::set_var("DEVCADE_DEV_API_DOMAIN", url.as_str());
+        std::env::set_var("DEVCADE_VERIFY_ON_LAUNCH", "full");
+
+        let outcome = download_with_priority(String::from("pong"), DownloadPriority::Interactive)
+            .await
+            .unwrap();
+        assert_eq!(outcome.kind, InstallKind::FreshInstall);
+        let dir = layout::game_dir("pong");
+        for (name, contents) in kept {
+            assert_eq!(std::fs::read(dir.join(name)).unwrap(), *contents);
+        }
+        for (name, _) in pruned {
+            assert!(!dir.join(name).exists(), "{name}");
+        }
+        let manifest = read_manifest(dir.as_path()).unwrap();
+        assert_eq!(manifest.len(), kept.len());
+        // Pruning doesn't change which version is installed
+        assert_eq!(installed_game("pong").unwrap().hash, game.hash);
+
+        // Every installed file matches the manifest of the pruned tree
+        super::super::launch::launch(String::from("pong"), false, false)
+            .await
+            .unwrap();
+
+        // Syncing again sees the same version, so nothing is downloaded
+        let requested = archive_requests.load(Ordering::SeqCst);
+        let outcome = download_with_priority(String::from("pong"), DownloadPriority::Interactive)
+            .await
+            .unwrap();
+        assert_eq!(outcome.kind, InstallKind::AlreadyInstalled);
+        assert_eq!(archive_requests.load(Ordering::SeqCst), requested);
+
+        std::env::remove_var("DEVCADE_API_DOMAIN");
+        std::env::remove_var("DEVCADE_DEV_API_DOMAIN");
+        std::env::remove_var("DEVCADE_VERIFY_ON_LAUNCH");
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,441 @@
+use super::{
+    accessibility, cabinet_settings, demo, display_protection, download_with_priority,
+    emit_launch_event, event_label, feature_usage, find_executable, game_setup, installed_game,
+    is_sideloaded, launch_verification, operations, peer, quiet_hours, record_launch, runtime,
+    sandbox, sessions, usage, RunningGame, CURRENT_GAME, INSTALLING, RUNNING_GAME, SAVE_NAMESPACE,
+};
+use crate::clock::{self, unix_now};
+use crate::env::{locale, timezone};
+use crate::layout;
+use crate::servers;
+use anyhow::{anyhow, Error};
+use devcade_onboard_types::{
+    DownloadPriority, GameIntent, GameOperation, LaunchEventKind, OperationOrigin,
+};
+use log::{log, Level};
+use std::ffi::OsStr;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+use tokio::process::Command;
+
+/**
+ * How long a game has to run to count as launching successfully, even if it then exits with an
+ * error
+ */
+const LAUNCH_SUCCESS_AFTER: Duration = Duration::from_secs(10);
+
+/**
+ * Launch an installed game, and wait for it to exit
+ *
+ * # Errors
+ * This function will return an error if the game isn't installed, or can't be launched.
+ */
+pub(super) async fn launch(
+    game_id: String,
+    ignore_policy: bool,
+    share_saves: bool,
+) -> Result<(), Error> {
+    let path = layout::game_dir(game_id.as_str()).join("publish");
+
+    quiet_hours::check()?;
+
+    display_protection::activity("launch");
+    log!(Level::Info, "Launching game {}...", game_id);
+    log!(Level::Trace, "Game path: {}", path.to_str().unwrap());
+
+    let installed = if installed_game(game_id.as_str()).is_some() {
+        None
+    } else {
+        if path.exists() {
+            log!(
+                Level::Warn,
+                "Game {} is only partly installed, installing it again",
+                game_id
+            );
+        }
+        // A player is waiting on the launch
+        Some(
+            download_with_priority(game_id.clone(), DownloadPriority::Interactive)
+                .await?
+                .game,
+        )
+    };
+
+    // Held until the game is spawned, so an install can't be swapped in halfway through a launch
+    let installing = INSTALLING.read().await;
+    let game = match installed {
+        Some(game) => game,
+        None => installed_game(game_id.as_str())
+            .ok_or_else(|| anyhow!("Game {game_id} is no longer installed"))?,
+    };
+    if ignore_policy {
+        log!(
+            Level::Warn,
+            "Launching {} regardless of the accessibility policy",
+            game.name
+        );
+    } else {
+        accessibility::check(&game)?;
+    }
+    let (path, strategy) = find_executable(path.as_path(), game.name.as_str())?;
+    {
+        // Never waits, since verifying only conflicts with a removal, which it's rejected by
+        let _verifying = operations::admit(
+            game.id.as_str(),
+            GameOperation {
+                intent: GameIntent::Verify,
+                origin: OperationOrigin::Launch,
+            },
+        )
+        .await?;
+        let game = game.clone();
+        let path = path.clone();
+        tokio::task::spawn_blocking(move || launch_verification::verify(&game, path.as_path()))
+            .await??;
+    }
+
+    // flush data every time a new game is opened (in case previous launched game forgor)
+    match servers::persistence::flush().await {
+        Ok(_) => {}
+        Err(e) => log::warn!("Failed to flush save cache: {e}"),
+    }
+    let namespace = if let Some(guest) = demo::guest_playing() {
+        log!(
+            Level::Info,
+            "{} is playing {} with a demo wristband, its saves won't be kept",
+            guest.name,
+            game.name
+        );
+        demo::namespace(&guest, game.id.as_str())
+    } else if is_sideloaded(game.id.as_str()) && !share_saves {
+        log!(
+            Level::Info,
+            "Game {} is sideloaded, keeping its saves apart from the API's copy",
+            game.name
+        );
+        format!("sideload/{}", game.id)
+    } else {
+        game.id.clone()
+    };
+    *SAVE_NAMESPACE.lock().unwrap() = namespace.clone();
+    CURRENT_GAME.lock().unwrap().set(game);
+
+    // Chmod +x the executable
+    let mut perms = path.metadata()?.permissions();
+    perms.set_mode(0o755);
+
+    std::fs::set_permissions(path.clone(), perms)?;
+
+    if let Some(failed) = game_setup::failed(layout::game_dir(game_id.as_str()).as_path()) {
+        log!(
+            Level::Warn,
+            "Setup of game {} failed ({}), launching it anyway",
+            game_id,
+            failed
+        );
+    }
+
+    // Launch the game and silence stdout (allow the game to print to stderr)
+    // This unwrap is safe because it is guaranteed to have a parent
+    let mut child = game_command(
+        path.as_os_str(),
+        path.parent().unwrap(),
+        game_id.as_str(),
+        namespace.as_str(),
+    );
+
+    child.stdout(Stdio::null());
+    // Unfortunately this will bypass the log crate, so no pretty logging for games
+    child.stderr(std::process::Stdio::inherit());
+    // Tells the game about the cabinet next to this one, if it's running the same game
+    child.envs(peer::game_started(game_id.as_str()));
+
+    emit_launch_event(LaunchEventKind::GameTakingFocus(game_id.clone()));
+    let mut child = match child.spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            // The frontend stopped handling input, so it has to be told to start again
+            emit_launch_event(LaunchEventKind::GameReleasedFocus);
+            runtime::launched(game_id.as_str(), strategy, false);
+            peer::game_exited();
+            return Err(anyhow!("Failed to launch game {game_id}: {e}"));
+        }
+    };
+    if let Some(pid) = child.id() {
+        emit_launch_event(LaunchEventKind::GameSpawned(pid));
+    }
+    let event = event_label::active();
+    *RUNNING_GAME.lock().unwrap() = child.id().map(|pid| RunningGame {
+        pid,
+        spawned: clock::now(),
+        paused: None,
+        event: event.clone(),
+    });
+    if let Some(pid) = child.id() {
+        usage::start(game_id.as_str(), pid);
+    }
+    feature_usage::start();
+    drop(installing);
+    record_launch(game_id.as_str());
+    let started = (unix_now(), clock::now());
+    let status = child.wait().await;
+    // Games that ran for a while worked, however they were stopped
+    let succeeded = status.as_ref().is_ok_and(std::process::ExitStatus::success)
+        || clock::elapsed(started.1) >= LAUNCH_SUCCESS_AFTER;
+    runtime::launched(game_id.as_str(), strategy, succeeded);
+    end_session(
+        game_id.as_str(),
+        started,
+        event.as_deref(),
+        status
+            .as_ref()
+            .ok()
+            .and_then(std::process::ExitStatus::code),
+    );
+    status?;
+
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    Ok(())
+}
+
+/**
+ * Make a command that runs `program` in `dir` the way a game is run: with the settings games are
+ * passed, under the game's network policy, and in its own process group so it can be signalled
+ * along with any children.
+ */
+pub(super) fn game_command(program: &OsStr, dir: &Path, game_id: &str, namespace: &str) -> Command {
+    let mut child = Command::new(program);
+    child.current_dir(dir);
+    if let Some(locale) = locale() {
+        child.env("DEVCADE_LOCALE", locale);
+    }
+    if let Some(tz) = timezone() {
+        child.env("DEVCADE_TZ", tz);
+    }
+    // Games read settings like calibration from here, the file may not exist until one is set
+    child.env("DEVCADE_CABINET_SETTINGS", cabinet_settings::path());
+    child.env("DEVCADE_SAVE_NAMESPACE", namespace);
+    if demo::is_demo_namespace(namespace) {
+        // Games can skip things like leaderboard entries for guests
+        child.env("DEVCADE_DEMO", "1");
+        if let Some(guest) = demo::guest_playing() {
+            child.env("DEVCADE_PLAYER_NAME", guest.name);
+        }
+    }
+
+    let policy = sandbox::policy_for(game_id);
+    let enforced = sandbox::apply(&mut child, policy);
+    log!(
+        Level::Info,
+        "Game {} network policy: {} ({})",
+        game_id,
+        policy,
+        if enforced { "enforced" } else { "not enforced" }
+    );
+
+    // SAFETY: setpgid is async-signal-safe
+    unsafe {
+        child.pre_exec(|| {
+            if libc::setpgid(0, 0) == 0 {
+                Ok(())
+            } else {
+                Err(std::io::Error::last_os_error())
+            }
+        });
+    }
+    child
+}
+
+/**
+ * Record the session of a game that exited, and tell clients it released focus. `started` is when
+ * the session started, as a unix timestamp and an instant, and `code` is the game's exit code if
+ * it's known. A demo guest's session is logged as a demo play, and its saves are thrown away.
+ */
+pub(super) fn end_session(
+    game_id: &str,
+    started: (u64, Instant),
+    event: Option<&str>,
+    code: Option<i32>,
+) {
+    *RUNNING_GAME.lock().unwrap() = None;
+    peer::game_exited();
+    let peak = usage::stop();
+    let namespace = SAVE_NAMESPACE.lock().unwrap().clone();
+    let demo = demo::is_demo_namespace(namespace.as_str());
+    if let Err(e) = sessions::finish(
+        game_id,
+        started.0,
+        clock::elapsed(started.1).as_secs(),
+        peak.as_ref(),
+        event,
+        demo,
+    ) {
+        log!(Level::Warn, "Couldn't record session of {}: {}", game_id, e);
+    }
+    let (id, used) = (game_id.to_string(), feature_usage::take());
+    tokio::task::spawn_blocking(move || feature_usage::finish(id.as_str(), used));
+    if demo {
+        tokio::spawn(async move { demo::end(namespace.as_str()).await });
+    }
+    emit_launch_event(LaunchEventKind::GameReleasedFocus);
+    emit_launch_event(LaunchEventKind::GameExited(code));
+}
+
+// The devcade directory lock is held across awaits on purpose, each test has its own runtime
+#[cfg(test)]
+#[allow(clippy::await_holding_lock)]
+mod tests {
+    use super::super::launch_events;
+    use super::*;
+    use devcade_onboard_types::schema::DevcadeGame;
+
+    /**
+     * Install a game whose executable is this script
+     */
+    fn install_stub(game_id: &str, script: &str) {
+        let dir = layout::game_dir(game_id);
+        std::fs::create_dir_all(dir.join("publish")).unwrap();
+        let game = DevcadeGame {
+            id: game_id.to_string(),
+            name: String::from("Stub"),
+            hash: String::from("stub"),
+            ..DevcadeGame::default()
+        };
+        std::fs::write(dir.join("game.json"), serde_json::to_vec(&game).unwrap()).unwrap();
+        std::fs::write(dir.join("publish/Stub"), script).unwrap();
+    }
+
+    /**
+     * Launch a game, and get the launch events it caused in order
+     */
+    async fn launch_events_of(game_id: &str) -> (Result<(), Error>, Vec<LaunchEventKind>) {
+        let before = launch_events(0).last().map_or(0, |event| event.seq);
+        let result = launch(game_id.to_string(), false, false).await;
+        let events = launch_events(before);
+        assert!(events.windows(2).all(|pair| pair[1].seq == pair[0].seq + 1));
+        let kinds = events
+            .into_iter()
+            .map(|event| event.kind)
+            // Other tests may record events of their own in between
+            .filter(|kind| {
+                matches!(
+                    kind,
+                    LaunchEventKind::GameTakingFocus(_)
+                        | LaunchEventKind::GameSpawned(_)
+                        | LaunchEventKind::GameReleasedFocus
+                        | LaunchEventKind::GameExited(_)
+                )
+            })
+            .collect();
+        (result, kinds)
+    }
+
+    #[tokio::test]
+    async fn focus_is_taken_and_released_around_a_launch() {
+        let (_guard, root) = crate::testing::root("launch-focus");
+        install_stub("stub", "#!/bin/sh\nexit 3\n");
+        let (result, events) = launch_events_of("stub").await;
+        result.unwrap();
+        let [taking, spawned, released, exited] = events.as_slice() else {
+            panic!("{events:?}");
+        };
+        assert_eq!(
+            *taking,
+            LaunchEventKind::GameTakingFocus(String::from("stub"))
+        );
+        assert!(matches!(spawned, LaunchEventKind::GameSpawned(pid) if *pid > 0));
+        assert_eq!(*released, LaunchEventKind::GameReleasedFocus);
+        assert_eq!(*exited, LaunchEventKind::GameExited(Some(3)));
+
+        // The interpreter doesn't exist, so spawning fails
+        install_stub("broken", "#!/nonexistent/interpreter\n");
+        let (result, events) = launch_events_of("broken").await;
+        assert!(result.is_err());
+        assert_eq!(
+            events,
+            vec![
+                LaunchEventKind::GameTakingFocus(String::from("broken")),
+                LaunchEventKind::GameReleasedFocus,
+            ]
+        );
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn sideloaded_copies_keep_their_saves_apart() {
+        use crate::command::handle;
+        use devcade_onboard_types::{RequestBody, ResponseBody};
+
+        let (_guard, root) = crate::testing::root("launch-sideload");
+        // Saves aren't kept under the devcade directory, so the ID is kept apart from other runs
+        let id = format!("jam-{}", std::process::id());
+        let sideload = format!("sideload/{id}");
+        // The game writes down the namespace it was given
+        install_stub(
+            id.as_str(),
+            "#!/bin/sh\necho \"$DEVCADE_SAVE_NAMESPACE\" > ../namespace\n",
+        );
+        let namespace = || {
+            std::fs::read_to_string(layout::game_dir(id.as_str()).join("namespace"))
+                .unwrap()
+                .trim()
+                .to_string()
+        };
+        // Requests go to the namespace of the game launched last, even after it exits
+        let save = |value: &str| {
+            handle(RequestBody::Save(
+                String::from("slot"),
+                String::from("score"),
+                value.to_string(),
+            ))
+        };
+        let load = || async {
+            match handle(RequestBody::Load(
+                String::from("slot"),
+                String::from("score"),
+            ))
+            .await
+            {
+                ResponseBody::Object(value) => Some(value),
+                _ => None,
+            }
+        };
+
+        // The API's copy
+        launch(id.clone(), false, false).await.unwrap();
+        assert_eq!(namespace(), id);
+        assert!(matches!(save("9001").await, ResponseBody::Ok));
+
+        // A dev build of the same game, sideloaded over it
+        std::fs::write(layout::game_dir(id.as_str()).join("sideloaded"), b"").unwrap();
+        launch(id.clone(), false, false).await.unwrap();
+        assert_eq!(namespace(), sideload);
+        assert_eq!(load().await, None);
+        assert!(matches!(save("3").await, ResponseBody::Ok));
+        assert_eq!(load().await.as_deref(), Some("3"));
+
+        // Unless it's launched to share the API copy's saves on purpose
+        launch(id.clone(), false, true).await.unwrap();
+        assert_eq!(namespace(), id);
+        assert_eq!(load().await.as_deref(), Some("9001"));
+
+        let saved = |namespace: &str| {
+            let group = format!("{namespace}/slot");
+            async move {
+                servers::persistence::load(group.as_str(), "score")
+                    .await
+                    .unwrap()
+            }
+        };
+        assert_eq!(saved(id.as_str()).await, "9001");
+        assert_eq!(saved(sideload.as_str()).await, "3");
+
+        servers::persistence::discard(id.as_str()).await.unwrap();
+        servers::persistence::discard(sideload.as_str())
+            .await
+            .unwrap();
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,315 @@
+use super::{emit_launch_event, file_hash, read_manifest, Manifest};
+use crate::clock;
+use crate::env::{verify_on_launch, verify_skip_dirs};
+use crate::layout;
+use crate::state::{self, JsonState};
+use anyhow::{anyhow, Error};
+use devcade_onboard_types::schema::DevcadeGame;
+use devcade_onboard_types::LaunchEventKind;
+use lazy_static::lazy_static;
+use log::{log, Level};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/**
+ * How many files are checked between progress events
+ */
+const PROGRESS_EVERY: usize = 50;
+
+lazy_static! {
+    // Files that matched their manifest, by game ID
+    static ref VERIFIED: JsonState<BTreeMap<String, Verified>> = JsonState::new(path);
+}
+
+/**
+ * How much of a game is checked before it's launched
+ */
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Mode {
+    Off,
+    Executable,
+    Full,
+}
+
+/**
+ * The error a launch fails with when a game's files don't match its manifest
+ */
+#[derive(Debug, Clone)]
+pub struct TamperDetected {
+    pub game_id: String,
+    /// The files that differ or are missing, relative to the game's directory
+    pub files: Vec<String>,
+}
+
+impl fmt::Display for TamperDetected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "TamperDetected: files of game {} don't match what was installed: {}",
+            self.game_id,
+            self.files.join(", ")
+        )
+    }
+}
+
+impl std::error::Error for TamperDetected {}
+
+/**
+ * The files of an install that matched its manifest when they were last checked
+ */
+#[derive(Serialize, Deserialize, Default)]
+struct Verified {
+    /// The hash of the game the files were checked for
+    hash: String,
+    /// When each file was last modified, in nanoseconds since the Unix epoch, and its size
+    files: BTreeMap<String, (u64, u64)>,
+}
+
+/**
+ * One line of the journal
+ */
+#[derive(Serialize)]
+struct JournalEntry<'a> {
+    time: u64,
+    game_id: &'a str,
+    hash: &'a str,
+    files: &'a [String],
+}
+
+fn path() -> PathBuf {
+    layout::state_dir().join("launch_verification.json")
+}
+
+fn journal_path() -> PathBuf {
+    layout::state_dir().join("tamper.journal")
+}
+
+/**
+ * Get the verification mode. Unknown modes check the executable, so a typo doesn't turn checks
+ * off.
+ */
+pub fn mode() -> Mode {
+    match verify_on_launch().as_deref() {
+        None | Some("off") => Mode::Off,
+        Some("executable") => Mode::Executable,
+        Some("full") => Mode::Full,
+        Some(mode) => {
+            log!(
+                Level::Warn,
+                "Unknown launch verification mode '{}', checking the executable",
+                mode
+            );
+            Mode::Executable
+        }
+    }
+}
+
+/**
+ * Whether a file of an install is in one of the directories games write to
+ */
+pub(super) fn skipped(path: &str, skip_dirs: &[String]) -> bool {
+    let Some(path) = path.strip_prefix("publish/") else {
+        return false;
+    };
+    skip_dirs.iter().any(|dir| {
+        path.strip_prefix(dir.as_str())
+            .is_some_and(|rest| rest.starts_with('/'))
+    })
+}
+
+/**
+ * Get when a file was last modified, in nanoseconds since the Unix epoch
+ */
+fn modified(meta: &std::fs::Metadata) -> u64 {
+    meta.modified()
+        .ok()
+        .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+        .and_then(|since| u64::try_from(since.as_nanos()).ok())
+        .unwrap_or(0)
+}
+
+/**
+ * Check files of an install against its manifest, hashing only the ones that changed since they
+ * last matched. Returns the files that differ or are missing.
+ */
+fn changed_files(
+    dir: &Path,
+    manifest: &Manifest,
+    paths: &[String],
+    verified: &mut Verified,
+    mut progress: impl FnMut(usize),
+) -> Vec<String> {
+    let mut changed = Vec::new();
+    for (i, path) in paths.iter().enumerate() {
+        let file = dir.join(path);
+        let matches = match (manifest.get(path), std::fs::metadata(&file)) {
+            (Some(expected), Ok(meta)) if meta.len() == expected.size => {
+                let stamp = (modified(&meta), meta.len());
+                verified.files.get(path) == Some(&stamp)
+                    || file_hash(&file).is_ok_and(|hash| hash == expected.hash) && {
+                        verified.files.insert(path.clone(), stamp);
+                        true
+                    }
+            }
+            _ => false,
+        };
+        if !matches {
+            verified.files.remove(path);
+            changed.push(path.clone());
+        }
+        if (i + 1) % PROGRESS_EVERY == 0 {
+            progress(i + 1);
+        }
+    }
+    changed
+}
+
+/**
+ * Check a game's files against its manifest before it's launched, as `DEVCADE_VERIFY_ON_LAUNCH`
+ * says. `executable` is the file that's about to be run. This hashes files, so call it off the
+ * async runtime.
+ *
+ * # Errors
+ * This function will return a `TamperDetected` error if any file differs from what was
+ * installed, or an error if the game has no manifest to check against.
+ */
+pub fn verify(game: &DevcadeGame, executable: &Path) -> Result<(), Error> {
+    let mode = mode();
+    if mode == Mode::Off {
+        return Ok(());
+    }
+    let dir = layout::game_dir(game.id.as_str());
+    let manifest = read_manifest(dir.as_path()).ok_or_else(|| {
+        anyhow!(
+            "Game {} has no manifest to verify it against, reinstall it to launch it",
+            game.id
+        )
+    })?;
+    let paths: Vec<String> = match mode {
+        Mode::Off => Vec::new(),
+        Mode::Executable => {
+            let relative = executable
+                .strip_prefix(&dir)
+                .map_err(|_| anyhow!("{} isn't in the game's directory", executable.display()))?;
+            vec![relative.to_string_lossy().into_owned()]
+        }
+        Mode::Full => {
+            let skip_dirs = verify_skip_dirs();
+            manifest
+                .keys()
+                .filter(|path| !skipped(path, &skip_dirs))
+                .cloned()
+                .collect()
+        }
+    };
+
+    let mut verified = VERIFIED.lock();
+    let game_verified = verified.entry(game.id.clone()).or_default();
+    if game_verified.hash != game.hash {
+        *game_verified = Verified {
+            hash: game.hash.clone(),
+            ..Verified::default()
+        };
+    }
+    let total = paths.len() as u64;
+    let changed = changed_files(dir.as_path(), &manifest, &paths, game_verified, |checked| {
+        if mode == Mode::Full {
+            emit_launch_event(LaunchEventKind::VerifyingFiles(checked as u64, total));
+        }
+    });
+    if let Err(e) = verified.save() {
+        log!(Level::Warn, "Couldn't save verified files: {}", e);
+    }
+    if changed.is_empty() {
+        log!(
+            Level::Debug,
+            "Verified {} files of game {}",
+            paths.len(),
+            game.id
+        );
+        return Ok(());
+    }
+
+    log!(
+        Level::Error,
+        "Refusing to launch game {}, {} files don't match its manifest",
+        game.id,
+        changed.len()
+    );
+    let entry = JournalEntry {
+        time: clock::unix_now(),
+        game_id: game.id.as_str(),
+        hash: game.hash.as_str(),
+        files: &changed,
+    };
+    if let Err(e) = state::journal(journal_path().as_path(), &entry) {
+        log!(Level::Warn, "Couldn't journal tampered game: {}", e);
+    }
+    Err(TamperDetected {
+        game_id: game.id.clone(),
+        files: changed,
+    }
+    .into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::extract::{to_hex, ManifestEntry};
+    use super::*;
+    use sha2::{Digest, Sha256};
+
+    fn entry(contents: &[u8]) -> ManifestEntry {
+        ManifestEntry {
+            hash: to_hex(&Sha256::digest(contents)),
+            size: contents.len() as u64,
+        }
+    }
+
+    #[test]
+    fn finds_changed_files_and_skips_unchanged_ones() {
+        let dir = std::env::temp_dir().join(format!("devcade-verify-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("publish/data")).unwrap();
+        let files: [(&str, &[u8]); 4] = [
+            ("publish/Game", b"game"),
+            ("publish/Game.dll", b"library"),
+            ("publish/level.dat", b"level"),
+            ("publish/data/settings.json", b"{}"),
+        ];
+        let mut manifest = Manifest::new();
+        for (path, contents) in files {
+            std::fs::write(dir.join(path), contents).unwrap();
+            manifest.insert(path.to_string(), entry(contents));
+        }
+        let skip_dirs = vec!["data".to_string()];
+        let paths: Vec<String> = manifest
+            .keys()
+            .filter(|path| !skipped(path, &skip_dirs))
+            .cloned()
+            .collect();
+        assert_eq!(paths.len(), 3);
+
+        let mut verified = Verified::default();
+        assert!(changed_files(&dir, &manifest, &paths, &mut verified, |_| ()).is_empty());
+        assert_eq!(verified.files.len(), 3);
+
+        // Changed in place with its modification time put back, so it isn't hashed again
+        let file = std::fs::File::options()
+            .write(true)
+            .open(dir.join("publish/level.dat"))
+            .unwrap();
+        let modified = file.metadata().unwrap().modified().unwrap();
+        std::fs::write(dir.join("publish/level.dat"), b"LEVEL").unwrap();
+        file.set_modified(modified).unwrap();
+        assert!(changed_files(&dir, &manifest, &paths, &mut verified, |_| ()).is_empty());
+
+        std::fs::write(dir.join("publish/Game"), b"hack").unwrap();
+        std::fs::remove_file(dir.join("publish/Game.dll")).unwrap();
+        std::fs::write(dir.join("publish/data/settings.json"), b"{\"volume\":1}").unwrap();
+        let changed = changed_files(&dir, &manifest, &paths, &mut verified, |_| ());
+        assert_eq!(changed, vec!["publish/Game", "publish/Game.dll"]);
+        assert!(!verified.files.contains_key("publish/Game"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,229 @@
+use super::{game_list_from_fs, listed_games};
+use crate::layout;
+use crate::state::JsonState;
+use anyhow::Error;
+use devcade_onboard_types::schema::DevcadeGame;
+use devcade_onboard_types::LibraryUpdate;
+use lazy_static::lazy_static;
+use log::{log, Level};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::path::PathBuf;
+
+/**
+ * How many generations of changes are kept. Clients further behind get every game.
+ */
+const MAX_HISTORY: usize = 32;
+
+lazy_static! {
+    static ref LIBRARY: JsonState<Library> = JsonState::new(path);
+}
+
+/**
+ * The installed games as clients last saw them, kept on disk so the generation survives restarts
+ */
+#[derive(Serialize, Deserialize, Default)]
+struct Library {
+    generation: u64,
+    games: BTreeMap<String, DevcadeGame>,
+    /// The IDs of the games added, changed or removed in each of the last `MAX_HISTORY`
+    /// generations, oldest first
+    history: VecDeque<(u64, BTreeSet<String>)>,
+}
+
+impl Library {
+    /**
+     * Replace the games, moving to the next generation if any of them changed. Returns whether
+     * any did.
+     */
+    fn update(&mut self, games: Vec<DevcadeGame>) -> bool {
+        let games: BTreeMap<String, DevcadeGame> = games
+            .into_iter()
+            .map(|game| (game.id.clone(), game))
+            .collect();
+        // `DevcadeGame` can't be compared, but its JSON can
+        let same = |a: &DevcadeGame, b: &DevcadeGame| {
+            serde_json::to_value(a).ok() == serde_json::to_value(b).ok()
+        };
+        let changed: BTreeSet<String> = games
+            .iter()
+            .filter(|(id, game)| !self.games.get(*id).is_some_and(|old| same(old, game)))
+            .map(|(id, _)| id.clone())
+            .chain(
+                self.games
+                    .keys()
+                    .filter(|id| !games.contains_key(*id))
+                    .cloned(),
+            )
+            .collect();
+        if changed.is_empty() {
+            return false;
+        }
+
+        self.generation += 1;
+        self.games = games;
+        self.history.push_back((self.generation, changed));
+        while self.history.len() > MAX_HISTORY {
+            self.history.pop_front();
+        }
+        true
+    }
+
+    /**
+     * Get what changed since the client's generation
+     */
+    fn since(&self, known: Option<u64>) -> LibraryUpdate {
+        let generation = self.generation;
+        let full = || LibraryUpdate::Full {
+            generation,
+            games: self.games.values().cloned().collect(),
+        };
+        let Some(known) = known else {
+            return full();
+        };
+        if known == generation {
+            return LibraryUpdate::NotModified { generation };
+        }
+        // The history has to cover every generation after the client's
+        let covered = self
+            .history
+            .front()
+            .is_some_and(|(oldest, _)| *oldest <= known + 1);
+        if known > generation || !covered {
+            return full();
+        }
+
+        let ids: BTreeSet<&String> = self
+            .history
+            .iter()
+            .filter(|(changed_in, _)| *changed_in > known)
+            .flat_map(|(_, ids)| ids)
+            .collect();
+        let (changed, removed): (Vec<&String>, Vec<&String>) =
+            ids.into_iter().partition(|id| self.games.contains_key(*id));
+        LibraryUpdate::Delta {
+            generation,
+            changed: changed
+                .into_iter()
+                .map(|id| self.games[id].clone())
+                .collect(),
+            removed: removed.into_iter().cloned().collect(),
+        }
+    }
+}
+
+fn path() -> PathBuf {
+    layout::state_dir().join("library.json")
+}
+
+/**
+ * Get the installed games, or what changed in them since the client's generation
+ *
+ * # Errors
+ * This function will return an error if the installed games can't be listed.
+ */
+pub fn get(known: Option<u64>) -> Result<LibraryUpdate, Error> {
+    let games = listed_games(game_list_from_fs()?);
+    let mut library = LIBRARY.lock();
+    if library.update(games) {
+        log!(
+            Level::Debug,
+            "Installed games changed, library is at generation {}",
+            library.generation
+        );
+        if let Err(e) = library.save() {
+            log!(Level::Warn, "Couldn't save library: {}", e);
+        }
+    }
+    Ok(library.since(known))
+}
+
+/**
+ * Get the library's generation
+ */
+pub fn generation() -> u64 {
+    LIBRARY.lock().generation
+}
+
+/**
+ * Move the library to at least the generation a previous backend handed off, so clients never see
+ * it go back. The history is dropped if it does move, since what changed in between isn't known.
+ */
+pub fn restore(generation: u64) {
+    let mut library = LIBRARY.lock();
+    if library.generation >= generation {
+        return;
+    }
+    library.generation = generation;
+    library.history.clear();
+    if let Err(e) = library.save() {
+        log!(Level::Warn, "Couldn't save library: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn game(id: &str, hash: &str) -> DevcadeGame {
+        DevcadeGame {
+            id: id.to_string(),
+            hash: hash.to_string(),
+            ..DevcadeGame::default()
+        }
+    }
+
+    fn ids(games: &[DevcadeGame]) -> Vec<&str> {
+        games.iter().map(|game| game.id.as_str()).collect()
+    }
+
+    #[test]
+    fn not_modified() {
+        let mut library = Library::default();
+        assert!(library.update(vec![game("pong", "1")]));
+        assert!(!library.update(vec![game("pong", "1")]));
+        assert!(matches!(
+            library.since(Some(1)),
+            LibraryUpdate::NotModified { generation: 1 }
+        ));
+    }
+
+    #[test]
+    fn small_delta() {
+        let mut library = Library::default();
+        library.update(vec![game("pong", "1"), game("snake", "1")]);
+        library.update(vec![game("pong", "2"), game("snake", "1")]);
+        library.update(vec![game("pong", "2"), game("tetris", "1")]);
+        let LibraryUpdate::Delta {
+            generation,
+            changed,
+            removed,
+        } = library.since(Some(1))
+        else {
+            panic!("expected a delta");
+        };
+        assert_eq!(generation, 3);
+        assert_eq!(ids(&changed), vec!["pong", "tetris"]);
+        assert_eq!(changed[0].hash, "2");
+        assert_eq!(removed, vec!["snake".to_string()]);
+    }
+
+    #[test]
+    fn too_old_or_unknown() {
+        let mut library = Library::default();
+        for hash in 0..=MAX_HISTORY {
+            library.update(vec![game("pong", hash.to_string().as_str())]);
+        }
+        let generation = library.generation;
+        for known in [None, Some(0), Some(generation + 1)] {
+            let LibraryUpdate::Full { games, .. } = library.since(known) else {
+                panic!("expected every game for {known:?}");
+            };
+            assert_eq!(ids(&games), vec!["pong"]);
+        }
+        assert!(matches!(
+            library.since(Some(generation - 1)),
+            LibraryUpdate::Delta { .. }
+        ));
+    }
+}
@@ -1,602 +1,3596 @@
-use crate::env::{api_url, devcade_path};
-use crate::nfc::NFC_CLIENT;
+use crate::audit;
+use crate::clock::{self, unix_now};
+use crate::env::{
+    api_url, audio_latency_ms, audio_sample_rate, auto_update_interval, auto_update_window,
+    controller_mapping, devcade_path, display_probe_command, event_history, input_telemetry,
+    max_asset_downloads, max_pause, previous_path, screenshot_command, session_retain_days,
+};
+use crate::fds;
+use crate::layout;
+use crate::nfc::{AuthUnavailable, NFC_CLIENT};
+use crate::resources;
 use crate::servers;
 use anyhow::{anyhow, Error};
 use devcade_onboard_types::{
-    schema::{DevcadeGame, MinimalGame, Tag, User},
-    Map, Player, Value,
+    schema::{AccessibilityFlag, DevcadeGame, MinimalGame, Tag, User},
+    AssetResult, CabinetHardware, CacheReport, Capability, CatalogEvents, CatalogSnapshot,
+    CatalogStats, DisplayMode, DisplayProtection, DownloadEstimate, DownloadPriority,
+    DownloadProgress, DownloadQueueState, DownloadStage, DuplicateGroup, EventHookStatus,
+    EventHookTest, EventLabel, FeatureAdoption, FeatureUsage, GameHighlights, GameIntent,
+    GameListWithThumbnails, GameOperation, GameResources, GameRuntime, GameSetupRecord,
+    HardwareProbe, IconAtlas, InputActivity, InstallKind, InstallOutcome, LaunchEvent,
+    LaunchEventKind, LibraryUpdate, LifetimeStats, Map, ObjectStoreReport, OperationOrigin,
+    PeerLink, Player, QuietHoursStatus, RequestBody, Schedule, ScheduleEntry, SessionExport,
+    SetupStatus, StatsCompaction, Subscription, SuspiciousUpdate, TagMembership, TapStats,
+    UpdateSummary, Value,
 };
 use lazy_static::lazy_static;
 use log::{log, Level};
+use network::{Fetched, Priority};
+use operations::Admission;
 
+use futures_util::future::{BoxFuture, FutureExt, Shared};
+use serde::{Deserialize, Serialize};
 use std::ffi::OsStr;
+use std::future::Future;
 
 use std::cell::Cell;
-use std::os::unix::fs::PermissionsExt;
-use std::path::Path;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
+use std::path::{Path, PathBuf};
 use std::process::Stdio;
 use std::sync::Mutex;
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::process::Command;
 
 lazy_static! {
     static ref CURRENT_GAME: Mutex<Cell<DevcadeGame>> =
         Mutex::new(Cell::new(DevcadeGame::default()));
-}
+    // Where the current game's saves go, decided when it's launched
+    static ref SAVE_NAMESPACE: Mutex<String> = Mutex::new(String::new());
+    static ref RUNNING_GAME: Mutex<Option<RunningGame>> = Mutex::new(None);
 
-/**
- * Internal module for network requests and JSON serialization
- */
-mod network {
-    use anyhow::Error;
-    use lazy_static::lazy_static;
-    use log::{log, Level};
-    use serde::Deserialize;
-    use std::ops::Deref;
+    // Recent launch events, for clients that poll or subscribe
+    static ref LAUNCH_EVENTS: event_history::History = event_history::History::new(event_history());
 
-    // Construct a static client to be used for all requests. Prevents opening a new connection for
-    // every request.
-    lazy_static! {
-        static ref CLIENT: reqwest::Client = reqwest::Client::new();
-    }
+    // The cabinet's hardware, filled in by `probe_hardware`
+    static ref HARDWARE: Mutex<HardwareProbe> = Mutex::new(HardwareProbe::Pending);
 
-    /**
-     * Request JSON from a URL and serialize it into a struct
-     *
-     * # Errors
-     * This function will return an error if the request fails, or if the JSON cannot be deserialized
-     */
-    pub async fn request_json<T: for<'de> Deserialize<'de>>(url: &str) -> Result<T, Error> {
-        log!(Level::Trace, "Requesting JSON from {}", url);
-        let response = CLIENT.deref().get(url).send().await?;
-        let json = response.json().await?;
-        Ok(json)
-    }
+    // Held for writing while an install is swapped into place, and for reading while a game
+    // launches
+    static ref INSTALLING: tokio::sync::RwLock<()> = tokio::sync::RwLock::new(());
 
-    /**
-     * Request binary data from a URL
-     *
-     * # Errors
-     * This function will return an error if the request fails.
-     */
-    pub async fn request_bytes(url: &str) -> Result<Vec<u8>, Error> {
-        log!(Level::Trace, "Requesting binary from {}", url);
-        let response = CLIENT.deref().get(url).send().await?;
-        let bytes = response.bytes().await?;
-        Ok(bytes.to_vec())
-    }
-}
+    // Archive sizes from HEAD requests, by game ID, along with the hash of the archive they're for
+    static ref ARCHIVE_SIZES: Mutex<HashMap<String, (String, u64)>> = Mutex::new(HashMap::new());
 
-/**
- * Internal module for API routes and URLs
- * This is used to make sure that the API routes are consistent across the codebase, and can be
- * changed from a single location.
- */
-mod route {
+    // Recent game download throughput in bytes per second, as a moving average
+    static ref THROUGHPUT: Mutex<Option<f64>> = Mutex::new(None);
 
-    /**
-     * Get the list of games
-     */
-    pub fn game_list() -> String {
-        String::from("games/")
-    }
+    // Slots for art downloads. Waiting downloads are let through in arrival order.
+    static ref ASSET_DOWNLOADS: tokio::sync::Semaphore =
+        tokio::sync::Semaphore::new(max_asset_downloads());
 
-    /**
-     * Get a specific game by ID
-     */
-    pub fn game(id: &str) -> String {
-        format!("games/{id}")
-    }
+    // Downloads in progress, so a second request for the same one waits on the first
+    static ref GAMES_IN_FLIGHT: Mutex<HashMap<(Download, String), InFlight<InstallOutcome>>> =
+        Mutex::new(HashMap::new());
+    static ref ASSETS_IN_FLIGHT: Mutex<HashMap<(Download, String), InFlight<()>>> =
+        Mutex::new(HashMap::new());
 
-    /**
-     * Get a specific game's icon by ID
-     */
-    pub fn game_icon(id: &str) -> String {
-        format!("games/{id}/icon")
-    }
+    // The hash of each game's current version, as last seen from the API
+    static ref KNOWN_HASHES: Mutex<HashMap<String, String>> = Mutex::new(HashMap::new());
 
-    /**
-     * Get a specific game's banner by ID
-     */
-    pub fn game_banner(id: &str) -> String {
-        format!("games/{id}/banner")
-    }
+    // Tag membership, loaded from the disk cache or fetched by `tag_membership`
+    static ref TAG_MEMBERSHIP: Mutex<Option<TagMembership>> = Mutex::new(None);
 
-    /**
-     * Get a specific game's binary by ID
-     */
-    pub fn game_download(id: &str) -> String {
-        format!("games/{id}/game")
-    }
+    // Held while tag membership is fetched, so only one fetch runs at a time
+    static ref FETCHING_TAGS: tokio::sync::Mutex<()> = tokio::sync::Mutex::new(());
+
+    // Progress of the games being downloaded, by game ID
+    static ref DOWNLOADS: Mutex<HashMap<String, DownloadProgress>> = Mutex::new(HashMap::new());
+
+    // What the last auto-update cycle that found out of date games did
+    static ref UPDATE_SUMMARY: Mutex<Option<UpdateSummary>> = Mutex::new(None);
+
+    // Whether the running orphan cleanup was asked to stop, or `None` if no cleanup is running
+    static ref CLEANUP_CANCELLED: Mutex<Option<bool>> = Mutex::new(None);
 
+    // Art that failed to download, by game ID and file, with when it last failed and how many
+    // times in a row
+    static ref ART_FAILURES: Mutex<HashMap<(String, String), (Instant, u32)>> =
+        Mutex::new(HashMap::new());
+}
+
+/**
+ * The process of the game that is currently running
+ */
+struct RunningGame {
     /**
-     * Get all tags
+     * The PID of the game, which is also the ID of its process group
      */
-    pub fn tag_list() -> String {
-        String::from("tags/")
-    }
-
+    pid: u32,
     /**
-     * Get a specific tag
+     * When the game was spawned
      */
-    pub fn tag(name: &str) -> String {
-        format!("tags/{name}")
-    }
-
+    spawned: Instant,
     /**
-     * Get all games with a specific tag
+     * When the game was paused, if it is paused
      */
-    pub fn tag_games(name: &str) -> String {
-        format!("tags/{name}/games")
-    }
-
+    paused: Option<Instant>,
     /**
-     * Get a specific user
+     * The event label active when the game was launched
      */
-    pub fn user(uid: &str) -> String {
-        format!("users/{uid}")
-    }
+    event: Option<String>,
 }
 
 /**
- * Get a list of games from the API. This is the preferred method of getting games.
- *
- * # Errors
- * This function will return an error if the request fails, or if the JSON cannot be deserialized
+ * How long after spawning a game it can't be paused, so pausing doesn't race engine initialization
  */
-pub async fn game_list() -> Result<Vec<DevcadeGame>, Error> {
-    let games =
-        network::request_json(format!("{}/{}", api_url(), route::game_list()).as_str()).await?;
-    Ok(games)
-}
+const PAUSE_GRACE_PERIOD: Duration = Duration::from_secs(5);
 
 /**
- * Get a specific game from the API. This is the preferred method of getting games.
- *
- * # Errors
- * This function will return an error if the request fails, or if the JSON cannot be deserialized
+ * How many screenshots are kept per game before the oldest are deleted
  */
-pub async fn get_game(id: &str) -> Result<DevcadeGame, Error> {
-    let game = network::request_json(format!("{}/{}", api_url(), route::game(id)).as_str()).await?;
-    Ok(game)
-}
+const SCREENSHOT_RETENTION: usize = 20;
 
 /**
- * Get the list of games currently installed on the filesystem. This can be used if the API is down.
- * This is not the preferred method of getting games.
- *
- * # Errors
- * This function will return an error if the filesystem cannot be read at the DEVCADE_PATH location.
+ * How long the screenshot command is given to finish
  */
-pub fn game_list_from_fs() -> Result<Vec<DevcadeGame>, Error> {
-    let mut games = Vec::new();
-    for entry in std::fs::read_dir(devcade_path())? {
-        let entry = entry?;
-        let path = entry.path();
-        if !path.is_dir() {
-            continue;
-        }
+const SCREENSHOT_TIMEOUT: Duration = Duration::from_secs(10);
 
-        for entry_ in std::fs::read_dir(path)? {
-            let entry_ = entry_?;
-            let path_ = entry_.path();
-            if path_.is_dir() {
-                continue;
-            }
+/**
+ * How long the display probe command is given to finish
+ */
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
 
-            if let Ok(game) = game_from_path(path_.to_str().unwrap()) {
-                games.push(game);
-            }
-        }
-    }
-    Ok(games)
-}
+/**
+ * How long a tap waits for the auth service before the player is let in as a guest
+ */
+const TAP_LOOKUP_TIMEOUT: Duration = Duration::from_secs(3);
 
 /**
- * Download's a game's banner from the API.
- *
- * # Errors
- * This function will return an error if the request fails, or if the filesystem cannot be written to.
+ * Internal module for network requests and JSON serialization
  */
-pub async fn download_banner(game_id: String) -> Result<(), Error> {
-    let path = Path::new(devcade_path().as_str())
-        .join(game_id.clone())
-        .join("banner.png");
-    if path.exists() {
-        return Ok(());
-    }
-    if !path.parent().unwrap().exists() {
-        std::fs::create_dir_all(path.parent().unwrap())?;
-    }
+mod network;
 
-    let bytes = network::request_bytes(
-        format!("{}/{}", api_url(), route::game_banner(game_id.as_str())).as_str(),
-    )
-    .await?;
-    std::fs::write(path, bytes)?;
-    Ok(())
-}
+/**
+ * Internal module for checking that games were signed by a trusted publisher, so a compromised CDN
+ * can't serve tampered games. The API signs each game's hash with ed25519, and serves the hex
+ * encoded signature next to the game.
+ */
+mod signature;
 
 /**
- * Download's a game's icon from the API.
- *
- * # Errors
- * This function will return an error if the request fails, or if the filesystem cannot be written to.
+ * Internal module for the cabinet's accessibility policy. When flags are required (for example
+ * `no_flashing_lights` during an event), games that don't declare all of them are hidden from the
+ * listings and refused launch.
  */
-pub async fn download_icon(game_id: String) -> Result<(), Error> {
-    let api_url = api_url();
-    let file_path = devcade_path();
+mod accessibility;
 
-    let path = Path::new(file_path.as_str())
-        .join(game_id.clone())
-        .join("icon.png");
-    if path.exists() {
-        return Ok(());
-    }
-    if !path.parent().unwrap().exists() {
-        std::fs::create_dir_all(path.parent().unwrap())?;
-    }
+/**
+ * Internal module for settings that belong to the cabinet rather than to any one game, like
+ * controller calibration. They're kept in one small JSON file, which games can read directly (see
+ * `DEVCADE_CABINET_SETTINGS`) or through the persistence socket. Every write is journaled.
+ */
+mod cabinet_settings;
 
-    let bytes = network::request_bytes(
-        format!("{}/{}", api_url, route::game_icon(game_id.as_str())).as_str(),
-    )
-    .await?;
-    std::fs::write(path, bytes)?;
-    Ok(())
-}
+/**
+ * Internal module for retiring installed games that the API removed, or marked as retired. What
+ * happens to them is up to `DEVCADE_RETIREMENT_POLICY`: they're kept as they are, hidden from
+ * listings but still launchable by ID, or hidden and then removed after `DEVCADE_RETIREMENT_DAYS`.
+ * Every change is journaled.
+ */
+mod retirement;
 
-pub async fn nfc_tags(reader_id: Player) -> Result<Option<String>, Error> {
-    assert!(reader_id == Player::P1);
-    NFC_CLIENT
-        .submit()
-        .await
-        .map_err(|err| anyhow!("Couldn't get NFC tags: {:?}", err))
-}
+/**
+ * Internal module for updates whose hash changed without a new upload date, which has happened
+ * when the API's storage was migrated and made every cabinet download its whole library again.
+ * Auto-update installs, skips or waits for an operator to confirm them under
+ * `DEVCADE_SUSPICIOUS_UPDATE_POLICY`, and every decision is journaled.
+ */
+mod hash_changes;
 
-pub async fn nfc_user(association_id: String) -> Result<Map<String, Value>, Error> {
-    NFC_CLIENT
-        .get_user(association_id)
-        .await
-        .map_err(|err| anyhow!("Couldn't get NFC user: {:?}", err))
-}
+/**
+ * Internal module for the summaries games submit at the end of a session, like a final score. Each
+ * session is appended to `.state/sessions.jsonl` with its summary if it has one, and every game's
+ * highlights (like its high score) are kept up to date in `.state/highlights.json` as sessions
+ * end, so they never need the session log to be read back.
+ */
+mod sessions;
 
 /**
- * Download's a game's zip file from the API and unzips it into the game's directory. If the game is
- * already downloaded, it will check if the hash is the same. If it is, it will not download the game
- * again.
- *
- * # Errors
- * This function will return an error if the request fails, or if the filesystem cannot be written to.
+ * Internal module for named configuration profiles, so a cabinet can be set up for an event in one
+ * step. A profile is a snapshot of the settings that can be changed while the backend runs: log
+ * level overrides, the required accessibility flags, and the cabinet settings. Profiles are kept in
+ * `.state/profiles/`.
  */
-pub async fn download_game(game_id: String) -> Result<(), Error> {
-    let path = Path::new(devcade_path().as_str())
-        .join(game_id.clone())
-        .join("game.json");
+mod profiles;
 
-    let game = get_game(game_id.as_str()).await?;
+/**
+ * Internal module for restricting what launched games can reach over the network. Games are put in
+ * a fresh user and network namespace, which leaves them with no interfaces but (optionally)
+ * loopback. Unix sockets are path based, so the persistence server is still reachable.
+ */
+mod sandbox;
 
-    // Check if the game is already downloaded, and if it is, check if the hash is the same
-    if path.exists() {
-        if let Ok(game_) = game_from_path(path.to_str().unwrap()) {
-            if game_.hash == game.hash {
-                return Ok(());
-            }
-        }
-    }
+/**
+ * Internal module for detecting what installed games need to run: the executable's architecture,
+ * the runtimes and engines they're built on, and shared libraries the cabinet is missing. Games are
+ * probed when they're installed, and the outcome of their last launch is recorded alongside, in
+ * `.state/runtimes.json`.
+ */
+mod runtime;
 
-    log!(Level::Info, "Downloading game {}...", game.name);
+/**
+ * Internal module for download jobs, which queue a game download in the background and remember
+ * how it went. The jobs wait for a slot in the same queue as every other game download.
+ */
+mod download_jobs;
 
-    let bytes = network::request_bytes(
-        format!("{}/{}", api_url(), route::game_download(game_id.as_str())).as_str(),
-    )
-    .await?;
+/**
+ * Internal module for packing game icons into atlases for the frontend
+ */
+mod atlas;
 
-    log!(Level::Info, "Unzipping game {}...", game.name);
-    log!(Level::Trace, "Zip file size: {} bytes", bytes.len());
+/**
+ * Internal module for small thumbnails of game icons, which the frontend can draw straight from a
+ * response before the full icons are loaded
+ */
+mod thumbnails;
 
-    // Unzip the game into the game's directory
-    let mut zip = zip::ZipArchive::new(std::io::Cursor::new(bytes))?;
+/**
+ * Internal module for API routes and URLs
+ * This is used to make sure that the API routes are consistent across the codebase, and can be
+ * changed from a single location.
+ */
+mod route;
 
-    for i in 0..zip.len() {
-        let mut file = match zip.by_index(i) {
-            Ok(f) => f,
-            Err(e) => {
-                log!(Level::Warn, "Error getting file from zip: {}", e);
-                continue;
-            }
-        };
-        let out_path = Path::new(devcade_path().as_str())
-            .join(game.id.clone())
-            .join(file.name());
-        log!(
-            Level::Trace,
-            "Unzipping file {} to {}",
-            file.name(),
-            out_path.to_str().unwrap()
-        );
-        if file.name().ends_with('/') {
-            match std::fs::create_dir_all(&out_path) {
-                Ok(_) => {}
-                Err(e) => {
-                    log!(
-                        Level::Warn,
-                        "Error creating directory {}: {}",
-                        out_path.to_str().unwrap(),
-                        e
-                    );
-                }
-            }
-        } else {
-            if let Some(p) = out_path.parent() {
-                if !p.exists() {
-                    match std::fs::create_dir_all(p) {
-                        Ok(_) => {}
-                        Err(e) => {
-                            log!(
-                                Level::Warn,
-                                "Error creating directory {}: {}",
-                                p.to_str().unwrap(),
-                                e
-                            );
-                        }
-                    };
-                }
-            }
-            let mut outfile = match std::fs::File::create(&out_path) {
-                Ok(f) => f,
-                Err(e) => {
-                    log!(
-                        Level::Warn,
-                        "Error creating file {}: {}",
-                        out_path.to_str().unwrap(),
-                        e
-                    );
-                    continue;
-                }
-            };
-            match std::io::copy(&mut file, &mut outfile) {
-                Ok(_) => {}
-                Err(e) => {
-                    log!(
-                        Level::Warn,
-                        "Error copying file {}: {}",
-                        out_path.to_str().unwrap(),
-                        e
-                    );
-                }
-            };
-        }
-    }
+/**
+ * Internal module for counting how much each of a game's controls is used, when input telemetry
+ * is enabled. Only counts per control per day are kept, keyed by game and never by player, so no
+ * timings or sequences of inputs are stored.
+ */
+mod input_activity;
 
-    // Write the game's JSON file to the game's directory (this is used later to get the games from
-    // the filesystem)
-    log!(
-        Level::Debug,
-        "Writing game.json file for game {}...",
-        game.name
-    );
-    log!(Level::Trace, "Game json path: {}", path.to_str().unwrap());
-    let json = serde_json::to_string(&game)?;
-    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
-    match std::fs::write(path, json) {
-        Ok(_) => {}
-        Err(e) => {
-            log!(Level::Warn, "Error writing game.json file: {}", e);
-        }
-    };
-    Ok(())
-}
+/**
+ * Internal module for protecting the display from burn-in while the cabinet sits idle
+ */
+mod display_protection;
 
 /**
- * Launch a game by its ID. This will check if the game is downloaded, and if it is, it will launch
- * the game. This returns a `JoinHandle`, which should be used to check for game exit and notify the
- * backend.
- *
- * # Errors
- * This function will return an error if the filesystem cannot be read from,
- * or if the game cannot be launched.
- *
- * # Panics
- * This function will never panic, but contains an `unwrap` call that will never fail. This section
- * is here to make clippy happy.
+ * Internal module for the cabinet's quiet hours (see `crate::env::quiet_hours`), during which games
+ * can't be launched and attract mode is off. Operators can replace the windows or lift the current
+ * quiet hours without a restart.
  */
-pub async fn launch_game(game_id: String) -> Result<(), Error> {
-    let path = Path::new(devcade_path().as_str())
-        .join(game_id.clone())
-        .join("publish");
+mod quiet_hours;
 
-    log!(Level::Info, "Launching game {}...", game_id);
-    log!(Level::Trace, "Game path: {}", path.to_str().unwrap());
+/**
+ * Internal module for the link to the cabinet next to this one, so games running on both can find
+ * each other. The backends exchange a small status signed with the shared `peer_key` secret: the
+ * running game, the players tapped in (with association IDs only of those who agreed), and a token
+ * the games can meet with.
+ */
+mod peer;
 
-    if !path.exists() {
-        download_game(game_id.clone()).await?;
-    }
+/**
+ * Internal module for sampling the running game's CPU, memory, open files and threads from
+ * `/proc`, along with the rest of its process group. Samples are cheap, skip processes that exit
+ * partway through, and stop for good if `/proc` can't be read a few times in a row.
+ */
+mod usage;
 
-    let game = game_from_path(
-        path.parent()
-            .unwrap()
-            .join("game.json")
-            .to_str()
-            .unwrap_or(""),
-    )?;
-    // flush data every time a new game is opened (in case previous launched game forgor)
-    match servers::persistence::flush().await {
-        Ok(_) => {}
-        Err(e) => log::warn!("Failed to flush save cache: {e}"),
-    }
-    CURRENT_GAME.lock().unwrap().set(game);
-
-    // Infer executable name from *.runtimeconfig.json
-    let mut executable = String::new();
-
-    for entry in std::fs::read_dir(path.clone())? {
-        let entry = match entry {
-            Ok(entry) => entry,
-            Err(_) => continue,
-        };
-        let path = entry.path();
-        if !path.is_file() {
-            continue;
-        }
+/**
+ * Internal module for freezing the catalog for events like tournaments. A freeze snapshots every
+ * installed game's hash and manifest, signed with the `freeze_key` secret, and refuses downloads,
+ * updates and removals until it's lifted. The snapshot can be checked against the installed files
+ * at any time to prove nothing changed.
+ */
+mod freeze;
 
-        if let Some(filename) = path.file_name().map(|s| s.to_str().unwrap_or("")) {
-            if !filename.ends_with("runtimeconfig.json") {
-                continue;
-            }
-            log!(Level::Debug, "Found runtimeconfig.json file: {}", filename);
-            executable = path
-                .file_prefix()
-                .unwrap_or(OsStr::new(""))
-                .to_str()
-                .unwrap_or("")
-                .to_string();
-            log!(
-                Level::Debug,
-                "Executable inferred from runtimeconfig.json: {}",
-                executable
-            );
-            break;
-        }
-    }
+pub use freeze::CatalogFrozen;
 
-    // If no *.runtimeconfig.json file is found, look for a file with the same name as the game
-    // (this is the case for games that don't use .NET)
-    // TODO: Some better way to find executable name?
-    if executable.is_empty() {
-        // This parent().unwrap() is safe because the path is guaranteed to have a parent
-        let game = game_from_path(
-            path.clone()
-                .parent()
-                .unwrap()
-                .join("game.json")
-                .to_str()
-                .unwrap_or(""),
-        )?;
-        executable = game.name;
-    }
+/**
+ * Internal module for the first-run setup, which lets the frontend fill in the settings a new
+ * cabinet is missing (like the API domain) and writes them to the config file, instead of someone
+ * editing it by hand. Only a few settings can be set this way, and only until setup is completed.
+ */
+mod setup;
 
-    let path = path.join(executable);
+/**
+ * Internal module for finding installed games with the same or mostly the same files, which
+ * happens when a developer uploads a game again as a new entry instead of updating it. Installs
+ * are compared by the file hashes in their manifests, so finding duplicates never reads a game's
+ * files.
+ */
+mod duplicates;
 
-    if !path.exists() {
-        return Err(anyhow!("Game executable not found"));
-    }
+/**
+ * Internal module for the event label, which tags play sessions and launch events during an event
+ * like a showcase so they can be reported on separately. Labels clear themselves at midnight, so
+ * one left on doesn't tag the following weeks.
+ */
+mod event_label;
 
-    // Chmod +x the executable
-    let mut perms = path.metadata()?.permissions();
-    perms.set_mode(0o755);
+/**
+ * Internal module for the frontend's library polling. The installed games are kept with a
+ * generation that goes up when they change, along with which games changed in recent
+ * generations, so a client that's only a little behind is sent just the difference.
+ */
+mod library;
 
-    std::fs::set_permissions(path.clone(), perms)?;
+/**
+ * Internal module for tracking which backend features games use. The persistence server sets a
+ * bit per kind of request the running game sends, and the bits are folded into the game's record
+ * when it exits, along with the `DEVCADE_*` variables its files mention.
+ */
+mod feature_usage;
 
-    // Launch the game and silence stdout (allow the game to print to stderr)
-    let mut child = Command::new(path.clone());
+/**
+ * Internal module for the totals shown on tag and author pages. They're computed from the cached
+ * tag membership, the installed games and the play time kept as sessions end, so they never need
+ * the API.
+ */
+mod catalog_stats;
 
-    child.stdout(Stdio::null());
-    // Unfortunately this will bypass the log crate, so no pretty logging for games
-    child.stderr(std::process::Stdio::inherit());
-    child.current_dir(path.parent().unwrap()); // This unwrap is safe because it is guaranteed to have a parent
+/**
+ * Internal module for counting NFC taps while the auth service is down. Those taps are queued
+ * with a salted hash of the card instead of the card itself, and counted as pending until the
+ * card is looked up again, either by the next tap with it or by the queue being drained.
+ */
+mod tap_queue;
 
-    let mut child = child.spawn().expect("Failed to launch game");
-    child.wait().await.expect("Failed to launch game");
+/**
+ * Internal module for counting how often each cache is used and how often it has what's asked
+ * for, to tell which caches are worth their memory. The last hour is counted per minute, so the
+ * hourly hit ratio reflects current traffic however long the backend has been running.
+ */
+mod cache_stats;
 
-    tokio::time::sleep(Duration::from_millis(200)).await;
-    Ok(())
-}
+/**
+ * Internal module for compacting the session log. Sessions older than a cutoff are folded into
+ * per-game lifetime totals in `.state/lifetime_stats.json` and removed from the log, so it doesn't
+ * grow forever, while the totals still count every session ever played.
+ */
+mod stats_compaction;
 
 /**
- * Returns a list of all tags in the database
- *
- * # Errors
- * This function will return an error if the server cannot be reached, or if the server returns an
- * error.
+ * Internal module for warm restarts. Before a restart the backend writes `.state/handoff.json`
+ * with the running game, unfinished downloads and the library generation, then exits with
+ * `RESTART_EXIT_CODE` for the service manager to restart it (e.g. `RestartForceExitStatus=75`
+ * under systemd). The next backend adopts the game, which was left running, and resumes the
+ * downloads from their partial files, so clients reconnect to the same world.
  */
-pub async fn tag_list() -> Result<Vec<Tag>, Error> {
-    network::request_json(format!("{}/{}", api_url(), route::tag_list()).as_str()).await
-}
+mod handoff;
+
+pub use handoff::RESTART_EXIT_CODE;
 
 /**
- * Returns a tag by its name
- *
- * # Errors
- * This function will return an error if the server cannot be reached, or if the server returns an
- * error.
+ * Internal module for checking a game's files against its manifest before it's launched, as
+ * `DEVCADE_VERIFY_ON_LAUNCH` says. Files that matched are remembered by modification time and
+ * size, so only the ones that changed are hashed again, and mismatches are journaled.
  */
-pub async fn tag(name: String) -> Result<Tag, Error> {
-    network::request_json(format!("{}/{}", api_url(), route::tag(name.as_str())).as_str()).await
-}
+mod launch_verification;
 
 /**
- * Returns a list of all games with the given tag
- *
- * # Errors
- * This function will return an error if the server cannot be reached, or if the server returns an
- * error.
+ * Internal module for running the one-time setup step a game declares, as the last step of staging
+ * its install. How it went is kept with the install, so launching a game whose setup failed warns
+ * instead of the game redoing it while a player waits.
  */
-pub async fn tag_games(name: String) -> Result<Vec<DevcadeGame>, Error> {
-    let games: Vec<MinimalGame> = network::request_json(
-        format!("{}/{}", api_url(), route::tag_games(name.as_str())).as_str(),
+mod game_setup;
+
+/**
+ * Internal module for the API's catalog notifications, sent as server-sent events when games are
+ * added, changed or removed. Each one invalidates what's cached about the game, and may refresh or
+ * update it, so changes show up without waiting for the next poll.
+ */
+mod catalog_events;
+
+/**
+ * Internal module for the history of launch events. A bounded number of recent events is kept,
+ * so a frontend that reconnects can replay what it missed instead of polling everything, and is
+ * told when it missed more than that.
+ */
+mod event_history;
+
+/**
+ * Internal module for arbitrating between operations on the same game. Installs, updates,
+ * removals and verifications declare what they're about to do, and one that conflicts with an
+ * operation in progress queues, supersedes it, or is rejected, as a fixed decision table says.
+ * Conflicts are journaled and listed in the download queue's state.
+ */
+mod operations;
+
+/**
+ * Internal module for event hooks, the commands in `DEVCADE_EVENT_HOOKS` run when launch events
+ * happen, for cabinet-specific automation. Hooks run in the background with their own timeouts
+ * and limits, so a stuck one can't hold up events, and their output goes to the log.
+ */
+mod event_hooks;
+
+/**
+ * Internal module for the object store, which installs share identical files through as hard
+ * links named by hash when `DEVCADE_OBJECT_STORE` is on. During the auto-update window, files no
+ * installed game uses are removed and a sample of the rest is hashed, and games with corrupted
+ * files are downloaded again.
+ */
+mod object_store;
+
+/**
+ * Internal module for the schedule of featured games, date ranges set by operators that feature
+ * a tag or a list of games (and hide others from the menu) for a while, like a themed week. The
+ * active entry is worked out again every minute, and transitions are sent as `ScheduleChanged`
+ * launch events.
+ */
+mod schedule;
+
+/**
+ * Internal module for the queue game downloads wait in for a download slot, and the progress and
+ * cancellation of each download
+ */
+mod download_queue;
+
+/**
+ * Internal module for downloading a game, verifying the archive, and installing it alongside the
+ * previous install before swapping it into place
+ */
+mod install;
+
+/**
+ * Internal module for extracting game archives into a staging directory, skipping entries that
+ * would escape it, and writing the manifest of the extracted files
+ */
+mod extract;
+
+/**
+ * Internal module for checking a game archive the way it would be installed, without installing
+ * it, for `devcade-ctl validate` and upload-time checks
+ */
+mod validate;
+
+/**
+ * Internal module for evicting the least recently launched games when the disk is too full to
+ * install another
+ */
+mod eviction;
+
+/**
+ * Internal module for launching games and recording their sessions when they exit
+ */
+mod launch;
+
+/**
+ * Internal module for demo wristbands, which resolve to guest users without asking gatekeeper.
+ * Games a guest plays get a save namespace that's only kept in memory and thrown away when the
+ * session ends.
+ */
+mod demo;
+
+pub use download_queue::DownloadCancelled;
+pub use install::InsufficientDiskSpace;
+pub use launch_verification::TamperDetected;
+pub use operations::OperationRejected;
+pub use quiet_hours::QuietHours;
+pub use validate::{ExecutableStrategy, ValidationReport};
+
+use download_queue::{promote_queued, queue_position, queued_stage, raise_queued, DownloadTracker};
+use eviction::{last_launched, make_room, record_launch};
+use extract::{
+    extract_archive, extracted_size, find_executable, read_manifest, wildcard_match, GameArchive,
+    Manifest,
+};
+use install::{download_with_priority, file_hash};
+use launch::{end_session, game_command};
+
+/**
+ * Limit the bandwidth game downloads use together to `bps` bytes per second, with 0 lifting the
+ * limit, or go back to `DEVCADE_MAX_DOWNLOAD_BPS` with `None`. Icons and banners are never
+ * limited.
+ */
+pub fn set_download_limit(bps: Option<u64>) {
+    network::set_download_limit(bps.unwrap_or_else(crate::env::max_download_bps));
+}
+
+/**
+ * Reload the TLS trust used for API requests (see `DEVCADE_CA_BUNDLE`) without restarting. If the
+ * new configuration can't be loaded, the old one stays active.
+ *
+ * # Errors
+ * This function will return an error if the CA bundle cannot be loaded.
+ */
+pub fn reload_tls() -> Result<(), Error> {
+    network::reload_tls()
+}
+
+/**
+ * Get a list of games from the API. This is the preferred method of getting games.
+ *
+ * # Errors
+ * This function will return an error if the request fails, or if the JSON cannot be deserialized
+ */
+pub async fn game_list() -> Result<Vec<DevcadeGame>, Error> {
+    let games: Vec<DevcadeGame> = network::request_json(
+        format!("{}/{}", api_url(), route::game_list()).as_str(),
+        Priority::Interactive,
     )
     .await?;
-    let games: Vec<_> = games.into_iter().map(game_from_minimal).collect();
-    // await all the games and return them
-    let games: Vec<Result<DevcadeGame, Error>> = futures_util::future::join_all(games).await;
-    Ok(games
-        .into_iter()
-        .filter_map(|g| {
-            if let Ok(g) = g {
-                Some(g)
-            } else {
-                log!(
-                    Level::Warn,
-                    "Failed to get game by tag {name}: {}",
-                    g.unwrap_err()
-                );
-                None
+    remember_hashes(&games);
+    Ok(games)
+}
+
+/**
+ * How often the game list is checked for retired games
+ */
+const RETIREMENT_CHECK_EVERY: Duration = Duration::from_secs(60 * 60);
+
+/**
+ * Check the game list for retired games at startup and then every hour, removing any that are due
+ * for removal under `DEVCADE_RETIREMENT_POLICY`. This is kept out of `game_list`, so listing games
+ * never waits on the filesystem or removes anything. This never returns.
+ */
+pub async fn watch_retirement() {
+    let mut interval = clock::interval(RETIREMENT_CHECK_EVERY);
+    loop {
+        match game_list().await {
+            Ok(games) => retire_games(&games).await,
+            Err(e) => log!(
+                Level::Warn,
+                "Couldn't get the game list to check for retired games: {}",
+                e
+            ),
+        }
+        interval.tick().await;
+    }
+}
+
+/**
+ * Update which installed games are retired from a fresh game list, and remove any that are due for
+ * removal
+ */
+async fn retire_games(upstream: &[DevcadeGame]) {
+    let installed: Vec<String> = match game_list_from_fs() {
+        Ok(games) => games.into_iter().map(|game| game.id).collect(),
+        Err(e) => {
+            log!(
+                Level::Warn,
+                "Couldn't list installed games to retire: {}",
+                e
+            );
+            return;
+        }
+    };
+    let due = retirement::observe(upstream, &installed, unix_now());
+    if !due.is_empty() {
+        remove_retired(due).await;
+    }
+}
+
+/**
+ * Remove retired games whose grace period is over. The running game, and games a player or
+ * operator is waiting on a download of, are left for next time. Removing a game doesn't remove
+ * its saves.
+ */
+async fn remove_retired(game_ids: Vec<String>) {
+    if let Err(e) = freeze::check() {
+        log!(Level::Info, "Not removing retired games: {}", e);
+        return;
+    }
+    let removal = GameOperation {
+        intent: GameIntent::Remove,
+        origin: OperationOrigin::Background,
+    };
+    for id in game_ids {
+        let _removing = match operations::admit(id.as_str(), removal).await {
+            Ok(Admission::Run(ticket)) => ticket,
+            // Another removal got to it first
+            Ok(Admission::Joined) => {
+                if !layout::game_dir(id.as_str()).exists() {
+                    retirement::removed(id.as_str(), unix_now());
+                }
+                continue;
             }
-        })
-        .collect())
+            Err(e) => {
+                log!(Level::Info, "Not removing retired game {}: {}", id, e);
+                continue;
+            }
+        };
+        // Launches wait until the removal is done, so a game can't start while it's being removed
+        let _installing = INSTALLING.write().await;
+        if game_running() && current_game().id == id {
+            log!(
+                Level::Info,
+                "Not removing retired game {}, it's running",
+                id
+            );
+            continue;
+        }
+        let dir = layout::game_dir(id.as_str());
+        if dir.exists() {
+            log!(Level::Info, "Removing retired game {}", id);
+            if let Err(e) = std::fs::remove_dir_all(&dir) {
+                log!(Level::Warn, "Couldn't remove {}: {}", dir.display(), e);
+                continue;
+            }
+        }
+        retirement::removed(id.as_str(), unix_now());
+    }
 }
 
 /**
- * Gets a user's information by their user ID
+ * Get a specific game from the API. This is the preferred method of getting games.
  *
  * # Errors
- * This function will return an error if the server cannot be reached, or if the server returns an
- * error.
+ * This function will return an error if the request fails, or if the JSON cannot be deserialized
  */
-pub async fn user(uid: String) -> Result<User, Error> {
-    network::request_json(format!("{}/{}", api_url(), route::user(uid.as_str())).as_str()).await
+pub async fn get_game(id: &str) -> Result<DevcadeGame, Error> {
+    let url = format!("{}/{}", api_url(), route::game(id));
+    let game = match network::request_json(url.as_str(), Priority::Interactive).await {
+        Ok(game) => game,
+        // A cache in front of the API may not have caught up with a game the catalog lists
+        Err(e) if network::is_not_found(&e) && listed_in_catalog(id) => {
+            if !network::may_bust_cache() {
+                return Err(e);
+            }
+            let game = network::request_json_uncached(url.as_str(), Priority::Interactive)
+                .await
+                .map_err(|_| e)?;
+            network::report_stale(url.as_str(), "it was missing although the catalog lists it");
+            game
+        }
+        Err(e) => return Err(e),
+    };
+    remember_hashes([&game]);
+    Ok(game)
 }
 
 /**
- * Returns a devcade game if the file at the path is a JSON file containing a devcade game
+ * Get the list of games currently installed on the filesystem. This can be used if the API is down.
+ * This is not the preferred method of getting games.
  *
  * # Errors
- * This function will return an error if the file does not exist, is a directory, or if the file
- * cannot be read.
+ * This function will return an error if the filesystem cannot be read at the DEVCADE_PATH location.
  */
-fn game_from_path(path: &str) -> Result<DevcadeGame, Error> {
-    log!(Level::Trace, "Reading game from path {}", path);
-    let path = Path::new(path);
-    if !path.exists() {
-        return Err(anyhow!("Path does not exist"));
+pub fn game_list_from_fs() -> Result<Vec<DevcadeGame>, Error> {
+    games_in_dir(layout::games_dir().as_path())
+}
+
+/**
+ * Get the list of games installed in a devcade directory
+ *
+ * # Errors
+ * This function will return an error if the directory cannot be read.
+ */
+fn games_in_dir(dir: &Path) -> Result<Vec<DevcadeGame>, Error> {
+    let mut games = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        if !path.is_dir() {
+            continue;
+        }
+
+        for entry_ in std::fs::read_dir(path)? {
+            let entry_ = entry_?;
+            let path_ = entry_.path();
+            if path_.is_dir() {
+                continue;
+            }
+
+            if let Ok(game) = game_from_path(path_.to_str().unwrap()) {
+                games.push(game);
+            }
+        }
     }
-    if path.is_dir() {
-        return Err(anyhow!("Path is a directory"));
+    Ok(games)
+}
+
+/**
+ * Check whether `DEVCADE_PATH` has changed since the last run while games are still installed in
+ * the old directory, and record the current directory for next time. The previous directory is
+ * read from `DEVCADE_PREVIOUS_PATH` if set, otherwise from `layout::last_root_marker`.
+ *
+ * Returns the old directory and the number of games installed there if the current directory has
+ * no games but the old one does, meaning the games should probably be moved over.
+ */
+pub fn check_data_root() -> Option<(String, usize)> {
+    let current = devcade_path();
+    let marker = layout::last_root_marker();
+    let previous = previous_path()
+        .or_else(|| std::fs::read_to_string(&marker).ok())
+        .map(|path| path.trim().to_string())
+        .filter(|path| !path.is_empty());
+
+    if let Err(e) = layout::write_atomic(&marker, current.as_str()) {
+        log!(
+            Level::Debug,
+            "Couldn't record devcade directory in {}: {}",
+            marker.display(),
+            e
+        );
     }
-    let str = std::fs::read_to_string(path)?;
 
-    let game: DevcadeGame = serde_json::from_str(&str)?;
+    let previous = previous.filter(|previous| *previous != current)?;
+    let installed = games_in_dir(layout::games_dir().as_path()).map_or(0, |games| games.len());
+    // Games in the old directory may not have been migrated into its games directory yet
+    let old_root = Path::new(previous.as_str());
+    let old_installed = [layout::games_dir_in(old_root).as_path(), old_root]
+        .iter()
+        .filter_map(|dir| games_in_dir(dir).ok())
+        .map(|games| games.len())
+        .sum();
+    if installed == 0 && old_installed > 0 {
+        Some((previous, old_installed))
+    } else {
+        None
+    }
+}
 
-    Ok(game)
+/**
+ * What is being downloaded for a game, so concurrent downloads of the same thing can be merged
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Download {
+    Game,
+    Icon,
+    Banner,
 }
 
-async fn game_from_minimal(game: MinimalGame) -> Result<DevcadeGame, Error> {
-    network::request_json::<DevcadeGame>(
-        format!("{}/{}", api_url(), route::game(game.id.as_str())).as_str(),
+/**
+ * A download that other callers can wait on. Errors are shared as their message, since `Error`
+ * can't be cloned.
+ */
+type InFlight<T> = Shared<BoxFuture<'static, Result<T, String>>>;
+
+/**
+ * Run a download, unless the same download is already running, in which case wait for that one
+ * instead. This keeps a double press from extracting the same game twice into the same directory.
+ * The download is forgotten once it finishes either way, so a failed download can be retried.
+ *
+ * # Errors
+ * This function will return an error if the download fails, for every caller waiting on it.
+ */
+async fn deduplicated<T, F>(
+    downloads: &'static Mutex<HashMap<(Download, String), InFlight<T>>>,
+    kind: Download,
+    game_id: String,
+    download: F,
+) -> Result<T, Error>
+where
+    T: Clone + Send + Sync + 'static,
+    F: Future<Output = Result<T, Error>> + Send + 'static,
+{
+    let key = (kind, game_id);
+    let in_flight = {
+        let mut in_flight = downloads.lock().unwrap();
+        if let Some(shared) = in_flight.get(&key) {
+            log!(
+                Level::Debug,
+                "{:?} of {} is already downloading, waiting for it",
+                key.0,
+                key.1
+            );
+            shared.clone()
+        } else {
+            let done = key.clone();
+            let shared = async move {
+                let result = download
+                    .await
+                    .map_err(|e| format!("{:#}", fds::classify(e)));
+                downloads.lock().unwrap().remove(&done);
+                result
+            }
+            .boxed()
+            .shared();
+            in_flight.insert(key, shared.clone());
+            shared
+        }
+    };
+    in_flight.await.map_err(|e| anyhow!(e))
+}
+
+/**
+ * Download the icons and banners of every listed game that don't have them yet, or whose art is
+ * out of date. The game list is fetched once, and the downloads run at once up to
+ * `DEVCADE_MAX_ASSET_DOWNLOADS`. A failed download doesn't stop the others, and is reported in the
+ * game's result.
+ *
+ * # Errors
+ * This function will return an error if the game list can't be fetched.
+ */
+pub async fn download_all_assets() -> Result<Vec<AssetResult>, Error> {
+    let games = listed_games(game_list().await?);
+    log!(Level::Info, "Downloading art of {} games", games.len());
+    let results: Vec<AssetResult> =
+        futures_util::future::join_all(games.into_iter().map(|game| async move {
+            let (icon, banner) = tokio::join!(
+                download_icon(game.id.clone()),
+                download_banner(game.id.clone())
+            );
+            AssetResult {
+                game_id: game.id,
+                icon_error: icon.err().map(|e| e.to_string()),
+                banner_error: banner.err().map(|e| e.to_string()),
+            }
+        }))
+        .await;
+
+    let failed = results
+        .iter()
+        .filter(|r| r.icon_error.is_some() || r.banner_error.is_some())
+        .count();
+    log!(
+        if failed == 0 {
+            Level::Info
+        } else {
+            Level::Warn
+        },
+        "Downloaded art of {} games, {} with failures",
+        results.len(),
+        failed
+    );
+    Ok(results)
+}
+
+/**
+ * Download's a game's banner from the API.
+ *
+ * # Errors
+ * This function will return an error if the request fails, or if the filesystem cannot be
+ * written to.
+ */
+pub async fn download_banner(game_id: String) -> Result<(), Error> {
+    let id = game_id.clone();
+    // Art is fetched in bulk in the background, so it waits out a shortage of file descriptors
+    let fetch = fds::patiently(move || fetch_banner(game_id.clone()));
+    deduplicated(&ASSETS_IN_FLIGHT, Download::Banner, id, fetch).await
+}
+
+/**
+ * Get the API's URLs of a game's icon and banner
+ */
+#[must_use]
+pub fn art_urls(game_id: &str) -> (String, String) {
+    (
+        format!("{}/{}", api_url(), route::game_icon(game_id)),
+        format!("{}/{}", api_url(), route::game_banner(game_id)),
     )
-    .await
 }
 
-pub fn current_game() -> DevcadeGame {
-    CURRENT_GAME.lock().unwrap().get_mut().clone()
+async fn fetch_banner(game_id: String) -> Result<(), Error> {
+    let route = route::game_banner(game_id.as_str());
+    fetch_art(game_id.as_str(), "banner.png", route.as_str()).await
+}
+
+/**
+ * Download's a game's icon from the API.
+ *
+ * # Errors
+ * This function will return an error if the request fails, or if the filesystem cannot be
+ * written to.
+ */
+pub async fn download_icon(game_id: String) -> Result<(), Error> {
+    let id = game_id.clone();
+    let fetch = fds::patiently(move || fetch_icon(game_id.clone()));
+    deduplicated(&ASSETS_IN_FLIGHT, Download::Icon, id, fetch).await
+}
+
+async fn fetch_icon(game_id: String) -> Result<(), Error> {
+    let route = route::game_icon(game_id.as_str());
+    fetch_art(game_id.as_str(), "icon.png", route.as_str()).await
+}
+
+/**
+ * A 1x1 grey PNG, written in place of art that couldn't be downloaded so the menu always has
+ * something to draw. It's never taken for the game's real art, so the download is tried again.
+ */
+const PLACEHOLDER_ART: &[u8] = &[
+    0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A, 0x00, 0x00, 0x00, 0x0D, 0x49, 0x48, 0x44, 0x52,
+    0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x08, 0x02, 0x00, 0x00, 0x00, 0x90, 0x77, 0x53,
+    0xDE, 0x00, 0x00, 0x00, 0x0C, 0x49, 0x44, 0x41, 0x54, 0x78, 0xDA, 0x63, 0x70, 0x70, 0x70, 0x00,
+    0x00, 0x01, 0x84, 0x00, 0xC1, 0xBD, 0xA2, 0xBA, 0x38, 0x00, 0x00, 0x00, 0x00, 0x49, 0x45, 0x4E,
+    0x44, 0xAE, 0x42, 0x60, 0x82,
+];
+
+/**
+ * How long to wait before downloading art again after its first failure. The wait doubles with
+ * each failure in a row, up to `ART_RETRY_MAX`.
+ */
+const ART_RETRY_MIN: Duration = Duration::from_secs(60);
+/**
+ * The longest wait before downloading art that keeps failing again
+ */
+const ART_RETRY_MAX: Duration = Duration::from_secs(60 * 60);
+
+/**
+ * Whether bytes are a whole PNG, JPEG or WebP image, judging by the magic bytes at the start and
+ * the marker each format ends with, so an error page or a cut off download isn't taken for one
+ */
+fn is_image(bytes: &[u8]) -> bool {
+    // Some encoders pad the end of the file, so the end marker only has to be near the end
+    let ends_with = |marker: &[u8]| {
+        bytes[bytes.len().saturating_sub(marker.len() + 16)..]
+            .windows(marker.len())
+            .any(|window| window == marker)
+    };
+    if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        ends_with(b"IEND\xAE\x42\x60\x82")
+    } else if bytes.starts_with(b"\xFF\xD8\xFF") {
+        ends_with(b"\xFF\xD9")
+    } else if bytes.len() >= 12 && bytes.starts_with(b"RIFF") && &bytes[8..12] == b"WEBP" {
+        // The RIFF header holds the size of everything after it
+        let size = u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]);
+        usize::try_from(size).is_ok_and(|size| size + 8 <= bytes.len())
+    } else {
+        false
+    }
+}
+
+/**
+ * Whether a file holds real art for a game, rather than nothing, the placeholder, or something
+ * that isn't an image
+ */
+fn is_art(path: &Path) -> bool {
+    std::fs::read(path).is_ok_and(|bytes| bytes != PLACEHOLDER_ART && is_image(&bytes))
+}
+
+/**
+ * How much longer to wait before downloading a piece of art that failed recently, or `None` if it
+ * can be downloaded now
+ */
+fn art_retry_wait(game_id: &str, file: &str) -> Option<Duration> {
+    let failures = ART_FAILURES.lock().unwrap();
+    let (failed, count) = failures.get(&(game_id.to_string(), file.to_string()))?;
+    let wait = ART_RETRY_MIN
+        .saturating_mul(1 << (count - 1).min(16))
+        .min(ART_RETRY_MAX);
+    wait.checked_sub(clock::elapsed(*failed))
+        .filter(|left| !left.is_zero())
+}
+
+/**
+ * Download a piece of a game's art into its directory, unless the copy there is already for the
+ * current version of the game. The game's hash is recorded next to the art (e.g. `icon.png.hash`),
+ * and the art is checked again once the hash changes. The art's `ETag` is kept next to it too
+ * (e.g. `icon.png.etag`), so art that didn't change with the game isn't downloaded again. A file
+ * that isn't a whole image, like one left by a failed write, is treated as missing.
+ *
+ * If the download fails and there's no art to fall back on, the placeholder is written instead.
+ * Art that failed isn't downloaded again until `ART_RETRY_MIN` has passed, doubling with each
+ * failure in a row, so a missing image doesn't get requested on every menu refresh.
+ *
+ * # Errors
+ * This function will return an error if the request fails, the response isn't an image, the art
+ * failed too recently to try again, or if the filesystem cannot be written to.
+ */
+async fn fetch_art(game_id: &str, file: &str, route: &str) -> Result<(), Error> {
+    let dir = layout::game_dir(game_id);
+    let path = dir.join(file);
+    let sidecar = dir.join(format!("{file}.hash"));
+    let hash = known_hash(game_id);
+
+    let present = is_art(&path);
+    let current = hash.as_ref().is_none_or(|hash| {
+        std::fs::read_to_string(&sidecar).is_ok_and(|recorded| recorded.trim() == hash)
+    });
+    if present && current {
+        cache_stats::ART.hit();
+        return Ok(());
+    }
+    if let Some(wait) = art_retry_wait(game_id, file) {
+        cache_stats::FAILED_ART.hit();
+        return Err(anyhow!(
+            "{file} of game {game_id} failed to download recently, not trying again for {wait:?}"
+        ));
+    }
+    cache_stats::FAILED_ART.miss();
+    std::fs::create_dir_all(&dir)?;
+
+    let key = (game_id.to_string(), file.to_string());
+    match refresh_art(game_id, file, route, present, hash).await {
+        Ok(()) => {
+            if ART_FAILURES.lock().unwrap().remove(&key).is_some() {
+                cache_stats::FAILED_ART.evicted();
+            }
+            Ok(())
+        }
+        Err(e) => {
+            if present {
+                cache_stats::ART.stale();
+            }
+            {
+                let mut failures = ART_FAILURES.lock().unwrap();
+                let count = failures.get(&key).map_or(0, |(_, count)| *count);
+                failures.insert(key, (clock::now(), count.saturating_add(1)));
+            }
+            if !present {
+                log!(
+                    Level::Warn,
+                    "Couldn't download {} of game {}, using a placeholder: {}",
+                    file,
+                    game_id,
+                    e
+                );
+                layout::write_atomic(&path, PLACEHOLDER_ART)?;
+                // The placeholder isn't what the ETag was for
+                let etag_path = dir.join(format!("{file}.etag"));
+                if etag_path.exists() {
+                    std::fs::remove_file(&etag_path)?;
+                }
+            }
+            Err(e)
+        }
+    }
+}
+
+/**
+ * Download a piece of a game's art and record the hash of the game it's for, for `fetch_art`.
+ * The `ETag` is only sent if the art on disk is real, so a broken copy is always replaced.
+ */
+async fn refresh_art(
+    game_id: &str,
+    file: &str,
+    route: &str,
+    present: bool,
+    hash: Option<String>,
+) -> Result<(), Error> {
+    let dir = layout::game_dir(game_id);
+    let path = dir.join(file);
+    let sidecar = dir.join(format!("{file}.hash"));
+    let etag_path = dir.join(format!("{file}.etag"));
+
+    // The semaphore is never closed, so acquiring can't fail
+    let _slot = ASSET_DOWNLOADS.acquire().await?;
+    let etag = if present {
+        std::fs::read_to_string(&etag_path).ok()
+    } else {
+        None
+    };
+    match network::request_bytes_if_changed(
+        format!("{}/{}", api_url(), route).as_str(),
+        Priority::Background,
+        etag.as_deref().map(str::trim),
+    )
+    .await?
+    {
+        Fetched::NotModified => {
+            cache_stats::ART.hit();
+            log!(Level::Trace, "{} of game {} hasn't changed", file, game_id);
+        }
+        Fetched::Modified { bytes, etag } => {
+            cache_stats::ART.miss();
+            if present {
+                cache_stats::ART.evicted();
+            }
+            if !is_image(&bytes) {
+                return Err(anyhow!(
+                    "{} of game {} isn't a PNG, JPEG or WebP image ({} bytes)",
+                    file,
+                    game_id,
+                    bytes.len()
+                ));
+            }
+            // Written atomically, so a failed write can't leave a broken image behind
+            layout::write_atomic(&path, bytes)?;
+            if file == "icon.png" {
+                if let Err(e) = thumbnails::generate(game_id) {
+                    log!(Level::Warn, "Couldn't generate thumbnail: {}", e);
+                }
+            }
+            match etag {
+                Some(etag) => layout::write_atomic(&etag_path, etag)?,
+                None => {
+                    if etag_path.exists() {
+                        std::fs::remove_file(&etag_path)?;
+                    }
+                }
+            }
+        }
+    }
+    match hash {
+        Some(hash) => layout::write_atomic(&sidecar, hash)?,
+        None => {
+            if sidecar.exists() {
+                std::fs::remove_file(sidecar)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/**
+ * Get the hash of the current version of a game, as last seen from the API, or from the installed
+ * copy if the API hasn't mentioned it since the backend started
+ */
+fn known_hash(game_id: &str) -> Option<String> {
+    if let Some(hash) = KNOWN_HASHES.lock().unwrap().get(game_id) {
+        return Some(hash.clone());
+    }
+    let path = layout::game_dir(game_id).join("game.json");
+    game_from_path(path.to_str()?).ok().map(|game| game.hash)
+}
+
+/**
+ * Whether the API has listed a game since the backend started
+ */
+fn listed_in_catalog(game_id: &str) -> bool {
+    KNOWN_HASHES.lock().unwrap().contains_key(game_id)
+}
+
+/**
+ * Remember the hashes of games fetched from the API, so art can be checked against them without
+ * asking again
+ */
+fn remember_hashes<'a>(games: impl IntoIterator<Item = &'a DevcadeGame>) {
+    let mut known = KNOWN_HASHES.lock().unwrap();
+    for game in games {
+        known.insert(game.id.clone(), game.hash.clone());
+    }
+}
+
+/**
+ * Forget what's cached about a game the API says was added, changed or removed, so it's asked for
+ * again next time. Tag membership is fetched again too, since the game may have joined or left
+ * tags.
+ */
+fn invalidate_game(game_id: &str) {
+    KNOWN_HASHES.lock().unwrap().remove(game_id);
+    if ARCHIVE_SIZES.lock().unwrap().remove(game_id).is_some() {
+        cache_stats::ARCHIVE_SIZES.evicted();
+    }
+    ART_FAILURES.lock().unwrap().retain(|(id, _), _| {
+        if id == game_id {
+            cache_stats::FAILED_ART.evicted();
+        }
+        id != game_id
+    });
+    if let Some(mut membership) = cached_tag_membership() {
+        membership.fetched = 0;
+        *TAG_MEMBERSHIP.lock().unwrap() = Some(membership);
+    }
+}
+
+pub async fn nfc_tags(reader_id: Player) -> Result<Option<String>, Error> {
+    assert!(reader_id == Player::P1);
+    NFC_CLIENT
+        .submit()
+        .await
+        .map_err(|err| anyhow!("Couldn't get NFC tags: {:?}", err))
+}
+
+pub async fn nfc_user(association_id: String) -> Result<Map<String, Value>, Error> {
+    if let Some(user) = demo::user(association_id.as_str()) {
+        log!(Level::Debug, "Association ID is a demo ID");
+        audit::record_tap(Player::P1, association_id.as_str(), "demo");
+        peer::tapped_in(association_id.as_str());
+        display_protection::activity("tap");
+        return Ok(user);
+    }
+    let user = clock::timeout(
+        TAP_LOOKUP_TIMEOUT,
+        NFC_CLIENT.get_user(association_id.clone()),
+    )
+    .await
+    .map_err(Error::from)
+    .and_then(|user| user);
+    let outcome = match &user {
+        Ok(_) => "member",
+        Err(e) if e.is::<AuthUnavailable>() || e.is::<clock::Elapsed>() => "pending",
+        Err(_) => "unknown",
+    };
+    audit::record_tap(Player::P1, association_id.as_str(), outcome);
+    match outcome {
+        "pending" => tap_queue::enqueue(Player::P1, association_id.as_str()),
+        _ => tap_queue::resolved(association_id.as_str(), user.is_ok()),
+    }
+    if user.is_ok() {
+        demo::member_tapped();
+        peer::tapped_in(association_id.as_str());
+    }
+    display_protection::activity("tap");
+    user.map_err(|err| anyhow!("Couldn't get NFC user: {:?}", err))
+}
+
+/**
+ * Get the number of NFC taps per local date, by members, unknown cards, and taps made while the
+ * auth service was down that haven't been resolved yet
+ */
+#[must_use]
+pub fn tap_stats() -> BTreeMap<String, TapStats> {
+    tap_queue::stats()
+}
+
+/**
+ * Look up the cards of taps made while the auth service was down, once a minute. This never
+ * returns.
+ */
+pub async fn drain_tap_queue() {
+    tap_queue::watch().await;
+}
+
+/**
+ * Hand the running game, unfinished downloads and library generation over to the next backend,
+ * then exit with `RESTART_EXIT_CODE` shortly after, so the response can still be sent
+ *
+ * # Errors
+ * This function will return an error if a game is being installed, or the saves or handoff can't
+ * be written.
+ */
+pub async fn prepare_restart() -> Result<(), Error> {
+    handoff::prepare().await
+}
+
+/**
+ * Pick up where the last backend left off if it restarted with `prepare_restart`. A handoff more
+ * than a few minutes old is ignored with a warning.
+ */
+pub fn resume_handoff() {
+    handoff::resume();
+}
+
+/**
+ * Run an installed game's setup step again, like after fixing what made it fail. Its output
+ * replaces the last run's in the game's log directory.
+ *
+ * # Errors
+ * This function will return an error if the game isn't installed, has no setup step, or is
+ * running.
+ */
+pub async fn run_game_setup(game_id: &str) -> Result<GameSetupRecord, Error> {
+    game_setup::rerun(game_id).await
+}
+
+/**
+ * Listen for the API's notifications about games being added, changed or removed, if
+ * `DEVCADE_CATALOG_EVENTS` is set. Polling carries on either way. This never returns unless
+ * notifications are turned off.
+ */
+pub async fn watch_catalog_events() {
+    catalog_events::watch().await;
+}
+
+/**
+ * Get whether catalog notifications are coming in, and how many were handled
+ */
+pub fn catalog_events() -> CatalogEvents {
+    catalog_events::status()
+}
+
+/**
+ * Get how each event hook has done since startup: its runs, failures, timeouts and skipped
+ * events
+ */
+pub fn event_hook_status() -> Vec<EventHookStatus> {
+    event_hooks::status()
+}
+
+/**
+ * Run the hooks of a type of launch event, like `DownloadFinished`, on a sample event and wait
+ * for them, to check they do what they should
+ *
+ * # Errors
+ * This function will return an error if there's no such type of event.
+ */
+pub async fn test_event_hooks(event_type: &str) -> Result<EventHookTest, Error> {
+    event_hooks::test(event_type).await
+}
+
+/**
+ * Get how well each cache is doing: what it holds, its hits and misses since startup, and its hit
+ * ratio over the last hour
+ */
+#[must_use]
+pub fn cache_stats() -> Vec<CacheReport> {
+    cache_stats::report()
+}
+
+/**
+ * Log a summary of the cache stats once a day. This never returns.
+ */
+pub async fn log_cache_stats() {
+    cache_stats::watch().await;
+}
+
+/**
+ * Downloads a game's archive (a zip file or a gzipped tarball) from the API and extracts it into
+ * the game's directory. If the game is already downloaded, it will check if the hash is the same.
+ * If it is, it will not download the game again. The downloaded archive is checked against the
+ * game's hash before it is extracted. Returns the installed game, and whether it was installed
+ * fresh, updated, or already current.
+ *
+ * # Errors
+ * This function will return an error if the request fails, if the archive doesn't match the game's
+ * hash or signature, or if the filesystem cannot be written to.
+ */
+pub async fn download_game(game_id: String) -> Result<InstallOutcome, Error> {
+    download_with_priority(game_id, DownloadPriority::Normal).await
+}
+
+/**
+ * Get a cabinet-wide setting, like controller calibration
+ *
+ * # Errors
+ * This function will return an error if the settings cannot be read, or the key isn't set.
+ */
+pub fn get_cabinet_setting(key: &str) -> Result<String, Error> {
+    cabinet_settings::get(key)
+}
+
+/**
+ * Set a cabinet-wide setting, or remove it if `value` is `None`. `writer` is the ID of the game
+ * writing it, or `operator`, and is recorded in the journal.
+ *
+ * # Errors
+ * This function will return an error if the key is invalid, the settings would be over quota, or
+ * the settings cannot be written.
+ */
+pub fn set_cabinet_setting(key: &str, value: Option<&str>, writer: &str) -> Result<(), Error> {
+    cabinet_settings::set(key, value, writer)
+}
+
+/**
+ * Whether the running game may write cabinet settings
+ */
+#[must_use]
+pub fn current_game_may_write_cabinet_settings() -> bool {
+    cabinet_settings::may_write(current_game().id.as_str())
+}
+
+/**
+ * Remove the games the accessibility policy hides from a listing
+ */
+#[must_use]
+pub fn accessible_games(mut games: Vec<DevcadeGame>) -> Vec<DevcadeGame> {
+    games.retain(accessibility::allowed);
+    games
+}
+
+/**
+ * Remove the games that shouldn't be in the menu from a listing: those the accessibility policy
+ * hides, retired games the retirement policy hides, and games the active schedule entry hides.
+ * Hidden games can still be found by ID.
+ */
+#[must_use]
+pub fn listed_games(games: Vec<DevcadeGame>) -> Vec<DevcadeGame> {
+    let mut games = accessible_games(games);
+    games.retain(|game| !retirement::hidden(game));
+    let blocked = schedule::blocked();
+    games.retain(|game| !blocked.contains(&game.id));
+    games
+}
+
+/**
+ * Get the active event label, or `None` if there isn't one or it expired
+ */
+#[must_use]
+pub fn event_label() -> Option<EventLabel> {
+    event_label::get()
+}
+
+/**
+ * Tag play sessions and launch events with a label until the next local midnight, or for
+ * `DEVCADE_EVENT_LABEL_HOURS` if that's sooner. `None` clears it. Returns the label as set.
+ *
+ * # Errors
+ * This function will return an error if the label is invalid or can't be saved.
+ */
+pub fn set_event_label(label: Option<String>) -> Result<Option<EventLabel>, Error> {
+    event_label::set(label)
+}
+
+/**
+ * Get the highlights of the sessions played during an event, by game ID. Events the cabinet never
+ * saw have none.
+ */
+#[must_use]
+pub fn event_highlights(label: &str) -> BTreeMap<String, GameHighlights> {
+    sessions::event_highlights(label)
+}
+
+/**
+ * Get every game's sessions added up over its lifetime, by game ID, including sessions compacted
+ * out of the session log
+ *
+ * # Errors
+ * This function will return an error if the session log can't be read.
+ */
+pub fn lifetime_stats() -> Result<BTreeMap<String, LifetimeStats>, Error> {
+    stats_compaction::lifetime()
+}
+
+/**
+ * Get the logged sessions that started in a range of time. If part of the range was compacted,
+ * the export says so, and those sessions are only in its lifetime totals.
+ *
+ * # Errors
+ * This function will return an error if the session log can't be read.
+ */
+pub fn export_sessions(start: u64, end: u64) -> Result<SessionExport, Error> {
+    stats_compaction::export(start, end)
+}
+
+/**
+ * Fold sessions that started more than `retain_days` ago into each game's lifetime totals and
+ * remove them from the session log. Each compaction is journaled to `.state/compactions.journal`.
+ *
+ * # Errors
+ * This function will return an error if the totals or the session log can't be written.
+ */
+pub fn compact_stats(retain_days: u64) -> Result<StatsCompaction, Error> {
+    stats_compaction::compact(retain_days)
+}
+
+/**
+ * Remove files no installed game has used for a day from the object store, and hash a sample of
+ * the rest to catch corruption. Games with corrupted files are downloaded again. Each pass is
+ * journaled to `.state/object_store.journal`.
+ *
+ * # Errors
+ * This function will return an error if the installed games or the store can't be read.
+ */
+pub async fn maintain_object_store() -> Result<ObjectStoreReport, Error> {
+    object_store::maintain().await
+}
+
+/**
+ * Get what the last maintenance of the object store found, or `None` if it hasn't run since
+ * startup
+ */
+#[must_use]
+pub fn object_store_report() -> Option<ObjectStoreReport> {
+    object_store::last_report()
+}
+
+/**
+ * Get the installed games the active schedule entry features, those it lists first. Empty if no
+ * entry is active.
+ *
+ * # Errors
+ * This function will return an error if the installed games can't be listed.
+ */
+pub fn featured_games() -> Result<Vec<DevcadeGame>, Error> {
+    Ok(schedule::featured(listed_games(game_list_from_fs()?)))
+}
+
+/**
+ * Get the schedule of featured games, which entry of it is active, and the tags and games it
+ * refers to that the cabinet doesn't know of
+ */
+#[must_use]
+pub fn schedule() -> Schedule {
+    schedule::get()
+}
+
+/**
+ * Add an entry to the schedule of featured games, replacing the one with the same name
+ *
+ * # Errors
+ * This function will return an error if the entry's name or dates are invalid, or the schedule
+ * can't be saved.
+ */
+pub fn add_schedule_entry(entry: ScheduleEntry) -> Result<Schedule, Error> {
+    schedule::add(entry)
+}
+
+/**
+ * Remove an entry from the schedule of featured games by name
+ *
+ * # Errors
+ * This function will return an error if there's no such entry, or the schedule can't be saved.
+ */
+pub fn remove_schedule_entry(name: &str) -> Result<Schedule, Error> {
+    schedule::remove(name)
+}
+
+/**
+ * Get the cabinet's quiet hours, and whether it's in them
+ */
+#[must_use]
+pub fn quiet_hours() -> QuietHoursStatus {
+    quiet_hours::status()
+}
+
+/**
+ * Replace the quiet hours windows, like `22:00-07:00,Fri 23:00-03:00`, or go back to
+ * `DEVCADE_QUIET_HOURS` with `None`
+ *
+ * # Errors
+ * This function will return an error if a window is invalid, or the windows can't be saved.
+ */
+pub fn set_quiet_hours(windows: Option<String>) -> Result<QuietHoursStatus, Error> {
+    quiet_hours::set(windows)
+}
+
+/**
+ * Let games be launched until the current quiet hours end, or stop letting them
+ *
+ * # Errors
+ * This function will return an error if the cabinet isn't in quiet hours, or the override can't be
+ * saved.
+ */
+pub fn override_quiet_hours(lifted: bool) -> Result<QuietHoursStatus, Error> {
+    quiet_hours::set_override(lifted)
+}
+
+/**
+ * Send `QuietHoursChanged` events as quiet hours start and end. This never returns.
+ */
+pub async fn watch_quiet_hours() {
+    quiet_hours::watch().await;
+}
+
+/**
+ * Work out which schedule entry is active at startup and whenever the day changes, sending a
+ * `ScheduleChanged` launch event when it's a different one. This never returns.
+ */
+pub async fn watch_schedule() {
+    schedule::watch().await;
+}
+
+/**
+ * Check the settings loaded at startup, logging invalid ones and whether setup is required
+ */
+pub fn check_setup() {
+    setup::check();
+}
+
+/**
+ * Get whether the cabinet still needs its first-run setup, and which settings it's missing
+ */
+#[must_use]
+pub fn setup_status() -> SetupStatus {
+    setup::status()
+}
+
+/**
+ * Set `api_url`, `cabinet_name`, `locale` or `data_root` during setup. Nothing is written until
+ * `complete_setup`.
+ *
+ * # Errors
+ * This function will return an error if setup is already complete, the setting isn't one of those,
+ * or the value is invalid.
+ */
+pub fn set_config_value(key: &str, value: &str) -> Result<(), Error> {
+    setup::set(key, value)
+}
+
+/**
+ * Check that the API being set up can be reached by listing its games
+ *
+ * # Errors
+ * This function will return an error if no API is set or it can't be reached.
+ */
+pub async fn test_api_connection() -> Result<(), Error> {
+    setup::test_connection().await
+}
+
+/**
+ * Write the settings from setup to the config file and start using them
+ *
+ * # Errors
+ * This function will return an error if `api_url` isn't set, or the config file can't be written.
+ */
+pub fn complete_setup() -> Result<(), Error> {
+    setup::complete()
+}
+
+/**
+ * Find installed games with the same files, or with at least `DEVCADE_DUPLICATE_SIMILARITY` of
+ * their bytes in common, so an operator can decide which to remove. Installs without a manifest
+ * are left out.
+ *
+ * # Errors
+ * This function will return an error if the installed games can't be listed.
+ */
+pub fn find_duplicate_installs() -> Result<Vec<DuplicateGroup>, Error> {
+    duplicates::find()
+}
+
+/**
+ * Get the installed games the frontend would list, or only what changed since `known`, the
+ * generation the client already has. Clients at the current generation are told nothing changed,
+ * and clients too far behind (or with no generation) get every game.
+ *
+ * # Errors
+ * This function will return an error if the installed games can't be listed.
+ */
+pub fn library(known: Option<u64>) -> Result<LibraryUpdate, Error> {
+    library::get(known)
+}
+
+/**
+ * Get the games from the API that declare all of the accessibility flags, and that the policy
+ * allows
+ *
+ * # Errors
+ * This function will return an error if the request fails, or if the JSON cannot be deserialized
+ */
+pub async fn game_list_with_accessibility(
+    flags: &[AccessibilityFlag],
+) -> Result<Vec<DevcadeGame>, Error> {
+    let mut games = listed_games(game_list().await?);
+    games.retain(|game| accessibility::matches(game, flags));
+    Ok(games)
+}
+
+/**
+ * Require accessibility flags of every listed and launched game until the backend restarts, or go
+ * back to `DEVCADE_REQUIRED_ACCESSIBILITY` if `None`
+ *
+ * # Errors
+ * This function will return an error if any of the flags is unknown.
+ */
+pub fn set_required_accessibility(flags: Option<Vec<AccessibilityFlag>>) -> Result<(), Error> {
+    accessibility::set_required(flags)
+}
+
+/**
+ * Keep the running game's end-of-session summary, which is logged with the session and used for
+ * the game's highlights once it exits. A later summary from the same session replaces it. Strings
+ * are truncated and deep nesting is dropped to keep the session log bounded.
+ *
+ * # Errors
+ * This function will return an error if no game is running, or if the summary is too large.
+ */
+pub fn submit_session_summary(data: Map<String, Value>) -> Result<(), Error> {
+    if RUNNING_GAME.lock().unwrap().is_none() {
+        return Err(anyhow!("No game is running"));
+    }
+    sessions::submit(current_game().id.as_str(), data)
+}
+
+/**
+ * Count how much each control of the running game was used in the last minute, when input
+ * telemetry is enabled with `DEVCADE_INPUT_TELEMETRY`. Counts are kept per game and day, never per
+ * player.
+ *
+ * # Errors
+ * This function will return an error if input telemetry is disabled, if no game is running, or if
+ * the report is too large or can't be saved.
+ */
+pub fn report_input_activity(counts: BTreeMap<String, u64>) -> Result<(), Error> {
+    if !input_telemetry() {
+        return Err(anyhow!("Input telemetry is disabled"));
+    }
+    if !game_running() {
+        return Err(anyhow!("No game is running"));
+    }
+    input_activity::report(current_game().id.as_str(), counts)
+}
+
+/**
+ * Get how much each of a game's controls was used, by local date (`YYYY-MM-DD`)
+ */
+pub fn input_activity(game_id: &str) -> BTreeMap<String, InputActivity> {
+    input_activity::get(game_id)
+}
+
+/**
+ * Get what an installed game needs to run: its architecture, the runtimes and engines it was built
+ * with, shared libraries the cabinet is missing, and how its last launch went. Games installed
+ * before this was recorded are probed now.
+ *
+ * # Errors
+ * This function will return an error if the game isn't installed.
+ */
+pub async fn game_runtime(game_id: &str) -> Result<GameRuntime, Error> {
+    if let Some(runtime) = runtime::get(game_id) {
+        return Ok(runtime);
+    }
+    let game = installed_game(game_id).ok_or_else(|| anyhow!("Game {game_id} isn't installed"))?;
+    runtime::probe(game_id, game.name.as_str()).await;
+    Ok(runtime::get(game_id).unwrap_or_default())
+}
+
+/**
+ * Freeze the catalog for an event: snapshot the installed games as `label`, then refuse
+ * downloads, auto-updates and removals with `CatalogFrozen` until `unfreeze_catalog`
+ *
+ * # Errors
+ * This function will return an error if the label is invalid or taken, `freeze_key` isn't set, or
+ * the snapshot can't be written.
+ */
+pub fn freeze_catalog(label: &str) -> Result<CatalogSnapshot, Error> {
+    freeze::freeze(label)
+}
+
+/**
+ * Lift the catalog freeze
+ *
+ * # Errors
+ * This function will return an error if the freeze state can't be saved.
+ */
+pub fn unfreeze_catalog() -> Result<(), Error> {
+    freeze::unfreeze()
+}
+
+/**
+ * Get the label of the active catalog freeze, or `None` if the catalog isn't frozen
+ */
+#[must_use]
+pub fn catalog_freeze() -> Option<String> {
+    freeze::active()
+}
+
+/**
+ * Check the installed games against the catalog snapshot `label`, rehashing every installed file
+ *
+ * Returns what changed since the snapshot, or nothing if nothing did.
+ *
+ * # Errors
+ * This function will return an error if the snapshot can't be read or was modified.
+ */
+pub async fn verify_freeze(label: String) -> Result<Vec<String>, Error> {
+    tokio::task::spawn_blocking(move || freeze::verify(label.as_str())).await?
+}
+
+/**
+ * Get the running game's recent CPU and memory usage, or `None` if no game is running
+ */
+#[must_use]
+pub fn game_resources() -> Option<GameResources> {
+    usage::get()
+}
+
+/**
+ * Get the runtimes and engines of games that have launched successfully on this cabinet
+ */
+#[must_use]
+pub fn supported_runtimes() -> Vec<String> {
+    runtime::supported()
+}
+
+/**
+ * Get the highlights of every game that submitted session summaries, by game ID
+ */
+#[must_use]
+pub fn highlights() -> BTreeMap<String, GameHighlights> {
+    sessions::highlights()
+}
+
+/**
+ * Save the current log level overrides, required accessibility flags and cabinet settings as a
+ * named profile in `.state/profiles/`
+ *
+ * # Errors
+ * This function will return an error if the name is invalid, or the profile cannot be written.
+ */
+pub fn save_profile(name: &str) -> Result<(), Error> {
+    profiles::save(name)
+}
+
+/**
+ * Apply a saved profile, either completely or (if any of its settings is invalid) not at all, and
+ * describe what changed
+ *
+ * # Errors
+ * This function will return an error if the profile doesn't exist or has an invalid setting, or if
+ * the settings cannot be written.
+ */
+pub fn apply_profile(name: &str) -> Result<Vec<String>, Error> {
+    profiles::apply(name)
+}
+
+/**
+ * Get the names of the saved profiles
+ *
+ * # Errors
+ * This function will return an error if the profiles cannot be listed.
+ */
+pub fn list_profiles() -> Result<Vec<String>, Error> {
+    profiles::list()
+}
+
+/**
+ * Delete a saved profile
+ *
+ * # Errors
+ * This function will return an error if the profile doesn't exist or cannot be removed.
+ */
+pub fn delete_profile(name: &str) -> Result<(), Error> {
+    profiles::delete(name)
+}
+
+/**
+ * Estimate what downloading a game would take, without downloading it. The archive size comes from
+ * a HEAD request, cached for each version of the game. If the API can't be reached, the last known
+ * size is returned and marked stale. The time is based on the throughput of recent game downloads,
+ * and counts only what's left of an interrupted download.
+ */
+pub async fn download_estimate(game_id: &str) -> DownloadEstimate {
+    let cached = ARCHIVE_SIZES.lock().unwrap().get(game_id).cloned();
+    let (size, stale) = match get_game(game_id).await {
+        Ok(game) => match archive_size(&game, Priority::Interactive).await {
+            Ok(size) => (size, false),
+            Err(e) => {
+                log!(Level::Debug, "Couldn't get size of game {}: {}", game_id, e);
+                (cached.map(|(_, size)| size), true)
+            }
+        },
+        Err(e) => {
+            log!(Level::Debug, "Couldn't get game {}: {}", game_id, e);
+            (cached.map(|(_, size)| size), true)
+        }
+    };
+    if stale && size.is_some() {
+        cache_stats::ARCHIVE_SIZES.stale();
+    }
+
+    let partial = std::fs::metadata(layout::partial_download(game_id))
+        .map(|meta| meta.len())
+        .unwrap_or(0);
+    let seconds = size.zip(*THROUGHPUT.lock().unwrap()).map(|(size, rate)| {
+        // Truncation is fine, this is an estimate
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let seconds = (size.saturating_sub(partial) as f64 / rate).ceil() as u64;
+        seconds
+    });
+    let free_bytes = free_space(layout::root().as_path());
+    let needs_space = size
+        .zip(free_bytes)
+        .is_some_and(|(size, free)| size.saturating_mul(INSTALL_SPACE_FACTOR) > free);
+
+    DownloadEstimate {
+        game_id: game_id.to_string(),
+        size,
+        seconds,
+        free_bytes,
+        needs_space,
+        stale,
+    }
+}
+
+/**
+ * Get the size of the current version of a game's archive with a HEAD request, or `None` if the
+ * server doesn't say. Sizes are cached for each version of a game.
+ *
+ * # Errors
+ * This function will return an error if the HEAD request fails.
+ */
+async fn archive_size(game: &DevcadeGame, priority: Priority) -> Result<Option<u64>, Error> {
+    if let Some((hash, size)) = ARCHIVE_SIZES.lock().unwrap().get(&game.id) {
+        if *hash == game.hash {
+            cache_stats::ARCHIVE_SIZES.hit();
+            return Ok(Some(*size));
+        }
+    }
+    cache_stats::ARCHIVE_SIZES.miss();
+    let url = format!("{}/{}", api_url(), route::game_download(game.id.as_str()));
+    let size = network::content_length(url.as_str(), priority).await?;
+    if let Some(size) = size {
+        let replaced = ARCHIVE_SIZES
+            .lock()
+            .unwrap()
+            .insert(game.id.clone(), (game.hash.clone(), size));
+        if replaced.is_some() {
+            cache_stats::ARCHIVE_SIZES.evicted();
+        }
+    }
+    Ok(size)
+}
+
+/**
+ * Roughly how much disk space installing a game takes, as a multiple of its archive size: the
+ * archive itself while it's extracted, plus the extracted files
+ */
+const INSTALL_SPACE_FACTOR: u64 = 3;
+
+/**
+ * Get the space available to the backend on the filesystem a path is on, in bytes
+ */
+fn free_space(path: &Path) -> Option<u64> {
+    let path = std::ffi::CString::new(path.as_os_str().as_encoded_bytes()).ok()?;
+    let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: path is a valid C string and stat is only read after statvfs succeeds
+    if unsafe { libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return None;
+    }
+    // SAFETY: statvfs succeeded, so it initialized stat
+    let stat = unsafe { stat.assume_init() };
+    #[allow(clippy::useless_conversion)]
+    Some(u64::from(stat.f_bavail).saturating_mul(u64::from(stat.f_frsize)))
+}
+
+/**
+ * Get the progress of a game that is being downloaded, or `None` if it isn't being downloaded
+ */
+#[must_use]
+pub fn download_progress(game_id: &str) -> Option<DownloadProgress> {
+    let mut progress = DOWNLOADS.lock().unwrap().get(game_id).cloned()?;
+    if matches!(progress.stage, DownloadStage::Queued { .. }) {
+        progress.stage = queued_stage(game_id);
+    }
+    Some(progress)
+}
+
+/**
+ * Queue a game download, to be run when a download slot is free and no download of a higher
+ * priority is waiting. Returns the job's ID. Queueing a game that's already queued returns the
+ * existing job, raised to this priority if it's higher.
+ */
+#[must_use]
+pub fn enqueue_download(game_id: &str, priority: DownloadPriority) -> u64 {
+    download_jobs::enqueue(game_id, priority)
+}
+
+/**
+ * Move a queued download to the front of the download queue
+ *
+ * # Errors
+ * This function will return an error if there's no queued or running download with the job ID.
+ */
+pub fn promote_download(job_id: u64) -> Result<(), Error> {
+    download_jobs::promote(job_id)
+}
+
+/**
+ * Get the download queue: the jobs waiting, running, and recently completed or failed
+ */
+#[must_use]
+pub fn download_queue() -> DownloadQueueState {
+    download_jobs::state()
+}
+
+/**
+ * Cancel a game download. A download still waiting for a slot leaves the queue. Otherwise the
+ * download stops at the next chunk or archive entry it handles, and removes its partial archive
+ * and staging directory. A previous install of the game is left as it was. Once the new install
+ * starts replacing the previous one it can't be cancelled anymore.
+ *
+ * # Errors
+ * This function will return an error if the game isn't being downloaded or queued.
+ */
+pub fn cancel_download(game_id: &str) -> Result<(), Error> {
+    download_queue::cancel(game_id)
+}
+
+/**
+ * Record a launch event, with the event label active when it happened
+ */
+fn emit_launch_event(kind: LaunchEventKind) {
+    let event = event_label::active();
+    let seq = LAUNCH_EVENTS.emit(kind.clone(), event.clone());
+    event_hooks::dispatch(&LaunchEvent { seq, kind, event });
+}
+
+/**
+ * Get the launch events with a sequence number after `after`, oldest first. Pass the `seq` of the
+ * last event seen to get only new ones. If the first event returned isn't `after + 1`, some were
+ * dropped before they could be read.
+ */
+pub fn launch_events(after: u64) -> Vec<LaunchEvent> {
+    LAUNCH_EVENTS.after(after)
+}
+
+/**
+ * Get the launch events after `since`, or wait for the next one if there aren't any yet. With
+ * `None`, only events from now on are returned. If some of the events asked for were already
+ * dropped, the subscription has a gap, and the client should refresh everything once before
+ * carrying on from the last event returned.
+ */
+pub async fn subscribe(since: Option<u64>) -> Subscription {
+    LAUNCH_EVENTS
+        .subscribe(since, event_history::SUBSCRIBE_WAIT)
+        .await
+}
+
+/**
+ * Launch a game by its ID. This will check if the game is downloaded, and if it is, it will launch
+ * the game, returning once it exits.
+ *
+ * A sideloaded install saves into its own namespace, so testing a dev build doesn't touch the
+ * saves of the API's copy, unless `share_saves` is set.
+ *
+ * # Errors
+ * This function will return an error if the filesystem cannot be read from,
+ * or if the game cannot be launched. It's a `TamperDetected` error if the game's files don't match
+ * its manifest, an `OperationRejected` error if the game is being removed, and a `QuietHours` error
+ * during quiet hours an operator hasn't lifted.
+ */
+pub async fn launch_game(
+    game_id: String,
+    ignore_policy: bool,
+    share_saves: bool,
+) -> Result<(), Error> {
+    launch::launch(game_id, ignore_policy, share_saves).await
+}
+
+/**
+ * Check whether a game archive would install and launch on the cabinet. The archive is extracted
+ * into a temporary directory with the same code used by `download_game`, and the executable is
+ * located the same way `launch_game` does. Nothing is written to `devcade_path()`.
+ *
+ * Since the archive has no game metadata attached, the file name of the archive (without
+ * extension) is used as the game name when looking for the executable.
+ */
+#[must_use]
+pub fn validate_game_archive(path: &Path) -> ValidationReport {
+    validate::report(path)
+}
+
+/**
+ * Returns a list of all tags in the database
+ *
+ * # Errors
+ * This function will return an error if the server cannot be reached, or if the server returns an
+ * error.
+ */
+pub async fn tag_list() -> Result<Vec<Tag>, Error> {
+    network::request_json(
+        format!("{}/{}", api_url(), route::tag_list()).as_str(),
+        Priority::Interactive,
+    )
+    .await
+}
+
+/**
+ * Returns a tag by its name
+ *
+ * # Errors
+ * This function will return an error if the server cannot be reached, or if the server returns an
+ * error.
+ */
+pub async fn tag(name: String) -> Result<Tag, Error> {
+    network::request_json(
+        format!("{}/{}", api_url(), route::tag(name.as_str())).as_str(),
+        Priority::Interactive,
+    )
+    .await
+}
+
+/**
+ * Returns a list of all games with the given tag
+ *
+ * # Errors
+ * This function will return an error if the server cannot be reached, or if the server returns an
+ * error.
+ */
+pub async fn tag_games(name: String) -> Result<Vec<DevcadeGame>, Error> {
+    let games: Vec<MinimalGame> = network::request_json(
+        format!("{}/{}", api_url(), route::tag_games(name.as_str())).as_str(),
+        Priority::Interactive,
+    )
+    .await?;
+    let games: Vec<_> = games.into_iter().map(game_from_minimal).collect();
+    // await all the games and return them
+    let games: Vec<Result<DevcadeGame, Error>> = futures_util::future::join_all(games).await;
+    Ok(games
+        .into_iter()
+        .filter_map(|g| {
+            if let Ok(g) = g {
+                Some(g)
+            } else {
+                log!(
+                    Level::Warn,
+                    "Failed to get game by tag {name}: {}",
+                    g.unwrap_err()
+                );
+                None
+            }
+        })
+        .collect())
+}
+
+/**
+ * How old tag membership can get before it's fetched again
+ */
+const TAG_MEMBERSHIP_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+/**
+ * How many tags have their games fetched at once, when the API can't list them all in one request
+ */
+const TAG_MEMBERSHIP_FETCHES: usize = 4;
+
+/**
+ * A tag from the bulk tag endpoint. The API leaves out `games` if it doesn't support the endpoint.
+ */
+#[derive(Deserialize)]
+struct TagWithGames {
+    name: String,
+    games: Option<Vec<MinimalGame>>,
+}
+
+/**
+ * Get the IDs of the games with each tag. This is answered from the cache unless it's more than a
+ * day old or `refresh` is set, in which case it's fetched from the API. If the API can't be
+ * reached, the cached membership is returned marked as stale. The cache is kept on disk, so it
+ * survives restarts.
+ *
+ * # Errors
+ * This function will return an error if the API can't be reached and nothing is cached.
+ */
+pub async fn tag_membership(refresh: bool) -> Result<TagMembership, Error> {
+    let now = unix_now;
+    let cached = cached_tag_membership;
+    let fresh = |membership: &TagMembership| {
+        !refresh && now().saturating_sub(membership.fetched) < TAG_MEMBERSHIP_MAX_AGE.as_secs()
+    };
+    if let Some(membership) = cached().filter(fresh) {
+        cache_stats::TAGS.hit();
+        return Ok(membership);
+    }
+
+    let _fetching = FETCHING_TAGS.lock().await;
+    // Another request may have fetched it while this one waited
+    let cached = cached();
+    if let Some(membership) = cached.clone().filter(fresh) {
+        cache_stats::TAGS.hit();
+        return Ok(membership);
+    }
+    match fetch_tag_membership().await {
+        Ok(tags) => {
+            cache_stats::TAGS.miss();
+            if cached.is_some() {
+                cache_stats::TAGS.evicted();
+            }
+            let membership = TagMembership {
+                tags,
+                fetched: now(),
+                stale: false,
+            };
+            if let Err(e) = serde_json::to_vec(&membership)
+                .map_err(Error::from)
+                .and_then(|json| Ok(layout::write_atomic(&tag_membership_path(), json)?))
+            {
+                log!(Level::Warn, "Couldn't cache tag membership: {}", e);
+            }
+            *TAG_MEMBERSHIP.lock().unwrap() = Some(membership.clone());
+            Ok(membership)
+        }
+        Err(e) => match cached {
+            Some(mut cached) => {
+                cache_stats::TAGS.stale();
+                log!(
+                    Level::Warn,
+                    "Couldn't refresh tag membership, using what was fetched at {}: {}",
+                    cached.fetched,
+                    e
+                );
+                cached.stale = true;
+                Ok(cached)
+            }
+            None => {
+                cache_stats::TAGS.miss();
+                Err(e)
+            }
+        },
+    }
+}
+
+/**
+ * Get the cached tag membership however old it is, loading it from the disk cache the first time
+ */
+fn cached_tag_membership() -> Option<TagMembership> {
+    let mut membership = TAG_MEMBERSHIP.lock().unwrap();
+    if membership.is_none() {
+        *membership = std::fs::read(tag_membership_path())
+            .ok()
+            .and_then(|json| serde_json::from_slice(&json).ok());
+    }
+    membership.clone()
+}
+
+/**
+ * Note a request the running game sent to the persistence server, for its feature usage. This
+ * only sets a bit, so it doesn't slow the request down.
+ */
+pub fn record_game_request(body: &RequestBody) {
+    feature_usage::used(body);
+}
+
+/**
+ * Flag the running game as misbehaving in its feature usage, after it was disconnected for
+ * sending too many requests
+ */
+pub fn record_game_misbehavior() {
+    let game_id = current_game().id;
+    if !game_id.is_empty() {
+        feature_usage::misbehaved(game_id.as_str());
+    }
+}
+
+/**
+ * Get which backend features a game used over all of its sessions, or `None` if it was never
+ * played
+ */
+#[must_use]
+pub fn feature_usage(game_id: &str) -> Option<FeatureUsage> {
+    feature_usage::get(game_id)
+}
+
+/**
+ * Count how many played games used each backend feature, for planning outreach to game developers
+ */
+#[must_use]
+pub fn feature_adoption_report() -> FeatureAdoption {
+    feature_usage::adoption()
+}
+
+/**
+ * Get the number of games with each tag, from the cached tag membership. Nothing is returned if
+ * tag membership was never fetched.
+ */
+#[must_use]
+pub fn tag_counts() -> BTreeMap<String, u64> {
+    catalog_stats::tag_counts()
+}
+
+/**
+ * Get the game count and local play time of the games with a tag. Tags the cabinet has never seen
+ * have zeros.
+ */
+#[must_use]
+pub fn tag_stats(name: &str) -> CatalogStats {
+    catalog_stats::tag(name)
+}
+
+/**
+ * Get the game count and local play time of the installed games by an author. Authors the cabinet
+ * has never seen have zeros.
+ *
+ * # Errors
+ * This function will return an error if the installed games can't be listed.
+ */
+pub fn author_stats(author: &str) -> Result<CatalogStats, Error> {
+    catalog_stats::author(author)
+}
+
+/**
+ * Fill the tag membership cache at startup, so tags can be filtered on before anyone asks
+ */
+pub async fn warm_tag_membership() {
+    if let Err(e) = tag_membership(false).await {
+        log!(Level::Warn, "Couldn't fetch tag membership: {}", e);
+    }
+}
+
+/**
+ * Watch for the cabinet going idle, suggesting display protection (and dimming the backlight with
+ * `DEVCADE_DIM_BACKLIGHT_COMMAND`) after `DEVCADE_DISPLAY_PROTECTION_MINS`, and counting how long
+ * the display sits static each day. This never returns.
+ */
+pub async fn watch_display() {
+    display_protection::watch().await;
+}
+
+/**
+ * Note that someone is using the cabinet, ending display protection and restoring the backlight
+ */
+pub fn user_activity() {
+    display_protection::activity("user activity");
+}
+
+/**
+ * Get whether display protection is suggested, and how long the display sat static each day
+ */
+pub fn display_protection() -> DisplayProtection {
+    display_protection::state()
+}
+
+/**
+ * Get what the cabinet next to this one is doing, or `None` if the peer link is off
+ */
+#[must_use]
+pub fn peer_status() -> Option<PeerLink> {
+    peer::link()
+}
+
+/**
+ * Record whether a player agrees to the cabinet next to this one seeing their association ID while
+ * they're tapped in. Players who didn't agree are only counted.
+ *
+ * # Errors
+ * This function will return an error if the `peer_key` secret isn't set, or if the consent can't
+ * be saved.
+ */
+pub fn set_peer_consent(association_id: &str, consent: bool) -> Result<(), Error> {
+    peer::set_consent(association_id, consent)
+}
+
+/**
+ * Exchange statuses with the cabinet next to this one. Does nothing unless `DEVCADE_PEER_ADDR` or
+ * `DEVCADE_PEER_LISTEN` is set.
+ */
+pub async fn watch_peer() {
+    peer::watch().await;
+}
+
+/**
+ * Update installed games in the background. Every `DEVCADE_AUTO_UPDATE_INTERVAL_MINS`, if the
+ * current time is inside `DEVCADE_AUTO_UPDATE_WINDOW` and no game is running, every installed game
+ * whose hash differs from the API's is downloaded again. This never returns.
+ */
+pub async fn auto_update() {
+    let mut interval = clock::interval(auto_update_interval());
+    loop {
+        interval.tick().await;
+        let Some(window) = auto_update_window() else {
+            continue;
+        };
+        if !quiet_hours::within(&window, "auto update") || game_running() {
+            continue;
+        }
+        if let Some(days) = session_retain_days() {
+            stats_compaction::nightly(days);
+        }
+        object_store::nightly().await;
+        demo::nightly().await;
+        if let Some(summary) = update_installed_games().await {
+            *UPDATE_SUMMARY.lock().unwrap() = Some(summary);
+        }
+    }
+}
+
+/**
+ * Run one auto-update cycle. A game that fails to update doesn't stop the others, and if a game is
+ * launched partway through, the rest are left for the next cycle.
+ *
+ * Returns what the cycle did, or `None` if there was nothing to update.
+ */
+async fn update_installed_games() -> Option<UpdateSummary> {
+    if let Err(e) = freeze::check() {
+        log!(Level::Info, "Auto-update skipped: {}", e);
+        return None;
+    }
+    let upstream = match game_list().await {
+        Ok(games) => games,
+        Err(e) => {
+            log!(Level::Warn, "Auto-update couldn't get the game list: {}", e);
+            return None;
+        }
+    };
+    let installed = match game_list_from_fs() {
+        Ok(games) => games,
+        Err(e) => {
+            log!(
+                Level::Warn,
+                "Auto-update couldn't list installed games: {}",
+                e
+            );
+            return None;
+        }
+    };
+    let mut summary = UpdateSummary::default();
+    let mut outdated = VecDeque::new();
+    for game in installed {
+        let Some(latest) = upstream
+            .iter()
+            .find(|latest| latest.id == game.id && latest.hash != game.hash)
+        else {
+            continue;
+        };
+        if hash_changes::is_suspicious(&game, latest) {
+            summary
+                .hash_changed_without_new_version
+                .push(game.id.clone());
+            if !hash_changes::allow(&game, latest) {
+                continue;
+            }
+        }
+        outdated.push_back(game.id);
+    }
+    hash_changes::retain(
+        &summary
+            .hash_changed_without_new_version
+            .iter()
+            .cloned()
+            .collect(),
+    );
+    if outdated.is_empty() && summary.hash_changed_without_new_version.is_empty() {
+        log!(
+            Level::Info,
+            "Auto-update found every installed game current"
+        );
+        return None;
+    }
+
+    while let Some(game_id) = outdated.pop_front() {
+        if game_running() {
+            outdated.push_front(game_id);
+            break;
+        }
+        match download_with_priority(game_id.clone(), DownloadPriority::Background).await {
+            Ok(outcome) if outcome.kind == InstallKind::AlreadyInstalled => {}
+            Ok(_) => summary.updated.push(game_id),
+            Err(e) => {
+                log!(
+                    Level::Warn,
+                    "Auto-update couldn't update {}: {}",
+                    game_id,
+                    e
+                );
+                summary.failed.insert(game_id, e.to_string());
+            }
+        }
+    }
+    summary.deferred = outdated.into();
+    summary.finished = unix_now();
+
+    log!(
+        Level::Info,
+        "Auto-update updated {} games, {} failed, {} changed without a new version{}",
+        summary.updated.len(),
+        summary.failed.len(),
+        summary.hash_changed_without_new_version.len(),
+        if summary.deferred.is_empty() {
+            String::new()
+        } else {
+            format!(
+                ", {} left for later because a game was launched",
+                summary.deferred.len()
+            )
+        }
+    );
+    Some(summary)
+}
+
+/**
+ * Get the installed games whose hash changed in the API without a new upload date
+ */
+pub fn suspicious_updates() -> Vec<SuspiciousUpdate> {
+    hash_changes::list()
+}
+
+/**
+ * Let auto-update install a game whose hash changed without a new upload date, on its next cycle
+ *
+ * # Errors
+ * This function will return an error if the game has no suspicious update, or if the confirmation
+ * can't be saved.
+ */
+pub fn confirm_suspicious_update(game_id: &str) -> Result<(), Error> {
+    hash_changes::confirm(game_id)
+}
+
+/**
+ * Get what the last auto-update cycle that found out of date games did, so the frontend can show
+ * something like "3 games updated"
+ */
+pub fn update_summary() -> Option<UpdateSummary> {
+    UPDATE_SUMMARY.lock().unwrap().clone()
+}
+
+fn tag_membership_path() -> PathBuf {
+    layout::cache_dir().join("tag_membership.json")
+}
+
+/**
+ * Fetch the IDs of the games with each tag from the API. All tags are requested at once if the API
+ * supports it, otherwise each tag's games are requested separately, a few at a time.
+ */
+async fn fetch_tag_membership() -> Result<BTreeMap<String, Vec<String>>, Error> {
+    use futures_util::StreamExt;
+
+    let ids = |games: Vec<MinimalGame>| games.into_iter().map(|game| game.id).collect();
+    let url = format!("{}/{}", api_url(), route::tags_with_games());
+    match network::request_json::<Vec<TagWithGames>>(url.as_str(), Priority::Background).await {
+        Ok(tags) if tags.iter().all(|tag| tag.games.is_some()) => {
+            return Ok(tags
+                .into_iter()
+                .map(|tag| (tag.name, ids(tag.games.unwrap_or_default())))
+                .collect());
+        }
+        Ok(_) => log!(
+            Level::Debug,
+            "The API doesn't list tags with their games, fetching each tag"
+        ),
+        Err(e) => log!(
+            Level::Debug,
+            "Couldn't list tags with their games, fetching each tag: {}",
+            e
+        ),
+    }
+
+    let tags: Vec<Tag> = network::request_json(
+        format!("{}/{}", api_url(), route::tag_list()).as_str(),
+        Priority::Background,
+    )
+    .await?;
+    let fetched: Vec<(String, Result<Vec<MinimalGame>, Error>)> = futures_util::stream::iter(tags)
+        .map(|tag| async move {
+            let url = format!("{}/{}", api_url(), route::tag_games(tag.name.as_str()));
+            let games = network::request_json(url.as_str(), Priority::Background).await;
+            (tag.name, games)
+        })
+        .buffer_unordered(TAG_MEMBERSHIP_FETCHES)
+        .collect()
+        .await;
+    // A partial map would make tags look emptier than they are, so one failure fails everything
+    let mut membership = BTreeMap::new();
+    for (name, games) in fetched {
+        let games = games.map_err(|e| e.context(format!("Couldn't get games with tag {name}")))?;
+        membership.insert(name, ids(games));
+    }
+    Ok(membership)
+}
+
+/**
+ * Gets a user's information by their user ID
+ *
+ * # Errors
+ * This function will return an error if the server cannot be reached, or if the server returns an
+ * error.
+ */
+pub async fn user(uid: String) -> Result<User, Error> {
+    network::request_json(
+        format!("{}/{}", api_url(), route::user(uid.as_str())).as_str(),
+        Priority::Interactive,
+    )
+    .await
+}
+
+/**
+ * Returns a devcade game if the file at the path is a JSON file containing a devcade game
+ *
+ * # Errors
+ * This function will return an error if the file does not exist, is a directory, or if the file
+ * cannot be read.
+ */
+fn game_from_path(path: &str) -> Result<DevcadeGame, Error> {
+    log!(Level::Trace, "Reading game from path {}", path);
+    let path = Path::new(path);
+    if !path.exists() {
+        return Err(anyhow!("Path does not exist"));
+    }
+    if path.is_dir() {
+        return Err(anyhow!("Path is a directory"));
+    }
+    let str = std::fs::read_to_string(path)?;
+
+    let game: DevcadeGame = serde_json::from_str(&str)?;
+
+    Ok(game)
+}
+
+/**
+ * Get the installed copy of a game, if it's completely installed: its `game.json` (which is put in
+ * place last) names the game, and its publish directory exists. Games that were never installed,
+ * or were left half installed by an older backend, return `None`.
+ */
+fn installed_game(game_id: &str) -> Option<DevcadeGame> {
+    let dir = layout::game_dir(game_id);
+    if !dir.join("publish").is_dir() {
+        return None;
+    }
+    let game = game_from_path(dir.join("game.json").to_str()?).ok()?;
+    (game.id == game_id).then_some(game)
+}
+
+async fn game_from_minimal(game: MinimalGame) -> Result<DevcadeGame, Error> {
+    let game = network::request_json::<DevcadeGame>(
+        format!("{}/{}", api_url(), route::game(game.id.as_str())).as_str(),
+        Priority::Normal,
+    )
+    .await?;
+    remember_hashes([&game]);
+    Ok(game)
+}
+
+/**
+ * Capture a screenshot of the running game with the command configured in
+ * `DEVCADE_SCREENSHOT_COMMAND`. The screenshot is saved to `<devcade_path>/<id>/screenshots/`,
+ * which keeps the newest few screenshots, and the path to it is returned.
+ *
+ * # Errors
+ * This function will return an error if no game is running, no screenshot command is configured, or
+ * if the screenshot command fails or times out.
+ */
+pub async fn capture_screenshot() -> Result<PathBuf, Error> {
+    if !game_running() {
+        return Err(anyhow!("No game is running"));
+    }
+    let command = screenshot_command()
+        .ok_or_else(|| anyhow!("No screenshot command configured (DEVCADE_SCREENSHOT_COMMAND)"))?;
+    let mut args = command.split_whitespace();
+    // This unwrap is safe because screenshot_command never returns a blank command
+    let program = args.next().unwrap();
+
+    let game = current_game();
+    let dir = layout::game_dir(game.id.as_str()).join("screenshots");
+    std::fs::create_dir_all(&dir)?;
+    let path = dir.join(format!(
+        "{}.png",
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis()
+    ));
+
+    log!(
+        Level::Info,
+        "Capturing screenshot to {}",
+        path.to_str().unwrap()
+    );
+    let mut capture = Command::new(program);
+    capture
+        .args(args)
+        .arg(&path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    let output = tokio::time::timeout(SCREENSHOT_TIMEOUT, capture.output())
+        .await
+        .map_err(|_| anyhow!("Screenshot command timed out"))?
+        .map_err(|e| anyhow!("Couldn't run screenshot command '{}': {}", program, e))?;
+    if !output.status.success() || !path.exists() {
+        return Err(anyhow!(
+            "Screenshot command failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    // Screenshots are named by timestamp, so sorting by name sorts them oldest first
+    let mut screenshots: Vec<PathBuf> = std::fs::read_dir(&dir)?
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| path.extension() == Some(OsStr::new("png")))
+        .collect();
+    screenshots.sort();
+    let excess = screenshots.len().saturating_sub(SCREENSHOT_RETENTION);
+    for old in &screenshots[..excess] {
+        if let Err(e) = std::fs::remove_file(old) {
+            log!(
+                Level::Warn,
+                "Error removing old screenshot {}: {}",
+                old.to_str().unwrap(),
+                e
+            );
+        }
+    }
+
+    Ok(path)
+}
+
+/**
+ * Whether a game is currently running
+ */
+pub fn game_running() -> bool {
+    RUNNING_GAME.lock().unwrap().is_some()
+}
+
+/**
+ * Pause the running game by stopping its process group, for when an operator needs the screen
+ * briefly. The game is resumed automatically after `DEVCADE_MAX_PAUSE_SECS` (10 minutes by
+ * default) in case it is forgotten.
+ *
+ * # Errors
+ * This function will return an error if no game is running, the game is already paused, the game
+ * was only just launched, or if the game cannot be signalled.
+ */
+pub fn pause_current_game() -> Result<(), Error> {
+    let mut running = RUNNING_GAME.lock().unwrap();
+    let game = running
+        .as_mut()
+        .ok_or_else(|| anyhow!("No game is running"))?;
+    if game.paused.is_some() {
+        return Err(anyhow!("Game is already paused"));
+    }
+    if clock::elapsed(game.spawned) < PAUSE_GRACE_PERIOD {
+        return Err(anyhow!(
+            "Game is still starting, try again in a few seconds"
+        ));
+    }
+
+    signal_game(game.pid, libc::SIGSTOP)?;
+    let paused = clock::now();
+    game.paused = Some(paused);
+    log!(Level::Info, "Paused game (PID {})", game.pid);
+
+    let max_pause = max_pause();
+    tokio::spawn(async move {
+        clock::sleep(max_pause).await;
+        let still_paused = RUNNING_GAME
+            .lock()
+            .unwrap()
+            .as_ref()
+            .is_some_and(|game| game.paused == Some(paused));
+        if still_paused {
+            log!(
+                Level::Warn,
+                "Game has been paused for {} seconds, resuming it",
+                max_pause.as_secs()
+            );
+            if let Err(e) = resume_current_game() {
+                log!(Level::Error, "Couldn't resume game: {}", e);
+            }
+        }
+    });
+    Ok(())
+}
+
+/**
+ * Resume the running game after it was paused with `pause_current_game`
+ *
+ * # Errors
+ * This function will return an error if no game is running, the game isn't paused, or if the game
+ * cannot be signalled.
+ */
+pub fn resume_current_game() -> Result<(), Error> {
+    let mut running = RUNNING_GAME.lock().unwrap();
+    let game = running
+        .as_mut()
+        .ok_or_else(|| anyhow!("No game is running"))?;
+    let paused = game.paused.ok_or_else(|| anyhow!("Game is not paused"))?;
+
+    signal_game(game.pid, libc::SIGCONT)?;
+    game.paused = None;
+    log!(
+        Level::Info,
+        "Resumed game (PID {}) after {} seconds",
+        game.pid,
+        clock::elapsed(paused).as_secs()
+    );
+    Ok(())
+}
+
+/**
+ * Send a signal to a game's process group
+ */
+fn signal_game(pid: u32, signal: libc::c_int) -> Result<(), Error> {
+    let pgid = libc::pid_t::try_from(pid)?;
+    // SAFETY: kill has no memory safety requirements
+    if unsafe { libc::kill(-pgid, signal) } != 0 {
+        return Err(anyhow!(
+            "Couldn't signal game: {}",
+            std::io::Error::last_os_error()
+        ));
+    }
+    Ok(())
+}
+
+pub fn current_game() -> DevcadeGame {
+    CURRENT_GAME.lock().unwrap().get_mut().clone()
+}
+
+/**
+ * Get the save namespace of the current game. This is the game's ID, or `sideload/<id>` for a
+ * sideloaded install, unless it was launched to share the saves of the API's copy.
+ */
+pub fn save_namespace() -> String {
+    let namespace = SAVE_NAMESPACE.lock().unwrap().clone();
+    if namespace.is_empty() {
+        current_game().id
+    } else {
+        namespace
+    }
+}
+
+/**
+ * Whether a game was sideloaded rather than installed from the API. Sideloading a game means
+ * putting it in its directory by hand, along with an empty `sideloaded` file.
+ */
+pub fn is_sideloaded(game_id: &str) -> bool {
+    layout::game_dir(game_id).join("sideloaded").exists()
+}
+
+/**
+ * Pack the icons of all installed games into atlases of at most `max_size` pixels square, with each
+ * icon scaled to `cell` pixels. The atlases are cached and only rebuilt when the installed icons
+ * change.
+ *
+ * # Errors
+ * This function will return an error if the installed games cannot be listed, if `cell` doesn't fit
+ * in `max_size`, if the atlases cannot be written, or if the resource profile skips them.
+ */
+pub fn build_icon_atlas(max_size: u32, cell: u32) -> Result<IconAtlas, Error> {
+    if !resources::allows(Capability::IconAtlas) {
+        return Err(anyhow!(
+            "CapabilitySkipped: icon atlases aren't built with the low resource profile"
+        ));
+    }
+    let ids = game_list_from_fs()?
+        .into_iter()
+        .map(|game| game.id)
+        .collect();
+    atlas::build(ids, max_size, cell)
+}
+
+/**
+ * Get the game list, along with thumbnails of the icons of the listed games that are on disk.
+ * Thumbnails are generated when an icon is downloaded, or on the first request after it changed.
+ *
+ * # Errors
+ * This function will return an error if the request fails, or if the JSON cannot be deserialized
+ */
+pub async fn game_list_with_thumbnails() -> Result<GameListWithThumbnails, Error> {
+    let games = listed_games(game_list().await?);
+    let thumbnails = thumbnails::inline(games.iter().map(|game| game.id.as_str()));
+    Ok(GameListWithThumbnails { games, thumbnails })
+}
+
+/**
+ * Get the cabinet's hardware, or `Pending` if it hasn't been probed yet
+ */
+pub fn cabinet_hardware() -> HardwareProbe {
+    HARDWARE.lock().unwrap().clone()
+}
+
+/**
+ * Detect the cabinet's hardware and store it for `cabinet_hardware`. This runs once at startup, and
+ * again whenever an operator asks for it after changing the hardware. Until the first probe
+ * finishes, `cabinet_hardware` returns `Pending` rather than making callers wait.
+ *
+ * Audio and controller details come from the environment. The display mode comes from the command
+ * in `DEVCADE_DISPLAY_PROBE`, or the kernel's DRM devices if it isn't set.
+ */
+pub async fn probe_hardware() -> CabinetHardware {
+    let display = match display_probe_command() {
+        Some(command) => probe_display(command.as_str()).await.unwrap_or_else(|e| {
+            log!(Level::Warn, "Couldn't probe display: {}", e);
+            None
+        }),
+        None => drm_display_mode(),
+    };
+    let hardware = CabinetHardware {
+        display,
+        audio_sample_rate: audio_sample_rate(),
+        audio_latency_ms: audio_latency_ms(),
+        controller_mapping: controller_mapping(),
+    };
+    log!(Level::Info, "Probed cabinet hardware: {:?}", hardware);
+    *HARDWARE.lock().unwrap() = HardwareProbe::Ready(hardware.clone());
+    hardware
+}
+
+/**
+ * Run the display probe command and parse the first display mode in its output
+ */
+async fn probe_display(command: &str) -> Result<Option<DisplayMode>, Error> {
+    let mut args = command.split_whitespace();
+    // This unwrap is safe because display_probe_command never returns a blank command
+    let program = args.next().unwrap();
+    let mut probe = Command::new(program);
+    probe
+        .args(args)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true);
+    let output = tokio::time::timeout(PROBE_TIMEOUT, probe.output())
+        .await
+        .map_err(|_| anyhow!("Display probe command timed out"))?
+        .map_err(|e| anyhow!("Couldn't run display probe command '{}': {}", program, e))?;
+    if !output.status.success() {
+        return Err(anyhow!("Display probe command failed ({})", output.status));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .split_whitespace()
+        .find_map(parse_display_mode))
+}
+
+/**
+ * Parse a display mode like `1920x1080@59.94` or `1920x1080`
+ */
+fn parse_display_mode(mode: &str) -> Option<DisplayMode> {
+    let (size, refresh_rate) = match mode.split_once('@') {
+        Some((size, rate)) => (size, Some(rate.trim_end_matches("Hz").parse().ok()?)),
+        None => (mode, None),
+    };
+    let (width, height) = size.split_once('x')?;
+    Some(DisplayMode {
+        width: width.parse().ok()?,
+        height: height.parse().ok()?,
+        refresh_rate,
+    })
+}
+
+/**
+ * Read the current mode of the first connected display from sysfs. The kernel lists the preferred
+ * mode first, which is what the display is driven at unless something changed it.
+ */
+fn drm_display_mode() -> Option<DisplayMode> {
+    let mut connectors: Vec<PathBuf> = std::fs::read_dir("/sys/class/drm")
+        .ok()?
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| {
+            std::fs::read_to_string(path.join("status"))
+                .is_ok_and(|status| status.trim() == "connected")
+        })
+        .collect();
+    connectors.sort();
+    connectors.iter().find_map(|connector| {
+        let modes = std::fs::read_to_string(connector.join("modes")).ok()?;
+        parse_display_mode(modes.lines().next()?.trim())
+    })
+}
+
+/**
+ * Data that can be wiped by `factory_reset`
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub enum ResetScope {
+    /**
+     * Everything games have saved through the persistence server
+     */
+    Saves,
+    /**
+     * Screenshots captured of running games
+     */
+    Screenshots,
+    /**
+     * Installed games, including their art and screenshots
+     */
+    Games,
+}
+
+impl std::str::FromStr for ResetScope {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "saves" => Ok(Self::Saves),
+            "screenshots" => Ok(Self::Screenshots),
+            "games" => Ok(Self::Games),
+            _ => Err(anyhow!(
+                "Unknown reset scope '{}', expected saves, screenshots or games",
+                s
+            )),
+        }
+    }
+}
+
+/**
+ * What a factory reset removes, or removed
+ */
+#[derive(Debug, Clone, Serialize)]
+pub struct ResetReport {
+    /**
+     * Whether this is only a plan (dry run) and nothing was removed
+     */
+    pub dry_run: bool,
+    /**
+     * The paths removed, or to be removed
+     */
+    pub paths: Vec<PathBuf>,
+    /**
+     * The total size of the removed paths in bytes
+     */
+    pub bytes: u64,
+    /**
+     * The token that has to be passed back to `factory_reset` to carry out this plan
+     */
+    pub token: String,
+}
+
+impl fmt::Display for ResetReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let verb = if self.dry_run {
+            "Would remove"
+        } else {
+            "Removed"
+        };
+        for path in &self.paths {
+            writeln!(f, "{verb}: {}", path.display())?;
+        }
+        write!(
+            f,
+            "{verb} {} paths ({} bytes)",
+            self.paths.len(),
+            self.bytes
+        )?;
+        if self.dry_run {
+            write!(f, "\nConfirmation token: {}", self.token)?;
+        }
+        Ok(())
+    }
+}
+
+/**
+ * Wipe cabinet data for reprovisioning. Without a confirmation token, this is a dry run that only
+ * reports what would be removed along with a token. Passing that token back carries out the reset,
+ * as long as the plan hasn't changed in the meantime.
+ *
+ * # Errors
+ * This function will return an error if a game is running, if the token doesn't match the current
+ * plan, or if the data directories cannot be read or removed.
+ */
+pub fn factory_reset(scopes: &[ResetScope], confirm: Option<&str>) -> Result<ResetReport, Error> {
+    if game_running() {
+        return Err(anyhow!("Can't reset while a game is running"));
+    }
+
+    let mut paths = Vec::new();
+    if scopes.contains(&ResetScope::Saves) && servers::persistence::save_root().exists() {
+        paths.push(servers::persistence::save_root().to_path_buf());
+    }
+    if scopes.contains(&ResetScope::Games) || scopes.contains(&ResetScope::Screenshots) {
+        let games = layout::games_dir();
+        if games.exists() {
+            for entry in std::fs::read_dir(&games)? {
+                let path = entry?.path();
+                if !path.is_dir() {
+                    continue;
+                }
+                if scopes.contains(&ResetScope::Games) {
+                    paths.push(path);
+                } else if path.join("screenshots").exists() {
+                    paths.push(path.join("screenshots"));
+                }
+            }
+        }
+    }
+    paths.sort();
+
+    let mut bytes = 0;
+    for path in &paths {
+        bytes += dir_size(path)?;
+    }
+    let token = reset_token(&paths, bytes);
+
+    let Some(confirm) = confirm else {
+        return Ok(ResetReport {
+            dry_run: true,
+            paths,
+            bytes,
+            token,
+        });
+    };
+    if confirm != token {
+        return Err(anyhow!(
+            "Confirmation token doesn't match, run a dry run again to get a new token"
+        ));
+    }
+
+    for path in &paths {
+        log!(Level::Info, "Factory reset: removing {}", path.display());
+        std::fs::remove_dir_all(path)
+            .map_err(|e| anyhow!("Couldn't remove {}: {}", path.display(), e))?;
+    }
+    log!(
+        Level::Info,
+        "Factory reset removed {} paths ({} bytes)",
+        paths.len(),
+        bytes
+    );
+    Ok(ResetReport {
+        dry_run: false,
+        paths,
+        bytes,
+        token,
+    })
+}
+
+/**
+ * The error a cancelled orphan cleanup fails with
+ */
+#[derive(Debug, Clone)]
+pub struct CleanupCancelled {
+    /**
+     * IDs of the games that were removed before the cleanup was cancelled
+     */
+    pub removed: Vec<String>,
+    /**
+     * IDs of the games that would have been removed, and are still installed
+     */
+    pub remaining: Vec<String>,
+}
+
+impl fmt::Display for CleanupCancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "CleanupCancelled: cleanup was cancelled after removing {} of {} games",
+            self.removed.len(),
+            self.removed.len() + self.remaining.len()
+        )?;
+        if !self.removed.is_empty() {
+            write!(f, " ({})", self.removed.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for CleanupCancelled {}
+
+/**
+ * Marks an orphan cleanup as running until it's dropped, however the cleanup ends
+ */
+struct CleanupRun;
+
+impl CleanupRun {
+    fn start() -> Self {
+        *CLEANUP_CANCELLED.lock().unwrap() = Some(false);
+        Self
+    }
+
+    fn cancelled(&self) -> bool {
+        *CLEANUP_CANCELLED.lock().unwrap() == Some(true)
+    }
+}
+
+impl Drop for CleanupRun {
+    fn drop(&mut self) {
+        *CLEANUP_CANCELLED.lock().unwrap() = None;
+    }
+}
+
+/**
+ * Remove installed games that the API no longer has, and return their IDs. With `dry_run`, nothing
+ * is removed and the games that would be are returned. The running game is never removed, and if
+ * the API can't be reached (or lists no games at all), nothing is removed, so an outage can't wipe
+ * the library. Removing a game also removes its art and screenshots, but not its saves.
+ *
+ * The cleanup can be stopped with `cancel_orphan_cleanup`, which takes effect before the next game
+ * is removed. Games are removed whole, so every game is either gone or still fully installed.
+ *
+ * # Errors
+ * This function will return an error if the game list can't be fetched from the API or read from
+ * the filesystem, or if a game's directory can't be removed. If the cleanup is cancelled, it fails
+ * with `CleanupCancelled`, listing the games removed before then.
+ */
+pub async fn cleanup_orphaned_games(dry_run: bool) -> Result<Vec<String>, Error> {
+    if !dry_run {
+        freeze::check()?;
+    }
+    let upstream = game_list()
+        .await
+        .map_err(|e| e.context("Couldn't get the game list, not cleaning up"))?;
+    let installed = game_list_from_fs()?;
+    if upstream.is_empty() && !installed.is_empty() {
+        return Err(anyhow!(
+            "The API lists no games, not cleaning up {} installed games",
+            installed.len()
+        ));
+    }
+
+    // Launches wait until the cleanup is done, so a game can't start while it's being removed
+    let _installing = INSTALLING.write().await;
+    let run = CleanupRun::start();
+    let running = game_running().then(|| current_game().id);
+    let mut orphaned: Vec<String> = installed
+        .into_iter()
+        .map(|game| game.id)
+        .filter(|id| !upstream.iter().any(|game| game.id == *id))
+        .filter(|id| {
+            let is_running = running.as_ref() == Some(id);
+            if is_running {
+                log!(Level::Info, "Not cleaning up game {}, it's running", id);
+            }
+            !is_running
+        })
+        .collect();
+    orphaned.sort();
+    orphaned.dedup();
+
+    if dry_run {
+        return Ok(orphaned);
+    }
+    let removal = GameOperation {
+        intent: GameIntent::Remove,
+        origin: OperationOrigin::Background,
+    };
+    // Games something else is being done with are left for the next cleanup
+    let mut removing = Vec::new();
+    orphaned.retain(|id| match operations::claim(id, removal) {
+        Some(ticket) => {
+            removing.push(ticket);
+            true
+        }
+        None => {
+            log!(
+                Level::Info,
+                "Not cleaning up game {}, something else is being done with it",
+                id
+            );
+            false
+        }
+    });
+    for (removed, id) in orphaned.iter().enumerate() {
+        if run.cancelled() {
+            let cancelled = CleanupCancelled {
+                removed: orphaned[..removed].to_vec(),
+                remaining: orphaned[removed..].to_vec(),
+            };
+            log!(Level::Info, "{}", cancelled);
+            return Err(cancelled.into());
+        }
+        let dir = layout::game_dir(id);
+        log!(
+            Level::Info,
+            "Removing game {}, which was removed from the API",
+            id
+        );
+        std::fs::remove_dir_all(&dir)
+            .map_err(|e| anyhow!("Couldn't remove {}: {}", dir.display(), e))?;
+    }
+    Ok(orphaned)
+}
+
+/**
+ * Stop the running orphan cleanup before it removes another game. The game being removed when this
+ * is called is still removed, and launches waiting on the cleanup go ahead as soon as it stops.
+ *
+ * # Errors
+ * This function will return an error if no cleanup is running.
+ */
+pub fn cancel_orphan_cleanup() -> Result<(), Error> {
+    match CLEANUP_CANCELLED.lock().unwrap().as_mut() {
+        Some(cancelled) => {
+            *cancelled = true;
+            log!(Level::Info, "Cancelling orphan cleanup");
+            Ok(())
+        }
+        None => Err(anyhow!("No cleanup is running")),
+    }
+}
+
+/**
+ * Get the size of all files under a path
+ */
+fn dir_size(path: &Path) -> Result<u64, Error> {
+    let meta = std::fs::symlink_metadata(path)?;
+    if !meta.is_dir() {
+        return Ok(meta.len());
+    }
+    let mut size = 0;
+    for entry in std::fs::read_dir(path)? {
+        size += dir_size(&entry?.path())?;
+    }
+    Ok(size)
+}
+
+/**
+ * Derive the confirmation token for a reset plan, so a token only confirms the plan it was printed
+ * for
+ */
+fn reset_token(paths: &[PathBuf], bytes: u64) -> String {
+    use std::hash::{Hash, Hasher};
+
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    paths.hash(&mut hasher);
+    bytes.hash(&mut hasher);
+    format!("{:08x}", hasher.finish() & 0xffff_ffff)
+}
+
+// The devcade directory lock is held across awaits on purpose, each test has its own runtime
+#[cfg(test)]
+#[allow(clippy::await_holding_lock)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, Reply};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn concurrent_art_downloads_stay_under_the_cap() {
+        let (_guard, root) = testing::root("asset-downloads");
+        let active = Arc::new(AtomicUsize::new(0));
+        let most = Arc::new(AtomicUsize::new(0));
+        let url = {
+            let (active, most) = (Arc::clone(&active), Arc::clone(&most));
+            testing::serve(move |_, _| {
+                let now = active.fetch_add(1, Ordering::SeqCst) + 1;
+                most.fetch_max(now, Ordering::SeqCst);
+                std::thread::sleep(Duration::from_millis(50));
+                active.fetch_sub(1, Ordering::SeqCst);
+                Reply::Respond("200 OK", Vec::new(), PLACEHOLDER_ART.to_vec())
+            })
+        };
+        std::env::set_var("DEVCADE_API_DOMAIN", url.as_str());
+        std::env::set_var("DEVCADE_DEV_API_DOMAIN", url.as_str());
+
+        let ids: Vec<String> = (0..50).map(|i| format!("game-{i}")).collect();
+        let downloads = ids.iter().cloned().map(download_icon);
+        for result in futures_util::future::join_all(downloads).await {
+            result.unwrap();
+        }
+        let most = most.load(Ordering::SeqCst);
+        assert!(most <= max_asset_downloads(), "{most} at once");
+        assert!(most > 1, "{most} at once");
+        for id in &ids {
+            let icon = std::fs::read(layout::game_dir(id).join("icon.png")).unwrap();
+            assert!(is_image(&icon), "{id}");
+        }
+
+        std::env::remove_var("DEVCADE_API_DOMAIN");
+        std::env::remove_var("DEVCADE_DEV_API_DOMAIN");
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    /**
+     * A whole PNG of one color
+     */
+    fn png(shade: u8) -> Vec<u8> {
+        let image = image::RgbaImage::from_pixel(2, 2, image::Rgba([shade, shade, shade, 255]));
+        let mut bytes = std::io::Cursor::new(Vec::new());
+        image.write_to(&mut bytes, image::ImageFormat::Png).unwrap();
+        bytes.into_inner()
+    }
+
+    #[tokio::test]
+    async fn unchanged_art_is_not_downloaded_again() {
+        let (_guard, root) = testing::root("art-etags");
+        // The current version of the art, and the requests answered in full and with 304
+        let version = Arc::new(AtomicUsize::new(1));
+        let full = Arc::new(AtomicUsize::new(0));
+        let not_modified = Arc::new(AtomicUsize::new(0));
+        let url = {
+            let (version, full, not_modified) = (
+                Arc::clone(&version),
+                Arc::clone(&full),
+                Arc::clone(&not_modified),
+            );
+            testing::serve(move |_, headers| {
+                let version = version.load(Ordering::SeqCst);
+                let etag = format!("\"v{version}\"");
+                let sent = headers.iter().find_map(|header| {
+                    let (name, value) = header.split_once(':')?;
+                    name.eq_ignore_ascii_case("if-none-match")
+                        .then(|| value.trim().to_string())
+                });
+                if sent.as_ref() == Some(&etag) {
+                    not_modified.fetch_add(1, Ordering::SeqCst);
+                    Reply::Respond("304 Not Modified", Vec::new(), Vec::new())
+                } else {
+                    full.fetch_add(1, Ordering::SeqCst);
+                    let shade = u8::try_from(version).unwrap();
+                    Reply::Respond("200 OK", vec![format!("ETag: {etag}")], png(shade))
+                }
+            })
+        };
+        std::env::set_var("DEVCADE_API_DOMAIN", url.as_str());
+        std::env::set_var("DEVCADE_DEV_API_DOMAIN", url.as_str());
+        let icon = layout::game_dir("pong").join("icon.png");
+        let etag = layout::game_dir("pong").join("icon.png.etag");
+        // The art is checked again each time the game's hash changes
+        let update = |hash: &str| {
+            KNOWN_HASHES
+                .lock()
+                .unwrap()
+                .insert(String::from("pong"), hash.to_string());
+        };
+
+        update("a");
+        download_icon(String::from("pong")).await.unwrap();
+        assert_eq!(std::fs::read(&icon).unwrap(), png(1));
+        assert_eq!(std::fs::read_to_string(&etag).unwrap(), "\"v1\"");
+
+        // The game changed but its art didn't
+        update("b");
+        download_icon(String::from("pong")).await.unwrap();
+        assert_eq!(not_modified.load(Ordering::SeqCst), 1);
+        assert_eq!(std::fs::read(&icon).unwrap(), png(1));
+
+        // Both changed
+        version.store(2, Ordering::SeqCst);
+        update("c");
+        download_icon(String::from("pong")).await.unwrap();
+        assert_eq!(std::fs::read(&icon).unwrap(), png(2));
+        assert_eq!(std::fs::read_to_string(&etag).unwrap(), "\"v2\"");
+        assert_eq!(full.load(Ordering::SeqCst), 2);
+        assert_eq!(not_modified.load(Ordering::SeqCst), 1);
+
+        KNOWN_HASHES.lock().unwrap().remove("pong");
+        std::env::remove_var("DEVCADE_API_DOMAIN");
+        std::env::remove_var("DEVCADE_DEV_API_DOMAIN");
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn tag_membership_is_cached_for_a_day() {
+        if !testing::alone("api::tests::tag_membership_is_cached_for_a_day") {
+            return;
+        }
+        let (_guard, root) = testing::root("tag-membership-ttl");
+        std::env::set_var("DEVCADE_REQUEST_ATTEMPTS", "1");
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let up = Arc::new(AtomicBool::new(true));
+        let url = {
+            let (fetches, up) = (Arc::clone(&fetches), Arc::clone(&up));
+            testing::serve(move |_, _| {
+                if !up.load(Ordering::SeqCst) {
+                    return Reply::Respond("404 Not Found", Vec::new(), Vec::new());
+                }
+                fetches.fetch_add(1, Ordering::SeqCst);
+                let pong = MinimalGame {
+                    id: String::from("pong"),
+                    ..MinimalGame::default()
+                };
+                let tags = serde_json::json!([{"name": "arcade", "games": [pong]}]);
+                Reply::Respond("200 OK", Vec::new(), serde_json::to_vec(&tags).unwrap())
+            })
+        };
+        std::env::set_var("DEVCADE_API_DOMAIN", url.as_str());
+        std::env::set_var("DEVCADE_DEV_API_DOMAIN", url.as_str());
+        clock::freeze();
+        let hour = Duration::from_secs(60 * 60);
+
+        let membership = tag_membership(false).await.unwrap();
+        assert_eq!(membership.tags["arcade"], ["pong"]);
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+        clock::advance(23 * hour);
+        assert!(!tag_membership(false).await.unwrap().stale);
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+        clock::advance(hour);
+        tag_membership(false).await.unwrap();
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
+        // Refreshing skips the cache, and so would a restart a day later
+        tag_membership(true).await.unwrap();
+        assert_eq!(fetches.load(Ordering::SeqCst), 3);
+        *TAG_MEMBERSHIP.lock().unwrap() = None;
+        assert_eq!(tag_membership(false).await.unwrap().tags.len(), 1);
+        assert_eq!(fetches.load(Ordering::SeqCst), 3);
+
+        // Expired membership is still used while the API is down, marked as stale
+        up.store(false, Ordering::SeqCst);
+        clock::advance(25 * hour);
+        let membership = tag_membership(false).await.unwrap();
+        assert!(membership.stale);
+        assert_eq!(membership.tags["arcade"], ["pong"]);
+
+        clock::unfreeze();
+        std::env::remove_var("DEVCADE_REQUEST_ATTEMPTS");
+        std::env::remove_var("DEVCADE_API_DOMAIN");
+        std::env::remove_var("DEVCADE_DEV_API_DOMAIN");
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn cancelled_cleanups_stop_between_games_and_release_their_locks() {
+        let (_guard, root) = testing::root("cleanup-cancel");
+        // The API still lists `kept`, every other installed game is orphaned
+        let url = testing::serve(|_, _| {
+            let kept = DevcadeGame {
+                id: String::from("kept"),
+                ..DevcadeGame::default()
+            };
+            Reply::Respond("200 OK", Vec::new(), serde_json::to_vec(&[kept]).unwrap())
+        });
+        std::env::set_var("DEVCADE_API_DOMAIN", url.as_str());
+        std::env::set_var("DEVCADE_DEV_API_DOMAIN", url.as_str());
+        let files = |id: &str| {
+            let mut files = Vec::new();
+            runtime::list_files(&layout::game_dir(id), "", 0, &mut files);
+            files.len()
+        };
+        let orphaned: Vec<String> = (0..16).map(|i| format!("orphan{i:02}")).collect();
+        for id in orphaned.iter().map(String::as_str).chain(["kept"]) {
+            let dir = layout::game_dir(id);
+            for i in 0..300 {
+                let file = dir.join(format!("publish/data{}/asset{i}.bin", i % 3));
+                std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+                std::fs::write(file, [0; 64]).unwrap();
+            }
+            let game = DevcadeGame {
+                id: id.to_string(),
+                ..DevcadeGame::default()
+            };
+            std::fs::write(dir.join("game.json"), serde_json::to_vec(&game).unwrap()).unwrap();
+        }
+        assert!(cancel_orphan_cleanup().is_err());
+
+        let cleanup = tokio::spawn(cleanup_orphaned_games(false));
+        // Cancel as soon as the first game is gone
+        while layout::game_dir(&orphaned[0]).exists() {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        cancel_orphan_cleanup().unwrap();
+        let cancelled_at = Instant::now();
+        // Like a launch that was waiting for the cleanup
+        let launch = tokio::spawn(async {
+            let _launching = INSTALLING.read().await;
+            Instant::now()
+        });
+        let e = cleanup.await.unwrap().unwrap_err();
+        let launched_at = launch.await.unwrap();
+        assert!(launched_at - cancelled_at < Duration::from_secs(2));
+
+        let cancelled = e.downcast_ref::<CleanupCancelled>().unwrap();
+        assert!(!cancelled.removed.is_empty() && !cancelled.remaining.is_empty());
+        assert_eq!(
+            [&cancelled.removed[..], &cancelled.remaining[..]].concat(),
+            orphaned
+        );
+        assert!(e.to_string().starts_with("CleanupCancelled"));
+        // Every game is either gone or still whole
+        for id in &cancelled.removed {
+            assert!(!layout::game_dir(id).exists(), "{id}");
+        }
+        for id in cancelled
+            .remaining
+            .iter()
+            .map(String::as_str)
+            .chain(["kept"])
+        {
+            assert_eq!(files(id), 301, "{id}");
+        }
+        // Nothing is left locked or marked as running
+        assert!(cancel_orphan_cleanup().is_err());
+        assert!(INSTALLING.try_write().is_ok());
+        let removal = GameOperation {
+            intent: GameIntent::Remove,
+            origin: OperationOrigin::Background,
+        };
+        assert!(operations::claim(&cancelled.remaining[0], removal).is_some());
+
+        // The next cleanup finishes the job
+        let removed = cleanup_orphaned_games(false).await.unwrap();
+        assert_eq!(removed, cancelled.remaining);
+        assert_eq!(game_list_from_fs().unwrap().len(), 1);
+
+        std::env::remove_var("DEVCADE_API_DOMAIN");
+        std::env::remove_var("DEVCADE_DEV_API_DOMAIN");
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    /// The most the low profile's warm-up of `WARM_UP_GAMES` may allocate at once
+    const LOW_PROFILE_PEAK: usize = 4 * 1024 * 1024;
+    const WARM_UP_GAMES: u8 = 100;
+
+    /**
+     * Not a test by itself: when run by `low_profile_warm_up_stays_small` with
+     * `DEVCADE_WARM_UP_TEST` set, installs `WARM_UP_GAMES` games with icons, then generates their
+     * thumbnails, inlines them and builds the icon atlas like a cold start does. Prints the most
+     * that was allocated at once along the way.
+     */
+    #[test]
+    fn warm_up() {
+        if std::env::var("DEVCADE_WARM_UP_TEST").is_err() {
+            return;
+        }
+        let (_guard, root) = testing::root("warm-up");
+        let ids: Vec<String> = (0..WARM_UP_GAMES).map(|i| format!("game{i:03}")).collect();
+        for (i, id) in (0..WARM_UP_GAMES).zip(&ids) {
+            let dir = layout::game_dir(id);
+            std::fs::create_dir_all(&dir).unwrap();
+            let game = DevcadeGame {
+                id: id.clone(),
+                ..DevcadeGame::default()
+            };
+            std::fs::write(dir.join("game.json"), serde_json::to_vec(&game).unwrap()).unwrap();
+            let icon =
+                image::RgbaImage::from_fn(256, 256, |x, y| image::Rgba([x as u8, y as u8, i, 255]));
+            icon.save(dir.join("icon.png")).unwrap();
+        }
+
+        let start = testing::reset_peak();
+        for id in &ids {
+            thumbnails::generate(id).unwrap();
+        }
+        let inlined = thumbnails::inline(ids.iter().map(String::as_str));
+        assert!(!inlined.is_empty());
+        drop(inlined);
+        let atlas = build_icon_atlas(2048, 128);
+        if resources::allows(Capability::IconAtlas) {
+            atlas.unwrap();
+        } else {
+            assert!(atlas
+                .unwrap_err()
+                .to_string()
+                .starts_with("CapabilitySkipped"));
+        }
+        println!("peak {}", testing::peak_since(start));
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    /**
+     * Run `warm_up` in a process of its own with a resource profile, and get its peak allocation
+     */
+    fn warm_up_peak(profile: &str) -> usize {
+        let output = std::process::Command::new(std::env::current_exe().unwrap())
+            .args(["api::tests::warm_up", "--exact", "--nocapture"])
+            .env("DEVCADE_WARM_UP_TEST", "1")
+            .env("DEVCADE_RESOURCE_PROFILE", profile)
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(output.status.success(), "{stdout}");
+        stdout
+            .lines()
+            // Printed after the test's name, on the same line
+            .find_map(|line| line.split_once("peak ").map(|(_, peak)| peak))
+            .unwrap()
+            .trim()
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn low_profile_warm_up_stays_small() {
+        let low = warm_up_peak("low");
+        assert!(low < LOW_PROFILE_PEAK, "low profile peaked at {low} bytes");
+        // The bound is tight enough that the full profile's atlas doesn't fit under it
+        let full = warm_up_peak("full");
+        assert!(
+            full > LOW_PROFILE_PEAK,
+            "full profile peaked at {full} bytes"
+        );
+    }
 }
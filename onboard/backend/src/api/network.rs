@@ -0,0 +1,1081 @@
+use crate::clock;
+use crate::env::{
+    ca_bundle, cache_bust_interval, cache_max_age, download_idle_timeout, http_connect_timeout,
+    http_timeout, max_download_bps, max_requests, redirect_hosts, request_attempts,
+    request_backoff,
+};
+use crate::faults::{self, site, InjectedFault};
+use anyhow::{anyhow, Error};
+use futures_util::StreamExt;
+use lazy_static::lazy_static;
+use log::{log, Level};
+use reqwest::header::{HeaderMap, HeaderValue};
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::oneshot;
+
+// Construct a static client to be used for all requests. Prevents opening a new connection for
+// every request. The client is rebuilt by `reload_tls`, requests already in flight finish on
+// the old client.
+lazy_static! {
+    static ref CLIENT: RwLock<reqwest::Client> =
+        RwLock::new(build_client().unwrap_or_else(|e| {
+            log!(
+                Level::Error,
+                "Error building HTTP client, falling back to defaults: {}",
+                e
+            );
+            reqwest::Client::builder()
+                .connect_timeout(http_connect_timeout())
+                .build()
+                .unwrap_or_default()
+        }));
+    static ref LIMITER: Mutex<Limiter> = Mutex::new(Limiter::default());
+
+    // When a request last went past the caches in front of the API
+    static ref LAST_CACHE_BUST: Mutex<Option<Instant>> = Mutex::new(None);
+
+    // Shared by every game download, so together they stay under the limit
+    static ref BANDWIDTH: Mutex<Bucket> = Mutex::new(Bucket::new(max_download_bps()));
+}
+
+/**
+ * A token bucket limiting game downloads to `rate` bytes per second, allowing bursts of up to a
+ * second's worth. A rate of 0 is unlimited.
+ */
+struct Bucket {
+    rate: u64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(rate: u64) -> Self {
+        Self {
+            rate,
+            tokens: rate as f64,
+            updated: clock::now(),
+        }
+    }
+
+    /**
+     * Take tokens for bytes that were just received, returning how long to wait before reading
+     * more. The bucket goes into debt rather than refusing, so a chunk bigger than the bucket
+     * still gets through.
+     */
+    fn take(&mut self, bytes: u64) -> Duration {
+        if self.rate == 0 {
+            return Duration::ZERO;
+        }
+        let now = clock::now();
+        let rate = self.rate as f64;
+        let refill = now.duration_since(self.updated).as_secs_f64() * rate;
+        self.tokens = (self.tokens + refill).min(rate) - bytes as f64;
+        self.updated = now;
+        if self.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-self.tokens / rate)
+        }
+    }
+}
+
+/**
+ * Limit game downloads to `rate` bytes per second, or lift the limit with 0. Downloads in
+ * progress slow down or speed up from their next chunk.
+ */
+pub fn set_download_limit(rate: u64) {
+    *BANDWIDTH.lock().unwrap() = Bucket::new(rate);
+    if rate == 0 {
+        log!(Level::Info, "Game downloads are no longer limited");
+    } else {
+        log!(
+            Level::Info,
+            "Limiting game downloads to {} bytes per second",
+            rate
+        );
+    }
+}
+
+/**
+ * How many stale responses from caches in front of the API have been caught since startup
+ */
+static STALE_RESPONSES: AtomicU64 = AtomicU64::new(0);
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/**
+ * How urgently a request is needed. When too many requests are in flight, waiting requests are
+ * let through in priority order, and in arrival order within a priority.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    /**
+     * A player is waiting on the result
+     */
+    Interactive = 0,
+    /**
+     * Needed soon, but nobody is staring at a spinner
+     */
+    Normal = 1,
+    /**
+     * Bulk work like fetching art for the whole game list
+     */
+    Background = 2,
+}
+
+/**
+ * How long a request can wait for a slot before it's worth logging
+ */
+const SLOW_QUEUE: Duration = Duration::from_secs(1);
+
+/**
+ * Tracks the requests in flight and the requests waiting for a slot
+ */
+#[derive(Default)]
+struct Limiter {
+    active: usize,
+    waiting: [VecDeque<oneshot::Sender<Permit>>; 3],
+}
+
+/**
+ * A slot for one request in flight. Dropping it passes the slot to the next waiting request.
+ */
+struct Permit;
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let mut limiter = LIMITER.lock().unwrap();
+        for queue in &mut limiter.waiting {
+            while let Some(waiter) = queue.pop_front() {
+                match waiter.send(Permit) {
+                    Ok(()) => return,
+                    // The waiter gave up, so the slot is handed on to the next one. The
+                    // returned permit stands for this same slot and mustn't be dropped here.
+                    Err(permit) => std::mem::forget(permit),
+                }
+            }
+        }
+        limiter.active -= 1;
+    }
+}
+
+/**
+ * Wait for a slot to make a request in
+ */
+async fn acquire(priority: Priority) -> Permit {
+    let start = Instant::now();
+    let receiver = {
+        let mut limiter = LIMITER.lock().unwrap();
+        if limiter.active < max_requests() {
+            limiter.active += 1;
+            return Permit;
+        }
+        let (sender, receiver) = oneshot::channel();
+        limiter.waiting[priority as usize].push_back(sender);
+        log!(
+            Level::Trace,
+            "Request queued at {:?} priority, {} waiting",
+            priority,
+            limiter.waiting.iter().map(VecDeque::len).sum::<usize>()
+        );
+        receiver
+    };
+    // The sender is only dropped after sending, since the limiter is never cleared
+    let permit = receiver.await.expect("Request limiter dropped a waiter");
+    let waited = start.elapsed();
+    if waited > SLOW_QUEUE {
+        log!(
+            Level::Debug,
+            "{:?} request waited {}ms for a slot",
+            priority,
+            waited.as_millis()
+        );
+    }
+    permit
+}
+
+fn client() -> reqwest::Client {
+    CLIENT.read().unwrap().clone()
+}
+
+/**
+ * Build a client trusting the certificates in `DEVCADE_CA_BUNDLE` in addition to the system's
+ *
+ * # Errors
+ * This function will return an error if the CA bundle cannot be read or contains no valid
+ * certificates.
+ */
+fn build_client() -> Result<reqwest::Client, Error> {
+    // There's no overall timeout here, since downloads can take much longer than other
+    // requests. Each request sets its own instead.
+    let mut builder = reqwest::Client::builder()
+        .redirect(redirect_policy())
+        .connect_timeout(http_connect_timeout());
+    if let Some(path) = ca_bundle() {
+        let pem = std::fs::read_to_string(&path)
+            .map_err(|e| anyhow!("Couldn't read CA bundle {}: {}", path, e))?;
+        let mut count = 0;
+        for cert in pem.split_inclusive("-----END CERTIFICATE-----") {
+            if !cert.contains("-----BEGIN CERTIFICATE-----") {
+                continue;
+            }
+            builder = builder
+                .add_root_certificate(reqwest::Certificate::from_pem(cert.trim().as_bytes())?);
+            count += 1;
+        }
+        if count == 0 {
+            return Err(anyhow!("CA bundle {} contains no certificates", path));
+        }
+        log!(
+            Level::Info,
+            "Loaded {} certificates from CA bundle {}",
+            count,
+            path
+        );
+    }
+    Ok(builder.build()?)
+}
+
+/**
+ * The most redirects followed for a single request
+ */
+const MAX_REDIRECTS: usize = 10;
+
+/**
+ * Build the redirect policy for the client. Redirects are only followed to http(s) URLs, never
+ * from https to http, and, if `DEVCADE_REDIRECT_HOSTS` is set, only to the API's own host or
+ * one of the listed hosts.
+ */
+fn redirect_policy() -> reqwest::redirect::Policy {
+    let allowed = redirect_hosts();
+
+    reqwest::redirect::Policy::custom(move |attempt| {
+        if attempt.previous().len() > MAX_REDIRECTS {
+            return attempt.error(format!("more than {MAX_REDIRECTS} redirects"));
+        }
+        let url = attempt.url();
+        let from_https = attempt
+            .previous()
+            .last()
+            .is_some_and(|prev| prev.scheme() == "https");
+        match url.scheme() {
+            "https" => {}
+            "http" if !from_https => {}
+            scheme => {
+                let err = format!("redirect to disallowed scheme {scheme}: {url}");
+                return attempt.error(err);
+            }
+        }
+        if let Some(allowed) = &allowed {
+            let host = url.host_str().unwrap_or("").to_lowercase();
+            // The first URL is the API route that was requested, which follows SetProduction
+            let api_host = attempt
+                .previous()
+                .first()
+                .and_then(|url| url.host_str())
+                .unwrap_or("")
+                .to_lowercase();
+            if host != api_host && !allowed.iter().any(|pattern| host_matches(pattern, &host)) {
+                let err = format!("redirect to disallowed host {host}: {url}");
+                return attempt.error(err);
+            }
+        }
+        attempt.follow()
+    })
+}
+
+/**
+ * Check whether a host matches an allowlist entry, where `*.example.com` matches any subdomain
+ * of example.com
+ */
+fn host_matches(pattern: &str, host: &str) -> bool {
+    match pattern.strip_prefix("*.") {
+        Some(domain) => host
+            .strip_suffix(domain)
+            .is_some_and(|sub| sub.ends_with('.') && sub.len() > 1),
+        None => pattern == host,
+    }
+}
+
+/**
+ * Re-read the CA bundle and replace the client used for new requests. If the bundle can't be
+ * loaded, the current client is kept.
+ *
+ * # Errors
+ * This function will return an error if the new client cannot be built.
+ */
+pub fn reload_tls() -> Result<(), Error> {
+    let client = build_client()?;
+    *CLIENT.write().unwrap() = client;
+    log!(Level::Info, "Reloaded TLS configuration");
+    Ok(())
+}
+
+/**
+ * Request JSON from a URL and serialize it into a struct
+ *
+ * # Errors
+ * This function will return an error if the request fails, or if the JSON cannot be
+ * deserialized
+ */
+pub async fn request_json<T: for<'de> Deserialize<'de>>(
+    url: &str,
+    priority: Priority,
+) -> Result<T, Error> {
+    let _permit = acquire(priority).await;
+    log!(Level::Trace, "Requesting JSON from {}", url);
+    let response = get(url, HeaderMap::new(), Timeout::Total(http_timeout())).await?;
+    let age = response_age(response.headers());
+    let via = response
+        .headers()
+        .get(reqwest::header::VIA)
+        .and_then(|via| via.to_str().ok())
+        .map(str::to_string);
+    let mut body = response.bytes().await?.to_vec();
+
+    // A proxy may keep serving an old copy long after the API has changed, so old responses
+    // are compared with one fetched past the caches
+    if let Some(age) = age.filter(|age| *age > cache_max_age()) {
+        if may_bust_cache() {
+            log!(
+                Level::Debug,
+                "Response from {} is {}s old, checking it past the caches",
+                url,
+                age.as_secs()
+            );
+            match get_uncached(url).await {
+                Ok(fresh) if fresh != body => {
+                    report_stale(
+                        url,
+                        format!(
+                            "a copy {}s old was served by {}",
+                            age.as_secs(),
+                            via.as_deref().unwrap_or("an unknown cache")
+                        )
+                        .as_str(),
+                    );
+                    body = fresh;
+                }
+                Ok(_) => {}
+                Err(e) => log!(
+                    Level::Debug,
+                    "Couldn't check {} past the caches: {}",
+                    url,
+                    e
+                ),
+            }
+        }
+    }
+    Ok(serde_json::from_slice(&body)?)
+}
+
+/**
+ * Request JSON from a URL past any caches in front of the API
+ *
+ * # Errors
+ * This function will return an error if the request fails, or if the JSON cannot be
+ * deserialized
+ */
+pub async fn request_json_uncached<T: for<'de> Deserialize<'de>>(
+    url: &str,
+    priority: Priority,
+) -> Result<T, Error> {
+    let _permit = acquire(priority).await;
+    Ok(serde_json::from_slice(&get_uncached(url).await?)?)
+}
+
+/**
+ * Take the chance to send a request past the caches, unless one was sent too recently
+ */
+pub fn may_bust_cache() -> bool {
+    let mut last = LAST_CACHE_BUST.lock().unwrap();
+    if last.is_some_and(|last| clock::elapsed(last) < cache_bust_interval()) {
+        return false;
+    }
+    *last = Some(clock::now());
+    true
+}
+
+/**
+ * Record that a cache in front of the API served stale data, loudly enough to take to whoever
+ * runs the cache
+ */
+pub fn report_stale(url: &str, reason: &str) {
+    let count = STALE_RESPONSES.fetch_add(1, Ordering::Relaxed) + 1;
+    log!(
+        Level::Warn,
+        "Stale response for {}: {}. Using a fresh copy instead ({} stale responses since \
+        startup)",
+        url,
+        reason,
+        count
+    );
+}
+
+/**
+ * Whether a request failed because there was nothing at the URL
+ */
+pub fn is_not_found(e: &Error) -> bool {
+    e.downcast_ref::<reqwest::Error>()
+        .and_then(reqwest::Error::status)
+        == Some(reqwest::StatusCode::NOT_FOUND)
+}
+
+/**
+ * Send a GET request that caches should pass on to the API, by asking them not to use a cached
+ * copy and adding a query parameter they can't have seen before
+ */
+async fn get_uncached(url: &str) -> Result<Vec<u8>, Error> {
+    let buster = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let response = client()
+        .get(url)
+        .query(&[("nocache", buster)])
+        .header(reqwest::header::CACHE_CONTROL, "no-cache")
+        .header(reqwest::header::PRAGMA, "no-cache")
+        .timeout(http_timeout())
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(response.bytes().await?.to_vec())
+}
+
+/**
+ * How long ago a response was generated, from its `Age` header or how long ago its `Date`
+ * header was, whichever is more
+ */
+fn response_age(headers: &HeaderMap) -> Option<Duration> {
+    let age = headers
+        .get(reqwest::header::AGE)
+        .and_then(|age| age.to_str().ok()?.trim().parse::<u64>().ok());
+    let now = clock::unix_now();
+    let since_date = headers
+        .get(reqwest::header::DATE)
+        .and_then(|date| http_date(date.to_str().ok()?))
+        .and_then(|date| now.checked_sub(date));
+    age.into_iter()
+        .chain(since_date)
+        .max()
+        .map(Duration::from_secs)
+}
+
+/**
+ * Parse an HTTP date like `Sun, 06 Nov 1994 08:49:37 GMT` into a unix timestamp
+ */
+fn http_date(date: &str) -> Option<u64> {
+    let mut parts = date.split_whitespace().skip(1);
+    let day: u64 = parts.next()?.parse().ok()?;
+    let month_name = parts.next()?;
+    let month = MONTHS.iter().position(|month| *month == month_name)? as u64 + 1;
+    let year: u64 = parts.next()?.parse().ok()?;
+    let mut time = parts
+        .next()?
+        .split(':')
+        .map(|part| part.parse::<u64>().ok());
+    let (hours, minutes, seconds) = (time.next()??, time.next()??, time.next()??);
+
+    // Days since the epoch, counting years from March so leap days come last
+    let (year, month) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = (era * 146_097 + day_of_era).checked_sub(719_468)?;
+    Some(days * 86_400 + hours * 3_600 + minutes * 60 + seconds)
+}
+
+/**
+ * Request binary data from a URL. The API may redirect to a CDN with signed URLs, so if the
+ * redirected request is refused with a 403 (usually because the signature expired), the
+ * original URL is requested once more to get a freshly signed one.
+ *
+ * # Errors
+ * This function will return an error if the request fails, is redirected somewhere that isn't
+ * allowed, or doesn't succeed.
+ */
+pub async fn request_bytes(url: &str, priority: Priority) -> Result<Vec<u8>, Error> {
+    let _permit = acquire(priority).await;
+    log!(Level::Trace, "Requesting binary from {}", url);
+    let bytes = get(url, HeaderMap::new(), Timeout::Total(http_timeout()))
+        .await?
+        .bytes()
+        .await?;
+    Ok(bytes.to_vec())
+}
+
+/**
+ * The result of a conditional request
+ */
+pub enum Fetched {
+    /**
+     * The server sent a new copy, along with its `ETag` if it has one
+     */
+    Modified {
+        bytes: Vec<u8>,
+        etag: Option<String>,
+    },
+    /**
+     * The copy with the `ETag` that was sent is still current
+     */
+    NotModified,
+}
+
+/**
+ * Request binary data from a URL like `request_bytes`, unless the copy with the given `ETag`
+ * is still current. Servers without `ETag` support just send the data every time.
+ *
+ * # Errors
+ * This function will return an error if the request fails, is redirected somewhere that isn't
+ * allowed, or doesn't succeed.
+ */
+pub async fn request_bytes_if_changed(
+    url: &str,
+    priority: Priority,
+    etag: Option<&str>,
+) -> Result<Fetched, Error> {
+    let _permit = acquire(priority).await;
+    log!(
+        Level::Trace,
+        "Requesting binary from {} unless it matches {:?}",
+        url,
+        etag
+    );
+    let mut headers = HeaderMap::new();
+    if let Some(etag) = etag.and_then(|etag| HeaderValue::from_str(etag).ok()) {
+        headers.insert(reqwest::header::IF_NONE_MATCH, etag);
+    }
+    let response = get(url, headers, Timeout::Total(http_timeout())).await?;
+    if response.status() == reqwest::StatusCode::NOT_MODIFIED {
+        return Ok(Fetched::NotModified);
+    }
+    let etag = response
+        .headers()
+        .get(reqwest::header::ETAG)
+        .and_then(|etag| etag.to_str().ok())
+        .map(str::to_string);
+    Ok(Fetched::Modified {
+        bytes: response.bytes().await?.to_vec(),
+        etag,
+    })
+}
+
+/**
+ * Get the size of what's at a URL from a HEAD request, without downloading it. Returns `None`
+ * if the server doesn't say.
+ *
+ * # Errors
+ * This function will return an error if the request fails or doesn't succeed.
+ */
+pub async fn content_length(url: &str, priority: Priority) -> Result<Option<u64>, Error> {
+    let _permit = acquire(priority).await;
+    log!(Level::Trace, "Requesting size of {}", url);
+    let response = client()
+        .head(url)
+        .timeout(http_timeout())
+        .send()
+        .await?
+        .error_for_status()?;
+    // The body of a HEAD response is empty, so the header has to be read directly
+    Ok(response
+        .headers()
+        .get(reqwest::header::CONTENT_LENGTH)
+        .and_then(|length| length.to_str().ok()?.parse().ok()))
+}
+
+/**
+ * Download a URL straight into a file, without holding the whole body in memory. If the file
+ * already has part of the download in it, only the rest is requested with a `Range` header,
+ * and if the server doesn't support ranges the file is downloaded again from the start.
+ * Redirects are handled the same way as `request_bytes`. `progress` is called with the bytes
+ * in the file so far and the total size, if the server sent one, and the download stops if it
+ * returns an error. Returns the size of the file. Downloads are kept under the bandwidth
+ * limit together (see `set_download_limit`).
+ *
+ * The file is left in place if the download fails, so it can be resumed. Callers should check
+ * the finished file, since a partial download from a different version of the file would be
+ * continued as if it were the same.
+ *
+ * # Errors
+ * This function will return an error if the request fails, doesn't succeed, or the file
+ * cannot be written.
+ */
+pub async fn download(
+    url: &str,
+    priority: Priority,
+    path: &Path,
+    mut progress: impl FnMut(u64, Option<u64>) -> Result<(), Error>,
+) -> Result<u64, Error> {
+    let _permit = acquire(priority).await;
+    let existing = tokio::fs::metadata(path)
+        .await
+        .map(|meta| meta.len())
+        .unwrap_or(0);
+    let resume = (existing > 0).then_some(existing);
+    log!(
+        Level::Trace,
+        "Downloading {} to {} from byte {}",
+        url,
+        path.display(),
+        existing
+    );
+
+    let mut headers = HeaderMap::new();
+    if let Some(start) = resume {
+        headers.insert(reqwest::header::RANGE, format!("bytes={start}-").parse()?);
+    }
+    let response = match get(url, headers, Timeout::Response(download_idle_timeout())).await {
+        Ok(response) => response,
+        // The partial file already has everything the server has
+        Err(e)
+            if e.downcast_ref::<reqwest::Error>()
+                .and_then(reqwest::Error::status)
+                == Some(reqwest::StatusCode::RANGE_NOT_SATISFIABLE) =>
+        {
+            log!(Level::Debug, "{} was already fully downloaded", url);
+            progress(existing, Some(existing))?;
+            return Ok(existing);
+        }
+        Err(e) => return Err(e),
+    };
+    let resumed = response.status() == reqwest::StatusCode::PARTIAL_CONTENT;
+    let mut written = if resumed { existing } else { 0 };
+    if resume.is_some() {
+        if resumed {
+            log!(
+                Level::Info,
+                "Resuming download of {} at byte {}",
+                url,
+                existing
+            );
+        } else {
+            log!(Level::Info, "{} can't be resumed, starting over", url);
+        }
+    }
+    let total = response.content_length().map(|length| written + length);
+    let mut stream = response.bytes_stream();
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(resumed)
+        .truncate(!resumed)
+        .open(path)
+        .await?;
+    progress(written, total)?;
+    let idle = download_idle_timeout();
+    while let Some(chunk) = clock::timeout(idle, stream.next())
+        .await
+        .map_err(|_| anyhow!("Download of {url} stalled for {idle:?}"))?
+    {
+        faults::check(site::NETWORK_DOWNLOAD)?;
+        let chunk = chunk?;
+        file.write_all(&chunk).await?;
+        written += chunk.len() as u64;
+        progress(written, total)?;
+        let wait = BANDWIDTH.lock().unwrap().take(chunk.len() as u64);
+        if !wait.is_zero() {
+            clock::sleep(wait).await;
+        }
+    }
+    file.flush().await?;
+    Ok(written)
+}
+
+/**
+ * Open a stream of server-sent events from a URL. The stream doesn't time out, so the caller
+ * should give up on it if it goes quiet for too long. It doesn't take a request slot, since it
+ * stays open.
+ *
+ * # Errors
+ * This function will return an error if the request fails or doesn't succeed.
+ */
+pub async fn event_stream(
+    url: &str,
+) -> Result<impl futures_util::Stream<Item = Result<Vec<u8>, Error>>, Error> {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        reqwest::header::ACCEPT,
+        HeaderValue::from_static("text/event-stream"),
+    );
+    let response = get_once(url, &headers, Timeout::Response(http_timeout())).await?;
+    Ok(response
+        .bytes_stream()
+        .map(|chunk| chunk.map(|bytes| bytes.to_vec()).map_err(Error::from)))
+}
+
+/**
+ * How long a request may take
+ */
+#[derive(Debug, Clone, Copy)]
+enum Timeout {
+    /**
+     * Limits the whole request, including reading the response body
+     */
+    Total(Duration),
+    /**
+     * Only limits waiting for the response to start, the caller limits reading the body
+     */
+    Response(Duration),
+}
+
+/**
+ * Send a GET request with extra headers (like `Range`), and fail on an unsuccessful status.
+ * Connection errors, timeouts and server errors are retried with exponential backoff, up to
+ * `DEVCADE_REQUEST_ATTEMPTS` attempts in total. Client errors are never retried. If the
+ * request was retried, the error says how many attempts were made.
+ */
+async fn get(url: &str, headers: HeaderMap, timeout: Timeout) -> Result<reqwest::Response, Error> {
+    let attempts = request_attempts();
+    let mut attempt = 1;
+    loop {
+        match get_once(url, &headers, timeout).await {
+            Ok(response) => return Ok(response),
+            Err(e) if attempt < attempts && is_transient(&e) => {
+                let wait = backoff(attempt);
+                log!(
+                    Level::Debug,
+                    "Request to {} failed (attempt {}/{}), retrying in {:?}: {}",
+                    url,
+                    attempt,
+                    attempts,
+                    wait,
+                    e
+                );
+                clock::sleep(wait).await;
+                attempt += 1;
+            }
+            Err(e) if attempt > 1 => {
+                let message = format!("{e} (after {attempt} attempts)");
+                return Err(e.context(message));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/**
+ * Whether a failed request might succeed if it's tried again
+ */
+fn is_transient(e: &Error) -> bool {
+    // Injected faults stand in for network failures, so they're retried the same way
+    if e.is::<clock::Elapsed>() || e.is::<InjectedFault>() {
+        return true;
+    }
+    e.downcast_ref::<reqwest::Error>().is_some_and(|e| {
+        e.is_connect()
+            || e.is_timeout()
+            || e.status().is_some_and(|status| status.is_server_error())
+    })
+}
+
+/**
+ * How long to wait before the retry after a given attempt: the configured backoff doubled for
+ * each attempt, between half and all of it so retries from several requests spread out
+ */
+fn backoff(attempt: u32) -> Duration {
+    let max = request_backoff().saturating_mul(1 << (attempt - 1).min(16));
+    // Jitter from the clock, which is random enough to keep retries from lining up
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos();
+    max / 2 + max.mul_f64(f64::from(nanos % 1000) / 2000.0)
+}
+
+/**
+ * Send a GET request once, retrying once if a redirected request is refused
+ */
+async fn get_once(
+    url: &str,
+    headers: &HeaderMap,
+    timeout: Timeout,
+) -> Result<reqwest::Response, Error> {
+    let mut retried = false;
+    loop {
+        faults::check(site::NETWORK_REQUEST)?;
+        let mut request = client().get(url).headers(headers.clone());
+        let limit = match timeout {
+            Timeout::Total(limit) => {
+                // Reading the body is only limited on the real clock
+                request = request.timeout(limit);
+                limit
+            }
+            Timeout::Response(limit) => limit,
+        };
+        let response = clock::timeout(limit, request.send())
+            .await
+            .map_err(|elapsed| {
+                Error::from(elapsed).context(format!("No response from {url} within {limit:?}"))
+            })??;
+        let redirected = response.url().as_str() != url;
+        if redirected {
+            log!(Level::Debug, "{} redirected to {}", url, response.url());
+        }
+        if redirected && !retried && response.status() == reqwest::StatusCode::FORBIDDEN {
+            log!(
+                Level::Warn,
+                "{} was refused after redirecting to {}, retrying",
+                url,
+                response.url()
+            );
+            retried = true;
+            continue;
+        }
+        return Ok(response.error_for_status()?);
+    }
+}
+
+// The devcade directory lock is held across awaits on purpose, each test has its own runtime
+#[cfg(test)]
+#[allow(clippy::await_holding_lock)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, Reply};
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Arc;
+
+    /// How far `testing::fast_forward` moves the clock each millisecond
+    const STEP: Duration = Duration::from_millis(100);
+
+    #[tokio::test]
+    async fn requests_to_a_server_that_never_answers_time_out() {
+        if !testing::alone("api::network::tests::requests_to_a_server_that_never_answers_time_out")
+        {
+            return;
+        }
+        let (_guard, root) = testing::root("network-timeout");
+        std::env::set_var("DEVCADE_REQUEST_ATTEMPTS", "1");
+        let url = testing::serve(|path, _| match path {
+            "/answered" => Reply::Respond("200 OK", Vec::new(), b"[]".to_vec()),
+            _ => Reply::Hang,
+        });
+        let answered = testing::fast_forward(
+            STEP,
+            request_bytes(format!("{url}/answered").as_str(), Priority::Interactive),
+        )
+        .await
+        .unwrap();
+        assert_eq!(answered, b"[]");
+
+        let (start, real) = (clock::now(), Instant::now());
+        let result = testing::fast_forward(
+            STEP,
+            request_bytes(format!("{url}/games").as_str(), Priority::Interactive),
+        )
+        .await;
+        let e = result.unwrap_err();
+        assert!(e.is::<clock::Elapsed>(), "{e:?}");
+        // The whole timeout passed on the clock, without waiting for it
+        assert!(clock::elapsed(start) >= http_timeout());
+        assert!(real.elapsed() < http_timeout() / 2, "{:?}", real.elapsed());
+
+        clock::unfreeze();
+        std::env::remove_var("DEVCADE_REQUEST_ATTEMPTS");
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn stalled_downloads_time_out_but_keep_what_arrived() {
+        if !testing::alone("api::network::tests::stalled_downloads_time_out_but_keep_what_arrived")
+        {
+            return;
+        }
+        let (_guard, root) = testing::root("network-stall");
+        // Longer than a request may take in total, since downloads only time out when idle
+        std::env::set_var("DEVCADE_DOWNLOAD_IDLE_TIMEOUT_SECS", "60");
+        std::env::set_var("DEVCADE_REQUEST_ATTEMPTS", "1");
+        let url = testing::serve(|_, _| Reply::Stall("200 OK", vec![7; 10_000], 4000));
+        let path = root.join("game.zip");
+
+        let (start, real) = (clock::now(), Instant::now());
+        let result = testing::fast_forward(
+            STEP,
+            download(
+                format!("{url}/games/pong/game").as_str(),
+                Priority::Background,
+                &path,
+                |_, _| Ok(()),
+            ),
+        )
+        .await;
+        assert!(result.unwrap_err().to_string().contains("stalled"));
+        assert!(clock::elapsed(start) >= download_idle_timeout());
+        assert!(
+            real.elapsed() < Duration::from_secs(30),
+            "{:?}",
+            real.elapsed()
+        );
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 4000);
+
+        clock::unfreeze();
+        std::env::remove_var("DEVCADE_DOWNLOAD_IDLE_TIMEOUT_SECS");
+        std::env::remove_var("DEVCADE_REQUEST_ATTEMPTS");
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn failed_requests_are_retried_with_backoff() {
+        if !testing::alone("api::network::tests::failed_requests_are_retried_with_backoff") {
+            return;
+        }
+        let (_guard, root) = testing::root("network-retry");
+        std::env::set_var("DEVCADE_REQUEST_ATTEMPTS", "3");
+        std::env::set_var("DEVCADE_REQUEST_BACKOFF_MS", "10000");
+        let requests = Arc::new(AtomicUsize::new(0));
+        let url = {
+            let requests = Arc::clone(&requests);
+            testing::serve(move |_, _| {
+                // Fails twice, then works
+                if requests.fetch_add(1, Ordering::SeqCst) < 2 {
+                    Reply::Respond("503 Service Unavailable", Vec::new(), Vec::new())
+                } else {
+                    Reply::Respond("200 OK", Vec::new(), b"[]".to_vec())
+                }
+            })
+        };
+
+        let (start, real) = (clock::now(), Instant::now());
+        let games = testing::fast_forward(
+            STEP,
+            request_bytes(format!("{url}/games").as_str(), Priority::Normal),
+        )
+        .await
+        .unwrap();
+        assert_eq!(games, b"[]");
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+        // At least half of each backoff, 10 and then 20 seconds
+        assert!(clock::elapsed(start) >= Duration::from_secs(15));
+        assert!(
+            real.elapsed() < Duration::from_secs(15),
+            "{:?}",
+            real.elapsed()
+        );
+
+        // Injected faults are retried like real failures, and never reach the server
+        faults::set("network", 1.0);
+        let result = testing::fast_forward(
+            STEP,
+            request_bytes(format!("{url}/games").as_str(), Priority::Normal),
+        )
+        .await;
+        let e = result.unwrap_err();
+        assert!(e.is::<InjectedFault>(), "{e:?}");
+        assert!(e.to_string().contains("after 3 attempts"), "{e}");
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+
+        faults::clear();
+        clock::unfreeze();
+        std::env::remove_var("DEVCADE_REQUEST_ATTEMPTS");
+        std::env::remove_var("DEVCADE_REQUEST_BACKOFF_MS");
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn redirect_chains_are_followed() {
+        let url = testing::serve(|path, _| match path {
+            "/games/pong/icon" => redirect("/cdn/a"),
+            "/cdn/a" => redirect("/cdn/b"),
+            "/cdn/b" => Reply::Respond("200 OK", Vec::new(), b"icon".to_vec()),
+            // Loops forever
+            _ => redirect("/loop"),
+        });
+        let icon = request_bytes(format!("{url}/games/pong/icon").as_str(), Priority::Normal)
+            .await
+            .unwrap();
+        assert_eq!(icon, b"icon");
+        let looping = request_bytes(format!("{url}/loop").as_str(), Priority::Normal).await;
+        assert!(format!("{:?}", looping.unwrap_err()).contains("redirects"));
+    }
+
+    #[tokio::test]
+    async fn expired_signed_urls_are_requested_again_from_the_api() {
+        let signed = Arc::new(AtomicUsize::new(0));
+        let url = {
+            let signed = Arc::clone(&signed);
+            testing::serve(move |path, _| match path {
+                "/games/pong/banner" => {
+                    let signature = signed.fetch_add(1, Ordering::SeqCst);
+                    redirect(format!("/cdn/banner?sig={signature}").as_str())
+                }
+                // The first signature expired before it was used
+                "/cdn/banner?sig=0" => Reply::Respond("403 Forbidden", Vec::new(), Vec::new()),
+                _ => Reply::Respond("200 OK", Vec::new(), b"banner".to_vec()),
+            })
+        };
+        let banner = request_bytes(
+            format!("{url}/games/pong/banner").as_str(),
+            Priority::Normal,
+        )
+        .await
+        .unwrap();
+        assert_eq!(banner, b"banner");
+        // The retry went back to the API for a fresh URL
+        assert_eq!(signed.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn redirects_to_disallowed_hosts_are_refused() {
+        let (_guard, root) = testing::root("network-redirect-hosts");
+        std::env::set_var(
+            "DEVCADE_REDIRECT_HOSTS",
+            "cdn.example.com,*.cdn.example.com",
+        );
+        reload_tls().unwrap();
+        let url = testing::serve(|path, headers| {
+            let host = headers
+                .iter()
+                .find_map(|header| {
+                    header
+                        .to_ascii_lowercase()
+                        .strip_prefix("host: ")
+                        .map(String::from)
+                })
+                .unwrap_or_default();
+            match path {
+                // The same server, under a name that isn't allowed
+                "/games/pong/icon" => redirect(
+                    format!("http://{}/cdn", host.replace("127.0.0.1", "localhost")).as_str(),
+                ),
+                "/games/snake/icon" => redirect("/cdn"),
+                _ => Reply::Respond("200 OK", Vec::new(), b"icon".to_vec()),
+            }
+        });
+
+        let refused =
+            request_bytes(format!("{url}/games/pong/icon").as_str(), Priority::Normal).await;
+        assert!(format!("{:?}", refused.unwrap_err()).contains("disallowed host localhost"));
+        // Redirects back to the API's own host are always allowed
+        let icon = request_bytes(format!("{url}/games/snake/icon").as_str(), Priority::Normal)
+            .await
+            .unwrap();
+        assert_eq!(icon, b"icon");
+        assert!(host_matches("*.cdn.example.com", "eu.cdn.example.com"));
+        assert!(!host_matches("*.cdn.example.com", "cdn.example.com"));
+        assert!(!host_matches("cdn.example.com", "evilcdn.example.com"));
+
+        std::env::remove_var("DEVCADE_REDIRECT_HOSTS");
+        reload_tls().unwrap();
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    fn redirect(location: &str) -> Reply {
+        Reply::Respond(
+            "302 Found",
+            vec![format!("Location: {location}")],
+            Vec::new(),
+        )
+    }
+}
@@ -0,0 +1,438 @@
+use super::launch_verification::skipped;
+use super::{download_jobs, file_hash, game_list_from_fs, read_manifest, Manifest, INSTALLING};
+use crate::clock;
+use crate::env::{object_store, verify_skip_dirs};
+use crate::layout;
+use crate::secrets;
+use crate::state::{self, JsonState};
+use anyhow::Error;
+use devcade_onboard_types::{DownloadPriority, ObjectStoreReport};
+use lazy_static::lazy_static;
+use log::{log, Level};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/**
+ * Seconds in a day
+ */
+const DAY: u64 = 24 * 60 * 60;
+
+/**
+ * How long a file no installed game uses is kept before it's removed, counted from when its last
+ * link went away. An install that's still being staged may be about to use it again.
+ */
+const GRACE: u64 = DAY;
+
+/**
+ * How many files are hashed on each pass to check they're intact. Every file gets checked over
+ * enough nights, without one pass reading the whole store.
+ */
+const SAMPLE: usize = 64;
+
+lazy_static! {
+    static ref MAINTAINED: JsonState<Maintained> = JsonState::new(path);
+}
+
+static LAST_REPORT: Mutex<Option<ObjectStoreReport>> = Mutex::new(None);
+
+/**
+ * When the store was last maintained
+ */
+#[derive(Serialize, Deserialize, Default)]
+struct Maintained {
+    /// The last day the store was maintained during the auto-update window, in days since the Unix
+    /// epoch
+    last_nightly: u64,
+}
+
+/**
+ * The files of installed games by hash, as their manifests list them
+ */
+type References = BTreeMap<String, Vec<(String, PathBuf)>>;
+
+fn path() -> PathBuf {
+    layout::state_dir().join("object_store.json")
+}
+
+fn journal_path() -> PathBuf {
+    layout::state_dir().join("object_store.journal")
+}
+
+/**
+ * Link a file of an install into the store, or replace it with the store's copy if there is one.
+ * Returns whether the store's copy was used.
+ */
+fn link(file: &Path, object: &Path, size: u64) -> std::io::Result<bool> {
+    let stored = match std::fs::metadata(object) {
+        Ok(stored) => stored,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            std::fs::hard_link(file, object)?;
+            return Ok(false);
+        }
+        Err(e) => return Err(e),
+    };
+    let extracted = std::fs::metadata(file)?;
+    // A copy of a different size is corrupted, and is left for maintenance to find
+    if stored.ino() == extracted.ino()
+        || stored.len() != size
+        || stored.permissions().mode() != extracted.permissions().mode()
+    {
+        return Ok(false);
+    }
+    let mut shared = file.as_os_str().to_owned();
+    shared.push(".shared");
+    std::fs::hard_link(object, &shared)?;
+    std::fs::rename(&shared, file)?;
+    Ok(true)
+}
+
+fn share_in(store: &Path, dir: &Path, manifest: &Manifest, skip_dirs: &[String]) -> (usize, u64) {
+    if let Err(e) = std::fs::create_dir_all(store) {
+        log!(Level::Warn, "Couldn't create the object store: {}", e);
+        return (0, 0);
+    }
+    let mut shared = (0, 0);
+    for (name, entry) in manifest {
+        if entry.size == 0 || skipped(name, skip_dirs) {
+            continue;
+        }
+        let file = dir.join(name);
+        match link(&file, store.join(entry.hash.as_str()).as_path(), entry.size) {
+            Ok(true) => {
+                shared.0 += 1;
+                shared.1 += entry.size;
+            }
+            Ok(false) => {}
+            Err(e) => log!(Level::Debug, "Couldn't share {}: {}", file.display(), e),
+        }
+    }
+    shared
+}
+
+/**
+ * Share the files of an install extracted to `dir` with other installs through the store, if
+ * `DEVCADE_OBJECT_STORE` is on. Files another install already has are replaced with links to its
+ * copy, and the rest are added to the store. Returns how many files were replaced, and their size
+ * in bytes.
+ */
+pub fn share(dir: &Path, manifest: &Manifest) -> (usize, u64) {
+    if !object_store() {
+        return (0, 0);
+    }
+    share_in(
+        layout::objects_dir().as_path(),
+        dir,
+        manifest,
+        &verify_skip_dirs(),
+    )
+}
+
+/**
+ * List the files every installed game uses by hash, from their manifests
+ */
+fn references() -> Result<References, Error> {
+    let mut references = References::new();
+    for game in game_list_from_fs()? {
+        let dir = layout::game_dir(game.id.as_str());
+        for (name, entry) in read_manifest(dir.as_path()).unwrap_or_default() {
+            references
+                .entry(entry.hash)
+                .or_default()
+                .push((game.id.clone(), dir.join(name)));
+        }
+    }
+    Ok(references)
+}
+
+/**
+ * Pick up to `count` items at random, moving them to the front
+ */
+fn sample<T>(items: &mut [T], count: usize, mut seed: u64) -> &[T] {
+    let count = count.min(items.len());
+    for i in 0..count {
+        // xorshift, which is random enough to spread checks over the store
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        let remaining = (items.len() - i) as u64;
+        items.swap(i, i + (seed % remaining) as usize);
+    }
+    &items[..count]
+}
+
+/**
+ * Remove unused files from `store` and check a sample of the rest. Returns the report, and the
+ * files of installed games that turned out corrupted, by game ID.
+ */
+fn maintain_in(
+    store: &Path,
+    references: &References,
+    now: u64,
+    seed: u64,
+) -> Result<(ObjectStoreReport, BTreeMap<String, Vec<PathBuf>>), Error> {
+    let mut report = ObjectStoreReport {
+        at: now,
+        ..ObjectStoreReport::default()
+    };
+    let mut used = Vec::new();
+    for entry in std::fs::read_dir(store)? {
+        let entry = entry?;
+        let meta = entry.metadata()?;
+        if !meta.is_file() {
+            continue;
+        }
+        let hash = entry.file_name().to_string_lossy().into_owned();
+        if references.contains_key(hash.as_str()) {
+            used.push((hash, entry.path(), meta.len()));
+        } else if now.saturating_sub(u64::try_from(meta.ctime()).unwrap_or(0)) >= GRACE {
+            std::fs::remove_file(entry.path())?;
+            report.reclaimed_objects += 1;
+            report.reclaimed_bytes += meta.len();
+            continue;
+        }
+        report.objects += 1;
+        report.bytes += meta.len();
+    }
+
+    let mut damaged: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+    for (hash, object, size) in sample(&mut used, SAMPLE, seed) {
+        report.sampled += 1;
+        match file_hash(object) {
+            Ok(actual) if actual == *hash => continue,
+            Ok(_) => {}
+            Err(e) => {
+                log!(Level::Warn, "Couldn't check {}: {}", object.display(), e);
+                continue;
+            }
+        }
+        log!(Level::Error, "Object {} is corrupted", hash);
+        report.corrupted.push(hash.clone());
+        // Installs still linked to it are just as damaged, so those files are removed too, and a
+        // repair can't link them into the new install
+        let ino = std::fs::metadata(object)?.ino();
+        std::fs::remove_file(object)?;
+        report.objects -= 1;
+        report.bytes -= size;
+        for (game_id, file) in &references[hash] {
+            if std::fs::metadata(file).map_or(true, |meta| meta.ino() != ino) {
+                continue;
+            }
+            if let Err(e) = std::fs::remove_file(file) {
+                log!(Level::Warn, "Couldn't remove {}: {}", file.display(), e);
+            }
+            damaged
+                .entry(game_id.clone())
+                .or_default()
+                .push(file.clone());
+        }
+    }
+    report.repaired = damaged.keys().cloned().collect();
+    Ok((report, damaged))
+}
+
+/**
+ * Remove files no installed game uses from the store, once they've gone unused for a day, and
+ * hash a random sample of the rest to catch corruption. Games that used a corrupted file are
+ * downloaded again. The report is journaled and kept for the cabinet's status.
+ *
+ * # Errors
+ * This function will return an error if the installed games or the store can't be read.
+ */
+pub async fn maintain() -> Result<ObjectStoreReport, Error> {
+    // Installs can't be swapped in partway through, which would change what's used
+    let installing = INSTALLING.read().await;
+    let seed = secrets::random_hex(8)
+        .ok()
+        .and_then(|hex| u64::from_str_radix(hex.as_str(), 16).ok())
+        .unwrap_or_else(clock::unix_now)
+        | 1;
+    let (report, damaged) = tokio::task::spawn_blocking(move || {
+        let store = layout::objects_dir();
+        if !store.exists() {
+            return Ok((
+                ObjectStoreReport {
+                    at: clock::unix_now(),
+                    ..ObjectStoreReport::default()
+                },
+                BTreeMap::new(),
+            ));
+        }
+        maintain_in(store.as_path(), &references()?, clock::unix_now(), seed)
+    })
+    .await??;
+    drop(installing);
+
+    for (game_id, files) in &damaged {
+        log!(
+            Level::Warn,
+            "Game {} has {} corrupted files, downloading it again",
+            game_id,
+            files.len()
+        );
+        download_jobs::enqueue(game_id.as_str(), DownloadPriority::Background);
+    }
+    state::journal(journal_path().as_path(), &report)?;
+    log!(
+        Level::Info,
+        "Maintained object store: removed {} unused files ({} bytes), checked {}, {} corrupted",
+        report.reclaimed_objects,
+        report.reclaimed_bytes,
+        report.sampled,
+        report.corrupted.len()
+    );
+    *LAST_REPORT.lock().unwrap() = Some(report.clone());
+    Ok(report)
+}
+
+/**
+ * Maintain the store if it wasn't already today, for the auto-update window. A store left behind
+ * after `DEVCADE_OBJECT_STORE` was turned off is still maintained, so it empties as games are
+ * removed.
+ */
+pub async fn nightly() {
+    if !object_store() && !layout::objects_dir().exists() {
+        return;
+    }
+    let today = clock::unix_now() / DAY;
+    if MAINTAINED.lock().last_nightly == today {
+        return;
+    }
+    if let Err(e) = maintain().await {
+        log!(Level::Warn, "Couldn't maintain the object store: {}", e);
+        return;
+    }
+    let mut maintained = MAINTAINED.lock();
+    maintained.last_nightly = today;
+    if let Err(e) = maintained.save() {
+        log!(
+            Level::Warn,
+            "Couldn't save when the object store was maintained: {}",
+            e
+        );
+    }
+}
+
+/**
+ * Get what the last maintenance found, or `None` if it hasn't run since startup
+ */
+pub fn last_report() -> Option<ObjectStoreReport> {
+    LAST_REPORT.lock().unwrap().clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::extract::{to_hex, ManifestEntry};
+    use super::*;
+
+    fn entry(contents: &[u8]) -> ManifestEntry {
+        use sha2::{Digest, Sha256};
+
+        ManifestEntry {
+            hash: to_hex(&Sha256::digest(contents)),
+            size: contents.len() as u64,
+        }
+    }
+
+    fn install(dir: &Path, files: &[(&str, &[u8])]) -> Manifest {
+        files
+            .iter()
+            .map(|(name, contents)| {
+                let file = dir.join(name);
+                std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+                std::fs::write(&file, contents).unwrap();
+                ((*name).to_string(), entry(contents))
+            })
+            .collect()
+    }
+
+    fn references_of(installs: &[(&str, &Path, &Manifest)]) -> References {
+        let mut references = References::new();
+        for (game_id, dir, manifest) in installs {
+            for (name, entry) in *manifest {
+                references
+                    .entry(entry.hash.clone())
+                    .or_default()
+                    .push(((*game_id).to_string(), dir.join(name)));
+            }
+        }
+        references
+    }
+
+    #[test]
+    fn identical_files_are_shared_except_writable_ones() {
+        let root = std::env::temp_dir().join(format!("devcade-objects-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let store = root.join("objects");
+        let skip = vec!["data".to_string()];
+        let files: &[(&str, &[u8])] = &[
+            ("publish/engine.dll", b"engine"),
+            ("publish/data/save.ini", b"engine"),
+        ];
+        let pong = install(&root.join("pong"), files);
+        let snake = install(&root.join("snake"), files);
+
+        assert_eq!(share_in(&store, &root.join("pong"), &pong, &skip), (0, 0));
+        assert_eq!(share_in(&store, &root.join("snake"), &snake, &skip), (1, 6));
+        let ino = |path: &str| std::fs::metadata(root.join(path)).unwrap().ino();
+        assert_eq!(
+            ino("pong/publish/engine.dll"),
+            ino("snake/publish/engine.dll")
+        );
+        assert_ne!(
+            ino("pong/publish/data/save.ini"),
+            ino("snake/publish/data/save.ini")
+        );
+        assert_eq!(std::fs::read_dir(&store).unwrap().count(), 1);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn unused_files_are_swept_and_corrupted_ones_repaired() {
+        let root = std::env::temp_dir().join(format!("devcade-sweep-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        let store = root.join("objects");
+        let pong_dir = root.join("pong");
+        let pong = install(&pong_dir, &[("publish/pong", b"pong"), ("publish/a", b"a")]);
+        let snake_dir = root.join("snake");
+        let snake = install(&snake_dir, &[("publish/snake", b"snake")]);
+        share_in(&store, &pong_dir, &pong, &[]);
+        share_in(&store, &snake_dir, &snake, &[]);
+        let now = clock::unix_now();
+
+        // Snake was removed. Its file stays through the grace period.
+        std::fs::remove_dir_all(&snake_dir).unwrap();
+        let references = references_of(&[("pong", &pong_dir, &pong)]);
+        let (report, damaged) = maintain_in(&store, &references, now, 1).unwrap();
+        assert_eq!((report.objects, report.reclaimed_objects), (3, 0));
+        assert_eq!(report.sampled, 2);
+        assert!(report.corrupted.is_empty() && damaged.is_empty());
+
+        let (report, _) = maintain_in(&store, &references, now + GRACE, 1).unwrap();
+        assert_eq!((report.objects, report.bytes), (2, 5));
+        assert_eq!((report.reclaimed_objects, report.reclaimed_bytes), (1, 5));
+
+        // Flipped bits in pong's executable
+        std::fs::write(pong_dir.join("publish/pong"), b"pixg").unwrap();
+        let (report, damaged) = maintain_in(&store, &references, now + GRACE, 1).unwrap();
+        assert_eq!(report.corrupted, vec![pong["publish/pong"].hash.clone()]);
+        assert_eq!(report.repaired, vec!["pong".to_string()]);
+        assert_eq!(damaged["pong"], vec![pong_dir.join("publish/pong")]);
+        assert!(!pong_dir.join("publish/pong").exists());
+        assert!(pong_dir.join("publish/a").exists());
+        assert_eq!(report.objects, 1);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn samples_are_distinct() {
+        let mut items: Vec<u32> = (0..100).collect();
+        let mut picked = sample(&mut items, 10, 12345).to_vec();
+        picked.sort_unstable();
+        picked.dedup();
+        assert_eq!(picked.len(), 10);
+        assert_eq!(sample(&mut items, 500, 1).len(), 100);
+    }
+}
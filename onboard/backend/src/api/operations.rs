@@ -0,0 +1,419 @@
+use crate::clock;
+use crate::layout;
+use crate::state;
+use anyhow::Error;
+use devcade_onboard_types::{
+    Arbitration, DownloadPriority, GameIntent, GameOperation, OperationDecision, OperationOrigin,
+};
+use lazy_static::lazy_static;
+use log::{log, Level};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use tokio::sync::Notify;
+
+/**
+ * How many decisions are remembered for the download queue's state
+ */
+const MAX_DECISIONS: usize = 32;
+
+#[derive(Default)]
+struct Registry {
+    next_id: u64,
+    /// The operations in progress, with their IDs, by game ID
+    active: HashMap<String, Vec<(u64, GameOperation)>>,
+    decisions: VecDeque<OperationDecision>,
+}
+
+lazy_static! {
+    static ref REGISTRY: Mutex<Registry> = Mutex::new(Registry::default());
+    // Notified whenever an operation finishes
+    static ref FINISHED: Notify = Notify::new();
+}
+
+/**
+ * The error an operation fails with when the arbiter refuses it
+ */
+#[derive(Debug, Clone)]
+pub struct OperationRejected(pub OperationDecision);
+
+impl fmt::Display for OperationRejected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "OperationRejected: {}", self.0)
+    }
+}
+
+impl std::error::Error for OperationRejected {}
+
+/**
+ * An operation in progress, which finishes when this is dropped
+ */
+#[must_use]
+pub struct Ticket {
+    game_id: String,
+    id: u64,
+}
+
+impl Drop for Ticket {
+    fn drop(&mut self) {
+        let mut registry = REGISTRY.lock().unwrap();
+        if let Some(active) = registry.active.get_mut(&self.game_id) {
+            active.retain(|(id, _)| *id != self.id);
+            if active.is_empty() {
+                registry.active.remove(&self.game_id);
+            }
+        }
+        drop(registry);
+        FINISHED.notify_waiters();
+    }
+}
+
+/**
+ * What an operation that was let through should do
+ */
+#[must_use]
+pub enum Admission {
+    /// Go ahead, holding the ticket until it's done
+    Run(Ticket),
+    /// Nothing, the operation in progress did it or is doing it
+    Joined,
+}
+
+/**
+ * Who's waiting on a game download of a priority
+ */
+pub fn origin(priority: DownloadPriority) -> OperationOrigin {
+    match priority {
+        DownloadPriority::Interactive => OperationOrigin::Launch,
+        DownloadPriority::Normal => OperationOrigin::Manual,
+        DownloadPriority::Background => OperationOrigin::Background,
+    }
+}
+
+/**
+ * Decide what happens to an operation requested on a game another operation is in progress on.
+ * `None` means the two don't conflict and both go ahead.
+ *
+ * | requested \ in progress | install / update             | remove | verify |
+ * |-------------------------|------------------------------|--------|--------|
+ * | install / update        | supersede if more urgent,    | queue  | -      |
+ * |                         | otherwise queue              |        |        |
+ * | remove                  | supersede if background,     | join   | queue  |
+ * |                         | otherwise reject             |        |        |
+ * | verify                  | -                            | reject | -      |
+ *
+ * A more urgent install or update takes over the download in progress at its own priority,
+ * instead of downloading the game twice. A queued one runs once the other is done, and finds the
+ * game current unless it changed again in between. A removal cancels a background download, but
+ * never one a player or operator is waiting on. Verifying doesn't wait for a download, since the
+ * install isn't replaced while a launch holds it.
+ */
+pub fn decide(active: GameOperation, requested: GameOperation) -> Option<Arbitration> {
+    use GameIntent::*;
+    Some(match (requested.intent, active.intent) {
+        (Install | Update, Install | Update) if requested.origin < active.origin => {
+            Arbitration::Supersede
+        }
+        (Install | Update, Install | Update | Remove) => Arbitration::Queue,
+        (Remove, Install | Update) if active.origin == OperationOrigin::Background => {
+            Arbitration::Supersede
+        }
+        (Remove, Install | Update) | (Verify, Remove) => Arbitration::Reject,
+        (Remove, Remove) => Arbitration::Join,
+        (Remove, Verify) => Arbitration::Queue,
+        (Install | Update | Verify, Verify) | (Verify, Install | Update) => return None,
+    })
+}
+
+fn journal_path() -> PathBuf {
+    layout::state_dir().join("operations.journal")
+}
+
+fn record(registry: &mut Registry, decision: OperationDecision) {
+    log!(Level::Info, "{}", decision);
+    if let Err(e) = state::journal(journal_path().as_path(), &decision) {
+        log!(Level::Warn, "Couldn't journal operation decision: {}", e);
+    }
+    registry.decisions.push_back(decision);
+    if registry.decisions.len() > MAX_DECISIONS {
+        registry.decisions.pop_front();
+    }
+}
+
+fn start(registry: &mut Registry, game_id: &str, operation: GameOperation) -> Ticket {
+    registry.next_id += 1;
+    let id = registry.next_id;
+    registry
+        .active
+        .entry(game_id.to_string())
+        .or_default()
+        .push((id, operation));
+    Ticket {
+        game_id: game_id.to_string(),
+        id,
+    }
+}
+
+/**
+ * Declare an operation on a game, waiting out the ones it has to queue behind. Conflicts are
+ * decided by `decide`, logged, journaled, and kept for the download queue's state. A superseded
+ * download is either taken over, which joins it, or cancelled before going ahead. A joined removal
+ * returns once the one in progress is done.
+ *
+ * Don't call this holding `INSTALLING`, since a download in progress may be waiting for it.
+ *
+ * # Errors
+ * This function will return an `OperationRejected` error if the operation conflicts with one that
+ * can't be interrupted.
+ */
+pub async fn admit(game_id: &str, requested: GameOperation) -> Result<Admission, Error> {
+    let mut recorded = false;
+    let mut joining = false;
+    loop {
+        // Registered before checking, so an operation finishing in between isn't missed
+        let finished = FINISHED.notified();
+        tokio::pin!(finished);
+        finished.as_mut().enable();
+        let mut cancel = false;
+        let mut wait = false;
+        {
+            let mut registry = REGISTRY.lock().unwrap();
+            let active = registry.active.get(game_id).cloned().unwrap_or_default();
+            let removing = active
+                .iter()
+                .any(|(_, operation)| operation.intent == GameIntent::Remove);
+            if joining && !removing {
+                return Ok(Admission::Joined);
+            }
+            let mut joined = false;
+            for (id, operation) in active {
+                let Some(arbitration) = decide(operation, requested) else {
+                    continue;
+                };
+                let decision = OperationDecision {
+                    at: clock::unix_now(),
+                    game_id: game_id.to_string(),
+                    requested,
+                    active: operation,
+                    arbitration,
+                };
+                match arbitration {
+                    Arbitration::Reject => {
+                        record(&mut registry, decision.clone());
+                        return Err(OperationRejected(decision).into());
+                    }
+                    Arbitration::Supersede if requested.intent != GameIntent::Remove => {
+                        // Taken over, so it's as urgent as the operation waiting on it now
+                        if let Some((_, taken)) = registry
+                            .active
+                            .get_mut(game_id)
+                            .and_then(|active| active.iter_mut().find(|(other, _)| *other == id))
+                        {
+                            taken.origin = requested.origin;
+                        }
+                        joined = true;
+                    }
+                    Arbitration::Supersede => cancel = true,
+                    Arbitration::Join => {
+                        joining = true;
+                        wait = true;
+                    }
+                    Arbitration::Queue => wait = true,
+                }
+                if !recorded {
+                    record(&mut registry, decision);
+                }
+            }
+            recorded = true;
+            if joined {
+                return Ok(Admission::Joined);
+            }
+            if !cancel && !wait {
+                return Ok(Admission::Run(start(&mut registry, game_id, requested)));
+            }
+        }
+        if cancel {
+            let _ = super::cancel_download(game_id);
+        }
+        finished.await;
+    }
+}
+
+/**
+ * Declare an operation on a game only if nothing else is in progress on it, for callers that hold
+ * `INSTALLING` and so can't wait. Returns `None` if something is.
+ */
+pub fn claim(game_id: &str, operation: GameOperation) -> Option<Ticket> {
+    let mut registry = REGISTRY.lock().unwrap();
+    if registry.active.contains_key(game_id) {
+        return None;
+    }
+    Some(start(&mut registry, game_id, operation))
+}
+
+/**
+ * Get the most recent conflicts between operations, oldest first
+ */
+pub fn decisions() -> Vec<OperationDecision> {
+    REGISTRY.lock().unwrap().decisions.iter().cloned().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INTENTS: [GameIntent; 4] = [
+        GameIntent::Install,
+        GameIntent::Update,
+        GameIntent::Remove,
+        GameIntent::Verify,
+    ];
+    const ORIGINS: [OperationOrigin; 3] = [
+        OperationOrigin::Launch,
+        OperationOrigin::Manual,
+        OperationOrigin::Background,
+    ];
+
+    fn op(intent: GameIntent, origin: OperationOrigin) -> GameOperation {
+        GameOperation { intent, origin }
+    }
+
+    /**
+     * The decision table, written out by intent, with what depends on who's waiting
+     */
+    fn expected(active: GameOperation, requested: GameOperation) -> Option<Arbitration> {
+        use GameIntent::*;
+        let downloading = |intent| matches!(intent, Install | Update);
+        if downloading(requested.intent) && downloading(active.intent) {
+            return Some(if requested.origin < active.origin {
+                Arbitration::Supersede
+            } else {
+                Arbitration::Queue
+            });
+        }
+        if requested.intent == Remove && downloading(active.intent) {
+            return Some(if active.origin == OperationOrigin::Background {
+                Arbitration::Supersede
+            } else {
+                Arbitration::Reject
+            });
+        }
+        let table = [
+            (Install, Remove, Some(Arbitration::Queue)),
+            (Update, Remove, Some(Arbitration::Queue)),
+            (Install, Verify, None),
+            (Update, Verify, None),
+            (Remove, Remove, Some(Arbitration::Join)),
+            (Remove, Verify, Some(Arbitration::Queue)),
+            (Verify, Install, None),
+            (Verify, Update, None),
+            (Verify, Remove, Some(Arbitration::Reject)),
+            (Verify, Verify, None),
+        ];
+        table
+            .iter()
+            .find(|(req, act, _)| *req == requested.intent && *act == active.intent)
+            .map(|(_, _, arbitration)| *arbitration)
+            .unwrap()
+    }
+
+    #[test]
+    fn every_pair_of_operations_is_decided_by_the_table() {
+        for active_intent in INTENTS {
+            for active_origin in ORIGINS {
+                for requested_intent in INTENTS {
+                    for requested_origin in ORIGINS {
+                        let active = op(active_intent, active_origin);
+                        let requested = op(requested_intent, requested_origin);
+                        assert_eq!(
+                            decide(active, requested),
+                            expected(active, requested),
+                            "{requested} requested during {active}"
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn documented_conflicts() {
+        use GameIntent::*;
+        use OperationOrigin::*;
+        // Update after update
+        assert_eq!(
+            decide(op(Update, Background), op(Update, Background)),
+            Some(Arbitration::Queue)
+        );
+        // A reinstall takes over a queued auto-update
+        assert_eq!(
+            decide(op(Update, Background), op(Install, Manual)),
+            Some(Arbitration::Supersede)
+        );
+        // An auto-update waits for a manual one
+        assert_eq!(
+            decide(op(Update, Manual), op(Update, Background)),
+            Some(Arbitration::Queue)
+        );
+        // A game a player is waiting on isn't removed from under them
+        assert_eq!(
+            decide(op(Install, Launch), op(Remove, Background)),
+            Some(Arbitration::Reject)
+        );
+        assert_eq!(
+            decide(op(Update, Background), op(Remove, Background)),
+            Some(Arbitration::Supersede)
+        );
+        assert_eq!(
+            decide(op(Remove, Background), op(Verify, Launch)),
+            Some(Arbitration::Reject)
+        );
+        assert_eq!(
+            decide(op(Remove, Background), op(Install, Launch)),
+            Some(Arbitration::Queue)
+        );
+    }
+
+    #[test]
+    fn decisions_read_the_way_operators_see_them() {
+        let decision = OperationDecision {
+            at: 0,
+            game_id: "pong".to_string(),
+            requested: op(GameIntent::Install, OperationOrigin::Manual),
+            active: op(GameIntent::Update, OperationOrigin::Background),
+            arbitration: Arbitration::Supersede,
+        };
+        assert_eq!(
+            decision.to_string(),
+            "background update of pong superseded by manual install"
+        );
+    }
+
+    #[tokio::test]
+    async fn queued_operations_wait_for_the_one_in_progress() {
+        let game_id = "operations-test-queue";
+        let removal = op(GameIntent::Remove, OperationOrigin::Background);
+        let Admission::Run(first) = admit(game_id, removal).await.unwrap() else {
+            panic!("nothing was in progress");
+        };
+        assert!(claim(game_id, removal).is_none());
+
+        let verify = op(GameIntent::Verify, OperationOrigin::Launch);
+        let Err(rejected) = admit(game_id, verify).await else {
+            panic!("verified a game that's being removed");
+        };
+        assert!(rejected.downcast_ref::<OperationRejected>().is_some());
+
+        let install = op(GameIntent::Install, OperationOrigin::Launch);
+        let queued = tokio::spawn(async move { admit(game_id, install).await.is_ok() });
+        tokio::task::yield_now().await;
+        assert!(!queued.is_finished());
+        drop(first);
+        assert!(queued.await.unwrap());
+        assert!(decisions()
+            .iter()
+            .any(|decision| decision.game_id == game_id
+                && decision.arbitration == Arbitration::Queue));
+    }
+}
@@ -0,0 +1,362 @@
+use super::signature::decode_hex;
+use crate::clock;
+use crate::env::{peer_addr, peer_listen};
+use crate::layout;
+use crate::secrets::{self, PEER_KEY};
+use crate::servers::{Frame, FrameReader};
+use crate::state::JsonState;
+use anyhow::{anyhow, Error};
+use devcade_onboard_types::{Dialect, PeerLink, PeerPlayer, PeerStatus};
+use hmac::Mac;
+use lazy_static::lazy_static;
+use log::{log, Level};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, VecDeque};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream};
+
+/**
+ * How often the status is sent to the peer
+ */
+const EXCHANGE_EVERY: Duration = Duration::from_secs(10);
+
+/**
+ * How long one exchange with the peer may take
+ */
+const EXCHANGE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/**
+ * How many seconds old a status can be before it's ignored. This also stops a recorded status from
+ * being replayed to the peer later.
+ */
+const MAX_AGE: u64 = 60;
+
+/**
+ * The most players kept as tapped in. The most recent taps are kept.
+ */
+const MAX_PLAYERS: usize = 4;
+
+/**
+ * The last status the peer sent, where it came from, and when
+ */
+struct Heard {
+    status: PeerStatus,
+    host: IpAddr,
+    at: u64,
+}
+
+/**
+ * A status signed with the peer key. The status is kept as the JSON that was signed.
+ */
+#[derive(Serialize, Deserialize)]
+struct Signed {
+    status: String,
+    signature: String,
+}
+
+lazy_static! {
+    static ref HEARD: Mutex<Option<Heard>> = Mutex::new(None);
+    // Association IDs of the players tapped in since the last game exited, oldest first. They're
+    // only kept in memory.
+    static ref PLAYERS: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+    // The running game, and the rendezvous token of its session
+    static ref SESSION: Mutex<Option<(String, String)>> = Mutex::new(None);
+    // Keys (see `consent_key`) of the players who agreed to share their association ID
+    static ref CONSENT: JsonState<BTreeSet<String>> = JsonState::new(consent_path);
+}
+
+fn consent_path() -> PathBuf {
+    layout::state_dir().join("peer_consent.json")
+}
+
+/**
+ * Whether the peer link is configured
+ */
+#[must_use]
+pub fn enabled() -> bool {
+    peer_addr().is_some() || peer_listen().is_some()
+}
+
+fn key() -> Result<String, Error> {
+    secrets::get(PEER_KEY, "the peer link")
+}
+
+/**
+ * Identify a player's consent without keeping their association ID on disk. Consent is given again
+ * after the peer key changes.
+ */
+fn consent_key(key: &str, association_id: &str) -> String {
+    let mut mac = secrets::mac(key);
+    mac.update(b"consent:");
+    mac.update(association_id.as_bytes());
+    secrets::hex(&mac.finalize().into_bytes())
+}
+
+/**
+ * Record whether a player agrees to the peer seeing their association ID while they're tapped in
+ *
+ * # Errors
+ * This function will return an error if the peer key isn't set, or if the consent can't be saved.
+ */
+pub fn set_consent(association_id: &str, consent: bool) -> Result<(), Error> {
+    let key = consent_key(key()?.as_str(), association_id);
+    let mut consented = CONSENT.lock();
+    let changed = if consent {
+        consented.insert(key)
+    } else {
+        consented.remove(&key)
+    };
+    if changed {
+        consented.save()?;
+    }
+    Ok(())
+}
+
+/**
+ * Record that a player tapped in, so the peer knows someone is playing
+ */
+pub fn tapped_in(association_id: &str) {
+    if !enabled() {
+        return;
+    }
+    let mut players = PLAYERS.lock().unwrap();
+    players.retain(|id| id != association_id);
+    players.push_back(association_id.to_string());
+    while players.len() > MAX_PLAYERS {
+        players.pop_front();
+    }
+}
+
+/**
+ * Start a game's session with a new rendezvous token, and get the environment variables to launch
+ * it with. The peer's details are only given to the game if the peer is running the same game.
+ */
+pub fn game_started(game_id: &str) -> Vec<(&'static str, String)> {
+    if !enabled() {
+        return Vec::new();
+    }
+    let token = match secrets::random_hex(16) {
+        Ok(token) => token,
+        Err(e) => {
+            log!(Level::Warn, "Couldn't generate a rendezvous token: {}", e);
+            return Vec::new();
+        }
+    };
+    *SESSION.lock().unwrap() = Some((game_id.to_string(), token.clone()));
+
+    let mut env = vec![("DEVCADE_PEER_LOCAL_TOKEN", token)];
+    if let Some((status, host)) = fresh() {
+        if status.running_game.as_deref() == Some(game_id) {
+            env.push(("DEVCADE_PEER_HOST", host.to_string()));
+            if let Some(token) = status.rendezvous {
+                env.push(("DEVCADE_PEER_TOKEN", token));
+            }
+            let players: Vec<String> = status
+                .players
+                .into_iter()
+                .filter_map(|player| player.association_id)
+                .collect();
+            env.push(("DEVCADE_PEER_PLAYERS", players.join(",")));
+        }
+    }
+    env
+}
+
+/**
+ * End the running game's session. Players tap in again for the next game.
+ */
+pub fn game_exited() {
+    *SESSION.lock().unwrap() = None;
+    PLAYERS.lock().unwrap().clear();
+}
+
+/**
+ * Get the peer's last status and where it came from, if it's recent enough to go by
+ */
+fn fresh() -> Option<(PeerStatus, IpAddr)> {
+    let heard = HEARD.lock().unwrap();
+    let heard = heard.as_ref()?;
+    (clock::unix_now().saturating_sub(heard.at) <= MAX_AGE)
+        .then(|| (heard.status.clone(), heard.host))
+}
+
+/**
+ * Get what's known about the peer, or `None` if the peer link is off
+ */
+#[must_use]
+pub fn link() -> Option<PeerLink> {
+    if !enabled() {
+        return None;
+    }
+    let last_seen = HEARD.lock().unwrap().as_ref().map(|heard| heard.at);
+    Some(PeerLink {
+        status: fresh().map(|(status, _)| status),
+        last_seen,
+    })
+}
+
+/**
+ * Get this cabinet's status to send to the peer
+ */
+fn status(key: &str) -> PeerStatus {
+    let consented = CONSENT.lock();
+    let players = PLAYERS
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|id| {
+            let consent = consented.contains(&consent_key(key, id));
+            PeerPlayer {
+                association_id: consent.then(|| id.clone()),
+                consent,
+            }
+        })
+        .collect();
+    let session = SESSION.lock().unwrap().clone();
+    PeerStatus {
+        running_game: session.as_ref().map(|(game_id, _)| game_id.clone()),
+        players,
+        rendezvous: session.map(|(_, token)| token),
+        sent: clock::unix_now(),
+    }
+}
+
+/**
+ * Get this cabinet's status as a signed frame
+ */
+fn signed_status(key: &str) -> Result<Vec<u8>, Error> {
+    let status = serde_json::to_string(&status(key))?;
+    let mut mac = secrets::mac(key);
+    mac.update(status.as_bytes());
+    let signature = secrets::hex(&mac.finalize().into_bytes());
+    Dialect::Framed.frame(&Signed { status, signature })
+}
+
+/**
+ * Check the signature and age of a status from the peer, and remember it
+ */
+fn receive(key: &str, frame: Option<Frame>, host: IpAddr) -> Result<(), Error> {
+    let Some(Frame::Complete(line)) = frame else {
+        return Err(anyhow!("Peer at {host} didn't send a status"));
+    };
+    let signed: Signed = serde_json::from_str(line.as_str())?;
+    let mut mac = secrets::mac(key);
+    mac.update(signed.status.as_bytes());
+    decode_hex(signed.signature.as_str())
+        .and_then(|signature| mac.verify_slice(&signature).ok())
+        .ok_or_else(|| {
+            anyhow!("Status from {host} has an invalid signature, is peer_key the same on both?")
+        })?;
+
+    let status: PeerStatus = serde_json::from_str(signed.status.as_str())?;
+    let now = clock::unix_now();
+    if now.abs_diff(status.sent) > MAX_AGE {
+        return Err(anyhow!(
+            "Status from {host} was sent at {}, too long before {now}",
+            status.sent
+        ));
+    }
+    *HEARD.lock().unwrap() = Some(Heard {
+        status,
+        host,
+        at: now,
+    });
+    Ok(())
+}
+
+/**
+ * Send this cabinet's status to the peer, and get the peer's back
+ */
+async fn exchange(addr: &str) -> Result<(), Error> {
+    let key = key()?;
+    let stream = TcpStream::connect(addr).await?;
+    let host = stream.peer_addr()?.ip();
+    let (reader, mut writer) = stream.into_split();
+    writer.write_all(&signed_status(key.as_str())?).await?;
+    let frame = FrameReader::new(reader).next_frame().await?;
+    receive(key.as_str(), frame, host)
+}
+
+/**
+ * Answer the peer's status with this cabinet's. Nothing is sent back unless the peer's status
+ * checks out, so only a cabinet with the key can see it.
+ */
+async fn answer(stream: TcpStream) -> Result<(), Error> {
+    let key = key()?;
+    let host = stream.peer_addr()?.ip();
+    let (reader, mut writer) = stream.into_split();
+    let frame = FrameReader::new(reader).next_frame().await?;
+    receive(key.as_str(), frame, host)?;
+    writer.write_all(&signed_status(key.as_str())?).await?;
+    Ok(())
+}
+
+/**
+ * Accept statuses from the peer on `DEVCADE_PEER_LISTEN`
+ */
+async fn listen(addr: String) {
+    let listener = match TcpListener::bind(addr.as_str()).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            log!(Level::Error, "Couldn't listen for peer on {}: {}", addr, e);
+            return;
+        }
+    };
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                log!(Level::Warn, "Couldn't accept peer connection: {}", e);
+                clock::sleep(EXCHANGE_TIMEOUT).await;
+                continue;
+            }
+        };
+        tokio::spawn(async move {
+            let result = match clock::timeout(EXCHANGE_TIMEOUT, answer(stream)).await {
+                Ok(result) => result,
+                Err(e) => Err(e.into()),
+            };
+            if let Err(e) = result {
+                log!(Level::Warn, "Rejected peer connection: {}", e);
+            }
+        });
+    }
+}
+
+/**
+ * Exchange statuses with the peer until the backend stops. Does nothing unless the peer link is
+ * configured. The peer being down is only logged when it goes down and comes back.
+ */
+pub async fn watch() {
+    if let Some(addr) = peer_listen() {
+        tokio::spawn(listen(addr));
+    }
+    let Some(addr) = peer_addr() else {
+        return;
+    };
+    let mut interval = clock::interval(EXCHANGE_EVERY);
+    let mut reachable = true;
+    loop {
+        let result = match clock::timeout(EXCHANGE_TIMEOUT, exchange(addr.as_str())).await {
+            Ok(result) => result,
+            Err(e) => Err(e.into()),
+        };
+        match result {
+            Ok(()) if !reachable => {
+                log!(Level::Info, "Peer at {} is reachable again", addr);
+                reachable = true;
+            }
+            Ok(()) => {}
+            Err(e) if reachable => {
+                log!(Level::Warn, "Couldn't exchange status with peer: {}", e);
+                reachable = false;
+            }
+            Err(e) => log!(Level::Debug, "Peer still unreachable: {}", e),
+        }
+        interval.tick().await;
+    }
+}
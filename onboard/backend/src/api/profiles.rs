@@ -0,0 +1,361 @@
+use super::{accessibility, cabinet_settings};
+use crate::layout;
+use crate::logging;
+use anyhow::{anyhow, Error};
+use devcade_onboard_types::schema::AccessibilityFlag;
+use log::{log, Level};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+const MAX_NAME_LENGTH: usize = 64;
+
+/**
+ * A profile as it's saved
+ */
+#[derive(Serialize, Deserialize, Default)]
+struct Profile {
+    #[serde(default)]
+    log_levels: Vec<LogLevel>,
+    /// `None` leaves `DEVCADE_REQUIRED_ACCESSIBILITY` in charge
+    #[serde(default)]
+    required_accessibility: Option<Vec<AccessibilityFlag>>,
+    #[serde(default)]
+    cabinet_settings: BTreeMap<String, String>,
+}
+
+/**
+ * A log level override in a profile. Overrides applied from a profile get the default expiry.
+ */
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+struct LogLevel {
+    target: Option<String>,
+    level: String,
+}
+
+fn dir() -> PathBuf {
+    layout::state_dir().join("profiles")
+}
+
+/**
+ * Get the file a profile is kept in, checking that the name is made of letters, digits, `_`
+ * and `-`, so it can't point outside of the profiles directory
+ */
+fn path(name: &str) -> Result<PathBuf, Error> {
+    let valid = !name.is_empty()
+        && name.len() <= MAX_NAME_LENGTH
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-'));
+    if !valid {
+        return Err(anyhow!(
+            "Invalid profile name '{name}', expected up to {MAX_NAME_LENGTH} letters, digits, \
+            '_' or '-'"
+        ));
+    }
+    Ok(dir().join(format!("{name}.json")))
+}
+
+fn read(name: &str) -> Result<Profile, Error> {
+    match std::fs::read(path(name)?) {
+        Ok(json) => {
+            serde_json::from_slice(&json).map_err(|e| anyhow!("Profile '{name}' is invalid: {e}"))
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            Err(anyhow!("Profile '{name}' doesn't exist"))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/**
+ * Save the current settings as a profile, replacing any profile with the same name
+ *
+ * # Errors
+ * This function will return an error if the name is invalid, or the settings cannot be read or
+ * the profile written.
+ */
+pub fn save(name: &str) -> Result<(), Error> {
+    let path = path(name)?;
+    let profile = Profile {
+        log_levels: logging::log_levels()
+            .into_iter()
+            .map(|o| LogLevel {
+                target: o.target,
+                level: o.level,
+            })
+            .collect(),
+        required_accessibility: accessibility::overridden(),
+        cabinet_settings: cabinet_settings::all()?,
+    };
+    layout::write_atomic(&path, serde_json::to_vec_pretty(&profile)?)?;
+    log!(Level::Info, "Saved profile '{}'", name);
+    Ok(())
+}
+
+/**
+ * Apply a profile, and describe every setting it changed. Every setting in the profile is
+ * checked the same way as when it's changed on its own before anything is changed, so a profile
+ * with an invalid setting (for example, a flag that no longer exists) changes nothing.
+ *
+ * # Errors
+ * This function will return an error if the profile doesn't exist or has an invalid setting,
+ * or if the settings cannot be written.
+ */
+pub fn apply(name: &str) -> Result<Vec<String>, Error> {
+    let profile = read(name)?;
+    let invalid = |e: Error| e.context(format!("Not applying profile '{name}'"));
+    for log_level in &profile.log_levels {
+        logging::parse_level(log_level.level.as_str()).map_err(invalid)?;
+    }
+    if let Some(flags) = &profile.required_accessibility {
+        accessibility::validate_flags(flags).map_err(invalid)?;
+    }
+    cabinet_settings::validate(&profile.cabinet_settings).map_err(invalid)?;
+
+    let mut changes = Vec::new();
+    for key in cabinet_settings::replace(&profile.cabinet_settings, &format!("profile:{name}"))? {
+        changes.push(format!(
+            "Cabinet setting '{key}' {}",
+            if profile.cabinet_settings.contains_key(&key) {
+                "set"
+            } else {
+                "removed"
+            }
+        ));
+    }
+
+    let current = accessibility::overridden();
+    if current != profile.required_accessibility {
+        changes.push(format!(
+            "Required accessibility flags changed from {current:?} to {:?}",
+            profile.required_accessibility
+        ));
+        accessibility::set_required(profile.required_accessibility.clone())?;
+    }
+
+    let current: Vec<LogLevel> = logging::log_levels()
+        .into_iter()
+        .map(|o| LogLevel {
+            target: o.target,
+            level: o.level,
+        })
+        .collect();
+    for log_level in &current {
+        if !profile
+            .log_levels
+            .iter()
+            .any(|l| l.target == log_level.target)
+        {
+            logging::set_log_level(log_level.target.clone(), None, None)?;
+            changes.push(format!(
+                "Log level for {} reset from {}",
+                log_level.target.as_deref().unwrap_or("everything"),
+                log_level.level
+            ));
+        }
+    }
+    for log_level in &profile.log_levels {
+        // Unchanged overrides keep their expiry
+        if !current.contains(log_level) {
+            logging::set_log_level(
+                log_level.target.clone(),
+                Some(log_level.level.clone()),
+                None,
+            )?;
+            changes.push(format!(
+                "Log level for {} set to {}",
+                log_level.target.as_deref().unwrap_or("everything"),
+                log_level.level
+            ));
+        }
+    }
+
+    log!(
+        Level::Info,
+        "Applied profile '{}' ({} changes)",
+        name,
+        changes.len()
+    );
+    Ok(changes)
+}
+
+/**
+ * Get the names of the saved profiles
+ *
+ * # Errors
+ * This function will return an error if the profiles directory cannot be read.
+ */
+pub fn list() -> Result<Vec<String>, Error> {
+    let entries = match std::fs::read_dir(dir()) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut names = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "json") {
+            if let Some(name) = path.file_stem().and_then(|name| name.to_str()) {
+                names.push(name.to_string());
+            }
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+/**
+ * Delete a saved profile
+ *
+ * # Errors
+ * This function will return an error if the profile doesn't exist or cannot be removed.
+ */
+pub fn delete(name: &str) -> Result<(), Error> {
+    match std::fs::remove_file(path(name)?) {
+        Ok(()) => {
+            log!(Level::Info, "Deleted profile '{}'", name);
+            Ok(())
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            Err(anyhow!("Profile '{name}' doesn't exist"))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    const TARGET: &str = "backend::profiles_test";
+
+    /**
+     * Go back to no log level overrides, required flags or cabinet settings
+     */
+    fn reset() {
+        for o in logging::log_levels() {
+            logging::set_log_level(o.target, None, None).unwrap();
+        }
+        accessibility::set_required(None).unwrap();
+        cabinet_settings::replace(&BTreeMap::new(), "operator").unwrap();
+    }
+
+    /// Log level overrides, required flags and cabinet settings
+    type Snapshot = (
+        Vec<(Option<String>, String)>,
+        Option<Vec<AccessibilityFlag>>,
+        BTreeMap<String, String>,
+    );
+
+    /**
+     * Everything a profile covers, to compare before and after applying one
+     */
+    fn snapshot() -> Snapshot {
+        (
+            logging::log_levels()
+                .into_iter()
+                .map(|o| (o.target, o.level))
+                .collect(),
+            accessibility::overridden(),
+            cabinet_settings::all().unwrap(),
+        )
+    }
+
+    #[test]
+    fn profiles_are_saved_applied_and_deleted() {
+        let (_guard, root) = testing::root("profiles");
+        reset();
+        logging::set_log_level(Some(TARGET.to_string()), Some(String::from("debug")), None)
+            .unwrap();
+        accessibility::set_required(Some(vec![AccessibilityFlag::OneHanded])).unwrap();
+        cabinet_settings::set("event/label", Some("Imagine RIT"), "operator").unwrap();
+        save("imagine").unwrap();
+        let saved = snapshot();
+
+        reset();
+        cabinet_settings::set("volume", Some("3"), "operator").unwrap();
+        let mut changes = apply("imagine").unwrap();
+        changes.sort();
+        assert_eq!(
+            changes,
+            [
+                "Cabinet setting 'event/label' set",
+                "Cabinet setting 'volume' removed",
+                "Log level for backend::profiles_test set to debug",
+                "Required accessibility flags changed from None to Some([OneHanded])",
+            ]
+        );
+        assert_eq!(snapshot(), saved);
+        // Applying it again changes nothing
+        assert!(apply("imagine").unwrap().is_empty());
+
+        assert_eq!(list().unwrap(), ["imagine"]);
+        delete("imagine").unwrap();
+        assert!(list().unwrap().is_empty());
+        assert!(delete("imagine").is_err());
+        assert!(apply("imagine").is_err());
+        for name in [
+            "",
+            "../cabinet_settings",
+            "a b",
+            &"x".repeat(MAX_NAME_LENGTH + 1),
+        ] {
+            assert!(save(name).is_err(), "{name:?}");
+        }
+
+        reset();
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn profiles_with_an_invalid_setting_change_nothing() {
+        let (_guard, root) = testing::root("profiles-invalid");
+        reset();
+        cabinet_settings::set("volume", Some("3"), "operator").unwrap();
+        let before = snapshot();
+        let valid = serde_json::json!({
+            "log_levels": [{"target": TARGET, "level": "trace"}],
+            "required_accessibility": ["one_handed"],
+            "cabinet_settings": {"event/label": "Demo day"},
+        });
+        // Each profile has one setting that's invalid now, with the rest valid and different
+        let invalid = [
+            // A flag that was removed since the profile was saved
+            (
+                "required_accessibility",
+                serde_json::json!(["one_handed", "retired_flag"]),
+            ),
+            (
+                "log_levels",
+                serde_json::json!([{"target": TARGET, "level": "loud"}]),
+            ),
+            // Longer than a setting may be
+            (
+                "cabinet_settings",
+                serde_json::json!({"event/label": "x".repeat(64 * 1024)}),
+            ),
+            ("cabinet_settings", serde_json::json!({"../escape": "1"})),
+        ];
+        for (i, (setting, value)) in invalid.into_iter().enumerate() {
+            let mut profile = valid.clone();
+            profile[setting] = value;
+            let name = format!("invalid{i}");
+            layout::write_atomic(&path(&name).unwrap(), serde_json::to_vec(&profile).unwrap())
+                .unwrap();
+            let e = apply(&name).unwrap_err();
+            assert!(
+                e.to_string().starts_with("Not applying profile"),
+                "{setting}: {e:#}"
+            );
+            assert_eq!(snapshot(), before, "{setting}");
+        }
+        // The same profile without the invalid setting applies fully
+        layout::write_atomic(&path("valid").unwrap(), serde_json::to_vec(&valid).unwrap()).unwrap();
+        assert_eq!(apply("valid").unwrap().len(), 4);
+
+        reset();
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,368 @@
+use super::emit_launch_event;
+use crate::clock;
+use crate::env::quiet_hours;
+use crate::layout;
+use crate::state::JsonState;
+use anyhow::{anyhow, Error};
+use devcade_onboard_types::{LaunchEventKind, QuietHoursStatus};
+use lazy_static::lazy_static;
+use log::{log, Level};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
+
+const MINUTES_PER_DAY: u32 = 24 * 60;
+
+/**
+ * How often the time is checked, so `QuietHoursChanged` is sent within a minute of a boundary
+ */
+const CHECK_EVERY: Duration = Duration::from_secs(60);
+
+lazy_static! {
+    static ref STORED: JsonState<Stored> = JsonState::new(path);
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct Stored {
+    /// Windows set by an operator, which replace `DEVCADE_QUIET_HOURS`
+    schedule: Option<String>,
+    /// The end of the quiet hours an operator lifted, so the override ends with them
+    overridden_until: Option<String>,
+    /// The end of the quiet hours last announced with `QuietHoursChanged`, `None` if none were
+    announced: Option<String>,
+}
+
+fn path() -> PathBuf {
+    layout::state_dir().join("quiet_hours.json")
+}
+
+/**
+ * The error returned when a game is launched during quiet hours, unless an operator lifted them
+ */
+#[derive(Debug, Clone)]
+pub struct QuietHours {
+    /**
+     * When the quiet hours end, as `HH:MM` local time
+     */
+    pub until: String,
+}
+
+impl fmt::Display for QuietHours {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "QuietHours: games can't be launched until {}",
+            self.until
+        )
+    }
+}
+
+impl std::error::Error for QuietHours {}
+
+const DAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+
+/**
+ * A single quiet hours window. Times are in minutes since midnight, and `end` may be before
+ * `start` if the window crosses midnight.
+ */
+struct Window {
+    day: Option<u32>,
+    start: u32,
+    end: u32,
+}
+
+impl Window {
+    fn parse(window: &str) -> Option<Self> {
+        let (day, times) = match window.trim().split_once(' ') {
+            Some((day, times)) => (
+                Some(DAYS.iter().position(|d| d.eq_ignore_ascii_case(day))? as u32),
+                times,
+            ),
+            None => (None, window.trim()),
+        };
+        let (start, end) = times.trim().split_once('-')?;
+        Some(Self {
+            day,
+            start: parse_time(start)?,
+            end: parse_time(end)?,
+        })
+    }
+
+    /**
+     * Whether this window covers the given day of the week and minute of the day
+     */
+    fn contains(&self, day: u32, minute: u32) -> bool {
+        let on = |d: u32| self.day.is_none_or(|day| day == d);
+        if self.start <= self.end {
+            on(day) && (self.start..self.end).contains(&minute)
+        } else {
+            (on(day) && minute >= self.start) || (on((day + 6) % 7) && minute < self.end)
+        }
+    }
+}
+
+fn parse_time(time: &str) -> Option<u32> {
+    let (hours, minutes) = time.trim().split_once(':')?;
+    let (hours, minutes): (u32, u32) = (hours.parse().ok()?, minutes.parse().ok()?);
+    if hours > 24 || minutes > 59 || hours * 60 + minutes > MINUTES_PER_DAY {
+        return None;
+    }
+    Some(hours * 60 + minutes)
+}
+
+/**
+ * Get the current day of the week (0 is Sunday) and minute of the day in local time
+ */
+fn now() -> (u32, u32) {
+    let tm = clock::local_time();
+    (tm.tm_wday as u32, (tm.tm_hour * 60 + tm.tm_min) as u32)
+}
+
+/**
+ * Parse a comma separated list of windows. `what` names the list in warnings about invalid
+ * windows.
+ */
+fn parse(windows: &str, what: &str) -> Vec<Window> {
+    windows
+        .split(',')
+        .filter_map(|window| {
+            let parsed = Window::parse(window);
+            if parsed.is_none() {
+                log!(Level::Warn, "Ignoring invalid {} window '{}'", what, window);
+            }
+            parsed
+        })
+        .collect()
+}
+
+/**
+ * Get when the window covering a day of the week and minute of the day ends (`HH:MM`), if any
+ * does
+ */
+fn until_at(windows: &[Window], day: u32, minute: u32) -> Option<String> {
+    windows
+        .iter()
+        .find(|window| window.contains(day, minute))
+        .map(|window| format!("{:02}:{:02}", window.end / 60 % 24, window.end % 60))
+}
+
+/**
+ * Get the quiet hours windows: those set by an operator, or `DEVCADE_QUIET_HOURS`
+ */
+fn schedule() -> Option<String> {
+    STORED.lock().schedule.clone().or_else(quiet_hours)
+}
+
+/**
+ * Get the status of quiet hours right now. Local time is worked out on every call, so a change of
+ * timezone applies right away.
+ */
+pub fn status() -> QuietHoursStatus {
+    let schedule = schedule();
+    let (day, minute) = now();
+    let until = schedule
+        .as_deref()
+        .and_then(|windows| until_at(&parse(windows, "quiet hours"), day, minute));
+    let overridden = until.is_some() && STORED.lock().overridden_until == until;
+    QuietHoursStatus {
+        schedule,
+        until,
+        overridden,
+    }
+}
+
+/**
+ * If the cabinet is in quiet hours that an operator hasn't lifted, returns the time they end at
+ * (`HH:MM`)
+ */
+pub fn until() -> Option<String> {
+    let status = status();
+    status.until.filter(|_| !status.overridden)
+}
+
+/**
+ * Refuse to launch a game during quiet hours, unless an operator lifted them
+ *
+ * # Errors
+ * This function will return a `QuietHours` error during quiet hours.
+ */
+pub fn check() -> Result<(), QuietHours> {
+    match until() {
+        Some(until) => Err(QuietHours { until }),
+        None => Ok(()),
+    }
+}
+
+/**
+ * Whether the current time is covered by a comma separated list of windows in the quiet
+ * hours format
+ */
+pub fn within(windows: &str, what: &str) -> bool {
+    let (day, minute) = now();
+    until_at(&parse(windows, what), day, minute).is_some()
+}
+
+/**
+ * Replace the quiet hours windows, or go back to `DEVCADE_QUIET_HOURS` with `None`. This applies
+ * right away, without a restart.
+ *
+ * # Errors
+ * This function will return an error if a window is invalid, or the schedule can't be saved.
+ */
+pub fn set(windows: Option<String>) -> Result<QuietHoursStatus, Error> {
+    let windows = windows.filter(|windows| !windows.trim().is_empty());
+    if let Some(windows) = &windows {
+        if let Some(invalid) = windows
+            .split(',')
+            .find(|window| Window::parse(window).is_none())
+        {
+            return Err(anyhow!("Invalid quiet hours window '{}'", invalid.trim()));
+        }
+    }
+    {
+        let mut stored = STORED.lock();
+        stored.schedule = windows;
+        stored.save()?;
+    }
+    log!(Level::Info, "Quiet hours set to {:?}", schedule());
+    evaluate();
+    Ok(status())
+}
+
+/**
+ * Lift the current quiet hours until they end, or put them back
+ *
+ * # Errors
+ * This function will return an error if the cabinet isn't in quiet hours, or the override can't be
+ * saved.
+ */
+pub fn set_override(lifted: bool) -> Result<QuietHoursStatus, Error> {
+    let until = status().until;
+    if lifted && until.is_none() {
+        return Err(anyhow!("The cabinet isn't in quiet hours"));
+    }
+    {
+        let mut stored = STORED.lock();
+        stored.overridden_until = until.filter(|_| lifted);
+        stored.save()?;
+    }
+    if lifted {
+        log!(Level::Warn, "Quiet hours lifted by an operator");
+    } else {
+        log!(Level::Info, "Quiet hours override removed");
+    }
+    evaluate();
+    Ok(status())
+}
+
+/**
+ * Announce quiet hours starting or ending with a `QuietHoursChanged` event, if that isn't what was
+ * announced last. An override is forgotten once the quiet hours it lifted are over.
+ */
+fn evaluate() {
+    let status = status();
+    let mut stored = STORED.lock();
+    let until = status.until.clone().filter(|_| !status.overridden);
+    let expired = stored.overridden_until.is_some() && !status.overridden;
+    if stored.announced == until && !expired {
+        return;
+    }
+    if expired {
+        stored.overridden_until = None;
+    }
+    stored.announced = until.clone();
+    if let Err(e) = stored.save() {
+        log!(Level::Warn, "Couldn't save quiet hours: {}", e);
+    }
+    drop(stored);
+
+    match &until {
+        Some(until) => log!(Level::Info, "Quiet hours until {}", until),
+        None => log!(Level::Info, "Quiet hours are over"),
+    }
+    emit_launch_event(LaunchEventKind::QuietHoursChanged(until));
+}
+
+/**
+ * Check for quiet hours starting or ending at startup, and again every minute. This never
+ * returns.
+ */
+pub async fn watch() {
+    let mut interval = clock::interval(CHECK_EVERY);
+    loop {
+        evaluate();
+        interval.tick().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SUN: u32 = 0;
+    const MON: u32 = 1;
+    const FRI: u32 = 5;
+    const SAT: u32 = 6;
+
+    fn at(windows: &str, day: u32, time: &str) -> Option<String> {
+        until_at(&parse(windows, "test"), day, parse_time(time).unwrap())
+    }
+
+    #[test]
+    fn windows_crossing_midnight_cover_both_days() {
+        let nightly = "22:00-07:00";
+        assert_eq!(at(nightly, MON, "21:59"), None);
+        assert_eq!(at(nightly, MON, "22:00").as_deref(), Some("07:00"));
+        assert_eq!(at(nightly, MON, "23:59").as_deref(), Some("07:00"));
+        assert_eq!(at(nightly, MON, "00:00").as_deref(), Some("07:00"));
+        assert_eq!(at(nightly, MON, "06:59").as_deref(), Some("07:00"));
+        assert_eq!(at(nightly, MON, "07:00"), None);
+    }
+
+    #[test]
+    fn weekly_windows_end_on_the_next_day() {
+        let weekend = "Fri 23:00-03:00, Sat 23:00-03:00, 12:00-13:00";
+        assert_eq!(at(weekend, FRI, "22:59"), None);
+        assert_eq!(at(weekend, FRI, "23:00").as_deref(), Some("03:00"));
+        assert_eq!(at(weekend, SAT, "02:59").as_deref(), Some("03:00"));
+        assert_eq!(at(weekend, SAT, "03:00"), None);
+        assert_eq!(at(weekend, SUN, "02:59").as_deref(), Some("03:00"));
+        assert_eq!(at(weekend, MON, "02:59"), None);
+        assert_eq!(at(weekend, MON, "12:30").as_deref(), Some("13:00"));
+        assert_eq!(at("00:00-24:00", SUN, "23:59").as_deref(), Some("00:00"));
+    }
+
+    #[test]
+    fn invalid_windows_are_ignored() {
+        assert_eq!(
+            parse("25:00-07:00,Caturday 10:00-11:00,10:00", "test").len(),
+            0
+        );
+        assert_eq!(
+            at("bogus,22:00-07:00", MON, "23:00").as_deref(),
+            Some("07:00")
+        );
+    }
+
+    #[test]
+    fn quiet_hours_refuse_launches_until_lifted() {
+        let (_guard, root) = crate::testing::root("quiet-hours");
+        set(Some(String::from("00:00-24:00"))).unwrap();
+        assert_eq!(
+            check().unwrap_err().to_string(),
+            "QuietHours: games can't be launched until 00:00"
+        );
+
+        let lifted = set_override(true).unwrap();
+        assert!(lifted.overridden);
+        assert!(check().is_ok());
+        assert!(set_override(false).is_ok());
+        assert!(check().is_err());
+
+        set(Some(String::from("Caturday 10:00-11:00"))).unwrap_err();
+        set(None).unwrap();
+        assert!(set_override(true).is_err());
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,275 @@
+use crate::env::{retirement_days, retirement_policy};
+use crate::layout;
+use crate::state::{self, JsonState};
+use devcade_onboard_types::schema::DevcadeGame;
+use lazy_static::lazy_static;
+use log::{log, Level};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+lazy_static! {
+    // When each retired game was first seen retired, by game ID
+    static ref RETIRED: JsonState<BTreeMap<String, u64>> = JsonState::new(path);
+}
+
+/**
+ * What happens to retired games
+ */
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Policy {
+    Keep,
+    Hide,
+    Remove,
+}
+
+/**
+ * One line of the journal
+ */
+#[derive(Serialize)]
+struct JournalEntry<'a> {
+    time: u64,
+    game_id: &'a str,
+    /// `retired`, `restored` or `removed`
+    transition: &'a str,
+}
+
+fn path() -> PathBuf {
+    layout::state_dir().join("retired.json")
+}
+
+fn journal_path() -> PathBuf {
+    layout::state_dir().join("retirement.journal")
+}
+
+/**
+ * Get the retirement policy. Unknown policies keep games, like the default.
+ */
+pub fn policy() -> Policy {
+    match retirement_policy().as_deref() {
+        None | Some("keep") => Policy::Keep,
+        Some("hide") => Policy::Hide,
+        Some("remove") => Policy::Remove,
+        Some(policy) => {
+            log!(
+                Level::Warn,
+                "Unknown retirement policy '{}', keeping retired games",
+                policy
+            );
+            Policy::Keep
+        }
+    }
+}
+
+fn journal(time: u64, game_id: &str, transition: &str) {
+    log!(Level::Info, "Game {} {}", game_id, transition);
+    let entry = JournalEntry {
+        time,
+        game_id,
+        transition,
+    };
+    if let Err(e) = state::journal(journal_path().as_path(), &entry) {
+        log!(Level::Warn, "Couldn't journal retirement: {}", e);
+    }
+}
+
+/**
+ * Whether a game is hidden from listings by the policy
+ */
+pub fn hidden(game: &DevcadeGame) -> bool {
+    policy() != Policy::Keep && (game.retired || RETIRED.lock().contains_key(&game.id))
+}
+
+/**
+ * Update which installed games are retired from a fresh game list. `now` is the time in seconds
+ * since the Unix epoch. Returns the IDs of the games due for removal. If the API lists no games
+ * at all, nothing changes, so an outage can't retire the library.
+ */
+pub fn observe(upstream: &[DevcadeGame], installed: &[String], now: u64) -> Vec<String> {
+    if upstream.is_empty() && !installed.is_empty() {
+        return Vec::new();
+    }
+    let is_retired = |id: &String| {
+        upstream
+            .iter()
+            .find(|game| game.id == *id)
+            .is_none_or(|game| game.retired)
+    };
+    let grace = retirement_days().saturating_mul(24 * 60 * 60);
+    let mut retired = RETIRED.lock();
+    let mut changed = false;
+    for id in installed.iter().filter(|id| is_retired(id)) {
+        if !retired.contains_key(id) {
+            retired.insert(id.clone(), now);
+            journal(now, id, "retired");
+            changed = true;
+        }
+    }
+    retired.retain(|id, _| {
+        if !installed.contains(id) {
+            // Removed some other way
+            changed = true;
+            false
+        } else if is_retired(id) {
+            true
+        } else {
+            journal(now, id, "restored");
+            changed = true;
+            false
+        }
+    });
+    if changed {
+        if let Err(e) = retired.save() {
+            log!(Level::Warn, "Couldn't save retired games: {}", e);
+        }
+    }
+
+    if policy() != Policy::Remove {
+        return Vec::new();
+    }
+    retired
+        .iter()
+        .filter(|(_, since)| now.saturating_sub(**since) >= grace)
+        .map(|(id, _)| id.clone())
+        .collect()
+}
+
+/**
+ * Record that a retired game was removed
+ */
+pub fn removed(game_id: &str, now: u64) {
+    let mut retired = RETIRED.lock();
+    if retired.remove(game_id).is_some() {
+        journal(now, game_id, "removed");
+        if let Err(e) = retired.save() {
+            log!(Level::Warn, "Couldn't save retired games: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    const DAY: u64 = 24 * 60 * 60;
+
+    fn game(id: &str, retired: bool) -> DevcadeGame {
+        DevcadeGame {
+            id: id.to_string(),
+            retired,
+            ..DevcadeGame::default()
+        }
+    }
+
+    /**
+     * The transitions in the journal, in order, as `game_id transition`
+     */
+    fn transitions() -> Vec<String> {
+        std::fs::read_to_string(journal_path())
+            .unwrap_or_default()
+            .lines()
+            .map(|line| {
+                let entry: serde_json::Value = serde_json::from_str(line).unwrap();
+                format!("{} {}", entry["game_id"], entry["transition"]).replace('"', "")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn each_policy_treats_retired_games_as_it_says() {
+        let (_guard, root) = testing::root("retirement-policies");
+        // pong is listed, tetris is listed but retired, and snake was removed from the API
+        let upstream = [game("pong", false), game("tetris", true)];
+        let installed = ["pong", "tetris", "snake"].map(String::from);
+        let hidden_ids = || {
+            ["pong", "tetris", "snake"]
+                .into_iter()
+                .filter(|id| hidden(&game(id, false)))
+                .collect::<Vec<_>>()
+        };
+
+        for name in ["keep", "hide", "remove", "delete-everything"] {
+            RETIRED.lock().clear();
+            std::env::set_var("DEVCADE_RETIREMENT_POLICY", name);
+            let now = 1_000 * DAY;
+            assert!(observe(&upstream, &installed, now).is_empty(), "{name}");
+            let later = observe(&upstream, &installed, now + 30 * DAY);
+            match name {
+                "hide" => {
+                    assert_eq!(policy(), Policy::Hide);
+                    assert_eq!(hidden_ids(), ["tetris", "snake"]);
+                    assert!(later.is_empty());
+                }
+                "remove" => {
+                    assert_eq!(policy(), Policy::Remove);
+                    assert_eq!(hidden_ids(), ["tetris", "snake"]);
+                    assert_eq!(later, ["snake", "tetris"]);
+                }
+                _ => {
+                    // Unknown policies keep games like the default does
+                    assert_eq!(policy(), Policy::Keep);
+                    assert!(hidden_ids().is_empty());
+                    assert!(later.is_empty());
+                }
+            }
+        }
+        // A game the API marks retired is hidden before retirement ever sees it
+        RETIRED.lock().clear();
+        std::env::set_var("DEVCADE_RETIREMENT_POLICY", "hide");
+        assert!(hidden(&game("galaga", true)));
+        assert!(!hidden(&game("galaga", false)));
+
+        std::env::remove_var("DEVCADE_RETIREMENT_POLICY");
+        RETIRED.lock().clear();
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn retired_games_are_removed_once_the_grace_period_is_over() {
+        let (_guard, root) = testing::root("retirement-grace");
+        RETIRED.lock().clear();
+        std::env::set_var("DEVCADE_RETIREMENT_POLICY", "remove");
+        std::env::set_var("DEVCADE_RETIREMENT_DAYS", "2");
+        let installed = ["pong", "snake"].map(String::from);
+        let listed = [game("pong", false), game("snake", false)];
+        let without_snake = [game("pong", false)];
+        let start = 1_000 * DAY;
+
+        assert!(observe(&without_snake, &installed, start).is_empty());
+        assert!(observe(&without_snake, &installed, start + 2 * DAY - 1).is_empty());
+        // An outage that lists nothing doesn't retire or restore anything
+        assert!(observe(&[], &installed, start + DAY).is_empty());
+        assert!(RETIRED.lock().contains_key("snake"));
+
+        // Listed again just before it was due, so its grace period starts over next time
+        assert!(observe(&listed, &installed, start + 2 * DAY - 1).is_empty());
+        assert!(observe(&without_snake, &installed, start + 2 * DAY).is_empty());
+        assert!(observe(&without_snake, &installed, start + 4 * DAY - 1).is_empty());
+        assert_eq!(
+            observe(&without_snake, &installed, start + 4 * DAY),
+            ["snake"]
+        );
+        removed("snake", start + 4 * DAY);
+        assert!(RETIRED.lock().is_empty());
+        // Removed games are forgotten, and so are retired games removed some other way
+        assert!(observe(&without_snake, &installed[..1], start + 5 * DAY).is_empty());
+
+        assert_eq!(
+            transitions(),
+            [
+                "snake retired",
+                "snake restored",
+                "snake retired",
+                "snake removed"
+            ]
+        );
+        let saved: BTreeMap<String, u64> =
+            serde_json::from_slice(&std::fs::read(path()).unwrap()).unwrap();
+        assert!(saved.is_empty());
+
+        std::env::remove_var("DEVCADE_RETIREMENT_POLICY");
+        std::env::remove_var("DEVCADE_RETIREMENT_DAYS");
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
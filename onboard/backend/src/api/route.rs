@@ -0,0 +1,83 @@
+/**
+ * Get the list of games
+ */
+pub fn game_list() -> String {
+    String::from("games/")
+}
+
+/**
+ * Get a specific game by ID
+ */
+pub fn game(id: &str) -> String {
+    format!("games/{id}")
+}
+
+/**
+ * Get a specific game's icon by ID
+ */
+pub fn game_icon(id: &str) -> String {
+    format!("games/{id}/icon")
+}
+
+/**
+ * Get a specific game's banner by ID
+ */
+pub fn game_banner(id: &str) -> String {
+    format!("games/{id}/banner")
+}
+
+/**
+ * Get a specific game's binary by ID
+ */
+pub fn game_download(id: &str) -> String {
+    format!("games/{id}/game")
+}
+
+/**
+ * Get the publisher's signature of a specific game's hash by ID
+ */
+pub fn game_signature(id: &str) -> String {
+    format!("games/{id}/signature")
+}
+
+/**
+ * Get the stream of notifications about games being added, changed or removed
+ */
+pub fn catalog_events() -> String {
+    String::from("events/catalog")
+}
+
+/**
+ * Get all tags
+ */
+pub fn tag_list() -> String {
+    String::from("tags/")
+}
+
+/**
+ * Get a specific tag
+ */
+pub fn tag(name: &str) -> String {
+    format!("tags/{name}")
+}
+
+/**
+ * Get all games with a specific tag
+ */
+pub fn tag_games(name: &str) -> String {
+    format!("tags/{name}/games")
+}
+
+/**
+ * Get all tags along with their games, if the API supports it
+ */
+pub fn tags_with_games() -> String {
+    String::from("tags/?include=games")
+}
+
+/**
+ * Get a specific user
+ */
+pub fn user(uid: &str) -> String {
+    format!("users/{uid}")
+}
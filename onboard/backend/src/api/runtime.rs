@@ -0,0 +1,491 @@
+use super::{find_executable, wildcard_match, ExecutableStrategy};
+use crate::clock;
+use crate::layout;
+use crate::state::JsonState;
+use anyhow::Error;
+use devcade_onboard_types::{GameRuntime, Value};
+use lazy_static::lazy_static;
+use log::{log, Level};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::process::Command;
+
+/**
+ * How long `ldd` may take to list an executable's libraries
+ */
+const LDD_TIMEOUT: Duration = Duration::from_secs(10);
+/**
+ * How deep into the publish directory signature files are looked for
+ */
+const MAX_DEPTH: usize = 3;
+
+/**
+ * Files that show a game was built with a runtime or engine. Patterns without a `/` match file
+ * names anywhere in the publish directory, and patterns with one match paths relative to it.
+ * `*` matches any number of characters. Add new engines here.
+ */
+struct Signature {
+    runtime: &'static str,
+    files: &'static [&'static str],
+}
+
+const SIGNATURES: &[Signature] = &[
+    Signature {
+        runtime: "SDL2",
+        files: &["libSDL2-2.0.so*", "libSDL2.so"],
+    },
+    Signature {
+        runtime: "Godot",
+        files: &["*.pck"],
+    },
+    Signature {
+        runtime: "Unity",
+        files: &["UnityPlayer.so", "*_Data/globalgamemanagers"],
+    },
+    Signature {
+        runtime: "MonoGame",
+        files: &["MonoGame.Framework.dll"],
+    },
+    Signature {
+        runtime: "FNA",
+        files: &["FNA.dll"],
+    },
+    Signature {
+        runtime: "LÖVE",
+        files: &["*.love", "liblove*.so*"],
+    },
+];
+
+lazy_static! {
+    static ref RUNTIMES: JsonState<BTreeMap<String, GameRuntime>> = JsonState::new(path);
+}
+
+fn path() -> PathBuf {
+    layout::state_dir().join("runtimes.json")
+}
+
+/**
+ * Change what's known about a game and write it out. Failing to write is only logged, since
+ * none of this is needed to run games.
+ */
+fn update(game_id: &str, f: impl FnOnce(&mut GameRuntime)) {
+    let mut runtimes = RUNTIMES.lock();
+    f(runtimes.entry(game_id.to_string()).or_default());
+    if let Err(e) = runtimes.save() {
+        log!(
+            Level::Warn,
+            "Couldn't save runtime of game {}: {}",
+            game_id,
+            e
+        );
+    }
+}
+
+/**
+ * Get the architecture of an ELF executable from its header, or `None` for anything else (like
+ * a shell script)
+ */
+fn architecture(executable: &Path) -> Option<String> {
+    let mut header = [0; 20];
+    std::fs::File::open(executable)
+        .ok()?
+        .read_exact(&mut header)
+        .ok()?;
+    if header[..4] != *b"\x7fELF" {
+        return None;
+    }
+    // e_machine, in the byte order EI_DATA says
+    let machine = match header[5] {
+        2 => u16::from_be_bytes([header[18], header[19]]),
+        _ => u16::from_le_bytes([header[18], header[19]]),
+    };
+    Some(match machine {
+        0x03 => String::from("x86"),
+        0x28 => String::from("arm"),
+        0x3e => String::from("x86_64"),
+        0xb7 => String::from("aarch64"),
+        0xf3 => String::from("riscv"),
+        machine => format!("unknown ({machine:#x})"),
+    })
+}
+
+/**
+ * Describe the .NET runtime a game uses from its `*.runtimeconfig.json`: self-contained games
+ * list the frameworks they include, framework-dependent ones the frameworks they need installed
+ */
+fn dotnet(runtimeconfig: &Path) -> Option<String> {
+    let json: Value = serde_json::from_slice(&std::fs::read(runtimeconfig).ok()?).ok()?;
+    let options = json.get("runtimeOptions")?;
+    let describe = |frameworks: &Value, kind: &str| {
+        let frameworks = match frameworks {
+            Value::Array(frameworks) => frameworks.iter().collect(),
+            framework => vec![framework],
+        };
+        let names: Vec<String> = frameworks
+            .iter()
+            .filter_map(|framework| {
+                Some(format!(
+                    "{} {}",
+                    framework.get("name")?.as_str()?,
+                    framework.get("version")?.as_str()?
+                ))
+            })
+            .collect();
+        format!(".NET ({kind}: {})", names.join(", "))
+    };
+    if let Some(included) = options.get("includedFrameworks") {
+        Some(describe(included, "self-contained"))
+    } else {
+        options
+            .get("frameworks")
+            .or_else(|| options.get("framework"))
+            .map(|frameworks| describe(frameworks, "framework-dependent"))
+    }
+}
+
+/**
+ * List the files under a directory, as paths relative to it
+ */
+pub(super) fn list_files(dir: &Path, prefix: &str, depth: usize, files: &mut Vec<String>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let relative = format!("{prefix}{name}");
+        if entry.path().is_dir() {
+            if depth < MAX_DEPTH {
+                list_files(&entry.path(), &format!("{relative}/"), depth + 1, files);
+            }
+        } else {
+            files.push(relative);
+        }
+    }
+}
+
+/**
+ * Get the runtimes and engines whose signature files a publish directory contains
+ */
+fn detect(publish: &Path) -> Vec<String> {
+    let mut found = Vec::new();
+    list_files(publish, "", 0, &mut found);
+    SIGNATURES
+        .iter()
+        .filter(|signature| {
+            signature.files.iter().any(|pattern| {
+                found.iter().any(|file| {
+                    if pattern.contains('/') {
+                        wildcard_match(pattern, file)
+                    } else {
+                        let name = file.rsplit('/').next().unwrap_or_default();
+                        wildcard_match(pattern, name)
+                    }
+                })
+            })
+        })
+        .map(|signature| signature.runtime.to_string())
+        .collect()
+}
+
+/**
+ * List the shared libraries an executable needs that the cabinet doesn't have, according to
+ * `ldd`
+ */
+async fn missing_libraries(executable: &Path) -> Result<Vec<String>, Error> {
+    let output =
+        clock::timeout(LDD_TIMEOUT, Command::new("ldd").arg(executable).output()).await??;
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|line| line.contains("not found"))
+        .filter_map(|line| line.split_whitespace().next())
+        .map(String::from)
+        .collect())
+}
+
+/**
+ * Probe an installed game and record what it needs to run. The launch history is kept.
+ */
+pub async fn probe(game_id: &str, game_name: &str) {
+    let publish = layout::game_dir(game_id).join("publish");
+    let mut runtimes = detect(publish.as_path());
+    let (architecture, missing) = match find_executable(publish.as_path(), game_name) {
+        Ok((executable, strategy)) => {
+            if strategy == ExecutableStrategy::RuntimeConfig {
+                let name = executable.file_name().unwrap_or_default().to_string_lossy();
+                let runtimeconfig = publish.join(format!("{name}.runtimeconfig.json"));
+                runtimes.extend(dotnet(runtimeconfig.as_path()));
+            }
+            let architecture = architecture(executable.as_path());
+            let missing = if architecture.is_some() {
+                missing_libraries(executable.as_path())
+                    .await
+                    .unwrap_or_else(|e| {
+                        log!(
+                            Level::Debug,
+                            "Couldn't list libraries of game {}: {}",
+                            game_id,
+                            e
+                        );
+                        Vec::new()
+                    })
+            } else {
+                Vec::new()
+            };
+            (architecture, missing)
+        }
+        Err(_) => (None, Vec::new()),
+    };
+    if !missing.is_empty() {
+        log!(
+            Level::Warn,
+            "Game {} needs libraries the cabinet doesn't have: {}",
+            game_id,
+            missing.join(", ")
+        );
+    }
+    update(game_id, |runtime| {
+        runtime.architecture = architecture;
+        runtime.runtimes = runtimes;
+        runtime.missing_libraries = missing;
+    });
+}
+
+/**
+ * Record how a launch of a game went
+ */
+pub fn launched(game_id: &str, strategy: ExecutableStrategy, succeeded: bool) {
+    update(game_id, |runtime| {
+        runtime.launch_strategy = Some(format!("{strategy:?}"));
+        runtime.launched = Some(succeeded);
+    });
+}
+
+/**
+ * Get what's known about what a game needs to run, if it's been probed
+ */
+pub fn get(game_id: &str) -> Option<GameRuntime> {
+    RUNTIMES.lock().get(game_id).cloned()
+}
+
+/**
+ * Get the runtimes of every game whose last launch succeeded
+ */
+pub fn supported() -> Vec<String> {
+    RUNTIMES
+        .lock()
+        .values()
+        .filter(|runtime| runtime.launched == Some(true))
+        .flat_map(|runtime| runtime.runtimes.iter().cloned())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
+
+// The devcade directory lock is held across awaits on purpose, each test has its own runtime
+#[cfg(test)]
+#[allow(clippy::await_holding_lock)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    /**
+     * The files of a game built with each engine in `SIGNATURES`, as the engine's export lays
+     * them out
+     */
+    const FIXTURES: &[(&str, &[&str])] = &[
+        ("SDL2", &["Pong", "lib/libSDL2-2.0.so.0"]),
+        ("Godot", &["Pong.x86_64", "Pong.pck"]),
+        (
+            "Unity",
+            &[
+                "Pong.x86_64",
+                "UnityPlayer.so",
+                "Pong_Data/globalgamemanagers",
+            ],
+        ),
+        ("MonoGame", &["Pong", "Pong.dll", "MonoGame.Framework.dll"]),
+        (
+            "FNA",
+            &["Pong", "Pong.exe", "FNA.dll", "lib64/libFNA3D.so.0"],
+        ),
+        ("LÖVE", &["Pong", "pong.love", "lib/liblove-11.4.so"]),
+    ];
+
+    /**
+     * A publish directory with these files, all empty
+     */
+    fn publish(root: &Path, name: &str, files: &[&str]) -> PathBuf {
+        let publish = root.join(name);
+        for file in files {
+            let path = publish.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, b"").unwrap();
+        }
+        publish
+    }
+
+    /**
+     * The start of an ELF header with a byte order and machine
+     */
+    fn elf(big_endian: bool, machine: u16) -> Vec<u8> {
+        let mut header = vec![0x7f, b'E', b'L', b'F', 2, if big_endian { 2 } else { 1 }, 1];
+        header.resize(18, 0);
+        if big_endian {
+            header.extend(machine.to_be_bytes());
+        } else {
+            header.extend(machine.to_le_bytes());
+        }
+        header.resize(64, 0);
+        header
+    }
+
+    #[test]
+    fn each_engine_is_detected_from_its_files() {
+        let (_guard, root) = testing::root("runtime-signatures");
+        // Every engine in the table has a fixture
+        for signature in SIGNATURES {
+            assert!(
+                FIXTURES
+                    .iter()
+                    .any(|(engine, _)| *engine == signature.runtime),
+                "no fixture for {}",
+                signature.runtime
+            );
+        }
+        for (engine, files) in FIXTURES {
+            let publish = publish(&root, engine, files);
+            assert_eq!(detect(&publish), [*engine]);
+        }
+
+        let plain = publish(&root, "plain", &["Pong", "assets/sprites.png"]);
+        assert!(detect(&plain).is_empty());
+        // Too deep to be looked at
+        let deep = publish(&root, "deep", &["Pong", "a/b/c/d/libSDL2.so"]);
+        assert!(detect(&deep).is_empty());
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn dotnet_runtimes_are_described_from_the_runtimeconfig() {
+        let (_guard, root) = testing::root("runtime-dotnet");
+        let describe = |json: Value| {
+            let path = root.join("Pong.runtimeconfig.json");
+            std::fs::write(&path, serde_json::to_vec(&json).unwrap()).unwrap();
+            dotnet(&path)
+        };
+        let framework =
+            |name: &str, version: &str| serde_json::json!({"name": name, "version": version});
+
+        assert_eq!(
+            describe(serde_json::json!({"runtimeOptions": {
+                "includedFrameworks": [framework("Microsoft.NETCore.App", "6.0.5")]
+            }}))
+            .as_deref(),
+            Some(".NET (self-contained: Microsoft.NETCore.App 6.0.5)")
+        );
+        assert_eq!(
+            describe(serde_json::json!({"runtimeOptions": {
+                "framework": framework("Microsoft.NETCore.App", "3.1.0")
+            }}))
+            .as_deref(),
+            Some(".NET (framework-dependent: Microsoft.NETCore.App 3.1.0)")
+        );
+        assert_eq!(
+            describe(serde_json::json!({"runtimeOptions": {"frameworks": [
+                framework("Microsoft.NETCore.App", "8.0.0"),
+                framework("Microsoft.AspNetCore.App", "8.0.0"),
+            ]}}))
+            .as_deref(),
+            Some(
+                ".NET (framework-dependent: Microsoft.NETCore.App 8.0.0, \
+                Microsoft.AspNetCore.App 8.0.0)"
+            )
+        );
+        assert_eq!(describe(serde_json::json!({"runtimeOptions": {}})), None);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn architectures_are_read_from_the_elf_header() {
+        let (_guard, root) = testing::root("runtime-architecture");
+        let path = root.join("Pong");
+        let architecture_of = |contents: &[u8]| {
+            std::fs::write(&path, contents).unwrap();
+            architecture(&path)
+        };
+        assert_eq!(
+            architecture_of(&elf(false, 0x3e)).as_deref(),
+            Some("x86_64")
+        );
+        assert_eq!(
+            architecture_of(&elf(false, 0xb7)).as_deref(),
+            Some("aarch64")
+        );
+        assert_eq!(architecture_of(&elf(true, 0x28)).as_deref(), Some("arm"));
+        assert_eq!(
+            architecture_of(&elf(false, 0x1234)).as_deref(),
+            Some("unknown (0x1234)")
+        );
+        assert_eq!(architecture_of(b"#!/bin/sh\nexec ./Pong.x86_64\n"), None);
+        assert_eq!(architecture_of(b"\x7fELF"), None);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[tokio::test]
+    async fn probes_and_launches_make_up_the_supported_runtimes() {
+        let (_guard, root) = testing::root("runtime-probe");
+        RUNTIMES.lock().clear();
+        let godot = publish(&layout::game_dir("godot"), "publish", &["Pong", "Pong.pck"]);
+        std::fs::write(godot.join("Pong"), b"#!/bin/sh\n").unwrap();
+        let dotnet = publish(
+            &layout::game_dir("dotnet"),
+            "publish",
+            &["Snake", "MonoGame.Framework.dll"],
+        );
+        std::fs::write(dotnet.join("Snake"), elf(false, 0x3e)).unwrap();
+        std::fs::write(
+            dotnet.join("Snake.runtimeconfig.json"),
+            br#"{"runtimeOptions": {"includedFrameworks": [
+                {"name": "Microsoft.NETCore.App", "version": "6.0.5"}
+            ]}}"#,
+        )
+        .unwrap();
+
+        probe("godot", "Pong").await;
+        probe("dotnet", "Snake").await;
+        let godot = get("godot").unwrap();
+        assert_eq!(godot.architecture, None);
+        assert_eq!(godot.runtimes, ["Godot"]);
+        let dotnet = get("dotnet").unwrap();
+        assert_eq!(dotnet.architecture.as_deref(), Some("x86_64"));
+        assert_eq!(
+            dotnet.runtimes,
+            [
+                "MonoGame",
+                ".NET (self-contained: Microsoft.NETCore.App 6.0.5)"
+            ]
+        );
+        assert!(get("missing").is_none());
+
+        // Only runtimes of games that last launched fine are supported
+        assert!(supported().is_empty());
+        launched("godot", ExecutableStrategy::GameName, true);
+        launched("dotnet", ExecutableStrategy::RuntimeConfig, false);
+        assert_eq!(supported(), ["Godot"]);
+        assert_eq!(
+            get("dotnet").unwrap().launch_strategy.as_deref(),
+            Some("RuntimeConfig")
+        );
+        // Probing again keeps the launch history
+        probe("godot", "Pong").await;
+        assert_eq!(get("godot").unwrap().launched, Some(true));
+        let saved: BTreeMap<String, GameRuntime> =
+            serde_json::from_slice(&std::fs::read(path()).unwrap()).unwrap();
+        assert_eq!(saved.len(), 2);
+
+        RUNTIMES.lock().clear();
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,175 @@
+use crate::env::{game_network, game_network_overrides};
+use anyhow::{anyhow, Error};
+use lazy_static::lazy_static;
+use log::{log, Level};
+use std::ffi::CStr;
+use std::fmt;
+use std::os::unix::process::CommandExt;
+use std::str::FromStr;
+
+lazy_static! {
+    // Whether this system lets unprivileged processes create namespaces. Checked once by
+    // isolating a throwaway process.
+    static ref SUPPORTED: bool = probe();
+}
+
+/**
+ * What a game is allowed to reach over the network
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetworkPolicy {
+    None,
+    Localhost,
+    Full,
+}
+
+impl FromStr for NetworkPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "none" => Ok(Self::None),
+            "localhost" => Ok(Self::Localhost),
+            "full" => Ok(Self::Full),
+            _ => Err(anyhow!(
+                "Unknown network policy '{}', expected none, localhost or full",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for NetworkPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::None => write!(f, "none"),
+            Self::Localhost => write!(f, "localhost"),
+            Self::Full => write!(f, "full"),
+        }
+    }
+}
+
+/**
+ * Get the network policy for a game, from `DEVCADE_GAME_NETWORK_OVERRIDES` or else
+ * `DEVCADE_GAME_NETWORK`. Invalid values are logged and ignored.
+ */
+pub fn policy_for(game_id: &str) -> NetworkPolicy {
+    let parse = |policy: &str| {
+        policy
+            .parse()
+            .map_err(|e| log!(Level::Warn, "Ignoring invalid network policy: {}", e))
+            .ok()
+    };
+    game_network_overrides()
+        .and_then(|overrides| {
+            overrides.split(',').find_map(|entry| {
+                let (id, policy) = entry.split_once('=')?;
+                (id.trim() == game_id).then(|| parse(policy)).flatten()
+            })
+        })
+        .or_else(|| game_network().and_then(|policy| parse(&policy)))
+        .unwrap_or(NetworkPolicy::Full)
+}
+
+/**
+ * Restrict the network access of a game about to be spawned. Returns whether the policy is
+ * actually enforced; if namespaces aren't available, a warning is logged and the game gets full
+ * access.
+ */
+pub fn apply(command: &mut tokio::process::Command, policy: NetworkPolicy) -> bool {
+    if policy == NetworkPolicy::Full {
+        return true;
+    }
+    if !*SUPPORTED {
+        log!(
+            Level::Warn,
+            "Network policy '{}' can't be enforced, user namespaces are unavailable",
+            policy
+        );
+        return false;
+    }
+    // SAFETY: isolate only makes async-signal-safe calls with memory allocated beforehand
+    unsafe {
+        command.pre_exec(isolate(policy == NetworkPolicy::Localhost));
+    }
+    true
+}
+
+/**
+ * Check whether namespaces can be created by isolating `/bin/true`
+ */
+fn probe() -> bool {
+    let mut command = std::process::Command::new("/bin/true");
+    // SAFETY: isolate only makes async-signal-safe calls with memory allocated beforehand
+    unsafe {
+        command.pre_exec(isolate(true));
+    }
+    command.status().is_ok_and(|status| status.success())
+}
+
+/**
+ * Build the function run in the child between fork and exec. Everything it needs is formatted
+ * up front, since allocating after fork isn't safe.
+ */
+fn isolate(loopback: bool) -> impl FnMut() -> std::io::Result<()> + Send + Sync + 'static {
+    // SAFETY: getuid and getgid can't fail
+    let (uid, gid) = unsafe { (libc::getuid(), libc::getgid()) };
+    // Map our own IDs into the namespace so files and the persistence socket keep their owners
+    let uid_map = format!("{uid} {uid} 1");
+    let gid_map = format!("{gid} {gid} 1");
+
+    move || {
+        // SAFETY: unshare has no memory safety requirements
+        if unsafe { libc::unshare(libc::CLONE_NEWUSER | libc::CLONE_NEWNET) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        write_proc(c"/proc/self/uid_map", uid_map.as_bytes())?;
+        write_proc(c"/proc/self/setgroups", b"deny")?;
+        write_proc(c"/proc/self/gid_map", gid_map.as_bytes())?;
+        if loopback {
+            loopback_up()?;
+        }
+        Ok(())
+    }
+}
+
+/**
+ * Write to a file in /proc without allocating
+ */
+fn write_proc(path: &CStr, data: &[u8]) -> std::io::Result<()> {
+    // SAFETY: path is nul terminated and data is valid for its length
+    unsafe {
+        let fd = libc::open(path.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let written = libc::write(fd, data.as_ptr().cast(), data.len());
+        libc::close(fd);
+        if written < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/**
+ * Bring up the loopback interface of the current network namespace, which starts out down
+ */
+fn loopback_up() -> std::io::Result<()> {
+    // SAFETY: ifreq is plain data, and the socket is closed before returning
+    unsafe {
+        let socket = libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0);
+        if socket < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let mut request: libc::ifreq = std::mem::zeroed();
+        request.ifr_name[..2].copy_from_slice(&[b'l' as libc::c_char, b'o' as libc::c_char]);
+        request.ifr_ifru.ifru_flags = (libc::IFF_UP | libc::IFF_LOOPBACK) as libc::c_short;
+        let result = libc::ioctl(socket, libc::SIOCSIFFLAGS, &request);
+        libc::close(socket);
+        if result < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
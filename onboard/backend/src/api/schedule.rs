@@ -0,0 +1,441 @@
+use super::{cached_tag_membership, emit_launch_event, game_list_from_fs, sessions};
+use crate::clock;
+use crate::layout;
+use crate::state::JsonState;
+use anyhow::{anyhow, Error};
+use devcade_onboard_types::schema::DevcadeGame;
+use devcade_onboard_types::{LaunchEventKind, Schedule, ScheduleEntry};
+use lazy_static::lazy_static;
+use log::{log, Level};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/**
+ * How often the date is checked, so a new day's entry takes over soon after midnight
+ */
+const CHECK_EVERY: Duration = Duration::from_secs(60);
+
+/**
+ * The longest name of a schedule entry
+ */
+const MAX_NAME_LENGTH: usize = 64;
+
+lazy_static! {
+    static ref SCHEDULE: JsonState<Stored> = JsonState::new(path);
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct Stored {
+    entries: Vec<ScheduleEntry>,
+    /// The entry last announced with `ScheduleChanged`, so restarting doesn't announce it again
+    announced: Option<ScheduleEntry>,
+}
+
+fn path() -> PathBuf {
+    layout::state_dir().join("schedule.json")
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/**
+ * Get the number of days from 1970-01-01 to a `YYYY-MM-DD` date, or `None` if it isn't one
+ */
+fn day_number(date: &str) -> Option<i64> {
+    let mut parts = date.split('-');
+    let (year, month, day) = (parts.next()?, parts.next()?, parts.next()?);
+    if parts.next().is_some() || year.len() != 4 || month.len() != 2 || day.len() != 2 {
+        return None;
+    }
+    let (year, month, day): (i64, i64, i64) =
+        (year.parse().ok()?, month.parse().ok()?, day.parse().ok()?);
+    if !(1..=12).contains(&month) || day < 1 || day > days_in_month(year, month) {
+        return None;
+    }
+    // Days from the civil calendar, counting years from March so leap days come last
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    Some(era * 146_097 + day_of_era - 719_468)
+}
+
+/**
+ * Get the entry active on a day. Of the entries covering it, the one with the shortest range
+ * wins, then the one that started last, then the first by name.
+ */
+fn active_on<'a>(entries: &'a [ScheduleEntry], date: &str) -> Option<&'a ScheduleEntry> {
+    let today = day_number(date)?;
+    entries
+        .iter()
+        .filter_map(|entry| {
+            Some((
+                entry,
+                day_number(entry.start.as_str())?,
+                day_number(entry.end.as_str())?,
+            ))
+        })
+        .filter(|(_, start, end)| (*start..=*end).contains(&today))
+        .min_by(|(a, a_start, a_end), (b, b_start, b_end)| {
+            (a_end - a_start)
+                .cmp(&(b_end - b_start))
+                .then(b_start.cmp(a_start))
+                .then(a.name.cmp(&b.name))
+        })
+        .map(|(entry, ..)| entry)
+}
+
+/**
+ * Get the entry active today
+ */
+fn active() -> Option<ScheduleEntry> {
+    active_on(&SCHEDULE.lock().entries, sessions::today().as_str()).cloned()
+}
+
+/**
+ * Describe the tags and games entries refer to that aren't in `games` or `tags`. Tags aren't
+ * checked if they've never been fetched.
+ */
+fn unknown(
+    entries: &[ScheduleEntry],
+    games: &BTreeSet<String>,
+    tags: Option<&BTreeSet<String>>,
+) -> Vec<String> {
+    let mut warnings = Vec::new();
+    for entry in entries {
+        if let (Some(tag), Some(tags)) = (&entry.featured_tag, tags) {
+            if !tags.contains(tag) {
+                warnings.push(format!(
+                    "Schedule entry '{}' features unknown tag '{tag}'",
+                    entry.name
+                ));
+            }
+        }
+        for game_id in entry.featured_games.iter().chain(&entry.blocked_games) {
+            if !games.contains(game_id) {
+                warnings.push(format!(
+                    "Schedule entry '{}' refers to unknown game '{game_id}'",
+                    entry.name
+                ));
+            }
+        }
+    }
+    warnings
+}
+
+/**
+ * Describe the tags and games entries refer to that the cabinet doesn't know of: games that
+ * aren't installed or in a tag, and tags the API didn't list
+ */
+fn warnings(entries: &[ScheduleEntry]) -> Vec<String> {
+    let membership = cached_tag_membership();
+    let mut games: BTreeSet<String> = game_list_from_fs()
+        .unwrap_or_default()
+        .into_iter()
+        .map(|game| game.id)
+        .collect();
+    if let Some(membership) = &membership {
+        games.extend(membership.tags.values().flatten().cloned());
+    }
+    let tags = membership.map(|membership| membership.tags.into_keys().collect());
+    unknown(entries, &games, tags.as_ref())
+}
+
+/**
+ * Check an entry's name and dates
+ */
+fn validate(entry: &ScheduleEntry) -> Result<(), Error> {
+    let valid_name = !entry.name.is_empty()
+        && entry.name.len() <= MAX_NAME_LENGTH
+        && entry
+            .name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-'));
+    if !valid_name {
+        return Err(anyhow!(
+            "Invalid schedule entry name '{}', expected up to {MAX_NAME_LENGTH} letters, digits, \
+            '_' or '-'",
+            entry.name
+        ));
+    }
+    let date = |date: &str| {
+        day_number(date).ok_or_else(|| anyhow!("Invalid date '{date}', expected YYYY-MM-DD"))
+    };
+    if date(entry.end.as_str())? < date(entry.start.as_str())? {
+        return Err(anyhow!(
+            "Schedule entry '{}' ends on {}, before it starts on {}",
+            entry.name,
+            entry.end,
+            entry.start
+        ));
+    }
+    Ok(())
+}
+
+/**
+ * Work out which entry is active today, announcing it with a `ScheduleChanged` event if it isn't
+ * the one announced last
+ */
+fn evaluate() {
+    let mut stored = SCHEDULE.lock();
+    let active = active_on(&stored.entries, sessions::today().as_str()).cloned();
+    if stored.announced == active {
+        return;
+    }
+    stored.announced = active.clone();
+    if let Err(e) = stored.save() {
+        log!(Level::Warn, "Couldn't save schedule: {}", e);
+    }
+    drop(stored);
+
+    let name = active.map(|entry| entry.name);
+    match &name {
+        Some(name) => log!(Level::Info, "Schedule entry '{}' is active", name),
+        None => log!(Level::Info, "No schedule entry is active"),
+    }
+    emit_launch_event(LaunchEventKind::ScheduleChanged(name));
+}
+
+/**
+ * Evaluate the schedule at startup, and again whenever the date may have changed. This never
+ * returns.
+ */
+pub async fn watch() {
+    let mut interval = clock::interval(CHECK_EVERY);
+    loop {
+        evaluate();
+        interval.tick().await;
+    }
+}
+
+/**
+ * Get the schedule, which entry is active, and what it refers to that the cabinet doesn't know of
+ */
+pub fn get() -> Schedule {
+    let entries = SCHEDULE.lock().entries.clone();
+    Schedule {
+        active: active_on(&entries, sessions::today().as_str()).map(|entry| entry.name.clone()),
+        warnings: warnings(&entries),
+        entries,
+    }
+}
+
+/**
+ * Add an entry, replacing the one with the same name. Tags and games the cabinet doesn't know of
+ * are warned about, since they may be added before the entry starts.
+ *
+ * # Errors
+ * This function will return an error if the entry's name or dates are invalid, or the schedule
+ * can't be saved.
+ */
+pub fn add(entry: ScheduleEntry) -> Result<Schedule, Error> {
+    validate(&entry)?;
+    for warning in warnings(std::slice::from_ref(&entry)) {
+        log!(Level::Warn, "{}", warning);
+    }
+    {
+        let mut stored = SCHEDULE.lock();
+        stored
+            .entries
+            .retain(|existing| existing.name != entry.name);
+        log!(
+            Level::Info,
+            "Scheduled '{}' from {} to {}",
+            entry.name,
+            entry.start,
+            entry.end
+        );
+        stored.entries.push(entry);
+        stored.entries.sort_by(|a, b| {
+            (a.start.as_str(), a.name.as_str()).cmp(&(b.start.as_str(), b.name.as_str()))
+        });
+        stored.save()?;
+    }
+    evaluate();
+    Ok(get())
+}
+
+/**
+ * Remove the entry with a name
+ *
+ * # Errors
+ * This function will return an error if there's no such entry, or the schedule can't be saved.
+ */
+pub fn remove(name: &str) -> Result<Schedule, Error> {
+    {
+        let mut stored = SCHEDULE.lock();
+        let before = stored.entries.len();
+        stored.entries.retain(|entry| entry.name != name);
+        if stored.entries.len() == before {
+            return Err(anyhow!("No schedule entry named '{name}'"));
+        }
+        stored.save()?;
+    }
+    log!(Level::Info, "Removed schedule entry '{}'", name);
+    evaluate();
+    Ok(get())
+}
+
+/**
+ * Get the IDs of the games the active entry hides from the menu
+ */
+pub fn blocked() -> Vec<String> {
+    active()
+        .map(|entry| entry.blocked_games)
+        .unwrap_or_default()
+}
+
+/**
+ * Keep the games an entry features: those it lists, in its order, then those with its tag
+ */
+fn featured_by(
+    entry: &ScheduleEntry,
+    tagged: &BTreeSet<String>,
+    mut games: Vec<DevcadeGame>,
+) -> Vec<DevcadeGame> {
+    let has_tag = |game: &DevcadeGame| {
+        tagged.contains(&game.id)
+            || entry
+                .featured_tag
+                .as_ref()
+                .is_some_and(|tag| game.tags.iter().any(|t| t.name == *tag))
+    };
+    games.retain(|game| entry.featured_games.contains(&game.id) || has_tag(game));
+    games.sort_by_key(|game| {
+        entry
+            .featured_games
+            .iter()
+            .position(|id| *id == game.id)
+            .unwrap_or(usize::MAX)
+    });
+    games
+}
+
+/**
+ * Keep the games the active entry features, or none if no entry is active
+ */
+pub fn featured(games: Vec<DevcadeGame>) -> Vec<DevcadeGame> {
+    let Some(entry) = active() else {
+        return Vec::new();
+    };
+    let tagged = entry
+        .featured_tag
+        .as_ref()
+        .and_then(|tag| cached_tag_membership()?.tags.remove(tag))
+        .unwrap_or_default()
+        .into_iter()
+        .collect();
+    featured_by(&entry, &tagged, games)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use devcade_onboard_types::schema::Tag;
+
+    fn entry(name: &str, start: &str, end: &str) -> ScheduleEntry {
+        ScheduleEntry {
+            name: name.to_string(),
+            start: start.to_string(),
+            end: end.to_string(),
+            ..ScheduleEntry::default()
+        }
+    }
+
+    fn game(id: &str, tags: &[&str]) -> DevcadeGame {
+        DevcadeGame {
+            id: id.to_string(),
+            tags: tags
+                .iter()
+                .map(|name| Tag {
+                    name: (*name).to_string(),
+                    description: String::new(),
+                })
+                .collect(),
+            ..DevcadeGame::default()
+        }
+    }
+
+    #[test]
+    fn dates_are_checked() {
+        assert_eq!(day_number("1970-01-01"), Some(0));
+        assert_eq!(day_number("2024-03-01"), Some(19_783));
+        assert_eq!(
+            day_number("2024-02-29").map(|day| day + 1),
+            day_number("2024-03-01")
+        );
+        assert_eq!(day_number("2023-02-29"), None);
+        assert_eq!(day_number("2024-13-01"), None);
+        assert_eq!(day_number("2024-1-01"), None);
+        assert_eq!(day_number("tomorrow"), None);
+
+        assert!(validate(&entry("halloween", "2024-10-25", "2024-10-31")).is_ok());
+        assert!(validate(&entry("backwards", "2024-10-31", "2024-10-25")).is_err());
+        assert!(validate(&entry("bad name", "2024-10-25", "2024-10-31")).is_err());
+    }
+
+    #[test]
+    fn most_specific_entry_wins() {
+        let entries = vec![
+            entry("october", "2024-10-01", "2024-10-31"),
+            entry("halloween", "2024-10-25", "2024-10-31"),
+            entry("spooky", "2024-10-28", "2024-11-03"),
+            entry("party", "2024-10-31", "2024-10-31"),
+        ];
+        let active = |date| active_on(&entries, date).map(|entry| entry.name.as_str());
+        assert_eq!(active("2024-09-30"), None);
+        assert_eq!(active("2024-10-10"), Some("october"));
+        assert_eq!(active("2024-10-26"), Some("halloween"));
+        // Same length, so the one that started later wins
+        assert_eq!(active("2024-10-29"), Some("spooky"));
+        assert_eq!(active("2024-10-31"), Some("party"));
+        assert_eq!(active("2024-11-02"), Some("spooky"));
+    }
+
+    #[test]
+    fn unknown_tags_and_games_are_warned_about() {
+        let mut halloween = entry("halloween", "2024-10-25", "2024-10-31");
+        halloween.featured_tag = Some("horror".to_string());
+        halloween.featured_games = vec!["ghosts".to_string(), "gone".to_string()];
+        halloween.blocked_games = vec!["bunnies".to_string()];
+        let games = BTreeSet::from(["ghosts".to_string(), "bunnies".to_string()]);
+
+        let warnings = unknown(std::slice::from_ref(&halloween), &games, None);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("'gone'"));
+
+        let tags = BTreeSet::from(["puzzle".to_string()]);
+        let warnings = unknown(std::slice::from_ref(&halloween), &games, Some(&tags));
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].contains("'horror'"));
+    }
+
+    #[test]
+    fn listed_games_come_before_tagged_ones() {
+        let mut halloween = entry("halloween", "2024-10-25", "2024-10-31");
+        halloween.featured_tag = Some("horror".to_string());
+        halloween.featured_games = vec!["pumpkins".to_string(), "ghosts".to_string()];
+        let tagged = BTreeSet::from(["zombies".to_string()]);
+        let games = vec![
+            game("bunnies", &["cute"]),
+            game("ghosts", &[]),
+            game("vampires", &["horror"]),
+            game("zombies", &[]),
+            game("pumpkins", &[]),
+        ];
+        let featured: Vec<String> = featured_by(&halloween, &tagged, games)
+            .into_iter()
+            .map(|game| game.id)
+            .collect();
+        assert_eq!(featured, ["pumpkins", "ghosts", "vampires", "zombies"]);
+    }
+}
@@ -0,0 +1,336 @@
+use crate::clock;
+use crate::layout;
+use crate::state::{self, JsonState};
+use anyhow::{anyhow, Error};
+use devcade_onboard_types::{GameHighlights, Map, ResourceSample, Value};
+use lazy_static::lazy_static;
+use log::{log, Level};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/**
+ * The largest summary kept, in bytes of JSON after sanitizing
+ */
+const MAX_SUMMARY_BYTES: usize = 4096;
+/**
+ * How deeply objects and arrays can nest in a summary. Anything deeper is replaced by `null`.
+ */
+const MAX_DEPTH: usize = 4;
+/**
+ * The longest string (and object key) kept in a summary, in characters. Longer strings are
+ * truncated and longer keys are dropped.
+ */
+const MAX_STRING_LENGTH: usize = 256;
+/**
+ * How many days count as recent play time
+ */
+const RECENT_DAYS: u64 = 7;
+/**
+ * Seconds in a day
+ */
+const DAY: u64 = 24 * 60 * 60;
+
+lazy_static! {
+    // The running game's ID and the summary it submitted
+    static ref SUMMARY: Mutex<Option<(String, Map<String, Value>)>> = Mutex::new(None);
+    // Held while the session log is written, so compaction can't lose a session logged meanwhile
+    static ref LOG: Mutex<()> = Mutex::new(());
+    static ref HIGHLIGHTS: JsonState<BTreeMap<String, GameHighlights>> =
+        JsonState::new(highlights_path);
+    static ref PLAYTIME: JsonState<BTreeMap<String, Playtime>> = JsonState::new(playtime_path);
+    // Highlights of the sessions played during each event, by event label and then game ID
+    static ref EVENT_HIGHLIGHTS: JsonState<BTreeMap<String, BTreeMap<String, GameHighlights>>> =
+        JsonState::new(event_highlights_path);
+}
+
+/**
+ * How long a game has been played, kept up to date as its sessions end
+ */
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub(super) struct Playtime {
+    /// Seconds played in total
+    pub seconds: u64,
+    /// Seconds played on each of the last `RECENT_DAYS` days, by days since the Unix epoch
+    days: BTreeMap<u64, u64>,
+}
+
+impl Playtime {
+    /**
+     * Get the seconds played in the last `RECENT_DAYS` days
+     */
+    pub fn recent_seconds(&self) -> u64 {
+        let since = (clock::unix_now() / DAY).saturating_sub(RECENT_DAYS - 1);
+        self.days.range(since..).map(|(_, seconds)| seconds).sum()
+    }
+}
+
+/**
+ * A session as it's logged
+ */
+#[derive(Serialize)]
+struct Session<'a> {
+    game_id: &'a str,
+    started: u64,
+    seconds: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    summary: Option<&'a Map<String, Value>>,
+    /// The game's peak resource usage
+    #[serde(skip_serializing_if = "Option::is_none")]
+    peak: Option<&'a ResourceSample>,
+    /// The event label active when the session started
+    #[serde(skip_serializing_if = "Option::is_none")]
+    event: Option<&'a str>,
+    /// Whether a demo guest played it
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    demo: bool,
+}
+
+fn log_path() -> PathBuf {
+    layout::state_dir().join("sessions.jsonl")
+}
+
+/**
+ * Read the session log
+ *
+ * # Errors
+ * This function will return an error if the log exists but can't be read.
+ */
+pub(super) fn read_log() -> Result<String, Error> {
+    let _log = LOG.lock().unwrap();
+    match std::fs::read_to_string(log_path()) {
+        Ok(log) => Ok(log),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/**
+ * Replace the session log with what `rewrite` makes of it, without any session being logged in
+ * between
+ *
+ * # Errors
+ * This function will return an error if the log can't be read or written, or `rewrite` fails.
+ */
+pub(super) fn rewrite_log<T>(
+    rewrite: impl FnOnce(&str) -> Result<(String, T), Error>,
+) -> Result<T, Error> {
+    let _log = LOG.lock().unwrap();
+    let path = log_path();
+    let log = match std::fs::read_to_string(&path) {
+        Ok(log) => log,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e.into()),
+    };
+    let (rewritten, result) = rewrite(log.as_str())?;
+    if rewritten != log {
+        layout::write_atomic(&path, rewritten)?;
+    }
+    Ok(result)
+}
+
+fn highlights_path() -> PathBuf {
+    layout::state_dir().join("highlights.json")
+}
+
+fn playtime_path() -> PathBuf {
+    layout::state_dir().join("playtime.json")
+}
+
+fn event_highlights_path() -> PathBuf {
+    layout::state_dir().join("event_highlights.json")
+}
+
+/**
+ * Keep a summary value within the depth and string length limits
+ */
+fn sanitize(value: Value, depth: usize) -> Value {
+    match value {
+        Value::Array(_) | Value::Object(_) if depth >= MAX_DEPTH => Value::Null,
+        Value::Array(values) => Value::Array(
+            values
+                .into_iter()
+                .map(|value| sanitize(value, depth + 1))
+                .collect(),
+        ),
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .filter(|(key, _)| key.chars().count() <= MAX_STRING_LENGTH)
+                .map(|(key, value)| (key, sanitize(value, depth + 1)))
+                .collect(),
+        ),
+        Value::String(s) => Value::String(s.chars().take(MAX_STRING_LENGTH).collect()),
+        value => value,
+    }
+}
+
+/**
+ * Keep the running game's summary until its session ends, replacing any it submitted earlier
+ *
+ * # Errors
+ * This function will return an error if the summary is too large once sanitized.
+ */
+pub fn submit(game_id: &str, data: Map<String, Value>) -> Result<(), Error> {
+    let Value::Object(data) = sanitize(Value::Object(data), 0) else {
+        unreachable!("sanitizing an object returns an object");
+    };
+    let size = serde_json::to_vec(&data)?.len();
+    if size > MAX_SUMMARY_BYTES {
+        return Err(anyhow!(
+            "Session summary is {size} bytes, the most allowed is {MAX_SUMMARY_BYTES}"
+        ));
+    }
+    log!(
+        Level::Debug,
+        "Game {} submitted a session summary with {} keys",
+        game_id,
+        data.len()
+    );
+    *SUMMARY.lock().unwrap() = Some((game_id.to_string(), data));
+    Ok(())
+}
+
+/**
+ * Get the current local date as `YYYY-MM-DD`
+ */
+pub(super) fn today() -> String {
+    let tm = clock::local_time();
+    format!(
+        "{:04}-{:02}-{:02}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday
+    )
+}
+
+/**
+ * Fold a session's summary into its game's highlights
+ */
+fn update(highlights: &mut GameHighlights, summary: &Map<String, Value>) {
+    highlights.sessions += 1;
+    if summary.get("completed").and_then(Value::as_bool) == Some(true) {
+        highlights.completed += 1;
+    }
+    if summary
+        .get("result")
+        .and_then(Value::as_str)
+        .is_some_and(|result| result.eq_ignore_ascii_case("win"))
+    {
+        highlights.wins += 1;
+    }
+
+    let today = today();
+    if highlights.day != today {
+        highlights.day = today;
+        highlights.day_high_score = None;
+    }
+    if let Some(score) = summary.get("score").and_then(Value::as_f64) {
+        let max = |best: Option<f64>| Some(best.map_or(score, |best| best.max(score)));
+        highlights.high_score = max(highlights.high_score);
+        highlights.day_high_score = max(highlights.day_high_score);
+    }
+}
+
+/**
+ * Add a session to its game's play time, forgetting days that are no longer recent
+ */
+fn add_playtime(game_id: &str, seconds: u64) -> Result<(), Error> {
+    let today = clock::unix_now() / DAY;
+    let mut playtime = PLAYTIME.lock();
+    let game = playtime.entry(game_id.to_string()).or_default();
+    game.seconds += seconds;
+    *game.days.entry(today).or_default() += seconds;
+    game.days
+        .retain(|day, _| today.saturating_sub(*day) < RECENT_DAYS);
+    playtime.save()
+}
+
+/**
+ * End the running game's session, adding it to the game's play time, logging it, and updating the
+ * game's highlights (and the event's, if it was started during one) if it submitted a summary.
+ * Sessions played by demo guests are logged as demo plays.
+ *
+ * # Errors
+ * This function will return an error if the play time, session log or highlights cannot be
+ * written.
+ */
+pub fn finish(
+    game_id: &str,
+    started: u64,
+    seconds: u64,
+    peak: Option<&ResourceSample>,
+    event: Option<&str>,
+    demo: bool,
+) -> Result<(), Error> {
+    let summary = SUMMARY
+        .lock()
+        .unwrap()
+        .take()
+        .filter(|(submitter, _)| submitter == game_id)
+        .map(|(_, summary)| summary);
+    add_playtime(game_id, seconds)?;
+
+    let session = Session {
+        game_id,
+        started,
+        seconds,
+        summary: summary.as_ref(),
+        peak,
+        event,
+        demo,
+    };
+    {
+        let _log = LOG.lock().unwrap();
+        state::journal(log_path().as_path(), &session)?;
+    }
+
+    let Some(summary) = summary else {
+        return Ok(());
+    };
+    if let Some(event) = event {
+        let mut highlights = EVENT_HIGHLIGHTS.lock();
+        let event = highlights.entry(event.to_string()).or_default();
+        update(event.entry(game_id.to_string()).or_default(), &summary);
+        highlights.save()?;
+    }
+    let mut highlights = HIGHLIGHTS.lock();
+    update(highlights.entry(game_id.to_string()).or_default(), &summary);
+    highlights.save()
+}
+
+/**
+ * Get every game's highlights. Today's high scores from an earlier day are left out.
+ */
+pub fn highlights() -> BTreeMap<String, GameHighlights> {
+    let today = today();
+    HIGHLIGHTS
+        .lock()
+        .iter()
+        .map(|(id, game)| {
+            let mut game = game.clone();
+            if game.day != today {
+                game.day_high_score = None;
+            }
+            (id.clone(), game)
+        })
+        .collect()
+}
+
+/**
+ * Get the highlights of every game played during an event, by game ID
+ */
+pub fn event_highlights(event: &str) -> BTreeMap<String, GameHighlights> {
+    EVENT_HIGHLIGHTS
+        .lock()
+        .get(event)
+        .cloned()
+        .unwrap_or_default()
+}
+
+/**
+ * Get every game's play time, by game ID
+ */
+pub(super) fn playtime() -> BTreeMap<String, Playtime> {
+    PLAYTIME.lock().clone()
+}
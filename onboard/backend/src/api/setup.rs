@@ -0,0 +1,270 @@
+use super::network::{self, Priority};
+use super::route;
+use crate::env::{config_file, try_api_url};
+use crate::layout;
+use anyhow::{anyhow, Error};
+use devcade_onboard_types::{SetupStatus, Value};
+use lazy_static::lazy_static;
+use log::{log, Level};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Mutex;
+
+/**
+ * The settings setup may change, with the variables they're kept in
+ */
+const KEYS: &[(&str, &str)] = &[
+    ("api_url", "DEVCADE_API_DOMAIN"),
+    ("cabinet_name", "DEVCADE_CABINET_NAME"),
+    ("locale", "DEVCADE_LOCALE"),
+    ("data_root", "DEVCADE_PATH"),
+];
+
+/**
+ * The longest value of a setting
+ */
+const MAX_VALUE_LENGTH: usize = 256;
+
+lazy_static! {
+    // Settings set during setup that haven't been written yet, by variable
+    static ref PENDING: Mutex<BTreeMap<&'static str, String>> = Mutex::new(BTreeMap::new());
+}
+
+/**
+ * Get the variable a setting is kept in
+ */
+fn var(key: &str) -> Result<&'static str, Error> {
+    KEYS.iter()
+        .find(|(name, _)| *name == key)
+        .map(|(_, var)| *var)
+        .ok_or_else(|| {
+            let keys: Vec<_> = KEYS.iter().map(|(name, _)| *name).collect();
+            anyhow!(
+                "Setting '{key}' can't be set during setup, expected one of {}",
+                keys.join(", ")
+            )
+        })
+}
+
+/**
+ * Check a setting's value, the same whether it came from the config file or setup
+ */
+fn validate(var: &str, value: &str) -> Result<(), Error> {
+    if value.is_empty() || value.len() > MAX_VALUE_LENGTH || value.contains(char::is_control) {
+        return Err(anyhow!(
+            "{var} must be 1 to {MAX_VALUE_LENGTH} characters without control characters"
+        ));
+    }
+    let valid = match var {
+        // A host name with an optional port, since `https://` is added to it
+        "DEVCADE_API_DOMAIN" => value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | ':')),
+        "DEVCADE_LOCALE" => value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | '@')),
+        "DEVCADE_PATH" => Path::new(value).is_absolute(),
+        _ => true,
+    };
+    if valid {
+        Ok(())
+    } else {
+        Err(anyhow!("Invalid value '{value}' for {var}"))
+    }
+}
+
+/**
+ * Get the settings that have to be set before setup can be completed
+ */
+fn missing(pending: &BTreeMap<&'static str, String>) -> Vec<String> {
+    if try_api_url().is_none() && !pending.contains_key("DEVCADE_API_DOMAIN") {
+        vec![String::from("api_url")]
+    } else {
+        Vec::new()
+    }
+}
+
+/**
+ * Check the settings loaded from the config file, logging the ones setup would refuse, and
+ * whether setup is required
+ */
+pub fn check() {
+    for (_, var) in KEYS {
+        if let Ok(value) = std::env::var(var) {
+            if let Err(e) = validate(var, value.as_str()) {
+                log!(Level::Warn, "{} in {}: {}", var, config_file(), e);
+            }
+        }
+    }
+    if required() {
+        log!(
+            Level::Warn,
+            "SETUP REQUIRED: the API isn't configured. Games can't be listed until setup is \
+            completed from the frontend, or {} is filled in.",
+            config_file()
+        );
+    }
+}
+
+/**
+ * Whether setup has to be completed before games can be listed
+ */
+pub fn required() -> bool {
+    try_api_url().is_none()
+}
+
+/**
+ * Get what setup still needs
+ */
+pub fn status() -> SetupStatus {
+    let pending = PENDING.lock().unwrap();
+    SetupStatus {
+        required: required(),
+        missing: missing(&pending),
+        pending: KEYS
+            .iter()
+            .filter(|(_, var)| pending.contains_key(var))
+            .map(|(name, _)| (*name).to_string())
+            .collect(),
+    }
+}
+
+/**
+ * Set a setting to be written when setup is completed
+ *
+ * # Errors
+ * This function will return an error if setup isn't required, the setting can't be set during
+ * setup, or the value is invalid.
+ */
+pub fn set(key: &str, value: &str) -> Result<(), Error> {
+    if !required() {
+        return Err(anyhow!(
+            "Setup is already complete, change {} instead",
+            config_file()
+        ));
+    }
+    let var = var(key)?;
+    validate(var, value)?;
+    PENDING.lock().unwrap().insert(var, value.to_string());
+    Ok(())
+}
+
+/**
+ * List the games of the API being set up, to check it can be reached
+ *
+ * # Errors
+ * This function will return an error if no API is set, or it can't be reached.
+ */
+pub async fn test_connection() -> Result<(), Error> {
+    let domain = PENDING.lock().unwrap().get("DEVCADE_API_DOMAIN").cloned();
+    let url = domain
+        .map(|domain| format!("https://{domain}"))
+        .or_else(try_api_url)
+        .ok_or_else(|| anyhow!("Set api_url before testing the connection"))?;
+    let games: Vec<Value> = network::request_json_uncached(
+        format!("{url}/{}", route::game_list()).as_str(),
+        Priority::Interactive,
+    )
+    .await?;
+    log!(
+        Level::Info,
+        "API at {} listed {} games during setup",
+        url,
+        games.len()
+    );
+    Ok(())
+}
+
+/**
+ * Replace or add `VAR=value` lines in the contents of a config file
+ */
+fn update_config(contents: &str, values: &BTreeMap<&'static str, String>) -> String {
+    let mut lines: Vec<String> = contents.lines().map(str::to_string).collect();
+    for (var, value) in values {
+        let line = format!("{var}={value}");
+        let prefix = format!("{var}=");
+        match lines.iter_mut().find(|line| line.starts_with(&prefix)) {
+            Some(existing) => *existing = line,
+            None => lines.push(line),
+        }
+    }
+    let mut contents = lines.join("\n");
+    contents.push('\n');
+    contents
+}
+
+/**
+ * Write the settings set during setup to the config file and apply them, leaving setup without a
+ * restart
+ *
+ * # Errors
+ * This function will return an error if a required setting is missing, or the config file can't be
+ * written.
+ */
+pub fn complete() -> Result<(), Error> {
+    let mut pending = PENDING.lock().unwrap();
+    let missing = missing(&pending);
+    if !missing.is_empty() {
+        return Err(anyhow!(
+            "Setup can't be completed without {}",
+            missing.join(", ")
+        ));
+    }
+    let path = config_file();
+    let contents = match std::fs::read_to_string(path.as_str()) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e.into()),
+    };
+    layout::write_atomic(
+        Path::new(path.as_str()),
+        update_config(contents.as_str(), &pending),
+    )?;
+    for (var, value) in pending.iter() {
+        std::env::set_var(var, value);
+    }
+    log!(
+        Level::Info,
+        "Setup completed, wrote {} settings to {}",
+        pending.len(),
+        path
+    );
+    pending.clear();
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn setup_writes_config_and_leaves_setup() {
+        let dir = std::env::temp_dir().join(format!("devcade-setup-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let config = dir.join(".env");
+        std::fs::write(&config, "RUST_LOG=info\nDEVCADE_API_DOMAIN=\n").unwrap();
+        std::env::set_var("DEVCADE_CONFIG_FILE", &config);
+        std::env::remove_var("DEVCADE_API_DOMAIN");
+
+        assert!(required());
+        assert_eq!(status().missing, vec!["api_url".to_string()]);
+        assert!(complete().is_err());
+        assert!(set("api_url", "https://api.example.com").is_err());
+        assert!(set("rust_log", "trace").is_err());
+        assert!(set("data_root", "relative/path").is_err());
+        set("api_url", "api.example.com").unwrap();
+        set("cabinet_name", "Cabinet 1").unwrap();
+        assert_eq!(status().pending, vec!["api_url", "cabinet_name"]);
+
+        complete().unwrap();
+        assert!(!required());
+        assert_eq!(try_api_url().as_deref(), Some("https://api.example.com"));
+        assert_eq!(
+            std::fs::read_to_string(&config).unwrap(),
+            "RUST_LOG=info\nDEVCADE_API_DOMAIN=api.example.com\n\
+            DEVCADE_CABINET_NAME=Cabinet 1\n"
+        );
+        assert!(set("api_url", "other.example.com").is_err());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
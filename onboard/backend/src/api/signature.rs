@@ -0,0 +1,235 @@
+use crate::env::{signature_policy, signing_keys};
+use anyhow::{anyhow, Error};
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use log::{log, Level};
+use std::fmt;
+use std::str::FromStr;
+
+/**
+ * What to do with games that don't have a signature
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    Warn,
+    Enforce,
+}
+
+impl FromStr for Policy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "warn" => Ok(Self::Warn),
+            "enforce" => Ok(Self::Enforce),
+            _ => Err(anyhow!("Unknown signature policy {s}")),
+        }
+    }
+}
+
+/**
+ * The outcome of checking a game's signature
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verification {
+    /// Signed by the trusted key at this index of `DEVCADE_SIGNING_KEYS`
+    Valid(usize),
+    /// No signature was published, and the policy allows that
+    Unsigned,
+    /// No keys are configured, so nothing was checked
+    Skipped,
+}
+
+impl fmt::Display for Verification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Valid(key) => write!(f, "signed by trusted key {key}"),
+            Self::Unsigned => write!(f, "unsigned"),
+            Self::Skipped => write!(f, "not checked, no signing keys configured"),
+        }
+    }
+}
+
+/**
+ * Get the configured signature policy. Invalid values are logged and treated as `warn`.
+ */
+fn policy() -> Policy {
+    signature_policy()
+        .and_then(|policy| {
+            policy
+                .parse()
+                .map_err(|e| log!(Level::Warn, "Ignoring invalid signature policy: {}", e))
+                .ok()
+        })
+        .unwrap_or(Policy::Warn)
+}
+
+/**
+ * Get the trusted publisher keys. Invalid keys are logged and skipped.
+ */
+fn trusted_keys() -> Vec<VerifyingKey> {
+    signing_keys()
+        .iter()
+        .filter_map(|key| {
+            let key = decode_hex(key)
+                .and_then(|bytes| bytes.try_into().ok())
+                .and_then(|bytes: [u8; 32]| VerifyingKey::from_bytes(&bytes).ok());
+            if key.is_none() {
+                log!(Level::Warn, "Ignoring invalid signing key");
+            }
+            key
+        })
+        .collect()
+}
+
+/**
+ * Whether any signing keys are configured, and signatures should be checked
+ */
+pub fn enabled() -> bool {
+    !signing_keys().is_empty()
+}
+
+/**
+ * Check a game's signature over its hash against the trusted keys. `signature` is the
+ * published hex signature, or `None` if the game has none.
+ *
+ * # Errors
+ * This function will return an error if the signature is invalid, or if the game is unsigned
+ * and the policy is `enforce`.
+ */
+pub fn verify(game_id: &str, hash: &str, signature: Option<&str>) -> Result<Verification, Error> {
+    let keys = trusted_keys();
+    if keys.is_empty() {
+        return Ok(Verification::Skipped);
+    }
+
+    let Some(signature) = signature else {
+        return match policy() {
+            Policy::Warn => {
+                log!(Level::Warn, "Game {} is not signed", game_id);
+                Ok(Verification::Unsigned)
+            }
+            Policy::Enforce => Err(anyhow!("Game {game_id} is not signed")),
+        };
+    };
+
+    let signature = decode_hex(signature.trim())
+        .and_then(|bytes| Signature::from_slice(&bytes).ok())
+        .ok_or_else(|| anyhow!("Game {game_id} has a malformed signature"))?;
+    keys.iter()
+        .position(|key| key.verify(hash.as_bytes(), &signature).is_ok())
+        .map(Verification::Valid)
+        .ok_or_else(|| anyhow!("Game {game_id} is not signed by a trusted key"))
+}
+
+/**
+ * Decode a hex string, returning `None` if it isn't valid hex
+ */
+pub fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    const HASH: &str = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{b:02x}")).collect()
+    }
+
+    fn fixture_key(seed: u8) -> SigningKey {
+        SigningKey::from_bytes(&[seed; 32])
+    }
+
+    fn sign(key: &SigningKey, hash: &str) -> String {
+        hex(&key.sign(hash.as_bytes()).to_bytes())
+    }
+
+    /**
+     * Trust the fixture keys with these seeds, under a policy
+     */
+    fn configure(seeds: &[u8], policy: &str) {
+        let keys: Vec<String> = seeds
+            .iter()
+            .map(|seed| hex(fixture_key(*seed).verifying_key().as_bytes()))
+            .collect();
+        std::env::set_var("DEVCADE_SIGNING_KEYS", keys.join(","));
+        std::env::set_var("DEVCADE_SIGNATURE_POLICY", policy);
+    }
+
+    fn unconfigure() {
+        std::env::remove_var("DEVCADE_SIGNING_KEYS");
+        std::env::remove_var("DEVCADE_SIGNATURE_POLICY");
+    }
+
+    #[test]
+    fn signatures_are_checked_under_both_policies() {
+        // The environment is shared, so this holds the same lock as tests that use it
+        let (_guard, root) = crate::testing::root("signature");
+        let trusted = fixture_key(1);
+        let untrusted = fixture_key(2);
+        for policy in ["warn", "enforce"] {
+            configure(&[1], policy);
+            assert_eq!(
+                verify("pong", HASH, Some(sign(&trusted, HASH).as_str())).unwrap(),
+                Verification::Valid(0),
+                "{policy}"
+            );
+            // Signed by a key that isn't trusted, over another hash, or not hex at all
+            assert!(verify("pong", HASH, Some(sign(&untrusted, HASH).as_str())).is_err());
+            assert!(verify("pong", HASH, Some(sign(&trusted, "tampered").as_str())).is_err());
+            assert!(verify("pong", HASH, Some("not a signature")).is_err());
+        }
+
+        configure(&[1], "warn");
+        assert_eq!(verify("pong", HASH, None).unwrap(), Verification::Unsigned);
+        configure(&[1], "enforce");
+        assert!(verify("pong", HASH, None).is_err());
+        // An invalid policy is treated as warn
+        configure(&[1], "sometimes");
+        assert_eq!(verify("pong", HASH, None).unwrap(), Verification::Unsigned);
+
+        unconfigure();
+        assert!(!enabled());
+        assert_eq!(
+            verify("pong", HASH, Some(sign(&untrusted, HASH).as_str())).unwrap(),
+            Verification::Skipped
+        );
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn any_trusted_key_is_accepted_while_keys_rotate() {
+        let (_guard, root) = crate::testing::root("signature-rotation");
+        let signature = sign(&fixture_key(2), HASH);
+        configure(&[1, 2], "enforce");
+        assert_eq!(
+            verify("pong", HASH, Some(signature.as_str())).unwrap(),
+            Verification::Valid(1)
+        );
+        // The old key is retired
+        configure(&[2, 3], "enforce");
+        assert!(verify("pong", HASH, Some(sign(&fixture_key(1), HASH).as_str())).is_err());
+        assert_eq!(
+            verify("pong", HASH, Some(signature.as_str())).unwrap(),
+            Verification::Valid(0)
+        );
+        unconfigure();
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn hex_is_decoded() {
+        assert_eq!(decode_hex("00ff7A"), Some(vec![0, 255, 122]));
+        assert_eq!(decode_hex("abc"), None);
+        assert_eq!(decode_hex("zz"), None);
+    }
+}
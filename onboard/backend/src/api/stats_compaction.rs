@@ -0,0 +1,316 @@
+use super::sessions;
+use crate::clock;
+use crate::layout;
+use crate::state::{self, JsonState};
+use anyhow::Error;
+use devcade_onboard_types::{LifetimeStats, Map, SessionExport, StatsCompaction, Value};
+use lazy_static::lazy_static;
+use log::{log, Level};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/**
+ * Seconds in a day
+ */
+const DAY: u64 = 24 * 60 * 60;
+
+lazy_static! {
+    static ref COMPACTED: JsonState<Compacted> = JsonState::new(path);
+}
+
+/**
+ * The sessions folded out of the log
+ */
+#[derive(Serialize, Deserialize, Default)]
+struct Compacted {
+    /// Sessions that started before this were folded into `games`. It never goes back, and logged
+    /// sessions before it are ignored, so they're never counted twice.
+    before: u64,
+    /// Totals of the folded sessions by game ID
+    games: BTreeMap<String, LifetimeStats>,
+    /// The last day the log was compacted during the auto-update window, in days since the Unix
+    /// epoch
+    last_nightly: u64,
+}
+
+/**
+ * The parts of a logged session that are added up
+ */
+#[derive(Deserialize)]
+struct Row {
+    game_id: String,
+    started: u64,
+    seconds: u64,
+    #[serde(default)]
+    demo: bool,
+}
+
+fn path() -> PathBuf {
+    layout::state_dir().join("lifetime_stats.json")
+}
+
+fn journal_path() -> PathBuf {
+    layout::state_dir().join("compactions.journal")
+}
+
+/**
+ * Add a session to a game's totals
+ */
+fn add(stats: &mut LifetimeStats, row: &Row) {
+    let Row {
+        started,
+        seconds,
+        demo,
+        ..
+    } = *row;
+    stats.first_played = if stats.plays == 0 {
+        started
+    } else {
+        stats.first_played.min(started)
+    };
+    stats.last_played = stats.last_played.max(started);
+    stats.plays += 1;
+    stats.seconds += seconds;
+    if demo {
+        stats.demo_plays += 1;
+    }
+}
+
+impl Compacted {
+    /**
+     * Fold the logged sessions that started before `cutoff` into the totals. Returns the log
+     * without them and how many were folded. Lines that can't be read are kept as they are.
+     */
+    fn fold(&mut self, log: &str, cutoff: u64) -> (String, u64) {
+        let cutoff = cutoff.max(self.before);
+        let mut kept = String::new();
+        let mut folded = 0;
+        for line in log.lines() {
+            match serde_json::from_str::<Row>(line) {
+                Ok(row) if row.started < cutoff => {
+                    // Sessions before the last cutoff were folded by a compaction that didn't get
+                    // to rewrite the log
+                    if row.started >= self.before {
+                        add(self.games.entry(row.game_id.clone()).or_default(), &row);
+                        folded += 1;
+                    }
+                }
+                _ => {
+                    kept.push_str(line);
+                    kept.push('\n');
+                }
+            }
+        }
+        self.before = cutoff;
+        (kept, folded)
+    }
+
+    /**
+     * Get every game's totals, from the folded sessions and the ones still in the log
+     */
+    fn lifetime(&self, log: &str) -> BTreeMap<String, LifetimeStats> {
+        let mut games = self.games.clone();
+        for row in log
+            .lines()
+            .filter_map(|line| serde_json::from_str::<Row>(line).ok())
+        {
+            if row.started >= self.before {
+                add(games.entry(row.game_id.clone()).or_default(), &row);
+            }
+        }
+        games
+    }
+
+    /**
+     * Get the logged sessions that started in `start..end`, marking the part of the range that was
+     * folded
+     */
+    fn export(&self, log: &str, start: u64, end: u64) -> SessionExport {
+        let sessions = log
+            .lines()
+            .filter_map(|line| serde_json::from_str::<Map<String, Value>>(line).ok())
+            .filter(|session| {
+                session
+                    .get("started")
+                    .and_then(Value::as_u64)
+                    .is_some_and(|started| started >= self.before.max(start) && started < end)
+            })
+            .collect();
+        SessionExport {
+            start,
+            end,
+            aggregate_only_until: (start < self.before).then(|| self.before.min(end)),
+            compacted: self.games.clone(),
+            sessions,
+        }
+    }
+}
+
+/**
+ * Fold the sessions that started more than `retain_days` ago into each game's lifetime totals, and
+ * remove them from the session log. Compacting again with the same or more days does nothing.
+ *
+ * # Errors
+ * This function will return an error if the totals or the session log can't be written.
+ */
+pub fn compact(retain_days: u64) -> Result<StatsCompaction, Error> {
+    let now = clock::unix_now();
+    let cutoff = now.saturating_sub(retain_days.saturating_mul(DAY));
+    let result = sessions::rewrite_log(|log| {
+        let mut compacted = COMPACTED.lock();
+        let games_before = compacted.games.clone();
+        let (kept, sessions) = compacted.fold(log, cutoff);
+        let games = compacted
+            .games
+            .iter()
+            .filter(|(id, stats)| games_before.get(*id) != Some(stats))
+            .count();
+        // Saved before the log is rewritten. If the rewrite fails, the sessions left in the log
+        // are before the cutoff, so they aren't counted again.
+        compacted.save()?;
+        let result = StatsCompaction {
+            at: now,
+            before: compacted.before,
+            sessions,
+            games: games as u64,
+        };
+        Ok((kept, result))
+    })?;
+    state::journal(journal_path().as_path(), &result)?;
+    log!(
+        Level::Info,
+        "Compacted {} sessions of {} games from before {}",
+        result.sessions,
+        result.games,
+        result.before
+    );
+    Ok(result)
+}
+
+/**
+ * Compact the session log if it wasn't already today, for the auto-update window
+ */
+pub fn nightly(retain_days: u64) {
+    let today = clock::unix_now() / DAY;
+    if COMPACTED.lock().last_nightly == today {
+        return;
+    }
+    if let Err(e) = compact(retain_days) {
+        log!(Level::Warn, "Couldn't compact the session log: {}", e);
+        return;
+    }
+    let mut compacted = COMPACTED.lock();
+    compacted.last_nightly = today;
+    if let Err(e) = compacted.save() {
+        log!(
+            Level::Warn,
+            "Couldn't save when stats were compacted: {}",
+            e
+        );
+    }
+}
+
+/**
+ * Get every game's lifetime totals, by game ID
+ *
+ * # Errors
+ * This function will return an error if the session log can't be read.
+ */
+pub fn lifetime() -> Result<BTreeMap<String, LifetimeStats>, Error> {
+    let log = sessions::read_log()?;
+    Ok(COMPACTED.lock().lifetime(log.as_str()))
+}
+
+/**
+ * Get the logged sessions that started in a range of time
+ *
+ * # Errors
+ * This function will return an error if the session log can't be read.
+ */
+pub fn export(start: u64, end: u64) -> Result<SessionExport, Error> {
+    let log = sessions::read_log()?;
+    Ok(COMPACTED.lock().export(log.as_str(), start, end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log(sessions: &[(&str, u64, u64)]) -> String {
+        sessions
+            .iter()
+            .map(|(game_id, started, seconds)| {
+                format!(
+                    "{{\"game_id\":\"{game_id}\",\"started\":{started},\"seconds\":{seconds}}}\n"
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn compacting_again_changes_nothing() {
+        let log = log(&[("pong", 100, 10), ("snake", 150, 5), ("pong", 300, 20)]);
+        let lifetime = Compacted::default().lifetime(log.as_str());
+
+        let mut compacted = Compacted::default();
+        let (kept, folded) = compacted.fold(log.as_str(), 200);
+        assert_eq!(folded, 2);
+        assert_eq!(kept.lines().count(), 1);
+        assert_eq!(compacted.lifetime(kept.as_str()), lifetime);
+        assert_eq!(
+            lifetime["pong"],
+            LifetimeStats {
+                plays: 2,
+                seconds: 30,
+                first_played: 100,
+                last_played: 300,
+                demo_plays: 0,
+            }
+        );
+
+        // Again, and again as if the log hadn't been rewritten the first time
+        for log in [kept.clone(), log] {
+            let (again, folded) = compacted.fold(log.as_str(), 200);
+            assert_eq!(folded, 0);
+            assert_eq!(again, kept);
+            assert_eq!(compacted.lifetime(again.as_str()), lifetime);
+        }
+        // An earlier cutoff doesn't bring anything back
+        assert_eq!(compacted.fold(kept.as_str(), 50), (kept, 0));
+        assert_eq!(compacted.before, 200);
+    }
+
+    #[test]
+    fn demo_plays_are_counted_apart() {
+        let mut log = log(&[("pong", 100, 10), ("pong", 300, 20)]);
+        log.push_str("{\"game_id\":\"pong\",\"started\":400,\"seconds\":5,\"demo\":true}\n");
+        let mut compacted = Compacted::default();
+        let (kept, _) = compacted.fold(log.as_str(), 200);
+
+        let lifetime = compacted.lifetime(kept.as_str());
+        assert_eq!(lifetime["pong"].plays, 3);
+        assert_eq!(lifetime["pong"].demo_plays, 1);
+    }
+
+    #[test]
+    fn export_marks_the_compacted_part() {
+        let log = log(&[("pong", 100, 10), ("snake", 150, 5), ("pong", 300, 20)]);
+        let mut compacted = Compacted::default();
+        let (kept, _) = compacted.fold(log.as_str(), 200);
+
+        let export = compacted.export(kept.as_str(), 0, 1000);
+        assert_eq!(export.aggregate_only_until, Some(200));
+        assert_eq!(export.sessions.len(), 1);
+        assert_eq!(export.compacted["pong"].plays, 1);
+        assert_eq!(export.compacted["snake"].plays, 1);
+
+        assert_eq!(
+            compacted.export(kept.as_str(), 0, 120).aggregate_only_until,
+            Some(120)
+        );
+        let export = compacted.export(kept.as_str(), 250, 1000);
+        assert_eq!(export.aggregate_only_until, None);
+        assert_eq!(export.sessions.len(), 1);
+    }
+}
@@ -0,0 +1,221 @@
+use super::sessions::today;
+use crate::clock;
+use crate::layout;
+use crate::nfc::{AuthUnavailable, NFC_CLIENT};
+use crate::secrets::{self, TAP_QUEUE_SALT};
+use crate::state::JsonState;
+use anyhow::Error;
+use devcade_onboard_types::{Player, TapStats};
+use lazy_static::lazy_static;
+use log::{log, Level};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/**
+ * The most taps kept waiting for the auth service. The oldest are dropped first.
+ */
+const MAX_QUEUED: usize = 1000;
+
+/**
+ * How often the queue is drained
+ */
+const DRAIN_EVERY: Duration = Duration::from_secs(60);
+
+/**
+ * How long one lookup may take while draining
+ */
+const LOOKUP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/**
+ * How many days of tap stats are kept
+ */
+const STATS_DAYS: usize = 90;
+
+lazy_static! {
+    // Taps made while the auth service was down, oldest first
+    static ref QUEUE: JsonState<VecDeque<PendingTap>> = JsonState::new(queue_path);
+    // Taps per day by how they were resolved
+    static ref STATS: JsonState<BTreeMap<String, TapStats>> = JsonState::new(stats_path);
+    // The association IDs of queued taps by their hash. These are only kept in memory, so after a
+    // restart a queued tap is only resolved when the same card taps again.
+    static ref PENDING_IDS: Mutex<HashMap<String, String>> = Mutex::new(HashMap::new());
+}
+
+/**
+ * A tap waiting for the auth service
+ */
+#[derive(Serialize, Deserialize, Clone)]
+struct PendingTap {
+    id_hash: String,
+    time: u64,
+    reader: Player,
+    /// The local date the tap is counted on once it's resolved
+    date: String,
+}
+
+fn queue_path() -> PathBuf {
+    layout::state_dir().join("tap_queue.json")
+}
+
+fn stats_path() -> PathBuf {
+    layout::state_dir().join("tap_stats.json")
+}
+
+/**
+ * Hash an association ID with the `tap_queue_salt` secret, generating it the first time
+ */
+fn hash_id(association_id: &str) -> Result<String, Error> {
+    let salt = match secrets::lookup(TAP_QUEUE_SALT)? {
+        Some((salt, _)) => salt,
+        None => {
+            let salt = secrets::random_hex(16)?;
+            secrets::set(TAP_QUEUE_SALT, salt.as_str())?;
+            salt
+        }
+    };
+    let mut hasher = Sha256::new();
+    hasher.update(salt.as_bytes());
+    hasher.update(association_id.as_bytes());
+    Ok(secrets::hex(&hasher.finalize()[..16]))
+}
+
+/**
+ * Change the stats of a day, dropping the oldest days past `STATS_DAYS`
+ */
+fn count(date: &str, f: impl FnOnce(&mut TapStats)) {
+    let mut stats = STATS.lock();
+    f(stats.entry(date.to_string()).or_default());
+    while stats.len() > STATS_DAYS {
+        stats.pop_first();
+    }
+    if let Err(e) = stats.save() {
+        log!(Level::Warn, "Couldn't save tap stats: {}", e);
+    }
+}
+
+/**
+ * Queue a tap the auth service couldn't be asked about, and count it as pending
+ */
+pub fn enqueue(reader: Player, association_id: &str) {
+    let id_hash = match hash_id(association_id) {
+        Ok(id_hash) => id_hash,
+        Err(e) => {
+            log!(Level::Warn, "Couldn't queue tap: {}", e);
+            return;
+        }
+    };
+    let date = today();
+    let mut pending_ids = PENDING_IDS.lock().unwrap();
+    pending_ids.insert(id_hash.clone(), association_id.to_string());
+    let mut queue = QUEUE.lock();
+    queue.push_back(PendingTap {
+        id_hash: id_hash.clone(),
+        time: clock::unix_now(),
+        reader,
+        date: date.clone(),
+    });
+    while queue.len() > MAX_QUEUED {
+        if let Some(dropped) = queue.pop_front() {
+            log!(
+                Level::Warn,
+                "Tap queue is full, dropping the tap from {}",
+                dropped.time
+            );
+            count(dropped.date.as_str(), |day| {
+                day.pending = day.pending.saturating_sub(1);
+            });
+            if !queue.iter().any(|tap| tap.id_hash == dropped.id_hash) {
+                pending_ids.remove(&dropped.id_hash);
+            }
+        }
+    }
+    if let Err(e) = queue.save() {
+        log!(Level::Warn, "Couldn't save tap queue: {}", e);
+    }
+    drop(queue);
+    drop(pending_ids);
+    count(date.as_str(), |day| day.pending += 1);
+}
+
+/**
+ * Count a tap the auth service answered, and resolve the card's queued taps the same way
+ */
+pub fn resolved(association_id: &str, member: bool) {
+    count(today().as_str(), |day| day.add(member));
+    resolve(association_id, member);
+}
+
+/**
+ * Move a card's queued taps from pending to members or unknown, on the days they were made
+ */
+fn resolve(association_id: &str, member: bool) {
+    let Ok(id_hash) = hash_id(association_id) else {
+        return;
+    };
+    PENDING_IDS.lock().unwrap().remove(&id_hash);
+
+    let mut queue = QUEUE.lock();
+    let before = queue.len();
+    let mut dates = Vec::new();
+    queue.retain(|tap| {
+        if tap.id_hash == id_hash {
+            dates.push(tap.date.clone());
+            return false;
+        }
+        true
+    });
+    if queue.len() == before {
+        return;
+    }
+    if let Err(e) = queue.save() {
+        log!(Level::Warn, "Couldn't save tap queue: {}", e);
+    }
+    drop(queue);
+    log!(Level::Info, "Resolved {} queued taps", dates.len());
+    for date in dates {
+        count(date.as_str(), |day| {
+            day.pending = day.pending.saturating_sub(1);
+            day.add(member);
+        });
+    }
+}
+
+/**
+ * Look up the cards of queued taps whose association IDs are still known, resolving their taps.
+ * Stops at the first lookup that finds the auth service still down.
+ */
+async fn drain() {
+    let pending: Vec<String> = PENDING_IDS.lock().unwrap().values().cloned().collect();
+    for association_id in pending {
+        let user =
+            clock::timeout(LOOKUP_TIMEOUT, NFC_CLIENT.get_user(association_id.clone())).await;
+        match user {
+            Ok(Ok(_)) => resolve(association_id.as_str(), true),
+            Ok(Err(e)) if !e.is::<AuthUnavailable>() => resolve(association_id.as_str(), false),
+            _ => return,
+        }
+    }
+}
+
+/**
+ * Drain the queue every `DRAIN_EVERY`. This never returns.
+ */
+pub async fn watch() {
+    let mut interval = clock::interval(DRAIN_EVERY);
+    loop {
+        interval.tick().await;
+        drain().await;
+    }
+}
+
+/**
+ * Get the tap stats by local date
+ */
+#[must_use]
+pub fn stats() -> BTreeMap<String, TapStats> {
+    STATS.lock().clone()
+}
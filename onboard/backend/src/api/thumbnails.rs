@@ -0,0 +1,101 @@
+use super::is_art;
+use crate::layout;
+use anyhow::{anyhow, Error};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use image::imageops::FilterType;
+use image::ImageOutputFormat;
+use log::{log, Level};
+use std::collections::BTreeMap;
+use std::io::Cursor;
+use std::path::PathBuf;
+
+/**
+ * The largest width or height of a thumbnail in pixels. Icons keep their aspect ratio.
+ */
+const SIZE: u32 = 64;
+
+/**
+ * The most base64 thumbnail data sent in one response, which keeps the response well under
+ * `MAX_FRAME_SIZE` however many games are installed
+ */
+const MAX_INLINE_BYTES: usize = 1024 * 1024;
+
+fn icon_path(game_id: &str) -> PathBuf {
+    layout::game_dir(game_id).join("icon.png")
+}
+
+fn thumbnail_path(game_id: &str) -> PathBuf {
+    layout::game_dir(game_id).join("icon.thumb.png")
+}
+
+/**
+ * Generate the thumbnail of a game's icon, replacing the old one. The thumbnail is kept in the
+ * game's directory, so it counts towards the game's size when making room and is removed with it.
+ *
+ * # Errors
+ * This function will return an error if the icon can't be decoded, or if the thumbnail cannot be
+ * written.
+ */
+pub fn generate(game_id: &str) -> Result<(), Error> {
+    let icon = image::open(icon_path(game_id))
+        .map_err(|e| anyhow!("Couldn't decode icon of game {game_id}: {e}"))?;
+    let mut png = Cursor::new(Vec::new());
+    icon.resize(SIZE, SIZE, FilterType::Triangle)
+        .write_to(&mut png, ImageOutputFormat::Png)?;
+    layout::write_atomic(thumbnail_path(game_id).as_path(), png.into_inner())?;
+    Ok(())
+}
+
+/**
+ * Get a game's thumbnail, generating it first if it's missing or older than the icon. Games whose
+ * icon is missing or the placeholder have no thumbnail.
+ */
+fn read(game_id: &str) -> Option<Vec<u8>> {
+    let icon = icon_path(game_id);
+    let path = thumbnail_path(game_id);
+    let modified = |path: &PathBuf| {
+        std::fs::metadata(path)
+            .and_then(|meta| meta.modified())
+            .ok()
+    };
+    let icon_modified = modified(&icon)?;
+    if modified(&path).is_none_or(|thumb| thumb < icon_modified) {
+        if !is_art(icon.as_path()) {
+            // The icon was replaced by the placeholder, so the old thumbnail is out of date
+            let _ = std::fs::remove_file(&path);
+            return None;
+        }
+        if let Err(e) = generate(game_id) {
+            log!(Level::Warn, "Couldn't generate thumbnail: {}", e);
+            return None;
+        }
+    }
+    std::fs::read(path).ok()
+}
+
+/**
+ * Get the base64 encoded thumbnails of the given games, in order until `MAX_INLINE_BYTES` is
+ * reached. Games without a thumbnail are left out.
+ */
+pub fn inline<'a>(game_ids: impl IntoIterator<Item = &'a str>) -> BTreeMap<String, String> {
+    let mut thumbnails = BTreeMap::new();
+    let mut total = 0;
+    for id in game_ids {
+        let Some(thumbnail) = read(id) else {
+            continue;
+        };
+        let encoded = STANDARD.encode(thumbnail);
+        total += encoded.len();
+        if total > MAX_INLINE_BYTES {
+            log!(
+                Level::Debug,
+                "Thumbnails past {} bytes aren't sent inline, leaving out the rest",
+                MAX_INLINE_BYTES
+            );
+            break;
+        }
+        thumbnails.insert(id.to_string(), encoded);
+    }
+    thumbnails
+}
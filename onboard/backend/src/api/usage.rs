@@ -0,0 +1,312 @@
+use super::emit_launch_event;
+use crate::clock;
+use crate::env::{resource_limits, resource_sample_interval};
+use anyhow::{anyhow, Error};
+use devcade_onboard_types::{GameResources, LaunchEventKind, ResourceSample};
+use lazy_static::lazy_static;
+use log::{log, Level};
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::io::ErrorKind;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+use tokio::task::JoinHandle;
+
+/**
+ * How many samples are kept as history
+ */
+const HISTORY_KEPT: usize = 60;
+
+/**
+ * How many samples in a row can fail before sampling is turned off
+ */
+const MAX_FAILURES: u32 = 3;
+
+/**
+ * The running game's samples
+ */
+struct Tracked {
+    game_id: String,
+    history: VecDeque<ResourceSample>,
+    peak: ResourceSample,
+    sampler: JoinHandle<()>,
+}
+
+/**
+ * What's read from `/proc/<pid>/stat`
+ */
+struct Stat {
+    pgrp: i64,
+    /// User and system CPU time in clock ticks
+    ticks: u64,
+    threads: u64,
+    rss_pages: u64,
+}
+
+lazy_static! {
+    static ref TRACKED: Mutex<Option<Tracked>> = Mutex::new(None);
+}
+
+/**
+ * Set once `/proc` couldn't be read too many times in a row, so sampling stops until the backend
+ * restarts
+ */
+static DISABLED: AtomicBool = AtomicBool::new(false);
+
+/**
+ * Parse the fields of `/proc/<pid>/stat` that are sampled. The process name can hold spaces and
+ * parentheses, so fields are counted from the last `)`.
+ */
+fn parse_stat(stat: &str) -> Option<Stat> {
+    let fields: Vec<&str> = stat
+        .get(stat.rfind(')')? + 1..)?
+        .split_whitespace()
+        .collect();
+    // Field 3 of the stat file (the state) is fields[0]
+    let field = |n: usize| fields.get(n - 3)?.parse::<i64>().ok();
+    let unsigned = |n: usize| u64::try_from(field(n)?).ok();
+    Some(Stat {
+        pgrp: field(5)?,
+        ticks: unsigned(14)? + unsigned(15)?,
+        threads: unsigned(20)?,
+        rss_pages: unsigned(24)?,
+    })
+}
+
+/**
+ * Read a process's stat, or `None` if the process exited
+ */
+fn read_stat(pid: &str) -> Result<Option<Stat>, Error> {
+    match std::fs::read_to_string(format!("/proc/{pid}/stat")) {
+        Ok(stat) => parse_stat(stat.as_str())
+            .map(Some)
+            .ok_or_else(|| anyhow!("Couldn't parse /proc/{pid}/stat")),
+        Err(e)
+            if matches!(e.kind(), ErrorKind::NotFound) || e.raw_os_error() == Some(libc::ESRCH) =>
+        {
+            Ok(None)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/**
+ * Sample the processes in a process group. `ticks` holds the CPU time of each process at the last
+ * sample, and is updated. Processes that exit partway through are left out.
+ *
+ * Returns `None` if the process group has no processes left.
+ */
+fn sample(
+    pgid: u32,
+    ticks: &mut HashMap<String, u64>,
+    since: Option<Instant>,
+) -> Result<Option<ResourceSample>, Error> {
+    // SAFETY: sysconf has no preconditions
+    let (clock_ticks, page_size) = unsafe {
+        (
+            libc::sysconf(libc::_SC_CLK_TCK),
+            libc::sysconf(libc::_SC_PAGESIZE),
+        )
+    };
+    let clock_ticks = u64::try_from(clock_ticks).unwrap_or(100);
+    let page_size = u64::try_from(page_size).unwrap_or(4096);
+
+    let mut sample = ResourceSample {
+        at: clock::unix_now(),
+        ..ResourceSample::default()
+    };
+    let mut used_ticks = 0;
+    let mut seen = HashMap::new();
+    for entry in std::fs::read_dir("/proc")? {
+        let name = entry?.file_name();
+        let Some(pid) = name
+            .to_str()
+            .filter(|name| name.bytes().all(|b| b.is_ascii_digit()))
+        else {
+            continue;
+        };
+        let Some(stat) = read_stat(pid)? else {
+            continue;
+        };
+        if stat.pgrp != i64::from(pgid) {
+            continue;
+        }
+        // Processes started since the last sample count from their next one
+        used_ticks += ticks
+            .get(pid)
+            .map_or(0, |before| stat.ticks.saturating_sub(*before));
+        seen.insert(pid.to_string(), stat.ticks);
+        sample.processes += 1;
+        sample.threads += stat.threads;
+        sample.rss_bytes += stat.rss_pages * page_size;
+        sample.open_fds +=
+            std::fs::read_dir(format!("/proc/{pid}/fd")).map_or(0, |fds| fds.count() as u64);
+    }
+    *ticks = seen;
+    if sample.processes == 0 {
+        return Ok(None);
+    }
+    if let Some(since) = since {
+        let secs = clock::elapsed(since).as_secs_f64();
+        if secs > 0.0 {
+            sample.cpu_percent = used_ticks as f64 / clock_ticks as f64 / secs * 100.0;
+        }
+    }
+    Ok(Some(sample))
+}
+
+/**
+ * Get the values in a sample that are over `DEVCADE_RESOURCE_LIMITS`, like `rss_mb=1900 (limit
+ * 1500)`, by name
+ */
+fn over_limits(sample: &ResourceSample) -> Vec<(String, String)> {
+    let Some(limits) = resource_limits() else {
+        return Vec::new();
+    };
+    let mut over = Vec::new();
+    for limit in limits.split(',') {
+        let Some((name, max)) = limit.trim().split_once('=') else {
+            continue;
+        };
+        let Ok(max) = max.trim().parse::<f64>() else {
+            log!(Level::Warn, "Resource limit '{}' isn't a number", limit);
+            continue;
+        };
+        let value = match name.trim() {
+            "rss_mb" => sample.rss_bytes as f64 / (1024.0 * 1024.0),
+            "cpu_percent" => sample.cpu_percent,
+            "open_fds" => sample.open_fds as f64,
+            "threads" => sample.threads as f64,
+            name => {
+                log!(Level::Warn, "Unknown resource limit '{}'", name);
+                continue;
+            }
+        };
+        if value > max {
+            let name = name.trim().to_string();
+            over.push((name.clone(), format!("{name}={value:.0} (limit {max})")));
+        }
+    }
+    over
+}
+
+/**
+ * Record a sample, and get the limits it's over
+ */
+fn record(sample: ResourceSample) -> Vec<(String, String)> {
+    let over = over_limits(&sample);
+    let mut tracked = TRACKED.lock().unwrap();
+    let Some(tracked) = tracked.as_mut() else {
+        return over;
+    };
+    let peak = &mut tracked.peak;
+    let before = peak.clone();
+    peak.rss_bytes = peak.rss_bytes.max(sample.rss_bytes);
+    peak.cpu_percent = peak.cpu_percent.max(sample.cpu_percent);
+    peak.open_fds = peak.open_fds.max(sample.open_fds);
+    peak.threads = peak.threads.max(sample.threads);
+    peak.processes = peak.processes.max(sample.processes);
+    if *peak != before {
+        peak.at = sample.at;
+    }
+    tracked.history.push_back(sample);
+    if tracked.history.len() > HISTORY_KEPT {
+        tracked.history.pop_front();
+    }
+    over
+}
+
+/**
+ * Sample the game's process group every `DEVCADE_RESOURCE_SAMPLE_SECS` until it has no processes
+ * left, sending `ResourcePressure` when it goes over a limit
+ */
+async fn watch(pgid: u32) {
+    let mut interval = clock::interval(resource_sample_interval());
+    let mut ticks = HashMap::new();
+    let mut last = None;
+    let mut failures = 0;
+    let mut pressure = BTreeSet::new();
+    loop {
+        interval.tick().await;
+        let taken = clock::now();
+        let sample = match sample(pgid, &mut ticks, last) {
+            Ok(Some(sample)) => sample,
+            Ok(None) => return,
+            Err(e) => {
+                failures += 1;
+                if failures >= MAX_FAILURES {
+                    log!(
+                        Level::Warn,
+                        "Couldn't sample game resources {} times, turning sampling off: {}",
+                        failures,
+                        e
+                    );
+                    DISABLED.store(true, Ordering::Relaxed);
+                    return;
+                }
+                log!(Level::Debug, "Couldn't sample game resources: {}", e);
+                continue;
+            }
+        };
+        failures = 0;
+        last = Some(taken);
+
+        let over = record(sample);
+        for (name, description) in &over {
+            if !pressure.contains(name) {
+                log!(
+                    Level::Warn,
+                    "Game is over a resource limit: {}",
+                    description
+                );
+                emit_launch_event(LaunchEventKind::ResourcePressure(description.clone()));
+            }
+        }
+        pressure = over.into_iter().map(|(name, _)| name).collect();
+    }
+}
+
+/**
+ * Start sampling a game that was just spawned. `pid` is also the ID of its process group.
+ */
+pub fn start(game_id: &str, pid: u32) {
+    if DISABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let sampler = tokio::spawn(watch(pid));
+    let old = TRACKED.lock().unwrap().replace(Tracked {
+        game_id: game_id.to_string(),
+        history: VecDeque::new(),
+        peak: ResourceSample::default(),
+        sampler,
+    });
+    if let Some(old) = old {
+        old.sampler.abort();
+    }
+}
+
+/**
+ * Stop sampling the game that exited
+ *
+ * Returns the game's peak usage, or `None` if it was never sampled.
+ */
+pub fn stop() -> Option<ResourceSample> {
+    let tracked = TRACKED.lock().unwrap().take()?;
+    tracked.sampler.abort();
+    (!tracked.history.is_empty()).then_some(tracked.peak)
+}
+
+/**
+ * Get the running game's latest samples and peak usage, or `None` if no game is being sampled
+ */
+pub fn get() -> Option<GameResources> {
+    TRACKED
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map(|tracked| GameResources {
+            game_id: tracked.game_id.clone(),
+            history: tracked.history.iter().cloned().collect(),
+            peak: tracked.peak.clone(),
+        })
+}
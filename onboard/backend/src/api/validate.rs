@@ -0,0 +1,191 @@
+use super::{extract_archive, find_executable, GameArchive};
+use log::{log, Level};
+use serde::Serialize;
+use std::ffi::OsStr;
+use std::fmt;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/**
+ * How the executable of a game was located inside its `publish` directory
+ */
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+pub enum ExecutableStrategy {
+    /**
+     * The executable was inferred from the name of a `*.runtimeconfig.json` file (.NET games)
+     */
+    RuntimeConfig,
+    /**
+     * The executable is a file with the same name as the game
+     */
+    GameName,
+}
+
+/**
+ * The result of checking a game archive with `validate_game_archive`
+ */
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ValidationReport {
+    /**
+     * The executable that would be launched, relative to the `publish` directory
+     */
+    pub executable: Option<String>,
+    /**
+     * How the executable was found
+     */
+    pub strategy: Option<ExecutableStrategy>,
+    /**
+     * Problems that don't prevent the game from installing, but probably should be fixed
+     */
+    pub warnings: Vec<String>,
+    /**
+     * Problems that would prevent the game from installing or launching
+     */
+    pub errors: Vec<String>,
+    /**
+     * The number of entries in the archive
+     */
+    pub entries: usize,
+    /**
+     * The total compressed size of all entries, in bytes
+     */
+    pub compressed_size: u64,
+    /**
+     * The total uncompressed size of all entries, in bytes
+     */
+    pub uncompressed_size: u64,
+    /**
+     * Entries that would be pruned on install
+     */
+    pub pruned: Vec<String>,
+    /**
+     * The uncompressed size of the pruned entries, in bytes
+     */
+    pub pruned_bytes: u64,
+}
+
+impl ValidationReport {
+    /**
+     * Whether the archive would install and launch on the cabinet
+     */
+    #[must_use]
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+impl fmt::Display for ValidationReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.executable, &self.strategy) {
+            (Some(executable), Some(strategy)) => {
+                writeln!(f, "Executable: {executable} (found by {strategy:?})")?;
+            }
+            _ => writeln!(f, "Executable: none")?,
+        }
+        writeln!(
+            f,
+            "Entries: {} ({} bytes compressed, {} bytes uncompressed)",
+            self.entries, self.compressed_size, self.uncompressed_size
+        )?;
+        if !self.pruned.is_empty() {
+            writeln!(
+                f,
+                "Pruned: {} entries ({} bytes)",
+                self.pruned.len(),
+                self.pruned_bytes
+            )?;
+        }
+        for warning in &self.warnings {
+            writeln!(f, "Warning: {warning}")?;
+        }
+        for error in &self.errors {
+            writeln!(f, "Error: {error}")?;
+        }
+        write!(f, "{}", if self.is_ok() { "OK" } else { "FAILED" })
+    }
+}
+
+/**
+ * Check a game archive the way installing it would, without installing it
+ */
+pub(super) fn report(path: &Path) -> ValidationReport {
+    let mut report = ValidationReport::default();
+
+    let mut archive = match GameArchive::open(path) {
+        Ok(archive) => archive,
+        Err(e) => {
+            report.errors.push(format!("Couldn't open archive: {e}"));
+            return report;
+        }
+    };
+
+    if let Ok(entries) = archive.entries() {
+        report.entries = entries.len();
+        report.uncompressed_size = entries.iter().map(|(_, size)| size).sum();
+    }
+    report.compressed_size = archive.compressed_size().unwrap_or_default();
+
+    let dir = std::env::temp_dir().join(format!(
+        "devcade-validate-{}-{}",
+        std::process::id(),
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos()
+    ));
+    if let Err(e) = std::fs::create_dir_all(&dir) {
+        report
+            .errors
+            .push(format!("Couldn't create temporary directory: {e}"));
+        return report;
+    }
+
+    let publish = dir.join("publish");
+    let extracted = match extract_archive(&mut archive, dir.as_path(), None, |_, _| Ok(())) {
+        Ok(extraction) => {
+            report.warnings = extraction.warnings;
+            report.errors.extend(extraction.errors);
+            report.pruned = extraction.pruned;
+            report.pruned_bytes = extraction.pruned_bytes;
+            true
+        }
+        Err(e) => {
+            report.errors.push(e.to_string());
+            false
+        }
+    };
+
+    // A rejected archive wasn't extracted, so there's nothing to inspect
+    if extracted && publish.is_dir() {
+        let name = path
+            .file_prefix()
+            .and_then(OsStr::to_str)
+            .unwrap_or_default();
+        match find_executable(publish.as_path(), name) {
+            Ok((executable, strategy)) => {
+                report.executable = executable
+                    .strip_prefix(&publish)
+                    .ok()
+                    .and_then(Path::to_str)
+                    .map(String::from);
+                report.strategy = Some(strategy);
+            }
+            Err(e) => report.errors.push(e.to_string()),
+        }
+    } else if extracted {
+        report
+            .errors
+            .push(String::from("Archive has no publish directory"));
+    }
+
+    if let Err(e) = std::fs::remove_dir_all(&dir) {
+        log!(
+            Level::Warn,
+            "Error removing temporary directory {}: {}",
+            dir.to_str().unwrap(),
+            e
+        );
+    }
+
+    report
+}
@@ -0,0 +1,220 @@
+use crate::clock;
+use crate::env::{tap_audit_retention, tap_audit_salt_rotation};
+use crate::layout;
+use crate::secrets::{self, TAP_AUDIT_SALT};
+use crate::state;
+use anyhow::Error;
+use devcade_onboard_types::{Player, TapAuditEntry};
+use lazy_static::lazy_static;
+use log::{log, Level};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+lazy_static! {
+    // Serializes access to the audit files
+    static ref AUDIT: Mutex<()> = Mutex::new(());
+}
+
+const HOUR: u64 = 60 * 60;
+
+/**
+ * The salt association IDs are hashed with, as older versions kept it in its own file. It's kept in
+ * the secrets file now. Rotating it means taps can only be linked to each other within one salt
+ * period.
+ */
+#[derive(Serialize, Deserialize)]
+struct Salt {
+    salt: String,
+    created: u64,
+}
+
+fn audit_dir() -> PathBuf {
+    layout::state_dir()
+}
+
+fn taps_path() -> PathBuf {
+    audit_dir().join("tap_audit.jsonl")
+}
+
+fn hourly_path() -> PathBuf {
+    audit_dir().join("tap_audit_hourly.json")
+}
+
+fn salt_path() -> PathBuf {
+    audit_dir().join("tap_audit_salt.json")
+}
+
+/**
+ * Record an NFC tap in the audit trail. Only a salted hash of the association ID is stored. Taps
+ * older than `DEVCADE_TAP_AUDIT_DAYS` are folded into hourly counts whenever a tap is recorded.
+ * Failing to record a tap is logged, but never stops the tap from working.
+ */
+pub fn record_tap(reader: Player, association_id: &str, outcome: &str) {
+    if let Err(e) = try_record_tap(reader, association_id, outcome) {
+        log!(Level::Error, "Couldn't record tap in audit trail: {}", e);
+    }
+}
+
+fn try_record_tap(reader: Player, association_id: &str, outcome: &str) -> Result<(), Error> {
+    let _guard = AUDIT.lock().unwrap();
+    let time = clock::unix_now();
+    let entry = TapAuditEntry::Tap {
+        time,
+        reader,
+        id_hash: hash_id(association_id, time)?,
+        outcome: outcome.to_string(),
+    };
+    state::journal(taps_path().as_path(), &entry)?;
+
+    compact(time)
+}
+
+/**
+ * Hash an association ID with the current salt, rotating the salt if it is too old
+ */
+fn hash_id(association_id: &str, time: u64) -> Result<String, Error> {
+    let salt = secrets::lookup(TAP_AUDIT_SALT)?
+        .filter(|(_, set)| time.saturating_sub(*set) < tap_audit_salt_rotation().as_secs());
+    let salt = match salt {
+        Some((salt, _)) => salt,
+        None => new_salt()?,
+    };
+
+    let mut hasher = Sha256::new();
+    hasher.update(salt.as_bytes());
+    hasher.update(association_id.as_bytes());
+    Ok(secrets::hex(&hasher.finalize()[..16]))
+}
+
+/**
+ * Generate a new salt and save it in the secrets file
+ */
+fn new_salt() -> Result<String, Error> {
+    log!(Level::Info, "Rotating tap audit salt");
+    let salt = secrets::random_hex(16)?;
+    secrets::set(TAP_AUDIT_SALT, salt.as_str())?;
+    Ok(salt)
+}
+
+/**
+ * Rotate the salt now instead of waiting for it to expire. Taps past the retention window are
+ * folded into hourly counts first, so the aggregation under the old salt is finished before taps
+ * start being hashed with the new one.
+ *
+ * # Errors
+ * This function will return an error if the audit trail can't be compacted or the new salt can't
+ * be saved.
+ */
+pub fn rotate_salt() -> Result<(), Error> {
+    let _guard = AUDIT.lock().unwrap();
+    compact(clock::unix_now())?;
+    new_salt()?;
+    Ok(())
+}
+
+/**
+ * Move the salt from the file older versions kept it in into the secrets file, keeping when it was
+ * created so it still expires on time
+ *
+ * # Errors
+ * This function will return an error if the old file can't be read or removed, or the secrets file
+ * can't be written.
+ */
+pub fn migrate_salt() -> Result<(), Error> {
+    let _guard = AUDIT.lock().unwrap();
+    let json = match std::fs::read(salt_path()) {
+        Ok(json) => json,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    if secrets::lookup(TAP_AUDIT_SALT)?.is_none() {
+        let salt: Salt = serde_json::from_slice(&json)?;
+        secrets::set(TAP_AUDIT_SALT, salt.salt.as_str())?;
+        // The salt only expires based on when it was created, so it isn't cut short by moving it
+        secrets::backdate(TAP_AUDIT_SALT, salt.created)?;
+    }
+    std::fs::remove_file(salt_path())?;
+    log!(
+        Level::Info,
+        "Moved the tap audit salt from {} into the secrets file",
+        salt_path().display()
+    );
+    Ok(())
+}
+
+/**
+ * Fold taps older than the retention window into hourly counts
+ */
+fn compact(time: u64) -> Result<(), Error> {
+    let cutoff = time.saturating_sub(tap_audit_retention().as_secs());
+    let taps = read_taps()?;
+    if !taps.iter().any(|(time, _)| *time < cutoff) {
+        return Ok(());
+    }
+
+    let mut hourly = read_hourly();
+    let mut kept = String::new();
+    for (time, entry) in taps {
+        if time < cutoff {
+            *hourly.entry(time - time % HOUR).or_default() += 1;
+        } else {
+            kept.push_str(&serde_json::to_string(&entry)?);
+            kept.push('\n');
+        }
+    }
+    // Counts are written first, so a crash in between can only double count, never lose taps
+    layout::write_atomic(&hourly_path(), serde_json::to_vec(&hourly)?)?;
+    layout::write_atomic(&taps_path(), kept)?;
+    Ok(())
+}
+
+fn read_taps() -> Result<Vec<(u64, TapAuditEntry)>, Error> {
+    let file = match std::fs::File::open(taps_path()) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut taps = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        match serde_json::from_str(&line) {
+            Ok(entry @ TapAuditEntry::Tap { time, .. }) => taps.push((time, entry)),
+            _ => log!(Level::Warn, "Skipping invalid tap audit record"),
+        }
+    }
+    Ok(taps)
+}
+
+fn read_hourly() -> BTreeMap<u64, u64> {
+    std::fs::read(hourly_path())
+        .ok()
+        .and_then(|hourly| serde_json::from_slice(&hourly).ok())
+        .unwrap_or_default()
+}
+
+/**
+ * Get the audit trail between two unix timestamps (inclusive start, exclusive end). Hours that have
+ * been compacted are returned as counts, anything newer as individual taps, oldest first.
+ *
+ * # Errors
+ * This function will return an error if the audit trail cannot be read.
+ */
+pub fn tap_audit(start: u64, end: u64) -> Result<Vec<TapAuditEntry>, Error> {
+    let _guard = AUDIT.lock().unwrap();
+    let mut entries: Vec<TapAuditEntry> = read_hourly()
+        .into_iter()
+        .filter(|(hour, _)| *hour + HOUR > start && *hour < end)
+        .map(|(hour, count)| TapAuditEntry::Hourly { hour, count })
+        .collect();
+    entries.extend(
+        read_taps()?
+            .into_iter()
+            .filter(|(time, _)| (start..end).contains(time))
+            .map(|(_, entry)| entry),
+    );
+    Ok(entries)
+}
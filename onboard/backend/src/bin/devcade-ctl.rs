@@ -0,0 +1,738 @@
+use backend::api::{factory_reset, validate_game_archive, ResetScope};
+use backend::boot::exit_safe_mode;
+use backend::lock::InstanceLock;
+use backend::servers::capture::replay;
+use backend::servers::path::onboard_pipe;
+use backend::servers::persistence::{self, Backend};
+use devcade_onboard_types::client::Client;
+use devcade_onboard_types::{
+    EventHookOutcome, GameSetupResult, RequestBody, ResponseBody, ScheduleEntry,
+};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::process::ExitCode;
+
+const USAGE: &str = "Usage:
+    devcade-ctl validate <archive>
+    devcade-ctl factory-reset --scopes <saves,screenshots,games> [--confirm <token>]
+    devcade-ctl safe-mode exit (--restore|--discard)
+    devcade-ctl replay <capture> --against <socket>
+    devcade-ctl profile list
+    devcade-ctl profile (save|apply|delete) <name>
+    devcade-ctl secret list
+    devcade-ctl secret set <name>      (reads the value from stdin)
+    devcade-ctl secret rotate <name>
+    devcade-ctl saves migrate --to (json|segmented)
+    devcade-ctl features report
+    devcade-ctl duplicates
+    devcade-ctl cache stats
+    devcade-ctl stats compact <days to keep>
+    devcade-ctl restart
+    devcade-ctl game setup <game id>
+    devcade-ctl hooks test <event type>
+    devcade-ctl objects maintain
+    devcade-ctl schedule list
+    devcade-ctl schedule add <name> <start> <end> [--tag <tag>] [--feature <game ids>] [--block <game ids>]
+    devcade-ctl schedule remove <name>
+    devcade-ctl event (show|clear)
+    devcade-ctl event set <label>
+    devcade-ctl quiet-hours (show|reset|lift|restore)
+    devcade-ctl quiet-hours set <windows>";
+
+/**
+ * Command line tool for checking and managing a devcade cabinet without going through the frontend.
+ */
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    match args
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .as_slice()
+    {
+        ["validate", path] => {
+            let report = validate_game_archive(Path::new(path));
+            println!("{report}");
+            if report.is_ok() {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::FAILURE
+            }
+        }
+        ["factory-reset", "--scopes", scopes] => reset(scopes, None),
+        ["factory-reset", "--scopes", scopes, "--confirm", token] => reset(scopes, Some(token)),
+        ["safe-mode", "exit", "--restore"] => exit_safe(true),
+        ["safe-mode", "exit", "--discard"] => exit_safe(false),
+        ["replay", capture, "--against", socket] => {
+            match replay(Path::new(capture), Path::new(socket)) {
+                Ok(report) => {
+                    println!("{report}");
+                    if report.differences.is_empty() {
+                        ExitCode::SUCCESS
+                    } else {
+                        ExitCode::FAILURE
+                    }
+                }
+                Err(e) => {
+                    eprintln!("{e}");
+                    ExitCode::FAILURE
+                }
+            }
+        }
+        ["profile", "list"] => profile(RequestBody::ListProfiles),
+        ["profile", "save", name] => profile(RequestBody::SaveProfile((*name).to_string())),
+        ["profile", "apply", name] => profile(RequestBody::ApplyProfile((*name).to_string())),
+        ["profile", "delete", name] => profile(RequestBody::DeleteProfile((*name).to_string())),
+        ["secret", "list"] => secret(RequestBody::ListSecrets),
+        ["secret", "set", name] => {
+            let mut value = String::new();
+            if let Err(e) = std::io::stdin().read_line(&mut value) {
+                eprintln!("Couldn't read the secret: {e}");
+                return ExitCode::FAILURE;
+            }
+            secret(RequestBody::SetSecret(
+                (*name).to_string(),
+                value.trim_end_matches(['\r', '\n']).to_string(),
+            ))
+        }
+        ["secret", "rotate", name] => secret(RequestBody::RotateSecret((*name).to_string())),
+        ["saves", "migrate", "--to", backend] => migrate(backend),
+        ["features", "report"] => features(),
+        ["duplicates"] => duplicates(),
+        ["cache", "stats"] => cache_stats(),
+        ["stats", "compact", days] => compact(days),
+        ["restart"] => restart(),
+        ["game", "setup", game_id] => game_setup(game_id),
+        ["hooks", "test", event_type] => test_hooks(event_type),
+        ["objects", "maintain"] => maintain_objects(),
+        ["schedule", "list"] => schedule(RequestBody::GetSchedule),
+        ["schedule", "add", name, start, end, options @ ..] => {
+            match schedule_entry(name, start, end, options) {
+                Some(entry) => schedule(RequestBody::AddScheduleEntry(entry)),
+                None => {
+                    eprintln!("{USAGE}");
+                    ExitCode::FAILURE
+                }
+            }
+        }
+        ["schedule", "remove", name] => {
+            schedule(RequestBody::RemoveScheduleEntry((*name).to_string()))
+        }
+        ["event", "show"] => event(RequestBody::GetEventLabel),
+        ["event", "clear"] => event(RequestBody::SetEventLabel(None)),
+        ["event", "set", label] => event(RequestBody::SetEventLabel(Some((*label).to_string()))),
+        ["quiet-hours", "show"] => quiet_hours(RequestBody::GetQuietHours),
+        ["quiet-hours", "reset"] => quiet_hours(RequestBody::SetQuietHours(None)),
+        ["quiet-hours", "set", windows] => {
+            quiet_hours(RequestBody::SetQuietHours(Some((*windows).to_string())))
+        }
+        ["quiet-hours", "lift"] => quiet_hours(RequestBody::OverrideQuietHours(true)),
+        ["quiet-hours", "restore"] => quiet_hours(RequestBody::OverrideQuietHours(false)),
+        _ => {
+            eprintln!("{USAGE}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/**
+ * Run a factory reset. This takes the backend's instance lock, so it refuses to run while the
+ * backend (and therefore any game) is running.
+ */
+fn reset(scopes: &str, confirm: Option<&str>) -> ExitCode {
+    let scopes = match scopes
+        .split(',')
+        .map(str::parse)
+        .collect::<Result<Vec<ResetScope>, _>>()
+    {
+        Ok(scopes) => scopes,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let _lock = match InstanceLock::acquire(false) {
+        Ok(lock) => lock,
+        Err(e) => {
+            eprintln!("Stop the backend before resetting: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match factory_reset(&scopes, confirm) {
+        Ok(report) => {
+            println!("{report}");
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/**
+ * Leave safe mode, restoring or discarding the quarantined files. Like a reset, this refuses to
+ * run while the backend is running.
+ */
+fn exit_safe(restore: bool) -> ExitCode {
+    let _lock = match InstanceLock::acquire(false) {
+        Ok(lock) => lock,
+        Err(e) => {
+            eprintln!("Stop the backend before leaving safe mode: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match exit_safe_mode(restore) {
+        Ok(paths) => {
+            for path in paths {
+                let verb = if restore { "Restored" } else { "Discarded" };
+                println!("{verb} {}", path.display());
+            }
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/**
+ * Move every save to another storage backend. Like a reset, this refuses to run while the backend
+ * is running, since it holds saves in memory.
+ */
+fn migrate(backend: &str) -> ExitCode {
+    let backend: Backend = match backend.parse() {
+        Ok(backend) => backend,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let _lock = match InstanceLock::acquire(false) {
+        Ok(lock) => lock,
+        Err(e) => {
+            eprintln!("Stop the backend before migrating saves: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match persistence::migrate(backend) {
+        Ok(moved) => {
+            println!("Moved {moved} groups of saves to {backend} storage");
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/**
+ * Manage configuration profiles. Unlike the other commands, these go through the running backend,
+ * since they change settings it holds in memory.
+ */
+fn profile(request: RequestBody) -> ExitCode {
+    match send(request) {
+        Ok(ResponseBody::Profiles(names)) => {
+            for name in names {
+                println!("{name}");
+            }
+            ExitCode::SUCCESS
+        }
+        Ok(ResponseBody::ProfileChanges(changes)) => {
+            if changes.is_empty() {
+                println!("Nothing changed");
+            }
+            for change in changes {
+                println!("{change}");
+            }
+            ExitCode::SUCCESS
+        }
+        Ok(ResponseBody::Err(e)) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Couldn't reach the backend: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/**
+ * Manage the backend's secrets. Values are read from stdin so they don't end up in the shell's
+ * history, and are never printed.
+ */
+fn secret(request: RequestBody) -> ExitCode {
+    match send(request) {
+        Ok(ResponseBody::Secrets(secrets)) => {
+            for secret in secrets {
+                match secret.rotated {
+                    Some(rotated) => println!(
+                        "{} (created {}, rotated {})",
+                        secret.name, secret.created, rotated
+                    ),
+                    None => println!("{} (created {})", secret.name, secret.created),
+                }
+            }
+            ExitCode::SUCCESS
+        }
+        Ok(ResponseBody::Err(e)) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Couldn't reach the backend: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/**
+ * Print how many played games used each backend feature. This goes through the running backend,
+ * since it's what tracks the usage.
+ */
+fn features() -> ExitCode {
+    match send(RequestBody::GetFeatureAdoption) {
+        Ok(ResponseBody::FeatureAdoption(adoption)) => {
+            println!("{} games played", adoption.games);
+            for (kind, games) in adoption.requests {
+                println!("{games:>5}  request: {kind}");
+            }
+            for (var, games) in adoption.env_vars {
+                println!("{games:>5}  variable: {var}");
+            }
+            ExitCode::SUCCESS
+        }
+        Ok(ResponseBody::Err(e)) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Couldn't reach the backend: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/**
+ * List installed games with the same or mostly the same files
+ */
+fn duplicates() -> ExitCode {
+    match send(RequestBody::GetDuplicateInstalls) {
+        Ok(ResponseBody::DuplicateInstalls(groups)) => {
+            if groups.is_empty() {
+                println!("No duplicate installs");
+            }
+            for group in groups {
+                if group.exact {
+                    println!("Same files:");
+                } else {
+                    println!("{:.0}% of bytes in common:", group.similarity * 100.0);
+                }
+                for game in group.games {
+                    let last_launched = game
+                        .last_launched
+                        .map_or_else(|| "never".to_string(), |time| time.to_string());
+                    println!(
+                        "    {} ({}), {} bytes, last launched {}",
+                        game.name, game.id, game.size, last_launched
+                    );
+                }
+            }
+            ExitCode::SUCCESS
+        }
+        Ok(ResponseBody::Err(e)) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Couldn't reach the backend: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/**
+ * Show a table of how well each of the backend's caches is doing
+ */
+fn cache_stats() -> ExitCode {
+    match send(RequestBody::GetCacheStats) {
+        Ok(ResponseBody::CacheStats(caches)) => {
+            println!(
+                "{:<16}{:>9}{:>12}{:>10}{:>10}{:>8}{:>8}{:>12}",
+                "cache", "entries", "bytes", "hits", "misses", "stale", "evicted", "last hour"
+            );
+            for cache in caches {
+                let hour = cache.hour_hit_ratio.map_or_else(
+                    || "-".to_string(),
+                    |ratio| format!("{:.0}% of {}", ratio * 100.0, cache.hour_lookups),
+                );
+                println!(
+                    "{:<16}{:>9}{:>12}{:>10}{:>10}{:>8}{:>8}{:>12}",
+                    cache.name,
+                    cache.entries,
+                    cache.bytes,
+                    cache.hits,
+                    cache.misses,
+                    cache.stale,
+                    cache.evictions,
+                    hour
+                );
+            }
+            ExitCode::SUCCESS
+        }
+        Ok(ResponseBody::Err(e)) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Couldn't reach the backend: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/**
+ * Restart the backend without losing the running game or downloads
+ */
+fn restart() -> ExitCode {
+    match send(RequestBody::PrepareRestart) {
+        Ok(ResponseBody::Err(e)) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+        Ok(_) => {
+            println!("Backend is restarting");
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("Couldn't reach the backend: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/**
+ * Remove unused files from the object store and check a sample of the rest for corruption
+ */
+fn maintain_objects() -> ExitCode {
+    match send(RequestBody::MaintainObjectStore) {
+        Ok(ResponseBody::ObjectStoreReport(report)) => {
+            println!(
+                "{} files ({} bytes) in the store, removed {} unused ({} bytes)",
+                report.objects, report.bytes, report.reclaimed_objects, report.reclaimed_bytes
+            );
+            println!(
+                "Checked {} files, {} corrupted",
+                report.sampled,
+                report.corrupted.len()
+            );
+            for game_id in &report.repaired {
+                println!("Downloading {game_id} again");
+            }
+            if report.corrupted.is_empty() {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::FAILURE
+            }
+        }
+        Ok(ResponseBody::Err(e)) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Couldn't reach the backend: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/**
+ * Run the event hooks of a type of launch event on a sample event
+ */
+fn test_hooks(event_type: &str) -> ExitCode {
+    match send(RequestBody::TestEventHooks(event_type.to_string())) {
+        Ok(ResponseBody::EventHookTest(test)) => {
+            println!(
+                "Sample event: {}",
+                serde_json::to_string(&test.event).unwrap_or_default()
+            );
+            if test.runs.is_empty() {
+                println!("No hooks run on {event_type} events");
+            }
+            let mut failed = false;
+            for (hook, outcome) in test.runs {
+                match outcome {
+                    EventHookOutcome::Succeeded => println!("{hook}: succeeded"),
+                    EventHookOutcome::Failed(reason) => {
+                        failed = true;
+                        println!("{hook}: failed: {reason}");
+                    }
+                    EventHookOutcome::TimedOut => {
+                        failed = true;
+                        println!("{hook}: timed out");
+                    }
+                }
+            }
+            if failed {
+                ExitCode::FAILURE
+            } else {
+                ExitCode::SUCCESS
+            }
+        }
+        Ok(ResponseBody::Err(e)) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Couldn't reach the backend: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/**
+ * Run an installed game's setup step again
+ */
+fn game_setup(game_id: &str) -> ExitCode {
+    match send(RequestBody::RunGameSetup(game_id.to_string())) {
+        Ok(ResponseBody::GameSetup(record)) => match record.result {
+            GameSetupResult::Completed { seconds } => {
+                println!("Setup of {game_id} completed in {seconds} seconds");
+                ExitCode::SUCCESS
+            }
+            GameSetupResult::SetupFailed(reason) => {
+                eprintln!("Setup of {game_id} failed, see {}: {reason}", record.log);
+                ExitCode::FAILURE
+            }
+        },
+        Ok(ResponseBody::Err(e)) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Couldn't reach the backend: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/**
+ * Fold sessions older than some days into each game's lifetime totals
+ */
+fn compact(days: &str) -> ExitCode {
+    let Ok(days) = days.parse() else {
+        eprintln!("Invalid number of days '{days}'");
+        return ExitCode::FAILURE;
+    };
+    match send(RequestBody::CompactStats(days)) {
+        Ok(ResponseBody::StatsCompaction(compaction)) => {
+            println!(
+                "Folded {} sessions of {} games from before {} into their lifetime totals",
+                compaction.sessions, compaction.games, compaction.before
+            );
+            ExitCode::SUCCESS
+        }
+        Ok(ResponseBody::Err(e)) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Couldn't reach the backend: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/**
+ * Make a schedule entry from its dates and options, or `None` if an option is invalid. Game IDs
+ * are separated by commas.
+ */
+fn schedule_entry(name: &str, start: &str, end: &str, options: &[&str]) -> Option<ScheduleEntry> {
+    let mut entry = ScheduleEntry {
+        name: name.to_string(),
+        start: start.to_string(),
+        end: end.to_string(),
+        ..ScheduleEntry::default()
+    };
+    let ids = |ids: &str| -> Vec<String> {
+        ids.split(',')
+            .map(str::trim)
+            .filter(|id| !id.is_empty())
+            .map(String::from)
+            .collect()
+    };
+    for option in options.chunks(2) {
+        match option {
+            ["--tag", tag] => entry.featured_tag = Some((*tag).to_string()),
+            ["--feature", games] => entry.featured_games.extend(ids(games)),
+            ["--block", games] => entry.blocked_games.extend(ids(games)),
+            _ => return None,
+        }
+    }
+    Some(entry)
+}
+
+/**
+ * Show the schedule of featured games after listing or changing it, marking the active entry
+ */
+fn schedule(request: RequestBody) -> ExitCode {
+    match send(request) {
+        Ok(ResponseBody::Schedule(schedule)) => {
+            if schedule.entries.is_empty() {
+                println!("Nothing scheduled");
+            }
+            for entry in &schedule.entries {
+                let marker = if schedule.active.as_ref() == Some(&entry.name) {
+                    "*"
+                } else {
+                    " "
+                };
+                println!("{marker} {}: {} to {}", entry.name, entry.start, entry.end);
+                if let Some(tag) = &entry.featured_tag {
+                    println!("      featuring tag {tag}");
+                }
+                if !entry.featured_games.is_empty() {
+                    println!("      featuring {}", entry.featured_games.join(", "));
+                }
+                if !entry.blocked_games.is_empty() {
+                    println!("      hiding {}", entry.blocked_games.join(", "));
+                }
+            }
+            for warning in &schedule.warnings {
+                eprintln!("Warning: {warning}");
+            }
+            ExitCode::SUCCESS
+        }
+        Ok(ResponseBody::Err(e)) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Couldn't reach the backend: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/**
+ * Show, set or clear the label play sessions are tagged with during an event
+ */
+fn event(request: RequestBody) -> ExitCode {
+    match send(request) {
+        Ok(ResponseBody::EventLabel(Some(label))) => {
+            println!("{} (until {})", label.label, label.expires);
+            ExitCode::SUCCESS
+        }
+        Ok(ResponseBody::EventLabel(None)) => {
+            println!("No event label");
+            ExitCode::SUCCESS
+        }
+        Ok(ResponseBody::Err(e)) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Couldn't reach the backend: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/**
+ * Show the quiet hours after showing or changing them
+ */
+fn quiet_hours(request: RequestBody) -> ExitCode {
+    match send(request) {
+        Ok(ResponseBody::QuietHours(status)) => {
+            match &status.schedule {
+                Some(schedule) => println!("Quiet hours: {schedule}"),
+                None => println!("No quiet hours"),
+            }
+            match (&status.until, status.overridden) {
+                (Some(until), false) => println!("In quiet hours until {until}"),
+                (Some(until), true) => println!("Quiet hours until {until} lifted by an operator"),
+                (None, _) => {}
+            }
+            ExitCode::SUCCESS
+        }
+        Ok(ResponseBody::Err(e)) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Couldn't reach the backend: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/**
+ * Send a request to the backend over the onboard socket and wait for its response
+ */
+fn send(body: RequestBody) -> Result<ResponseBody, anyhow::Error> {
+    Client::new(UnixStream::connect(onboard_pipe())?).send(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::time::Duration;
+
+    #[test]
+    fn talks_to_a_backend_without_legacy_protocol() {
+        let root = std::env::temp_dir().join(format!("devcade-ctl-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        std::env::set_var("DEVCADE_PATH", &root);
+        std::env::set_var("DEVCADE_LEGACY_PROTOCOL", "false");
+
+        std::thread::spawn(|| {
+            tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap()
+                .block_on(backend::servers::onboard::main(onboard_pipe().as_str()));
+        });
+        let mut response = send(RequestBody::Ping);
+        for _ in 0..100 {
+            if response.is_ok() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+            response = send(RequestBody::Ping);
+        }
+        assert!(matches!(response.unwrap(), ResponseBody::Pong));
+
+        // A newline-delimited client is turned away
+        let mut legacy = UnixStream::connect(onboard_pipe()).unwrap();
+        legacy
+            .write_all(b"{\"request_id\":1,\"type\":\"Ping\"}\n")
+            .unwrap();
+        let mut line = String::new();
+        BufReader::new(legacy).read_line(&mut line).unwrap();
+        assert!(line.contains("turned off"), "{line}");
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,50 @@
+use anyhow::{anyhow, Error};
+use backend::servers::path::onboard_pipe;
+use devcade_onboard_types::client::Client;
+use devcade_onboard_types::{RequestBody, ResponseBody};
+use std::io::{BufRead, Write};
+use std::os::unix::net::UnixStream;
+
+/**
+ * Minimal text menu started by the backend when the frontend has been gone for too long. It talks
+ * to the onboard socket exactly like the frontend does, so installed games can still be launched.
+ */
+fn main() -> Result<(), Error> {
+    let mut client = Client::new(UnixStream::connect(onboard_pipe())?);
+    let stdin = std::io::stdin();
+
+    loop {
+        let mut games = match client.send(RequestBody::GetGameListFromFs)? {
+            ResponseBody::GameList(games) => games,
+            ResponseBody::Err(e) => return Err(anyhow!("Couldn't list games: {e}")),
+            response => return Err(anyhow!("Unexpected response: {response}")),
+        };
+        games.sort_by(|a, b| a.name.cmp(&b.name));
+
+        println!("\nDevcade is running in fallback mode. Installed games:");
+        for (i, game) in games.iter().enumerate() {
+            println!("{:>3}. {}", i + 1, game.name);
+        }
+        print!("Enter a number to play: ");
+        std::io::stdout().flush()?;
+
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        let Some(game) = line
+            .trim()
+            .parse::<usize>()
+            .ok()
+            .and_then(|i| games.get(i.checked_sub(1)?))
+        else {
+            continue;
+        };
+
+        println!("Launching {}...", game.name);
+        // The response only arrives once the game has exited
+        if let ResponseBody::Err(e) = client.send(RequestBody::LaunchGame(game.id.clone()))? {
+            println!("Couldn't launch {}: {e}", game.name);
+        }
+    }
+}
@@ -0,0 +1,971 @@
+use anyhow::{anyhow, bail, Error};
+use backend::clock;
+use backend::faults::{self, site};
+use backend::layout;
+use backend::logging;
+use backend::servers::path::onboard_pipe;
+use backend::servers::{persistence, ThreadHandles};
+use devcade_onboard_types::schema::DevcadeGame;
+use devcade_onboard_types::{Dialect, Request, RequestBody, Response, ResponseBody};
+use image::ImageOutputFormat;
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::io::{BufRead, BufReader, Cursor, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UnixStream;
+
+const USAGE: &str = "Usage:
+    devcade-soak [--seed <n>] [--steps <n>] [--max-rss-mb <n>] [--verbose]";
+
+/**
+ * How much simulated time passes between operations. The default number of steps is a week.
+ */
+const STEP: Duration = Duration::from_secs(10 * 60);
+const DEFAULT_STEPS: u64 = 7 * 24 * 6;
+const DEFAULT_MAX_RSS_MB: u64 = 512;
+/**
+ * How far the clock moves every `TICK` of real time while waiting on the backend, so its timeouts
+ * and retry backoffs run out
+ */
+const WAIT_STEP: Duration = Duration::from_secs(1);
+const TICK: Duration = Duration::from_millis(10);
+/**
+ * How long a command can go without a response, in real time, before the backend counts as
+ * deadlocked
+ */
+const DEADLOCK_AFTER: Duration = Duration::from_secs(60);
+/**
+ * How long, in real time, a game has to be gone after its launch returned, and the filesystem has
+ * to settle after a dropped connection's download
+ */
+const SETTLE_AFTER: Duration = Duration::from_secs(5);
+const GAMES: usize = 4;
+const SAVE_GROUPS: u64 = 4;
+const SAVE_KEYS: u64 = 16;
+const TAP_IDS: u64 = 16;
+/**
+ * How many of the last operations are kept for the failure report
+ */
+const HISTORY: usize = 30;
+
+/**
+ * The stub every game runs. It records its PID for the orphan check, then sleeps and exits with
+ * what the soak wrote to the control file before launching it.
+ */
+const STUB: &str = r#"#!/bin/sh
+echo $$ > "$DEVCADE_PATH/soak/pids/$$"
+read duration code < "$DEVCADE_PATH/soak/next"
+sleep "$duration"
+exit "$code"
+"#;
+
+/**
+ * The xorshift generator operations are drawn from, so a seed repeats the same run
+ */
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n.max(1)
+    }
+
+    fn pick<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.below(items.len() as u64) as usize]
+    }
+}
+
+struct Options {
+    seed: u64,
+    steps: u64,
+    max_rss_mb: u64,
+    verbose: bool,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Option<Self> {
+        let mut options = Self {
+            seed: clock::unix_now() ^ u64::from(std::process::id()),
+            steps: DEFAULT_STEPS,
+            max_rss_mb: DEFAULT_MAX_RSS_MB,
+            verbose: false,
+        };
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--seed" => options.seed = args.next()?.parse().ok()?,
+                "--steps" => options.steps = args.next()?.parse().ok()?,
+                "--max-rss-mb" => options.max_rss_mb = args.next()?.parse().ok()?,
+                "--verbose" => options.verbose = true,
+                _ => return None,
+            }
+        }
+        options.seed = options.seed.max(1);
+        Some(options)
+    }
+}
+
+/**
+ * The games the mock API serves. Publishing a game bumps its version, which changes its archive
+ * and so its hash.
+ */
+struct Catalog {
+    versions: Mutex<Vec<u32>>,
+    art: Vec<u8>,
+}
+
+impl Catalog {
+    fn new() -> Result<Self, Error> {
+        let mut art = Cursor::new(Vec::new());
+        image::RgbImage::from_pixel(8, 8, image::Rgb([200, 60, 30]))
+            .write_to(&mut art, ImageOutputFormat::Png)?;
+        Ok(Self {
+            versions: Mutex::new(vec![1; GAMES]),
+            art: art.into_inner(),
+        })
+    }
+
+    fn id(n: usize) -> String {
+        format!("soak-game-{n}")
+    }
+
+    fn publish(&self, n: usize) -> u32 {
+        let mut versions = self.versions.lock().unwrap();
+        versions[n] += 1;
+        versions[n]
+    }
+
+    fn archive(n: usize, version: u32) -> Result<Vec<u8>, Error> {
+        let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let options = zip::write::FileOptions::default().unix_permissions(0o755);
+        zip.start_file(format!("publish/Soak{n}"), options)?;
+        zip.write_all(STUB.as_bytes())?;
+        writeln!(zip, "# version {version}")?;
+        zip.start_file("publish/data.txt", options)?;
+        zip.write_all(&vec![b'x'; 1024 * (n + 1)])?;
+        Ok(zip.finish()?.into_inner())
+    }
+
+    fn game(&self, n: usize) -> Result<(DevcadeGame, Vec<u8>), Error> {
+        let version = self.versions.lock().unwrap()[n];
+        let archive = Self::archive(n, version)?;
+        let game = DevcadeGame {
+            id: Self::id(n),
+            name: format!("Soak{n}"),
+            author: "soak".to_string(),
+            description: format!("Soak test game {n}, version {version}"),
+            upload_date: "2024-01-01".to_string(),
+            hash: Sha256::digest(&archive)
+                .iter()
+                .map(|b| format!("{b:02x}"))
+                .collect(),
+            ..DevcadeGame::default()
+        };
+        Ok((game, archive))
+    }
+
+    /**
+     * Get the body served for an API path, or `None` for a 404
+     */
+    fn route(&self, path: &str) -> Option<Vec<u8>> {
+        let path = path.split('?').next()?.trim_matches('/');
+        let parts: Vec<&str> = path.split('/').collect();
+        let find = |id: &str| (0..GAMES).find(|n| Self::id(*n) == id);
+        match parts.as_slice() {
+            ["games"] => {
+                let games = (0..GAMES)
+                    .map(|n| self.game(n).map(|(game, _)| game))
+                    .collect::<Result<Vec<_>, _>>()
+                    .ok()?;
+                serde_json::to_vec(&games).ok()
+            }
+            ["games", id] => serde_json::to_vec(&self.game(find(id)?).ok()?.0).ok(),
+            ["games", id, "game"] => Some(self.game(find(id)?).ok()?.1),
+            ["games", id, "icon" | "banner"] => find(id).map(|_| self.art.clone()),
+            _ => None,
+        }
+    }
+}
+
+/**
+ * Serve the mock API until the process exits, a thread per connection
+ */
+fn serve(listener: TcpListener, catalog: Arc<Catalog>) {
+    for stream in listener.incoming().flatten() {
+        let catalog = catalog.clone();
+        std::thread::spawn(move || {
+            let _ = respond(stream, &catalog);
+        });
+    }
+}
+
+/**
+ * Answer one request, honoring `HEAD` and a `Range` header so partial downloads resume
+ */
+fn respond(stream: TcpStream, catalog: &Catalog) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    let mut range = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some(value) = line.to_ascii_lowercase().strip_prefix("range: bytes=") {
+            range = Some(value.trim().to_string());
+        }
+    }
+    let mut parts = request.split_whitespace();
+    let method = parts.next().unwrap_or("");
+    let path = parts.next().unwrap_or("");
+
+    let mut stream = stream;
+    let Some(body) = catalog.route(path) else {
+        return write!(
+            stream,
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+        );
+    };
+    let start = range
+        .and_then(|range| range.trim_end_matches('-').parse::<usize>().ok())
+        .filter(|start| *start < body.len())
+        .unwrap_or(0);
+    let status = if start > 0 {
+        format!(
+            "206 Partial Content\r\nContent-Range: bytes {start}-{}/{}",
+            body.len() - 1,
+            body.len()
+        )
+    } else {
+        "200 OK".to_string()
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len() - start
+    )?;
+    if method != "HEAD" {
+        stream.write_all(&body[start..])?;
+    }
+    Ok(())
+}
+
+/**
+ * A frontend connected to the onboard server, speaking length-prefixed frames
+ */
+struct Client {
+    stream: UnixStream,
+    next_id: u32,
+}
+
+impl Client {
+    async fn connect() -> Result<Self, Error> {
+        Ok(Self {
+            stream: UnixStream::connect(onboard_pipe()).await?,
+            next_id: 0,
+        })
+    }
+
+    async fn send(&mut self, body: RequestBody) -> Result<u32, Error> {
+        self.next_id += 1;
+        let frame = Dialect::Framed.frame(&Request {
+            request_id: self.next_id,
+            body,
+        })?;
+        self.stream.write_all(&frame).await?;
+        Ok(self.next_id)
+    }
+
+    async fn receive(&mut self, request_id: u32) -> Result<ResponseBody, Error> {
+        loop {
+            let mut len = [0; 4];
+            self.stream.read_exact(&mut len).await?;
+            let mut json = vec![0; u32::from_be_bytes(len) as usize];
+            self.stream.read_exact(&mut json).await?;
+            let response: Response = serde_json::from_slice(&json)?;
+            if response.request_id == request_id {
+                return Ok(response.body);
+            }
+        }
+    }
+
+    /**
+     * Send a request and wait for its response, moving the clock along while waiting. Fails if no
+     * response comes within `DEADLOCK_AFTER` of real time.
+     */
+    async fn request(&mut self, body: RequestBody) -> Result<ResponseBody, Error> {
+        let name = body.to_string();
+        let request_id = self.send(body).await?;
+        let started = Instant::now();
+        let response = self.receive(request_id);
+        tokio::pin!(response);
+        loop {
+            tokio::select! {
+                response = &mut response => return response,
+                () = tokio::time::sleep(TICK) => {
+                    if started.elapsed() > DEADLOCK_AFTER {
+                        bail!("{name} got no response in {DEADLOCK_AFTER:?}");
+                    }
+                    clock::advance(WAIT_STEP);
+                }
+            }
+        }
+    }
+}
+
+/**
+ * An invariant that didn't hold
+ */
+struct Failure {
+    invariant: &'static str,
+    detail: String,
+}
+
+fn failure(invariant: &'static str, detail: impl Into<String>) -> Failure {
+    Failure {
+        invariant,
+        detail: detail.into(),
+    }
+}
+
+/**
+ * Turn a request that couldn't be answered into a deadlock failure
+ */
+fn answered(response: Result<ResponseBody, Error>) -> Result<ResponseBody, Failure> {
+    response.map_err(|e| failure("no deadlocked commands", e.to_string()))
+}
+
+/**
+ * Describe a response for the operation history
+ */
+fn outcome(response: &ResponseBody) -> String {
+    match response {
+        ResponseBody::Err(e) => format!("error: {e}"),
+        other => other.to_string(),
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Operation {
+    Launch,
+    Download,
+    CancelledDownload,
+    Publish,
+    Save,
+    RestartSaves,
+    Tap,
+    Reload,
+    Faults,
+    Disconnect,
+}
+
+/**
+ * Operations with how often they're picked, out of the total
+ */
+const OPERATIONS: [(Operation, u64); 10] = [
+    (Operation::Launch, 20),
+    (Operation::Download, 14),
+    (Operation::CancelledDownload, 8),
+    (Operation::Publish, 4),
+    (Operation::Save, 20),
+    (Operation::RestartSaves, 4),
+    (Operation::Tap, 12),
+    (Operation::Reload, 7),
+    (Operation::Faults, 5),
+    (Operation::Disconnect, 6),
+];
+
+struct Soak {
+    rng: Rng,
+    client: Client,
+    handles: ThreadHandles,
+    catalog: Arc<Catalog>,
+    root: PathBuf,
+    /// Games that were installed successfully, which must stay installed
+    installed: BTreeSet<String>,
+    /// What every save should load back as
+    saves: BTreeMap<(String, String), String>,
+    /// Who every demo tap resolved to
+    taps: BTreeMap<String, Map<String, Value>>,
+    history: VecDeque<String>,
+    counts: BTreeMap<String, u64>,
+    peak_rss_mb: u64,
+}
+
+impl Soak {
+    fn pick_operation(&mut self) -> Operation {
+        let total: u64 = OPERATIONS.iter().map(|(_, weight)| weight).sum();
+        let mut roll = self.rng.below(total);
+        for (operation, weight) in OPERATIONS {
+            if roll < weight {
+                return operation;
+            }
+            roll -= weight;
+        }
+        Operation::Save
+    }
+
+    fn pick_game(&mut self) -> (usize, String) {
+        let n = self.rng.below(GAMES as u64) as usize;
+        (n, Catalog::id(n))
+    }
+
+    async fn run(&mut self, operation: Operation) -> Result<String, Failure> {
+        match operation {
+            Operation::Launch => {
+                let (_, id) = self.pick_game();
+                let millis = self.rng.below(300);
+                let code = *self.rng.pick(&[0, 0, 0, 1, 2, 137]);
+                std::fs::write(
+                    self.root.join("soak/next"),
+                    format!("{}.{:03} {code}\n", millis / 1000, millis % 1000),
+                )
+                .map_err(|e| failure("harness", e.to_string()))?;
+                let response = answered(
+                    self.client
+                        .request(RequestBody::LaunchGame(id.clone()))
+                        .await,
+                )?;
+                if matches!(response, ResponseBody::Ok) {
+                    self.installed.insert(id.clone());
+                }
+                Ok(format!(
+                    "launch {id} for {millis}ms exiting {code}: {}",
+                    outcome(&response)
+                ))
+            }
+            Operation::Download => {
+                let (_, id) = self.pick_game();
+                let response = answered(
+                    self.client
+                        .request(RequestBody::DownloadGame(id.clone()))
+                        .await,
+                )?;
+                if matches!(response, ResponseBody::Installed(_)) {
+                    self.installed.insert(id.clone());
+                }
+                Ok(format!("download {id}: {}", outcome(&response)))
+            }
+            Operation::CancelledDownload => {
+                let (_, id) = self.pick_game();
+                let mut downloader = Client::connect()
+                    .await
+                    .map_err(|e| failure("no deadlocked commands", e.to_string()))?;
+                let body = RequestBody::DownloadGame(id.clone());
+                let download = tokio::spawn(async move { downloader.request(body).await });
+                tokio::time::sleep(Duration::from_millis(self.rng.below(20))).await;
+                let cancelled = answered(
+                    self.client
+                        .request(RequestBody::CancelDownload(id.clone()))
+                        .await,
+                )?;
+                let downloaded = answered(
+                    download
+                        .await
+                        .unwrap_or_else(|e| Err(anyhow!("Download task failed: {e}"))),
+                )?;
+                if matches!(downloaded, ResponseBody::Installed(_)) {
+                    self.installed.insert(id.clone());
+                }
+                Ok(format!(
+                    "download {id} and cancel it: {} / {}",
+                    outcome(&downloaded),
+                    outcome(&cancelled)
+                ))
+            }
+            Operation::Publish => {
+                let (n, id) = self.pick_game();
+                let version = self.catalog.publish(n);
+                Ok(format!("publish {id} version {version}"))
+            }
+            Operation::Save => {
+                let group = format!("soak/{}", self.rng.below(SAVE_GROUPS));
+                let key = format!("key{}", self.rng.below(SAVE_KEYS));
+                let value = format!("{:x}", self.rng.next());
+                let result = persistence::save(group.as_str(), key.as_str(), value.as_str()).await;
+                let description = format!("save {group}/{key} = {value}: {result:?}");
+                if result.is_ok() {
+                    self.saves.insert((group, key), value);
+                }
+                Ok(description)
+            }
+            Operation::RestartSaves => {
+                // Flushes, then drops the cache, so every load below comes from disk
+                let result = persistence::clear_db().await;
+                for ((group, key), value) in &self.saves {
+                    check_save(group, key, value).await?;
+                }
+                Ok(format!(
+                    "reload {} saves from disk: {result:?}",
+                    self.saves.len()
+                ))
+            }
+            Operation::Tap => {
+                let id = format!("demo-{}", self.rng.below(TAP_IDS));
+                let response = answered(
+                    self.client
+                        .request(RequestBody::GetNfcUser(id.clone()))
+                        .await,
+                )?;
+                let ResponseBody::NfcUser(user) = response else {
+                    return Err(failure(
+                        "demo taps resolve",
+                        format!("Tap of {id} got {}", outcome(&response)),
+                    ));
+                };
+                if let Some(previous) = self.taps.get(&id) {
+                    if *previous != user {
+                        return Err(failure(
+                            "demo taps resolve",
+                            format!("Tap of {id} resolved to {user:?}, before to {previous:?}"),
+                        ));
+                    }
+                }
+                let uid = user.get("uid").cloned().unwrap_or_default();
+                self.taps.insert(id.clone(), user);
+                Ok(format!("tap {id}: {uid}"))
+            }
+            Operation::Reload => {
+                let body = match self.rng.below(3) {
+                    0 => RequestBody::ReloadTls,
+                    1 => RequestBody::SetDownloadLimit(Some(64 * 1024 * (1 + self.rng.below(16)))),
+                    _ => RequestBody::SetDownloadLimit(None),
+                };
+                let name = body.to_string();
+                let response = answered(self.client.request(body).await)?;
+                Ok(format!("{name}: {}", outcome(&response)))
+            }
+            Operation::Faults => {
+                let sites = [
+                    site::NETWORK_REQUEST,
+                    site::NETWORK_DOWNLOAD,
+                    site::FS_INSTALL,
+                    site::FS_SAVE,
+                ];
+                if self.rng.below(3) == 0 {
+                    faults::clear();
+                    Ok("clear faults".to_string())
+                } else {
+                    let site = *self.rng.pick(&sites);
+                    let probability = self.rng.below(5) as f64 / 10.0;
+                    faults::set(site, probability);
+                    Ok(format!(
+                        "inject faults at {site} with probability {probability}"
+                    ))
+                }
+            }
+            Operation::Disconnect => {
+                let (_, id) = self.pick_game();
+                let body = if self.rng.below(2) == 0 {
+                    RequestBody::LaunchGame(id)
+                } else {
+                    RequestBody::DownloadGame(id)
+                };
+                let name = body.to_string();
+                let mut client = Client::connect()
+                    .await
+                    .map_err(|e| failure("no deadlocked commands", e.to_string()))?;
+                client
+                    .send(body)
+                    .await
+                    .map_err(|e| failure("no deadlocked commands", e.to_string()))?;
+                drop(client);
+                Ok(format!("{name} and disconnect"))
+            }
+        }
+    }
+
+    /**
+     * Check every invariant, after an operation finished
+     */
+    async fn check(&mut self) -> Result<(), Failure> {
+        if let Some(e) = self.handles.onboard_error() {
+            return Err(failure(
+                "server stays up",
+                format!("Onboard thread panicked: {e}"),
+            ));
+        }
+        match answered(self.client.request(RequestBody::Ping).await)? {
+            ResponseBody::Pong => (),
+            other => return Err(failure("server stays up", outcome(&other))),
+        }
+        self.drain_downloads().await?;
+        settled(|| check_orphans(self.root.as_path())).await?;
+        settled(|| self.check_index()).await?;
+        if !self.saves.is_empty() {
+            let index = self.rng.below(self.saves.len() as u64) as usize;
+            if let Some(((group, key), value)) = self.saves.iter().nth(index) {
+                check_save(group, key, value).await?;
+            }
+        }
+        let rss = rss_mb();
+        self.peak_rss_mb = self.peak_rss_mb.max(rss);
+        Ok(())
+    }
+
+    /**
+     * Wait for the download queue to empty, so the filesystem is checked while it's still
+     */
+    async fn drain_downloads(&mut self) -> Result<(), Failure> {
+        let started = Instant::now();
+        loop {
+            let response = answered(self.client.request(RequestBody::GetDownloadQueue).await)?;
+            let ResponseBody::DownloadQueue(queue) = response else {
+                return Err(failure("downloads drain", outcome(&response)));
+            };
+            if queue.pending.is_empty() && queue.active.is_empty() {
+                return Ok(());
+            }
+            if started.elapsed() > DEADLOCK_AFTER {
+                return Err(failure(
+                    "downloads drain",
+                    format!(
+                        "{} pending and {} active downloads after {DEADLOCK_AFTER:?}",
+                        queue.pending.len(),
+                        queue.active.len()
+                    ),
+                ));
+            }
+            tokio::time::sleep(TICK).await;
+            clock::advance(WAIT_STEP);
+        }
+    }
+
+    /**
+     * Check that the installed games the backend lists are the ones on disk, that every file
+     * their manifests list is there in full, and that no install was left half done
+     */
+    fn check_index(&self) -> Result<(), Failure> {
+        // Nothing is installed until the first download makes the directory
+        if !layout::games_dir().exists() && self.installed.is_empty() {
+            return Ok(());
+        }
+        let listed: BTreeSet<String> = backend::api::game_list_from_fs()
+            .map_err(|e| failure("index matches filesystem", e.to_string()))?
+            .into_iter()
+            .map(|game| game.id)
+            .collect();
+        let on_disk: BTreeSet<String> = std::fs::read_dir(layout::games_dir())
+            .map(|entries| {
+                entries
+                    .flatten()
+                    .filter(|entry| entry.path().join("game.json").is_file())
+                    .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                    .collect()
+            })
+            .unwrap_or_default();
+        if listed != on_disk {
+            return Err(failure(
+                "index matches filesystem",
+                format!("Listed {listed:?}, but {on_disk:?} are on disk"),
+            ));
+        }
+        if let Some(missing) = self.installed.difference(&listed).next() {
+            return Err(failure(
+                "index matches filesystem",
+                format!("{missing} was installed, but isn't listed"),
+            ));
+        }
+        for id in &listed {
+            check_manifest(layout::game_dir(id).as_path())?;
+            for leftover in [layout::staging_dir(id), layout::replaced_dir(id)] {
+                if leftover.exists() {
+                    return Err(failure(
+                        "index matches filesystem",
+                        format!("{} was left behind", leftover.display()),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn record(&mut self, step: u64, operation: Operation, description: &str) {
+        *self.counts.entry(format!("{operation:?}")).or_default() += 1;
+        if self.history.len() == HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back(format!("{step}: {description}"));
+    }
+}
+
+/**
+ * Retry a check until it passes or `SETTLE_AFTER` runs out, for state a dropped connection's
+ * request may still be changing
+ */
+async fn settled(mut check: impl FnMut() -> Result<(), Failure>) -> Result<(), Failure> {
+    let started = Instant::now();
+    loop {
+        match check() {
+            Ok(()) => return Ok(()),
+            Err(e) if started.elapsed() > SETTLE_AFTER => return Err(e),
+            Err(_) => {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                clock::advance(WAIT_STEP);
+            }
+        }
+    }
+}
+
+async fn check_save(group: &str, key: &str, value: &str) -> Result<(), Failure> {
+    match persistence::load(group, key).await {
+        Ok(loaded) if loaded == value => Ok(()),
+        Ok(loaded) => Err(failure(
+            "saves durable",
+            format!("{group}/{key} loaded as {loaded}, but {value} was saved"),
+        )),
+        Err(e) => Err(failure(
+            "saves durable",
+            format!("{group}/{key} couldn't be loaded: {e}"),
+        )),
+    }
+}
+
+fn check_manifest(dir: &Path) -> Result<(), Failure> {
+    let Ok(json) = std::fs::read(dir.join("manifest.json")) else {
+        return Ok(());
+    };
+    let manifest: Map<String, Value> = serde_json::from_slice(&json).map_err(|e| {
+        failure(
+            "index matches filesystem",
+            format!("Unreadable manifest in {}: {e}", dir.display()),
+        )
+    })?;
+    for (path, entry) in manifest {
+        let expected = entry.get("size").and_then(Value::as_u64);
+        let actual = std::fs::metadata(dir.join(&path)).ok().map(|m| m.len());
+        if actual.is_none() || actual != expected {
+            return Err(failure(
+                "index matches filesystem",
+                format!(
+                    "{} is {actual:?} bytes, but its manifest says {expected:?}",
+                    dir.join(path).display()
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/**
+ * Check that every game that was launched is gone, along with its process group
+ */
+fn check_orphans(root: &Path) -> Result<(), Failure> {
+    let pids = root.join("soak/pids");
+    for entry in std::fs::read_dir(&pids).into_iter().flatten().flatten() {
+        let Ok(pid) = entry.file_name().to_string_lossy().parse::<libc::pid_t>() else {
+            continue;
+        };
+        // SAFETY: signal 0 only checks whether the process exists
+        let alive = unsafe { libc::kill(pid, 0) == 0 || libc::kill(-pid, 0) == 0 };
+        if alive {
+            return Err(failure(
+                "no orphan processes",
+                format!("Game process {pid} is still running"),
+            ));
+        }
+        let _ = std::fs::remove_file(entry.path());
+    }
+    Ok(())
+}
+
+/**
+ * Get this process's resident memory in MiB
+ */
+fn rss_mb() -> u64 {
+    let pages: u64 = std::fs::read_to_string("/proc/self/statm")
+        .ok()
+        .and_then(|statm| statm.split_whitespace().nth(1)?.parse().ok())
+        .unwrap_or(0);
+    // SAFETY: sysconf has no preconditions
+    let page_size = u64::try_from(unsafe { libc::sysconf(libc::_SC_PAGESIZE) }).unwrap_or(4096);
+    pages * page_size / (1024 * 1024)
+}
+
+/**
+ * Run the backend through randomized operations, checking the invariants after each. Returns the
+ * step that failed along with the failure.
+ */
+async fn soak(
+    options: &Options,
+    root: PathBuf,
+    catalog: Arc<Catalog>,
+) -> Result<Soak, (u64, Failure, Soak)> {
+    let mut handles = ThreadHandles::new();
+    handles.restart_onboard(onboard_pipe());
+    // The server binds its socket in the background
+    let started = Instant::now();
+    let client = loop {
+        match Client::connect().await {
+            Ok(client) => break client,
+            Err(_) if started.elapsed() < SETTLE_AFTER => {
+                tokio::time::sleep(TICK).await;
+            }
+            Err(e) => panic!("Couldn't connect to the onboard server: {e}"),
+        }
+    };
+    clock::freeze();
+
+    let mut soak = Soak {
+        rng: Rng(options.seed),
+        client,
+        handles,
+        catalog,
+        root,
+        installed: BTreeSet::new(),
+        saves: BTreeMap::new(),
+        taps: BTreeMap::new(),
+        history: VecDeque::new(),
+        counts: BTreeMap::new(),
+        peak_rss_mb: 0,
+    };
+    for step in 1..=options.steps {
+        let operation = soak.pick_operation();
+        let result = soak.run(operation).await;
+        let description = match &result {
+            Ok(description) => description.clone(),
+            Err(_) => format!("{operation:?}"),
+        };
+        soak.record(step, operation, description.as_str());
+        if options.verbose {
+            println!("{step}: {description}");
+        }
+        clock::advance(STEP);
+        let result = result.map(|_| ()).and(soak.check().await).and_then(|()| {
+            if soak.peak_rss_mb > options.max_rss_mb {
+                Err(failure(
+                    "memory below threshold",
+                    format!(
+                        "RSS is {} MiB, over the limit of {} MiB",
+                        soak.peak_rss_mb, options.max_rss_mb
+                    ),
+                ))
+            } else {
+                Ok(())
+            }
+        });
+        if let Err(e) = result {
+            return Err((step, e, soak));
+        }
+    }
+    Ok(soak)
+}
+
+/**
+ * Soak test for the backend. Runs a real backend against a mock API in a temporary data root,
+ * through a week of simulated cabinet use, and reports how to reproduce the first broken
+ * invariant.
+ */
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let Some(options) = Options::parse(&args) else {
+        eprintln!("{USAGE}");
+        return ExitCode::FAILURE;
+    };
+
+    let root = std::env::temp_dir().join(format!(
+        "devcade-soak-{}-{}",
+        options.seed,
+        std::process::id()
+    ));
+    let catalog = match Catalog::new() {
+        Ok(catalog) => Arc::new(catalog),
+        Err(e) => {
+            eprintln!("Couldn't build the mock catalog: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let listener = match std::fs::create_dir_all(root.join("soak/pids"))
+        .and_then(|()| TcpListener::bind("127.0.0.1:0"))
+    {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Couldn't set up the soak: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let api = format!("http://{}", listener.local_addr().unwrap());
+    let served = catalog.clone();
+    std::thread::spawn(move || serve(listener, served));
+
+    // Set before the backend starts, since it reads its configuration from the environment
+    std::env::set_var("DEVCADE_PATH", root.as_os_str());
+    std::env::set_var("DEVCADE_API_DOMAIN", api.as_str());
+    std::env::set_var("DEVCADE_DEV_API_DOMAIN", api.as_str());
+    std::env::set_var("DEVCADE_DEMO_ID_PREFIX", "demo-");
+    std::env::set_var("DEVCADE_FAULT_SEED", options.seed.to_string());
+    std::env::set_var("DEVCADE_MIN_FREE_MB", "0");
+    // Saves are kept relative to the working directory off the cabinet
+    if let Err(e) = std::env::set_current_dir(&root) {
+        eprintln!("Couldn't enter {}: {e}", root.display());
+        return ExitCode::FAILURE;
+    }
+    if options.verbose {
+        logging::init();
+    }
+
+    println!(
+        "Soaking for {} steps with seed {} in {}",
+        options.steps,
+        options.seed,
+        root.display()
+    );
+    let started = Instant::now();
+    let runtime = tokio::runtime::Runtime::new().expect("Couldn't start the runtime");
+    let result = runtime.block_on(soak(&options, root.clone(), catalog));
+    runtime.shutdown_background();
+
+    match result {
+        Ok(soak) => {
+            let simulated = STEP.as_secs() * options.steps;
+            println!(
+                "Passed {} steps ({:.1} simulated days) in {:.0?}, peak RSS {} MiB",
+                options.steps,
+                simulated as f64 / 86400.0,
+                started.elapsed(),
+                soak.peak_rss_mb
+            );
+            for (operation, count) in &soak.counts {
+                println!("    {operation}: {count}");
+            }
+            let _ = std::fs::remove_dir_all(&root);
+            ExitCode::SUCCESS
+        }
+        Err((step, failure, soak)) => {
+            let mut report = format!(
+                "devcade-soak failed at step {step} of {} (seed {})\nInvariant: {}\n{}\n\nLast operations:\n",
+                options.steps, options.seed, failure.invariant, failure.detail
+            );
+            for line in &soak.history {
+                report.push_str(format!("    {line}\n").as_str());
+            }
+            report.push_str(
+                format!(
+                    "\nReproduce with: devcade-soak --seed {} --steps {}\nData root kept at {}\n",
+                    options.seed,
+                    options.steps,
+                    root.display()
+                )
+                .as_str(),
+            );
+            let path = root.join("soak-report.txt");
+            let _ = std::fs::write(&path, report.as_bytes());
+            eprint!("{report}");
+            eprintln!("Report written to {}", path.display());
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,283 @@
+use crate::clock;
+use crate::env::{safe_mode_boots, safe_mode_window};
+use crate::layout;
+use anyhow::Error;
+use devcade_onboard_types::RequestBody;
+use log::{log, Level};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/**
+ * Whether the backend started in safe mode
+ */
+static SAFE_MODE: AtomicBool = AtomicBool::new(false);
+
+/**
+ * Files in the state directory that are never quarantined, since the backend needs them to boot.
+ * Secrets are kept too, since the peer and freeze keys can't be regenerated without unpairing the
+ * peer and invalidating every snapshot.
+ */
+const KEEP: [&str; 4] = ["boot.json", "backend.lock", "quarantine", "secrets.json"];
+
+/**
+ * Startups that haven't reached ready yet, as unix timestamps in seconds, and whether the backend
+ * is in safe mode. Safe mode is kept here so it lasts across restarts until it's exited.
+ */
+#[derive(Serialize, Deserialize, Default)]
+struct BootRecord {
+    attempts: Vec<u64>,
+    #[serde(default)]
+    safe_mode: bool,
+}
+
+fn boot_path() -> PathBuf {
+    layout::state_dir().join("boot.json")
+}
+
+/**
+ * The directory suspect files are moved to in safe mode
+ */
+#[must_use]
+pub fn quarantine_dir() -> PathBuf {
+    layout::state_dir().join("quarantine")
+}
+
+fn read_record() -> BootRecord {
+    std::fs::read(boot_path())
+        .ok()
+        .and_then(|record| serde_json::from_slice(&record).ok())
+        .unwrap_or_default()
+}
+
+fn write_record(record: &BootRecord) {
+    let result = serde_json::to_vec(record)
+        .map_err(std::io::Error::from)
+        .and_then(|record| layout::write_atomic(&boot_path(), record));
+    if let Err(e) = result {
+        log!(Level::Error, "Couldn't write boot record: {}", e);
+    }
+}
+
+/**
+ * Record that the backend is starting, and decide whether to start in safe mode. If the backend
+ * has started `DEVCADE_SAFE_MODE_BOOTS` times within `DEVCADE_SAFE_MODE_WINDOW_MINS` without
+ * reaching ready, it is probably crashing on something it loads, so the cache and state files are
+ * moved aside into `.state/quarantine/` and the backend starts in safe mode. A backend that was
+ * in safe mode when it stopped starts in safe mode again. Returns whether it did.
+ */
+pub fn start() -> bool {
+    let now = clock::unix_now();
+    let window = safe_mode_window().as_secs();
+    let mut record = read_record();
+    if record.safe_mode {
+        log!(
+            Level::Error,
+            "Backend was in SAFE MODE when it stopped, starting in SAFE MODE again"
+        );
+        SAFE_MODE.store(true, Ordering::SeqCst);
+        return true;
+    }
+    record
+        .attempts
+        .retain(|attempt| now.saturating_sub(*attempt) < window);
+    record.attempts.push(now);
+    write_record(&record);
+
+    if record.attempts.len() <= safe_mode_boots() {
+        return false;
+    }
+
+    log!(
+        Level::Error,
+        "Backend failed to start {} times in {} minutes, starting in SAFE MODE",
+        record.attempts.len() - 1,
+        window / 60
+    );
+    if let Err(e) = quarantine() {
+        log!(Level::Error, "Couldn't quarantine state files: {}", e);
+    }
+    record.safe_mode = true;
+    write_record(&record);
+    SAFE_MODE.store(true, Ordering::SeqCst);
+    true
+}
+
+/**
+ * Record that the backend started successfully, which resets the crash loop detection. Safe mode
+ * lasts until it's exited with devcade-ctl, even once the backend is ready.
+ */
+pub fn ready() {
+    let mut record = read_record();
+    record.attempts.clear();
+    write_record(&record);
+}
+
+/**
+ * Whether the backend is running in safe mode, where only the core commands are served
+ */
+#[must_use]
+pub fn safe_mode() -> bool {
+    SAFE_MODE.load(Ordering::SeqCst)
+}
+
+/**
+ * Move the cache directory and the state files into a new directory under `.state/quarantine/`
+ */
+fn quarantine() -> Result<(), Error> {
+    let dir = quarantine_dir().join(clock::unix_now().to_string());
+    std::fs::create_dir_all(&dir)?;
+
+    let mut suspects = vec![layout::cache_dir()];
+    for entry in std::fs::read_dir(layout::state_dir())? {
+        let path = entry?.path();
+        let keep = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| KEEP.contains(&name));
+        if !keep {
+            suspects.push(path);
+        }
+    }
+
+    for path in suspects.iter().filter(|path| path.exists()) {
+        // This unwrap is safe because every suspect has a file name
+        let target = dir.join(path.file_name().unwrap());
+        std::fs::rename(path, &target)?;
+        log!(
+            Level::Warn,
+            "Quarantined {} to {}",
+            path.display(),
+            target.display()
+        );
+    }
+    Ok(())
+}
+
+/**
+ * Leave safe mode by either restoring the quarantined files to where they were, or deleting them.
+ * Files that have been recreated since they were quarantined are kept, and the quarantined copies
+ * deleted. This should only be done while the backend is stopped.
+ *
+ * # Errors
+ * This function will return an error if the quarantined files cannot be moved or deleted.
+ */
+pub fn exit_safe_mode(restore: bool) -> Result<Vec<PathBuf>, Error> {
+    let mut handled = Vec::new();
+    if quarantine_dir().exists() {
+        let mut batches: Vec<PathBuf> = std::fs::read_dir(quarantine_dir())?
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .collect();
+        // Oldest first, so the state from before the crash loop wins
+        batches.sort();
+        for batch in batches {
+            for entry in std::fs::read_dir(&batch)? {
+                let path = entry?.path();
+                if restore {
+                    // This unwrap is safe because entries of a directory always have a file name
+                    let name = path.file_name().unwrap();
+                    let target = original_location(name);
+                    if target.exists() {
+                        log!(
+                            Level::Warn,
+                            "Not restoring {}, it has been recreated since it was quarantined",
+                            target.display()
+                        );
+                        continue;
+                    }
+                    std::fs::rename(&path, &target)?;
+                    handled.push(target);
+                } else {
+                    handled.push(path);
+                }
+            }
+        }
+        std::fs::remove_dir_all(quarantine_dir())?;
+    }
+    write_record(&BootRecord::default());
+    Ok(handled)
+}
+
+/**
+ * Where a quarantined file was moved from
+ */
+fn original_location(name: &std::ffi::OsStr) -> PathBuf {
+    let cache = layout::cache_dir();
+    if cache.file_name() == Some(name) {
+        return cache;
+    }
+    Path::new(&layout::state_dir()).join(name)
+}
+
+/**
+ * Whether a command is served in safe mode. Only what's needed to show and launch installed games
+ * is allowed, so nothing that might have caused the crash loop runs.
+ */
+#[must_use]
+pub fn allowed(body: &RequestBody) -> bool {
+    matches!(
+        body,
+        RequestBody::Ping
+            | RequestBody::GetGameListFromFs
+            | RequestBody::LaunchGame(_)
+            | RequestBody::GetCabinetInfo
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn poison_cache() {
+        std::fs::create_dir_all(layout::cache_dir()).unwrap();
+        std::fs::write(layout::cache_dir().join("atlas.json"), "{not json").unwrap();
+        std::fs::create_dir_all(layout::state_dir()).unwrap();
+        std::fs::write(layout::state_dir().join("highlights.json"), "{").unwrap();
+    }
+
+    #[test]
+    fn crash_loop_trips_safe_mode_and_survives_restart() {
+        let (_guard, root) = crate::testing::root("boot");
+        poison_cache();
+
+        // Each boot crashes on the poisoned cache before reaching ready
+        for _ in 0..safe_mode_boots() {
+            assert!(!start());
+        }
+        assert!(start());
+        assert!(safe_mode());
+        assert!(!layout::cache_dir().exists());
+        assert!(!layout::state_dir().join("highlights.json").exists());
+        assert!(boot_path().exists());
+
+        // Reaching ready doesn't leave safe mode, and neither does restarting
+        ready();
+        SAFE_MODE.store(false, Ordering::SeqCst);
+        assert!(start());
+        assert!(safe_mode());
+        assert!(quarantine_dir().exists());
+
+        let restored = exit_safe_mode(true).unwrap();
+        assert_eq!(restored.len(), 2);
+        assert!(layout::cache_dir().join("atlas.json").exists());
+        SAFE_MODE.store(false, Ordering::SeqCst);
+        assert!(!start());
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn quarantine_keeps_secrets() {
+        let (_guard, root) = crate::testing::root("boot-secrets");
+        poison_cache();
+        crate::secrets::set(crate::secrets::PEER_KEY, "shared").unwrap();
+
+        quarantine().unwrap();
+        assert!(!layout::state_dir().join("highlights.json").exists());
+        crate::secrets::forget();
+        assert_eq!(
+            crate::secrets::get(crate::secrets::PEER_KEY, "peer pairing").unwrap(),
+            "shared"
+        );
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
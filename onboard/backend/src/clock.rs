@@ -0,0 +1,265 @@
+use lazy_static::lazy_static;
+use log::{log, Level};
+use std::fmt;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::watch;
+
+/**
+ * A stand-in for the system clocks that only moves when it's advanced, so timeouts, expiries and
+ * retries can be driven without waiting for them
+ */
+#[derive(Clone, Copy)]
+struct Fake {
+    instant: Instant,
+    system: SystemTime,
+    advanced: Duration,
+}
+
+/**
+ * Checked before `FAKE`, so the real clock costs a single atomic load
+ */
+static FAKED: AtomicBool = AtomicBool::new(false);
+
+extern "C" {
+    // Not exposed by the libc crate
+    fn tzset();
+}
+
+lazy_static! {
+    static ref FAKE: Mutex<Option<Fake>> = Mutex::new(None);
+    // Sent whenever the fake clock moves, to wake up fake sleeps
+    static ref MOVED: watch::Sender<()> = watch::channel(()).0;
+}
+
+/**
+ * The error returned by `timeout` when the future took too long
+ */
+#[derive(Debug, Clone, Copy)]
+pub struct Elapsed(pub Duration);
+
+impl fmt::Display for Elapsed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Timed out after {:?}", self.0)
+    }
+}
+
+impl std::error::Error for Elapsed {}
+
+fn fake() -> Option<Fake> {
+    if FAKED.load(Ordering::Relaxed) {
+        *FAKE.lock().unwrap()
+    } else {
+        None
+    }
+}
+
+/**
+ * Get the current time on the monotonic clock. Use this (and `elapsed`) instead of
+ * `Instant::now()`, so the time can be faked.
+ */
+#[must_use]
+pub fn now() -> Instant {
+    match fake() {
+        Some(fake) => fake.instant + fake.advanced,
+        None => Instant::now(),
+    }
+}
+
+/**
+ * Get how much time has passed since an instant from `now`
+ */
+#[must_use]
+pub fn elapsed(since: Instant) -> Duration {
+    now().saturating_duration_since(since)
+}
+
+/**
+ * Get the current wall clock time
+ */
+#[must_use]
+pub fn system_now() -> SystemTime {
+    match fake() {
+        Some(fake) => fake.system + fake.advanced,
+        None => SystemTime::now(),
+    }
+}
+
+/**
+ * Get the current wall clock time as a unix timestamp in seconds
+ */
+#[must_use]
+pub fn unix_now() -> u64 {
+    system_now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/**
+ * Get the current wall clock time broken down in the local timezone. The timezone is re-read every
+ * call, so a change to the system timezone applies immediately.
+ */
+#[must_use]
+pub fn local_time() -> libc::tm {
+    let time = libc::time_t::try_from(unix_now()).unwrap_or(libc::time_t::MAX);
+    // SAFETY: localtime_r only writes to the tm struct we give it
+    unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+        tzset();
+        libc::localtime_r(&time, &mut tm);
+        tm
+    }
+}
+
+/**
+ * Wait for a while. With a fake clock, this waits for the clock to be advanced past the deadline.
+ */
+pub async fn sleep(duration: Duration) {
+    if fake().is_none() {
+        tokio::time::sleep(duration).await;
+        return;
+    }
+    let deadline = now() + duration;
+    let mut moved = MOVED.subscribe();
+    while fake().is_some() && now() < deadline {
+        if moved.changed().await.is_err() {
+            break;
+        }
+    }
+    // The clock went back to the real one partway through
+    tokio::time::sleep(deadline.saturating_duration_since(now())).await;
+}
+
+/**
+ * Wait for a future, giving up once `duration` has passed
+ *
+ * # Errors
+ * This function will return `Elapsed` if the future doesn't finish in time.
+ */
+pub async fn timeout<F: Future>(duration: Duration, future: F) -> Result<F::Output, Elapsed> {
+    if fake().is_none() {
+        return tokio::time::timeout(duration, future)
+            .await
+            .map_err(|_| Elapsed(duration));
+    }
+    tokio::select! {
+        output = future => Ok(output),
+        () = sleep(duration) => Err(Elapsed(duration)),
+    }
+}
+
+/**
+ * Something that happens every `period`, like a periodic check
+ */
+pub struct Interval {
+    period: Duration,
+    next: Instant,
+}
+
+impl Interval {
+    /**
+     * Wait for the next tick. The first tick is one period after the interval was created. Ticks
+     * that were missed (because the caller was busy) are skipped rather than bunched up.
+     */
+    pub async fn tick(&mut self) {
+        sleep(self.next.saturating_duration_since(now())).await;
+        let now = now();
+        while self.next <= now {
+            self.next += self.period;
+        }
+    }
+}
+
+/**
+ * Get an interval that ticks every `period`
+ */
+#[must_use]
+pub fn interval(period: Duration) -> Interval {
+    Interval {
+        period,
+        next: now() + period,
+    }
+}
+
+/**
+ * Stop the clocks at the current time. They only move when `advance` is called, until `unfreeze`.
+ */
+pub fn freeze() {
+    let mut fake = FAKE.lock().unwrap();
+    if fake.is_none() {
+        *fake = Some(Fake {
+            instant: Instant::now(),
+            system: SystemTime::now(),
+            advanced: Duration::ZERO,
+        });
+        FAKED.store(true, Ordering::Relaxed);
+        log!(Level::Warn, "Clock frozen");
+    }
+}
+
+/**
+ * Move frozen clocks forward, waking up everything waiting for them to get this far
+ */
+pub fn advance(duration: Duration) {
+    if let Some(fake) = FAKE.lock().unwrap().as_mut() {
+        fake.advanced += duration;
+    }
+    MOVED.send_replace(());
+}
+
+/**
+ * Go back to the real clocks. Anything still waiting on the frozen clock waits for what's left in
+ * real time.
+ */
+pub fn unfreeze() {
+    FAKED.store(false, Ordering::Relaxed);
+    *FAKE.lock().unwrap() = None;
+    MOVED.send_replace(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[tokio::test]
+    async fn waits_only_end_when_the_frozen_clock_gets_there() {
+        if !testing::alone("clock::tests::waits_only_end_when_the_frozen_clock_gets_there") {
+            return;
+        }
+        freeze();
+        let (start, system) = (now(), unix_now());
+        let sleeping = tokio::spawn(sleep(Duration::from_secs(10)));
+        let timing_out = tokio::spawn(timeout(
+            Duration::from_secs(10),
+            std::future::pending::<()>(),
+        ));
+        // Real time passes, but the clock doesn't
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        advance(Duration::from_secs(9));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!sleeping.is_finished() && !timing_out.is_finished());
+        assert_eq!(elapsed(start), Duration::from_secs(9));
+        assert_eq!(unix_now() - system, 9);
+
+        advance(Duration::from_secs(1));
+        sleeping.await.unwrap();
+        assert!(timing_out.await.unwrap().is_err());
+
+        // Ticks the clock skipped past are skipped, not bunched up
+        let mut ticks = interval(Duration::from_secs(60));
+        advance(Duration::from_secs(150));
+        ticks.tick().await;
+        let ticking = tokio::spawn(async move { ticks.tick().await });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!ticking.is_finished());
+        advance(Duration::from_secs(30));
+        ticking.await.unwrap();
+
+        unfreeze();
+        assert!(elapsed(start) < Duration::from_secs(1));
+    }
+}
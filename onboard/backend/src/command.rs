@@ -1,11 +1,14 @@
 use crate::api::{self, nfc_user};
 
 use crate::api::{
-    download_banner, download_game, download_icon, game_list, game_list_from_fs, launch_game,
-    nfc_tags, tag_games, tag_list, user,
+    accessible_games, download_banner, download_game, download_icon, game_list, game_list_from_fs,
+    launch_game, listed_games, nfc_tags, tag_games, tag_list, user,
 };
+use crate::logging;
+use crate::secrets;
 use crate::servers;
-use devcade_onboard_types::{RequestBody, ResponseBody};
+use devcade_onboard_types::{CabinetInfo, HardwareProbe, RequestBody, ResponseBody};
+use std::time::Duration;
 
 /**
  * Handle a request from the frontend.
@@ -14,22 +17,32 @@ pub async fn handle(req: RequestBody) -> ResponseBody {
     match req {
         RequestBody::Ping => ResponseBody::Pong,
         RequestBody::GetGameList => match game_list().await {
-            Ok(games) => ResponseBody::GameList(games),
+            Ok(games) => ResponseBody::GameList(listed_games(games)),
             Err(err) => err.into(),
         },
         RequestBody::GetGameListFromFs => match game_list_from_fs() {
-            Ok(games) => ResponseBody::GameList(games),
+            Ok(games) => ResponseBody::GameList(listed_games(games)),
+            Err(err) => err.into(),
+        },
+        RequestBody::GetLibrary(known) => match api::library(known) {
+            Ok(update) => ResponseBody::Library(update),
             Err(err) => err.into(),
         },
+        RequestBody::GetGameListWithAccessibility(flags) => {
+            match api::game_list_with_accessibility(&flags).await {
+                Ok(games) => ResponseBody::GameList(games),
+                Err(err) => err.into(),
+            }
+        }
         RequestBody::GetGame(game_id) => match game_list().await {
-            Ok(game) => match game.into_iter().find(|g| g.id == game_id) {
+            Ok(game) => match accessible_games(game).into_iter().find(|g| g.id == game_id) {
                 Some(game) => ResponseBody::Game(game),
                 None => ResponseBody::Err(format!("Game with ID {game_id} not found")),
             },
             Err(err) => err.into(),
         },
         RequestBody::DownloadGame(game_id) => match download_game(game_id).await {
-            Ok(_) => ResponseBody::Ok,
+            Ok(outcome) => ResponseBody::Installed(outcome),
             Err(err) => err.into(),
         },
         RequestBody::DownloadIcon(game_id) => match download_icon(game_id).await {
@@ -40,14 +53,300 @@ pub async fn handle(req: RequestBody) -> ResponseBody {
             Ok(_) => ResponseBody::Ok,
             Err(err) => err.into(),
         },
-        RequestBody::LaunchGame(game_id) => match launch_game(game_id).await {
+        RequestBody::DownloadAllAssets => match api::download_all_assets().await {
+            Ok(results) => ResponseBody::Assets(results),
+            Err(err) => err.into(),
+        },
+        RequestBody::LaunchGame(game_id) => match launch_game(game_id, false, false).await {
             Ok(_) => ResponseBody::Ok,
             Err(err) => err.into(),
         },
+        RequestBody::LaunchGameIgnoringPolicy(game_id) => {
+            match launch_game(game_id, true, false).await {
+                Ok(_) => ResponseBody::Ok,
+                Err(err) => err.into(),
+            }
+        }
+        RequestBody::LaunchGameSharingSaves(game_id) => {
+            match launch_game(game_id, false, true).await {
+                Ok(_) => ResponseBody::Ok,
+                Err(err) => err.into(),
+            }
+        }
+        RequestBody::CaptureScreenshot => match api::capture_screenshot().await {
+            Ok(path) => ResponseBody::Object(path.to_string_lossy().into_owned()),
+            Err(err) => err.into(),
+        },
+        RequestBody::PauseGame => match api::pause_current_game() {
+            Ok(()) => ResponseBody::Ok,
+            Err(err) => err.into(),
+        },
+        RequestBody::ResumeGame => match api::resume_current_game() {
+            Ok(()) => ResponseBody::Ok,
+            Err(err) => err.into(),
+        },
         RequestBody::SetProduction(prod) => {
             crate::env::set_production(prod);
             ResponseBody::Ok
         }
+        RequestBody::ReloadTls => match api::reload_tls() {
+            Ok(()) => ResponseBody::Ok,
+            Err(err) => err.into(),
+        },
+        RequestBody::SetDownloadLimit(bps) => {
+            api::set_download_limit(bps);
+            ResponseBody::Ok
+        }
+        RequestBody::SetLogLevel(target, level, expiry) => {
+            match logging::set_log_level(target, level, expiry.map(Duration::from_secs)) {
+                Ok(overrides) => ResponseBody::LogLevels(overrides),
+                Err(err) => err.into(),
+            }
+        }
+        RequestBody::SetCapture(enable, secs) => {
+            servers::capture::set_capture(enable, secs.map(Duration::from_secs));
+            ResponseBody::Ok
+        }
+        RequestBody::SetRequiredAccessibility(flags) => {
+            match api::set_required_accessibility(flags) {
+                Ok(()) => ResponseBody::Ok,
+                Err(err) => err.into(),
+            }
+        }
+        RequestBody::SaveProfile(name) => match api::save_profile(name.as_str()) {
+            Ok(()) => ResponseBody::Ok,
+            Err(err) => err.into(),
+        },
+        RequestBody::ApplyProfile(name) => match api::apply_profile(name.as_str()) {
+            Ok(changes) => ResponseBody::ProfileChanges(changes),
+            Err(err) => err.into(),
+        },
+        RequestBody::ListProfiles => match api::list_profiles() {
+            Ok(names) => ResponseBody::Profiles(names),
+            Err(err) => err.into(),
+        },
+        RequestBody::DeleteProfile(name) => match api::delete_profile(name.as_str()) {
+            Ok(()) => ResponseBody::Ok,
+            Err(err) => err.into(),
+        },
+        RequestBody::GetLogLevels => ResponseBody::LogLevels(logging::log_levels()),
+        RequestBody::GetSetupStatus => ResponseBody::SetupStatus(api::setup_status()),
+        RequestBody::SetConfigValue(key, value) => {
+            match api::set_config_value(key.as_str(), value.as_str()) {
+                Ok(()) => ResponseBody::Ok,
+                Err(err) => err.into(),
+            }
+        }
+        RequestBody::TestApiConnection => match api::test_api_connection().await {
+            Ok(()) => ResponseBody::Ok,
+            Err(err) => err.into(),
+        },
+        RequestBody::CompleteSetup => match api::complete_setup() {
+            Ok(()) => ResponseBody::Ok,
+            Err(err) => err.into(),
+        },
+        RequestBody::GetCabinetInfo => ResponseBody::CabinetInfo(CabinetInfo {
+            name: crate::env::cabinet_name(),
+            locale: crate::env::locale(),
+            timezone: crate::env::timezone(),
+            hardware: api::cabinet_hardware(),
+            safe_mode: crate::boot::safe_mode(),
+            resource_profile: crate::resources::profile(),
+            skipped: crate::resources::skipped(),
+            supported_runtimes: api::supported_runtimes(),
+            input_telemetry: crate::env::input_telemetry(),
+            catalog_events: api::catalog_events(),
+            event_hooks: api::event_hook_status(),
+            protocol: crate::servers::protocol_stats(),
+            object_store: api::object_store_report(),
+            quiet_hours: api::quiet_hours(),
+            instance_lock: crate::lock::InstanceLock::status(),
+        }),
+        RequestBody::CleanupOrphanedGames(dry_run) => {
+            match api::cleanup_orphaned_games(dry_run).await {
+                Ok(ids) => ResponseBody::OrphanedGames(ids),
+                Err(err) => err.into(),
+            }
+        }
+        RequestBody::GetDuplicateInstalls => match api::find_duplicate_installs() {
+            Ok(groups) => ResponseBody::DuplicateInstalls(groups),
+            Err(err) => err.into(),
+        },
+        RequestBody::CancelOrphanCleanup => match api::cancel_orphan_cleanup() {
+            Ok(()) => ResponseBody::Ok,
+            Err(err) => err.into(),
+        },
+        RequestBody::GetDownloadEstimate(game_id) => {
+            ResponseBody::DownloadEstimate(api::download_estimate(game_id.as_str()).await)
+        }
+        RequestBody::EnqueueDownload(game_id, priority) => {
+            ResponseBody::DownloadJob(api::enqueue_download(game_id.as_str(), priority))
+        }
+        RequestBody::PromoteDownload(job_id) => match api::promote_download(job_id) {
+            Ok(()) => ResponseBody::Ok,
+            Err(err) => err.into(),
+        },
+        RequestBody::GetDownloadQueue => ResponseBody::DownloadQueue(api::download_queue()),
+        RequestBody::CancelDownload(game_id) => match api::cancel_download(game_id.as_str()) {
+            Ok(()) => ResponseBody::Ok,
+            Err(err) => err.into(),
+        },
+        RequestBody::GetHighlights => ResponseBody::Highlights(api::highlights()),
+        RequestBody::GetFeaturedGames => match api::featured_games() {
+            Ok(games) => ResponseBody::GameList(games),
+            Err(err) => err.into(),
+        },
+        RequestBody::GetSchedule => ResponseBody::Schedule(api::schedule()),
+        RequestBody::AddScheduleEntry(entry) => match api::add_schedule_entry(entry) {
+            Ok(schedule) => ResponseBody::Schedule(schedule),
+            Err(err) => err.into(),
+        },
+        RequestBody::RemoveScheduleEntry(name) => match api::remove_schedule_entry(name.as_str()) {
+            Ok(schedule) => ResponseBody::Schedule(schedule),
+            Err(err) => err.into(),
+        },
+        RequestBody::GetQuietHours => ResponseBody::QuietHours(api::quiet_hours()),
+        RequestBody::SetQuietHours(windows) => match api::set_quiet_hours(windows) {
+            Ok(status) => ResponseBody::QuietHours(status),
+            Err(err) => err.into(),
+        },
+        RequestBody::OverrideQuietHours(lifted) => match api::override_quiet_hours(lifted) {
+            Ok(status) => ResponseBody::QuietHours(status),
+            Err(err) => err.into(),
+        },
+        RequestBody::GetEventHighlights(label) => {
+            ResponseBody::Highlights(api::event_highlights(label.as_str()))
+        }
+        RequestBody::GetEventLabel => ResponseBody::EventLabel(api::event_label()),
+        RequestBody::GetLifetimeStats => match api::lifetime_stats() {
+            Ok(games) => ResponseBody::LifetimeStats(games),
+            Err(err) => err.into(),
+        },
+        RequestBody::ExportSessions(start, end) => match api::export_sessions(start, end) {
+            Ok(export) => ResponseBody::SessionExport(export),
+            Err(err) => err.into(),
+        },
+        RequestBody::CompactStats(days) => match api::compact_stats(days) {
+            Ok(compaction) => ResponseBody::StatsCompaction(compaction),
+            Err(err) => err.into(),
+        },
+        RequestBody::SetEventLabel(label) => match api::set_event_label(label) {
+            Ok(label) => ResponseBody::EventLabel(label),
+            Err(err) => err.into(),
+        },
+        RequestBody::UserActivity => {
+            api::user_activity();
+            ResponseBody::Ok
+        }
+        RequestBody::ReportInputActivity(counts) => match api::report_input_activity(counts) {
+            Ok(()) => ResponseBody::Ok,
+            Err(err) => err.into(),
+        },
+        RequestBody::GetInputActivity(game_id) => {
+            ResponseBody::InputActivity(api::input_activity(game_id.as_str()))
+        }
+        RequestBody::SetSecret(name, value) => match secrets::set(name.as_str(), value.as_str()) {
+            Ok(()) => ResponseBody::Ok,
+            Err(err) => err.into(),
+        },
+        RequestBody::RotateSecret(name) => match secrets::rotate(name.as_str()) {
+            Ok(()) => ResponseBody::Ok,
+            Err(err) => err.into(),
+        },
+        RequestBody::ListSecrets => match secrets::list() {
+            Ok(secrets) => ResponseBody::Secrets(secrets),
+            Err(err) => err.into(),
+        },
+        RequestBody::GetDisplayProtection => {
+            ResponseBody::DisplayProtection(api::display_protection())
+        }
+        RequestBody::GetUpdateSummary => ResponseBody::UpdateSummary(api::update_summary()),
+        RequestBody::GetPeerStatus => ResponseBody::PeerLink(api::peer_status()),
+        RequestBody::SetPeerConsent(association_id, consent) => {
+            match api::set_peer_consent(association_id.as_str(), consent) {
+                Ok(()) => ResponseBody::Ok,
+                Err(err) => err.into(),
+            }
+        }
+        RequestBody::GetGameResources => ResponseBody::GameResources(api::game_resources()),
+        RequestBody::GetFeatureUsage(game_id) => {
+            ResponseBody::FeatureUsage(api::feature_usage(game_id.as_str()))
+        }
+        RequestBody::GetFeatureAdoption => {
+            ResponseBody::FeatureAdoption(api::feature_adoption_report())
+        }
+        RequestBody::FreezeCatalog(label) => match api::freeze_catalog(label.as_str()) {
+            Ok(snapshot) => ResponseBody::CatalogSnapshot(snapshot),
+            Err(err) => err.into(),
+        },
+        RequestBody::Unfreeze => match api::unfreeze_catalog() {
+            Ok(()) => ResponseBody::Ok,
+            Err(err) => err.into(),
+        },
+        RequestBody::GetCatalogFreeze => ResponseBody::CatalogFreeze(api::catalog_freeze()),
+        RequestBody::VerifyFreeze(label) => match api::verify_freeze(label).await {
+            Ok(drift) => ResponseBody::FreezeDrift(drift),
+            Err(err) => err.into(),
+        },
+        RequestBody::GetSuspiciousUpdates => {
+            ResponseBody::SuspiciousUpdates(api::suspicious_updates())
+        }
+        RequestBody::ConfirmSuspiciousUpdate(game_id) => {
+            match api::confirm_suspicious_update(game_id.as_str()) {
+                Ok(()) => ResponseBody::Ok,
+                Err(err) => err.into(),
+            }
+        }
+        RequestBody::GetGameRuntime(game_id) => match api::game_runtime(game_id.as_str()).await {
+            Ok(runtime) => ResponseBody::GameRuntime(runtime),
+            Err(err) => err.into(),
+        },
+        RequestBody::SubmitSessionSummary(data) => match api::submit_session_summary(data) {
+            Ok(()) => ResponseBody::Ok,
+            Err(err) => err.into(),
+        },
+        RequestBody::GetLaunchEvents(after) => {
+            ResponseBody::LaunchEvents(api::launch_events(after))
+        }
+        RequestBody::Negotiate(version) => {
+            ResponseBody::Negotiated(crate::servers::legacy::negotiated(version))
+        }
+        RequestBody::Subscribe(since) => ResponseBody::Subscription(api::subscribe(since).await),
+        RequestBody::GetDownloadProgress(game_id) => {
+            ResponseBody::DownloadProgress(api::download_progress(game_id.as_str()))
+        }
+        RequestBody::GetCabinetHardware => ResponseBody::CabinetHardware(api::cabinet_hardware()),
+        RequestBody::GetCabinetSetting(key) => match api::get_cabinet_setting(key.as_str()) {
+            Ok(value) => ResponseBody::Object(value),
+            Err(err) => err.into(),
+        },
+        RequestBody::SetCabinetSetting(key, value) => {
+            match api::set_cabinet_setting(key.as_str(), value.as_deref(), "operator") {
+                Ok(()) => ResponseBody::Ok,
+                Err(err) => err.into(),
+            }
+        }
+        RequestBody::ProbeHardware => {
+            ResponseBody::CabinetHardware(HardwareProbe::Ready(api::probe_hardware().await))
+        }
+        RequestBody::PrepareRestart => match api::prepare_restart().await {
+            Ok(()) => ResponseBody::Ok,
+            Err(err) => err.into(),
+        },
+        RequestBody::RunGameSetup(game_id) => match api::run_game_setup(game_id.as_str()).await {
+            Ok(record) => ResponseBody::GameSetup(record),
+            Err(err) => err.into(),
+        },
+        RequestBody::TestEventHooks(event_type) => {
+            match api::test_event_hooks(event_type.as_str()).await {
+                Ok(test) => ResponseBody::EventHookTest(test),
+                Err(err) => err.into(),
+            }
+        }
+        RequestBody::MaintainObjectStore => match api::maintain_object_store().await {
+            Ok(report) => ResponseBody::ObjectStoreReport(report),
+            Err(err) => err.into(),
+        },
         RequestBody::GetTagList => match tag_list().await {
             Ok(tags) => ResponseBody::TagList(tags),
             Err(err) => err.into(),
@@ -59,8 +358,20 @@ pub async fn handle(req: RequestBody) -> ResponseBody {
             },
             Err(err) => err.into(),
         },
+        RequestBody::GetTagMembership(refresh) => match api::tag_membership(refresh).await {
+            Ok(membership) => ResponseBody::TagMembership(membership),
+            Err(err) => err.into(),
+        },
+        RequestBody::GetTagCounts => ResponseBody::TagCounts(api::tag_counts()),
+        RequestBody::GetTagStats(tag_name) => {
+            ResponseBody::CatalogStats(api::tag_stats(tag_name.as_str()))
+        }
+        RequestBody::GetAuthorStats(author) => match api::author_stats(author.as_str()) {
+            Ok(stats) => ResponseBody::CatalogStats(stats),
+            Err(err) => err.into(),
+        },
         RequestBody::GetGameListFromTag(tag_name) => match tag_games(tag_name).await {
-            Ok(games) => ResponseBody::GameList(games),
+            Ok(games) => ResponseBody::GameList(listed_games(games)),
             Err(err) => err.into(),
         },
         RequestBody::GetUser(uid) => match user(uid).await {
@@ -71,24 +382,65 @@ pub async fn handle(req: RequestBody) -> ResponseBody {
             Ok(association_id) => ResponseBody::NfcTag(association_id),
             Err(err) => err.into(),
         },
+        RequestBody::GetIconAtlas(max_size, cell) => match api::build_icon_atlas(max_size, cell) {
+            Ok(atlas) => ResponseBody::IconAtlas(atlas),
+            Err(err) => err.into(),
+        },
+        RequestBody::GetGameListWithThumbnails => match api::game_list_with_thumbnails().await {
+            Ok(list) => ResponseBody::GameListWithThumbnails(list),
+            Err(err) => err.into(),
+        },
+        RequestBody::GetTapStats => ResponseBody::TapStats(api::tap_stats()),
+        RequestBody::GetCacheStats => ResponseBody::CacheStats(api::cache_stats()),
+        RequestBody::GetTapAudit(start, end) => match crate::audit::tap_audit(start, end) {
+            Ok(entries) => ResponseBody::TapAudit(entries),
+            Err(err) => err.into(),
+        },
         RequestBody::GetNfcUser(association_id) => match nfc_user(association_id).await {
             Ok(user) => ResponseBody::NfcUser(user),
             Err(err) => err.into(),
         },
         RequestBody::Save(group, key, value) => {
-            let group = format!("{}/{}", api::current_game().id, group);
+            let group = format!("{}/{}", api::save_namespace(), group);
             match servers::persistence::save(group.as_str(), key.as_str(), value.as_str()).await {
                 Ok(()) => ResponseBody::Ok,
                 Err(err) => err.into(),
             }
         }
         RequestBody::Load(group, key) => {
-            let group = format!("{}/{}", api::current_game().id, group);
+            let group = format!("{}/{}", api::save_namespace(), group);
             match servers::persistence::load(group.as_str(), key.as_str()).await {
                 Ok(s) => ResponseBody::Object(s),
                 Err(err) => err.into(),
             }
         }
+        RequestBody::BeginSave(group, key) => {
+            let group = format!("{}/{}", api::save_namespace(), group);
+            match servers::persistence::begin_save(group.as_str(), key.as_str()).await {
+                Ok(stream_id) => ResponseBody::Object(stream_id),
+                Err(err) => err.into(),
+            }
+        }
+        RequestBody::AppendSave(stream_id, chunk) => {
+            match servers::persistence::append_save(stream_id.as_str(), chunk.as_str()).await {
+                Ok(()) => ResponseBody::Ok,
+                Err(err) => err.into(),
+            }
+        }
+        RequestBody::CommitSave(stream_id) => {
+            match servers::persistence::commit_save(stream_id.as_str()).await {
+                Ok(()) => ResponseBody::Ok,
+                Err(err) => err.into(),
+            }
+        }
+        RequestBody::LoadRange(group, key, offset, len) => {
+            let group = format!("{}/{}", api::save_namespace(), group);
+            match servers::persistence::load_range(group.as_str(), key.as_str(), offset, len).await
+            {
+                Ok(s) => ResponseBody::Object(s),
+                Err(err) => err.into(),
+            }
+        }
         RequestBody::Flush => match servers::persistence::flush().await {
             Ok(()) => ResponseBody::Ok,
             Err(err) => err.into(),
@@ -0,0 +1,185 @@
+use crate::env;
+use anyhow::{anyhow, Error};
+use lazy_static::lazy_static;
+use log::{log, Level};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/**
+ * Where faults can be injected. Faults configured for a site apply to everything under it, so
+ * `network` covers both `network:request` and `network:download`.
+ */
+pub mod site {
+    pub const NETWORK_REQUEST: &str = "network:request";
+    pub const NETWORK_DOWNLOAD: &str = "network:download";
+    pub const FS_INSTALL: &str = "fs:install";
+    pub const FS_SAVE: &str = "fs:save";
+    pub const IPC_ONBOARD: &str = "ipc:onboard";
+    pub const IPC_PERSISTENCE: &str = "ipc:persistence";
+}
+
+/**
+ * Checked before `FAULTS`, so the backend pays a single atomic load per site when no faults are
+ * configured
+ */
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    static ref FAULTS: Mutex<Faults> = Mutex::new(Faults::default());
+}
+
+#[derive(Default)]
+struct Faults {
+    /**
+     * How likely an operation is to fail, from 0 to 1, by site
+     */
+    probabilities: BTreeMap<String, f64>,
+    /**
+     * State of the xorshift generator faults are drawn from. It's seeded from
+     * `DEVCADE_FAULT_SEED`, so a run can be repeated exactly.
+     */
+    state: u64,
+}
+
+impl Faults {
+    fn probability(&self, site: &str) -> Option<f64> {
+        // The most specific match wins, e.g. `network:download` over `network`
+        self.probabilities
+            .iter()
+            .filter(|(prefix, _)| {
+                site == prefix.as_str()
+                    || site
+                        .strip_prefix(prefix.as_str())
+                        .is_some_and(|rest| rest.starts_with(':'))
+            })
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, probability)| *probability)
+    }
+
+    fn draw(&mut self) -> f64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        (self.state >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/**
+ * The error returned by an operation a fault was injected into
+ */
+#[derive(Debug, Clone)]
+pub struct InjectedFault {
+    pub site: String,
+}
+
+impl fmt::Display for InjectedFault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "InjectedFault: {}", self.site)
+    }
+}
+
+/**
+ * Read the faults to inject from `DEVCADE_FAULTS`. Call this once at startup.
+ */
+pub fn init() {
+    let Some(faults) = env::faults() else {
+        return;
+    };
+    for fault in faults.split(',').filter(|fault| !fault.trim().is_empty()) {
+        let parsed = fault
+            .split_once('=')
+            .and_then(|(site, p)| Some((site.trim(), p.trim().parse::<f64>().ok()?)))
+            .filter(|(site, p)| !site.is_empty() && (0.0..=1.0).contains(p));
+        match parsed {
+            Some((site, probability)) => set(site, probability),
+            None => log!(Level::Warn, "Ignoring invalid fault '{}'", fault),
+        }
+    }
+}
+
+/**
+ * Make operations at a site (and everything under it) fail with a probability from 0 to 1. 1
+ * makes every operation fail, and 0 stops injecting faults there.
+ */
+pub fn set(site: &str, probability: f64) {
+    let mut faults = FAULTS.lock().unwrap();
+    if faults.state == 0 {
+        faults.state = env::fault_seed().max(1);
+    }
+    faults.probabilities.insert(site.to_string(), probability);
+    log!(
+        Level::Warn,
+        "Injecting faults at {} with probability {}",
+        site,
+        probability
+    );
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/**
+ * Stop injecting faults anywhere
+ */
+pub fn clear() {
+    ENABLED.store(false, Ordering::Relaxed);
+    FAULTS.lock().unwrap().probabilities.clear();
+}
+
+/**
+ * Call before an operation at a site, to make it fail if a fault is due
+ *
+ * # Errors
+ * This function will return an `InjectedFault` if the operation should fail.
+ */
+pub fn check(site: &str) -> Result<(), Error> {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+    let mut faults = FAULTS.lock().unwrap();
+    let Some(probability) = faults.probability(site) else {
+        return Ok(());
+    };
+    if faults.draw() < probability {
+        log!(Level::Warn, "Injecting fault at {}", site);
+        return Err(anyhow!(InjectedFault {
+            site: site.to_string()
+        }));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn faults(sites: &[(&str, f64)], seed: u64) -> Faults {
+        Faults {
+            probabilities: sites
+                .iter()
+                .map(|(site, probability)| (site.to_string(), *probability))
+                .collect(),
+            state: seed,
+        }
+    }
+
+    #[test]
+    fn the_most_specific_site_wins() {
+        let faults = faults(&[("network", 1.0), (site::NETWORK_DOWNLOAD, 0.25)], 1);
+        assert_eq!(faults.probability(site::NETWORK_REQUEST), Some(1.0));
+        assert_eq!(faults.probability(site::NETWORK_DOWNLOAD), Some(0.25));
+        assert_eq!(faults.probability("networking"), None);
+        assert_eq!(faults.probability(site::FS_SAVE), None);
+    }
+
+    #[test]
+    fn the_same_seed_draws_the_same_faults() {
+        let draws = |seed| {
+            let mut faults = faults(&[], seed);
+            (0..100).map(|_| faults.draw()).collect::<Vec<_>>()
+        };
+        assert_eq!(draws(42), draws(42));
+        assert_ne!(draws(42), draws(43));
+        assert!(draws(42).iter().all(|draw| (0.0..1.0).contains(draw)));
+    }
+}
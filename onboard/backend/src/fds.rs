@@ -0,0 +1,278 @@
+use anyhow::Error;
+use log::{log, Level};
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::time::Duration;
+
+/**
+ * How long background work waits for file descriptors to free up before trying again, and how many
+ * times it tries before giving up
+ */
+const PAUSE: Duration = Duration::from_secs(5);
+const MAX_PAUSES: u32 = 12;
+
+/**
+ * The error wrapped around anything that failed because the backend or the system ran out of file
+ * descriptors, so it can be told apart from the I/O error it surfaced as
+ */
+#[derive(Debug, Clone, Copy)]
+pub struct ResourceExhausted {
+    /**
+     * How many file descriptors the backend had open when it was noticed, if they could be counted
+     */
+    pub open: Option<usize>,
+}
+
+impl fmt::Display for ResourceExhausted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ResourceExhausted: out of file descriptors")?;
+        if let Some(open) = self.open {
+            write!(f, " ({open} open of {})", limit())?;
+        }
+        Ok(())
+    }
+}
+
+/**
+ * Get the soft limit on open file descriptors, or the usual default if it can't be read
+ */
+#[must_use]
+pub fn limit() -> u64 {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: limit is a valid rlimit for getrlimit to fill in
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+        return 1024;
+    }
+    limit.rlim_cur
+}
+
+/**
+ * Raise the soft limit on open file descriptors to the hard limit, since syncing the whole library
+ * can hold more than the default 1024 open at once. Call this at startup, before the download
+ * limits are read.
+ */
+pub fn raise_limit() {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    // SAFETY: limit is a valid rlimit for getrlimit to fill in
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+        log!(
+            Level::Warn,
+            "Couldn't read the open file limit: {}",
+            io::Error::last_os_error()
+        );
+        return;
+    }
+    if limit.rlim_cur >= limit.rlim_max {
+        log!(Level::Info, "Open file limit is {}", limit.rlim_cur);
+        return;
+    }
+
+    let old = limit.rlim_cur;
+    limit.rlim_cur = limit.rlim_max;
+    // SAFETY: limit is a valid rlimit, and only the soft limit was changed
+    if unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &limit) } == 0 {
+        log!(
+            Level::Info,
+            "Raised open file limit from {} to {}",
+            old,
+            limit.rlim_cur
+        );
+    } else {
+        log!(
+            Level::Warn,
+            "Couldn't raise open file limit from {}: {}",
+            old,
+            io::Error::last_os_error()
+        );
+    }
+}
+
+/**
+ * Count the file descriptors the backend has open
+ */
+#[must_use]
+pub fn open_count() -> Option<usize> {
+    Some(std::fs::read_dir("/proc/self/fd").ok()?.count())
+}
+
+/**
+ * Whether an I/O error means the backend or the system is out of file descriptors
+ */
+#[must_use]
+pub fn is_exhaustion(error: &io::Error) -> bool {
+    matches!(error.raw_os_error(), Some(libc::EMFILE | libc::ENFILE))
+}
+
+/**
+ * Whether an error was caused by running out of file descriptors, anywhere in its chain
+ */
+#[must_use]
+pub fn is_exhausted(error: &Error) -> bool {
+    error.downcast_ref::<ResourceExhausted>().is_some()
+        || error
+            .chain()
+            .filter_map(|cause| cause.downcast_ref::<io::Error>())
+            .any(is_exhaustion)
+}
+
+/**
+ * Mark an error caused by running out of file descriptors as `ResourceExhausted`. Other errors are
+ * returned as they are.
+ */
+#[must_use]
+pub fn classify(error: Error) -> Error {
+    if error.downcast_ref::<ResourceExhausted>().is_none() && is_exhausted(&error) {
+        let exhausted = ResourceExhausted { open: open_count() };
+        log!(Level::Warn, "{}", exhausted);
+        return error.context(exhausted);
+    }
+    error
+}
+
+/**
+ * Run background work, and if it fails for lack of file descriptors, wait for some to free up and
+ * run it again instead of failing. Other errors are returned straight away.
+ *
+ * # Errors
+ * This function will return the work's error if it isn't caused by running out of file
+ * descriptors, or if it still is after waiting a while.
+ */
+pub async fn patiently<F, U, T>(mut work: F) -> Result<T, Error>
+where
+    F: FnMut() -> U,
+    U: Future<Output = Result<T, Error>>,
+{
+    let mut pauses = 0;
+    loop {
+        match work().await {
+            Err(e) if pauses < MAX_PAUSES && is_exhausted(&e) => {
+                pauses += 1;
+                log!(
+                    Level::Warn,
+                    "Out of file descriptors, pausing background work for {:?}",
+                    PAUSE
+                );
+                crate::clock::sleep(PAUSE).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock;
+    use std::process::Command;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    const JOBS: usize = 8;
+
+    #[test]
+    fn exhaustion_is_told_apart_from_other_io_errors() {
+        let emfile = Error::from(io::Error::from_raw_os_error(libc::EMFILE)).context("Opening");
+        assert!(is_exhausted(&emfile));
+        let classified = classify(emfile);
+        assert!(classified.downcast_ref::<ResourceExhausted>().is_some());
+        assert!(classified.to_string().starts_with("ResourceExhausted"));
+
+        let missing = Error::from(io::Error::from_raw_os_error(libc::ENOENT));
+        assert!(!is_exhausted(&missing));
+        assert!(classify(missing)
+            .downcast_ref::<ResourceExhausted>()
+            .is_none());
+    }
+
+    /**
+     * Not a test by itself: when run by `background_work_waits_out_a_low_limit` with
+     * `DEVCADE_FD_TEST` set, lowers the open file limit so only a few more files fit, and runs
+     * jobs that each hold a couple of files open at once. Prints how many attempts they took.
+     */
+    #[tokio::test]
+    async fn low_limit_sync() {
+        if std::env::var("DEVCADE_FD_TEST").is_err() {
+            return;
+        }
+        let open = open_count().unwrap() as u64;
+        let limit = libc::rlimit {
+            rlim_cur: open + 6,
+            rlim_max: limit_max(),
+        };
+        // SAFETY: limit is a valid rlimit, and this process only runs this test
+        assert_eq!(unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &limit) }, 0);
+        // Pauses pass as soon as everything else has had a turn
+        clock::freeze();
+        tokio::spawn(async {
+            loop {
+                tokio::task::yield_now().await;
+                clock::advance(PAUSE);
+            }
+        });
+
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let jobs: Vec<_> = (0..JOBS)
+            .map(|_| {
+                let attempts = Arc::clone(&attempts);
+                tokio::spawn(patiently(move || {
+                    let attempts = Arc::clone(&attempts);
+                    async move {
+                        attempts.fetch_add(1, Ordering::SeqCst);
+                        let exe = std::env::current_exe()?;
+                        let files = (0..2)
+                            .map(|_| std::fs::File::open(&exe))
+                            .collect::<Result<Vec<_>, _>>()
+                            .map_err(|e| classify(e.into()))?;
+                        for _ in 0..10 {
+                            tokio::task::yield_now().await;
+                        }
+                        drop(files);
+                        Ok(())
+                    }
+                }))
+            })
+            .collect();
+        for job in jobs {
+            job.await.unwrap().unwrap();
+        }
+        println!("attempts {}", attempts.load(Ordering::SeqCst));
+    }
+
+    fn limit_max() -> u64 {
+        let mut limit = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        // SAFETY: limit is a valid rlimit for getrlimit to fill in
+        unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) };
+        limit.rlim_max
+    }
+
+    #[test]
+    fn background_work_waits_out_a_low_limit() {
+        let output = Command::new(std::env::current_exe().unwrap())
+            .args(["fds::tests::low_limit_sync", "--exact", "--nocapture"])
+            .env("DEVCADE_FD_TEST", "1")
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(output.status.success(), "{stdout}");
+        let attempts: usize = stdout
+            .lines()
+            // Printed after the test's name, on the same line
+            .find_map(|line| line.split_once("attempts ").map(|(_, attempts)| attempts))
+            .unwrap()
+            .trim()
+            .parse()
+            .unwrap();
+        // Every job finished, and some had to wait for files to be closed first
+        assert!(attempts > JOBS, "{attempts} attempts");
+    }
+}
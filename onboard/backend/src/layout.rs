@@ -0,0 +1,313 @@
+use crate::env::devcade_path;
+use lazy_static::lazy_static;
+use log::{log, Level};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+lazy_static! {
+    // basically just checks if a user 'devcade' exists. If so, assumes that this is running on the
+    // machine, and saves to the homedir. Otherwise, saves to the cwd.
+    static ref ON_MACHINE: bool = Path::new("/home/devcade").exists();
+}
+
+/**
+ * Whether the backend is running on the cabinet, rather than on a developer's machine
+ */
+#[must_use]
+pub fn on_machine() -> bool {
+    *ON_MACHINE
+}
+
+/**
+ * The layout of the devcade directory. Every path the backend uses inside it is built here, so the
+ * structure is defined in one place:
+ *
+ * ```text
+ * <devcade_path>/
+ * |- games/<id>/        installed games (game.json, art, publish/, screenshots/)
+ * |- .state/            backend-internal data
+ * |- .cache/            data the backend can regenerate
+ * |- logs/              frontend logs
+ * |- tmp/               partial downloads, and installs being staged
+ * |- onboard.sock       socket the frontend connects to
+ * |- persistence.sock   socket games connect to
+ * ```
+ *
+ * Anything else in the directory is unmanaged: it's left alone, and never treated as a game.
+ */
+#[must_use]
+pub fn root() -> PathBuf {
+    PathBuf::from(devcade_path())
+}
+
+/**
+ * The directory all games are installed in
+ */
+#[must_use]
+pub fn games_dir() -> PathBuf {
+    games_dir_in(root().as_path())
+}
+
+/**
+ * The directory games are installed in under a given devcade directory
+ */
+#[must_use]
+pub fn games_dir_in(root: &Path) -> PathBuf {
+    root.join("games")
+}
+
+/**
+ * The directory a game is installed in
+ */
+#[must_use]
+pub fn game_dir(id: &str) -> PathBuf {
+    games_dir().join(id)
+}
+
+/**
+ * The directory for backend-internal data
+ */
+#[must_use]
+pub fn state_dir() -> PathBuf {
+    root().join(".state")
+}
+
+/**
+ * The directory for data the backend can regenerate, like icon atlases
+ */
+#[must_use]
+pub fn cache_dir() -> PathBuf {
+    root().join(".cache")
+}
+
+/**
+ * The directory installed games share identical files through, each named by its SHA-256 hash
+ */
+#[must_use]
+pub fn objects_dir() -> PathBuf {
+    root().join(".objects")
+}
+
+/**
+ * The directory for in-progress downloads, which are removed once they're finished with
+ */
+#[must_use]
+pub fn tmp_dir() -> PathBuf {
+    root().join("tmp")
+}
+
+/**
+ * Where a game's archive is downloaded to until the download is complete, so an interrupted
+ * download can be resumed
+ */
+#[must_use]
+pub fn partial_download(id: &str) -> PathBuf {
+    tmp_dir().join(format!("{id}.partial"))
+}
+
+/**
+ * Where a complete game archive is kept until it's installed, named by its type (like `zip`)
+ */
+#[must_use]
+pub fn downloaded_archive(id: &str, extension: &str) -> PathBuf {
+    tmp_dir().join(format!("{id}.{extension}"))
+}
+
+/**
+ * Where a game's new install is extracted before it replaces the installed copy. It's kept out of
+ * the game's directory, so removing the game can't pull it out from under the install.
+ */
+#[must_use]
+pub fn staging_dir(id: &str) -> PathBuf {
+    tmp_dir().join(format!("{id}.staging"))
+}
+
+/**
+ * Where the files a new install replaces are moved until it's in place
+ */
+#[must_use]
+pub fn replaced_dir(id: &str) -> PathBuf {
+    tmp_dir().join(format!("{id}.old"))
+}
+
+/**
+ * The directory the frontend writes its logs to
+ */
+#[must_use]
+pub fn logs_dir() -> PathBuf {
+    root().join("logs")
+}
+
+/**
+ * The directory output of a game's setup step is written to
+ */
+#[must_use]
+pub fn game_logs_dir(id: &str) -> PathBuf {
+    logs_dir().join("games").join(id)
+}
+
+/**
+ * The socket the frontend connects to
+ */
+#[must_use]
+pub fn onboard_socket() -> PathBuf {
+    root().join("onboard.sock")
+}
+
+/**
+ * The socket games connect to
+ */
+#[must_use]
+pub fn persistence_socket() -> PathBuf {
+    root().join("persistence.sock")
+}
+
+/**
+ * The file recording the devcade directory the backend last ran with. It's kept outside the
+ * devcade directory, so it's still found after `DEVCADE_PATH` changes.
+ */
+#[must_use]
+pub fn last_root_marker() -> PathBuf {
+    PathBuf::from(if on_machine() {
+        "/home/devcade/.devcade_last_root"
+    } else {
+        "./.devcade_last_root"
+    })
+}
+
+/**
+ * Write a file by writing a temporary file next to it and renaming it into place, so anything
+ * reading it, or a crash partway through, never sees half a file. The file's directory is created
+ * if it's missing.
+ *
+ * # Errors
+ * This function will return an error if the file cannot be written.
+ */
+pub fn write_atomic(path: &Path, contents: impl AsRef<[u8]>) -> std::io::Result<()> {
+    write_with_mode(path, contents.as_ref(), 0o666)
+}
+
+/**
+ * Write a file the same way as `write_atomic`, but so only the backend's user can read it
+ *
+ * # Errors
+ * This function will return an error if the file cannot be written.
+ */
+pub fn write_private(path: &Path, contents: impl AsRef<[u8]>) -> std::io::Result<()> {
+    write_with_mode(path, contents.as_ref(), 0o600)
+}
+
+fn write_with_mode(path: &Path, contents: &[u8], mode: u32) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    // The mode only applies when the file is created, so a leftover temporary file can't keep
+    // looser permissions
+    if tmp.exists() {
+        std::fs::remove_file(&tmp)?;
+    }
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(mode)
+        .open(&tmp)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    std::fs::rename(&tmp, path)
+}
+
+/**
+ * Whether a path directly inside the devcade directory is part of the layout
+ */
+fn is_managed(path: &Path) -> bool {
+    let hidden = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.starts_with('.'));
+    hidden
+        || *path == games_dir()
+        || *path == logs_dir()
+        || *path == tmp_dir()
+        || *path == onboard_socket()
+        || *path == persistence_socket()
+}
+
+/**
+ * Find everything in the devcade directory that isn't part of the layout, like backups or folders
+ * operators dropped there
+ */
+#[must_use]
+pub fn unmanaged_paths() -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(root()) else {
+        return Vec::new();
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| !is_managed(path))
+        .collect();
+    paths.sort();
+    paths
+}
+
+/**
+ * Move games installed directly in the devcade directory, from before games had their own
+ * directory, into `games/`. Old game directories are recognized by their `game.json`, so unrelated
+ * directories stay where they are. Unmanaged paths left over afterwards are logged once.
+ */
+pub fn migrate() {
+    if let Err(e) = std::fs::create_dir_all(games_dir()) {
+        log!(Level::Error, "Couldn't create games directory: {}", e);
+        return;
+    }
+
+    for path in unmanaged_paths() {
+        if !path.join("game.json").is_file() {
+            continue;
+        }
+        // This unwrap is safe because entries of a directory always have a file name
+        let target = games_dir().join(path.file_name().unwrap());
+        if target.exists() {
+            log!(
+                Level::Warn,
+                "Not moving {} into the games directory, {} already exists",
+                path.display(),
+                target.display()
+            );
+            continue;
+        }
+        match std::fs::rename(&path, &target) {
+            Ok(()) => log!(
+                Level::Info,
+                "Moved game {} to {}",
+                path.display(),
+                target.display()
+            ),
+            Err(e) => log!(
+                Level::Error,
+                "Couldn't move game {} to {}: {}",
+                path.display(),
+                target.display(),
+                e
+            ),
+        }
+    }
+
+    let unmanaged = unmanaged_paths();
+    if !unmanaged.is_empty() {
+        log!(
+            Level::Info,
+            "Ignoring unmanaged paths in {}: {}",
+            root().display(),
+            unmanaged
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+}
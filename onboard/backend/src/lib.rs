@@ -21,14 +21,80 @@ pub mod command;
  */
 pub mod nfc;
 
+/**
+ * Module for making sure only one backend runs at a time
+ */
+pub mod lock;
+
+/**
+ * Module defining where everything lives in the devcade directory
+ */
+pub mod layout;
+
+/**
+ * Module for the privacy-preserving audit trail of NFC taps
+ */
+pub mod audit;
+
+/**
+ * Module for detecting crash loops at startup and booting into safe mode
+ */
+pub mod boot;
+
+/**
+ * Module for the logger, whose levels can be changed while the backend is running
+ */
+pub mod logging;
+
+/**
+ * Module for keeping the backend within its file descriptor budget
+ */
+pub mod fds;
+
+/**
+ * Module for scaling back optional work on low memory cabinets
+ */
+pub mod resources;
+
+/**
+ * Module for the time, which can be frozen and moved forward to check timing dependent behaviour
+ * without waiting
+ */
+pub mod clock;
+
+/**
+ * Module for injecting failures into network, filesystem and IPC operations, to check how the
+ * backend copes with them
+ */
+pub mod faults;
+
+/**
+ * Module for secrets the backend keeps on disk, like salts, readable only by the backend's user
+ */
+pub mod secrets;
+
+/**
+ * Module for state the backend keeps in JSON files, like game runtimes and highlights
+ */
+pub mod state;
+
+/**
+ * Module for helpers shared by tests
+ */
+#[cfg(test)]
+mod testing;
+
 /**
  * Module for safely getting environment variables, logging any errors that occur and providing
  * default values.
  */
 pub mod env {
     // TODO Cache env vars? Probably not necessary
+    use devcade_onboard_types::ResourceProfile;
     use log::{log, Level};
     use std::env;
+    use std::path::Path;
+    use std::time::Duration;
 
     static mut PRODUCTION: bool = true;
 
@@ -54,6 +120,54 @@ pub mod env {
         }
     }
 
+    /**
+     * Get the file settings are loaded from at startup, and written to by the first-run setup.
+     * If the value is not set in the environment, it will default to `../.env`.
+     */
+    #[must_use]
+    pub fn config_file() -> String {
+        env::var("DEVCADE_CONFIG_FILE")
+            .ok()
+            .filter(|path| !path.is_empty())
+            .unwrap_or_else(|| String::from("../.env"))
+    }
+
+    /**
+     * Get the cabinet's name, shown to players and operators.
+     * If the value is not set in the environment, the cabinet has no name.
+     */
+    #[must_use]
+    pub fn cabinet_name() -> Option<String> {
+        env::var("DEVCADE_CABINET_NAME")
+            .ok()
+            .filter(|name| !name.is_empty())
+    }
+
+    /**
+     * Get the URL of an API domain. A domain with a scheme is used as is, so a local mock API like
+     * `devcade-soak`'s can be served over plain HTTP.
+     */
+    fn domain_url(domain: String) -> String {
+        if domain.contains("://") {
+            domain
+        } else {
+            format!("https://{domain}")
+        }
+    }
+
+    /**
+     * Get the URL of the API, or `None` if it isn't configured yet
+     */
+    #[must_use]
+    pub fn try_api_url() -> Option<String> {
+        let url = if unsafe { PRODUCTION } {
+            env::var("DEVCADE_API_DOMAIN")
+        } else {
+            env::var("DEVCADE_DEV_API_DOMAIN")
+        };
+        url.ok().filter(|url| !url.is_empty()).map(domain_url)
+    }
+
     /**
      * Get the URL of the API. This is where games are downloaded from.
      * If the value is not set in the environment, it will throw a fatal error and panic.
@@ -67,7 +181,7 @@ pub mod env {
         };
 
         match url {
-            Ok(url) => format!("https://{url}"),
+            Ok(url) => domain_url(url),
             Err(e) => {
                 if unsafe { PRODUCTION } {
                     log!(Level::Error, "Error getting DEVCADE_API_DOMAIN: {}", e);
@@ -79,6 +193,997 @@ pub mod env {
         }
     }
 
+    /**
+     * Get the prefix of association IDs that belong to demo wristbands. Taps from these IDs are
+     * resolved to a guest user locally instead of looking them up with gatekeeper.
+     * If the value is not set in the environment, there are no demo IDs.
+     */
+    #[must_use]
+    pub fn demo_id_prefix() -> Option<String> {
+        env::var("DEVCADE_DEMO_ID_PREFIX")
+            .ok()
+            .filter(|prefix| !prefix.is_empty())
+    }
+
+    /**
+     * Get the association IDs of demo wristbands that don't share a prefix, as a comma separated
+     * list. They're treated the same as IDs starting with `DEVCADE_DEMO_ID_PREFIX`.
+     * If the value is not set in the environment, only the prefix is used.
+     */
+    #[must_use]
+    pub fn demo_ids() -> Vec<String> {
+        env::var("DEVCADE_DEMO_IDS")
+            .map(|ids| {
+                ids.split(',')
+                    .map(|id| id.trim().to_string())
+                    .filter(|id| !id.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /**
+     * Get the cabinet's quiet hours, during which games can't be launched. This is a comma
+     * separated list of windows like `22:00-07:00` (every day) or `Fri 23:00-03:00` (starting on
+     * a specific day). If the value is not set in the environment, there are no quiet hours.
+     */
+    #[must_use]
+    pub fn quiet_hours() -> Option<String> {
+        env::var("DEVCADE_QUIET_HOURS")
+            .ok()
+            .filter(|hours| !hours.is_empty())
+    }
+
+    /**
+     * Get how long the cabinet has to sit idle before display protection is suggested, or `None`
+     * if it's disabled. If the value is not set in the environment, display protection is
+     * disabled.
+     */
+    #[must_use]
+    pub fn display_protection_after() -> Option<Duration> {
+        let mins: u64 = env::var("DEVCADE_DISPLAY_PROTECTION_MINS")
+            .ok()?
+            .parse()
+            .ok()
+            .filter(|mins| *mins > 0)?;
+        Some(Duration::from_secs(mins * 60))
+    }
+
+    /**
+     * Get the command that dims the display's backlight during display protection, e.g. a
+     * `ddcutil` or sysfs script. If the value is not set in the environment, the backlight is
+     * left alone.
+     */
+    #[must_use]
+    pub fn dim_backlight_command() -> Option<String> {
+        env::var("DEVCADE_DIM_BACKLIGHT_COMMAND")
+            .ok()
+            .filter(|command| !command.trim().is_empty())
+    }
+
+    /**
+     * Get the command that restores the display's backlight when display protection ends. If the
+     * value is not set in the environment, the backlight is left dimmed.
+     */
+    #[must_use]
+    pub fn restore_backlight_command() -> Option<String> {
+        env::var("DEVCADE_RESTORE_BACKLIGHT_COMMAND")
+            .ok()
+            .filter(|command| !command.trim().is_empty())
+    }
+
+    /**
+     * Get the devcade directory the backend used before `DEVCADE_PATH` was changed. If the value
+     * is not set in the environment, the directory recorded on the last run is used instead.
+     */
+    #[must_use]
+    pub fn previous_path() -> Option<String> {
+        env::var("DEVCADE_PREVIOUS_PATH")
+            .ok()
+            .filter(|path| !path.is_empty())
+    }
+
+    /**
+     * Get the windows during which installed games are updated in the background, in the same
+     * format as the quiet hours (e.g. `03:00-06:00`). If the value is not set in the environment,
+     * games are never updated in the background.
+     */
+    #[must_use]
+    pub fn auto_update_window() -> Option<String> {
+        env::var("DEVCADE_AUTO_UPDATE_WINDOW")
+            .ok()
+            .filter(|window| !window.is_empty())
+    }
+
+    /**
+     * Get what's done when the API says a game was added, changed or removed: `invalidate`
+     * forgets what's cached about it, `refresh` also fetches its details again, and `download`
+     * also updates it in the background if it's installed.
+     * If the value is not set in the environment, notifications aren't listened for and the
+     * catalog is only polled.
+     */
+    #[must_use]
+    pub fn catalog_events() -> Option<String> {
+        env::var("DEVCADE_CATALOG_EVENTS")
+            .ok()
+            .filter(|mode| !mode.is_empty() && mode != "off")
+    }
+
+    /**
+     * Get how many days of sessions are kept in the session log when it's compacted during the
+     * auto-update window. If the value is not set in the environment, it's never compacted there.
+     */
+    #[must_use]
+    pub fn session_retain_days() -> Option<u64> {
+        env::var("DEVCADE_SESSION_RETAIN_DAYS")
+            .ok()
+            .and_then(|days| days.parse().ok())
+    }
+
+    /**
+     * Get how often installed games are checked for updates during the auto-update window. If
+     * the value is not set in the environment, it defaults to 30 minutes.
+     */
+    #[must_use]
+    pub fn auto_update_interval() -> Duration {
+        let mins = env::var("DEVCADE_AUTO_UPDATE_INTERVAL_MINS")
+            .ok()
+            .and_then(|mins| mins.parse().ok())
+            .filter(|mins| *mins > 0)
+            .unwrap_or(30);
+        Duration::from_secs(mins * 60)
+    }
+
+    /**
+     * Get the cabinet's locale (e.g. `en_US`), which is passed to games as `DEVCADE_LOCALE`.
+     * If the value is not set in the environment, games are left to guess.
+     */
+    #[must_use]
+    pub fn locale() -> Option<String> {
+        env::var("DEVCADE_LOCALE")
+            .ok()
+            .filter(|locale| !locale.is_empty())
+    }
+
+    /**
+     * Get the cabinet's timezone (e.g. `America/New_York`), which is passed to games as
+     * `DEVCADE_TZ` and used for local time in the backend. If the value is not set in the
+     * environment, or isn't a timezone known to the system, the system timezone is used.
+     */
+    #[must_use]
+    pub fn timezone() -> Option<String> {
+        let tz = env::var("DEVCADE_TZ").ok().filter(|tz| !tz.is_empty())?;
+        if tz.contains("..") || !Path::new("/usr/share/zoneinfo").join(&tz).is_file() {
+            log!(
+                Level::Warn,
+                "DEVCADE_TZ '{}' is not a known timezone, using the system timezone",
+                tz
+            );
+            return None;
+        }
+        Some(tz)
+    }
+
+    /**
+     * Get the command used to capture screenshots of the running game, e.g. `import -window root`
+     * on X11. The path to save the screenshot to is appended as the last argument.
+     * If the value is not set in the environment, screenshots can't be captured.
+     */
+    #[must_use]
+    pub fn screenshot_command() -> Option<String> {
+        env::var("DEVCADE_SCREENSHOT_COMMAND")
+            .ok()
+            .filter(|command| !command.trim().is_empty())
+    }
+
+    /**
+     * Get the command that prints the display's current mode as `<width>x<height>@<refresh rate>`,
+     * e.g. a script wrapping `xrandr`.
+     * If the value is not set in the environment, the mode is read from the kernel's DRM devices,
+     * which doesn't include the refresh rate.
+     */
+    #[must_use]
+    pub fn display_probe_command() -> Option<String> {
+        env::var("DEVCADE_DISPLAY_PROBE")
+            .ok()
+            .filter(|command| !command.trim().is_empty())
+    }
+
+    /**
+     * Get how often the running game's CPU and memory are sampled.
+     * If the value is not set in the environment, it will default to 5 seconds.
+     */
+    #[must_use]
+    pub fn resource_sample_interval() -> Duration {
+        env::var("DEVCADE_RESOURCE_SAMPLE_SECS")
+            .ok()
+            .and_then(|secs| secs.parse().ok())
+            .filter(|secs| *secs > 0)
+            .map_or(Duration::from_secs(5), Duration::from_secs)
+    }
+
+    /**
+     * Get the limits on the running game's resources that send a `ResourcePressure` event, as a
+     * comma separated list like `rss_mb=1500,cpu_percent=350,open_fds=900,threads=500`.
+     * If the value is not set in the environment, no events are sent.
+     */
+    #[must_use]
+    pub fn resource_limits() -> Option<String> {
+        env::var("DEVCADE_RESOURCE_LIMITS")
+            .ok()
+            .filter(|limits| !limits.trim().is_empty())
+    }
+
+    /**
+     * Get how many recent launch events are kept for clients catching up on what they missed.
+     * If the value is not set in the environment, it will default to 64.
+     */
+    #[must_use]
+    pub fn event_history() -> usize {
+        env::var("DEVCADE_EVENT_HISTORY")
+            .ok()
+            .and_then(|kept| kept.parse().ok())
+            .filter(|kept| *kept > 0)
+            .unwrap_or(64)
+    }
+
+    /**
+     * Get the path to the JSON file of event hooks, commands run with a launch event on stdin when
+     * one of its type happens.
+     * If the value is not set in the environment, no hooks are run.
+     */
+    #[must_use]
+    pub fn event_hooks() -> Option<String> {
+        env::var("DEVCADE_EVENT_HOOKS")
+            .ok()
+            .filter(|path| !path.is_empty())
+    }
+
+    /**
+     * Get the sample rate of the cabinet's audio output in Hz.
+     * If the value is not set in the environment, games aren't told the sample rate.
+     */
+    #[must_use]
+    pub fn audio_sample_rate() -> Option<u32> {
+        env::var("DEVCADE_AUDIO_SAMPLE_RATE").ok()?.parse().ok()
+    }
+
+    /**
+     * Get the operator-measured latency of the cabinet's audio output in milliseconds.
+     * If the value is not set in the environment, games aren't told the latency.
+     */
+    #[must_use]
+    pub fn audio_latency_ms() -> Option<i32> {
+        env::var("DEVCADE_AUDIO_LATENCY_MS").ok()?.parse().ok()
+    }
+
+    /**
+     * Get the identifier of the cabinet's controller layout, so games can pick the right mapping.
+     * If the value is not set in the environment, there is no identifier.
+     */
+    #[must_use]
+    pub fn controller_mapping() -> Option<String> {
+        env::var("DEVCADE_CONTROLLER_MAPPING")
+            .ok()
+            .filter(|mapping| !mapping.is_empty())
+    }
+
+    /**
+     * Get the path to a PEM bundle of extra CA certificates to trust for API requests, for when the
+     * API's certificate chain isn't in the system store.
+     * If the value is not set in the environment, only the system's certificates are trusted.
+     */
+    #[must_use]
+    pub fn ca_bundle() -> Option<String> {
+        env::var("DEVCADE_CA_BUNDLE")
+            .ok()
+            .filter(|path| !path.is_empty())
+    }
+
+    /**
+     * Get the network access games get by default: `none`, `localhost` or `full`.
+     * If the value is not set in the environment, games get full network access.
+     */
+    #[must_use]
+    pub fn game_network() -> Option<String> {
+        env::var("DEVCADE_GAME_NETWORK")
+            .ok()
+            .filter(|policy| !policy.is_empty())
+    }
+
+    /**
+     * Get the publisher public keys game signatures are checked against, as hex encoded ed25519
+     * keys. Several keys can be trusted at once so keys can be rotated.
+     */
+    #[must_use]
+    pub fn signing_keys() -> Vec<String> {
+        env::var("DEVCADE_SIGNING_KEYS")
+            .map(|keys| {
+                keys.split(',')
+                    .map(|key| key.trim().to_string())
+                    .filter(|key| !key.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /**
+     * Get what to do with games that aren't signed: `warn` or `enforce`.
+     * If the value is not set in the environment, unsigned games are installed with a warning.
+     */
+    #[must_use]
+    pub fn signature_policy() -> Option<String> {
+        env::var("DEVCADE_SIGNATURE_POLICY")
+            .ok()
+            .filter(|policy| !policy.is_empty())
+    }
+
+    /**
+     * Get the accessibility flags (like `no_flashing_lights`) every game must declare to be listed
+     * or launched. If the value is not set in the environment, no flags are required.
+     */
+    #[must_use]
+    pub fn required_accessibility() -> Vec<String> {
+        env::var("DEVCADE_REQUIRED_ACCESSIBILITY")
+            .map(|flags| {
+                flags
+                    .split(',')
+                    .map(|flag| flag.trim().to_string())
+                    .filter(|flag| !flag.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /**
+     * Get whether games that don't declare any accessibility flags pass the required flags:
+     * `include` or `exclude`.
+     * If the value is not set in the environment, they are excluded.
+     */
+    #[must_use]
+    pub fn accessibility_undeclared() -> Option<String> {
+        env::var("DEVCADE_ACCESSIBILITY_UNDECLARED")
+            .ok()
+            .filter(|policy| !policy.is_empty())
+    }
+
+    /**
+     * Get what happens to installed games the API removed or retired: `keep`, `hide` (from
+     * listings, but still launchable by ID) or `remove` (hide, then remove after
+     * `DEVCADE_RETIREMENT_DAYS`).
+     * If the value is not set in the environment, they are kept.
+     */
+    #[must_use]
+    pub fn retirement_policy() -> Option<String> {
+        env::var("DEVCADE_RETIREMENT_POLICY")
+            .ok()
+            .filter(|policy| !policy.is_empty())
+    }
+
+    /**
+     * Get what auto-update does with a game whose hash changed without a new upload date:
+     * `proceed` (update it), `skip` (leave it) or `confirm` (leave it until an operator confirms
+     * it with `ConfirmSuspiciousUpdate`).
+     * If the value is not set in the environment, it will default to `confirm`.
+     */
+    #[must_use]
+    pub fn suspicious_update_policy() -> Option<String> {
+        env::var("DEVCADE_SUSPICIOUS_UPDATE_POLICY")
+            .ok()
+            .filter(|policy| !policy.is_empty())
+    }
+
+    /**
+     * Get how much of a game is checked against the hashes in its manifest before it's launched:
+     * `off`, `executable` (just the file that's run) or `full` (every installed file).
+     * If the value is not set in the environment, it will default to `off`.
+     */
+    #[must_use]
+    pub fn verify_on_launch() -> Option<String> {
+        env::var("DEVCADE_VERIFY_ON_LAUNCH")
+            .ok()
+            .filter(|mode| !mode.is_empty())
+    }
+
+    /**
+     * Get the directories games write to inside their install, relative to its `publish`
+     * directory, as a comma separated list like `data,logs`. Files in them aren't checked by
+     * `DEVCADE_VERIFY_ON_LAUNCH=full`.
+     * If the value is not set in the environment, every installed file is checked.
+     */
+    #[must_use]
+    pub fn verify_skip_dirs() -> Vec<String> {
+        env::var("DEVCADE_VERIFY_SKIP_DIRS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|dir| dir.trim().trim_matches('/'))
+            .filter(|dir| !dir.is_empty())
+            .map(String::from)
+            .collect()
+    }
+
+    /**
+     * Get how many days retired games stay installed with the `remove` retirement policy.
+     * If the value is not set in the environment, it will default to 30 days.
+     */
+    #[must_use]
+    pub fn retirement_days() -> u64 {
+        env::var("DEVCADE_RETIREMENT_DAYS")
+            .ok()
+            .and_then(|days| days.parse().ok())
+            .unwrap_or(30)
+    }
+
+    /**
+     * Get the per-game overrides of `DEVCADE_GAME_NETWORK`, as a comma separated list like
+     * `<game id>=full,<game id>=none`.
+     */
+    #[must_use]
+    pub fn game_network_overrides() -> Option<String> {
+        env::var("DEVCADE_GAME_NETWORK_OVERRIDES")
+            .ok()
+            .filter(|overrides| !overrides.is_empty())
+    }
+
+    /**
+     * Get how long the frontend can be gone before the fallback menu is started, or `None` if the
+     * fallback menu is disabled. The fallback menu is enabled by setting `DEVCADE_FALLBACK_MENU`
+     * to `true`, and `DEVCADE_FALLBACK_AFTER_SECS` defaults to 60 seconds.
+     */
+    #[must_use]
+    pub fn fallback_after() -> Option<Duration> {
+        if env::var("DEVCADE_FALLBACK_MENU").ok()? != "true" {
+            return None;
+        }
+        let secs = env::var("DEVCADE_FALLBACK_AFTER_SECS")
+            .ok()
+            .and_then(|secs| secs.parse().ok())
+            .unwrap_or(60);
+        Some(Duration::from_secs(secs))
+    }
+
+    /**
+     * Get the terminal the fallback menu runs on (e.g. `/dev/tty1`).
+     * If the value is not set in the environment, the menu uses the backend's own terminal.
+     */
+    #[must_use]
+    pub fn fallback_tty() -> Option<String> {
+        env::var("DEVCADE_FALLBACK_TTY")
+            .ok()
+            .filter(|tty| !tty.is_empty())
+    }
+
+    /**
+     * Get the roles of onboard clients, as a comma separated list like
+     * `uid:1000=frontend,gid:27=operator,uid:1001=readonly`.
+     * If the value is not set in the environment, every client is an operator.
+     */
+    #[must_use]
+    pub fn client_roles() -> Option<String> {
+        env::var("DEVCADE_CLIENT_ROLES")
+            .ok()
+            .filter(|roles| !roles.is_empty())
+    }
+
+    /**
+     * Get how many requests of each type a game can keep sending per second over the persistence
+     * socket. If the value is not set in the environment, it will default to 100.
+     */
+    #[must_use]
+    pub fn ipc_rate() -> f64 {
+        env::var("DEVCADE_IPC_RATE")
+            .ok()
+            .and_then(|rate| rate.parse().ok())
+            .filter(|rate: &f64| *rate > 0.0)
+            .unwrap_or(100.0)
+    }
+
+    /**
+     * Get the IDs of the games allowed to write cabinet settings (like controller calibration)
+     * over the persistence socket. Every other game can only read them.
+     */
+    #[must_use]
+    pub fn cabinet_settings_writers() -> Vec<String> {
+        env::var("DEVCADE_CABINET_SETTINGS_WRITERS")
+            .map(|games| {
+                games
+                    .split(',')
+                    .map(|game| game.trim().to_string())
+                    .filter(|game| !game.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /**
+     * Get how long an event label lasts before it's cleared, if that's before the next local
+     * midnight. If the value is not set in the environment, labels last until midnight.
+     */
+    #[must_use]
+    pub fn event_label_duration() -> Option<Duration> {
+        env::var("DEVCADE_EVENT_LABEL_HOURS")
+            .ok()
+            .and_then(|hours| hours.parse::<u64>().ok())
+            .filter(|hours| *hours > 0)
+            .map(|hours| Duration::from_secs(hours * 60 * 60))
+    }
+
+    /**
+     * Get how saves are stored: `json` (one file per group, rewritten on every flush) or
+     * `segmented` (changes appended to segments that are merged in the background). Saves are
+     * moved over as they're flushed, or all at once with `devcade-ctl saves migrate`.
+     * If the value is not set in the environment, they are stored as `json`.
+     */
+    #[must_use]
+    pub fn save_storage() -> Option<String> {
+        env::var("DEVCADE_SAVE_STORAGE")
+            .ok()
+            .filter(|storage| !storage.is_empty())
+    }
+
+    /**
+     * Get the share of bytes two installs need in common to be reported as duplicates, from 0 to
+     * 1. If the value is not set in the environment, it will default to 0.9.
+     */
+    #[must_use]
+    pub fn duplicate_similarity() -> f64 {
+        env::var("DEVCADE_DUPLICATE_SIMILARITY")
+            .ok()
+            .and_then(|similarity| similarity.parse().ok())
+            .filter(|similarity: &f64| *similarity > 0.0 && *similarity <= 1.0)
+            .unwrap_or(0.9)
+    }
+
+    /**
+     * Get how many requests of each type a game can send in a burst over the persistence socket,
+     * before being held to `DEVCADE_IPC_RATE`. If the value is not set in the environment, it will
+     * default to 1000.
+     */
+    #[must_use]
+    pub fn ipc_burst() -> f64 {
+        env::var("DEVCADE_IPC_BURST")
+            .ok()
+            .and_then(|burst| burst.parse().ok())
+            .filter(|burst: &f64| *burst >= 1.0)
+            .unwrap_or(1000.0)
+    }
+
+    /**
+     * Get how many requests a game can have refused for going over its rate within 10 seconds
+     * before it's disconnected from the persistence socket. If the value is not set in the
+     * environment, it will default to 10000.
+     */
+    #[must_use]
+    pub fn ipc_disconnect_after() -> u64 {
+        env::var("DEVCADE_IPC_DISCONNECT_AFTER")
+            .ok()
+            .and_then(|count| count.parse().ok())
+            .filter(|count| *count > 0)
+            .unwrap_or(10_000)
+    }
+
+    /**
+     * Get whether the frontend may report how much each control of the running game is used. This
+     * is off unless `DEVCADE_INPUT_TELEMETRY` is set to `true`.
+     */
+    #[must_use]
+    pub fn input_telemetry() -> bool {
+        env::var("DEVCADE_INPUT_TELEMETRY").is_ok_and(|enabled| enabled == "true")
+    }
+
+    /**
+     * Get whether clients may still send newline-delimited JSON instead of length-prefixed frames.
+     * Set `DEVCADE_LEGACY_PROTOCOL` to `false` once every frontend and game has been upgraded.
+     * If the value is not set in the environment, it will default to true.
+     */
+    #[must_use]
+    pub fn legacy_protocol() -> bool {
+        env::var("DEVCADE_LEGACY_PROTOCOL").map_or(true, |allowed| allowed != "false")
+    }
+
+    /**
+     * Get whether clients that don't negotiate a protocol version get games in the legacy shape,
+     * with camelCase fields and links to their art, which frontends from before the schema changed
+     * read. Set `DEVCADE_LEGACY_GAME_SHAPE` to `true` on cabinets still running one.
+     * If the value is not set in the environment, it will default to false.
+     */
+    #[must_use]
+    pub fn legacy_game_shape() -> bool {
+        env::var("DEVCADE_LEGACY_GAME_SHAPE").is_ok_and(|enabled| enabled == "true")
+    }
+
+    /**
+     * Get whether installed games share identical files through hard links in `.objects`, so a
+     * library with many copies of the same engine takes that space once. Files in
+     * `DEVCADE_VERIFY_SKIP_DIRS`, and games with a setup step, are never shared, since they're
+     * written to.
+     * If the value is not set in the environment, it will default to false.
+     */
+    #[must_use]
+    pub fn object_store() -> bool {
+        env::var("DEVCADE_OBJECT_STORE").is_ok_and(|enabled| enabled == "true")
+    }
+
+    /**
+     * Get how long individual NFC taps are kept in the audit trail before they are reduced to
+     * hourly counts. If the value is not set in the environment, it will default to 7 days.
+     */
+    #[must_use]
+    pub fn tap_audit_retention() -> Duration {
+        let days = env::var("DEVCADE_TAP_AUDIT_DAYS")
+            .ok()
+            .and_then(|days| days.parse().ok())
+            .unwrap_or(7);
+        Duration::from_secs(days * 24 * 60 * 60)
+    }
+
+    /**
+     * Get how long the salt used to hash association IDs in the tap audit trail is used before it
+     * is replaced. If the value is not set in the environment, it will default to 30 days.
+     */
+    #[must_use]
+    pub fn tap_audit_salt_rotation() -> Duration {
+        let days = env::var("DEVCADE_TAP_AUDIT_SALT_DAYS")
+            .ok()
+            .and_then(|days| days.parse().ok())
+            .unwrap_or(30);
+        Duration::from_secs(days * 24 * 60 * 60)
+    }
+
+    /**
+     * Get the most API requests that can be in flight at once. Requests over the limit wait, with
+     * interactive requests going first.
+     * If the value is not set in the environment, it will default to 8.
+     */
+    #[must_use]
+    pub fn max_requests() -> usize {
+        env::var("DEVCADE_MAX_REQUESTS")
+            .ok()
+            .and_then(|max| max.parse().ok())
+            .filter(|max| *max > 0)
+            .unwrap_or(8)
+    }
+
+    /**
+     * Get the most game archives that can be downloaded at once. Downloads over the limit wait
+     * their turn.
+     * If the value is not set in the environment, it will default to 4, or fewer if the open file
+     * limit is below 1024.
+     */
+    #[must_use]
+    pub fn max_game_downloads() -> usize {
+        env::var("DEVCADE_MAX_GAME_DOWNLOADS")
+            .ok()
+            .and_then(|max| max.parse().ok())
+            .filter(|max| *max > 0)
+            .unwrap_or_else(|| fd_share(256, 4))
+    }
+
+    /**
+     * Get the most icons and banners that can be downloaded at once. Downloads over the limit wait
+     * their turn.
+     * If the value is not set in the environment, it will default to 8 (2 with the low resource
+     * profile), or fewer if the open file limit is below 1024.
+     */
+    #[must_use]
+    pub fn max_asset_downloads() -> usize {
+        let max = match crate::resources::profile() {
+            ResourceProfile::Full => 8,
+            ResourceProfile::Low => 2,
+        };
+        env::var("DEVCADE_MAX_ASSET_DOWNLOADS")
+            .ok()
+            .and_then(|max| max.parse().ok())
+            .filter(|max| *max > 0)
+            .unwrap_or_else(|| fd_share(128, max))
+    }
+
+    /**
+     * Get the resource profile: `full` or `low`.
+     * If the value is not set in the environment, it's `low` on cabinets with less than 2 GB of
+     * memory.
+     */
+    #[must_use]
+    pub fn resource_profile() -> Option<String> {
+        env::var("DEVCADE_RESOURCE_PROFILE")
+            .ok()
+            .filter(|profile| !profile.is_empty())
+    }
+
+    /**
+     * Get the faults to inject, as comma separated `<site>=<probability>` pairs (e.g.
+     * `network=0.2,fs:install=1`).
+     * If the value is not set in the environment, no faults are injected.
+     */
+    #[must_use]
+    pub fn faults() -> Option<String> {
+        env::var("DEVCADE_FAULTS")
+            .ok()
+            .filter(|faults| !faults.is_empty())
+    }
+
+    /**
+     * Get the seed faults are drawn from, so a run can be repeated. If the value is not set in the
+     * environment, it will default to 1.
+     */
+    #[must_use]
+    pub fn fault_seed() -> u64 {
+        env::var("DEVCADE_FAULT_SEED")
+            .ok()
+            .and_then(|seed| seed.parse().ok())
+            .unwrap_or(1)
+    }
+
+    /**
+     * Allow one of something per `per` file descriptors the backend may open, up to `max`
+     */
+    fn fd_share(per: u64, max: usize) -> usize {
+        usize::try_from(crate::fds::limit() / per)
+            .unwrap_or(max)
+            .clamp(1, max)
+    }
+
+    /**
+     * Get how long to wait for a connection to the API to be established.
+     * If the value is not set in the environment, it will default to 5 seconds.
+     */
+    #[must_use]
+    pub fn http_connect_timeout() -> Duration {
+        let secs = env::var("DEVCADE_HTTP_CONNECT_TIMEOUT_SECS")
+            .ok()
+            .and_then(|secs| secs.parse().ok())
+            .unwrap_or(5);
+        Duration::from_secs(secs)
+    }
+
+    /**
+     * Get how long an API request can take in total, from sending it to reading the whole
+     * response. Game downloads use `download_idle_timeout` instead.
+     * If the value is not set in the environment, it will default to 30 seconds.
+     */
+    #[must_use]
+    pub fn http_timeout() -> Duration {
+        let secs = env::var("DEVCADE_HTTP_TIMEOUT_SECS")
+            .ok()
+            .and_then(|secs| secs.parse().ok())
+            .unwrap_or(30);
+        Duration::from_secs(secs)
+    }
+
+    /**
+     * Get the most bandwidth game downloads may use together, in bytes per second. If the value is
+     * not set in the environment, or is 0, downloads are not limited.
+     */
+    #[must_use]
+    pub fn max_download_bps() -> u64 {
+        env::var("DEVCADE_MAX_DOWNLOAD_BPS")
+            .ok()
+            .and_then(|bps| bps.parse().ok())
+            .unwrap_or(0)
+    }
+
+    /**
+     * Get how long a game download can go without receiving any data before it is abandoned.
+     * If the value is not set in the environment, it will default to 30 seconds.
+     */
+    #[must_use]
+    pub fn download_idle_timeout() -> Duration {
+        let secs = env::var("DEVCADE_DOWNLOAD_IDLE_TIMEOUT_SECS")
+            .ok()
+            .and_then(|secs| secs.parse().ok())
+            .unwrap_or(30);
+        Duration::from_secs(secs)
+    }
+
+    /**
+     * Get how many times an API request is attempted before giving up, when it fails with a
+     * connection error, a timeout or a server error.
+     * If the value is not set in the environment, it will default to 3.
+     */
+    #[must_use]
+    pub fn request_attempts() -> u32 {
+        env::var("DEVCADE_REQUEST_ATTEMPTS")
+            .ok()
+            .and_then(|attempts| attempts.parse().ok())
+            .filter(|attempts| *attempts > 0)
+            .unwrap_or(3)
+    }
+
+    /**
+     * Get how long to wait before retrying a failed API request. The wait doubles with each retry,
+     * plus some jitter. If the value is not set in the environment, it will default to 500ms.
+     */
+    #[must_use]
+    pub fn request_backoff() -> Duration {
+        let millis = env::var("DEVCADE_REQUEST_BACKOFF_MS")
+            .ok()
+            .and_then(|millis| millis.parse().ok())
+            .unwrap_or(500);
+        Duration::from_millis(millis)
+    }
+
+    /**
+     * Get how old a cached API response can be before it's checked against a fresh copy, in case a
+     * proxy is serving stale data. If the value is not set in the environment, it will default to
+     * 60 minutes.
+     */
+    #[must_use]
+    pub fn cache_max_age() -> Duration {
+        let mins = env::var("DEVCADE_CACHE_MAX_AGE_MINS")
+            .ok()
+            .and_then(|mins| mins.parse().ok())
+            .unwrap_or(60);
+        Duration::from_secs(mins * 60)
+    }
+
+    /**
+     * Get how often a response can be re-requested past any caches, so legitimate caching isn't
+     * defeated. If the value is not set in the environment, it will default to 60 seconds.
+     */
+    #[must_use]
+    pub fn cache_bust_interval() -> Duration {
+        let secs = env::var("DEVCADE_CACHE_BUST_INTERVAL_SECS")
+            .ok()
+            .and_then(|secs| secs.parse().ok())
+            .unwrap_or(60);
+        Duration::from_secs(secs)
+    }
+
+    /**
+     * Get the hosts the API is allowed to redirect requests to, as a comma separated list like
+     * `cdn.example.com,*.example.net`. A leading `*.` matches any subdomain.
+     * If the value is not set in the environment, redirects to any host are followed. Redirects to
+     * the API's own host are always followed.
+     */
+    #[must_use]
+    pub fn redirect_hosts() -> Option<Vec<String>> {
+        env::var("DEVCADE_REDIRECT_HOSTS").ok().map(|hosts| {
+            hosts
+                .split(',')
+                .map(str::trim)
+                .filter(|host| !host.is_empty())
+                .map(str::to_lowercase)
+                .collect()
+        })
+    }
+
+    /**
+     * Get the patterns of archive entries that are skipped when installing a game, as a comma
+     * separated list like `*.pdb,*.dSYM`. Patterns ending in a `**` path component match whole
+     * directories.
+     * If the value is not set in the environment, debug symbols, git directories and Windows
+     * builds are pruned. If it is set but empty, nothing is pruned.
+     */
+    #[must_use]
+    pub fn prune_patterns() -> Vec<String> {
+        match env::var("DEVCADE_PRUNE_PATTERNS") {
+            Ok(patterns) => patterns
+                .split(',')
+                .map(str::trim)
+                .filter(|pattern| !pattern.is_empty())
+                .map(String::from)
+                .collect(),
+            Err(_) => ["*.pdb", ".git/**", "*.dSYM/**", "win-x64/**"]
+                .into_iter()
+                .map(String::from)
+                .collect(),
+        }
+    }
+
+    /**
+     * Get the longest a game can stay paused before it is resumed automatically.
+     * If the value is not set in the environment, it will default to 10 minutes.
+     */
+    #[must_use]
+    pub fn max_pause() -> Duration {
+        let secs = env::var("DEVCADE_MAX_PAUSE_SECS")
+            .ok()
+            .and_then(|secs| secs.parse().ok())
+            .unwrap_or(600);
+        Duration::from_secs(secs)
+    }
+
+    /**
+     * Get how much disk space must be left free after installing a game, in bytes. Installed games
+     * are evicted to keep it free. If the value is not set in the environment, it will default to
+     * 500 MB.
+     */
+    #[must_use]
+    pub fn min_free_space() -> u64 {
+        let mb: u64 = env::var("DEVCADE_MIN_FREE_MB")
+            .ok()
+            .and_then(|mb| mb.parse().ok())
+            .unwrap_or(500);
+        mb.saturating_mul(1024 * 1024)
+    }
+
+    /**
+     * Get how many startups within the safe mode window may fail before the backend boots into
+     * safe mode. If the value is not set in the environment, it will default to 3.
+     */
+    #[must_use]
+    pub fn safe_mode_boots() -> usize {
+        env::var("DEVCADE_SAFE_MODE_BOOTS")
+            .ok()
+            .and_then(|boots| boots.parse().ok())
+            .unwrap_or(3)
+    }
+
+    /**
+     * Get the window in which failed startups are counted towards safe mode.
+     * If the value is not set in the environment, it will default to 10 minutes.
+     */
+    #[must_use]
+    pub fn safe_mode_window() -> Duration {
+        let mins = env::var("DEVCADE_SAFE_MODE_WINDOW_MINS")
+            .ok()
+            .and_then(|mins| mins.parse().ok())
+            .unwrap_or(10);
+        Duration::from_secs(mins * 60)
+    }
+
+    /**
+     * Get how long command capture lasts when it's started without a duration, so it isn't left
+     * on by accident. If the value is not set in the environment, it will default to 30 minutes.
+     */
+    #[must_use]
+    pub fn capture_duration() -> Duration {
+        let mins = env::var("DEVCADE_CAPTURE_MINS")
+            .ok()
+            .and_then(|mins| mins.parse().ok())
+            .unwrap_or(30);
+        Duration::from_secs(mins * 60)
+    }
+
+    /**
+     * Get the largest payload in bytes written to a command capture, larger ones are replaced with
+     * their size. If the value is not set in the environment, it will default to 4096.
+     */
+    #[must_use]
+    pub fn capture_payload_limit() -> usize {
+        env::var("DEVCADE_CAPTURE_PAYLOAD_BYTES")
+            .ok()
+            .and_then(|bytes| bytes.parse().ok())
+            .unwrap_or(4096)
+    }
+
+    /**
+     * Get how long a log level set at runtime lasts when no expiry is given, so cabinets don't
+     * stay in trace mode forever. If the value is not set in the environment, it will default to
+     * 1 hour.
+     */
+    #[must_use]
+    pub fn log_override_expiry() -> Duration {
+        let mins = env::var("DEVCADE_LOG_OVERRIDE_MINS")
+            .ok()
+            .and_then(|mins| mins.parse().ok())
+            .unwrap_or(60);
+        Duration::from_secs(mins * 60)
+    }
+
+    /**
+     * Get the address (`host:port`) of the cabinet next to this one, which the backend sends its
+     * status to every few seconds. If the value is not set in the environment, the status isn't
+     * sent.
+     */
+    #[must_use]
+    pub fn peer_addr() -> Option<String> {
+        env::var("DEVCADE_PEER_ADDR")
+            .ok()
+            .filter(|addr| !addr.trim().is_empty())
+    }
+
+    /**
+     * Get the address (`host:port`) the backend accepts the status of the cabinet next to it on.
+     * If the value is not set in the environment, the status isn't accepted.
+     */
+    #[must_use]
+    pub fn peer_listen() -> Option<String> {
+        env::var("DEVCADE_PEER_LISTEN")
+            .ok()
+            .filter(|addr| !addr.trim().is_empty())
+    }
+
     /**
      * Sets whether the API will interact with the production or development API.
      */
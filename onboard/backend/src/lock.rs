@@ -0,0 +1,240 @@
+use crate::clock;
+use crate::layout;
+use anyhow::{anyhow, Error};
+use devcade_onboard_types::InstanceLockInfo;
+use lazy_static::lazy_static;
+use log::{log, Level};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/**
+ * How long to wait for the old instance to release the lock when taking over
+ */
+const TAKEOVER_TIMEOUT: Duration = Duration::from_secs(10);
+
+lazy_static! {
+    // The lock this process holds, for `GetCabinetInfo`
+    static ref HELD: Mutex<Option<InstanceLockInfo>> = Mutex::new(None);
+}
+
+/**
+ * An advisory lock that makes sure only one backend runs against a devcade directory at a time.
+ * The lock is held for as long as this struct is alive, and is released by the kernel when the
+ * file is closed, including when the process exits or panics.
+ */
+pub struct InstanceLock {
+    _file: File,
+}
+
+impl InstanceLock {
+    /**
+     * Get the path to the lock file
+     */
+    #[must_use]
+    pub fn lock_path() -> PathBuf {
+        layout::state_dir().join("backend.lock")
+    }
+
+    /**
+     * Take the instance lock. If another backend already holds it, this fails with an error naming
+     * its PID, unless `takeover` is set, in which case the other backend is asked to shut down
+     * (SIGTERM) and the lock is taken once it has been released.
+     *
+     * # Errors
+     * This function will return an error if the lock file cannot be opened, if the lock is held by
+     * another backend and `takeover` is not set, or if the other backend doesn't release the lock
+     * in time.
+     */
+    pub fn acquire(takeover: bool) -> Result<Self, Error> {
+        let path = Self::lock_path();
+        // This unwrap is safe because the lock path always has a parent
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+
+        if !try_lock(&file)? {
+            let pid = holder(&mut file);
+            let pid_str = pid.map_or(String::from("unknown"), |pid| pid.to_string());
+            if !takeover {
+                return Err(anyhow!(
+                    "Another backend (PID {}) is already running, it holds {}",
+                    pid_str,
+                    path.to_str().unwrap_or("")
+                ));
+            }
+
+            log!(
+                Level::Warn,
+                "Taking over from the running backend (PID {})",
+                pid_str
+            );
+            if let Some(pid) = pid {
+                // SAFETY: kill has no memory safety requirements
+                unsafe {
+                    libc::kill(pid, libc::SIGTERM);
+                }
+            }
+            let start = Instant::now();
+            while !try_lock(&file)? {
+                if start.elapsed() > TAKEOVER_TIMEOUT {
+                    return Err(anyhow!(
+                        "Backend (PID {}) didn't release {} in time",
+                        pid_str,
+                        path.to_str().unwrap_or("")
+                    ));
+                }
+                std::thread::sleep(Duration::from_millis(100));
+            }
+        }
+
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        write!(file, "{}", std::process::id())?;
+        file.flush()?;
+
+        log!(
+            Level::Info,
+            "Acquired instance lock {}",
+            path.to_str().unwrap_or("")
+        );
+        *HELD.lock().unwrap() = Some(InstanceLockInfo {
+            path: path.to_string_lossy().into_owned(),
+            pid: std::process::id(),
+            acquired: clock::unix_now(),
+        });
+        Ok(Self { _file: file })
+    }
+
+    /**
+     * Get the lock this backend holds: its path, this backend's PID and when it was taken, or
+     * `None` if it doesn't hold it
+     */
+    #[must_use]
+    pub fn status() -> Option<InstanceLockInfo> {
+        HELD.lock().unwrap().clone()
+    }
+}
+
+impl Drop for InstanceLock {
+    fn drop(&mut self) {
+        HELD.lock().unwrap().take();
+    }
+}
+
+/**
+ * Try to take an exclusive lock on the file without blocking. Returns whether the lock was taken.
+ */
+fn try_lock(file: &File) -> Result<bool, Error> {
+    // SAFETY: the file descriptor is valid for as long as `file` is alive
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+        return Ok(true);
+    }
+    let err = std::io::Error::last_os_error();
+    if err.kind() == std::io::ErrorKind::WouldBlock {
+        Ok(false)
+    } else {
+        Err(err.into())
+    }
+}
+
+/**
+ * Read the PID of the backend holding the lock from the lock file
+ */
+fn holder(file: &mut File) -> Option<i32> {
+    let mut pid = String::new();
+    file.seek(SeekFrom::Start(0)).ok()?;
+    file.read_to_string(&mut pid).ok()?;
+    pid.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::{Child, Command};
+
+    /**
+     * Not a test by itself: when run by `spawn_holder`, takes the lock and then does what
+     * `DEVCADE_LOCK_HOLDER` says
+     */
+    #[test]
+    fn lock_holder() {
+        let Ok(mode) = std::env::var("DEVCADE_LOCK_HOLDER") else {
+            return;
+        };
+        let lock = InstanceLock::acquire(false).unwrap();
+        match mode.as_str() {
+            "exit" => {
+                std::mem::forget(lock);
+                std::process::exit(0);
+            }
+            "panic" => panic!("Holder panicked with the lock held"),
+            _ => std::thread::sleep(Duration::from_secs(30)),
+        }
+    }
+
+    /**
+     * Run `lock_holder` in another process, and wait until it holds the lock
+     */
+    fn spawn_holder(mode: &str) -> Child {
+        let mut child = Command::new(std::env::current_exe().unwrap())
+            .args(["lock::tests::lock_holder", "--exact", "--nocapture"])
+            .env("DEVCADE_LOCK_HOLDER", mode)
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .spawn()
+            .unwrap();
+        let pid = child.id().to_string();
+        for _ in 0..200 {
+            if std::fs::read_to_string(InstanceLock::lock_path()).is_ok_and(|held| held == pid) {
+                return child;
+            }
+            std::thread::sleep(Duration::from_millis(25));
+        }
+        let _ = child.kill();
+        let _ = child.wait();
+        panic!("Lock holder didn't take the lock");
+    }
+
+    #[test]
+    fn second_instance_is_refused_and_can_take_over() {
+        let (_guard, root) = crate::testing::root("lock-takeover");
+        let mut holder = spawn_holder("hold");
+
+        let refused = InstanceLock::acquire(false).err().unwrap().to_string();
+        assert!(
+            refused.contains(&format!("PID {}", holder.id())),
+            "{refused}"
+        );
+        assert!(InstanceLock::status().is_none());
+
+        let lock = InstanceLock::acquire(true).unwrap();
+        assert!(!holder.wait().unwrap().success());
+        let status = InstanceLock::status().unwrap();
+        assert_eq!(status.pid, std::process::id());
+        assert_eq!(PathBuf::from(status.path), InstanceLock::lock_path());
+
+        drop(lock);
+        assert!(InstanceLock::status().is_none());
+        drop(InstanceLock::acquire(false).unwrap());
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn lock_is_released_on_exit_and_panic() {
+        let (_guard, root) = crate::testing::root("lock-release");
+        for mode in ["exit", "panic"] {
+            let mut holder = spawn_holder(mode);
+            holder.wait().unwrap();
+            drop(InstanceLock::acquire(false).unwrap());
+        }
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
@@ -0,0 +1,243 @@
+use crate::clock;
+use crate::env::log_override_expiry;
+use crate::layout;
+use anyhow::{anyhow, Error};
+use devcade_onboard_types::LogOverride;
+use lazy_static::lazy_static;
+use log::{log, Level, LevelFilter, Log, Metadata, Record};
+use std::path::PathBuf;
+use std::sync::RwLock;
+use std::time::Duration;
+
+lazy_static! {
+    // Log levels set at runtime, which take precedence over RUST_LOG until they expire
+    static ref OVERRIDES: RwLock<Vec<LogOverride>> = RwLock::new(Vec::new());
+
+    // The most verbose level RUST_LOG allows, for when overrides are removed
+    static ref BASE_LEVEL: RwLock<LevelFilter> = RwLock::new(LevelFilter::Error);
+}
+
+/**
+ * A logger that filters with `RUST_LOG` like `env_logger`, except that the filter can be
+ * overridden per module at runtime. Records that pass are written by an `env_logger` that lets
+ * everything through.
+ */
+struct Logger {
+    base: env_logger::filter::Filter,
+    writer: env_logger::Logger,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        match override_for(metadata.target()) {
+            Some(level) => metadata.level() <= level,
+            None => self.base.enabled(metadata),
+        }
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            self.writer.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.writer.flush();
+    }
+}
+
+fn overrides_path() -> PathBuf {
+    layout::state_dir().join("log_levels.json")
+}
+
+/**
+ * Get the overridden level for a log target, from the most specific unexpired override that covers
+ * it. An override without a target covers everything.
+ */
+fn override_for(target: &str) -> Option<LevelFilter> {
+    let overrides = OVERRIDES.read().unwrap();
+    if overrides.is_empty() {
+        return None;
+    }
+    let now = clock::unix_now();
+    overrides
+        .iter()
+        .filter(|o| o.expires > now)
+        .filter_map(|o| match &o.target {
+            None => Some((0, o)),
+            Some(module) => {
+                let covers = target == module
+                    || target
+                        .strip_prefix(module.as_str())
+                        .is_some_and(|rest| rest.starts_with("::"));
+                covers.then_some((module.len() + 1, o))
+            }
+        })
+        .max_by_key(|(specificity, _)| *specificity)
+        .and_then(|(_, o)| o.level.parse().ok())
+}
+
+/**
+ * Let the `log` macros through up to the most verbose level anything could be logged at
+ */
+fn update_max_level(base: LevelFilter) {
+    let now = clock::unix_now();
+    let max = OVERRIDES
+        .read()
+        .unwrap()
+        .iter()
+        .filter(|o| o.expires > now)
+        .filter_map(|o| o.level.parse().ok())
+        .fold(base, Ord::max);
+    log::set_max_level(max);
+}
+
+/**
+ * Install the logger, restoring any overrides that haven't expired since the last run. This
+ * replaces `env_logger::init`, and reads `RUST_LOG` and `RUST_LOG_STYLE` the same way.
+ *
+ * # Panics
+ * This function will panic if a logger has already been installed.
+ */
+pub fn init() {
+    let base = env_logger::filter::Builder::from_env("RUST_LOG").build();
+    let mut writer = env_logger::Builder::new();
+    if let Ok(style) = std::env::var("RUST_LOG_STYLE") {
+        writer.parse_write_style(style.as_str());
+    }
+    writer.filter_level(LevelFilter::Trace);
+
+    *BASE_LEVEL.write().unwrap() = base.filter();
+    let restored: Vec<LogOverride> = std::fs::read(overrides_path())
+        .ok()
+        .and_then(|overrides| serde_json::from_slice(&overrides).ok())
+        .unwrap_or_default();
+    *OVERRIDES.write().unwrap() = restored;
+    prune();
+
+    let base_level = base.filter();
+    log::set_boxed_logger(Box::new(Logger {
+        base,
+        writer: writer.build(),
+    }))
+    .expect("Logger already installed");
+    update_max_level(base_level);
+
+    for o in log_levels() {
+        log!(
+            Level::Warn,
+            "Log level for {} is overridden to {} until {}",
+            o.target.as_deref().unwrap_or("everything"),
+            o.level,
+            o.expires
+        );
+    }
+}
+
+/**
+ * Drop expired overrides
+ */
+fn prune() {
+    let now = clock::unix_now();
+    OVERRIDES.write().unwrap().retain(|o| o.expires > now);
+}
+
+/**
+ * Get the active log level overrides
+ */
+#[must_use]
+pub fn log_levels() -> Vec<LogOverride> {
+    prune();
+    update_max_level(*BASE_LEVEL.read().unwrap());
+    OVERRIDES.read().unwrap().clone()
+}
+
+/**
+ * Check a log level like `debug`, and get it in the form overrides store it in
+ *
+ * # Errors
+ * This function will return an error if the level isn't a valid log level.
+ */
+pub fn parse_level(level: &str) -> Result<String, Error> {
+    level
+        .parse::<LevelFilter>()
+        .map(|level| level.to_string().to_lowercase())
+        .map_err(|_| anyhow!("Unknown log level {level}"))
+}
+
+/**
+ * Override the log level of a module (like `backend::api::network`), or of everything if `target`
+ * is `None`, until `expiry` has passed. If `expiry` is `None`, `DEVCADE_LOG_OVERRIDE_MINS` is
+ * used. Passing no level removes the override instead. Overrides are saved, so they survive a
+ * restart until they expire.
+ *
+ * # Errors
+ * This function will return an error if the level isn't a valid log level, or if the overrides
+ * cannot be saved.
+ */
+pub fn set_log_level(
+    target: Option<String>,
+    level: Option<String>,
+    expiry: Option<Duration>,
+) -> Result<Vec<LogOverride>, Error> {
+    let level = level.as_deref().map(parse_level).transpose()?;
+
+    {
+        let mut overrides = OVERRIDES.write().unwrap();
+        overrides.retain(|o| o.target != target);
+        if let Some(level) = level.clone() {
+            overrides.push(LogOverride {
+                target: target.clone(),
+                level,
+                expires: clock::unix_now() + expiry.unwrap_or_else(log_override_expiry).as_secs(),
+            });
+        }
+    }
+    let overrides = log_levels();
+
+    layout::write_atomic(&overrides_path(), serde_json::to_vec(&overrides)?)?;
+    log!(
+        Level::Info,
+        "Log level for {} set to {}",
+        target.as_deref().unwrap_or("everything"),
+        level.as_deref().unwrap_or("the default")
+    );
+    Ok(overrides)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expired_overrides_revert_to_the_default_level() {
+        let (_guard, root) = crate::testing::root("log-levels");
+        let target = "backend::logging_test";
+        let module = "backend::logging_test::inner";
+        assert_eq!(override_for(module), None);
+
+        let overrides =
+            set_log_level(Some(target.to_string()), Some(String::from("TRACE")), None).unwrap();
+        assert!(overrides
+            .iter()
+            .any(|o| o.target.as_deref() == Some(target) && o.level == "trace"));
+        assert_eq!(override_for(module), Some(LevelFilter::Trace));
+        assert_eq!(log::max_level(), LevelFilter::Trace);
+        let saved: Vec<LogOverride> =
+            serde_json::from_slice(&std::fs::read(overrides_path()).unwrap()).unwrap();
+        assert_eq!(saved, overrides);
+
+        // As if its time had run out
+        for o in OVERRIDES.write().unwrap().iter_mut() {
+            if o.target.as_deref() == Some(target) {
+                o.expires = clock::unix_now() - 1;
+            }
+        }
+        assert_eq!(override_for(module), None);
+        assert!(log_levels()
+            .iter()
+            .all(|o| o.target.as_deref() != Some(target)));
+        assert_eq!(log::max_level(), *BASE_LEVEL.read().unwrap());
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
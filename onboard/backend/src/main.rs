@@ -1,27 +1,123 @@
-use backend::env::devcade_path;
+use backend::api::{
+    auto_update, check_data_root, check_setup, drain_tap_queue, log_cache_stats, probe_hardware,
+    resume_handoff, warm_tag_membership, watch_catalog_events, watch_display, watch_peer,
+    watch_quiet_hours, watch_retirement, watch_schedule,
+};
+use backend::boot;
+use backend::env::{config_file, devcade_path, timezone};
+use backend::faults;
+use backend::fds;
+use backend::layout;
+use backend::lock::InstanceLock;
+use backend::logging;
+use backend::secrets;
 use backend::servers::path::{onboard_pipe, persistence_pipe};
-use backend::servers::ThreadHandles;
+use backend::servers::{fallback, ThreadHandles};
 use log::{log, Level};
-use tokio::fs;
 
-#[tokio::main]
-async fn main() -> ! {
+/**
+ * How long the backend has to stay up before its startup counts as successful
+ */
+const READY_AFTER: std::time::Duration = std::time::Duration::from_secs(10);
+
+fn main() -> ! {
     #[cfg(not(target_os = "linux"))]
     {
         compile_error!("This project only supports Linux.\nTo build for linux, run `cargo build --target x86_64-unknown-linux-gnu`");
     }
 
-    fs::create_dir_all(devcade_path())
-        .await
-        .expect("Couldn't create devcade dir");
+    std::fs::create_dir_all(devcade_path()).expect("Couldn't create devcade dir");
 
-    match dotenv::from_filename("../.env") {
+    match dotenv::from_filename(config_file()) {
         Ok(_) => (),
         Err(e) => {
             log!(Level::Error, "Error loading .env file: {}", e);
         }
     }
-    env_logger::init();
+    logging::init();
+    fds::raise_limit();
+    faults::init();
+
+    // Make local time in the backend agree with the timezone given to games. Changing the
+    // environment isn't safe once other threads are running, so this is done before the runtime
+    // starts its worker threads.
+    if let Some(tz) = timezone() {
+        std::env::set_var("TZ", tz);
+    }
+
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("Couldn't start the async runtime")
+        .block_on(run())
+}
+
+/**
+ * Start the backend's servers and background tasks, and restart servers that panic. This never
+ * returns.
+ */
+async fn run() -> ! {
+    // Held until the process exits so a second backend can't fight over the sockets and saves
+    let takeover = std::env::args().any(|arg| arg == "--takeover");
+    let _lock = match InstanceLock::acquire(takeover) {
+        Ok(lock) => lock,
+        Err(e) => {
+            log!(Level::Error, "Couldn't acquire instance lock: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // Counted before anything that might crash, so a crash loop ends up in safe mode
+    let safe_mode = boot::start();
+    if safe_mode {
+        log!(
+            Level::Error,
+            "SAFE MODE: the cache and state were quarantined to {}, and only core commands are \
+            served. Run `devcade-ctl safe-mode exit --restore` or `--discard` once fixed.",
+            boot::quarantine_dir().display()
+        );
+    } else {
+        // Move games installed by older versions into the games directory
+        layout::migrate();
+        secrets::migrate();
+    }
+
+    check_setup();
+
+    if let Some((old, count)) = check_data_root() {
+        log!(
+            Level::Warn,
+            "DEVCADE_PATH changed from {} to {}, but {} games are still installed in {}. Move the \
+            game directories over to avoid downloading everything again.",
+            old,
+            devcade_path(),
+            count,
+            old
+        );
+    }
+
+    if !safe_mode {
+        // Before anything else can launch a game or queue a download
+        resume_handoff();
+        // Games launched before this finishes are told the hardware is still pending
+        tokio::spawn(probe_hardware());
+        // So tags can still be filtered on if the API goes down later
+        tokio::spawn(warm_tag_membership());
+        // Does nothing unless DEVCADE_AUTO_UPDATE_WINDOW is set
+        tokio::spawn(auto_update());
+        tokio::spawn(watch_display());
+        tokio::spawn(watch_retirement());
+        tokio::spawn(watch_schedule());
+        tokio::spawn(watch_quiet_hours());
+        // Does nothing unless DEVCADE_CATALOG_EVENTS is set
+        tokio::spawn(watch_catalog_events());
+        // Does nothing unless DEVCADE_PEER_ADDR or DEVCADE_PEER_LISTEN is set
+        tokio::spawn(watch_peer());
+        tokio::spawn(drain_tap_queue());
+        tokio::spawn(log_cache_stats());
+
+        tokio::spawn(fallback::watch());
+    }
 
     let mut handles: ThreadHandles = ThreadHandles::new();
 
@@ -32,8 +128,15 @@ async fn main() -> ! {
     // TODO Gatekeeper / Authentication
 
     // Main loop
+    let started = tokio::time::Instant::now();
+    let mut ready = false;
     loop {
         tokio::time::sleep(tokio::time::Duration::from_millis(1000)).await;
+        // Staying up this long counts as a successful startup
+        if !ready && started.elapsed() >= READY_AFTER {
+            boot::ready();
+            ready = true;
+        }
         // Check if any of the handles have finished
         if let Some(err) = handles.onboard_error() {
             log!(Level::Error, "Onboard thread has panicked: {}", err);
@@ -1,5 +1,5 @@
 use devcade_onboard_types::{Map, Value};
-use gatekeeper_members::GateKeeperMemberListener;
+use gatekeeper_members::{FetchError, GateKeeperMemberListener};
 use lazy_static::lazy_static;
 use libgatekeeper_sys::Nfc;
 use std::fmt;
@@ -12,6 +12,8 @@ use tokio::sync::oneshot;
 use tokio::sync::Mutex;
 
 type NfcCallback = oneshot::Sender<Option<String>>;
+/// `Ok(None)` if the user wasn't found, `Err` if the auth service couldn't be asked
+type UserCallback = oneshot::Sender<Result<Option<Map<String, Value>>, AuthUnavailable>>;
 pub struct NfcClient {
     request_queue: Mutex<Sender<NfcRequest>>,
     thread: JoinHandle<()>,
@@ -23,7 +25,7 @@ enum NfcRequest {
     },
     User {
         association_id: String,
-        callback: oneshot::Sender<Option<Map<String, Value>>>,
+        callback: UserCallback,
     },
 }
 
@@ -60,7 +62,9 @@ impl NfcClient {
                         log::error!("Couldn't build Gatekeeper listener?");
                         // Unwrap rationale: If the main thread is crashed, not much we can do
                         match callback {
-                            NfcRequest::User { callback, .. } => callback.send(None).unwrap(),
+                            NfcRequest::User { callback, .. } => {
+                                callback.send(Err(AuthUnavailable)).unwrap();
+                            }
                             NfcRequest::Tags { callback } => callback.send(None).unwrap(),
                         }
                         continue;
@@ -73,14 +77,13 @@ impl NfcClient {
                         callback,
                         association_id,
                     } => {
-                        callback
-                            .send(
-                                listener
-                                    .fetch_user(association_id)
-                                    .ok()
-                                    .and_then(|user| user["user"].as_object().cloned()),
-                            )
-                            .unwrap();
+                        let user = match listener.fetch_user(association_id) {
+                            Ok(user) => Ok(user["user"].as_object().cloned()),
+                            Err(FetchError::NotFound) => Ok(None),
+                            Err(_) => Err(AuthUnavailable),
+                        };
+                        // Unwrap rationale: If the main thread is crashed, not much we can do
+                        callback.send(user).unwrap();
                     }
                     NfcRequest::Tags { callback } => {
                         // Unwrap rationale: If the main thread is crashed, not much we can do
@@ -115,13 +118,28 @@ impl NfcClient {
             association_id,
             callback: tx,
         })?;
-        match rx.await? {
+        match rx.await?? {
             Some(user) => Ok(user),
             None => Err(anyhow::anyhow!("User not found with that association ID")),
         }
     }
 }
 
+/**
+ * The error a user lookup fails with when the auth service can't be reached or gives an unexpected
+ * answer, as opposed to not knowing the user
+ */
+#[derive(Debug, Clone, Copy)]
+pub struct AuthUnavailable;
+
+impl fmt::Display for AuthUnavailable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "AuthUnavailable: the auth service couldn't be reached")
+    }
+}
+
+impl std::error::Error for AuthUnavailable {}
+
 #[derive(Debug)]
 struct NfcThreadError;
 
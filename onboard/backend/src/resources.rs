@@ -0,0 +1,80 @@
+use crate::env;
+use devcade_onboard_types::{Capability, ResourceProfile};
+use lazy_static::lazy_static;
+use log::{log, Level};
+
+/**
+ * Cabinets with less memory than this get the low resource profile unless it's set explicitly
+ */
+const LOW_MEMORY_BYTES: u64 = 2 * 1024 * 1024 * 1024;
+
+lazy_static! {
+    static ref PROFILE: ResourceProfile = detect();
+}
+
+/**
+ * Get the resource profile the backend runs with. It's read once, so changing
+ * `DEVCADE_RESOURCE_PROFILE` takes a restart.
+ */
+#[must_use]
+pub fn profile() -> ResourceProfile {
+    *PROFILE
+}
+
+/**
+ * Get the optional work the backend skips with its resource profile, so the frontend can do
+ * without it
+ */
+#[must_use]
+pub fn skipped() -> Vec<Capability> {
+    match profile() {
+        ResourceProfile::Full => Vec::new(),
+        ResourceProfile::Low => vec![Capability::IconAtlas],
+    }
+}
+
+/**
+ * Whether the resource profile allows some optional work
+ */
+#[must_use]
+pub fn allows(capability: Capability) -> bool {
+    !skipped().contains(&capability)
+}
+
+fn detect() -> ResourceProfile {
+    let profile = match env::resource_profile().as_deref() {
+        Some("full") => ResourceProfile::Full,
+        Some("low") => ResourceProfile::Low,
+        configured => {
+            if let Some(profile) = configured {
+                log!(
+                    Level::Warn,
+                    "Unknown resource profile '{}', detecting it instead",
+                    profile
+                );
+            }
+            match total_memory() {
+                Some(total) if total < LOW_MEMORY_BYTES => ResourceProfile::Low,
+                _ => ResourceProfile::Full,
+            }
+        }
+    };
+    log!(Level::Info, "Resource profile: {:?}", profile);
+    profile
+}
+
+/**
+ * Get the cabinet's total memory in bytes from `/proc/meminfo`
+ */
+fn total_memory() -> Option<u64> {
+    let meminfo = std::fs::read_to_string("/proc/meminfo").ok()?;
+    let kb: u64 = meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemTotal:"))?
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()?;
+    Some(kb * 1024)
+}
@@ -0,0 +1,346 @@
+use crate::audit;
+use crate::clock;
+use crate::layout;
+use anyhow::{anyhow, Error};
+use devcade_onboard_types::SecretInfo;
+use hmac::{Hmac, Mac};
+use lazy_static::lazy_static;
+use log::{log, Level};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/**
+ * The salt association IDs are hashed with in the tap audit trail
+ */
+pub const TAP_AUDIT_SALT: &str = "tap_audit_salt";
+
+/**
+ * The salt association IDs are hashed with in the queue of taps made while the auth service was
+ * down. Unlike the audit salt it isn't rotated, so a queued tap still matches the card's next tap.
+ */
+pub const TAP_QUEUE_SALT: &str = "tap_queue_salt";
+
+/**
+ * The key the cabinet and the cabinet next to it sign the status they exchange with. Both cabinets
+ * need the same value.
+ */
+pub const PEER_KEY: &str = "peer_key";
+
+/**
+ * The key catalog snapshots are signed with, so a snapshot can't be edited to match a changed
+ * install
+ */
+pub const FREEZE_KEY: &str = "freeze_key";
+
+lazy_static! {
+    // The secrets file's contents, loaded on first use
+    static ref SECRETS: Mutex<Option<BTreeMap<String, Secret>>> = Mutex::new(None);
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct Secret {
+    value: String,
+    created: u64,
+    rotated: Option<u64>,
+}
+
+/**
+ * Where secrets are kept. Only the backend's user may read it.
+ */
+fn path() -> PathBuf {
+    layout::state_dir().join("secrets.json")
+}
+
+/**
+ * Read the secrets file, refusing to use it if other users could have read it
+ */
+fn load() -> Result<BTreeMap<String, Secret>, Error> {
+    let meta = match std::fs::metadata(path()) {
+        Ok(meta) => meta,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mode = meta.permissions().mode() & 0o777;
+    if mode & 0o077 != 0 {
+        return Err(anyhow!(
+            "{} can be read by other users (mode {:o}), refusing to use it. Run `chmod 600` on it \
+            and rotate the secrets in it.",
+            path().display(),
+            mode
+        ));
+    }
+    Ok(serde_json::from_slice(&std::fs::read(path())?)?)
+}
+
+fn save(secrets: &BTreeMap<String, Secret>) -> Result<(), Error> {
+    layout::write_private(&path(), serde_json::to_vec(secrets)?)?;
+    Ok(())
+}
+
+fn with_secrets<T>(
+    f: impl FnOnce(&mut BTreeMap<String, Secret>) -> Result<T, Error>,
+) -> Result<T, Error> {
+    let mut secrets = SECRETS.lock().unwrap();
+    if secrets.is_none() {
+        *secrets = Some(load()?);
+    }
+    // This unwrap is safe because the secrets were just loaded
+    f(secrets.as_mut().unwrap())
+}
+
+/**
+ * Drop the cached secrets, so they're read again from the devcade directory
+ */
+#[cfg(test)]
+pub(crate) fn forget() {
+    *SECRETS.lock().unwrap() = None;
+}
+
+/**
+ * Get a secret's value and when it was last set (created or rotated), or `None` if it isn't set
+ *
+ * # Errors
+ * This function will return an error if the secrets file can't be read, or other users can read it.
+ */
+pub fn lookup(name: &str) -> Result<Option<(String, u64)>, Error> {
+    with_secrets(|secrets| {
+        Ok(secrets.get(name).map(|secret| {
+            (
+                secret.value.clone(),
+                secret.rotated.unwrap_or(secret.created),
+            )
+        }))
+    })
+}
+
+/**
+ * Get a secret's value. `feature` names what needs it, for the error if it isn't set.
+ *
+ * # Errors
+ * This function will return an error naming the secret and the feature if it isn't set, or if the
+ * secrets file can't be used.
+ */
+pub fn get(name: &str, feature: &str) -> Result<String, Error> {
+    lookup(name)?.map(|(value, _)| value).ok_or_else(|| {
+        anyhow!(
+            "Secret '{name}' isn't set, but {feature} needs it. Set it with `devcade-ctl secret \
+            set {name}`."
+        )
+    })
+}
+
+/**
+ * Set a secret, keeping when it was first created
+ *
+ * # Errors
+ * This function will return an error if the name is empty, or if the secrets file can't be read
+ * or written.
+ */
+pub fn set(name: &str, value: &str) -> Result<(), Error> {
+    if name.is_empty() || name.contains(char::is_whitespace) {
+        return Err(anyhow!("Invalid secret name '{name}'"));
+    }
+    let now = clock::unix_now();
+    with_secrets(|secrets| {
+        let mut updated = secrets.clone();
+        let previous = updated.get(name).map(|secret| secret.created);
+        updated.insert(
+            name.to_string(),
+            Secret {
+                value: value.to_string(),
+                created: previous.unwrap_or(now),
+                rotated: previous.map(|_| now),
+            },
+        );
+        save(&updated)?;
+        *secrets = updated;
+        Ok(())
+    })?;
+    log!(Level::Info, "Secret '{}' was set", name);
+    Ok(())
+}
+
+/**
+ * Set when a secret was created, for secrets moved from elsewhere that are older than the move
+ *
+ * # Errors
+ * This function will return an error if the secret isn't set, or the secrets file can't be
+ * written.
+ */
+pub fn backdate(name: &str, created: u64) -> Result<(), Error> {
+    with_secrets(|secrets| {
+        let mut updated = secrets.clone();
+        let secret = updated
+            .get_mut(name)
+            .ok_or_else(|| anyhow!("Secret '{name}' isn't set"))?;
+        secret.created = created;
+        secret.rotated = None;
+        save(&updated)?;
+        *secrets = updated;
+        Ok(())
+    })
+}
+
+/**
+ * Replace a secret the backend generates itself with a new value, letting the feature that uses it
+ * wrap up anything tied to the old one first
+ *
+ * # Errors
+ * This function will return an error if the backend can't generate the secret, or the rotation
+ * fails.
+ */
+pub fn rotate(name: &str) -> Result<(), Error> {
+    match name {
+        TAP_AUDIT_SALT => audit::rotate_salt(),
+        _ => Err(anyhow!(
+            "Secret '{name}' isn't generated by the backend, set a new value with SetSecret"
+        )),
+    }
+}
+
+/**
+ * Generate `len` random bytes, hex encoded, for salts and tokens
+ *
+ * # Errors
+ * This function will return an error if the system's random source can't be read.
+ */
+pub fn random_hex(len: usize) -> Result<String, Error> {
+    let mut bytes = vec![0u8; len];
+    std::fs::File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    Ok(hex(&bytes))
+}
+
+/**
+ * Hex encode bytes, like a hash or a signature
+ */
+#[must_use]
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/**
+ * Start an HMAC-SHA256 signature with a secret's value as the key
+ */
+#[must_use]
+pub fn mac(key: &str) -> Hmac<Sha256> {
+    // This unwrap is safe because HMAC takes keys of any length
+    Hmac::new_from_slice(key.as_bytes()).unwrap()
+}
+
+/**
+ * List the secrets that are set, without their values
+ *
+ * # Errors
+ * This function will return an error if the secrets file can't be used.
+ */
+pub fn list() -> Result<Vec<SecretInfo>, Error> {
+    with_secrets(|secrets| {
+        Ok(secrets
+            .iter()
+            .map(|(name, secret)| SecretInfo {
+                name: name.clone(),
+                created: secret.created,
+                rotated: secret.rotated,
+            })
+            .collect())
+    })
+}
+
+/**
+ * Move secrets kept elsewhere by older versions into the secrets file. Each move is logged, and
+ * the old copy is removed once the secret is saved. Failures are logged, and the old copy is left
+ * to be moved on the next start.
+ */
+pub fn migrate() {
+    if let Err(e) = audit::migrate_salt() {
+        log!(
+            Level::Error,
+            "Couldn't move the tap audit salt into the secrets file: {}",
+            e
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use devcade_onboard_types::{Player, TapAuditEntry};
+
+    #[test]
+    fn old_salt_file_is_migrated() {
+        let (_guard, root) = crate::testing::root("secrets-migrate");
+        let old = layout::state_dir().join("tap_audit_salt.json");
+        layout::write_atomic(&old, r#"{"salt":"0123abcd","created":1000}"#).unwrap();
+
+        migrate();
+        assert!(!old.exists());
+        assert_eq!(
+            lookup(TAP_AUDIT_SALT).unwrap(),
+            Some(("0123abcd".to_string(), 1000))
+        );
+        let mode = std::fs::metadata(path()).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        // Still there once it's read back from the file
+        forget();
+        assert_eq!(list().unwrap()[0].created, 1000);
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn rotating_the_audit_salt_finishes_the_old_window_first() {
+        let (_guard, root) = crate::testing::root("secrets-rotate");
+        set(TAP_AUDIT_SALT, "old salt").unwrap();
+        // A tap from before the retention window, which should be counted under the old salt
+        let time = clock::unix_now() - 30 * 24 * 60 * 60;
+        let tap = TapAuditEntry::Tap {
+            time,
+            reader: Player::P1,
+            id_hash: "hash".to_string(),
+            outcome: "ok".to_string(),
+        };
+        crate::state::journal(&layout::state_dir().join("tap_audit.jsonl"), &tap).unwrap();
+
+        rotate(TAP_AUDIT_SALT).unwrap();
+        let (salt, _) = lookup(TAP_AUDIT_SALT).unwrap().unwrap();
+        assert_ne!(salt, "old salt");
+        assert!(list().unwrap()[0].rotated.is_some());
+        assert!(matches!(
+            audit::tap_audit(0, u64::MAX).unwrap()[..],
+            [TapAuditEntry::Hourly { count: 1, .. }]
+        ));
+
+        // Secrets the backend doesn't generate can't be rotated by it
+        set(PEER_KEY, "shared").unwrap();
+        assert!(rotate(PEER_KEY).is_err());
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn readable_secrets_file_is_refused() {
+        let (_guard, root) = crate::testing::root("secrets-mode");
+        set(PEER_KEY, "shared").unwrap();
+        std::fs::set_permissions(path(), std::fs::Permissions::from_mode(0o644)).unwrap();
+
+        forget();
+        let e = get(PEER_KEY, "peer pairing").unwrap_err().to_string();
+        assert!(e.contains("chmod 600"), "{e}");
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn missing_secret_names_the_feature() {
+        let (_guard, root) = crate::testing::root("secrets-missing");
+        let e = get(FREEZE_KEY, "catalog freezing").unwrap_err().to_string();
+        assert!(
+            e.contains("'freeze_key'") && e.contains("catalog freezing"),
+            "{e}"
+        );
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
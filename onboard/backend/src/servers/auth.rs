@@ -0,0 +1,359 @@
+use crate::clock;
+use crate::env::client_roles;
+use crate::layout;
+use crate::state;
+use devcade_onboard_types::{Request, RequestBody};
+use log::{log, Level};
+use serde::Serialize;
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use tokio::net::unix::UCred;
+
+/**
+ * What a client connected to the onboard server is allowed to do. Each role can do everything the
+ * roles before it can.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    /**
+     * Can look at the library and the cabinet, but not change anything
+     */
+    ReadOnly,
+    /**
+     * Can do everything a player can through the frontend
+     */
+    Frontend,
+    /**
+     * Can also change how the backend is configured
+     */
+    Operator,
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "readonly" => Ok(Self::ReadOnly),
+            "frontend" => Ok(Self::Frontend),
+            "operator" => Ok(Self::Operator),
+            _ => Err(format!(
+                "Unknown role '{s}', expected readonly, frontend or operator"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ReadOnly => write!(f, "readonly"),
+            Self::Frontend => write!(f, "frontend"),
+            Self::Operator => write!(f, "operator"),
+        }
+    }
+}
+
+/**
+ * Work out the role of a client from its credentials and `DEVCADE_CLIENT_ROLES`. Without a role map
+ * every client is an operator, like before roles existed. With one, clients that aren't in it get
+ * no role at all and every command is refused. If a client matches several entries, it gets the
+ * highest role.
+ */
+#[must_use]
+pub fn resolve_role(peer: Option<UCred>) -> Option<Role> {
+    let Some(roles) = client_roles() else {
+        return Some(Role::Operator);
+    };
+    let peer = peer?;
+    roles
+        .split(',')
+        .filter(|entry| !entry.trim().is_empty())
+        .filter_map(|entry| {
+            let parsed = parse_entry(entry);
+            if parsed.is_none() {
+                log!(
+                    Level::Warn,
+                    "Ignoring invalid client role entry '{}'",
+                    entry
+                );
+            }
+            parsed
+        })
+        .filter(|(kind, id, _)| match *kind {
+            "uid" => *id == peer.uid(),
+            _ => *id == peer.gid(),
+        })
+        .map(|(_, _, role)| role)
+        .max()
+}
+
+/**
+ * A command that was refused, as kept in the refusal journal
+ */
+#[derive(Serialize)]
+struct Refusal {
+    time: u64,
+    uid: Option<u32>,
+    gid: Option<u32>,
+    pid: Option<i32>,
+    role: Option<String>,
+    command: String,
+}
+
+fn journal_path() -> PathBuf {
+    layout::state_dir().join("refused_commands.jsonl")
+}
+
+/**
+ * Record that a client's command was refused, in the log and in the refusal journal
+ */
+pub fn refuse(peer: Option<UCred>, role: Option<Role>, command: &Request) {
+    log!(
+        Level::Warn,
+        "Refused command from client {:?} with role {:?}: {}",
+        peer,
+        role,
+        command
+    );
+    let refusal = Refusal {
+        time: clock::unix_now(),
+        uid: peer.map(|peer| peer.uid()),
+        gid: peer.map(|peer| peer.gid()),
+        pid: peer.and_then(|peer| peer.pid()),
+        role: role.map(|role| role.to_string()),
+        command: command.body.to_string(),
+    };
+    if let Err(e) = state::journal(journal_path().as_path(), &refusal) {
+        log!(Level::Error, "Couldn't record refused command: {}", e);
+    }
+}
+
+/**
+ * Parse a role map entry like `uid:1000=frontend`
+ */
+fn parse_entry(entry: &str) -> Option<(&str, u32, Role)> {
+    let (who, role) = entry.split_once('=')?;
+    let (kind, id) = who.trim().split_once(':')?;
+    if kind != "uid" && kind != "gid" {
+        return None;
+    }
+    Some((kind, id.parse().ok()?, role.parse().ok()?))
+}
+
+/**
+ * Get the role a client needs to send a command. Every command is listed, so a new one doesn't
+ * compile until it's given a role.
+ */
+#[must_use]
+pub fn required_role(body: &RequestBody) -> Role {
+    match body {
+        RequestBody::Ping
+        | RequestBody::GetGameList
+        | RequestBody::GetGameListFromFs
+        | RequestBody::GetLibrary(_)
+        | RequestBody::GetGameListWithAccessibility(_)
+        | RequestBody::GetGame(_)
+        | RequestBody::GetTagList
+        | RequestBody::GetTag(_)
+        | RequestBody::GetGameListFromTag(_)
+        | RequestBody::GetTagMembership(_)
+        | RequestBody::GetTagCounts
+        | RequestBody::GetTagStats(_)
+        | RequestBody::GetAuthorStats(_)
+        | RequestBody::GetCabinetInfo
+        | RequestBody::GetSetupStatus
+        | RequestBody::GetCabinetHardware
+        | RequestBody::GetCabinetSetting(_)
+        | RequestBody::GetIconAtlas(_, _)
+        | RequestBody::GetGameListWithThumbnails
+        | RequestBody::GetDownloadProgress(_)
+        | RequestBody::GetDownloadEstimate(_)
+        | RequestBody::GetDownloadQueue
+        | RequestBody::GetLaunchEvents(_)
+        | RequestBody::Subscribe(_)
+        | RequestBody::Negotiate(_)
+        | RequestBody::GetHighlights
+        | RequestBody::GetFeaturedGames
+        | RequestBody::GetSchedule
+        | RequestBody::GetQuietHours
+        | RequestBody::GetEventHighlights(_)
+        | RequestBody::GetLifetimeStats
+        | RequestBody::ExportSessions(_, _)
+        | RequestBody::GetEventLabel
+        | RequestBody::GetGameRuntime(_)
+        | RequestBody::GetUpdateSummary
+        | RequestBody::GetDisplayProtection
+        | RequestBody::GetPeerStatus
+        | RequestBody::GetSuspiciousUpdates
+        | RequestBody::GetGameResources
+        | RequestBody::GetFeatureUsage(_)
+        | RequestBody::GetFeatureAdoption
+        | RequestBody::GetCatalogFreeze
+        | RequestBody::GetTapStats
+        | RequestBody::GetCacheStats
+        | RequestBody::VerifyFreeze(_)
+        | RequestBody::GetInputActivity(_)
+        | RequestBody::ListProfiles => Role::ReadOnly,
+        // The setup commands are refused by the backend once setup is complete, so the frontend can
+        // run the first-run wizard
+        RequestBody::SetConfigValue(_, _)
+        | RequestBody::TestApiConnection
+        | RequestBody::CompleteSetup
+        | RequestBody::DownloadGame(_)
+        | RequestBody::DownloadIcon(_)
+        | RequestBody::DownloadBanner(_)
+        | RequestBody::DownloadAllAssets
+        | RequestBody::CancelDownload(_)
+        | RequestBody::EnqueueDownload(_, _)
+        | RequestBody::PromoteDownload(_)
+        | RequestBody::GetUser(_)
+        | RequestBody::GetNfcTag(_)
+        | RequestBody::GetNfcUser(_)
+        | RequestBody::LaunchGame(_)
+        | RequestBody::CaptureScreenshot
+        | RequestBody::PauseGame
+        | RequestBody::ResumeGame
+        | RequestBody::UserActivity
+        | RequestBody::ReportInputActivity(_)
+        | RequestBody::SetPeerConsent(_, _)
+        | RequestBody::SubmitSessionSummary(_)
+        | RequestBody::Save(_, _, _)
+        | RequestBody::Load(_, _)
+        | RequestBody::Flush
+        | RequestBody::BeginSave(_, _)
+        | RequestBody::AppendSave(_, _)
+        | RequestBody::CommitSave(_)
+        | RequestBody::LoadRange(_, _, _, _) => Role::Frontend,
+        RequestBody::SetProduction(_)
+        | RequestBody::ReloadTls
+        | RequestBody::SetDownloadLimit(_)
+        | RequestBody::SetLogLevel(_, _, _)
+        | RequestBody::GetLogLevels
+        | RequestBody::SetCapture(_, _)
+        | RequestBody::SetRequiredAccessibility(_)
+        | RequestBody::SaveProfile(_)
+        | RequestBody::ApplyProfile(_)
+        | RequestBody::DeleteProfile(_)
+        | RequestBody::SetCabinetSetting(_, _)
+        | RequestBody::CleanupOrphanedGames(_)
+        | RequestBody::CancelOrphanCleanup
+        | RequestBody::GetDuplicateInstalls
+        | RequestBody::LaunchGameIgnoringPolicy(_)
+        | RequestBody::LaunchGameSharingSaves(_)
+        | RequestBody::SetSecret(_, _)
+        | RequestBody::RotateSecret(_)
+        | RequestBody::ListSecrets
+        | RequestBody::ProbeHardware
+        | RequestBody::PrepareRestart
+        | RequestBody::RunGameSetup(_)
+        | RequestBody::TestEventHooks(_)
+        | RequestBody::MaintainObjectStore
+        | RequestBody::AddScheduleEntry(_)
+        | RequestBody::RemoveScheduleEntry(_)
+        | RequestBody::SetQuietHours(_)
+        | RequestBody::OverrideQuietHours(_)
+        | RequestBody::ConfirmSuspiciousUpdate(_)
+        | RequestBody::FreezeCatalog(_)
+        | RequestBody::SetEventLabel(_)
+        | RequestBody::CompactStats(_)
+        | RequestBody::Unfreeze
+        | RequestBody::GetTapAudit(_, _) => Role::Operator,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::UnixStream;
+
+    /**
+     * Credentials of this process, as the onboard server would see them
+     */
+    fn own_credentials() -> UCred {
+        let (client, _server) = UnixStream::pair().unwrap();
+        client.peer_cred().unwrap()
+    }
+
+    fn allowed(role: Option<Role>, body: &RequestBody) -> bool {
+        role.is_some_and(|role| role >= required_role(body))
+    }
+
+    #[tokio::test]
+    async fn peer_credentials_resolve_to_roles() {
+        let (_guard, root) = crate::testing::root("auth-roles");
+        let peer = own_credentials();
+        let uid = peer.uid();
+        let gid = peer.gid();
+
+        std::env::remove_var("DEVCADE_CLIENT_ROLES");
+        assert_eq!(resolve_role(Some(peer)), Some(Role::Operator));
+
+        std::env::set_var("DEVCADE_CLIENT_ROLES", format!("uid:{uid}=readonly"));
+        assert_eq!(resolve_role(Some(peer)), Some(Role::ReadOnly));
+        // The FIFO transport has no credentials
+        assert_eq!(resolve_role(None), None);
+
+        // The highest matching entry wins, and invalid entries are skipped
+        std::env::set_var(
+            "DEVCADE_CLIENT_ROLES",
+            format!("uid:{uid}=readonly,gid:{gid}=operator,uid:x=frontend"),
+        );
+        assert_eq!(resolve_role(Some(peer)), Some(Role::Operator));
+
+        // Default deny for anyone not in the map
+        std::env::set_var(
+            "DEVCADE_CLIENT_ROLES",
+            format!("uid:{}=operator", uid.wrapping_add(1)),
+        );
+        assert_eq!(resolve_role(Some(peer)), None);
+        std::env::remove_var("DEVCADE_CLIENT_ROLES");
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn each_role_is_refused_what_is_above_it() {
+        // Read-only clients can still see the status and the library
+        assert!(allowed(Some(Role::ReadOnly), &RequestBody::GetCabinetInfo));
+        assert!(allowed(
+            Some(Role::ReadOnly),
+            &RequestBody::GetGameListFromFs
+        ));
+        assert!(!allowed(
+            Some(Role::ReadOnly),
+            &RequestBody::LaunchGame(String::new())
+        ));
+
+        assert!(allowed(
+            Some(Role::Frontend),
+            &RequestBody::LaunchGame(String::new())
+        ));
+        assert!(!allowed(
+            Some(Role::Frontend),
+            &RequestBody::SetSecret(String::new(), String::new())
+        ));
+
+        assert!(allowed(
+            Some(Role::Operator),
+            &RequestBody::SetSecret(String::new(), String::new())
+        ));
+        assert!(!allowed(None, &RequestBody::Ping));
+    }
+
+    #[tokio::test]
+    async fn refusals_are_journaled() {
+        let (_guard, root) = crate::testing::root("auth-refusals");
+        let request = Request {
+            request_id: 1,
+            body: RequestBody::SetSecret("peer_key".to_string(), "hunter2".to_string()),
+        };
+        refuse(Some(own_credentials()), Some(Role::Frontend), &request);
+
+        let journal = std::fs::read_to_string(journal_path()).unwrap();
+        assert!(journal.contains("\"role\":\"frontend\""), "{journal}");
+        assert!(journal.contains("peer_key"), "{journal}");
+        assert!(!journal.contains("hunter2"), "{journal}");
+        let _ = std::fs::remove_dir_all(&root);
+    }
+}
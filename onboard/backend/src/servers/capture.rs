@@ -0,0 +1,388 @@
+use crate::env::{capture_duration, capture_payload_limit};
+use crate::layout;
+use anyhow::{anyhow, Error};
+use devcade_onboard_types::client::{read_frame, write_frame};
+use devcade_onboard_types::{to_frame, Value};
+use lazy_static::lazy_static;
+use log::{log, Level};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, UNIX_EPOCH};
+
+/**
+ * Whether commands are being captured. This is all the onboard server checks when capturing is off.
+ */
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/**
+ * When capturing stops on its own, in unix milliseconds
+ */
+static UNTIL: AtomicU64 = AtomicU64::new(0);
+
+/**
+ * Source of connection ids, so requests in a capture can be told apart by client
+ */
+static NEXT_CONNECTION: AtomicU64 = AtomicU64::new(1);
+
+/**
+ * Captures are rotated once they reach this size, and only the newest few are kept
+ */
+const MAX_FILE_BYTES: u64 = 10 * 1024 * 1024;
+const MAX_FILES: usize = 5;
+
+/**
+ * Commands whose data identifies a member, which is never written to a capture
+ */
+const REDACTED: [&str; 6] = [
+    "GetNfcUser",
+    "NfcTag",
+    "NfcUser",
+    "SetSecret",
+    "SetPeerConsent",
+    "PeerLink",
+];
+const REDACTION: &str = "[redacted]";
+const TRUNCATION: &str = "[truncated";
+
+/**
+ * Fields that change from run to run, and are ignored when comparing replayed responses
+ */
+const VOLATILE: [&str; 6] = [
+    "time",
+    "hour",
+    "expires",
+    "bytes_downloaded",
+    "seconds",
+    "free_bytes",
+];
+
+lazy_static! {
+    // The capture file being written to
+    static ref FILE: Mutex<Option<CaptureFile>> = Mutex::new(None);
+}
+
+struct CaptureFile {
+    file: File,
+    size: u64,
+}
+
+/**
+ * Whether a captured message was sent to or by the backend
+ */
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Request,
+    Response,
+}
+
+/**
+ * One line of a capture file
+ */
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    /// Unix timestamp in milliseconds
+    time: u64,
+    connection: u64,
+    direction: Direction,
+    payload: Value,
+}
+
+fn captures_dir() -> PathBuf {
+    layout::state_dir().join("captures")
+}
+
+fn now_millis() -> u64 {
+    crate::clock::system_now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis()
+        .try_into()
+        .unwrap_or(u64::MAX)
+}
+
+/**
+ * Get an id for a new connection to the onboard server
+ */
+pub fn connection_id() -> u64 {
+    NEXT_CONNECTION.fetch_add(1, Ordering::Relaxed)
+}
+
+/**
+ * Whether commands are being captured
+ */
+#[inline]
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/**
+ * Start capturing commands for `duration`, or `DEVCADE_CAPTURE_MINS` if `None`, or stop capturing
+ */
+pub fn set_capture(enable: bool, duration: Option<Duration>) {
+    if enable {
+        let duration = duration.unwrap_or_else(capture_duration);
+        let until =
+            now_millis().saturating_add(duration.as_millis().try_into().unwrap_or(u64::MAX));
+        UNTIL.store(until, Ordering::Relaxed);
+        ENABLED.store(true, Ordering::Relaxed);
+        log!(
+            Level::Warn,
+            "Capturing commands to {} for {:?}",
+            captures_dir().display(),
+            duration
+        );
+    } else {
+        stop();
+    }
+}
+
+fn stop() {
+    if ENABLED.swap(false, Ordering::Relaxed) {
+        log!(Level::Info, "Stopped capturing commands");
+    }
+    *FILE.lock().unwrap() = None;
+}
+
+/**
+ * Write a request or response to the capture, redacted and truncated. Only call this when capture
+ * is `enabled`.
+ */
+pub fn record<T: Serialize>(connection: u64, direction: Direction, message: &T) {
+    let time = now_millis();
+    if time > UNTIL.load(Ordering::Relaxed) {
+        stop();
+        return;
+    }
+    let Ok(mut payload) = serde_json::to_value(message) else {
+        return;
+    };
+    sanitize(&mut payload);
+    let entry = Entry {
+        time,
+        connection,
+        direction,
+        payload,
+    };
+    if let Err(e) = write(&entry) {
+        log!(Level::Warn, "Couldn't write to command capture: {}", e);
+    }
+}
+
+/**
+ * Remove member data from a message, and shorten messages with large payloads
+ */
+fn sanitize(payload: &mut Value) {
+    let redact = payload
+        .get("type")
+        .and_then(Value::as_str)
+        .is_some_and(|kind| REDACTED.contains(&kind));
+    let Some(data) = payload.get_mut("data") else {
+        return;
+    };
+    if redact {
+        *data = Value::from(REDACTION);
+        return;
+    }
+    let size = data.to_string().len();
+    if size > capture_payload_limit() {
+        *data = Value::from(format!("{TRUNCATION} {size} bytes]"));
+    }
+}
+
+fn write(entry: &Entry) -> Result<(), Error> {
+    let line = to_frame(entry)?;
+    let mut file = FILE.lock().unwrap();
+    if file
+        .as_ref()
+        .is_none_or(|file| file.size + line.len() as u64 > MAX_FILE_BYTES)
+    {
+        *file = Some(rotate()?);
+    }
+    // This unwrap is safe because the file was just opened if there wasn't one
+    let capture = file.as_mut().unwrap();
+    capture.file.write_all(&line)?;
+    capture.size += line.len() as u64;
+    Ok(())
+}
+
+/**
+ * Start a new capture file, deleting the oldest ones past the limit
+ */
+fn rotate() -> Result<CaptureFile, Error> {
+    std::fs::create_dir_all(captures_dir())?;
+    let mut captures: Vec<PathBuf> = std::fs::read_dir(captures_dir())?
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "jsonl"))
+        .collect();
+    captures.sort();
+    while captures.len() >= MAX_FILES {
+        std::fs::remove_file(captures.remove(0))?;
+    }
+
+    let path = captures_dir().join(format!("capture-{}.jsonl", now_millis()));
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    Ok(CaptureFile { file, size: 0 })
+}
+
+/**
+ * The result of replaying a capture
+ */
+#[derive(Default)]
+pub struct ReplayReport {
+    /**
+     * How many requests were sent
+     */
+    pub replayed: usize,
+    /**
+     * Requests that weren't sent because they were redacted or truncated in the capture
+     */
+    pub skipped: usize,
+    /**
+     * Differences between the captured and replayed responses, by request
+     */
+    pub differences: Vec<String>,
+}
+
+impl fmt::Display for ReplayReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for difference in &self.differences {
+            writeln!(f, "{difference}")?;
+        }
+        write!(
+            f,
+            "Replayed {} requests ({} skipped), {} differences",
+            self.replayed,
+            self.skipped,
+            self.differences.len()
+        )
+    }
+}
+
+/**
+ * Send the requests in a capture to an onboard socket in order, and compare each response to the
+ * captured one, ignoring fields that change from run to run. Requests are sent one at a time over a
+ * single connection.
+ *
+ * # Errors
+ * This function will return an error if the capture cannot be read, or the socket cannot be
+ * talked to.
+ */
+pub fn replay(capture: &Path, socket: &Path) -> Result<ReplayReport, Error> {
+    let mut requests = Vec::new();
+    let mut responses = HashMap::new();
+    for line in BufReader::new(File::open(capture)?).lines() {
+        let entry: Entry = serde_json::from_str(&line?)?;
+        let id = entry.payload.get("request_id").and_then(Value::as_u64);
+        match entry.direction {
+            Direction::Request => requests.push((entry.connection, entry.payload)),
+            Direction::Response => {
+                responses.insert((entry.connection, id), entry.payload);
+            }
+        }
+    }
+
+    let mut stream = UnixStream::connect(socket)?;
+    let mut report = ReplayReport::default();
+    for (request_id, (connection, mut request)) in (1u64..).zip(requests) {
+        let captured_id = request.get("request_id").and_then(Value::as_u64);
+        if is_elided(request.get("data").unwrap_or(&Value::Null)) {
+            report.skipped += 1;
+            continue;
+        }
+        request["request_id"] = Value::from(request_id);
+        write_frame(&mut stream, &request)?;
+        report.replayed += 1;
+
+        let response = loop {
+            let mut response: Value =
+                read_frame(&mut stream)?.ok_or_else(|| anyhow!("Backend disconnected"))?;
+            if response.get("request_id").and_then(Value::as_u64) == Some(request_id) {
+                sanitize(&mut response);
+                break response;
+            }
+        };
+
+        let label = format!(
+            "Request {} on connection {} ({})",
+            captured_id.unwrap_or_default(),
+            connection,
+            request.get("type").and_then(Value::as_str).unwrap_or("?")
+        );
+        let Some(mut expected) = responses.remove(&(connection, captured_id)) else {
+            report
+                .differences
+                .push(format!("{label}: no response was captured"));
+            continue;
+        };
+        expected["request_id"] = Value::from(request_id);
+        let mut differences = Vec::new();
+        diff("", &expected, &response, &mut differences);
+        report.differences.extend(
+            differences
+                .into_iter()
+                .map(|difference| format!("{label}: {difference}")),
+        );
+    }
+    Ok(report)
+}
+
+/**
+ * Whether a captured value was redacted or truncated, so it can't be compared or resent
+ */
+fn is_elided(value: &Value) -> bool {
+    value
+        .as_str()
+        .is_some_and(|s| s == REDACTION || s.starts_with(TRUNCATION))
+}
+
+/**
+ * Compare two JSON values structurally, ignoring volatile fields and values that were elided from
+ * the capture
+ */
+fn diff(path: &str, expected: &Value, actual: &Value, differences: &mut Vec<String>) {
+    if is_elided(expected) {
+        return;
+    }
+    match (expected, actual) {
+        (Value::Object(expected), Value::Object(actual)) => {
+            for (key, e) in expected {
+                if VOLATILE.contains(&key.as_str()) {
+                    continue;
+                }
+                match actual.get(key) {
+                    Some(a) => diff(&format!("{path}.{key}"), e, a, differences),
+                    None => differences.push(format!("{path}.{key} is missing")),
+                }
+            }
+            for key in actual.keys() {
+                if !expected.contains_key(key) && !VOLATILE.contains(&key.as_str()) {
+                    differences.push(format!("{path}.{key} is unexpected"));
+                }
+            }
+        }
+        (Value::Array(expected), Value::Array(actual)) => {
+            if expected.len() != actual.len() {
+                differences.push(format!(
+                    "{path} has {} items, expected {}",
+                    actual.len(),
+                    expected.len()
+                ));
+            }
+            for (i, (e, a)) in expected.iter().zip(actual).enumerate() {
+                diff(&format!("{path}[{i}]"), e, a, differences);
+            }
+        }
+        (expected, actual) if expected != actual => {
+            differences.push(format!("{path} is {actual}, expected {expected}"));
+        }
+        _ => {}
+    }
+}
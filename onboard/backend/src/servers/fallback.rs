@@ -0,0 +1,141 @@
+use crate::clock;
+use crate::env::{fallback_after, fallback_tty};
+use anyhow::Error;
+use lazy_static::lazy_static;
+use log::{log, Level};
+use std::fs::OpenOptions;
+use std::process::Stdio;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::process::{Child, Command};
+
+/**
+ * How often the frontend's absence is checked
+ */
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+lazy_static! {
+    static ref STATE: Mutex<State> = Mutex::new(State {
+        frontends: 0,
+        last_seen: clock::now(),
+        menu: None,
+    });
+}
+
+struct State {
+    /**
+     * How many frontends are connected, not counting the fallback menu
+     */
+    frontends: usize,
+    /**
+     * When a frontend was last connected (or when the backend started)
+     */
+    last_seen: Instant,
+    /**
+     * The running fallback menu, if any
+     */
+    menu: Option<Child>,
+}
+
+/**
+ * Tracks one connection to the onboard server. The fallback menu connects like any other frontend,
+ * so its own connection isn't counted.
+ */
+pub struct Connection {
+    counted: bool,
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        if self.counted {
+            let mut state = STATE.lock().unwrap();
+            state.frontends -= 1;
+            state.last_seen = clock::now();
+        }
+    }
+}
+
+/**
+ * Record that a client connected to the onboard server. The returned guard should be held until
+ * the client disconnects.
+ */
+#[must_use]
+pub fn connected(pid: Option<i32>) -> Connection {
+    let mut state = STATE.lock().unwrap();
+    let menu_pid = state
+        .menu
+        .as_ref()
+        .and_then(Child::id)
+        .and_then(|pid| i32::try_from(pid).ok());
+    let counted = pid.is_none() || pid != menu_pid;
+    if counted {
+        state.frontends += 1;
+        state.last_seen = clock::now();
+    }
+    Connection { counted }
+}
+
+/**
+ * Watch for the frontend disappearing. When the fallback menu is enabled and no frontend has been
+ * connected for long enough, the `devcade-fallback` binary next to the backend is started. It is
+ * stopped again as soon as a frontend connects.
+ *
+ * This function never returns and should be spawned as a task.
+ */
+pub async fn watch() -> ! {
+    let mut interval = clock::interval(CHECK_INTERVAL);
+    loop {
+        interval.tick().await;
+        let Some(after) = fallback_after() else {
+            continue;
+        };
+
+        let mut state = STATE.lock().unwrap();
+        // Forget a menu that exited on its own, so it's started again
+        if let Some(menu) = state.menu.as_mut() {
+            if let Ok(Some(status)) = menu.try_wait() {
+                log!(Level::Warn, "Fallback menu exited ({})", status);
+                state.menu = None;
+            }
+        }
+
+        if state.frontends > 0 {
+            if let Some(mut menu) = state.menu.take() {
+                log!(Level::Info, "Frontend connected, stopping fallback menu");
+                if let Err(e) = menu.start_kill() {
+                    log!(Level::Error, "Couldn't stop fallback menu: {}", e);
+                }
+            }
+        } else if state.menu.is_none() && clock::elapsed(state.last_seen) > after {
+            log!(
+                Level::Warn,
+                "No frontend for {} seconds, starting fallback menu",
+                clock::elapsed(state.last_seen).as_secs()
+            );
+            match spawn_menu() {
+                Ok(menu) => state.menu = Some(menu),
+                Err(e) => {
+                    log!(Level::Error, "Couldn't start fallback menu: {}", e);
+                    // Don't retry every second
+                    state.last_seen = clock::now();
+                }
+            }
+        }
+    }
+}
+
+/**
+ * Start the fallback menu on the configured terminal
+ */
+fn spawn_menu() -> Result<Child, Error> {
+    let exe = std::env::current_exe()?.with_file_name("devcade-fallback");
+    let mut command = Command::new(exe);
+    command.kill_on_drop(true);
+    if let Some(tty) = fallback_tty() {
+        let tty = OpenOptions::new().read(true).write(true).open(tty)?;
+        command
+            .stdin(Stdio::from(tty.try_clone()?))
+            .stdout(Stdio::from(tty));
+    }
+    Ok(command.spawn()?)
+}
@@ -0,0 +1,235 @@
+use crate::api;
+use crate::env::legacy_game_shape;
+use anyhow::Error;
+use devcade_onboard_types::schema::DevcadeGame;
+use devcade_onboard_types::{Dialect, Response, ResponseBody, PROTOCOL_VERSION};
+use log::{log, Level};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+/**
+ * The last protocol version that reads games in the legacy shape
+ */
+const LEGACY_VERSION: u32 = 1;
+
+/**
+ * A game in the shape frontends from before the schema changed read. The field names and their
+ * order are part of the protocol, and must not change.
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Default, Eq, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct LegacyDevcadeGame {
+    pub id: String,
+    pub author: String,
+    pub upload_date: String,
+    pub name: String,
+    pub hash: String,
+    pub description: String,
+    pub icon_link: String,
+    pub banner_link: String,
+}
+
+impl LegacyDevcadeGame {
+    /**
+     * Convert a game to the legacy shape, with the URLs of its icon and banner, which older
+     * frontends download themselves
+     */
+    #[must_use]
+    pub fn new(game: &DevcadeGame, (icon_link, banner_link): (String, String)) -> Self {
+        Self {
+            id: game.id.clone(),
+            author: game.author.clone(),
+            upload_date: game.upload_date.clone(),
+            name: game.name.clone(),
+            hash: game.hash.clone(),
+            description: game.description.clone(),
+            icon_link,
+            banner_link,
+        }
+    }
+}
+
+impl From<&DevcadeGame> for LegacyDevcadeGame {
+    fn from(game: &DevcadeGame) -> Self {
+        let links = api::art_urls(game.id.as_str());
+        Self::new(game, links)
+    }
+}
+
+/**
+ * The responses that carry games, with them in the legacy shape. Tagged the same way as
+ * `ResponseBody`.
+ */
+#[derive(Serialize)]
+#[serde(tag = "type", content = "data")]
+enum LegacyBody {
+    GameList(Vec<LegacyDevcadeGame>),
+    Game(LegacyDevcadeGame),
+}
+
+#[derive(Serialize)]
+struct LegacyResponse {
+    request_id: u32,
+    #[serde(flatten)]
+    body: LegacyBody,
+}
+
+/**
+ * Get the protocol version a connection uses when its client speaks `requested`
+ */
+#[must_use]
+pub fn negotiated(requested: u32) -> u32 {
+    requested.clamp(LEGACY_VERSION, PROTOCOL_VERSION)
+}
+
+/**
+ * Get the body of a response with its games in the legacy shape, or `None` if it has none
+ */
+fn legacy_body(
+    body: &ResponseBody,
+    to_legacy: impl Fn(&DevcadeGame) -> LegacyDevcadeGame,
+) -> Option<LegacyBody> {
+    match body {
+        ResponseBody::GameList(games) => {
+            Some(LegacyBody::GameList(games.iter().map(to_legacy).collect()))
+        }
+        ResponseBody::Game(game) => Some(LegacyBody::Game(to_legacy(game))),
+        _ => None,
+    }
+}
+
+/**
+ * The protocol version a client of the onboard server negotiated
+ */
+pub struct Connection {
+    version: AtomicU32,
+    /// Whether the deprecation warning was logged for it yet
+    warned: AtomicBool,
+}
+
+impl Default for Connection {
+    /**
+     * A connection that hasn't negotiated, which reads the legacy shape if
+     * `DEVCADE_LEGACY_GAME_SHAPE` is on
+     */
+    fn default() -> Self {
+        Self {
+            version: AtomicU32::new(if legacy_game_shape() {
+                LEGACY_VERSION
+            } else {
+                PROTOCOL_VERSION
+            }),
+            warned: AtomicBool::new(false),
+        }
+    }
+}
+
+impl Connection {
+    /**
+     * Use the version the client speaks from now on, or this backend's if the client's is newer
+     */
+    pub fn negotiate(&self, requested: u32) {
+        self.version.store(negotiated(requested), Ordering::Relaxed);
+    }
+
+    /**
+     * Frame a response for the client, with its games in the legacy shape if that's what the
+     * client reads. The first time it does, a deprecation warning is logged, so it's known when
+     * the legacy shape is finally unused.
+     *
+     * # Errors
+     * This function will return an error if the response is too large for a frame.
+     */
+    pub fn frame(&self, dialect: Dialect, response: &Response) -> Result<Vec<u8>, Error> {
+        if self.version.load(Ordering::Relaxed) > LEGACY_VERSION {
+            return dialect.frame(response);
+        }
+        let Some(body) = legacy_body(&response.body, |game| LegacyDevcadeGame::from(game)) else {
+            return dialect.frame(response);
+        };
+        if !self.warned.swap(true, Ordering::Relaxed) {
+            log!(
+                Level::Warn,
+                "A client is reading games in the legacy shape, which is deprecated. Upgrade its frontend."
+            );
+        }
+        dialect.frame(&LegacyResponse {
+            request_id: response.request_id,
+            body,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /**
+     * A game list response as the old frontend received it
+     */
+    const CAPTURED: &str = r#"{"request_id":7,"type":"GameList","data":[{"id":"5b2f1c9e-3a4d-4e8b-9f61-2d7c0a1e4b3f","author":"ella","uploadDate":"2023-02-11T19:42:07.123Z","name":"Bomberman","hash":"9a0364b9e99bb480dd25e1f0284c8555","description":"Blow up your friends","iconLink":"https://devcade-api.example/games/5b2f1c9e-3a4d-4e8b-9f61-2d7c0a1e4b3f/icon","bannerLink":"https://devcade-api.example/games/5b2f1c9e-3a4d-4e8b-9f61-2d7c0a1e4b3f/banner"}]}"#;
+
+    fn links(id: &str) -> (String, String) {
+        (
+            format!("https://devcade-api.example/games/{id}/icon"),
+            format!("https://devcade-api.example/games/{id}/banner"),
+        )
+    }
+
+    #[test]
+    fn legacy_games_match_captured_payload() {
+        let game = DevcadeGame {
+            id: "5b2f1c9e-3a4d-4e8b-9f61-2d7c0a1e4b3f".to_string(),
+            author: "ella".to_string(),
+            upload_date: "2023-02-11T19:42:07.123Z".to_string(),
+            name: "Bomberman".to_string(),
+            hash: "9a0364b9e99bb480dd25e1f0284c8555".to_string(),
+            description: "Blow up your friends".to_string(),
+            ..DevcadeGame::default()
+        };
+        let body = legacy_body(&ResponseBody::GameList(vec![game]), |game| {
+            LegacyDevcadeGame::new(game, links(game.id.as_str()))
+        })
+        .unwrap();
+        let response = LegacyResponse {
+            request_id: 7,
+            body,
+        };
+        assert_eq!(serde_json::to_string(&response).unwrap(), CAPTURED);
+
+        // What the old frontend sent back reads and writes the same
+        let captured: serde_json::Value = serde_json::from_str(CAPTURED).unwrap();
+        let games: Vec<LegacyDevcadeGame> =
+            serde_json::from_value(captured["data"].clone()).unwrap();
+        assert_eq!(serde_json::to_value(&games).unwrap(), captured["data"]);
+    }
+
+    #[test]
+    fn only_legacy_clients_get_the_legacy_shape() {
+        assert_eq!(negotiated(0), LEGACY_VERSION);
+        assert_eq!(negotiated(1), LEGACY_VERSION);
+        assert_eq!(negotiated(PROTOCOL_VERSION + 5), PROTOCOL_VERSION);
+
+        let connection = Connection::default();
+        connection.negotiate(PROTOCOL_VERSION);
+        let response = Response {
+            request_id: 1,
+            body: ResponseBody::Game(DevcadeGame::default()),
+        };
+        let framed = connection.frame(Dialect::Legacy, &response).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&framed).unwrap();
+        assert!(json["data"].get("upload_date").is_some());
+        assert!(json["data"].get("iconLink").is_none());
+
+        // Responses without games are the same either way
+        connection.negotiate(LEGACY_VERSION);
+        let response = Response {
+            request_id: 2,
+            body: ResponseBody::Negotiated(LEGACY_VERSION),
+        };
+        let framed = connection.frame(Dialect::Legacy, &response).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&framed).unwrap();
+        assert_eq!(json["type"], "Negotiated");
+        assert!(!connection.warned.load(Ordering::Relaxed));
+    }
+}
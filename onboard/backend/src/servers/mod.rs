@@ -1,12 +1,18 @@
 use anyhow::anyhow;
+use devcade_onboard_types::{
+    Dialect, ProtocolStats, Request, Response, ResponseBody, Value, MAX_FRAME_SIZE,
+};
 use futures_util::future;
 use futures_util::FutureExt;
 use log::{log, Level};
 use std::fs::remove_file;
 use std::future::Future;
 use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use tokio::io::{AsyncBufReadExt, BufReader, Lines, ReadHalf, WriteHalf};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader, ReadHalf, WriteHalf};
+use tokio::net::unix::UCred;
 use tokio::net::{UnixListener, UnixStream};
 use tokio::task;
 use tokio::task::JoinError;
@@ -15,14 +21,14 @@ use tokio::task::JoinError;
  * Module for getting the paths to the pipes that the servers use to communicate
  */
 pub mod path {
-    use crate::env::devcade_path;
+    use crate::layout;
 
     /**
      * Get the path to the pipe that the frontend will write to
      */
     #[must_use]
     pub fn onboard_pipe() -> String {
-        format!("{}/onboard.sock", devcade_path())
+        layout::onboard_socket().to_string_lossy().into_owned()
     }
 
     /**
@@ -30,7 +36,7 @@ pub mod path {
      * */
     #[must_use]
     pub fn persistence_pipe() -> String {
-        format!("{}/persistence.sock", devcade_path())
+        layout::persistence_socket().to_string_lossy().into_owned()
     }
 }
 
@@ -45,6 +51,27 @@ pub mod onboard;
  * */
 pub mod persistence;
 
+/**
+ * Decides which commands a client of the onboard server may send
+ */
+pub mod auth;
+
+/**
+ * The fallback menu is started when the frontend has been gone for too long
+ */
+pub mod fallback;
+
+/**
+ * Records commands sent to the onboard server for debugging, and replays them
+ */
+pub mod capture;
+
+/**
+ * Sends games in the shape older frontends read, to clients that negotiated the old protocol
+ * version
+ */
+pub mod legacy;
+
 /**
  * A struct to hold the handles to the threads spawned by the backend.
  */
@@ -151,9 +178,268 @@ impl Default for ThreadHandles {
     }
 }
 
+/**
+ * How long the rest of a frame may take to arrive once part of it has been read. A client that is
+ * killed mid-write leaves an unterminated frame behind, which is discarded after this long.
+ */
+const FRAME_TIMEOUT: Duration = Duration::from_secs(5);
+
+/**
+ * A frame read from a client
+ */
+#[derive(Debug)]
+pub enum Frame {
+    /**
+     * A complete frame, without the trailing newline
+     */
+    Complete(String),
+    /**
+     * A frame that was discarded because it was too large, timed out before it was terminated, or
+     * wasn't valid UTF-8. Contains the reason it was discarded.
+     */
+    Discarded(String),
+}
+
+/**
+ * Connections that used each dialect since startup
+ */
+static LEGACY_CONNECTIONS: AtomicU64 = AtomicU64::new(0);
+static FRAMED_CONNECTIONS: AtomicU64 = AtomicU64::new(0);
+
+/**
+ * Get how many connections used each dialect since startup, and whether the legacy one is still
+ * accepted
+ */
+pub fn protocol_stats() -> ProtocolStats {
+    ProtocolStats {
+        legacy_connections: LEGACY_CONNECTIONS.load(Ordering::Relaxed),
+        framed_connections: FRAMED_CONNECTIONS.load(Ordering::Relaxed),
+        legacy_allowed: crate::env::legacy_protocol(),
+    }
+}
+
+/**
+ * Reads frames from a client, in whichever dialect (see `devcade_onboard_types::Dialect`) its first
+ * byte says it speaks. Oversized and unterminated frames are discarded so a misbehaving client
+ * can't stop later frames from being read.
+ */
+pub struct FrameReader<R> {
+    reader: BufReader<R>,
+    dialect: Option<Dialect>,
+    /// Done reading, after turning away a client that speaks the legacy dialect while it's turned
+    /// off, or losing track of where a length-prefixed frame ends
+    refused: bool,
+    buf: Vec<u8>,
+    /// The bytes left of the current framed frame, once its length has been read
+    remaining: Option<usize>,
+    oversized: bool,
+}
+
+/**
+ * Read part of a newline-delimited frame, returning how many bytes were used and whether the frame
+ * is complete
+ */
+fn legacy_chunk(available: &[u8], buf: &mut Vec<u8>, oversized: &mut bool) -> (usize, bool) {
+    let (chunk, complete) = match available.iter().position(|&b| b == b'\n') {
+        Some(i) => (&available[..i], true),
+        None => (available, false),
+    };
+    if buf.len() + chunk.len() >= MAX_FRAME_SIZE {
+        *oversized = true;
+        buf.clear();
+    } else if !*oversized {
+        buf.extend_from_slice(chunk);
+    }
+    (chunk.len() + usize::from(complete), complete)
+}
+
+/**
+ * Read part of a length-prefixed frame, returning how many bytes were used and whether the frame
+ * is complete. Until the length has been read, `buf` holds the part of it that was.
+ */
+fn framed_chunk(
+    available: &[u8],
+    buf: &mut Vec<u8>,
+    remaining: &mut Option<usize>,
+    oversized: &mut bool,
+) -> (usize, bool) {
+    let Some(left) = *remaining else {
+        let used = (4 - buf.len()).min(available.len());
+        buf.extend_from_slice(&available[..used]);
+        let Ok(length) = <[u8; 4]>::try_from(buf.as_slice()) else {
+            return (used, false);
+        };
+        let length = u32::from_be_bytes(length) as usize;
+        buf.clear();
+        *oversized = length + 4 > MAX_FRAME_SIZE;
+        *remaining = (length > 0).then_some(length);
+        return (used, length == 0);
+    };
+    let used = left.min(available.len());
+    if !*oversized {
+        buf.extend_from_slice(&available[..used]);
+    }
+    let complete = used == left;
+    *remaining = (!complete).then_some(left - used);
+    (used, complete)
+}
+
+impl<R: AsyncRead + Unpin> FrameReader<R> {
+    /**
+     * Create a new frame reader reading from the given reader
+     */
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader: BufReader::new(reader),
+            dialect: None,
+            refused: false,
+            buf: Vec::new(),
+            remaining: None,
+            oversized: false,
+        }
+    }
+
+    /**
+     * The dialect the client speaks, which responses should be written in. Until the client has
+     * sent anything, it's the legacy one.
+     */
+    pub fn dialect(&self) -> Dialect {
+        self.dialect.unwrap_or_default()
+    }
+
+    /**
+     * Read the next frame. Returns `None` once the client has disconnected, after telling a client
+     * that speaks the legacy dialect it's turned off, or after a length-prefixed frame timed out.
+     *
+     * # Errors
+     * This function will return an error if reading from the client fails.
+     */
+    pub async fn next_frame(&mut self) -> Result<Option<Frame>, std::io::Error> {
+        if self.refused {
+            return Ok(None);
+        }
+        loop {
+            let mid_frame = !self.buf.is_empty() || self.remaining.is_some() || self.oversized;
+            let available = if mid_frame {
+                match crate::clock::timeout(FRAME_TIMEOUT, self.reader.fill_buf()).await {
+                    Ok(available) => available?,
+                    Err(_) => {
+                        // The rest of a length-prefixed frame would be read as the next one
+                        self.refused = self.dialect == Some(Dialect::Framed);
+                        return Ok(Some(self.discard("Timed out waiting for end of frame")));
+                    }
+                }
+            } else {
+                self.reader.fill_buf().await?
+            };
+
+            if available.is_empty() {
+                // The client disconnected, anything left over is a truncated frame
+                return Ok(if mid_frame {
+                    Some(self.discard("Client disconnected mid-frame"))
+                } else {
+                    None
+                });
+            }
+
+            let dialect = match self.dialect {
+                Some(dialect) => dialect,
+                None => {
+                    let dialect = if available[0] == 0 {
+                        FRAMED_CONNECTIONS.fetch_add(1, Ordering::Relaxed);
+                        Dialect::Framed
+                    } else {
+                        LEGACY_CONNECTIONS.fetch_add(1, Ordering::Relaxed);
+                        log::warn!(
+                            "A client sent newline-delimited JSON, which a future release will \
+                             stop accepting"
+                        );
+                        Dialect::Legacy
+                    };
+                    self.dialect = Some(dialect);
+                    if dialect == Dialect::Legacy && !crate::env::legacy_protocol() {
+                        self.refused = true;
+                        return Ok(Some(Frame::Discarded(String::from(
+                            "Newline-delimited JSON is turned off, send length-prefixed frames",
+                        ))));
+                    }
+                    dialect
+                }
+            };
+            let (consumed, complete) = match dialect {
+                Dialect::Legacy => legacy_chunk(available, &mut self.buf, &mut self.oversized),
+                Dialect::Framed => framed_chunk(
+                    available,
+                    &mut self.buf,
+                    &mut self.remaining,
+                    &mut self.oversized,
+                ),
+            };
+            self.reader.consume(consumed);
+
+            if complete {
+                if self.oversized {
+                    return Ok(Some(self.discard("Frame is too large")));
+                }
+                let frame = std::mem::take(&mut self.buf);
+                return Ok(Some(match String::from_utf8(frame) {
+                    Ok(frame) => Frame::Complete(frame),
+                    Err(_) => Frame::Discarded(String::from("Frame is not valid UTF-8")),
+                }));
+            }
+        }
+    }
+
+    fn discard(&mut self, reason: &str) -> Frame {
+        log::warn!(
+            "Discarding frame ({} bytes buffered): {}",
+            self.buf.len(),
+            reason
+        );
+        self.buf.clear();
+        self.remaining = None;
+        self.oversized = false;
+        Frame::Discarded(String::from(reason))
+    }
+}
+
+/**
+ * Parse a request from a frame. If the frame can't be parsed, returns the error response that
+ * should be sent back instead. When the frame is valid JSON with a request id, the error response
+ * uses that id so the client can match it up.
+ */
+pub fn parse_request(frame: &Frame) -> Result<Request, Box<Response>> {
+    let (request_id, reason) = match frame {
+        Frame::Complete(line) => match serde_json::from_str::<Request>(line) {
+            Ok(request) => return Ok(request),
+            Err(e) => {
+                log::warn!("Couldn't parse request: {}", e);
+                (
+                    serde_json::from_str::<Value>(line)
+                        .ok()
+                        .and_then(|v| v.get("request_id")?.as_u64())
+                        .and_then(|id| u32::try_from(id).ok())
+                        .unwrap_or(0),
+                    format!("Couldn't parse request: {e}"),
+                )
+            }
+        },
+        Frame::Discarded(reason) => (0, reason.clone()),
+    };
+    Err(Box::new(Response {
+        request_id,
+        body: ResponseBody::Err(format!("Invalid frame: {reason}")),
+    }))
+}
+
+/**
+ * How long to stop accepting connections when out of file descriptors
+ */
+const ACCEPT_PAUSE: std::time::Duration = std::time::Duration::from_millis(500);
+
 pub async fn open_server<'a, T, U>(path: &str, handle_client: T) -> !
 where
-    T: (Fn(Lines<BufReader<ReadHalf<UnixStream>>>, WriteHalf<UnixStream>) -> U)
+    T: (Fn(FrameReader<ReadHalf<UnixStream>>, WriteHalf<UnixStream>, Option<UCred>) -> U)
         + Send
         + Sync
         + 'a + 'static,
@@ -163,13 +449,23 @@ where
     let handle_client = Arc::new(handle_client);
 
     let mut handles = vec![];
-    while let Ok((stream, _address)) = listener.accept().await {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _address)) => stream,
+            // Running out of file descriptors passes once some are closed, so keep serving
+            Err(e) if crate::fds::is_exhaustion(&e) => {
+                log::warn!("Couldn't accept connection on {path}, out of file descriptors");
+                crate::clock::sleep(ACCEPT_PAUSE).await;
+                continue;
+            }
+            Err(_) => break,
+        };
         let handle_client = handle_client.clone();
         handles.push(task::spawn(async move {
+            let peer = stream.peer_cred().ok();
             let (reader, writer) = tokio::io::split(stream);
-            let reader = BufReader::new(reader);
 
-            match handle_client(reader.lines(), writer).await {
+            match handle_client(FrameReader::new(reader), writer, peer).await {
                 Ok(()) => log::info!("Finished handling connections from client"),
                 Err(err) => log::error!("Finished handling connections from client: {:?}", err),
             }
@@ -203,3 +499,328 @@ fn bind_listener(path: &str) -> Result<UnixListener, anyhow::Error> {
         },
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_request, Frame, FrameReader, FRAME_TIMEOUT};
+    use crate::clock;
+    use devcade_onboard_types::{
+        to_frame, Dialect, Request, RequestBody, Response, ResponseBody, MAX_FRAME_SIZE,
+    };
+    use std::collections::VecDeque;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use tokio::io::{AsyncRead, AsyncWriteExt, ReadBuf};
+
+    /**
+     * A client that delivers its bytes in the given writes, then disconnects
+     */
+    struct Writes(VecDeque<Vec<u8>>);
+
+    impl AsyncRead for Writes {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<std::io::Result<()>> {
+            // An empty read means the client disconnected, so empty writes are skipped
+            while self.0.front().is_some_and(Vec::is_empty) {
+                self.0.pop_front();
+            }
+            if let Some(mut write) = self.0.pop_front() {
+                if write.len() > buf.remaining() {
+                    let rest = write.split_off(buf.remaining());
+                    self.0.push_front(rest);
+                }
+                buf.put_slice(&write);
+            }
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    /**
+     * Tiny xorshift so the split points are random-looking but the same on every run
+     */
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: usize) -> usize {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            (self.0 % n as u64) as usize
+        }
+    }
+
+    fn split_at(bytes: &[u8], points: &[usize]) -> Writes {
+        let mut writes = VecDeque::new();
+        let mut start = 0;
+        for &point in points {
+            writes.push_back(bytes[start..point].to_vec());
+            start = point;
+        }
+        writes.push_back(bytes[start..].to_vec());
+        Writes(writes)
+    }
+
+    fn random_writes(bytes: &[u8], rng: &mut Rng) -> Writes {
+        let mut points: Vec<usize> = (0..rng.below(8)).map(|_| rng.below(bytes.len())).collect();
+        points.sort_unstable();
+        split_at(bytes, &points)
+    }
+
+    async fn read_all<R: AsyncRead + Unpin>(mut frames: FrameReader<R>) -> Vec<Frame> {
+        let mut all = Vec::new();
+        while let Some(frame) = frames.next_frame().await.unwrap() {
+            all.push(frame);
+        }
+        all
+    }
+
+    fn requests() -> Vec<Request> {
+        vec![
+            Request {
+                request_id: 1,
+                body: RequestBody::Ping,
+            },
+            Request {
+                request_id: 2,
+                body: RequestBody::GetGame(String::from("b0a4ebd8-🕹-\n\"}")),
+            },
+            Request {
+                request_id: u32::MAX,
+                body: RequestBody::SetLogLevel(Some(String::from("backend")), None, Some(60)),
+            },
+        ]
+    }
+
+    fn complete(frame: &Frame) -> &str {
+        match frame {
+            Frame::Complete(line) => line,
+            Frame::Discarded(reason) => panic!("Frame was discarded: {reason}"),
+        }
+    }
+
+    fn discarded(frame: &Frame) -> &str {
+        match frame {
+            Frame::Complete(line) => panic!("Frame wasn't discarded: {line}"),
+            Frame::Discarded(reason) => reason,
+        }
+    }
+
+    #[tokio::test]
+    async fn split_writes() {
+        let frame = to_frame(&requests()[1]).unwrap();
+        for a in 0..=frame.len() {
+            for b in a..=frame.len() {
+                let frames = read_all(FrameReader::new(split_at(&frame, &[a, b]))).await;
+                assert_eq!(frames.len(), 1, "split at {a} and {b}");
+                assert_eq!(complete(&frames[0]).as_bytes(), &frame[..frame.len() - 1]);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn round_trip() {
+        let requests = requests();
+        let bytes: Vec<u8> = requests.iter().flat_map(|r| to_frame(r).unwrap()).collect();
+        let mut rng = Rng(0x5eed);
+        for _ in 0..500 {
+            let frames = read_all(FrameReader::new(random_writes(&bytes, &mut rng))).await;
+            assert_eq!(frames.len(), requests.len());
+            for (frame, request) in frames.iter().zip(&requests) {
+                let parsed = parse_request(frame).unwrap();
+                assert_eq!(to_frame(&parsed).unwrap(), to_frame(request).unwrap());
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn interleaved_garbage() {
+        let garbage: [&[u8]; 4] = [b"not json\n", b"\xff\xfe\n", b"{\"request_id\": 7\n", b"\n"];
+        let requests = requests();
+        let mut rng = Rng(0xdecade);
+        for _ in 0..200 {
+            let mut bytes = Vec::new();
+            let mut expected = Vec::new();
+            for _ in 0..10 {
+                if rng.below(2) == 0 {
+                    let request = &requests[rng.below(requests.len())];
+                    bytes.extend(to_frame(request).unwrap());
+                    expected.push(Some(request.request_id));
+                } else {
+                    bytes.extend(garbage[rng.below(garbage.len())]);
+                    expected.push(None);
+                }
+            }
+            let frames = read_all(FrameReader::new(random_writes(&bytes, &mut rng))).await;
+            let ids: Vec<_> = frames
+                .iter()
+                .map(|frame| parse_request(frame).ok().map(|r| r.request_id))
+                .collect();
+            assert_eq!(ids, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn invalid_frames_keep_request_id() {
+        let frames = read_all(FrameReader::new(split_at(
+            b"{\"request_id\": 7, \"Bogus\": 1}\n\xff\n",
+            &[],
+        )))
+        .await;
+        assert_eq!(parse_request(&frames[0]).unwrap_err().request_id, 7);
+        assert_eq!(discarded(&frames[1]), "Frame is not valid UTF-8");
+        assert_eq!(parse_request(&frames[1]).unwrap_err().request_id, 0);
+    }
+
+    #[tokio::test]
+    async fn oversized_frames() {
+        let ping = to_frame(&requests()[0]).unwrap();
+        let mut rng = Rng(0xb16);
+        // The limit includes the newline, so the largest frame that fits is one byte shorter
+        for (len, fits) in [(MAX_FRAME_SIZE - 1, true), (MAX_FRAME_SIZE, false)] {
+            let mut bytes = vec![b'x'; len];
+            bytes.push(b'\n');
+            bytes.extend(&ping);
+            for _ in 0..5 {
+                let frames = read_all(FrameReader::new(random_writes(&bytes, &mut rng))).await;
+                assert_eq!(frames.len(), 2);
+                if fits {
+                    assert_eq!(complete(&frames[0]).len(), len);
+                } else {
+                    assert_eq!(discarded(&frames[0]), "Frame is too large");
+                }
+                assert_eq!(parse_request(&frames[1]).unwrap().request_id, 1);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn truncated_frame_at_disconnect() {
+        let ping = to_frame(&requests()[0]).unwrap();
+        let mut bytes = ping.clone();
+        bytes.extend(&ping[..ping.len() / 2]);
+        let frames = read_all(FrameReader::new(split_at(&bytes, &[]))).await;
+        assert_eq!(frames.len(), 2);
+        assert_eq!(parse_request(&frames[0]).unwrap().request_id, 1);
+        assert_eq!(discarded(&frames[1]), "Client disconnected mid-frame");
+    }
+
+    #[tokio::test]
+    async fn unterminated_frame_then_valid_frame() {
+        let (mut client, server) = tokio::io::duplex(64);
+        let mut frames = FrameReader::new(server);
+        let ping = to_frame(&requests()[0]).unwrap();
+        client.write_all(&ping[..ping.len() / 2]).await.unwrap();
+
+        clock::freeze();
+        let reading = tokio::spawn(async move {
+            let frame = frames.next_frame().await.unwrap().unwrap();
+            (frames, frame)
+        });
+        while !reading.is_finished() {
+            tokio::task::yield_now().await;
+            clock::advance(FRAME_TIMEOUT);
+        }
+        clock::unfreeze();
+        let (mut frames, frame) = reading.await.unwrap();
+        assert_eq!(discarded(&frame), "Timed out waiting for end of frame");
+
+        client.write_all(&ping).await.unwrap();
+        let frame = frames.next_frame().await.unwrap().unwrap();
+        assert_eq!(parse_request(&frame).unwrap().request_id, 1);
+        drop(client);
+        assert!(frames.next_frame().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn framed_split_writes_and_oversized_frames() {
+        let frame = Dialect::Framed.frame(&requests()[1]).unwrap();
+        for a in 0..=frame.len() {
+            for b in a..=frame.len() {
+                let frames = read_all(FrameReader::new(split_at(&frame, &[a, b]))).await;
+                assert_eq!(frames.len(), 1, "split at {a} and {b}");
+                assert_eq!(complete(&frames[0]).as_bytes(), &frame[4..]);
+            }
+        }
+
+        // The limit includes the length, and a frame over it is skipped whole
+        let ping = Dialect::Framed.frame(&requests()[0]).unwrap();
+        let mut rng = Rng(0xf4a3);
+        for (len, fits) in [(MAX_FRAME_SIZE - 4, true), (MAX_FRAME_SIZE - 3, false)] {
+            let mut bytes = (len as u32).to_be_bytes().to_vec();
+            bytes.extend(vec![b'x'; len]);
+            bytes.extend(&ping);
+            for _ in 0..5 {
+                let frames = read_all(FrameReader::new(random_writes(&bytes, &mut rng))).await;
+                assert_eq!(frames.len(), 2);
+                if fits {
+                    assert_eq!(complete(&frames[0]).len(), len);
+                } else {
+                    assert_eq!(discarded(&frames[0]), "Frame is too large");
+                }
+                assert_eq!(parse_request(&frames[1]).unwrap().request_id, 1);
+            }
+        }
+    }
+
+    /**
+     * Send every message through a reader in both dialects, and check each comes out as it went in
+     */
+    async fn conformance<T: serde::Serialize>(
+        messages: &[T],
+        parse: impl Fn(&Frame) -> serde_json::Value,
+    ) {
+        let mut rng = Rng(0xd1a1);
+        let expected: Vec<serde_json::Value> = messages
+            .iter()
+            .map(|message| serde_json::to_value(message).unwrap())
+            .collect();
+        for dialect in [Dialect::Legacy, Dialect::Framed] {
+            let bytes: Vec<u8> = messages
+                .iter()
+                .flat_map(|message| dialect.frame(message).unwrap())
+                .collect();
+            let mut frames = FrameReader::new(random_writes(&bytes, &mut rng));
+            let mut parsed = Vec::new();
+            while let Some(frame) = frames.next_frame().await.unwrap() {
+                parsed.push(parse(&frame));
+            }
+            assert_eq!(frames.dialect(), dialect);
+            assert_eq!(parsed, expected, "{dialect:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn every_message_round_trips_through_both_dialects() {
+        let requests: Vec<Request> = (1..)
+            .zip(RequestBody::variants())
+            .map(|(request_id, body)| Request { request_id, body })
+            .collect();
+        conformance(&requests, |frame| {
+            serde_json::to_value(parse_request(frame).unwrap()).unwrap()
+        })
+        .await;
+
+        // Not `ResponseBody::variants`, which starts a thread that exits the process
+        let responses: Vec<Response> = (1..)
+            .zip([
+                ResponseBody::Ok,
+                ResponseBody::Err(String::from("Couldn't reach the API:\n\"timeout\"")),
+                ResponseBody::PermissionDenied(String::from("Launch Game requires role operator")),
+                ResponseBody::RateLimited(250),
+                ResponseBody::Object(String::from("{\"🕹\": null}")),
+                ResponseBody::OrphanedGames(vec![String::from("pong"), String::from("snake")]),
+                ResponseBody::CabinetInfo(Default::default()),
+                ResponseBody::DownloadQueue(Default::default()),
+            ])
+            .map(|(request_id, body)| Response { request_id, body })
+            .collect();
+        conformance(&responses, |frame| {
+            let response: Response = serde_json::from_str(complete(frame)).unwrap();
+            serde_json::to_value(response).unwrap()
+        })
+        .await;
+    }
+}
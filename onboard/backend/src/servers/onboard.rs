@@ -1,6 +1,9 @@
+use crate::boot;
 use crate::command::handle;
-use crate::servers::open_server;
-use devcade_onboard_types::{Request, RequestBody, Response};
+use crate::faults::{self, site};
+use crate::servers::capture::{self, Direction};
+use crate::servers::{auth, fallback, legacy, open_server, parse_request};
+use devcade_onboard_types::{RequestBody, Response, ResponseBody};
 use futures_util::future;
 use log::{log, Level};
 use std::sync::Arc;
@@ -24,11 +27,46 @@ pub async fn main(command_pipe: &str) -> ! {
 
     log!(Level::Debug, "Opened command pipe at {}", command_pipe_path);
 
-    open_server(command_pipe_path, async move |mut lines, writer| {
+    open_server(command_pipe_path, async move |mut frames, writer, peer| {
+        // Held for as long as the client is connected, so the fallback menu knows a frontend is up
+        let _frontend = fallback::connected(peer.and_then(|peer| peer.pid()));
+        let role = auth::resolve_role(peer);
+        let connection = capture::connection_id();
+        let protocol = Arc::new(legacy::Connection::default());
+        log!(
+            Level::Debug,
+            "Client {:?} connected with role {:?}",
+            peer,
+            role
+        );
         let writer = Arc::new(Mutex::new(writer));
         let mut handles = vec![];
-        while let Some(line) = lines.next_line().await? {
-            let command: Request = serde_json::from_str(&line)?;
+        while let Some(frame) = frames.next_frame().await? {
+            let dialect = frames.dialect();
+            let command = match parse_request(&frame) {
+                Ok(command) => command,
+                Err(response) => {
+                    log::debug!("Sending: {response}");
+                    if capture::enabled() {
+                        capture::record(connection, Direction::Response, &response);
+                    }
+                    writer
+                        .lock()
+                        .await
+                        .write_all(&dialect.frame(&response)?)
+                        .await?;
+                    continue;
+                }
+            };
+
+            if capture::enabled() {
+                capture::record(connection, Direction::Request, &command);
+            }
+
+            // Applied before anything else is handled, so responses to later requests use it
+            if let RequestBody::Negotiate(version) = &command.body {
+                protocol.negotiate(*version);
+            }
 
             if let RequestBody::Ping = &command.body {
                 log!(Level::Trace, "Handling command: {}", command);
@@ -37,16 +75,30 @@ pub async fn main(command_pipe: &str) -> ! {
             }
 
             let writer = writer.clone();
+            let protocol = protocol.clone();
 
             handles.push(task::spawn(async move {
-                let body = handle(command.body).await;
+                let required = auth::required_role(&command.body);
+                let body = if boot::safe_mode() && !boot::allowed(&command.body) {
+                    ResponseBody::Err(format!("{command} is unavailable in safe mode"))
+                } else if role.is_some_and(|role| role >= required) {
+                    match faults::check(site::IPC_ONBOARD) {
+                        Ok(()) => handle(command.body).await,
+                        Err(err) => err.into(),
+                    }
+                } else {
+                    auth::refuse(peer, role, &command);
+                    ResponseBody::PermissionDenied(format!("{command} requires role {required}"))
+                };
                 let response = Response {
                     request_id: command.request_id,
                     body,
                 };
                 log::debug!("Sending: {response}");
-                let mut response = serde_json::to_vec(&response)?;
-                response.push(b'\n');
+                if capture::enabled() {
+                    capture::record(connection, Direction::Response, &response);
+                }
+                let response = protocol.frame(dialect, &response)?;
 
                 let mut writer = writer.lock().await;
                 writer.write_all(&response).await?;
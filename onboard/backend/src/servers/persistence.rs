@@ -1,38 +1,272 @@
+use crate::api;
+use crate::clock;
 use crate::command::handle;
-use crate::servers::open_server;
+use crate::env::{ipc_burst, ipc_disconnect_after, ipc_rate};
+use crate::faults::{self, site};
+use crate::layout;
+use crate::servers::{open_server, parse_request};
 use anyhow::anyhow;
-use devcade_onboard_types::{Request, RequestBody, Response, ResponseBody};
+use devcade_onboard_types::{RequestBody, Response, ResponseBody};
 use futures_util::future;
 use lazy_static::lazy_static;
 use std::collections::{HashMap, HashSet};
-use std::path::Path;
+use std::mem::Discriminant;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use tokio::fs;
+use std::time::{Duration, Instant};
 use tokio::io::AsyncWriteExt;
 use tokio::sync::Mutex;
 use tokio::task;
 
+/**
+ * Internal module for how groups of saves are stored on disk, picked with `DEVCADE_SAVE_STORAGE`:
+ * one JSON file per group that's rewritten on every flush, or segments that flushes only append
+ * the changed keys to and that are merged in the background.
+ */
+mod storage;
+
+pub use storage::Backend;
+
+/**
+ * The namespace of saves that are only kept in memory, like those of demo guests. They're never
+ * flushed to storage, and are thrown away with `discard`.
+ */
+pub const EPHEMERAL_NAMESPACE: &str = "demo";
+
+/**
+ * The largest chunk that can be appended to a streamed save at once
+ */
+const MAX_CHUNK_SIZE: usize = 1024 * 1024;
+
+/**
+ * The largest value that can be saved with a streamed save
+ */
+const MAX_STREAMED_VALUE_SIZE: usize = 64 * 1024 * 1024;
+
+/**
+ * How long a streamed save can go without being appended to before it is discarded
+ */
+const STREAM_TIMEOUT: Duration = Duration::from_secs(60);
+
+/**
+ * A streamed save that hasn't been committed yet
+ */
+struct PendingSave {
+    group: String,
+    key: String,
+    value: String,
+    last_write: Instant,
+}
+
 lazy_static! {
-    // basically just checks if a user 'devcade' exists. If so, assumes that this is running on the
-    // machine, and saves to the homedir. Otherwise, saves to the cwd.
-    static ref ON_MACHINE: bool = Path::new("/home/devcade").exists();
     static ref DB: Mutex<HashMap<String, HashMap<String, String>>> = Mutex::new(HashMap::new());
-    static ref DB_MODIFIED: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+    // The keys changed since the last flush, by group
+    static ref DB_MODIFIED: Mutex<HashMap<String, HashSet<String>>> = Mutex::new(HashMap::new());
+    static ref STREAMS: Mutex<HashMap<String, PendingSave>> = Mutex::new(HashMap::new());
+    // Rate limiters by the PID of the game they limit, so reconnecting doesn't refill its buckets
+    static ref LIMITERS: std::sync::Mutex<HashMap<Option<i32>, RateLimiter>> =
+        std::sync::Mutex::new(HashMap::new());
+}
+
+static NEXT_STREAM_ID: AtomicU64 = AtomicU64::new(0);
+
+/**
+ * How many refused requests from one game are logged together, so a misbehaving game doesn't flood
+ * the log as well
+ */
+const REFUSALS_PER_LOG: u64 = 1000;
+
+/**
+ * How long refused requests are counted towards `DEVCADE_IPC_DISCONNECT_AFTER`
+ */
+const MISBEHAVIOR_WINDOW: Duration = Duration::from_secs(10);
+
+/**
+ * How long a game's rate limiter is kept after its last request
+ */
+const LIMITER_IDLE: Duration = Duration::from_secs(10 * 60);
+
+/**
+ * Token bucket for one type of request
+ */
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+/**
+ * Why a request was refused
+ */
+enum Refusal {
+    /// The game went over its rate, and can retry after a while
+    RetryAfter(Duration),
+    /// The game kept going over its rate without backing off, so it's disconnected
+    Disconnect,
+}
+
+/**
+ * Limits how fast a single game can send each type of request. Every request type gets its own
+ * bucket of `DEVCADE_IPC_BURST` requests, refilled at `DEVCADE_IPC_RATE` per second. The burst is
+ * large so games can load everything they need at startup. A game that has more than
+ * `DEVCADE_IPC_DISCONNECT_AFTER` requests refused within `MISBEHAVIOR_WINDOW` is disconnected.
+ */
+struct RateLimiter {
+    rate: f64,
+    burst: f64,
+    buckets: HashMap<Discriminant<RequestBody>, Bucket>,
+    refused: u64,
+    disconnect_after: u64,
+    /// When the current misbehavior window started, and the requests refused in it
+    window: (Instant, u64),
+    last_request: Instant,
+}
+
+impl RateLimiter {
+    fn new() -> Self {
+        Self {
+            rate: ipc_rate(),
+            burst: ipc_burst(),
+            buckets: HashMap::new(),
+            refused: 0,
+            disconnect_after: ipc_disconnect_after(),
+            window: (clock::now(), 0),
+            last_request: clock::now(),
+        }
+    }
+
+    /**
+     * Take a token for a request from the game with a PID (`None` if it isn't known), or refuse
+     * it. Limiters of games that stopped sending requests are forgotten.
+     */
+    fn check_peer(pid: Option<i32>, body: &RequestBody) -> Result<(), Refusal> {
+        let mut limiters = LIMITERS.lock().unwrap();
+        limiters.retain(|_, limiter| clock::elapsed(limiter.last_request) < LIMITER_IDLE);
+        limiters
+            .entry(pid)
+            .or_insert_with(RateLimiter::new)
+            .check(body)
+    }
+
+    /**
+     * Take a token for a request, or return how long until one is available
+     */
+    fn check(&mut self, body: &RequestBody) -> Result<(), Refusal> {
+        let now = clock::now();
+        self.last_request = now;
+        let bucket = self
+            .buckets
+            .entry(std::mem::discriminant(body))
+            .or_insert(Bucket {
+                tokens: self.burst,
+                last_refill: now,
+            });
+        let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.burst);
+        bucket.last_refill = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+
+        if self.refused.is_multiple_of(REFUSALS_PER_LOG) {
+            log::warn!(
+                "Game is sending too many requests, refused {} so far (latest: {})",
+                self.refused + 1,
+                body
+            );
+        }
+        self.refused += 1;
+        let retry_after = Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate);
+
+        if now.duration_since(self.window.0) > MISBEHAVIOR_WINDOW {
+            self.window = (now, 0);
+        }
+        self.window.1 += 1;
+        if self.window.1 > self.disconnect_after {
+            self.window = (now, 0);
+            return Err(Refusal::Disconnect);
+        }
+        Err(Refusal::RetryAfter(retry_after))
+    }
 }
 
 pub async fn main(command_pipe: &str) -> ! {
     log::info!("Starting save/load process");
     log::debug!("Opened command pipe at {}", command_pipe);
 
-    open_server(command_pipe, async move |mut lines, writer| {
+    open_server(command_pipe, async move |mut frames, writer, peer| {
         let writer = Arc::new(Mutex::new(writer));
         let mut handles = vec![];
+        let pid = peer.and_then(|peer| peer.pid());
         log::debug!("New client connected to persistence socket");
-        while let Some(line) = lines.next_line().await? {
-            let command: Request = serde_json::from_str(&line)?;
+        while let Some(frame) = frames.next_frame().await? {
+            let dialect = frames.dialect();
+            let command = match parse_request(&frame) {
+                Ok(command) => command,
+                Err(response) => {
+                    log::debug!("Sending: {response}");
+                    writer
+                        .lock()
+                        .await
+                        .write_all(&dialect.frame(&response)?)
+                        .await?;
+                    continue;
+                }
+            };
+
+            // Refused requests are answered right here, so a game spamming requests doesn't cost a
+            // task per request
+            match RateLimiter::check_peer(pid, &command.body) {
+                Ok(()) => {}
+                Err(Refusal::RetryAfter(retry_after)) => {
+                    let response = Response {
+                        request_id: command.request_id,
+                        body: ResponseBody::RateLimited(retry_after.as_millis() as u64),
+                    };
+                    writer
+                        .lock()
+                        .await
+                        .write_all(&dialect.frame(&response)?)
+                        .await?;
+                    continue;
+                }
+                Err(Refusal::Disconnect) => {
+                    log::warn!(
+                        "Disconnecting game (PID {:?}) for ignoring rate limits, {} requests \
+                        refused in {:?}",
+                        pid,
+                        ipc_disconnect_after(),
+                        MISBEHAVIOR_WINDOW
+                    );
+                    api::record_game_misbehavior();
+                    let response = Response {
+                        request_id: command.request_id,
+                        body: anyhow!("Disconnected for sending too many requests").into(),
+                    };
+                    writer
+                        .lock()
+                        .await
+                        .write_all(&dialect.frame(&response)?)
+                        .await?;
+                    break;
+                }
+            }
+            api::record_game_request(&command.body);
 
             match &command.body {
-                RequestBody::Save(_, _, _) | RequestBody::Load(_, _) | RequestBody::Flush => {
+                RequestBody::Save(_, _, _)
+                | RequestBody::Load(_, _)
+                | RequestBody::Flush
+                | RequestBody::BeginSave(_, _)
+                | RequestBody::AppendSave(_, _)
+                | RequestBody::CommitSave(_)
+                | RequestBody::LoadRange(_, _, _, _)
+                | RequestBody::SubmitSessionSummary(_)
+                | RequestBody::GetCabinetHardware
+                | RequestBody::GetCabinetSetting(_)
+                | RequestBody::SetCabinetSetting(_, _) => {
                     log::debug!("Handling command: {}", command);
                 }
                 RequestBody::Ping => {
@@ -47,10 +281,36 @@ pub async fn main(command_pipe: &str) -> ! {
 
             handles.push(task::spawn(async move {
                 let body: ResponseBody = match &command.body {
+                    _ if faults::check(site::IPC_PERSISTENCE).is_err() => {
+                        anyhow!(faults::InjectedFault {
+                            site: site::IPC_PERSISTENCE.to_string()
+                        })
+                        .into()
+                    }
                     RequestBody::Save(_, _, _)
                     | RequestBody::Load(_, _)
                     | RequestBody::Flush
+                    | RequestBody::BeginSave(_, _)
+                    | RequestBody::AppendSave(_, _)
+                    | RequestBody::CommitSave(_)
+                    | RequestBody::LoadRange(_, _, _, _)
+                    | RequestBody::SubmitSessionSummary(_)
+                    | RequestBody::GetCabinetHardware
+                    | RequestBody::GetCabinetSetting(_)
                     | RequestBody::Ping => handle(command.body).await,
+                    // Cabinet settings are shared by every game, so only games an operator trusts
+                    // with them can change them
+                    RequestBody::SetCabinetSetting(key, value) => {
+                        if api::current_game_may_write_cabinet_settings() {
+                            let writer = api::current_game().id;
+                            match api::set_cabinet_setting(key, value.as_deref(), writer.as_str()) {
+                                Ok(()) => ResponseBody::Ok,
+                                Err(err) => err.into(),
+                            }
+                        } else {
+                            anyhow!("Cabinet settings are read-only for this game").into()
+                        }
+                    }
                     // Don't allow game save/load to (for example) download a game, launch a game,
                     // etc. If games could launch other games, it would update the 'current game' in
                     // crate::api and allow games to corrupt other games' save data (possibly
@@ -62,8 +322,7 @@ pub async fn main(command_pipe: &str) -> ! {
                     body,
                 };
                 log::debug!("Sending: {response}");
-                let mut response = serde_json::to_vec(&response)?;
-                response.push(b'\n');
+                let response = dialect.frame(&response)?;
 
                 let mut writer = writer.lock().await;
                 writer.write_all(&response).await?;
@@ -91,7 +350,10 @@ pub async fn save(group: &str, key: &str, value: &str) -> Result<(), anyhow::Err
     let inner = get_submap_or_load(&mut data, full_key.clone()).await?;
 
     inner.insert(key.to_string(), value.to_string());
-    mod_list.insert(full_key);
+    mod_list
+        .entry(full_key)
+        .or_default()
+        .insert(key.to_string());
 
     Ok(())
 }
@@ -115,6 +377,108 @@ pub async fn load(group: &str, key: &str) -> Result<String, anyhow::Error> {
         .cloned()
 }
 
+/**
+ * Start a streamed save of a value that is too large to send in one request. Returns the ID of the
+ * stream, which chunks are appended to with `append_save`. The value is not visible until the
+ * stream is committed with `commit_save`, and uncommitted streams are discarded after a minute
+ * without being appended to.
+ * */
+pub async fn begin_save(group: &str, key: &str) -> Result<String, anyhow::Error> {
+    let mut streams = STREAMS.lock().await;
+    streams.retain(|id, stream| {
+        let expired = clock::elapsed(stream.last_write) > STREAM_TIMEOUT;
+        if expired {
+            log::warn!(
+                "Discarding uncommitted save stream {} to {}/{}",
+                id,
+                stream.group,
+                stream.key
+            );
+        }
+        !expired
+    });
+
+    let id = NEXT_STREAM_ID.fetch_add(1, Ordering::Relaxed).to_string();
+    log::trace!("beginning save stream {} to {}/{}", id, group, key);
+    streams.insert(
+        id.clone(),
+        PendingSave {
+            group: group.to_string(),
+            key: key.to_string(),
+            value: String::new(),
+            last_write: clock::now(),
+        },
+    );
+    Ok(id)
+}
+
+/**
+ * Append a chunk to a streamed save started with `begin_save`
+ * */
+pub async fn append_save(stream_id: &str, chunk: &str) -> Result<(), anyhow::Error> {
+    if chunk.len() > MAX_CHUNK_SIZE {
+        return Err(anyhow!(
+            "Chunk of {} bytes is larger than the maximum of {} bytes",
+            chunk.len(),
+            MAX_CHUNK_SIZE
+        ));
+    }
+
+    let mut streams = STREAMS.lock().await;
+    let stream = streams
+        .get_mut(stream_id)
+        .ok_or_else(|| anyhow!("No save stream with ID {}", stream_id))?;
+    if stream.value.len() + chunk.len() > MAX_STREAMED_VALUE_SIZE {
+        streams.remove(stream_id);
+        return Err(anyhow!(
+            "Streamed value is larger than the maximum of {} bytes",
+            MAX_STREAMED_VALUE_SIZE
+        ));
+    }
+    stream.value.push_str(chunk);
+    stream.last_write = clock::now();
+    Ok(())
+}
+
+/**
+ * Commit a streamed save, making the value visible to `load`
+ * */
+pub async fn commit_save(stream_id: &str) -> Result<(), anyhow::Error> {
+    let stream = STREAMS
+        .lock()
+        .await
+        .remove(stream_id)
+        .ok_or_else(|| anyhow!("No save stream with ID {}", stream_id))?;
+    save(
+        stream.group.as_str(),
+        stream.key.as_str(),
+        stream.value.as_str(),
+    )
+    .await
+}
+
+/**
+ * Load part of a value, for values too large to send in one response. `offset` and `len` are in
+ * bytes, and the range is cut short if it goes past the end of the value or ends partway through
+ * a character, so the next range can start where this one ended.
+ * */
+pub async fn load_range(
+    group: &str,
+    key: &str,
+    offset: usize,
+    len: usize,
+) -> Result<String, anyhow::Error> {
+    let value = load(group, key).await?;
+    let mut end = offset.saturating_add(len).min(value.len());
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+    value
+        .get(offset.min(end)..end)
+        .map(String::from)
+        .ok_or_else(|| anyhow!("Range {}..{} is not on a character boundary", offset, end))
+}
+
 /**
  * Flush all pending writes to the filesystem.
  * */
@@ -127,16 +491,24 @@ pub async fn flush() -> Result<(), anyhow::Error> {
         mod_list.len()
     );
 
-    for key in mod_list.iter() {
-        let inner = get_submap_or_load(&mut data, key.clone()).await?;
-        let file_name = format!("{}.save", key);
-        log::debug!("Flushing to {}", file_name);
-        let path = Path::new(&file_name);
-        let dir = path.parent().expect("path failed to have parents");
-        if !dir.exists() {
-            fs::create_dir_all(dir).await?;
+    faults::check(site::FS_SAVE)?;
+    let backend = Backend::configured();
+    for (group, keys) in mod_list.iter() {
+        if is_ephemeral(group) {
+            continue;
+        }
+        let inner = get_submap_or_load(&mut data, group.clone()).await?;
+        log::debug!("Flushing {} to {} storage", group, backend);
+        let path = PathBuf::from(group);
+        backend.flush(path.as_path(), inner, keys)?;
+        if backend.needs_compaction(path.as_path()) {
+            // Compaction only touches segments flushes are done with, so it doesn't hold the DB
+            task::spawn_blocking(move || {
+                if let Err(e) = storage::compact(path.as_path()) {
+                    log::warn!("Couldn't compact saves in {}: {}", path.display(), e);
+                }
+            });
         }
-        fs::write(path, serde_json::to_string(inner)?.as_bytes()).await?;
     }
 
     mod_list.clear();
@@ -154,18 +526,85 @@ pub async fn clear_db() -> Result<(), anyhow::Error> {
     log::info!("Flushing and clearing DB cache");
     flush().await?;
 
+    // Ephemeral saves only exist in memory, so they're kept until they're discarded
     let mut data = DB.lock().await;
-    data.clear();
+    data.retain(|group, _| is_ephemeral(group));
 
     Ok(())
 }
 
-fn from_group(group: &str) -> (String, String) {
-    let save_path = Path::new(if *ON_MACHINE {
+/**
+ * Throw away every save under a namespace, including unflushed changes and streamed saves that
+ * haven't been committed. Anything stored on disk under it is removed too.
+ *
+ * Returns how many groups were thrown away.
+ *
+ * # Errors
+ * This function will return an error if saves stored on disk under the namespace can't be removed.
+ */
+pub async fn discard(namespace: &str) -> Result<usize, anyhow::Error> {
+    let dir = save_root().join(namespace);
+    let prefix = format!("{}/", dir.display());
+    let mut data = DB.lock().await;
+    let mut mod_list = DB_MODIFIED.lock().await;
+
+    let before = data.len();
+    data.retain(|group, _| !group.starts_with(prefix.as_str()));
+    mod_list.retain(|group, _| !group.starts_with(prefix.as_str()));
+    let discarded = before - data.len();
+    STREAMS
+        .lock()
+        .await
+        .retain(|_, stream| !stream.group.starts_with(format!("{namespace}/").as_str()));
+
+    match std::fs::remove_dir_all(&dir) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    log::debug!(
+        "Discarded {} groups of saves under {}",
+        discarded,
+        namespace
+    );
+    Ok(discarded)
+}
+
+/**
+ * Move every group of saves to a storage backend. This has to be done while the backend isn't
+ * running.
+ *
+ * Returns how many groups were moved.
+ *
+ * # Errors
+ * This function will return an error if a group can't be moved. The groups moved before it stay
+ * moved.
+ */
+pub fn migrate(to: Backend) -> Result<usize, anyhow::Error> {
+    storage::migrate(save_root(), to)
+}
+
+/**
+ * Gets the directory all game saves are stored under
+ * */
+#[must_use]
+pub fn save_root() -> &'static Path {
+    Path::new(if layout::on_machine() {
         "/home/devcade/.save"
     } else {
         "./.save"
-    });
+    })
+}
+
+/**
+ * Whether a group (as a full path, like the DB is keyed by) is in `EPHEMERAL_NAMESPACE`
+ * */
+fn is_ephemeral(group: &str) -> bool {
+    Path::new(group).starts_with(save_root().join(EPHEMERAL_NAMESPACE))
+}
+
+fn from_group(group: &str) -> (String, String) {
+    let save_path = save_root();
 
     let mut parts: Vec<String> = group.split("/").map(|a| a.to_string()).collect();
     let group = parts.pop().unwrap_or(String::new());
@@ -181,16 +620,13 @@ async fn get_submap_or_load(
     db: &mut HashMap<String, HashMap<String, String>>,
     group: String,
 ) -> Result<&mut HashMap<String, String>, anyhow::Error> {
-    let file_name = format!("{}.save", group);
     if !db.contains_key(&group) {
-        if Path::new(&file_name).exists() {
-            let map = serde_json::from_str::<HashMap<String, String>>(
-                fs::read_to_string(file_name).await?.as_str(),
-            )?;
-            db.insert(group.clone(), map);
+        let map = if is_ephemeral(group.as_str()) {
+            HashMap::new()
         } else {
-            db.insert(group.clone(), HashMap::new());
-        }
+            Backend::configured().load(Path::new(&group))?
+        };
+        db.insert(group.clone(), map);
     }
     Ok(db.get_mut(&group).unwrap())
 }
@@ -203,3 +639,112 @@ pub async fn db_cache_size() -> usize {
     let data = DB.lock().await;
     data.values().map(|hm| hm.len()).sum()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use devcade_onboard_types::client::Client;
+    use std::os::unix::net::UnixStream;
+
+    #[tokio::test]
+    async fn streamed_values_round_trip_byte_exact() {
+        // Kept in memory only, so nothing is written to the save directory
+        let namespace = format!("{EPHEMERAL_NAMESPACE}/stream-test-{}", std::process::id());
+        let group = format!("{namespace}/replays");
+        let value = "replay frame é → 🎮\n".repeat(150_000);
+        assert!(value.len() > 3 * 1024 * 1024);
+
+        let stream = begin_save(group.as_str(), "last").await.unwrap();
+        let mut start = 0;
+        while start < value.len() {
+            let mut end = (start + 4096).min(value.len());
+            while !value.is_char_boundary(end) {
+                end -= 1;
+            }
+            append_save(stream.as_str(), &value[start..end])
+                .await
+                .unwrap();
+            start = end;
+        }
+        // Uncommitted streams are invisible
+        assert!(load(group.as_str(), "last").await.is_err());
+        commit_save(stream.as_str()).await.unwrap();
+
+        let mut loaded = String::new();
+        loop {
+            let chunk = load_range(group.as_str(), "last", loaded.len(), 4095)
+                .await
+                .unwrap();
+            if chunk.is_empty() {
+                break;
+            }
+            loaded.push_str(chunk.as_str());
+        }
+        assert!(loaded == value);
+        assert!(load_range(group.as_str(), "last", 14, 10).await.is_err());
+
+        let too_large = "x".repeat(MAX_CHUNK_SIZE + 1);
+        let stream = begin_save(group.as_str(), "big").await.unwrap();
+        assert!(append_save(stream.as_str(), too_large.as_str())
+            .await
+            .is_err());
+        discard(namespace.as_str()).await.unwrap();
+    }
+
+    #[test]
+    fn flooding_game_is_throttled_then_disconnected() {
+        std::env::set_var("DEVCADE_IPC_BURST", "20");
+        std::env::set_var("DEVCADE_IPC_RATE", "1");
+        std::env::set_var("DEVCADE_IPC_DISCONNECT_AFTER", "200");
+        let socket =
+            std::env::temp_dir().join(format!("devcade-flood-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&socket);
+        let path = socket.to_str().unwrap().to_string();
+        std::thread::spawn(move || {
+            tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap()
+                .block_on(main(path.as_str()));
+        });
+        let connect = || {
+            for _ in 0..100 {
+                if let Ok(stream) = UnixStream::connect(&socket) {
+                    return Client::new(stream);
+                }
+                std::thread::sleep(Duration::from_millis(50));
+            }
+            panic!("Persistence server didn't start");
+        };
+        // Writes to a stream that doesn't exist, so nothing is saved
+        let write = || RequestBody::AppendSave(String::from("flood"), String::from("x"));
+
+        let mut game = connect();
+        let (mut answered, mut throttled) = (0, 0);
+        let disconnected = loop {
+            match game.send(write()) {
+                Ok(ResponseBody::RateLimited(retry_after)) => {
+                    assert!(retry_after > 0);
+                    throttled += 1;
+                }
+                Ok(ResponseBody::Err(e)) if e.contains("too many requests") => break true,
+                Ok(_) => answered += 1,
+                Err(_) => break false,
+            }
+            assert!(answered + throttled < 10_000, "never disconnected");
+        };
+        assert!(disconnected);
+        // The burst is served, anything past it (bar what refilled meanwhile) is throttled
+        assert!((20..=25).contains(&answered), "{answered} answered");
+        assert_eq!(throttled, 200);
+        assert!(game.send(write()).is_err());
+
+        // Reconnecting doesn't refill the buckets
+        let mut game = connect();
+        assert!(matches!(
+            game.send(write()).unwrap(),
+            ResponseBody::RateLimited(_)
+        ));
+        let _ = std::fs::remove_file(&socket);
+    }
+}
@@ -0,0 +1,625 @@
+use crate::env::save_storage;
+use crate::layout;
+use anyhow::{anyhow, Error};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+
+/**
+ * How large a segment grows before flushes start a new one
+ */
+const SEGMENT_BYTES: u64 = 1024 * 1024;
+
+/**
+ * How many segments a group can have before its closed segments are merged
+ */
+const MAX_SEGMENTS: usize = 4;
+
+lazy_static! {
+    // Groups being compacted, so the same group is never compacted twice at once
+    static ref COMPACTING: Mutex<HashSet<PathBuf>> = Mutex::new(HashSet::new());
+}
+
+/**
+ * How a group of saves is stored. Either backend reads groups the other one wrote, and the first
+ * flush moves them over, so switching backends never loses saves.
+ */
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Backend {
+    /**
+     * `<group>.save`, one JSON object rewritten whole on every flush
+     */
+    Json,
+    /**
+     * `<group>.segments/<n>.log`, lines of JSON that flushes append the changed keys to. Later
+     * lines win, and segments are merged in the background once there are too many.
+     */
+    Segmented,
+}
+
+/**
+ * One line of a segment
+ */
+#[derive(Serialize, Deserialize)]
+struct Entry<'a> {
+    key: Cow<'a, str>,
+    value: Cow<'a, str>,
+}
+
+impl FromStr for Backend {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Self::Json),
+            "segmented" => Ok(Self::Segmented),
+            _ => Err(anyhow!(
+                "Unknown save storage '{s}', expected json or segmented"
+            )),
+        }
+    }
+}
+
+impl Display for Backend {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Json => write!(f, "json"),
+            Self::Segmented => write!(f, "segmented"),
+        }
+    }
+}
+
+fn json_path(group: &Path) -> PathBuf {
+    let mut path = group.as_os_str().to_owned();
+    path.push(".save");
+    PathBuf::from(path)
+}
+
+fn segments_dir(group: &Path) -> PathBuf {
+    let mut path = group.as_os_str().to_owned();
+    path.push(".segments");
+    PathBuf::from(path)
+}
+
+fn segment_path(group: &Path, segment: u64) -> PathBuf {
+    segments_dir(group).join(format!("{segment:08}.log"))
+}
+
+/**
+ * Get the numbers of a group's segments, oldest first. Temporary files left by compaction are
+ * ignored.
+ */
+fn segments(group: &Path) -> Result<Vec<u64>, Error> {
+    let entries = match std::fs::read_dir(segments_dir(group)) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut segments = Vec::new();
+    for entry in entries {
+        let name = entry?.file_name();
+        if let Some(segment) = name
+            .to_str()
+            .and_then(|name| name.strip_suffix(".log"))
+            .and_then(|number| number.parse().ok())
+        {
+            segments.push(segment);
+        }
+    }
+    segments.sort_unstable();
+    Ok(segments)
+}
+
+/**
+ * Read segments in order into a map, later entries replacing earlier ones. Segments removed by a
+ * compaction partway through are skipped, since the segment they were merged into holds their
+ * entries. A line that doesn't parse, like one cut short by a crash, is skipped.
+ */
+fn read_segments(group: &Path, segments: &[u64]) -> Result<HashMap<String, String>, Error> {
+    let mut map = HashMap::new();
+    for segment in segments {
+        let path = segment_path(group, *segment);
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+        for line in contents.lines().filter(|line| !line.is_empty()) {
+            match serde_json::from_str::<Entry>(line) {
+                Ok(entry) => {
+                    map.insert(entry.key.into_owned(), entry.value.into_owned());
+                }
+                Err(e) => log::warn!("Skipping unreadable line in {}: {}", path.display(), e),
+            }
+        }
+    }
+    Ok(map)
+}
+
+/**
+ * Make a renamed or removed file in a directory durable
+ */
+fn sync_dir(dir: &Path) -> Result<(), Error> {
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+/**
+ * Write entries as segment lines
+ */
+fn lines<'a>(entries: impl Iterator<Item = (&'a String, &'a String)>) -> Result<Vec<u8>, Error> {
+    let mut lines = Vec::new();
+    for (key, value) in entries {
+        serde_json::to_writer(
+            &mut lines,
+            &Entry {
+                key: Cow::Borrowed(key),
+                value: Cow::Borrowed(value),
+            },
+        )?;
+        lines.push(b'\n');
+    }
+    Ok(lines)
+}
+
+/**
+ * Append entries to a segment, creating it if it's missing, and make them durable. If the segment
+ * ends partway through a line, the entries start on a new line.
+ */
+fn append<'a>(
+    path: &Path,
+    entries: impl Iterator<Item = (&'a String, &'a String)>,
+) -> Result<(), Error> {
+    let mut lines = lines(entries)?;
+    if lines.is_empty() {
+        return Ok(());
+    }
+    let mut file = OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(true)
+        .open(path)?;
+    if file.metadata()?.len() > 0 {
+        let mut last = [0];
+        file.seek(SeekFrom::End(-1))?;
+        file.read_exact(&mut last)?;
+        if last[0] != b'\n' {
+            lines.insert(0, b'\n');
+        }
+    }
+    file.write_all(&lines)?;
+    file.sync_data()?;
+    Ok(())
+}
+
+impl Backend {
+    /**
+     * Get the backend set with `DEVCADE_SAVE_STORAGE`. Unknown backends fall back to JSON, like
+     * the default.
+     */
+    #[must_use]
+    pub fn configured() -> Self {
+        match save_storage().as_deref().map(str::parse) {
+            None => Self::Json,
+            Some(Ok(backend)) => backend,
+            Some(Err(e)) => {
+                log::warn!("{}, using json", e);
+                Self::Json
+            }
+        }
+    }
+
+    /**
+     * Whether a group is stored with this backend. A group only counts as segmented once it has a
+     * segment, which is written whole the first time.
+     */
+    fn holds(self, group: &Path) -> bool {
+        match self {
+            Self::Json => json_path(group).exists(),
+            Self::Segmented => segments(group).is_ok_and(|segments| !segments.is_empty()),
+        }
+    }
+
+    fn other(self) -> Self {
+        match self {
+            Self::Json => Self::Segmented,
+            Self::Segmented => Self::Json,
+        }
+    }
+
+    /**
+     * Read a group stored with this backend
+     */
+    fn read(self, group: &Path) -> Result<HashMap<String, String>, Error> {
+        match self {
+            Self::Json => Ok(serde_json::from_str(
+                std::fs::read_to_string(json_path(group))?.as_str(),
+            )?),
+            Self::Segmented => read_segments(group, &segments(group)?),
+        }
+    }
+
+    /**
+     * Load a group of saves, from the other backend if this one doesn't hold it. A group nobody
+     * saved to yet is empty.
+     *
+     * # Errors
+     * This function will return an error if the group can't be read.
+     */
+    pub fn load(self, group: &Path) -> Result<HashMap<String, String>, Error> {
+        if self.holds(group) {
+            self.read(group)
+        } else if self.other().holds(group) {
+            self.other().read(group)
+        } else {
+            Ok(HashMap::new())
+        }
+    }
+
+    /**
+     * Write a group of saves. `changed` holds the keys changed since the last flush, which is all
+     * the segmented backend writes once the group is stored with it. The first flush with a
+     * backend writes every key and then removes the other backend's copy.
+     *
+     * # Errors
+     * This function will return an error if the group can't be written. The other backend's copy
+     * is kept until the new one is durable.
+     */
+    pub fn flush(
+        self,
+        group: &Path,
+        data: &HashMap<String, String>,
+        changed: &HashSet<String>,
+    ) -> Result<(), Error> {
+        let moved = !self.holds(group);
+        match self {
+            Self::Json => layout::write_atomic(&json_path(group), serde_json::to_vec(data)?)?,
+            Self::Segmented => {
+                if moved {
+                    // Written atomically, so a crash can't leave a partial group that hides the
+                    // other backend's copy
+                    layout::write_atomic(&segment_path(group, 1), lines(data.iter())?)?;
+                } else {
+                    let mut segment = segments(group)?.last().copied().unwrap_or(1);
+                    let full = std::fs::metadata(segment_path(group, segment))
+                        .is_ok_and(|meta| meta.len() >= SEGMENT_BYTES);
+                    if full {
+                        segment += 1;
+                    }
+                    append(
+                        &segment_path(group, segment),
+                        data.iter().filter(|(key, _)| changed.contains(*key)),
+                    )?;
+                }
+                sync_dir(&segments_dir(group))?;
+            }
+        }
+        if moved {
+            match self.other() {
+                Self::Json => match std::fs::remove_file(json_path(group)) {
+                    Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+                    _ => {}
+                },
+                Self::Segmented => match std::fs::remove_dir_all(segments_dir(group)) {
+                    Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+                    _ => {}
+                },
+            }
+        }
+        Ok(())
+    }
+
+    /**
+     * Whether a group has enough segments to be worth compacting
+     */
+    pub fn needs_compaction(self, group: &Path) -> bool {
+        self == Self::Segmented
+            && segments(group).is_ok_and(|segments| segments.len() > MAX_SEGMENTS)
+    }
+}
+
+/**
+ * Merge a group's closed segments (every one but the newest, which flushes append to) into one.
+ * The merged entries replace the newest closed segment with an atomic write, and only once that's
+ * durable are the older segments removed. A crash partway through leaves segments that read back
+ * the same: the older ones are read first, and the merged segment after them holds their latest
+ * values. Does nothing if the group is already being compacted.
+ *
+ * # Errors
+ * This function will return an error if the segments can't be read or written.
+ */
+pub fn compact(group: &Path) -> Result<(), Error> {
+    if !COMPACTING.lock().unwrap().insert(group.to_path_buf()) {
+        return Ok(());
+    }
+    let result = merge_closed(group);
+    COMPACTING.lock().unwrap().remove(group);
+    result
+}
+
+fn merge_closed(group: &Path) -> Result<(), Error> {
+    let segments = segments(group)?;
+    let Some((_, closed)) = segments.split_last() else {
+        return Ok(());
+    };
+    let Some((target, older)) = closed.split_last() else {
+        return Ok(());
+    };
+    if older.is_empty() {
+        return Ok(());
+    }
+    // Sorted, so compacting the same entries always writes the same segment
+    let merged: BTreeMap<String, String> = read_segments(group, closed)?.into_iter().collect();
+    layout::write_atomic(&segment_path(group, *target), lines(merged.iter())?)?;
+    sync_dir(&segments_dir(group))?;
+    for segment in older {
+        std::fs::remove_file(segment_path(group, *segment))?;
+    }
+    log::debug!(
+        "Compacted {} segments of {} into {} keys",
+        closed.len(),
+        group.display(),
+        merged.len()
+    );
+    Ok(())
+}
+
+/**
+ * Find every group under a directory, in either backend
+ */
+fn groups(dir: &Path, found: &mut HashSet<PathBuf>) -> Result<(), Error> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    for entry in entries {
+        let entry = entry?;
+        let path = entry.path();
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if let Some(group) = name.strip_suffix(".save") {
+            found.insert(dir.join(group));
+        } else if let Some(group) = name.strip_suffix(".segments") {
+            found.insert(dir.join(group));
+        } else if entry.file_type()?.is_dir() {
+            groups(path.as_path(), found)?;
+        }
+    }
+    Ok(())
+}
+
+/**
+ * Move every group of saves under `root` to a backend. This should only be done while the backend
+ * isn't running, since it doesn't go through the in-memory cache.
+ *
+ * Returns how many groups were moved.
+ *
+ * # Errors
+ * This function will return an error if a group can't be read or written. Groups moved before it
+ * stay moved, and the rest are left as they were.
+ */
+pub fn migrate(root: &Path, to: Backend) -> Result<usize, Error> {
+    let mut found = HashSet::new();
+    groups(root, &mut found)?;
+    let mut moved = 0;
+    for group in found {
+        if to.holds(group.as_path()) {
+            continue;
+        }
+        let data = to.load(group.as_path())?;
+        to.flush(group.as_path(), &data, &HashSet::new())?;
+        moved += 1;
+    }
+    Ok(moved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /**
+     * A directory for one test's saves, removed when the test ends
+     */
+    struct Dir(PathBuf);
+
+    impl Dir {
+        fn new(name: &str) -> Self {
+            let dir =
+                std::env::temp_dir().join(format!("devcade-storage-{name}-{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+
+        fn group(&self) -> PathBuf {
+            self.0.join("game").join("group")
+        }
+    }
+
+    impl Drop for Dir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn data(keys: usize) -> HashMap<String, String> {
+        (0..keys)
+            .map(|i| (format!("key{i}"), format!("value{i}")))
+            .collect()
+    }
+
+    fn changed(keys: &[&str]) -> HashSet<String> {
+        keys.iter().map(|key| (*key).to_string()).collect()
+    }
+
+    fn segments_size(group: &Path) -> u64 {
+        segments(group)
+            .unwrap()
+            .into_iter()
+            .map(|segment| {
+                std::fs::metadata(segment_path(group, segment))
+                    .unwrap()
+                    .len()
+            })
+            .sum()
+    }
+
+    #[test]
+    fn switching_backends_keeps_saves() {
+        let dir = Dir::new("switching");
+        let group = dir.group();
+        let mut saves = data(10);
+        Backend::Json
+            .flush(&group, &saves, &HashSet::new())
+            .unwrap();
+        assert_eq!(Backend::Segmented.load(&group).unwrap(), saves);
+
+        saves.insert("key3".to_string(), "changed".to_string());
+        Backend::Segmented
+            .flush(&group, &saves, &changed(&["key3"]))
+            .unwrap();
+        assert!(!json_path(&group).exists());
+        assert_eq!(Backend::Segmented.load(&group).unwrap(), saves);
+        assert_eq!(Backend::Json.load(&group).unwrap(), saves);
+
+        Backend::Json
+            .flush(&group, &saves, &HashSet::new())
+            .unwrap();
+        assert!(!segments_dir(&group).exists());
+        assert_eq!(Backend::Json.load(&group).unwrap(), saves);
+    }
+
+    #[test]
+    fn migrate_moves_every_group() {
+        let dir = Dir::new("migrate");
+        let groups = [
+            dir.0.join("a").join("saves"),
+            dir.0.join("b").join("c").join("d"),
+        ];
+        for group in &groups {
+            Backend::Json
+                .flush(group, &data(5), &HashSet::new())
+                .unwrap();
+        }
+        assert_eq!(migrate(&dir.0, Backend::Segmented).unwrap(), 2);
+        assert_eq!(migrate(&dir.0, Backend::Segmented).unwrap(), 0);
+        for group in &groups {
+            assert!(!json_path(group).exists());
+            assert_eq!(Backend::Segmented.read(group).unwrap(), data(5));
+        }
+    }
+
+    #[test]
+    fn compaction_keeps_latest_values() {
+        let dir = Dir::new("compaction");
+        let group = dir.group();
+        let mut saves = data(3);
+        Backend::Segmented
+            .flush(&group, &saves, &HashSet::new())
+            .unwrap();
+        // Split the flushes over segments by hand, since real ones only roll over at a megabyte
+        for round in 2..=6 {
+            saves.insert("key1".to_string(), format!("round{round}"));
+            append(
+                &segment_path(&group, round),
+                saves.iter().filter(|(key, _)| *key == "key1"),
+            )
+            .unwrap();
+        }
+        assert!(Backend::Segmented.needs_compaction(&group));
+        compact(&group).unwrap();
+        assert_eq!(segments(&group).unwrap(), vec![5, 6]);
+        assert_eq!(Backend::Segmented.load(&group).unwrap(), saves);
+    }
+
+    #[test]
+    fn crash_during_compaction_reads_back_the_same() {
+        let dir = Dir::new("crash");
+        let group = dir.group();
+        let mut saves = data(3);
+        Backend::Segmented
+            .flush(&group, &saves, &HashSet::new())
+            .unwrap();
+        for round in 2..=4 {
+            saves.insert("key2".to_string(), format!("round{round}"));
+            append(
+                &segment_path(&group, round),
+                saves.iter().filter(|(key, _)| *key == "key2"),
+            )
+            .unwrap();
+        }
+        let before: Vec<(u64, Vec<u8>)> = segments(&group)
+            .unwrap()
+            .into_iter()
+            .map(|segment| {
+                (
+                    segment,
+                    std::fs::read(segment_path(&group, segment)).unwrap(),
+                )
+            })
+            .collect();
+        compact(&group).unwrap();
+
+        // Put back the older segments, as if the crash came after the merged segment was renamed
+        // into place but before they were removed
+        for (segment, contents) in &before[..2] {
+            std::fs::write(segment_path(&group, *segment), contents).unwrap();
+        }
+        assert_eq!(Backend::Segmented.load(&group).unwrap(), saves);
+        // And a leftover temporary file from a crash before the rename is ignored
+        std::fs::write(segments_dir(&group).join("00000003.log.tmp"), "garbage").unwrap();
+        assert_eq!(Backend::Segmented.load(&group).unwrap(), saves);
+    }
+
+    #[test]
+    fn torn_append_is_skipped() {
+        let dir = Dir::new("torn");
+        let group = dir.group();
+        let mut saves = data(2);
+        Backend::Segmented
+            .flush(&group, &saves, &HashSet::new())
+            .unwrap();
+        let mut segment = OpenOptions::new()
+            .append(true)
+            .open(segment_path(&group, 1))
+            .unwrap();
+        segment.write_all(br#"{"key":"key0","val"#).unwrap();
+
+        saves.insert("key1".to_string(), "after crash".to_string());
+        Backend::Segmented
+            .flush(&group, &saves, &changed(&["key1"]))
+            .unwrap();
+        assert_eq!(Backend::Segmented.load(&group).unwrap(), saves);
+    }
+
+    #[test]
+    fn flush_cost_does_not_grow_with_keys() {
+        let dir = Dir::new("flat");
+        let mut appended = Vec::new();
+        for keys in [100, 1_000, 10_000, 40_000] {
+            let group = dir.0.join(keys.to_string());
+            let mut saves = data(keys);
+            Backend::Segmented
+                .flush(&group, &saves, &HashSet::new())
+                .unwrap();
+            let before = segments_size(&group);
+            saves.insert("key7".to_string(), "changed".to_string());
+            Backend::Segmented
+                .flush(&group, &saves, &changed(&["key7"]))
+                .unwrap();
+            appended.push(segments_size(&group) - before);
+        }
+        assert!(
+            appended.windows(2).all(|sizes| sizes[0] == sizes[1]),
+            "{appended:?}"
+        );
+    }
+}
@@ -0,0 +1,117 @@
+use crate::layout;
+use anyhow::Error;
+use log::{log, Level};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+/**
+ * State the backend keeps in a JSON file, loaded the first time it's used and kept in memory after
+ * that. A missing file loads as the default value, and so does a file that can't be parsed, with a
+ * warning.
+ */
+pub struct JsonState<T> {
+    path: fn() -> PathBuf,
+    value: Mutex<Option<T>>,
+}
+
+/**
+ * The locked state, which can be changed and then saved
+ */
+pub struct JsonGuard<'a, T> {
+    path: fn() -> PathBuf,
+    value: MutexGuard<'a, Option<T>>,
+}
+
+impl<T: Serialize + DeserializeOwned + Default> JsonState<T> {
+    /**
+     * Keep state in the file at `path`. Nothing is read until the state is first locked.
+     */
+    #[must_use]
+    pub const fn new(path: fn() -> PathBuf) -> Self {
+        Self {
+            path,
+            value: Mutex::new(None),
+        }
+    }
+
+    /**
+     * Lock the state, reading it from its file if it hasn't been read yet
+     */
+    pub fn lock(&self) -> JsonGuard<'_, T> {
+        let mut value = self.value.lock().unwrap();
+        if value.is_none() {
+            *value = Some(self.load());
+        }
+        JsonGuard {
+            path: self.path,
+            value,
+        }
+    }
+
+    fn load(&self) -> T {
+        let path = (self.path)();
+        let Ok(json) = std::fs::read(&path) else {
+            return T::default();
+        };
+        serde_json::from_slice(&json).unwrap_or_else(|e| {
+            log!(
+                Level::Warn,
+                "Couldn't parse {}, starting over: {}",
+                path.display(),
+                e
+            );
+            T::default()
+        })
+    }
+}
+
+impl<T: Serialize> JsonGuard<'_, T> {
+    /**
+     * Write the state to its file
+     *
+     * # Errors
+     * This function will return an error if the state can't be serialized or written.
+     */
+    pub fn save(&self) -> Result<(), Error> {
+        let json = serde_json::to_vec_pretty(&**self)?;
+        layout::write_atomic(&(self.path)(), json)?;
+        Ok(())
+    }
+}
+
+/**
+ * Append an entry to a journal, a file with one JSON object per line that's only ever added to
+ *
+ * # Errors
+ * This function will return an error if the entry can't be serialized or written.
+ */
+pub fn journal(path: &Path, entry: &impl Serialize) -> Result<(), Error> {
+    let line = serde_json::to_string(entry)?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let mut journal = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(journal, "{line}")?;
+    Ok(())
+}
+
+impl<T> Deref for JsonGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        // This unwrap is safe because the state is loaded before a guard is made
+        self.value.as_ref().unwrap()
+    }
+}
+
+impl<T> DerefMut for JsonGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // This unwrap is safe because the state is loaded before a guard is made
+        self.value.as_mut().unwrap()
+    }
+}
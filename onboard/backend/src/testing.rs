@@ -0,0 +1,205 @@
+use crate::clock;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::future::Future;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+/**
+ * Held by tests that use the devcade directory, since it's read from the environment every test
+ * shares
+ */
+static ROOT: Mutex<()> = Mutex::new(());
+
+/**
+ * Point `DEVCADE_PATH` at a new empty directory for a test. Tests that use the devcade directory
+ * run one at a time, for as long as the returned guard is held.
+ */
+pub fn root(name: &str) -> (MutexGuard<'static, ()>, PathBuf) {
+    let guard = ROOT.lock().unwrap_or_else(PoisonError::into_inner);
+    let root = std::env::temp_dir().join(format!("devcade-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(&root).unwrap();
+    std::env::set_var("DEVCADE_PATH", &root);
+    crate::secrets::forget();
+    (guard, root)
+}
+
+/**
+ * Run just one test again in a process of its own, for tests that change something the whole
+ * process shares, like the clock or injected faults. Returns `true` in that process, where the test
+ * goes ahead, and `false` in the original one once the test has passed on its own.
+ *
+ * `test` is the test's full path, like `api::network::tests::name`.
+ */
+pub fn alone(test: &str) -> bool {
+    if std::env::var("DEVCADE_ALONE").is_ok_and(|alone| alone == test) {
+        return true;
+    }
+    let output = Command::new(std::env::current_exe().unwrap())
+        .args([test, "--exact", "--nocapture"])
+        .env("DEVCADE_ALONE", test)
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success(), "{test} failed:\n{stdout}{stderr}");
+    // A misspelled name would pass without running anything
+    assert!(stdout.contains("1 passed"), "{test} didn't run:\n{stdout}");
+    false
+}
+
+/**
+ * Freeze the clock and wait for a future, moving the clock forward by `step` every millisecond
+ * until it's done. Waits on the clock pass in a fraction of the time, while real I/O still has time
+ * to happen. Only for tests run `alone`.
+ */
+pub async fn fast_forward<F: Future>(step: Duration, future: F) -> F::Output {
+    clock::freeze();
+    tokio::pin!(future);
+    loop {
+        tokio::select! {
+            output = &mut future => return output,
+            () = tokio::time::sleep(Duration::from_millis(1)) => clock::advance(step),
+        }
+    }
+}
+
+/**
+ * Allocates through the system allocator while counting the bytes in use, so tests can check how
+ * much memory something needed at most
+ */
+struct Counting;
+
+/// Bytes allocated right now
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+/// The most bytes allocated at once since the last `reset_peak`
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+// SAFETY: every call is passed on to the system allocator unchanged
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            grew(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        ALLOCATED.fetch_sub(layout.size(), Ordering::SeqCst);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new = System.realloc(ptr, layout, new_size);
+        if !new.is_null() {
+            ALLOCATED.fetch_sub(layout.size(), Ordering::SeqCst);
+            grew(new_size);
+        }
+        new
+    }
+}
+
+fn grew(size: usize) {
+    let now = ALLOCATED.fetch_add(size, Ordering::SeqCst) + size;
+    PEAK.fetch_max(now, Ordering::SeqCst);
+}
+
+/**
+ * Start measuring the peak from what's allocated now, returning that for `peak_since`
+ */
+pub fn reset_peak() -> usize {
+    let now = ALLOCATED.load(Ordering::SeqCst);
+    PEAK.store(now, Ordering::SeqCst);
+    now
+}
+
+/**
+ * The most bytes allocated at once since `reset_peak`, above what was allocated when it was called.
+ * Only meaningful in a process running a single test, since every thread's allocations count.
+ */
+pub fn peak_since(start: usize) -> usize {
+    PEAK.load(Ordering::SeqCst).saturating_sub(start)
+}
+
+/**
+ * How a mock server answers a request
+ */
+pub enum Reply {
+    /// A status line like `200 OK`, extra header lines, and the body
+    Respond(&'static str, Vec<String>, Vec<u8>),
+    /// Send the status and headers with the whole body's length, then only part of the body, and
+    /// never finish it
+    Stall(&'static str, Vec<u8>, usize),
+    /// Never answer at all
+    Hang,
+}
+
+/**
+ * Serve HTTP on a free local port, answering every request with what `reply` returns for its
+ * path and header lines. Each connection is handled on its own thread, and closed after one
+ * request. Returns the server's URL, like `http://127.0.0.1:1234`.
+ */
+pub fn serve(reply: impl Fn(&str, &[String]) -> Reply + Send + Sync + 'static) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let reply = Arc::new(reply);
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else {
+                continue;
+            };
+            let reply = Arc::clone(&reply);
+            std::thread::spawn(move || answer(stream, reply.as_ref()));
+        }
+    });
+    url
+}
+
+fn answer(mut stream: TcpStream, reply: &dyn Fn(&str, &[String]) -> Reply) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut request = String::new();
+    let _ = reader.read_line(&mut request);
+    let path = request.split(' ').nth(1).unwrap_or("/").to_string();
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        match reader.read_line(&mut line) {
+            Ok(n) if n > 0 && !line.trim().is_empty() => headers.push(line.trim().to_string()),
+            _ => break,
+        }
+    }
+    let (status, extra, body, sent) = match reply(path.as_str(), &headers) {
+        Reply::Respond(status, extra, body) => {
+            let len = body.len();
+            (status, extra, body, len)
+        }
+        Reply::Stall(status, body, sent) => (status, Vec::new(), body, sent),
+        Reply::Hang => {
+            std::thread::sleep(Duration::from_secs(600));
+            return;
+        }
+    };
+    let mut head = format!(
+        "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n",
+        body.len()
+    );
+    for header in extra {
+        head.push_str(header.as_str());
+        head.push_str("\r\n");
+    }
+    head.push_str("\r\n");
+    let _ = stream.write_all(head.as_bytes());
+    let _ = stream.write_all(&body[..sent]);
+    if sent < body.len() {
+        std::thread::sleep(Duration::from_secs(600));
+    }
+}
@@ -27,7 +27,7 @@ public static class Client {
     
     /**
      * FS
-     *   tmp/
+     *   {DEVCADE_PATH}/games/
      *   |- {game.id}/
      *       |- banner.png
      *       |- icon.png
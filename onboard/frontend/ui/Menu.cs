@@ -79,7 +79,7 @@ public class Menu : IMenu {
     public void LoadContent(ContentManager contentManager) {
         // Setup banner finished callback
         Client.onBannerFinished += (_, game) => {
-            Devcade.instance.loadTextureFromFile($"{devcadePath}/{game.id}/banner.png").ContinueWith(t => {
+            Devcade.instance.loadTextureFromFile($"{devcadePath}/games/{game.id}/banner.png").ContinueWith(t => {
                 if (t.IsCompletedSuccessfully && t.Result.is_ok() && cards.ContainsKey(game.id)) {
                     cards[game.name].setTexture(t.Result.unwrap());
                     return;
@@ -182,7 +182,7 @@ public class Menu : IMenu {
                 Client.downloadBanner(game.id);
             } // check if /tmp/ has the banner
 
-            string bannerPath = $"/tmp/devcade/{game.id}/banner.png";
+            string bannerPath = $"{devcadePath}/games/{game.id}/banner.png";
             if (File.Exists(bannerPath)) {
                 try {
                     Texture2D banner = Texture2D.FromStream(graphics, File.OpenRead(bannerPath));
@@ -23,6 +23,8 @@ public class Response {
         
         User,
 
+        Installed,
+
         Unknown,
     }
 
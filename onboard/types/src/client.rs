@@ -0,0 +1,357 @@
+use crate::{Dialect, Request, RequestBody, Response, ResponseBody, MAX_FRAME_SIZE};
+use anyhow::{anyhow, Error};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::io::{ErrorKind, Read, Write};
+
+/**
+ * Write a message to one of the backend's sockets as a length-prefixed frame
+ *
+ * # Errors
+ * This function will return an error if the message cannot be serialized, is larger than
+ * [`MAX_FRAME_SIZE`], or cannot be written.
+ */
+pub fn write_frame<W: Write, T: Serialize>(writer: &mut W, message: &T) -> Result<(), Error> {
+    writer.write_all(&Dialect::Framed.frame(message)?)?;
+    Ok(())
+}
+
+/**
+ * Read the next length-prefixed frame from one of the backend's sockets, or `None` if the socket
+ * was closed between frames
+ *
+ * # Errors
+ * This function will return an error if the socket is closed partway through a frame, or the frame
+ * is too large or cannot be deserialized.
+ */
+pub fn read_frame<R: Read, T: DeserializeOwned>(reader: &mut R) -> Result<Option<T>, Error> {
+    let mut len = [0u8; 4];
+    match reader.read_exact(&mut len) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e.into()),
+    }
+    let len = u32::from_be_bytes(len) as usize;
+    if len + 4 > MAX_FRAME_SIZE {
+        return Err(anyhow!(
+            "Frame of {} bytes is larger than the maximum of {MAX_FRAME_SIZE} bytes",
+            len + 4
+        ));
+    }
+    let mut json = vec![0u8; len];
+    reader.read_exact(&mut json)?;
+    Ok(Some(serde_json::from_slice(&json)?))
+}
+
+/**
+ * A blocking connection to one of the backend's sockets, which sends one request at a time and
+ * waits for its response
+ */
+pub struct Client<S> {
+    stream: S,
+    request_id: u32,
+}
+
+impl<S: Read + Write> Client<S> {
+    /**
+     * Talk to the backend over a connected socket
+     */
+    pub fn new(stream: S) -> Self {
+        Self {
+            stream,
+            request_id: 0,
+        }
+    }
+
+    /**
+     * Send a request and wait for its response
+     *
+     * # Errors
+     * This function will return an error if the socket cannot be talked to, or the backend
+     * disconnects before responding.
+     */
+    pub fn send(&mut self, body: RequestBody) -> Result<ResponseBody, Error> {
+        self.request_id += 1;
+        write_frame(
+            &mut self.stream,
+            &Request {
+                request_id: self.request_id,
+                body,
+            },
+        )?;
+        loop {
+            let response: Response =
+                read_frame(&mut self.stream)?.ok_or_else(|| anyhow!("Backend disconnected"))?;
+            if response.request_id == self.request_id {
+                return Ok(response.body);
+            }
+        }
+    }
+
+    /**
+     * Send a request whose successful response is `Ok` or `Object`, returning the object
+     */
+    fn send_expecting(&mut self, body: RequestBody) -> Result<Option<String>, Error> {
+        match self.send(body)? {
+            ResponseBody::Ok => Ok(None),
+            ResponseBody::Object(object) => Ok(Some(object)),
+            ResponseBody::Err(e) => Err(anyhow!(e)),
+            other => Err(anyhow!("Unexpected response: {other}")),
+        }
+    }
+
+    /**
+     * Save a value too large to send in one request, in chunks of at most `chunk_size` bytes (but
+     * never less than a character). The value only becomes visible once every chunk was sent, so
+     * a failed save leaves the old value in place.
+     *
+     * # Errors
+     * This function will return an error if the socket cannot be talked to, or the backend refuses
+     * a chunk, like when the value is larger than it allows.
+     */
+    pub fn save_stream(
+        &mut self,
+        group: &str,
+        key: &str,
+        value: &str,
+        chunk_size: usize,
+    ) -> Result<(), Error> {
+        let stream_id = self
+            .send_expecting(RequestBody::BeginSave(group.to_string(), key.to_string()))?
+            .ok_or_else(|| anyhow!("Backend didn't return a stream ID"))?;
+        let mut start = 0;
+        while start < value.len() {
+            let end = chunk_end(value, start, chunk_size);
+            self.send_expecting(RequestBody::AppendSave(
+                stream_id.clone(),
+                value[start..end].to_string(),
+            ))?;
+            start = end;
+        }
+        self.send_expecting(RequestBody::CommitSave(stream_id))?;
+        Ok(())
+    }
+
+    /**
+     * Load a value too large to receive in one response, in chunks of at most `chunk_size` bytes.
+     * Chunks smaller than [`MIN_CHUNK_SIZE`] are made that large, so a chunk always fits a
+     * character.
+     *
+     * # Errors
+     * This function will return an error if the socket cannot be talked to, or the value can't be
+     * loaded.
+     */
+    pub fn load_stream(
+        &mut self,
+        group: &str,
+        key: &str,
+        chunk_size: usize,
+    ) -> Result<String, Error> {
+        let chunk_size = chunk_size.max(MIN_CHUNK_SIZE);
+        let mut value = String::new();
+        loop {
+            let chunk = self
+                .send_expecting(RequestBody::LoadRange(
+                    group.to_string(),
+                    key.to_string(),
+                    value.len(),
+                    chunk_size,
+                ))?
+                .unwrap_or_default();
+            if chunk.is_empty() {
+                return Ok(value);
+            }
+            value.push_str(chunk.as_str());
+        }
+    }
+}
+
+/**
+ * The smallest chunk `load_stream` asks for, the length of the longest UTF-8 character
+ */
+pub const MIN_CHUNK_SIZE: usize = 4;
+
+/**
+ * Get where a chunk of at most `size` bytes starting at `start` ends, on a character boundary. A
+ * chunk always holds at least one character.
+ */
+fn chunk_end(value: &str, start: usize, size: usize) -> usize {
+    let mut end = start.saturating_add(size).min(value.len());
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+    if end > start {
+        return end;
+    }
+    // The chunk size is smaller than the character at `start`
+    value[start..]
+        .chars()
+        .next()
+        .map_or(value.len(), |c| start + c.len_utf8())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::{HashMap, VecDeque};
+    use std::io::Cursor;
+
+    /**
+     * A backend that answers streamed saves and ranged loads the way the persistence socket does,
+     * keeping the saves in memory
+     */
+    #[derive(Default)]
+    struct FakeBackend {
+        max_chunk: usize,
+        saves: HashMap<(String, String), String>,
+        streams: HashMap<String, (String, String, String)>,
+        largest_chunk: usize,
+        incoming: Vec<u8>,
+        outgoing: VecDeque<u8>,
+    }
+
+    impl FakeBackend {
+        fn handle(&mut self, body: RequestBody) -> ResponseBody {
+            match body {
+                RequestBody::BeginSave(group, key) => {
+                    let id = self.streams.len().to_string();
+                    self.streams.insert(id.clone(), (group, key, String::new()));
+                    ResponseBody::Object(id)
+                }
+                RequestBody::AppendSave(id, chunk) => {
+                    if chunk.len() > self.max_chunk {
+                        return ResponseBody::Err(String::from("Chunk too large"));
+                    }
+                    self.largest_chunk = self.largest_chunk.max(chunk.len());
+                    self.streams
+                        .get_mut(&id)
+                        .unwrap()
+                        .2
+                        .push_str(chunk.as_str());
+                    ResponseBody::Ok
+                }
+                RequestBody::CommitSave(id) => {
+                    let (group, key, value) = self.streams.remove(&id).unwrap();
+                    self.saves.insert((group, key), value);
+                    ResponseBody::Ok
+                }
+                RequestBody::LoadRange(group, key, offset, len) => {
+                    let value = &self.saves[&(group, key)];
+                    let mut end = offset.saturating_add(len).min(value.len());
+                    while !value.is_char_boundary(end) {
+                        end -= 1;
+                    }
+                    match value.get(offset.min(end)..end) {
+                        Some(range) => ResponseBody::Object(range.to_string()),
+                        None => ResponseBody::Err(String::from("Not on a character boundary")),
+                    }
+                }
+                other => ResponseBody::Err(format!("Unexpected request: {other}")),
+            }
+        }
+    }
+
+    impl Write for FakeBackend {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.incoming.extend_from_slice(buf);
+            while self.incoming.len() >= 4 {
+                let len = u32::from_be_bytes(self.incoming[..4].try_into().unwrap()) as usize;
+                if self.incoming.len() < len + 4 {
+                    break;
+                }
+                let frame: Vec<u8> = self.incoming.drain(..len + 4).collect();
+                let request: Request = read_frame(&mut frame.as_slice()).unwrap().unwrap();
+                let response = Response {
+                    request_id: request.request_id,
+                    body: self.handle(request.body),
+                };
+                write_frame(&mut self.outgoing, &response).unwrap();
+            }
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Read for FakeBackend {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.outgoing.read(buf)
+        }
+    }
+
+    #[test]
+    fn multi_megabyte_values_stream_byte_exact() {
+        let mut client = Client::new(FakeBackend {
+            max_chunk: 1000,
+            ..FakeBackend::default()
+        });
+        // Multi-byte characters land on chunk edges with these sizes
+        let value = "replay frame é → 🎮\n".repeat(150_000);
+        assert!(value.len() > 3 * 1024 * 1024);
+
+        client
+            .save_stream("replays", "last", value.as_str(), 1000)
+            .unwrap();
+        assert!((997..=1000).contains(&client.stream.largest_chunk));
+        let loaded = client.load_stream("replays", "last", 999).unwrap();
+        assert_eq!(loaded.len(), value.len());
+        assert!(loaded == value);
+    }
+
+    #[test]
+    fn chunks_always_hold_a_character() {
+        let mut client = Client::new(FakeBackend {
+            max_chunk: 4,
+            ..FakeBackend::default()
+        });
+        client.save_stream("group", "key", "a🎮é", 1).unwrap();
+        assert_eq!(client.stream.largest_chunk, 4);
+        assert_eq!(client.load_stream("group", "key", 1).unwrap(), "a🎮é");
+
+        client.save_stream("group", "empty", "", 1).unwrap();
+        assert_eq!(client.load_stream("group", "empty", 1).unwrap(), "");
+    }
+
+    #[test]
+    fn refused_chunks_fail_the_save() {
+        let mut client = Client::new(FakeBackend {
+            max_chunk: 10,
+            ..FakeBackend::default()
+        });
+        assert!(client
+            .save_stream("group", "key", "too long for one chunk", 20)
+            .is_err());
+        assert!(client.stream.saves.is_empty());
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        let mut bytes = Vec::new();
+        let pong = Response {
+            request_id: 7,
+            body: ResponseBody::Pong,
+        };
+        write_frame(&mut bytes, &pong).unwrap();
+        write_frame(&mut bytes, &"second").unwrap();
+        assert_eq!(bytes[0], 0);
+
+        let mut reader = Cursor::new(bytes);
+        let first: Response = read_frame(&mut reader).unwrap().unwrap();
+        assert_eq!(first.request_id, 7);
+        assert!(matches!(first.body, ResponseBody::Pong));
+        assert_eq!(
+            read_frame::<_, String>(&mut reader).unwrap().unwrap(),
+            "second"
+        );
+        assert!(read_frame::<_, String>(&mut reader).unwrap().is_none());
+    }
+
+    #[test]
+    fn truncated_frame_is_an_error() {
+        let mut bytes = Dialect::Framed.frame(&"truncated").unwrap();
+        bytes.pop();
+        assert!(read_frame::<_, String>(&mut Cursor::new(bytes)).is_err());
+    }
+}
@@ -1,8 +1,13 @@
+/**
+ * Blocking clients for the backend's sockets
+ */
+pub mod client;
 pub mod schema;
 use crate::schema::*;
 use anyhow::Error;
 use serde::{Deserialize, Serialize};
 pub use serde_json::{Map, Value};
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{self, Display};
 use std::process::ExitStatus;
 use std::thread::JoinHandle;
@@ -36,6 +41,1161 @@ impl From<Player> for u8 {
     }
 }
 
+/**
+ * An entry in the NFC tap audit trail. Recent taps are kept individually, older ones only as hourly
+ * counts.
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub enum TapAuditEntry {
+    Tap {
+        /// Unix timestamp of the tap in seconds
+        time: u64,
+        /// The reader that was tapped
+        reader: Player,
+        /// Salted hash of the association ID, only comparable between taps using the same salt
+        id_hash: String,
+        /// How the tap was resolved, e.g. `member`, `demo` or `unknown`
+        outcome: String,
+    },
+    Hourly {
+        /// Unix timestamp of the start of the hour in seconds
+        hour: u64,
+        /// How many taps there were during the hour
+        count: u64,
+    },
+}
+
+/**
+ * How many NFC taps there were in a day, by how they were resolved
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Default, Eq, PartialEq)]
+pub struct TapStats {
+    /// Taps by members
+    pub members: u64,
+    /// Taps by cards the auth service didn't know
+    pub unknown: u64,
+    /// Taps made while the auth service was down, not resolved yet. They're moved to `members` or
+    /// `unknown` once they are.
+    pub pending: u64,
+}
+
+impl TapStats {
+    /**
+     * Count a resolved tap
+     */
+    pub fn add(&mut self, member: bool) {
+        if member {
+            self.members += 1;
+        } else {
+            self.unknown += 1;
+        }
+    }
+}
+
+/**
+ * A log level set at runtime, overriding `RUST_LOG` for a module until it expires
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct LogOverride {
+    /// The module path the level applies to (e.g. `backend::api::network`), or `None` for all
+    pub target: Option<String>,
+    /// The log level, e.g. `debug` or `trace`
+    pub level: String,
+    /// Unix timestamp in seconds when the override stops applying
+    pub expires: u64,
+}
+
+/**
+ * How far along a game download is, so the frontend can show a progress bar
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct DownloadProgress {
+    /// The game being downloaded
+    pub game_id: String,
+    /// How many bytes of the archive have been downloaded
+    pub bytes_downloaded: u64,
+    /// The size of the archive in bytes, if the server sent it
+    pub total_bytes: Option<u64>,
+    /// What the download is doing now
+    pub stage: DownloadStage,
+}
+
+/**
+ * Something that happened while installing, launching or running a game. The frontend should ignore input
+ * between `GameTakingFocus` and `GameReleasedFocus`.
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub enum LaunchEventKind {
+    GameTakingFocus(String), // String is the game ID, sent just before it is spawned
+    GameSpawned(u32),        // u32 is the PID
+    GameReleasedFocus,       // Sent when the game exits, or if it couldn't be spawned
+    GameExited(Option<i32>), // Exit code, None if the game was killed by a signal
+    // The running game went over a limit in `DEVCADE_RESOURCE_LIMITS`. String says which, like
+    // `rss_mb=1900 (limit 1500)`. Sent again only after it drops back under the limit.
+    ResourcePressure(String),
+    // Files of the game checked against its manifest so far, and how many there are to check,
+    // sent before `GameTakingFocus` when `DEVCADE_VERIFY_ON_LAUNCH` is `full`
+    VerifyingFiles(u64, u64),
+    DownloadFinished(String), // String is the game ID, sent once it's installed
+    CatalogChanged(String), // String is the ID of a game the API says was added, changed or removed
+    ScheduleChanged(Option<String>), // Name of the schedule entry now active, None if none is
+    // When the quiet hours that just started end, like `07:00`. None once they're over or an
+    // operator lifted them.
+    QuietHoursChanged(Option<String>),
+}
+
+/**
+ * Launch events a subscriber missed, or waited for
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Subscription {
+    /// Set if events after the requested one were dropped, or the numbering started over. The
+    /// client should refresh everything once, then carry on from the last event here.
+    pub gap: Option<GapDetected>,
+    /// Oldest first
+    pub events: Vec<LaunchEvent>,
+}
+
+/**
+ * The events a subscriber asked for aren't all kept anymore
+ */
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
+pub struct GapDetected {
+    /// The sequence number of the oldest event that's still kept
+    pub oldest_available: u64,
+}
+
+/**
+ * A launch event, numbered in the order events happened
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct LaunchEvent {
+    /// Increases by one with every event, starting at 1
+    pub seq: u64,
+    pub kind: LaunchEventKind,
+    /// The event label active when it happened
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub event: Option<String>,
+}
+
+/**
+ * A label play sessions and launch events are tagged with during an event, like a showcase
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Default, Eq, PartialEq)]
+pub struct EventLabel {
+    pub label: String,
+    /// When it was set, as a unix timestamp in seconds
+    pub set: u64,
+    /// When it's cleared, as a unix timestamp in seconds
+    pub expires: u64,
+}
+
+/**
+ * Games to feature over a range of days, like a themed week, and games to hide from the menu
+ * meanwhile. Dates are local and `YYYY-MM-DD`, and the range includes both.
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Default, Eq, PartialEq)]
+pub struct ScheduleEntry {
+    /// Identifies the entry, so it can be replaced or removed
+    pub name: String,
+    pub start: String,
+    pub end: String,
+    /// Games with this tag are featured
+    #[serde(default)]
+    pub featured_tag: Option<String>,
+    /// IDs of games featured along with the tag's
+    #[serde(default)]
+    pub featured_games: Vec<String>,
+    /// IDs of games hidden from the menu until the entry ends
+    #[serde(default)]
+    pub blocked_games: Vec<String>,
+}
+
+/**
+ * The schedule of featured games. When entries overlap, the one with the shortest range is
+ * active.
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Default, Eq, PartialEq)]
+pub struct Schedule {
+    /// Sorted by start date
+    pub entries: Vec<ScheduleEntry>,
+    /// Name of the entry active today
+    pub active: Option<String>,
+    /// Tags and games the entries refer to that the cabinet doesn't know of
+    pub warnings: Vec<String>,
+}
+
+/**
+ * A game's sessions added up over its lifetime
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Default, Eq, PartialEq)]
+pub struct LifetimeStats {
+    pub plays: u64,
+    pub seconds: u64,
+    /// When the first and last sessions started, as unix timestamps in seconds
+    pub first_played: u64,
+    pub last_played: u64,
+    /// Plays by demo guests, which are also counted in `plays`
+    #[serde(default)]
+    pub demo_plays: u64,
+}
+
+/**
+ * What compacting the session log did
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Default, Eq, PartialEq)]
+pub struct StatsCompaction {
+    /// When it ran, as a unix timestamp in seconds
+    pub at: u64,
+    /// Sessions that started before this are only kept in the lifetime totals
+    pub before: u64,
+    /// How many sessions were folded into the lifetime totals
+    pub sessions: u64,
+    /// How many games they were of
+    pub games: u64,
+}
+
+/**
+ * The logged sessions that started in a range of time
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct SessionExport {
+    pub start: u64,
+    pub end: u64,
+    /// If part of the range was compacted, the end of that part. Sessions from `start` up to this
+    /// aren't in `sessions`, they're only counted in `compacted`.
+    pub aggregate_only_until: Option<u64>,
+    /// The totals of every compacted session by game ID, whether or not it's in the range
+    pub compacted: BTreeMap<String, LifetimeStats>,
+    /// Sessions as they were logged
+    pub sessions: Vec<Map<String, Value>>,
+}
+
+/**
+ * How downloading one game's icon and banner went, for `DownloadAllAssets`
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Default, Eq, PartialEq)]
+pub struct AssetResult {
+    pub game_id: String,
+    /// Why the icon couldn't be downloaded, or `None` if it's in place
+    pub icon_error: Option<String>,
+    /// Why the banner couldn't be downloaded, or `None` if it's in place
+    pub banner_error: Option<String>,
+}
+
+/**
+ * Highlights of a game's sessions, kept up to date from the session summaries it submits
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct GameHighlights {
+    /// The highest `score` submitted
+    pub high_score: Option<f64>,
+    /// The local date (`YYYY-MM-DD`) `day_high_score` is from
+    pub day: String,
+    /// The highest `score` submitted on `day`
+    pub day_high_score: Option<f64>,
+    /// Sessions that submitted a summary
+    pub sessions: u64,
+    /// Sessions whose summary had `completed` set to true
+    pub completed: u64,
+    /// Sessions whose summary had `result` set to `win`
+    pub wins: u64,
+}
+
+/**
+ * What an installed game needs to run, detected when it was installed
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Default, Eq, PartialEq)]
+pub struct GameRuntime {
+    /// CPU architecture of the executable (e.g. `x86_64`), None if it isn't an ELF file
+    pub architecture: Option<String>,
+    /// Runtimes and engines the game was built with (e.g. `Godot`, `SDL2`), from its files
+    pub runtimes: Vec<String>,
+    /// Shared libraries the executable needs that the cabinet didn't have
+    pub missing_libraries: Vec<String>,
+    /// How the executable was found the last time the game was launched
+    pub launch_strategy: Option<String>,
+    /// Whether the last launch worked, None if the game hasn't been launched
+    pub launched: Option<bool>,
+}
+
+/**
+ * An installed game as it was when the catalog was frozen
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Default, Eq, PartialEq)]
+pub struct FrozenGame {
+    pub id: String,
+    /// The hash of the installed game, from its `game.json`
+    pub hash: String,
+    /// The SHA-256 hash of the install's `manifest.json`, which lists the hash of every file.
+    /// None for installs from before manifests were written.
+    pub manifest_digest: Option<String>,
+}
+
+/**
+ * The installed games at the moment the catalog was frozen, signed with the `freeze_key` secret
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Default, Eq, PartialEq)]
+pub struct CatalogSnapshot {
+    pub label: String,
+    /// When the catalog was frozen, in seconds since the Unix epoch
+    pub created: u64,
+    /// Sorted by ID
+    pub games: Vec<FrozenGame>,
+    /// HMAC-SHA256 of the rest of the snapshot, in hex
+    pub signature: String,
+}
+
+/**
+ * What the running game and the rest of its process group were using at one moment
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct ResourceSample {
+    /// When the sample was taken, in seconds since the Unix epoch
+    pub at: u64,
+    /// Resident memory in bytes
+    pub rss_bytes: u64,
+    /// CPU time used since the last sample, 100 is one core
+    pub cpu_percent: f64,
+    pub open_fds: u64,
+    pub threads: u64,
+    /// Processes in the game's process group
+    pub processes: u64,
+}
+
+/**
+ * The resource usage of the running game
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct GameResources {
+    pub game_id: String,
+    /// Recent samples, oldest first. The last one is the latest.
+    pub history: Vec<ResourceSample>,
+    /// The highest of each value this session. `at` is when the last of them was reached.
+    pub peak: ResourceSample,
+}
+
+/**
+ * The installed games, or what changed in them since a generation the client already has. The
+ * generation goes up by one whenever the installed games change.
+ */
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum LibraryUpdate {
+    /// Nothing changed since the client's generation
+    NotModified { generation: u64 },
+    /// The games added or changed since the client's generation, and the IDs of those removed
+    Delta {
+        generation: u64,
+        changed: Vec<DevcadeGame>,
+        removed: Vec<String>,
+    },
+    /// Every game, when the client's generation is too old or unknown
+    Full {
+        generation: u64,
+        games: Vec<DevcadeGame>,
+    },
+}
+
+/**
+ * Whether the cabinet still needs its first-run setup
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Default, Eq, PartialEq)]
+pub struct SetupStatus {
+    /// Whether setup has to be completed before games can be listed
+    pub required: bool,
+    /// Settings that have to be set before setup can be completed
+    pub missing: Vec<String>,
+    /// Settings set with `SetConfigValue` that `CompleteSetup` will write
+    pub pending: Vec<String>,
+}
+
+/**
+ * How well one of the backend's caches is doing
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct CacheReport {
+    pub name: String,
+    pub entries: u64,
+    /// Roughly how much the entries take, in memory or on disk
+    pub bytes: u64,
+    /// Counts since startup
+    pub hits: u64,
+    pub misses: u64,
+    /// Lookups answered with an outdated entry because the source couldn't be reached
+    pub stale: u64,
+    /// Entries replaced or dropped
+    pub evictions: u64,
+    /// Lookups in the last hour, of any kind
+    pub hour_lookups: u64,
+    /// Share of the last hour's lookups that were hits, `None` if there weren't any
+    pub hour_hit_ratio: Option<f64>,
+}
+
+/**
+ * An installed game in a group of duplicates
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Default, Eq, PartialEq)]
+pub struct DuplicateInstall {
+    pub id: String,
+    pub name: String,
+    /// Size of the install's files in bytes
+    pub size: u64,
+    /// When it was last launched, as a unix timestamp in seconds. None if it never was.
+    pub last_launched: Option<u64>,
+}
+
+/**
+ * Installed games with the same or mostly the same files
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct DuplicateGroup {
+    /// Whether every game in the group has exactly the same files
+    pub exact: bool,
+    /// The lowest share of bytes two linked games in the group have in common, from 0 to 1
+    pub similarity: f64,
+    pub games: Vec<DuplicateInstall>,
+}
+
+/**
+ * Which backend features a game has used, over all of its sessions
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Default, Eq, PartialEq)]
+pub struct FeatureUsage {
+    /// Kinds of requests the game sent to the persistence server, like `saves` or
+    /// `session summary`
+    pub requests: BTreeSet<String>,
+    /// `DEVCADE_*` variables the game's files mention, so it likely reads them
+    pub env_vars: BTreeSet<String>,
+    /// Hash of the install `env_vars` was found in
+    pub scanned_hash: String,
+    /// Sessions the usage was collected over
+    pub sessions: u64,
+    /// Times the game was disconnected for flooding the persistence socket with requests
+    #[serde(default)]
+    pub misbehaved: u64,
+}
+
+/**
+ * How many games have used each backend feature
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Default, Eq, PartialEq)]
+pub struct FeatureAdoption {
+    /// Games that were played at least once, which the counts are out of
+    pub games: u64,
+    /// Games by request kind
+    pub requests: BTreeMap<String, u64>,
+    /// Games by `DEVCADE_*` variable
+    pub env_vars: BTreeMap<String, u64>,
+}
+
+/**
+ * How soon a queued game download is needed. Higher priorities are downloaded first.
+ */
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, Eq, PartialEq, Ord, PartialOrd)]
+pub enum DownloadPriority {
+    Interactive, // A player picked the game
+    #[default]
+    Normal,
+    Background, // Like updates of installed games
+}
+
+/**
+ * A game download in the download queue
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct DownloadJob {
+    pub id: u64,
+    pub game_id: String,
+    pub priority: DownloadPriority,
+    /// Why the download failed, if it did
+    pub error: Option<String>,
+}
+
+/**
+ * The jobs in the download queue
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Default, Eq, PartialEq)]
+pub struct DownloadQueueState {
+    /// Jobs waiting for a download slot, in the order they'll start
+    pub pending: Vec<DownloadJob>,
+    pub active: Vec<DownloadJob>,
+    /// The most recent completed jobs, oldest first
+    pub completed: Vec<DownloadJob>,
+    /// The most recent failed jobs, oldest first
+    pub failed: Vec<DownloadJob>,
+    /// The most recent conflicts between operations on the same game, oldest first
+    #[serde(default)]
+    pub decisions: Vec<OperationDecision>,
+}
+
+/**
+ * What an operation does to a game
+ */
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum GameIntent {
+    Install,
+    Update,
+    Remove,
+    Verify,
+}
+
+/**
+ * Who's waiting on an operation on a game, most urgent first
+ */
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub enum OperationOrigin {
+    Launch,     // A player launched the game
+    Manual,     // A frontend or operator asked for it
+    Background, // Like auto-update, retirement and catalog events
+}
+
+/**
+ * An operation on a game, as the operation arbiter sees it
+ */
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
+pub struct GameOperation {
+    pub intent: GameIntent,
+    pub origin: OperationOrigin,
+}
+
+impl Display for GameOperation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let origin = match self.origin {
+            OperationOrigin::Launch => "launch-triggered",
+            OperationOrigin::Manual => "manual",
+            OperationOrigin::Background => "background",
+        };
+        let intent = match self.intent {
+            GameIntent::Install => "install",
+            GameIntent::Update => "update",
+            GameIntent::Remove => "removal",
+            GameIntent::Verify => "verification",
+        };
+        write!(f, "{origin} {intent}")
+    }
+}
+
+/**
+ * What the operation arbiter did about an operation that conflicted with one in progress on the
+ * same game
+ */
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Arbitration {
+    Queue,     // It waited for the one in progress to finish
+    Supersede, // It replaced the one in progress
+    Join,      // The one in progress did it instead
+    Reject,    // It was refused
+}
+
+/**
+ * A conflict between two operations on the same game, and what was done about it
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct OperationDecision {
+    /// When it was decided, in seconds since the Unix epoch
+    pub at: u64,
+    pub game_id: String,
+    pub requested: GameOperation,
+    /// The operation that was in progress
+    pub active: GameOperation,
+    pub arbitration: Arbitration,
+}
+
+impl Display for OperationDecision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self {
+            game_id,
+            requested,
+            active,
+            ..
+        } = self;
+        match self.arbitration {
+            Arbitration::Queue => write!(f, "{requested} of {game_id} queued behind {active}"),
+            Arbitration::Supersede => {
+                write!(f, "{active} of {game_id} superseded by {requested}")
+            }
+            Arbitration::Join => write!(f, "{requested} of {game_id} joined {active}"),
+            Arbitration::Reject => write!(
+                f,
+                "{requested} of {game_id} rejected, {active} is in progress"
+            ),
+        }
+    }
+}
+
+/**
+ * The state of burn-in protection, and how long the display sat static each day
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Default, Eq, PartialEq)]
+pub struct DisplayProtection {
+    /// Whether the cabinet has been idle long enough that the frontend should dim or shift the
+    /// screen
+    pub suggested: bool,
+    /// Whether the backlight was dimmed with the configured command
+    pub dimmed: bool,
+    /// Seconds since the last activity
+    pub idle_secs: u64,
+    /// Seconds the display sat static, by local date (`YYYY-MM-DD`)
+    pub static_secs: BTreeMap<String, u64>,
+    /// IDs of the games to show off while idle, from the active schedule entry. Empty if the
+    /// frontend should pick them itself.
+    #[serde(default)]
+    pub attract: Vec<String>,
+}
+
+/**
+ * What a cycle of the auto-updater did
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Default, Eq, PartialEq)]
+pub struct UpdateSummary {
+    /// When the cycle finished, in seconds since the Unix epoch
+    pub finished: u64,
+    /// IDs of the games that were updated
+    pub updated: Vec<String>,
+    /// Why games couldn't be updated, by game ID
+    pub failed: BTreeMap<String, String>,
+    /// IDs of out of date games left for the next cycle, because a game was launched
+    pub deferred: Vec<String>,
+    /// IDs of games whose hash changed without a new upload date, which isn't expected of a real
+    /// update. Whether they were updated depends on `DEVCADE_SUSPICIOUS_UPDATE_POLICY`.
+    #[serde(default)]
+    pub hash_changed_without_new_version: Vec<String>,
+}
+
+/**
+ * An installed game whose hash changed in the API without a new upload date
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Default, Eq, PartialEq)]
+pub struct SuspiciousUpdate {
+    pub game_id: String,
+    /// The hash of the installed copy
+    pub installed_hash: String,
+    /// The hash the API reports
+    pub api_hash: String,
+    /// The upload date both share
+    pub upload_date: String,
+    /// When the change was first seen, in seconds since the Unix epoch
+    pub first_seen: u64,
+    /// Whether an operator confirmed the update, so it's installed on the next cycle
+    pub confirmed: bool,
+}
+
+/**
+ * What `DownloadGame` did to get a game installed
+ */
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum InstallKind {
+    AlreadyInstalled, // The installed version was already current
+    Updated,          // An older version was replaced
+    FreshInstall,     // The game wasn't installed before
+}
+
+/**
+ * The game installed by `DownloadGame`, and what it took to install it
+ */
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct InstallOutcome {
+    pub game: DevcadeGame,
+    pub kind: InstallKind,
+    /// How the game's setup step went, if it has one and it was run
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub setup: Option<GameSetupResult>,
+}
+
+/**
+ * How a game's setup step went
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub enum GameSetupResult {
+    Completed { seconds: u64 },
+    // Why it failed. The game is still installed, and launching it warns instead of waiting on a
+    // setup that didn't happen.
+    SetupFailed(String),
+}
+
+/**
+ * The last run of a game's setup step, kept in `setup.json` in the game's directory
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct GameSetupRecord {
+    /// The hash of the version of the game it was run for
+    pub hash: String,
+    /// When it finished, as a unix timestamp in seconds
+    pub at: u64,
+    pub result: GameSetupResult,
+    /// Where its output was written
+    pub log: String,
+}
+
+/**
+ * What installing a game would take, so players can decide before committing to a download
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Default, Eq, PartialEq)]
+pub struct DownloadEstimate {
+    /// The game the estimate is for
+    pub game_id: String,
+    /// The size of the game's archive in bytes, if known
+    pub size: Option<u64>,
+    /// About how long the download would take in seconds, if there's a throughput to go by
+    pub seconds: Option<u64>,
+    /// Free space on the cabinet's disk in bytes, if it could be checked
+    pub free_bytes: Option<u64>,
+    /// Whether the game probably won't fit without removing other games
+    pub needs_space: bool,
+    /// Whether the API couldn't be reached, so the size is from an earlier check
+    pub stale: bool,
+}
+
+/**
+ * The stages of installing a game
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub enum DownloadStage {
+    /// The download is waiting for other game downloads to finish
+    Queued {
+        /// How many downloads are running or queued ahead of this one
+        ahead: usize,
+        /// About how many seconds until this download starts, if there's a throughput to go by
+        seconds: Option<u64>,
+    },
+    /// The archive is being downloaded
+    Downloading,
+    /// The archive's hash and signature are being checked
+    Verifying,
+    /// The archive is being unzipped
+    Extracting {
+        /// How many archive entries have been handled
+        entries_extracted: usize,
+        /// How many entries the archive has
+        total_entries: usize,
+    },
+    /// The game's setup step is running
+    RunningSetup,
+}
+
+/**
+ * Game icons packed into one or more atlas images, so the frontend can load them all at once
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Default, Eq, PartialEq)]
+pub struct IconAtlas {
+    /// Paths to the atlas images
+    pub atlases: Vec<String>,
+    /// Where each game's icon is in the atlases
+    pub icons: Vec<AtlasIcon>,
+}
+
+/**
+ * The game list along with small thumbnails of the games' icons, so the whole menu can be drawn
+ * before the full icons are loaded
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct GameListWithThumbnails {
+    pub games: Vec<DevcadeGame>,
+    /// Base64 encoded PNG thumbnails by game ID. Games without an icon on disk, and games past the
+    /// size limit of one response, are left out.
+    pub thumbnails: BTreeMap<String, String>,
+}
+
+/**
+ * The position of one game's icon in an [`IconAtlas`]
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct AtlasIcon {
+    pub game_id: String,
+    /// Index into [`IconAtlas::atlases`]
+    pub atlas: usize,
+    pub x: u32,
+    pub y: u32,
+    pub w: u32,
+    pub h: u32,
+}
+
+/**
+ * Totals over the games with a tag or by an author, from what the cabinet has seen locally
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Default, Eq, PartialEq)]
+pub struct CatalogStats {
+    /// Games with the tag or by the author. For a tag this counts games that aren't installed, for
+    /// an author only installed games.
+    pub games: u64,
+    /// Seconds the games were played on this cabinet
+    pub seconds_played: u64,
+    /// Seconds the games were played on this cabinet in the last 7 days
+    pub recent_seconds_played: u64,
+    /// ID of the game played the longest, None if none were played
+    pub most_played: Option<String>,
+    /// ID of the installed game uploaded last, None if none are installed
+    pub newest_upload: Option<String>,
+}
+
+/**
+ * The IDs of the games with each tag, so games can be filtered by tag without the API
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Default, Eq, PartialEq)]
+pub struct TagMembership {
+    /// Game IDs by tag name
+    pub tags: BTreeMap<String, Vec<String>>,
+    /// Unix timestamp in seconds when this was fetched from the API
+    pub fetched: u64,
+    /// Whether this is older than it should be, because the API couldn't be reached to refresh it
+    pub stale: bool,
+}
+
+/**
+ * Information about the cabinet that games and the frontend should agree on
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct CabinetInfo {
+    /// The cabinet's name, if configured
+    #[serde(default)]
+    pub name: Option<String>,
+    /// The cabinet's locale (e.g. `en_US`), if configured
+    pub locale: Option<String>,
+    /// The cabinet's timezone (e.g. `America/New_York`), if configured
+    pub timezone: Option<String>,
+    /// The cabinet's display, audio and controller setup
+    pub hardware: HardwareProbe,
+    /// Whether the backend booted into safe mode after repeatedly failing to start
+    pub safe_mode: bool,
+    /// How much work the backend takes on, for the cabinet's memory
+    pub resource_profile: ResourceProfile,
+    /// Optional work the backend skips with its resource profile
+    pub skipped: Vec<Capability>,
+    /// Runtimes and engines of games that have launched successfully on this cabinet
+    pub supported_runtimes: Vec<String>,
+    /// Whether the frontend reports how much each control of a game is used
+    pub input_telemetry: bool,
+    /// Whether the backend hears about catalog changes as they happen
+    #[serde(default)]
+    pub catalog_events: CatalogEvents,
+    /// How each of the hooks in `DEVCADE_EVENT_HOOKS` has done since startup
+    #[serde(default)]
+    pub event_hooks: Vec<EventHookStatus>,
+    /// Which wire formats clients have connected with since startup
+    #[serde(default)]
+    pub protocol: ProtocolStats,
+    /// What the last maintenance of the shared file store found, or `None` if it hasn't run since
+    /// startup
+    #[serde(default)]
+    pub object_store: Option<ObjectStoreReport>,
+    /// The cabinet's quiet hours, and whether it's in them
+    #[serde(default)]
+    pub quiet_hours: QuietHoursStatus,
+    /// The lock that keeps a second backend from running, `None` if this backend doesn't hold it
+    #[serde(default)]
+    pub instance_lock: Option<InstanceLockInfo>,
+}
+
+/**
+ * The lock a backend holds so no other backend runs against the same devcade directory
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Default, Eq, PartialEq)]
+pub struct InstanceLockInfo {
+    pub path: String,
+    /// The PID of the backend holding it
+    pub pid: u32,
+    /// When it was taken, as a unix timestamp in seconds
+    pub acquired: u64,
+}
+
+/**
+ * The cabinet's quiet hours, during which games can't be launched
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Default, Eq, PartialEq)]
+pub struct QuietHoursStatus {
+    /// The windows, like `22:00-07:00,Fri 23:00-03:00`, or `None` if there are no quiet hours
+    pub schedule: Option<String>,
+    /// When the current quiet hours end (`HH:MM` local time), or `None` outside of them
+    pub until: Option<String>,
+    /// Whether an operator lifted the current quiet hours
+    pub overridden: bool,
+}
+
+/**
+ * How many connections to the backend's sockets used each wire format since startup
+ */
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ProtocolStats {
+    /// Connections that sent newline-delimited JSON, which a future release will stop accepting
+    pub legacy_connections: u64,
+    /// Connections that sent length-prefixed frames
+    pub framed_connections: u64,
+    /// Whether newline-delimited JSON is still accepted, see `DEVCADE_LEGACY_PROTOCOL`
+    pub legacy_allowed: bool,
+}
+
+/**
+ * What a maintenance pass over the shared file store did
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Default, Eq, PartialEq)]
+pub struct ObjectStoreReport {
+    /// When it ran, as a unix timestamp in seconds
+    pub at: u64,
+    /// How many files were in the store, and their size in bytes, after unused ones were removed
+    pub objects: u64,
+    pub bytes: u64,
+    /// Files no installed game used anymore that were removed, and the space that freed
+    pub reclaimed_objects: u64,
+    pub reclaimed_bytes: u64,
+    /// How many files were hashed to check they're still intact
+    pub sampled: u64,
+    /// Hashes of the checked files whose contents no longer match
+    pub corrupted: Vec<String>,
+    /// IDs of the games downloaded again because they used a corrupted file
+    pub repaired: Vec<String>,
+}
+
+/**
+ * How an event hook has done since startup
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Default, Eq, PartialEq)]
+pub struct EventHookStatus {
+    pub name: String,
+    /// The type of launch event it runs on, like `DownloadFinished`
+    pub event: String,
+    pub runs: u64,
+    /// Runs that exited unsuccessfully or couldn't be started
+    pub failures: u64,
+    /// Runs that were stopped for going over the hook's timeout
+    pub timeouts: u64,
+    /// Events it didn't run on, because too many runs were already waiting
+    pub skipped: u64,
+    /// Why the last run that didn't succeed failed
+    pub last_error: Option<String>,
+}
+
+/**
+ * How a run of an event hook went
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub enum EventHookOutcome {
+    Succeeded,
+    Failed(String), // Why
+    TimedOut,
+}
+
+/**
+ * The hooks of a type of launch event, run on a sample event
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct EventHookTest {
+    /// The sample event, as the hooks got it on stdin
+    pub event: LaunchEvent,
+    /// How each hook's run went, by hook name
+    pub runs: Vec<(String, EventHookOutcome)>,
+}
+
+/**
+ * The backend's connection to the API's catalog change notifications
+ */
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct CatalogEvents {
+    pub state: CatalogEventsState,
+    /// How many notifications were handled since startup
+    pub notifications: u64,
+}
+
+/**
+ * Whether catalog change notifications are coming in. Polling carries on either way.
+ */
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum CatalogEventsState {
+    #[default]
+    Disabled, // Turned off with DEVCADE_CATALOG_EVENTS
+    Connecting,
+    Connected,
+    Reconnecting, // The connection was lost, waiting to try again
+    Unavailable,  // The API doesn't send notifications
+}
+
+/**
+ * A player tapped in on a cabinet, as shared with the cabinet next to it
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
+pub struct PeerPlayer {
+    /// The player's association ID, only if they agreed to share it
+    pub association_id: Option<String>,
+    /// Whether the player agreed to share that they're playing
+    pub consent: bool,
+}
+
+/**
+ * What a cabinet shares with the cabinet next to it, so games running on both can find each other
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Default, Eq, PartialEq)]
+pub struct PeerStatus {
+    /// The ID of the game running on the cabinet, if any
+    pub running_game: Option<String>,
+    /// The players tapped in on the cabinet
+    pub players: Vec<PeerPlayer>,
+    /// A token for the running game's session, which games on both cabinets can meet with
+    pub rendezvous: Option<String>,
+    /// When the status was sent, in seconds since the Unix epoch
+    pub sent: u64,
+}
+
+/**
+ * What the backend knows about the cabinet next to it
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Default, Eq, PartialEq)]
+pub struct PeerLink {
+    /// The peer's status, or `None` if nothing recent was heard from it
+    pub status: Option<PeerStatus>,
+    /// When the peer was last heard from, in seconds since the Unix epoch
+    pub last_seen: Option<u64>,
+}
+
+/**
+ * A secret kept by the backend, without its value
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Default, Eq, PartialEq)]
+pub struct SecretInfo {
+    pub name: String,
+    /// When the secret was first set, in seconds since the Unix epoch
+    pub created: u64,
+    /// When the secret was last replaced, if it has been
+    pub rotated: Option<u64>,
+}
+
+/**
+ * How much a game's controls were used over a day
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Default, Eq, PartialEq)]
+pub struct InputActivity {
+    /// Minutes of play that were reported
+    pub minutes: u64,
+    /// Inputs on each control
+    pub controls: BTreeMap<String, u64>,
+}
+
+/**
+ * How much work the backend takes on. Low memory cabinets skip optional work and do less at once.
+ */
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ResourceProfile {
+    #[default]
+    Full,
+    Low,
+}
+
+/**
+ * Optional work the backend can skip, depending on its resource profile
+ */
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Capability {
+    IconAtlas, // GetIconAtlas, load the icons one by one instead
+}
+
+/**
+ * The hardware details of the cabinet, for games that need to calibrate (e.g. rhythm games)
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct CabinetHardware {
+    /// The display's current mode, if it could be detected
+    pub display: Option<DisplayMode>,
+    /// The audio output's sample rate in Hz, if configured
+    pub audio_sample_rate: Option<u32>,
+    /// The measured audio output latency in milliseconds, if configured
+    pub audio_latency_ms: Option<i32>,
+    /// An identifier for the cabinet's controller layout, if configured
+    pub controller_mapping: Option<String>,
+}
+
+/**
+ * A display resolution and refresh rate
+ */
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct DisplayMode {
+    pub width: u32,
+    pub height: u32,
+    /// The refresh rate in Hz, if known
+    pub refresh_rate: Option<f64>,
+}
+
+/**
+ * The result of probing the cabinet's hardware, which happens in the background at startup
+ */
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub enum HardwareProbe {
+    /// The probe hasn't finished yet, ask again later
+    #[default]
+    Pending,
+    Ready(CabinetHardware),
+}
+
+/**
+ * The version of the protocol the backend speaks. Clients that negotiate version 1 get games in
+ * lists and details in the legacy shape, which older frontends read.
+ */
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/**
+ * The largest frame (one serialized request or response, including the trailing newline) that
+ * either side of the socket will accept. Frames larger than this are discarded by the reader.
+ */
+pub const MAX_FRAME_SIZE: usize = 4 * 1024 * 1024;
+
+/**
+ * Serialize a message into a frame that can be written to the socket. Frames are a single line of
+ * JSON terminated by a newline.
+ *
+ * # Errors
+ * This function will return an error if the message cannot be serialized, or if the serialized
+ * frame is larger than [`MAX_FRAME_SIZE`].
+ */
+pub fn to_frame<T: Serialize>(message: &T) -> Result<Vec<u8>, Error> {
+    let mut frame = serde_json::to_vec(message)?;
+    frame.push(b'\n');
+    if frame.len() > MAX_FRAME_SIZE {
+        return Err(anyhow::anyhow!(
+            "Frame of {} bytes is larger than the maximum of {MAX_FRAME_SIZE} bytes",
+            frame.len()
+        ));
+    }
+    Ok(frame)
+}
+
+/**
+ * The wire formats the backend's sockets speak. Which one a connection uses is told by its first
+ * byte: a framed connection starts with a length, whose first byte is always 0 since frames are
+ * smaller than 16 MiB, and a line of JSON never starts with 0.
+ */
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Dialect {
+    #[default]
+    Legacy, // Newline-delimited JSON, as written by `to_frame`
+    Framed, // JSON prefixed with its length, as a 4 byte big-endian integer
+}
+
+impl Dialect {
+    /**
+     * Serialize a message into a frame of this dialect
+     *
+     * # Errors
+     * This function will return an error if the message cannot be serialized, or if the frame is
+     * larger than [`MAX_FRAME_SIZE`].
+     */
+    pub fn frame<T: Serialize>(self, message: &T) -> Result<Vec<u8>, Error> {
+        match self {
+            Self::Legacy => to_frame(message),
+            Self::Framed => {
+                let json = serde_json::to_vec(message)?;
+                if json.len() + 4 > MAX_FRAME_SIZE {
+                    return Err(anyhow::anyhow!(
+                        "Frame of {} bytes is larger than the maximum of {MAX_FRAME_SIZE} bytes",
+                        json.len() + 4
+                    ));
+                }
+                let mut frame = Vec::with_capacity(json.len() + 4);
+                // Can't overflow, since it was just checked against MAX_FRAME_SIZE
+                frame.extend_from_slice(&(json.len() as u32).to_be_bytes());
+                frame.extend_from_slice(&json);
+                Ok(frame)
+            }
+        }
+    }
+}
+
 /**
  * A request received by the backend from the frontend.
  */
@@ -61,32 +1221,148 @@ pub enum RequestBody {
     // --- Onboard backend ---
     GetGameList,
     GetGameListFromFs,
-    GetGame(String),        // String is the game ID
-    DownloadGame(String),   // String is the game ID
-    DownloadIcon(String),   // String is the game ID
-    DownloadBanner(String), // String is the game ID
+    GetLibrary(Option<u64>), // The generation the client has, None to get every game
+    GetGame(String),         // String is the game ID
+    DownloadGame(String),    // String is the game ID
+    DownloadIcon(String),    // String is the game ID
+    DownloadBanner(String),  // String is the game ID
+    DownloadAllAssets,       // Icons and banners of every listed game that are missing
+    GetIconAtlas(u32, u32),  // Largest atlas size and icon size in pixels
+    GetGameListWithThumbnails, // The game list with thumbnails of the installed icons inline
+    GetDownloadProgress(String), // String is the game ID
+    GetDownloadEstimate(String), // String is the game ID
+    CleanupOrphanedGames(bool), // Remove games the API no longer has. True for a dry run
+    CancelOrphanCleanup,     // Stop a running CleanupOrphanedGames before the next game
+    GetDuplicateInstalls,    // Installed games with the same or mostly the same files
+    GetCacheStats,           // Hits, misses and size of each of the backend's caches
+    GetLaunchEvents(u64),    // Launch events with a sequence number after this one
+    // Launch events after this sequence number, or None for only new ones. Waits for one if
+    // there are none yet.
+    Subscribe(Option<u64>),
+    // The protocol version the client speaks, answered with the one the connection will use
+    Negotiate(u32),
+    CancelDownload(String), // String is the game ID
+    // Queue a game download, responds with the job ID. Queueing a game again reuses its job.
+    EnqueueDownload(String, DownloadPriority),
+    PromoteDownload(u64), // Move a queued download (by job ID) to the front
+    GetDownloadQueue,
+    // Games declaring all of the flags. Undeclared games never match.
+    GetGameListWithAccessibility(Vec<AccessibilityFlag>),
 
     GetTagList,
     GetTag(String),             // String is the tag name
     GetGameListFromTag(String), // String is the tag name
+    GetTagMembership(bool),     // True to refresh from the API even if it isn't old yet
+    GetTagCounts,               // Game counts of every tag, from the cached membership
+    GetTagStats(String),        // String is the tag name
+    GetAuthorStats(String),     // String is the author's username
 
     GetUser(String), // String is the user ID
 
     SetProduction(bool), // Sets prod / dev api url
+    ReloadTls,           // Re-reads the CA bundle used for the api
+    // Bytes per second for game downloads. 0 is unlimited, None restores DEVCADE_MAX_DOWNLOAD_BPS
+    SetDownloadLimit(Option<u64>),
+    // Module (None for all), Level (None clears the override), Expiry in seconds
+    SetLogLevel(Option<String>, Option<String>, Option<u64>),
+    GetLogLevels,
+    SetCapture(bool, Option<u64>), // Whether to capture commands, for how many seconds
+    // Flags every listed and launched game must declare.
+    // None restores DEVCADE_REQUIRED_ACCESSIBILITY
+    SetRequiredAccessibility(Option<Vec<AccessibilityFlag>>),
+    SaveProfile(String),  // String is the profile name
+    ApplyProfile(String), // String is the profile name
+    ListProfiles,
+    DeleteProfile(String), // String is the profile name
+
+    GetCabinetInfo,
+    GetSetupStatus,
+    SetConfigValue(String, String), // Setting and value, only while setup is required
+    TestApiConnection,              // Lists games from the API being set up
+    CompleteSetup,                  // Writes the settings and leaves setup
+    GetCabinetHardware,             // Also available to games
+    GetCabinetSetting(String),      // Key. Also available to games
+    // Key, Value (None removes it). Games can only send this if operators allowed them to
+    SetCabinetSetting(String, Option<String>),
+    ProbeHardware, // Re-detects the cabinet's hardware after it changed
+    // Hand the running game, downloads and library generation over to the next backend and exit
+    // to be restarted
+    PrepareRestart,
+    RunGameSetup(String), // Runs an installed game's setup step again. String is the game ID
+    // Runs the hooks of a type of launch event, like `DownloadFinished`, on a sample event
+    TestEventHooks(String),
+    // Removes files no installed game uses from the shared file store, and checks a sample of the
+    // rest for corruption
+    MaintainObjectStore,
+    GetSchedule, // The schedule of featured games, and which entry is active
+    // Adds an entry to the schedule, replacing the entry with the same name
+    AddScheduleEntry(ScheduleEntry),
+    RemoveScheduleEntry(String), // String is the entry's name
+    GetQuietHours,
+    // Replaces the quiet hours windows, like `22:00-07:00,Fri 23:00-03:00`. None goes back to
+    // `DEVCADE_QUIET_HOURS`.
+    SetQuietHours(Option<String>),
+    OverrideQuietHours(bool), // Allows launching games until the current quiet hours end
 
-    LaunchGame(String), // String is the game
+    LaunchGame(String),               // String is the game
+    LaunchGameIgnoringPolicy(String), // Launch even if the accessibility policy forbids it
+    LaunchGameSharingSaves(String),   // Launch a sideloaded game with the API copy's saves
+    CaptureScreenshot,                // Screenshot the running game
+    PauseGame,                        // Pause the running game
+    ResumeGame,                       // Resume the running game
+    GetHighlights,                    // Highlights of every game with session summaries
+    GetFeaturedGames,                 // Installed games the active schedule entry features
+    GetEventHighlights(String),       // Highlights of sessions played during an event
+    GetEventLabel,
+    SetEventLabel(Option<String>),              // None clears the label
+    GetLifetimeStats,                           // Every game's sessions added up
+    ExportSessions(u64, u64), // Start and end of the range as unix timestamps in seconds
+    CompactStats(u64),        // Days of sessions to keep in the log
+    GetGameRuntime(String),   // What an installed game needs to run. String is the game ID
+    GetUpdateSummary,         // What the last auto-update cycle did
+    UserActivity,             // Someone is using the cabinet, ends display protection
+    ReportInputActivity(BTreeMap<String, u64>), // Inputs on each control in the last minute
+    GetInputActivity(String), // How much a game's controls were used. String is the game ID
+    SetSecret(String, String), // Name and value. The value is never sent back or logged
+    RotateSecret(String),     // Replace a secret the backend generates, like a salt
+    ListSecrets,              // Names and ages of the secrets, without their values
+    GetDisplayProtection,     // Whether display protection is suggested
+    GetPeerStatus,            // What the cabinet next to this one is doing
+    // Association ID, whether the player agrees to the cabinet next to this one seeing their ID
+    SetPeerConsent(String, bool),
+    GetSuspiciousUpdates, // Games whose hash changed without a new upload date
+    GetGameResources,     // CPU and memory of the running game
+    GetFeatureUsage(String), // String is the game ID
+    GetFeatureAdoption,   // How many games use each feature
+    FreezeCatalog(String), // Snapshot the installed games and refuse changes. String is the label
+    Unfreeze,             // Allow installed games to change again
+    GetCatalogFreeze,     // The label of the active freeze
+    VerifyFreeze(String), // Check the installed games against a snapshot. String is the label
+    ConfirmSuspiciousUpdate(String), // Let auto-update install a suspicious update. String is ID
     // ---
 
     // --- Persistence ---
     Save(String, String, String), // Group, Key, Value
     Load(String, String),         // Group, Key
     Flush,
+    // Streamed saves for values too large for a single frame. BeginSave responds with an Object
+    // containing a stream ID, which chunks are appended to. The value is only visible once
+    // committed.
+    BeginSave(String, String),               // Group, Key
+    AppendSave(String, String),              // Stream ID, Chunk
+    CommitSave(String),                      // Stream ID
+    LoadRange(String, String, usize, usize), // Group, Key, Byte offset, Byte length
+    // Structured results of the running game's session. Well-known keys are score (a number),
+    // completed (a bool) and result (e.g. "win"). Replaces any summary sent earlier in the session.
+    SubmitSessionSummary(Map<String, Value>),
     // ---
 
     // --- Gatekeeper ---
-    GetNfcTag(Player), // u8 is the index of the reader. Right now just 0.
-    GetNfcUser(String), // String is the association ID
-                       // ---
+    GetNfcTag(Player),     // u8 is the index of the reader. Right now just 0.
+    GetNfcUser(String),    // String is the association ID
+    GetTapAudit(u64, u64), // Start and end of the range as unix timestamps in seconds
+    GetTapStats,           // Taps per day by members and unknown cards
+                           // ---
 }
 
 impl RequestBody {
@@ -98,20 +1374,111 @@ impl RequestBody {
             Self::Ping,
             Self::GetGameList,
             Self::GetGameListFromFs,
+            Self::GetLibrary(None),
             Self::GetGame(String::new()),
             Self::DownloadGame(String::new()),
             Self::DownloadIcon(String::new()),
             Self::DownloadBanner(String::new()),
+            Self::DownloadAllAssets,
+            Self::GetIconAtlas(0, 0),
+            Self::GetGameListWithThumbnails,
+            Self::GetDownloadProgress(String::new()),
+            Self::GetDownloadEstimate(String::new()),
+            Self::CleanupOrphanedGames(true),
+            Self::CancelOrphanCleanup,
+            Self::GetDuplicateInstalls,
+            Self::GetCacheStats,
+            Self::GetLaunchEvents(0),
+            Self::Subscribe(None),
+            Self::Negotiate(PROTOCOL_VERSION),
+            Self::CancelDownload(String::new()),
+            Self::EnqueueDownload(String::new(), DownloadPriority::Normal),
+            Self::PromoteDownload(0),
+            Self::GetDownloadQueue,
+            Self::GetGameListWithAccessibility(Vec::new()),
             Self::GetTagList,
             Self::GetTag(String::new()),
             Self::GetGameListFromTag(String::new()),
+            Self::GetTagMembership(false),
+            Self::GetTagCounts,
+            Self::GetTagStats(String::new()),
+            Self::GetAuthorStats(String::new()),
             Self::SetProduction(false),
+            Self::ReloadTls,
+            Self::SetDownloadLimit(None),
+            Self::SetLogLevel(None, None, None),
+            Self::GetLogLevels,
+            Self::SetCapture(false, None),
+            Self::SaveProfile(String::new()),
+            Self::ApplyProfile(String::new()),
+            Self::ListProfiles,
+            Self::DeleteProfile(String::new()),
+            Self::SetRequiredAccessibility(None),
+            Self::GetCabinetInfo,
+            Self::GetSetupStatus,
+            Self::SetConfigValue(String::new(), String::new()),
+            Self::TestApiConnection,
+            Self::CompleteSetup,
+            Self::GetCabinetHardware,
+            Self::GetCabinetSetting(String::new()),
+            Self::SetCabinetSetting(String::new(), None),
+            Self::ProbeHardware,
+            Self::PrepareRestart,
+            Self::RunGameSetup(String::new()),
+            Self::TestEventHooks(String::new()),
+            Self::MaintainObjectStore,
+            Self::GetSchedule,
+            Self::AddScheduleEntry(ScheduleEntry::default()),
+            Self::RemoveScheduleEntry(String::new()),
+            Self::GetQuietHours,
+            Self::SetQuietHours(None),
+            Self::OverrideQuietHours(true),
             Self::LaunchGame(String::new()),
+            Self::LaunchGameIgnoringPolicy(String::new()),
+            Self::LaunchGameSharingSaves(String::new()),
+            Self::CaptureScreenshot,
+            Self::PauseGame,
+            Self::ResumeGame,
+            Self::GetHighlights,
+            Self::GetFeaturedGames,
+            Self::GetEventHighlights(String::new()),
+            Self::GetEventLabel,
+            Self::SetEventLabel(None),
+            Self::GetLifetimeStats,
+            Self::ExportSessions(0, 0),
+            Self::CompactStats(0),
+            Self::GetGameRuntime(String::new()),
+            Self::GetUpdateSummary,
+            Self::UserActivity,
+            Self::ReportInputActivity(BTreeMap::new()),
+            Self::GetInputActivity(String::new()),
+            Self::SetSecret(String::new(), String::new()),
+            Self::RotateSecret(String::new()),
+            Self::ListSecrets,
+            Self::GetDisplayProtection,
+            Self::GetPeerStatus,
+            Self::SetPeerConsent(String::new(), false),
+            Self::GetSuspiciousUpdates,
+            Self::GetGameResources,
+            Self::GetFeatureUsage(String::new()),
+            Self::GetFeatureAdoption,
+            Self::FreezeCatalog(String::new()),
+            Self::Unfreeze,
+            Self::GetCatalogFreeze,
+            Self::VerifyFreeze(String::new()),
+            Self::ConfirmSuspiciousUpdate(String::new()),
             Self::Save(String::new(), String::new(), String::new()),
             Self::Load(String::new(), String::new()),
             Self::Flush,
+            Self::BeginSave(String::new(), String::new()),
+            Self::AppendSave(String::new(), String::new()),
+            Self::CommitSave(String::new()),
+            Self::LoadRange(String::new(), String::new(), 0, 0),
+            Self::SubmitSessionSummary(Map::new()),
             Self::GetNfcTag(Player::P1),
             Self::GetNfcUser(String::new()),
+            Self::GetTapAudit(0, 0),
+            Self::GetTapStats,
         ]
     }
 }
@@ -138,12 +1505,18 @@ pub enum ResponseBody {
 
     Ok,
     Err(String),
+    PermissionDenied(String), // The client's role isn't allowed to send the command
+    RateLimited(u64),         // Too many requests, u64 is how long to wait in milliseconds
 
     GameList(Vec<DevcadeGame>),
+    Library(LibraryUpdate),
     Game(DevcadeGame),
 
     TagList(Vec<Tag>),
     Tag(Tag),
+    TagMembership(TagMembership),
+    TagCounts(BTreeMap<String, u64>), // Game counts by tag name
+    CatalogStats(CatalogStats),
 
     User(User),
 
@@ -152,6 +1525,52 @@ pub enum ResponseBody {
     NfcTag(Option<String>),
     NfcUser(Map<String, Value>),
 
+    CabinetInfo(CabinetInfo),
+    SetupStatus(SetupStatus),
+    CabinetHardware(HardwareProbe),
+    GameSetup(GameSetupRecord),
+    EventHookTest(EventHookTest),
+    ObjectStoreReport(ObjectStoreReport),
+    Schedule(Schedule),
+    QuietHours(QuietHoursStatus),
+    TapAudit(Vec<TapAuditEntry>),
+    TapStats(BTreeMap<String, TapStats>), // By local date
+    LogLevels(Vec<LogOverride>),
+    IconAtlas(IconAtlas),
+    GameListWithThumbnails(GameListWithThumbnails),
+    DownloadProgress(Option<DownloadProgress>), // None if the game isn't being downloaded
+    DownloadJob(u64),                           // ID of the queued download
+    DownloadQueue(DownloadQueueState),
+    Installed(InstallOutcome),
+    DownloadEstimate(DownloadEstimate),
+    OrphanedGames(Vec<String>), // IDs of the games removed, or that would be in a dry run
+    DuplicateInstalls(Vec<DuplicateGroup>),
+    CacheStats(Vec<CacheReport>),
+    Assets(Vec<AssetResult>),
+    Profiles(Vec<String>),       // Names of the saved profiles
+    ProfileChanges(Vec<String>), // What applying a profile changed
+    LaunchEvents(Vec<LaunchEvent>),
+    Subscription(Subscription),
+    Negotiated(u32), // The protocol version the connection uses from now on
+    Highlights(BTreeMap<String, GameHighlights>), // By game ID
+    EventLabel(Option<EventLabel>), // None if no label is set
+    LifetimeStats(BTreeMap<String, LifetimeStats>), // By game ID
+    SessionExport(SessionExport),
+    StatsCompaction(StatsCompaction),
+    GameRuntime(GameRuntime),
+    UpdateSummary(Option<UpdateSummary>), // None if no cycle has updated anything yet
+    DisplayProtection(DisplayProtection),
+    InputActivity(BTreeMap<String, InputActivity>), // By local date
+    Secrets(Vec<SecretInfo>),
+    PeerLink(Option<PeerLink>), // None if the peer link is off
+    SuspiciousUpdates(Vec<SuspiciousUpdate>),
+    GameResources(Option<GameResources>), // None if no game is running
+    FeatureUsage(Option<FeatureUsage>),   // None if the game was never played
+    FeatureAdoption(FeatureAdoption),
+    CatalogSnapshot(CatalogSnapshot),
+    CatalogFreeze(Option<String>), // The label of the active freeze, None if not frozen
+    FreezeDrift(Vec<String>),      // What changed since the snapshot, empty if nothing did
+
     #[serde(skip)]
     InternalGame(JoinHandle<ExitStatus>),
 }
@@ -171,15 +1590,82 @@ impl ResponseBody {
             Self::Pong,
             Self::Ok,
             Self::Err(String::new()),
+            Self::PermissionDenied(String::new()),
+            Self::RateLimited(0),
             Self::GameList(Vec::new()),
+            Self::Library(LibraryUpdate::NotModified { generation: 0 }),
             Self::Game(DevcadeGame::default()),
             Self::TagList(Vec::new()),
             Self::Tag(Tag::default()),
+            Self::TagMembership(TagMembership::default()),
+            Self::TagCounts(BTreeMap::new()),
+            Self::CatalogStats(CatalogStats::default()),
             Self::User(User::default()),
             Self::Object(String::from("")),
             Self::InternalGame(std::thread::spawn(|| std::process::exit(0))),
             Self::NfcTag(None),
             Self::NfcUser(Map::default()),
+            Self::CabinetInfo(CabinetInfo::default()),
+            Self::SetupStatus(SetupStatus::default()),
+            Self::CabinetHardware(HardwareProbe::default()),
+            Self::GameSetup(GameSetupRecord {
+                hash: String::new(),
+                at: 0,
+                result: GameSetupResult::Completed { seconds: 0 },
+                log: String::new(),
+            }),
+            Self::EventHookTest(EventHookTest {
+                event: LaunchEvent {
+                    seq: 0,
+                    kind: LaunchEventKind::GameReleasedFocus,
+                    event: None,
+                },
+                runs: Vec::new(),
+            }),
+            Self::ObjectStoreReport(ObjectStoreReport::default()),
+            Self::Schedule(Schedule::default()),
+            Self::QuietHours(QuietHoursStatus::default()),
+            Self::TapAudit(Vec::new()),
+            Self::TapStats(BTreeMap::new()),
+            Self::LogLevels(Vec::new()),
+            Self::IconAtlas(IconAtlas::default()),
+            Self::GameListWithThumbnails(GameListWithThumbnails::default()),
+            Self::DownloadProgress(None),
+            Self::DownloadJob(0),
+            Self::DownloadQueue(DownloadQueueState::default()),
+            Self::Installed(InstallOutcome {
+                game: DevcadeGame::default(),
+                kind: InstallKind::AlreadyInstalled,
+                setup: None,
+            }),
+            Self::DownloadEstimate(DownloadEstimate::default()),
+            Self::OrphanedGames(Vec::new()),
+            Self::DuplicateInstalls(Vec::new()),
+            Self::CacheStats(Vec::new()),
+            Self::Assets(Vec::new()),
+            Self::Profiles(Vec::new()),
+            Self::ProfileChanges(Vec::new()),
+            Self::LaunchEvents(Vec::new()),
+            Self::Subscription(Subscription::default()),
+            Self::Negotiated(PROTOCOL_VERSION),
+            Self::Highlights(BTreeMap::new()),
+            Self::EventLabel(None),
+            Self::LifetimeStats(BTreeMap::new()),
+            Self::SessionExport(SessionExport::default()),
+            Self::StatsCompaction(StatsCompaction::default()),
+            Self::GameRuntime(GameRuntime::default()),
+            Self::UpdateSummary(Some(UpdateSummary::default())),
+            Self::DisplayProtection(DisplayProtection::default()),
+            Self::InputActivity(BTreeMap::new()),
+            Self::Secrets(Vec::new()),
+            Self::PeerLink(None),
+            Self::SuspiciousUpdates(Vec::new()),
+            Self::GameResources(None),
+            Self::FeatureUsage(None),
+            Self::FeatureAdoption(FeatureAdoption::default()),
+            Self::CatalogSnapshot(CatalogSnapshot::default()),
+            Self::CatalogFreeze(None),
+            Self::FreezeDrift(Vec::new()),
         ]
     }
 }
@@ -190,6 +1676,10 @@ impl Display for RequestBody {
             Self::Ping => write!(f, "Ping"),
             Self::GetGameList => write!(f, "Get Game List"),
             Self::GetGameListFromFs => write!(f, "Get Game List From Filesystem"),
+            Self::GetLibrary(None) => write!(f, "Get library"),
+            Self::GetLibrary(Some(generation)) => {
+                write!(f, "Get library changes since generation {generation}")
+            }
             Self::GetGame(game_id) => {
                 write!(f, "Get Game object with id '{game_id}'")
             }
@@ -202,9 +1692,59 @@ impl Display for RequestBody {
             Self::DownloadBanner(game_id) => {
                 write!(f, "Download banner with id '{game_id}'")
             }
+            Self::DownloadAllAssets => write!(f, "Download all icons and banners"),
+            Self::GetIconAtlas(max_size, cell) => {
+                write!(f, "Get {cell}px icons in atlases up to {max_size}px")
+            }
+            Self::GetGameListWithThumbnails => write!(f, "Get Game List with thumbnails"),
+            Self::GetDownloadProgress(game_id) => {
+                write!(f, "Get download progress of game with id '{game_id}'")
+            }
+            Self::GetDownloadEstimate(game_id) => {
+                write!(f, "Get download estimate of game with id '{game_id}'")
+            }
+            Self::CleanupOrphanedGames(dry_run) => write!(
+                f,
+                "Clean up games removed from the API{}",
+                if *dry_run { " (dry run)" } else { "" }
+            ),
+            Self::CancelOrphanCleanup => write!(f, "Cancel cleanup of games removed from the API"),
+            Self::GetDuplicateInstalls => write!(f, "Get duplicate installs"),
+            Self::GetCacheStats => write!(f, "Get cache stats"),
+            Self::GetGameListWithAccessibility(flags) => {
+                write!(f, "Get Game List with accessibility flags {flags:?}")
+            }
+            Self::GetLaunchEvents(after) => write!(f, "Get launch events after {after}"),
+            Self::Subscribe(Some(since)) => write!(f, "Get launch events since {since}"),
+            Self::Subscribe(None) => write!(f, "Get new launch events"),
+            Self::Negotiate(version) => write!(f, "Negotiate protocol version {version}"),
+            Self::CancelDownload(game_id) => {
+                write!(f, "Cancel download of game with id '{game_id}'")
+            }
+            Self::EnqueueDownload(game_id, priority) => write!(
+                f,
+                "Queue download of game with id '{game_id}' at {priority:?} priority"
+            ),
+            Self::PromoteDownload(job_id) => write!(f, "Promote download job {job_id}"),
+            Self::GetDownloadQueue => write!(f, "Get download queue"),
             Self::LaunchGame(game_id) => {
                 write!(f, "Launch game with id '{game_id}'")
             }
+            Self::LaunchGameSharingSaves(game_id) => {
+                write!(
+                    f,
+                    "Launch game with id '{game_id}' sharing the API copy's saves"
+                )
+            }
+            Self::LaunchGameIgnoringPolicy(game_id) => {
+                write!(
+                    f,
+                    "Launch game with id '{game_id}' ignoring accessibility policy"
+                )
+            }
+            Self::CaptureScreenshot => write!(f, "Capture screenshot of the running game"),
+            Self::PauseGame => write!(f, "Pause the running game"),
+            Self::ResumeGame => write!(f, "Resume the running game"),
             Self::SetProduction(prod) => {
                 write!(
                     f,
@@ -212,21 +1752,131 @@ impl Display for RequestBody {
                     if *prod { "production" } else { "development" }
                 )
             }
+            Self::ReloadTls => write!(f, "Reload TLS configuration"),
+            Self::SetDownloadLimit(Some(0)) => write!(f, "Lift the download limit"),
+            Self::SetDownloadLimit(Some(bps)) => write!(f, "Limit downloads to {bps} bytes/s"),
+            Self::SetDownloadLimit(None) => write!(f, "Restore the configured download limit"),
+            Self::SetLogLevel(target, level, _) => write!(
+                f,
+                "Set log level of '{}' to '{}'",
+                target.as_deref().unwrap_or("everything"),
+                level.as_deref().unwrap_or("default")
+            ),
+            Self::GetLogLevels => write!(f, "Get log levels"),
+            Self::SetCapture(enable, _) => {
+                write!(
+                    f,
+                    "{} command capture",
+                    if *enable { "Start" } else { "Stop" }
+                )
+            }
+            Self::SetRequiredAccessibility(flags) => match flags {
+                Some(flags) => write!(f, "Require accessibility flags {flags:?}"),
+                None => write!(f, "Reset required accessibility flags"),
+            },
+            Self::SaveProfile(name) => write!(f, "Save profile '{name}'"),
+            Self::ApplyProfile(name) => write!(f, "Apply profile '{name}'"),
+            Self::ListProfiles => write!(f, "List profiles"),
+            Self::DeleteProfile(name) => write!(f, "Delete profile '{name}'"),
+            Self::GetCabinetInfo => write!(f, "Get Cabinet Info"),
+            Self::GetSetupStatus => write!(f, "Get setup status"),
+            Self::SetConfigValue(key, _) => write!(f, "Set config value {key}"),
+            Self::TestApiConnection => write!(f, "Test API connection"),
+            Self::CompleteSetup => write!(f, "Complete setup"),
+            Self::GetCabinetHardware => write!(f, "Get Cabinet Hardware"),
+            Self::GetCabinetSetting(key) => write!(f, "Get cabinet setting '{key}'"),
+            Self::SetCabinetSetting(key, value) => match value {
+                Some(_) => write!(f, "Set cabinet setting '{key}'"),
+                None => write!(f, "Remove cabinet setting '{key}'"),
+            },
+            Self::ProbeHardware => write!(f, "Probe Cabinet Hardware"),
+            Self::PrepareRestart => write!(f, "Prepare restart"),
+            Self::RunGameSetup(game_id) => write!(f, "Run setup of game '{game_id}'"),
+            Self::TestEventHooks(event) => write!(f, "Test hooks of event '{event}'"),
+            Self::MaintainObjectStore => write!(f, "Maintain object store"),
+            Self::GetSchedule => write!(f, "Get schedule"),
+            Self::AddScheduleEntry(entry) => write!(
+                f,
+                "Add schedule entry '{}' from {} to {}",
+                entry.name, entry.start, entry.end
+            ),
+            Self::RemoveScheduleEntry(name) => write!(f, "Remove schedule entry '{name}'"),
+            Self::GetQuietHours => write!(f, "Get quiet hours"),
+            Self::SetQuietHours(Some(windows)) => write!(f, "Set quiet hours to '{windows}'"),
+            Self::SetQuietHours(None) => write!(f, "Reset quiet hours"),
+            Self::OverrideQuietHours(true) => write!(f, "Override quiet hours"),
+            Self::OverrideQuietHours(false) => write!(f, "Stop overriding quiet hours"),
             Self::GetTagList => write!(f, "Get Tag List"),
             Self::GetTag(tag_name) => write!(f, "Get Tag with name '{tag_name}'"),
             Self::GetGameListFromTag(tag_name) => {
                 write!(f, "Get Game List from Tag with name '{tag_name}'")
             }
+            Self::GetTagMembership(refresh) => write!(
+                f,
+                "Get tag membership{}",
+                if *refresh { " (refreshed)" } else { "" }
+            ),
+            Self::GetTagCounts => write!(f, "Get tag counts"),
+            Self::GetTagStats(tag_name) => write!(f, "Get stats of tag '{tag_name}'"),
+            Self::GetAuthorStats(author) => write!(f, "Get stats of author '{author}'"),
             Self::GetUser(uid) => write!(f, "Get User with id '{uid}'"),
             Self::Save(group, key, _value) => write!(f, "Save value to {group}/{key}"),
             Self::Load(group, key) => write!(f, "Load value from {group}/{key}"),
             Self::Flush => write!(f, "Flush cached save data"),
+            Self::BeginSave(group, key) => write!(f, "Begin streamed save to {group}/{key}"),
+            Self::AppendSave(stream_id, chunk) => write!(
+                f,
+                "Append {} bytes to streamed save '{stream_id}'",
+                chunk.len()
+            ),
+            Self::CommitSave(stream_id) => write!(f, "Commit streamed save '{stream_id}'"),
+            Self::LoadRange(group, key, offset, len) => {
+                write!(f, "Load {len} bytes at {offset} from {group}/{key}")
+            }
+            Self::SubmitSessionSummary(data) => {
+                write!(f, "Submit session summary with {} keys", data.len())
+            }
+            Self::GetHighlights => write!(f, "Get highlights"),
+            Self::GetFeaturedGames => write!(f, "Get featured games"),
+            Self::GetEventHighlights(label) => write!(f, "Get highlights of event '{label}'"),
+            Self::GetEventLabel => write!(f, "Get event label"),
+            Self::SetEventLabel(Some(label)) => write!(f, "Set event label to '{label}'"),
+            Self::SetEventLabel(None) => write!(f, "Clear event label"),
+            Self::GetLifetimeStats => write!(f, "Get lifetime stats"),
+            Self::ExportSessions(start, end) => write!(f, "Export sessions from {start} to {end}"),
+            Self::CompactStats(days) => write!(f, "Compact sessions older than {days} days"),
+            Self::GetGameRuntime(game_id) => write!(f, "Get runtime of game '{game_id}'"),
+            Self::GetUpdateSummary => write!(f, "Get auto-update summary"),
+            Self::UserActivity => write!(f, "User activity"),
+            Self::ReportInputActivity(counts) => {
+                write!(f, "Report input activity on {} controls", counts.len())
+            }
+            Self::GetInputActivity(game_id) => write!(f, "Get input activity of game '{game_id}'"),
+            Self::SetSecret(name, _) => write!(f, "Set secret '{name}'"),
+            Self::RotateSecret(name) => write!(f, "Rotate secret '{name}'"),
+            Self::ListSecrets => write!(f, "List secrets"),
+            Self::GetDisplayProtection => write!(f, "Get display protection"),
+            Self::GetPeerStatus => write!(f, "Get peer status"),
+            // Association IDs identify people, so they're kept out of the logs
+            Self::SetPeerConsent(_, consent) => write!(f, "Set peer consent to {consent}"),
+            Self::GetSuspiciousUpdates => write!(f, "Get suspicious updates"),
+            Self::GetGameResources => write!(f, "Get running game resources"),
+            Self::GetFeatureUsage(game_id) => write!(f, "Get feature usage of game {game_id}"),
+            Self::GetFeatureAdoption => write!(f, "Get feature adoption"),
+            Self::FreezeCatalog(label) => write!(f, "Freeze catalog as '{label}'"),
+            Self::Unfreeze => write!(f, "Unfreeze catalog"),
+            Self::GetCatalogFreeze => write!(f, "Get catalog freeze"),
+            Self::VerifyFreeze(label) => write!(f, "Verify catalog freeze '{label}'"),
+            Self::ConfirmSuspiciousUpdate(game_id) => {
+                write!(f, "Confirm suspicious update of game with id '{game_id}'")
+            }
             Self::GetNfcTag(player) => {
                 write!(f, "Get NFC tags for player '{player}'")
             }
-            Self::GetNfcUser(association_id) => {
-                write!(f, "Get NFC users for association ID '{association_id}'")
-            }
+            // Association IDs identify people, so they're kept out of the logs
+            Self::GetNfcUser(_) => write!(f, "Get NFC user for association ID"),
+            Self::GetTapAudit(start, end) => write!(f, "Get tap audit from {start} to {end}"),
+            Self::GetTapStats => write!(f, "Get tap stats"),
         }
     }
 }
@@ -254,6 +1904,26 @@ impl Display for ResponseBody {
             Self::Pong => write!(f, "Pong"),
             Self::Ok => write!(f, "Ok"),
             Self::Err(err) => write!(f, "Err: {err}"),
+            Self::PermissionDenied(err) => write!(f, "Permission denied: {err}"),
+            Self::RateLimited(ms) => write!(f, "Rate limited, retry after {ms}ms"),
+            Self::Library(LibraryUpdate::NotModified { generation }) => {
+                write!(f, "Got library, not modified since generation {generation}")
+            }
+            Self::Library(LibraryUpdate::Delta {
+                generation,
+                changed,
+                removed,
+            }) => write!(
+                f,
+                "Got library changes up to generation {generation}, {} changed and {} removed",
+                changed.len(),
+                removed.len()
+            ),
+            Self::Library(LibraryUpdate::Full { generation, games }) => write!(
+                f,
+                "Got library generation {generation} with {} games",
+                games.len()
+            ),
             Self::GameList(games) => {
                 write!(f, "Got game list with {} games", games.len())
             }
@@ -265,16 +1935,183 @@ impl Display for ResponseBody {
                 write!(f, "Got tag list with {} tags", tags.len())
             }
             Self::Tag(Tag { name, .. }) => write!(f, "Got tag with name '{name}'"),
+            Self::TagMembership(membership) => write!(
+                f,
+                "Got membership of {} tags{}",
+                membership.tags.len(),
+                if membership.stale { " (stale)" } else { "" }
+            ),
+            Self::TagCounts(counts) => write!(f, "Got game counts of {} tags", counts.len()),
+            Self::CatalogStats(stats) => write!(
+                f,
+                "Got stats of {} games played for {} seconds",
+                stats.games, stats.seconds_played
+            ),
             Self::User(User { id, .. }) => write!(f, "Got user with id '{id}'"),
             Self::Object(value) => {
                 write!(f, "Got Save data object ({} bytes)", value.bytes().len())
             }
             Self::NfcTag(tag_id) => {
-                write!(f, "Got NFC tag ID '{tag_id:?}'")
+                write!(f, "Got NFC tag ID: {}", tag_id.is_some())
             }
             Self::NfcUser(user) => {
                 write!(f, "Got NFC user '{user:?}'")
             }
+            Self::CabinetInfo(info) => write!(f, "Got cabinet info '{info:?}'"),
+            Self::SetupStatus(status) => write!(
+                f,
+                "Got setup status, {}",
+                if status.required {
+                    "setup required"
+                } else {
+                    "set up"
+                }
+            ),
+            Self::CabinetHardware(hardware) => write!(f, "Got cabinet hardware '{hardware:?}'"),
+            Self::GameSetup(record) => write!(f, "Got game setup '{:?}'", record.result),
+            Self::EventHookTest(test) => write!(f, "Got {} event hook runs", test.runs.len()),
+            Self::ObjectStoreReport(report) => write!(
+                f,
+                "Got object store report with {} corrupted files",
+                report.corrupted.len()
+            ),
+            Self::Schedule(schedule) => {
+                write!(f, "Got schedule with {} entries", schedule.entries.len())
+            }
+            Self::QuietHours(status) => match &status.until {
+                Some(until) => write!(f, "Got quiet hours until {until}"),
+                None => write!(f, "Got quiet hours, not in them"),
+            },
+            Self::TapAudit(entries) => write!(f, "Got {} tap audit entries", entries.len()),
+            Self::TapStats(days) => write!(f, "Got tap stats of {} days", days.len()),
+            Self::LogLevels(overrides) => write!(f, "Got log level overrides '{overrides:?}'"),
+            Self::IconAtlas(atlas) => write!(
+                f,
+                "Got {} icons in {} atlases",
+                atlas.icons.len(),
+                atlas.atlases.len()
+            ),
+            Self::GameListWithThumbnails(list) => write!(
+                f,
+                "Got game list with {} games and {} thumbnails",
+                list.games.len(),
+                list.thumbnails.len()
+            ),
+            Self::DownloadProgress(progress) => write!(f, "Got download progress '{progress:?}'"),
+            Self::DownloadJob(job_id) => write!(f, "Queued download job {job_id}"),
+            Self::DownloadQueue(queue) => write!(
+                f,
+                "Got download queue ({} pending, {} active)",
+                queue.pending.len(),
+                queue.active.len()
+            ),
+            Self::Installed(InstallOutcome { game, kind, .. }) => {
+                write!(f, "Installed game with id '{}' ({kind:?})", game.id)
+            }
+            Self::DownloadEstimate(estimate) => write!(f, "Got download estimate '{estimate:?}'"),
+            Self::OrphanedGames(ids) => write!(f, "Got {} orphaned games", ids.len()),
+            Self::DuplicateInstalls(groups) => {
+                write!(f, "Got {} groups of duplicate installs", groups.len())
+            }
+            Self::CacheStats(caches) => write!(f, "Got stats of {} caches", caches.len()),
+            Self::Profiles(names) => write!(f, "Got {} profiles", names.len()),
+            Self::ProfileChanges(changes) => {
+                write!(f, "Applied profile with {} changes", changes.len())
+            }
+            Self::Assets(results) => write!(
+                f,
+                "Downloaded art of {} games, {} with failures",
+                results.len(),
+                results
+                    .iter()
+                    .filter(|r| r.icon_error.is_some() || r.banner_error.is_some())
+                    .count()
+            ),
+            Self::LaunchEvents(events) => write!(f, "Got {} launch events", events.len()),
+            Self::Negotiated(version) => write!(f, "Negotiated protocol version {version}"),
+            Self::Subscription(subscription) => match subscription.gap {
+                Some(gap) => write!(
+                    f,
+                    "Got {} launch events after a gap before {}",
+                    subscription.events.len(),
+                    gap.oldest_available
+                ),
+                None => write!(f, "Got {} launch events", subscription.events.len()),
+            },
+            Self::Highlights(games) => write!(f, "Got highlights of {} games", games.len()),
+            Self::EventLabel(Some(EventLabel { label, .. })) => {
+                write!(f, "Got event label '{label}'")
+            }
+            Self::EventLabel(None) => write!(f, "Got no event label"),
+            Self::LifetimeStats(games) => write!(f, "Got lifetime stats of {} games", games.len()),
+            Self::SessionExport(export) => {
+                write!(f, "Got export of {} sessions", export.sessions.len())
+            }
+            Self::StatsCompaction(compaction) => write!(
+                f,
+                "Got compaction of {} sessions of {} games",
+                compaction.sessions, compaction.games
+            ),
+            Self::Secrets(secrets) => write!(f, "Got {} secrets", secrets.len()),
+            Self::PeerLink(link) => match link {
+                Some(PeerLink {
+                    status: Some(status),
+                    ..
+                }) => write!(f, "Got peer status running {:?}", status.running_game),
+                Some(_) => write!(f, "Got peer status, peer unreachable"),
+                None => write!(f, "Got peer status, peer link off"),
+            },
+            Self::SuspiciousUpdates(updates) => {
+                write!(f, "Got {} suspicious updates", updates.len())
+            }
+            Self::GameResources(None) => write!(f, "Got no game resources, no game running"),
+            Self::FeatureUsage(None) => write!(f, "Got no feature usage, game never played"),
+            Self::FeatureUsage(Some(usage)) => write!(
+                f,
+                "Got feature usage of {} request kinds and {} variables",
+                usage.requests.len(),
+                usage.env_vars.len()
+            ),
+            Self::FeatureAdoption(adoption) => {
+                write!(f, "Got feature adoption of {} games", adoption.games)
+            }
+            Self::CatalogSnapshot(snapshot) => write!(
+                f,
+                "Froze catalog as '{}' ({} games)",
+                snapshot.label,
+                snapshot.games.len()
+            ),
+            Self::CatalogFreeze(None) => write!(f, "Got catalog freeze, not frozen"),
+            Self::CatalogFreeze(Some(label)) => write!(f, "Got catalog freeze '{label}'"),
+            Self::FreezeDrift(drift) => write!(f, "Got {} changes since the freeze", drift.len()),
+            Self::GameResources(Some(resources)) => write!(
+                f,
+                "Got resources of game '{}' ({} samples)",
+                resources.game_id,
+                resources.history.len()
+            ),
+            Self::InputActivity(days) => write!(f, "Got input activity of {} days", days.len()),
+            Self::DisplayProtection(state) => write!(
+                f,
+                "Got display protection ({}suggested, idle for {}s)",
+                if state.suggested { "" } else { "not " },
+                state.idle_secs
+            ),
+            Self::UpdateSummary(None) => write!(f, "Got no auto-update summary"),
+            Self::UpdateSummary(Some(summary)) => write!(
+                f,
+                "Got auto-update summary ({} updated, {} failed)",
+                summary.updated.len(),
+                summary.failed.len()
+            ),
+            Self::GameRuntime(runtime) => write!(
+                f,
+                "Got game runtime ({})",
+                runtime
+                    .architecture
+                    .as_deref()
+                    .unwrap_or("unknown architecture")
+            ),
         }
     }
 }
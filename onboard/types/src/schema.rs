@@ -121,6 +121,75 @@ pub struct DevcadeGame {
      * The user that uploaded the game.
      */
     pub user: User,
+
+    /**
+     * The accessibility features the game declares, or `None` if it hasn't declared any. Games can
+     * be hidden and refused launch by the cabinet's accessibility policy based on these.
+     */
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub accessibility: Option<Vec<AccessibilityFlag>>,
+
+    /**
+     * Whether the developer retired the game. Retired games are still listed by the API, but the
+     * cabinet treats them like games the API removed.
+     */
+    #[serde(default)]
+    pub retired: bool,
+
+    /**
+     * A step the game needs run once after it's installed, like pre-compiling shaders, or `None` if
+     * it doesn't need one. It's run again whenever the game is updated.
+     */
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub setup: Option<GameSetup>,
+}
+
+/**
+ * A one-time setup step of a game, run from its `publish` directory with the same environment and
+ * network policy as the game itself
+ */
+#[derive(Clone, Default, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GameSetup {
+    /**
+     * The command to run, passed to `sh -c`
+     */
+    pub command: String,
+
+    /**
+     * How many seconds the step may take before it's stopped, or `None` for the cabinet's default.
+     */
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_secs: Option<u64>,
+}
+
+/**
+ * An accessibility feature a game can declare. This is a fixed set so the frontend, backend, and
+ * game authors agree on what each flag means.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccessibilityFlag {
+    /**
+     * The game has a mode for colorblind players, or doesn't rely on color to convey information.
+     */
+    ColorblindMode,
+
+    /**
+     * The game can be played with one hand.
+     */
+    OneHanded,
+
+    /**
+     * The game has no flashing lights or rapidly changing patterns, and is safe for players with
+     * photosensitivity.
+     */
+    NoFlashingLights,
+
+    /**
+     * A flag this version doesn't know about. Games declaring one are refused at install.
+     */
+    #[serde(other)]
+    Unknown,
 }
 
 /**